@@ -75,6 +75,11 @@ export enum ErrorCode {
   TodoWildcard = 45,
   TodoStarRange = 46,
   TodoRange = 47,
+  UnknownSubscript = 48,
+  XmileSpecViolation = 49,
+  ManifestMismatch = 50,
+  JsonDeserialization = 51,
+  QuotaExceeded = 52,
 }
 
 const equationErrorDefaults = {
@@ -98,6 +98,16 @@ export function errorCodeDescription(code: ErrorCode): string {
       return 'Expected an identifier';
     case ErrorCode.UnitMismatch:
       return 'Unit mismatch';
+    case ErrorCode.UnknownSubscript:
+      return 'Subscript is not an element of the dimension';
+    case ErrorCode.XmileSpecViolation:
+      return 'File does not conform to the XMILE v1.0 specification';
+    case ErrorCode.ManifestMismatch:
+      return 'Project does not match the one the manifest was recorded from';
+    case ErrorCode.JsonDeserialization:
+      return 'Unable to parse JSON project';
+    case ErrorCode.QuotaExceeded:
+      return 'Simulation exceeds the resource limits allowed';
   }
   return 'Unknown error from core engine';
 }
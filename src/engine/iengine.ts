@@ -12,11 +12,24 @@ export interface Engine {
    * @param {Function} callback
    * @returns {number}
    */
-  onChange(callback: () => undefined): number;
+  onChange(callback: (changes: ChangeSet) => undefined): number;
   /**
    * @param {number} callback_id
    */
   removeOnChangeCallback(callback_id: number): void;
+  /**
+   * @returns {Error | undefined}
+   */
+  beginTransaction(): Error | undefined;
+  /**
+   * @param {boolean} allow_errors
+   * @returns {Error | undefined}
+   */
+  commitTransaction(allow_errors: boolean): Error | undefined;
+  /**
+   * @returns {Error | undefined}
+   */
+  rollbackTransaction(): Error | undefined;
   /**
    * @param {number} value
    * @returns {Error | undefined}
@@ -132,9 +145,21 @@ export interface Engine {
    * @returns {Error | undefined}
    */
   setView(model_name: string, view_off: number, view_pb: Uint8Array): Error | undefined;
+  /**
+   * @param {string} model_name
+   * @param {number} view_off
+   * @param {Uint8Array} view_pb
+   * @returns {Array<ViewChange> | undefined}
+   */
+  diffView(model_name: string, view_off: number, view_pb: Uint8Array): Array<ViewChange> | undefined;
   /**
    */
   simRunToEnd(): void;
+  /**
+   * @param {number} budget_ms
+   * @returns {boolean}
+   */
+  simRunForMs(budget_ms: number): boolean;
   /**
    * @returns {Array<any>}
    */
@@ -144,10 +169,51 @@ export interface Engine {
    * @returns {Float64Array}
    */
   simSeries(ident: string): Float64Array;
+  /**
+   * @returns {Array<any>}
+   */
+  simColumnNames(): Array<any>;
+  /**
+   * @returns {Float64Array}
+   */
+  simColumnData(): Float64Array;
+  /**
+   * @param {string} model_name
+   * @param {number} graph_off
+   * @returns {string}
+   */
+  renderGraph(model_name: string, graph_off: number): string;
   /**
    */
   simClose(): void;
 }
+/**
+ */
+export interface ChangeSet {
+  free(): void;
+  /**
+   * @returns {Array<any>}
+   */
+  models(): Array<any>;
+  /**
+   * @param {string} model_name
+   * @returns {Array<any>}
+   */
+  variables(model_name: string): Array<any>;
+  /**
+   * @param {string} model_name
+   * @returns {boolean}
+   */
+  viewsChanged(model_name: string): boolean;
+  /**
+   * @returns {boolean}
+   */
+  sim_specs: boolean;
+  /**
+   * @returns {boolean}
+   */
+  source: boolean;
+}
 /**
  */
 export interface EquationError {
@@ -213,6 +279,26 @@ export interface UnitError {
   start: number;
 }
 
+export interface ViewChange {
+  free(): void;
+  /**
+   * @returns {number}
+   */
+  uid: number;
+  /**
+   * @returns {number}
+   */
+  kind: ViewChangeKind;
+}
+
+export enum ViewChangeKind {
+  Added,
+  Removed,
+  Moved,
+  Relabeled,
+  Changed,
+}
+
 export enum ErrorKind {
   Import,
   Model,
@@ -269,4 +355,9 @@ export enum ErrorCode {
   TodoWildcard = 45,
   TodoStarRange = 46,
   TodoRange = 47,
+  UnknownSubscript = 48,
+  XmileSpecViolation = 49,
+  ManifestMismatch = 50,
+  JsonDeserialization = 51,
+  QuotaExceeded = 52,
 }
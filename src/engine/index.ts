@@ -2,11 +2,11 @@
 // Use of this source code is governed by the Apache License,
 // Version 2.0, that can be found in the LICENSE file.
 
-import type { Engine, Error, EquationError, UnitError } from './iengine';
-export type { Engine, Error, EquationError, UnitError };
+import type { Engine, Error, EquationError, UnitError, ViewChange } from './iengine';
+export type { Engine, Error, EquationError, UnitError, ViewChange };
 
 export { ErrorCode, errorCodeDescription } from './error_codes';
-export { ErrorKind } from './iengine';
+export { ErrorKind, ViewChangeKind } from './iengine';
 
 let cachedWasmModule: typeof import('./core/engine') | undefined;
 function getWasmModule(): Promise<typeof import('./core/engine')> {
@@ -6,7 +6,7 @@
 #![allow(clippy::unused_unit)]
 
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 use wasm_bindgen::prelude::*;
 
@@ -16,16 +16,72 @@ use prost::Message;
 use simlin_engine as engine;
 use simlin_engine::common::{ErrorCode, ErrorKind};
 use simlin_engine::datamodel::{Extension, GraphicalFunction, Source, Variable, Visibility};
-use simlin_engine::{canonicalize, datamodel, project_io, prost, serde, Error, Vm};
+use simlin_engine::{canonicalize, datamodel, project_io, prost, serde, Error, Stats, Vm};
+
+// how many time steps simRunForMs takes between looking at the clock
+const STEPS_BETWEEN_CLOCK_CHECKS: usize = 64;
+
+// Listener is called with what changed each time the project does
+type Listener = Box<dyn Fn(&ChangeSet)>;
 
 #[wasm_bindgen]
 pub struct Engine {
     project: engine::Project,
     sim_vm: Option<Vm>,
+    sim_stats: Option<Stats>,
     sim_error: Option<Error>,
     results: Option<engine::Results>,
     next_callback_ref: u32,
-    on_change_callbacks: HashMap<u32, Function>,
+    on_change_callbacks: HashMap<u32, Listener>,
+    // the project as it was when the open transaction began
+    transaction: Option<datamodel::Project>,
+    // what has changed since onChange callbacks were last called
+    changes: ChangeSet,
+}
+
+/// ChangeSet is passed to onChange callbacks, and describes what the
+/// changes since the previous call affected.
+#[wasm_bindgen]
+#[derive(Clone, Default, Debug)]
+pub struct ChangeSet {
+    variables: BTreeMap<String, BTreeSet<String>>,
+    views: BTreeSet<String>,
+    pub sim_specs: bool,
+    pub source: bool,
+}
+
+#[wasm_bindgen]
+impl ChangeSet {
+    /// models returns the names of the models whose variables or views
+    /// changed.
+    pub fn models(&self) -> StringArray {
+        let models: BTreeSet<&String> = self.variables.keys().chain(self.views.iter()).collect();
+        models.into_iter().map(JsValue::from).collect()
+    }
+
+    /// variables returns the idents of the variables in the model that
+    /// were added, changed or deleted.  A renamed variable is listed
+    /// under both its old and new idents.
+    pub fn variables(&self, model_name: &str) -> StringArray {
+        match self.variables.get(model_name) {
+            Some(idents) => idents.iter().map(JsValue::from).collect(),
+            None => Array::new(),
+        }
+    }
+
+    #[wasm_bindgen(js_name = viewsChanged)]
+    pub fn views_changed(&self, model_name: &str) -> bool {
+        self.views.contains(model_name)
+    }
+}
+
+impl ChangeSet {
+    fn variable(&mut self, model_name: &str, ident: &str) {
+        self.variables
+            .entry(model_name.to_owned())
+            .or_default()
+            .insert(ident.to_owned());
+    }
 }
 
 #[wasm_bindgen]
@@ -54,6 +110,9 @@ type MaybeErrorArray = Array;
 //#[wasm_bindgen(typescript_type = "Array<string>")]
 type StringArray = Array;
 
+//#[wasm_bindgen(typescript_type = "Array<ViewChange>")]
+type ViewChangeArray = Array;
+
 impl From<engine::common::UnitError> for UnitError {
     fn from(err: engine::common::UnitError) -> Self {
         match err {
@@ -78,20 +137,35 @@ impl From<engine::common::UnitError> for UnitError {
 #[wasm_bindgen]
 impl Engine {
     fn instantiate_sim(&mut self) {
+        // the simulation is rebuilt, and listeners told, once the
+        // transaction is committed or rolled back
+        if self.transaction.is_some() {
+            return;
+        }
+        self.build_sim();
+        self.notify_on_change();
+    }
+
+    fn build_sim(&mut self) {
         let compiler = engine::Simulation::new(&self.project, "main");
-        let sim_result = compiler
-            .and_then(|compiler| compiler.compile())
-            .and_then(Vm::new);
+        let compiled = compiler.and_then(|compiler| compiler.compile());
+        self.sim_stats = compiled.as_ref().ok().map(|compiled| compiled.stats());
+        let sim_result = compiled.and_then(Vm::new);
         self.sim_error = sim_result.as_ref().err().cloned();
         self.sim_vm = sim_result.ok();
-        self.notify_on_change();
     }
 
     #[wasm_bindgen(js_name = onChange)]
     pub fn on_change(&mut self, callback: Function) -> u32 {
+        self.add_listener(Box::new(move |changes: &ChangeSet| {
+            let _ = callback.call1(&JsValue::null(), &JsValue::from(changes.clone()));
+        }))
+    }
+
+    fn add_listener(&mut self, listener: Listener) -> u32 {
         let cb_id = self.next_callback_ref;
         self.next_callback_ref += 1;
-        self.on_change_callbacks.insert(cb_id, callback);
+        self.on_change_callbacks.insert(cb_id, listener);
 
         cb_id
     }
@@ -101,11 +175,11 @@ impl Engine {
         self.on_change_callbacks.remove(&callback_id);
     }
 
-    fn notify_on_change(&self) {
-        let this = JsValue::null();
+    fn notify_on_change(&mut self) {
+        let changes = std::mem::take(&mut self.changes);
 
-        for (_, cb) in self.on_change_callbacks.iter() {
-            let _ = cb.call0(&this);
+        for (_, listener) in self.on_change_callbacks.iter() {
+            listener(&changes);
         }
     }
 
@@ -119,10 +193,74 @@ impl Engine {
         buf
     }
 
+    // transactions
+
+    /// beginTransaction groups the changes that follow, like pasting
+    /// several variables, so that the simulation is rebuilt and onChange
+    /// callbacks are called once when the transaction is committed,
+    /// rather than after each change.  Until then the simulation and its
+    /// errors reflect the project from before the transaction.
+    #[wasm_bindgen(js_name = beginTransaction)]
+    pub fn begin_transaction(&mut self) -> Option<Error> {
+        if self.transaction.is_some() {
+            return Some(Error::new(
+                ErrorKind::Model,
+                ErrorCode::Generic,
+                Some("a transaction is already in progress".to_owned()),
+            ));
+        }
+        self.transaction = Some(self.project.datamodel.clone());
+
+        None
+    }
+
+    /// commitTransaction keeps the changes made since beginTransaction.
+    /// Unless allow_errors is set, changes that leave a simulatable
+    /// project unable to simulate are rolled back, and the error that
+    /// prevents simulating is returned.
+    #[wasm_bindgen(js_name = commitTransaction)]
+    pub fn commit_transaction(&mut self, allow_errors: bool) -> Option<Error> {
+        let snapshot = match self.transaction.take() {
+            Some(snapshot) => snapshot,
+            None => return Some(no_transaction()),
+        };
+        let was_simulatable = self.sim_error.is_none();
+        self.build_sim();
+        if !allow_errors && was_simulatable {
+            if let Some(err) = self.sim_error.clone() {
+                self.project = snapshot.into();
+                self.changes = ChangeSet::default();
+                self.build_sim();
+                return Some(err);
+            }
+        }
+        self.notify_on_change();
+
+        None
+    }
+
+    /// rollbackTransaction undoes every change made since
+    /// beginTransaction.  As the project ends up as it was, onChange
+    /// callbacks aren't called.
+    #[wasm_bindgen(js_name = rollbackTransaction)]
+    pub fn rollback_transaction(&mut self) -> Option<Error> {
+        let snapshot = match self.transaction.take() {
+            Some(snapshot) => snapshot,
+            None => return Some(no_transaction()),
+        };
+        // the simulation wasn't rebuilt during the transaction, so it
+        // still matches the restored project
+        self.project = snapshot.into();
+        self.changes = ChangeSet::default();
+
+        None
+    }
+
     // time control
 
     fn update_sim_specs(&mut self, specs: datamodel::SimSpecs) -> Option<Error> {
         self.project.datamodel.sim_specs = specs;
+        self.changes.sim_specs = true;
         self.instantiate_sim();
 
         None
@@ -271,6 +409,7 @@ impl Engine {
                 units: None,
                 gf: None,
                 can_be_module_input: false,
+                supplementary: false,
                 visibility: Visibility::Private,
             }),
             "flow" => datamodel::Variable::Flow(datamodel::Flow {
@@ -281,6 +420,7 @@ impl Engine {
                 gf: None,
                 non_negative: false,
                 can_be_module_input: false,
+                supplementary: false,
                 visibility: Visibility::Private,
             }),
             "stock" => datamodel::Variable::Stock(datamodel::Stock {
@@ -291,12 +431,15 @@ impl Engine {
                 inflows: vec![],
                 outflows: vec![],
                 non_negative: false,
+                force_euler: false,
                 can_be_module_input: false,
+                supplementary: false,
                 visibility: Visibility::Private,
             }),
             _ => return None,
         };
 
+        self.changes.variable(model_name, var.get_ident());
         model.variables.push(var);
 
         self.project = project.into();
@@ -318,15 +461,18 @@ impl Engine {
             .iter()
             .position(|v| v.get_ident() == ident)?;
         let removed = model.variables.remove(off);
+        self.changes.variable(model_name, ident);
         if let Variable::Flow(flow) = removed {
             for var in model.variables.iter_mut() {
                 if let Variable::Stock(stock) = var {
                     if let Some(off) = stock.inflows.iter().position(|ident| ident == &flow.ident) {
                         let _ = stock.inflows.remove(off);
+                        self.changes.variable(model_name, &stock.ident);
                     }
                     if let Some(off) = stock.outflows.iter().position(|ident| ident == &flow.ident)
                     {
                         let _ = stock.outflows.remove(off);
+                        self.changes.variable(model_name, &stock.ident);
                     }
                 }
             }
@@ -367,6 +513,9 @@ impl Engine {
             }
         }
 
+        self.changes.variable(model_name, stock);
+        self.changes.variable(model_name, flow);
+
         self.project = project.into();
         self.instantiate_sim();
 
@@ -402,6 +551,9 @@ impl Engine {
             }
         }
 
+        self.changes.variable(model_name, stock);
+        self.changes.variable(model_name, flow);
+
         self.project = project.into();
         self.instantiate_sim();
 
@@ -424,6 +576,8 @@ impl Engine {
             }
         }
 
+        self.changes.variable(model_name, ident);
+
         self.project = project.into();
         self.instantiate_sim();
 
@@ -441,6 +595,8 @@ impl Engine {
             }
         }
 
+        self.changes.variable(model_name, ident);
+
         self.project = project.into();
         self.instantiate_sim();
 
@@ -463,6 +619,8 @@ impl Engine {
             }
         }
 
+        self.changes.variable(model_name, ident);
+
         self.project = project.into();
         self.instantiate_sim();
 
@@ -484,6 +642,8 @@ impl Engine {
             }
         }
 
+        self.changes.variable(model_name, ident);
+
         self.project = project.into();
         self.instantiate_sim();
 
@@ -539,6 +699,8 @@ impl Engine {
             Some(var) => {
                 is_flow = matches!(var, Variable::Flow(_));
                 var.set_ident(new_ident.clone());
+                self.changes.variable(model_name, &old_ident);
+                self.changes.variable(model_name, &new_ident);
             }
             _ => {
                 return None;
@@ -551,11 +713,13 @@ impl Engine {
                     for inflow in stock.inflows.iter_mut() {
                         if inflow == &old_ident {
                             inflow.clone_from(&new_ident);
+                            self.changes.variable(model_name, &stock.ident);
                         }
                     }
                     for outflow in stock.outflows.iter_mut() {
                         if outflow == &old_ident {
                             outflow.clone_from(&new_ident);
+                            self.changes.variable(model_name, &stock.ident);
                         }
                     }
                 }
@@ -596,12 +760,40 @@ impl Engine {
                 Ordering::Equal => model.views.push(view.clone()),
                 Ordering::Greater => {}
             });
+        self.changes.views.insert(model_name.to_owned());
 
         self.instantiate_sim();
 
         None
     }
 
+    #[wasm_bindgen(js_name = diffView)]
+    pub fn diff_view(
+        &self,
+        model_name: &str,
+        view_off: usize,
+        view_pb: &[u8],
+    ) -> Option<ViewChangeArray> {
+        let new_view = match project_io::View::decode(view_pb) {
+            Ok(view) => serde::deserialize_view(view),
+            Err(_err) => {
+                return None;
+            }
+        };
+
+        let model = self.project.datamodel.get_model(model_name)?;
+        let (old, new) = match (model.views.get(view_off)?, &new_view) {
+            (datamodel::View::StockFlow(old), datamodel::View::StockFlow(new)) => (old, new),
+        };
+
+        Some(
+            engine::view_diff::diff_views(old, new)
+                .into_iter()
+                .map(JsValue::from)
+                .collect(),
+        )
+    }
+
     #[wasm_bindgen(js_name = setSource)]
     pub fn set_source(&mut self, content: &str, extension: &str) -> Option<Error> {
         let project = &mut self.project.datamodel;
@@ -623,6 +815,7 @@ impl Engine {
                 content: content.to_owned(),
             })
         };
+        self.changes.source = true;
 
         self.instantiate_sim();
 
@@ -631,6 +824,11 @@ impl Engine {
 
     // simulation control
 
+    #[wasm_bindgen(js_name = getSimStats)]
+    pub fn get_sim_stats(&self) -> Option<Stats> {
+        self.sim_stats
+    }
+
     #[wasm_bindgen(js_name = simRunToEnd)]
     pub fn sim_run_to_end(&mut self) {
         if self.sim_vm.is_none() {
@@ -645,6 +843,38 @@ impl Engine {
         }
     }
 
+    /// simRunForMs simulates until either the run is complete or
+    /// `budget_ms` milliseconds have passed, and returns true once the
+    /// run is over: either its results are available, or it failed and
+    /// getSimError says why.  Calling it once per animation frame keeps
+    /// a long run from freezing the page.
+    #[wasm_bindgen(js_name = simRunForMs)]
+    pub fn sim_run_for_ms(&mut self, budget_ms: f64) -> bool {
+        let deadline = js_sys::Date::now() + budget_ms;
+        let vm = match self.sim_vm.as_mut() {
+            Some(vm) => vm,
+            None => return self.results.is_some() || self.sim_error.is_some(),
+        };
+        loop {
+            match vm.run_steps_to_end(STEPS_BETWEEN_CLOCK_CHECKS) {
+                Ok(true) => break,
+                Ok(false) => {}
+                Err(err) => {
+                    self.sim_vm = None;
+                    self.sim_error = Some(err);
+                    return true;
+                }
+            }
+            if js_sys::Date::now() >= deadline {
+                return false;
+            }
+        }
+
+        let vm = self.sim_vm.take().unwrap();
+        self.results = Some(vm.into_results());
+        true
+    }
+
     #[wasm_bindgen(js_name = simVarNames)]
     pub fn sim_var_names(&self) -> StringArray {
         if self.results.is_none() {
@@ -655,18 +885,68 @@ impl Engine {
         results.offsets.keys().map(JsValue::from).collect()
     }
 
+    /// simColumnNames returns the names of the variables in the last
+    /// run's results, in the order of their columns in simColumnData.
+    #[wasm_bindgen(js_name = simColumnNames)]
+    pub fn sim_column_names(&self) -> StringArray {
+        match self.results.as_ref() {
+            Some(results) => results.columns().0.into_iter().map(JsValue::from).collect(),
+            None => Array::new(),
+        }
+    }
+
+    /// simColumnData returns every value of the last run in a single
+    /// buffer, one variable after another: the series for the `i`th of
+    /// simColumnNames is the `data.length / names.length` values
+    /// starting at `i` times that.  The buffer belongs to JavaScript, so
+    /// a Web Worker can transfer it to the page without copying it.
+    #[wasm_bindgen(js_name = simColumnData)]
+    pub fn sim_column_data(&self) -> Vec<f64> {
+        match self.results.as_ref() {
+            Some(results) => results.columns().1,
+            None => vec![],
+        }
+    }
+
     #[wasm_bindgen(js_name = simSeries)]
     pub fn sim_series(&self, ident: &str) -> Vec<f64> {
         if self.results.is_none() {
             return vec![];
         }
         let results = self.results.as_ref().unwrap();
-        if !results.offsets.contains_key(ident) {
-            return vec![];
+        match results.offset(ident) {
+            Some(off) => results.iter_rows().map(|curr| curr[off]).collect(),
+            None => vec![],
         }
+    }
 
-        let off = results.offsets[ident];
-        results.iter().map(|curr| curr[off]).collect()
+    /// renderGraph draws one of the graphs saved with the model, using
+    /// the results of the last run, as an SVG document.
+    #[wasm_bindgen(js_name = renderGraph)]
+    pub fn render_graph(&self, model_name: &str, graph_off: usize) -> Result<String, Error> {
+        let results = match self.results.as_ref() {
+            Some(results) => results,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::Simulation,
+                    ErrorCode::Generic,
+                    Some("the model hasn't been simulated".to_owned()),
+                ))
+            }
+        };
+        let graph = self
+            .project
+            .datamodel
+            .get_model(model_name)
+            .and_then(|model| model.graphs.get(graph_off));
+        match graph {
+            Some(graph) => Ok(engine::plot::Chart::from_graph(graph, results)?.to_svg()),
+            None => Err(Error::new(
+                ErrorKind::Model,
+                ErrorCode::DoesNotExist,
+                Some(format!("no graph {} in model {}", graph_off, model_name)),
+            )),
+        }
     }
 
     #[wasm_bindgen(js_name = simClose)]
@@ -675,6 +955,55 @@ impl Engine {
     }
 }
 
+fn no_transaction() -> Error {
+    Error::new(
+        ErrorKind::Model,
+        ErrorCode::Generic,
+        Some("no transaction is in progress".to_owned()),
+    )
+}
+
+/// capabilities returns the names of the optional features this build of
+/// the engine supports, like `arrays` or `builtins.delays`.
+#[wasm_bindgen]
+pub fn capabilities() -> StringArray {
+    engine::capabilities::capabilities()
+        .names()
+        .into_iter()
+        .map(JsValue::from)
+        .collect()
+}
+
+/// errorCodeId returns the stable ID of an error code, like `E0021`.
+#[wasm_bindgen(js_name = errorCodeId)]
+pub fn error_code_id(code: ErrorCode) -> String {
+    code.id()
+}
+
+/// explainErrorCode describes what causes an error and how to fix it.
+#[wasm_bindgen(js_name = explainErrorCode)]
+pub fn explain_error_code(code: ErrorCode) -> String {
+    code.explanation().to_owned()
+}
+
+/// evaluate returns the value of an expression like `max(a, b) * 2`,
+/// where `env` maps the names of the variables it refers to to their
+/// values.
+#[wasm_bindgen]
+pub fn evaluate(expr: &str, env: &Map) -> Result<f64, Error> {
+    let mut values: Vec<(String, f64)> = vec![];
+    env.for_each(&mut |value, key| {
+        if let (Some(key), Some(value)) = (key.as_string(), value.as_f64()) {
+            values.push((key, value));
+        }
+    });
+    let env: Vec<(&str, f64)> = values
+        .iter()
+        .map(|(ident, value)| (ident.as_str(), *value))
+        .collect();
+    engine::eval::evaluate(expr, &env)
+}
+
 #[wasm_bindgen]
 pub fn open(project_pb: &[u8]) -> Option<Engine> {
     let project = match project_io::Project::decode(project_pb) {
@@ -687,12 +1016,188 @@ pub fn open(project_pb: &[u8]) -> Option<Engine> {
     let mut project = Engine {
         project: project.into(),
         sim_vm: None,
+        sim_stats: None,
         sim_error: None,
         results: None,
         next_callback_ref: 1,
         on_change_callbacks: HashMap::new(),
+        transaction: None,
+        changes: Default::default(),
     };
     project.instantiate_sim();
 
     Some(project)
 }
+
+#[cfg(test)]
+fn test_engine() -> Engine {
+    let aux = |ident: &str, eqn: &str| {
+        Variable::Aux(datamodel::Aux {
+            ident: ident.to_owned(),
+            equation: datamodel::Equation::Scalar(eqn.to_owned(), None),
+            documentation: "".to_owned(),
+            units: None,
+            gf: None,
+            can_be_module_input: false,
+            supplementary: false,
+            visibility: Visibility::Private,
+        })
+    };
+    let project = datamodel::Project {
+        name: "test".to_owned(),
+        sim_specs: datamodel::SimSpecs {
+            stop: 10.0,
+            ..Default::default()
+        },
+        dimensions: vec![],
+        units: vec![],
+        models: vec![datamodel::Model {
+            name: "main".to_owned(),
+            variables: vec![aux("a", "1"), aux("b", "a * 2")],
+            views: vec![],
+            graphs: vec![],
+            groups: vec![],
+            variable_index: Default::default(),
+        }],
+        constants: vec![],
+        tests: vec![],
+        source: None,
+        import_report: vec![],
+    };
+    let pb = serde::serialize(&project);
+    let mut buf = Vec::with_capacity(pb.encoded_len());
+    pb.encode(&mut buf).unwrap();
+    open(&buf).unwrap()
+}
+
+#[cfg(test)]
+fn test_equation(engine: &Engine, ident: &str) -> Option<String> {
+    let model = engine.project.datamodel.get_model("main")?;
+    match model.get_variable(ident)?.get_equation()? {
+        datamodel::Equation::Scalar(eqn, _) => Some(eqn.clone()),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_transaction_rollback() {
+    let mut engine = test_engine();
+    let before = engine.serialize_to_protobuf();
+
+    assert!(engine.begin_transaction().is_none());
+    assert!(engine.set_equation("main", "a", "3").is_none());
+    assert!(engine.add_new_variable("main", "aux", "c").is_none());
+    // nothing is rebuilt until the transaction ends
+    assert!(engine.get_sim_error().is_none());
+    assert!(engine.rollback_transaction().is_none());
+
+    assert_eq!(before, engine.serialize_to_protobuf());
+    assert_eq!(Some("1".to_owned()), test_equation(&engine, "a"));
+    assert_eq!(None, test_equation(&engine, "c"));
+    assert!(engine.is_simulatable());
+}
+
+#[test]
+fn test_transaction_commit() {
+    let mut engine = test_engine();
+
+    assert!(engine.begin_transaction().is_none());
+    // only one transaction can be open at a time
+    assert!(engine.begin_transaction().is_some());
+    assert!(engine.set_equation("main", "a", "3").is_none());
+    assert!(engine.add_new_variable("main", "aux", "c").is_none());
+    assert!(engine.set_equation("main", "c", "b + 1").is_none());
+    assert!(engine.commit_transaction(false).is_none());
+
+    assert_eq!(Some("3".to_owned()), test_equation(&engine, "a"));
+    assert_eq!(Some("b + 1".to_owned()), test_equation(&engine, "c"));
+    assert!(engine.is_simulatable());
+    engine.sim_run_to_end();
+    let results = engine.results.as_ref().unwrap();
+    assert_eq!(Some(7.0), results.get_series("c").map(|c| c[0]));
+
+    // with no transaction open, there is nothing to commit or roll back
+    assert!(engine.commit_transaction(false).is_some());
+    assert!(engine.rollback_transaction().is_some());
+}
+
+#[test]
+fn test_transaction_failing_edit() {
+    let mut engine = test_engine();
+    let before = engine.serialize_to_protobuf();
+
+    assert!(engine.begin_transaction().is_none());
+    assert!(engine.set_equation("main", "a", "3").is_none());
+    // leaves the model unable to simulate
+    assert!(engine.set_equation("main", "b", "a +").is_none());
+    assert!(engine.set_equation("main", "a", "4").is_none());
+    assert!(engine.commit_transaction(false).is_some());
+
+    // every edit is undone, including the ones before the failing one
+    assert_eq!(before, engine.serialize_to_protobuf());
+    assert_eq!(Some("1".to_owned()), test_equation(&engine, "a"));
+    assert!(engine.is_simulatable());
+
+    // unless errors are allowed
+    assert!(engine.begin_transaction().is_none());
+    assert!(engine.set_equation("main", "a", "3").is_none());
+    assert!(engine.set_equation("main", "b", "a +").is_none());
+    assert!(engine.commit_transaction(true).is_none());
+    assert_eq!(Some("3".to_owned()), test_equation(&engine, "a"));
+    assert!(!engine.is_simulatable());
+}
+
+#[test]
+fn test_change_listeners() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let mut engine = test_engine();
+    let seen: Rc<RefCell<Vec<ChangeSet>>> = Default::default();
+    let listener_seen = seen.clone();
+    let id = engine.add_listener(Box::new(move |changes| {
+        listener_seen.borrow_mut().push(changes.clone())
+    }));
+    let variables = |off: usize| -> Vec<String> {
+        match seen.borrow()[off].variables.get("main") {
+            Some(idents) => idents.iter().cloned().collect(),
+            None => vec![],
+        }
+    };
+
+    assert!(engine.set_equation("main", "a", "3").is_none());
+    assert_eq!(1, seen.borrow().len());
+    assert_eq!(vec!["a"], variables(0));
+    assert!(!seen.borrow()[0].sim_specs);
+    assert!(engine.set_sim_spec_stop(20.0).is_none());
+    assert_eq!(2, seen.borrow().len());
+    assert!(variables(1).is_empty());
+    assert!(seen.borrow()[1].sim_specs);
+
+    // a transaction is reported once, when it is committed
+    assert!(engine.begin_transaction().is_none());
+    assert!(engine.set_equation("main", "a", "4").is_none());
+    assert!(engine.add_new_variable("main", "aux", "c").is_none());
+    assert!(engine.set_equation("main", "c", "b + 1").is_none());
+    assert_eq!(2, seen.borrow().len());
+    assert!(engine.commit_transaction(false).is_none());
+    assert_eq!(3, seen.borrow().len());
+    assert_eq!(vec!["a", "c"], variables(2));
+
+    // nothing is reported for transactions that are undone
+    assert!(engine.begin_transaction().is_none());
+    assert!(engine.set_equation("main", "a", "5").is_none());
+    assert!(engine.rollback_transaction().is_none());
+    assert!(engine.begin_transaction().is_none());
+    assert!(engine.set_equation("main", "b", "a +").is_none());
+    assert!(engine.commit_transaction(false).is_some());
+    assert_eq!(3, seen.borrow().len());
+    // and their changes aren't reported with the next one
+    assert!(engine.set_equation("main", "c", "b + 2").is_none());
+    assert_eq!(4, seen.borrow().len());
+    assert_eq!(vec!["c"], variables(3));
+
+    engine.remove_on_change_callback(id);
+    assert!(engine.set_equation("main", "a", "6").is_none());
+    assert_eq!(4, seen.borrow().len());
+}
@@ -28,3 +28,46 @@ export async function toXmile(projectPb: Readonly<Uint8Array>): Promise<string |
   const wasm = await getWasmModule();
   return wasm.to_xmile(projectPb as Uint8Array);
 }
+
+export type ModelSource = AsyncIterable<Uint8Array | string> | Iterable<Uint8Array | string>;
+
+// importFrom feeds each chunk of an XMILE model to the wasm importer as it
+// arrives, so a Node.js stream (like a request body or fs.createReadStream)
+// can be passed straight through.  Vensim models should be converted to
+// XMILE first with `convertMdlToXmile` from @system-dynamics/xmutil.
+async function importFrom(source: ModelSource): Promise<import('./core/importer').Importer> {
+  const wasm = await getWasmModule();
+  const encoder = new TextEncoder();
+  const importer = new wasm.Importer();
+  try {
+    for await (const chunk of source) {
+      importer.write(typeof chunk === 'string' ? encoder.encode(chunk) : (chunk as Uint8Array));
+    }
+  } catch (err) {
+    importer.free();
+    throw err;
+  }
+  return importer;
+}
+
+export async function convert(source: ModelSource, format: 'protobuf'): Promise<Uint8Array>;
+export async function convert(source: ModelSource, format: 'xmile'): Promise<string>;
+export async function convert(source: ModelSource, format: 'protobuf' | 'xmile'): Promise<Uint8Array | string> {
+  const importer = await importFrom(source);
+  try {
+    return format === 'xmile' ? importer.to_xmile() : importer.to_protobuf();
+  } finally {
+    importer.free();
+  }
+}
+
+// simulate runs the model to the end and resolves to its results as
+// tab-separated values, the same output as `simlin simulate`.
+export async function simulate(source: ModelSource, modelName = 'main'): Promise<string> {
+  const importer = await importFrom(source);
+  try {
+    return importer.simulate(modelName);
+  } finally {
+    importer.free();
+  }
+}
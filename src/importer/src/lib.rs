@@ -9,7 +9,7 @@ use std::io::BufReader;
 
 use wasm_bindgen::prelude::*;
 
-use simlin_compat::engine::{self, project_io, serde};
+use simlin_compat::engine::{self, project_io, serde, Project, Simulation, Vm};
 use simlin_compat::{open_xmile, prost, to_xmile as compat_to_xmile};
 
 #[wasm_bindgen]
@@ -44,6 +44,65 @@ pub fn to_xmile(project_pb: &[u8]) -> Option<String> {
     compat_to_xmile(&project).ok()
 }
 
+/// Importer collects an XMILE model as it arrives in chunks, so a
+/// server can feed it a request body or file stream without first
+/// building one large string on the JavaScript side.
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct Importer {
+    source: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl Importer {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Importer {
+        Importer::default()
+    }
+
+    pub fn write(&mut self, chunk: &[u8]) {
+        self.source.extend_from_slice(chunk);
+    }
+
+    /// to_protobuf returns the model written so far as a serialized
+    /// project protobuf.
+    pub fn to_protobuf(&self) -> Result<Box<[u8]>, JsValue> {
+        use prost::Message;
+        let project = self.open()?;
+        Ok(engine::serde::serialize(&project)
+            .encode_to_vec()
+            .into_boxed_slice())
+    }
+
+    /// to_xmile returns the model written so far re-serialized as XMILE.
+    pub fn to_xmile(&self) -> Result<String, JsValue> {
+        let project = self.open()?;
+        compat_to_xmile(&project).map_err(|err| JsValue::from_str(&err.to_string()))
+    }
+
+    /// simulate runs `model_name` to the end and returns its results as
+    /// tab-separated values, in the same format as the `simulate`
+    /// command of the CLI.
+    pub fn simulate(&self, model_name: &str) -> Result<String, JsValue> {
+        let to_js = |err: engine::Error| JsValue::from_str(&err.to_string());
+        let project = Project::from(self.open()?);
+        let sim = Simulation::new(&project, model_name).map_err(to_js)?;
+        let mut vm = Vm::new(sim.compile().map_err(to_js)?).map_err(to_js)?;
+        vm.run_to_end().map_err(to_js)?;
+
+        let mut tsv: Vec<u8> = vec![];
+        vm.into_results()
+            .write_tsv(&mut tsv)
+            .map_err(|err| JsValue::from_str(&err.to_string()))?;
+        String::from_utf8(tsv).map_err(|err| JsValue::from_str(&err.to_string()))
+    }
+
+    fn open(&self) -> Result<engine::datamodel::Project, JsValue> {
+        open_xmile(&mut BufReader::new(self.source.as_slice()))
+            .map_err(|err| JsValue::from_str(&err.to_string()))
+    }
+}
+
 // #[wasm_bindgen]
 // pub fn from_vensim(xmile_xml: &str) -> Box<[u8]> {
 //     use simlin_compat::open_xmile;
@@ -3,23 +3,56 @@
 // Version 2.0, that can be found in the LICENSE file.
 
 use std::fs::File;
-use std::io::{BufRead, BufReader, Write};
+use std::io::{BufRead, BufWriter, Cursor, Read, Write};
+use std::path::Path;
 use std::rc::Rc;
 use std::result::Result as StdResult;
 
+use ::serde::Serialize;
 use pico_args::Arguments;
 
-use simlin_compat::engine::common::ErrorKind;
-use simlin_compat::engine::datamodel::Project as DatamodelProject;
+use simlin_compat::engine::calibrate::{calibrate, CalibrationOptions, ErrorMetric};
+use simlin_compat::engine::capabilities::capabilities;
+use simlin_compat::engine::common::{ErrorKind, UnitError};
+use simlin_compat::engine::complexity::ComplexityLimits;
+use simlin_compat::engine::datamodel::{
+    GraphKind, ImportIssue, Project as DatamodelProject, UnitMap,
+};
+use simlin_compat::engine::dep_tree::{dependency_graph, dependency_tree, DependencyKind};
+use simlin_compat::engine::ensemble::{run_ensemble, Manifest, Override, Run};
+use simlin_compat::engine::events::{Change, Schedule};
+use simlin_compat::engine::model_tests::{run_tests, TestResult};
+use simlin_compat::engine::molecules::{molecule, molecules};
+use simlin_compat::engine::optimize::Parameter;
+use simlin_compat::engine::partial::stub_broken_variables;
+use simlin_compat::engine::plot::Chart;
+use simlin_compat::engine::query::{Query, VariableKind};
+use simlin_compat::engine::replace::Find;
+use simlin_compat::engine::resample::Interpolation;
+use simlin_compat::engine::scaffold::{new_project, scaffold_names};
+use simlin_compat::engine::sensitivity::{run_sensitivity, sample_runs, Spec};
+use simlin_compat::engine::smoothing::Smoothing;
+use simlin_compat::engine::stubs::{Stub, Stubs};
+use simlin_compat::engine::workspace::Workspace;
 use simlin_compat::engine::{
-    build_sim_with_stderrors, datamodel, eprintln, project_io, serde, Error, ErrorCode, Project,
-    Result, Results, Variable, Vm,
+    build_sim_with_stderrors, datamodel, eprintln, json, project_io, quoteize, serde, Error,
+    ErrorCode, Project, Result, Results, Simulation, Variable, Vm,
 };
 use simlin_compat::prost::Message;
-use simlin_compat::{load_csv, load_dat, open_vensim, open_xmile, to_xmile};
+use simlin_compat::sqlite::export_runs;
+use simlin_compat::workspace::{load_data, open_workspace, resolve};
+use simlin_compat::{
+    input_series, load_run, open_vensim, open_xmile_with_strictness, set_array_data, to_xmile,
+    Strictness,
+};
 
 const VERSION: &str = "1.0";
 const EXIT_FAILURE: i32 = 1;
+const EXIT_IO_ERROR: i32 = 2;
+const EXIT_PARSE_ERROR: i32 = 3;
+const EXIT_MODEL_ERROR: i32 = 4;
+const EXIT_SIMULATION_ERROR: i32 = 5;
+const EXIT_TEST_FAILURE: i32 = 6;
 
 #[macro_export]
 macro_rules! die(
@@ -40,41 +73,505 @@ fn usage() -> ! {
          \n\
          USAGE:\n",
             "    {} [SUBCOMMAND] [OPTION...] PATH\n",
+            "    {} tree [--depth N] VAR PATH\n",
+            "    {} graph [--initial | --combined] PATH\n",
+            "    {} plot [--var VAR... | --graph N] [OPTION...] PATH\n",
+            "    {} units [--explain VAR] PATH\n",
+            "    {} sensitivity --spec FILE [--output FILE] PATH\n",
+            "    {} export-db [--spec FILE] --output FILE PATH\n",
+            "    {} calibrate --observed FILE --fit PATH=LOW:HIGH... [OPTION...] PATH\n",
+            "    {} capabilities\n",
+            "    {} explain-error CODE\n",
+            "    {} molecules list\n",
+            "    {} molecules insert [OPTION...] NAME PATH\n",
+            "    {} set-data [OPTION...] VAR DATA PATH\n",
+            "    {} new --template NAME [--output FILE] [PROJECT_NAME]\n",
+            "    {} workspace new [--output FILE] MODEL\n",
+            "    {} workspace run WORKSPACE\n",
             "\n\
+         PATH may be '-' to read the model from stdin.\n\
+         \n\
          OPTIONS:\n",
             "    -h, --help       show this message\n",
             "    --vensim         model is a Vensim .mdl file\n",
-            "    --pb-input       input is binary protobuf project\n",
-            "    --to-xmile       output should be XMILE not protobuf\n",
+            "    --pb-input       input is a binary protobuf project (output of convert)\n",
+            "    --json-input     input is a JSON project (output of convert --to json)\n",
+            "    --strict         reject XMILE input that doesn't follow the v1.0 spec\n",
+            "    --to FORMAT      for conversion, write 'protobuf' (the default), 'xmile'\n",
+            "                     or 'json'\n",
+            "    --to-xmile       the same as --to xmile\n",
             "    --model-only     for conversion, only output model instead of project\n",
-            "    --output FILE    path to write output file\n",
-            "    --reference FILE reference TSV for debug subcommand\n",
+            "    --canonical      for conversion, order the protobuf output by name, so\n",
+            "                     equivalent projects produce identical bytes\n",
+            "    --anonymize      for conversion, rename every variable to an opaque\n",
+            "                     identifier and leave out documentation\n",
+            "    --output FILE    path to write output file ('-' for stdout, the default)\n",
+            "    --reference FILE reference run for debug subcommand: TSV, CSV, or a\n",
+            "                     Vensim .dat or .tab export\n",
+            "    --resample METHOD  how to line up a reference saved at different times:\n",
+            "                     'linear' interpolation (default) or 'hold' the last value\n",
             "    --no-output      don't print the output (for benchmarking)\n",
+            "    --all-variables  print every variable, even if the model has a saved table\n",
+            "    --no-supplementary  leave out variables only computed to be reported\n",
+            "    --error-format FMT  report errors as 'text' (default) or 'json'\n",
+            "    --allow-errors   simulate variables with errors as NaN, rather than failing\n",
+            "    -p PATH=VALUE    set a variable to a constant when simulating, where\n",
+            "                     PATH may reach into modules, like lynxes.init=10\n",
+            "    --at TIME:PATH=VALUE  change a constant to VALUE from TIME on, exactly\n",
+            "                     at TIME even between time steps, like --at 5.5:tax=0.2\n",
+            "    --data FILE      drive variables with time series from a CSV or TSV file,\n",
+            "                     one column per variable, interpolated between rows\n",
+            "    --smooth WIDTH   replace STEP, IF comparisons, MIN and MAX with smooth\n",
+            "                     approximations changing over about WIDTH, for calibration\n",
+            "\n\
+         REPLACE OPTIONS:\n",
+            "    --find IDENT     replace a variable, function or unit name\n",
+            "    --regex PATTERN  replace matches of a regular expression\n",
+            "    --with TEXT      the replacement ($1 refers to a regex capture group)\n",
+            "    --dry-run        print the changes as a diff rather than the new project\n",
+            "\n\
+         MOLECULE OPTIONS:\n",
+            "    --prefix NAME    prepended to the names of the new variables\n",
+            "    --arg PARAM=EQN  the equation to use for one of the molecule's params\n",
+            "    --position X,Y   also add the variables to the diagram at X,Y\n",
+            "\n\
+         GREP OPTIONS:\n",
+            "    --type KIND      only stocks, flows, auxs or modules\n",
+            "    --name TEXT      name contains TEXT\n",
+            "    --eqn TEXT       equation contains TEXT\n",
+            "    --dimension DIM  variable is arrayed over DIM\n",
+            "    --missing-units  variable has no units\n",
+            "\n\
+         TREE OPTIONS:\n",
+            "    --depth N        how many levels of dependencies to show (default 5)\n",
+            "\n\
+         GRAPH OPTIONS:\n",
+            "    --initial        show the dependencies used to compute initial values\n",
+            "    --combined       show both, coloring initial-only edges red and\n",
+            "                     time step-only edges blue\n",
+            "\n\
+         PLOT OPTIONS:\n",
+            "    --var VAR        a variable to plot; may be given more than once\n",
+            "    --graph N        plot the Nth graph saved with the model, with its\n",
+            "                     colors and scales (default, with N = 0)\n",
+            "    --title TEXT     the chart's title\n",
+            "    --reference FILE also plot the variables from a reference run, dashed\n",
+            "    --sweep PATH=LOW:HIGH  shade the range each variable covers as PATH\n",
+            "                     varies from LOW to HIGH\n",
+            "    --runs N         how many runs the sweep takes (default 10)\n",
+            "\n\
+         SENSITIVITY OPTIONS:\n",
+            "    --spec FILE      JSON listing the parameters to vary, each with a uniform,\n",
+            "                     normal or triangular distribution, the variables to\n",
+            "                     report, and optionally the runs, seed, percentiles and\n",
+            "                     sampling: 'monte_carlo' (default) or 'latin_hypercube'\n",
+            "\n\
+         CALIBRATE OPTIONS:\n",
+            "    --observed FILE  observed data to fit, as CSV, TSV or a Vensim export,\n",
+            "                     with a column per variable\n",
+            "    --fit PATH=LOW:HIGH  a constant to fit, between LOW and HIGH; may be\n",
+            "                     given more than once\n",
+            "    --var VAR        only fit to this observed variable; may be given more\n",
+            "                     than once (default: every one the model has)\n",
+            "    --metric METRIC  the error to minimize: 'mse' (default) or 'mae'\n",
+            "\n\
+         EXPORT-DB OPTIONS:\n",
+            "    --spec FILE      simulate the runs of a sensitivity spec, rather than\n",
+            "                     a single run with any -p overrides\n",
+            "\n\
+         UNITS OPTIONS:\n",
+            "    --explain VAR    show the equations that determined VAR's units\n",
+            "\n\
+         LINT OPTIONS:\n",
+            "    --max-depth N    flag equations nested more than N deep (default 6)\n",
+            "    --max-operands N flag equations with more than N variables and constants\n",
+            "                     (default 12)\n",
+            "    --max-units N    flag equations referring to more than N different units\n",
+            "                     (default 3)\n",
             "\n\
          SUBCOMMANDS:\n",
-            "    simulate         Simulate a model and display output\n",
-            "    convert          Convert an XMILE or Vensim model to protobuf\n",
+            "    simulate         Simulate a model (XMILE, Vensim, protobuf or JSON) and display output\n",
+            "    convert          Convert an XMILE or Vensim model to protobuf, XMILE or JSON\n",
             "    equations        Print the equations out\n",
             "    debug            Output model equations interleaved with a reference run\n",
+            "    grep             List the variables matching the grep options\n",
+            "    test             Run the tests stored in the project\n",
+            "    tree             Print the upstream dependencies of VAR (like hares.births)\n",
+            "    graph            Print the model's dependency graph in Graphviz DOT format\n",
+            "    plot             Render a chart of variables over time as SVG\n",
+            "    units            Print the declared and inferred units of each variable\n",
+            "    sensitivity      Simulate with parameters drawn at random and print the\n",
+            "                     percentile bands of each variable as CSV\n",
+            "    export-db        Write the results of one or more runs to a SQLite database\n",
+            "    calibrate        Fit constants to observed data, printing the fit and\n",
+            "                     writing the fitted project like convert\n",
+            "    replace          Find and replace in every equation and units string\n",
+            "    stats            Print the size of the compiled model and its results\n",
+            "    lint             Report duplicated and overly complex equations\n",
+            "    capabilities     List the optional features this build supports\n",
+            "    explain-error    Explain an error code, like E0021\n",
+            "    molecules        List the molecule library, or insert one into the main model\n",
+            "    set-data         Set the elements of arrayed constant VAR from a CSV matrix\n",
+            "    new              Write a starter XMILE project: sir, bass or inventory\n",
+            "    workspace        Write a workspace referring to MODEL (relative to the\n",
+            "                     workspace file), or simulate a workspace's saved runs,\n",
+            "                     writing their results, and run its tests\n",
+            "\n\
+         EXIT CODES:\n",
+            "    0                success\n",
+            "    1                bad command line arguments\n",
+            "    2                error reading or writing a file\n",
+            "    3                error parsing or converting the model\n",
+            "    4                model has errors and can't be simulated\n",
+            "    5                error at simulation time\n",
+            "    6                one or more model tests failed\n",
         ),
         VERSION,
+        argv0,
+        argv0,
+        argv0,
+        argv0,
+        argv0,
+        argv0,
+        argv0,
+        argv0,
+        argv0,
+        argv0,
+        argv0,
+        argv0,
+        argv0,
+        argv0,
+        argv0,
         argv0
     );
 }
 
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+enum ErrorFormat {
+    #[default]
+    Text,
+    Json,
+}
+
 #[derive(Clone, Default, Debug)]
 struct Args {
     path: Option<String>,
     output: Option<String>,
     reference: Option<String>,
+    resample: Interpolation,
     is_vensim: bool,
     is_pb_input: bool,
+    is_json_input: bool,
+    is_strict: bool,
+    allow_errors: bool,
     is_to_xmile: bool,
+    is_to_json: bool,
     is_convert: bool,
     is_model_only: bool,
+    is_canonical: bool,
+    is_anonymize: bool,
     is_no_output: bool,
+    is_all_variables: bool,
+    is_no_supplementary: bool,
     is_equations: bool,
     is_debug: bool,
+    is_grep: bool,
+    is_test: bool,
+    is_tree: bool,
+    is_stats: bool,
+    is_lint: bool,
+    complexity_limits: ComplexityLimits,
+    is_capabilities: bool,
+    explain_error: Option<String>,
+    new_template: Option<String>,
+    new_project_name: Option<String>,
+    is_molecules_list: bool,
+    is_workspace_new: bool,
+    is_workspace_run: bool,
+    is_molecules_insert: bool,
+    molecule: Option<String>,
+    molecule_prefix: String,
+    molecule_args: Vec<(String, String)>,
+    molecule_at: Option<(f64, f64)>,
+    is_replace: bool,
+    is_dry_run: bool,
+    set_data: Option<(String, String)>,
+    find: Option<Find>,
+    replacement: String,
+    tree_var: Option<String>,
+    tree_depth: usize,
+    is_graph: bool,
+    graph_kind: DependencyKind,
+    is_plot: bool,
+    plot_vars: Vec<String>,
+    plot_graph: usize,
+    plot_title: Option<String>,
+    plot_sweep: Option<Sweep>,
+    plot_runs: usize,
+    is_units: bool,
+    is_sensitivity: bool,
+    is_export_db: bool,
+    calibrate_observed: Option<String>,
+    calibrate_fit: Vec<Sweep>,
+    calibrate_metric: ErrorMetric,
+    sensitivity_spec: Option<String>,
+    units_explain: Option<String>,
+    query: Query,
+    error_format: ErrorFormat,
+    overrides: Vec<(String, f64)>,
+    schedule: Schedule,
+    data: Vec<String>,
+    smoothing: Option<Smoothing>,
+}
+
+/// Sweep varies a constant evenly between two values over a set of runs.
+#[derive(Clone, Debug)]
+struct Sweep {
+    path: String,
+    low: f64,
+    high: f64,
+}
+
+/// FailureKind classifies why a run failed, and determines the exit code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FailureKind {
+    Io,
+    Parse,
+    Model,
+    Simulation,
+    Test,
+}
+
+impl FailureKind {
+    fn exit_code(self) -> i32 {
+        match self {
+            FailureKind::Io => EXIT_IO_ERROR,
+            FailureKind::Parse => EXIT_PARSE_ERROR,
+            FailureKind::Model => EXIT_MODEL_ERROR,
+            FailureKind::Simulation => EXIT_SIMULATION_ERROR,
+            FailureKind::Test => EXIT_TEST_FAILURE,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            FailureKind::Io => "io",
+            FailureKind::Parse => "parse",
+            FailureKind::Model => "model",
+            FailureKind::Simulation => "simulation",
+            FailureKind::Test => "test",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct VariableError {
+    model: String,
+    variable: String,
+    code: ErrorCode,
+    start: u16,
+    end: u16,
+    details: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+struct CliError {
+    kind: FailureKind,
+    code: Option<ErrorCode>,
+    message: String,
+    variable_errors: Vec<VariableError>,
+}
+
+impl CliError {
+    fn new(kind: FailureKind, code: Option<ErrorCode>, message: String) -> Self {
+        CliError {
+            kind,
+            code,
+            message,
+            variable_errors: vec![],
+        }
+    }
+
+    fn io(path: &str, err: std::io::Error) -> Self {
+        CliError::new(FailureKind::Io, None, format!("{}: {}", path, err))
+    }
+
+    fn engine(kind: FailureKind, err: &Error) -> Self {
+        CliError::new(kind, Some(err.code), format!("{}", err))
+    }
+
+    fn load(err: &(dyn std::error::Error + 'static), message: String) -> Self {
+        let code = err.downcast_ref::<Error>().map(|err| err.code);
+        CliError::new(load_failure_kind(err), code, message)
+    }
+
+    fn to_json(&self, path: &str) -> String {
+        let payload = JsonError {
+            kind: self.kind.name(),
+            exit_code: self.kind.exit_code(),
+            code: self.code.map(|code| code.to_string()),
+            code_id: self.code.map(|code| code.id()),
+            message: &self.message,
+            path,
+            variable_errors: self
+                .variable_errors
+                .iter()
+                .map(|err| JsonVariableError {
+                    model: &err.model,
+                    variable: &err.variable,
+                    code: err.code.to_string(),
+                    code_id: err.code.id(),
+                    start: err.start,
+                    end: err.end,
+                    details: err.details.as_deref(),
+                })
+                .collect(),
+        };
+        serde_json::to_string(&payload).unwrap()
+    }
+}
+
+/// JsonError is what `--error-format json` writes to stderr when a
+/// command fails.
+#[derive(Serialize)]
+struct JsonError<'a> {
+    kind: &'static str,
+    exit_code: i32,
+    code: Option<String>,
+    code_id: Option<String>,
+    message: &'a str,
+    path: &'a str,
+    variable_errors: Vec<JsonVariableError<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonVariableError<'a> {
+    model: &'a str,
+    variable: &'a str,
+    code: String,
+    code_id: String,
+    start: u16,
+    end: u16,
+    details: Option<&'a str>,
+}
+
+/// JsonImportIssue is what `--error-format json` writes to stderr for
+/// each construct dropped on import.
+#[derive(Serialize)]
+struct JsonImportIssue<'a> {
+    kind: &'static str,
+    construct: &'a str,
+    location: &'a str,
+    reason: &'a str,
+    path: &'a str,
+}
+
+fn report_error(err: &CliError, format: ErrorFormat, path: &str) {
+    match format {
+        ErrorFormat::Text if err.kind == FailureKind::Io => {
+            eprintln!("error: {}", err.message);
+        }
+        ErrorFormat::Text => match err.code {
+            // the ID can be looked up with the explain-error subcommand
+            Some(code) => eprintln!("model '{}' error: {} [{}]", path, err.message, code.id()),
+            None => eprintln!("model '{}' error: {}", path, err.message),
+        },
+        ErrorFormat::Json => {
+            eprintln!("{}", err.to_json(path));
+        }
+    }
+}
+
+/// report_import_issues warns about anything left out when importing
+/// the model, so lost fidelity doesn't come as a surprise later.
+fn report_import_issues(issues: &[ImportIssue], format: ErrorFormat, path: &str) {
+    for issue in issues.iter() {
+        match format {
+            ErrorFormat::Text => {
+                let location = if issue.location.is_empty() {
+                    path.to_owned()
+                } else {
+                    format!("{}: {}", path, issue.location)
+                };
+                eprintln!(
+                    "warning: {}: dropped <{}>: {}",
+                    location, issue.construct, issue.reason
+                );
+            }
+            ErrorFormat::Json => {
+                let payload = JsonImportIssue {
+                    kind: "import",
+                    construct: &issue.construct,
+                    location: &issue.location,
+                    reason: &issue.reason,
+                    path,
+                };
+                eprintln!("{}", serde_json::to_string(&payload).unwrap());
+            }
+        }
+    }
+}
+
+/// parse_override parses a `PATH=VALUE` override, like `lynxes.init=10`.
+fn parse_override(arg: &str) -> StdResult<(String, f64), String> {
+    let (path, value) = arg
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected PATH=VALUE, not '{}'", arg))?;
+    let value = value
+        .trim()
+        .parse::<f64>()
+        .map_err(|err| format!("bad value for {}: {}", path, err))?;
+    Ok((path.trim().to_owned(), value))
+}
+
+/// parse_change parses a `TIME:PATH=VALUE` scheduled change, like
+/// `5.5:tax=0.2`.
+fn parse_change(arg: &str) -> StdResult<Change, String> {
+    let (time, rest) = arg
+        .split_once(':')
+        .ok_or_else(|| format!("expected TIME:PATH=VALUE, not '{}'", arg))?;
+    let time = time
+        .trim()
+        .parse::<f64>()
+        .map_err(|err| format!("bad time in '{}': {}", arg, err))?;
+    let (path, value) = parse_override(rest)?;
+    Ok(Change { time, path, value })
+}
+
+/// parse_sweep parses a `PATH=LOW:HIGH` sweep, like `birth_rate=0.05:0.15`.
+fn parse_sweep(arg: &str) -> StdResult<Sweep, String> {
+    let (path, range) = arg
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected PATH=LOW:HIGH, not '{}'", arg))?;
+    let (low, high) = range
+        .split_once(':')
+        .ok_or_else(|| format!("expected PATH=LOW:HIGH, not '{}'", arg))?;
+    let parse = |value: &str| {
+        value
+            .trim()
+            .parse::<f64>()
+            .map_err(|err| format!("bad value for {}: {}", path, err))
+    };
+    Ok(Sweep {
+        path: path.trim().to_owned(),
+        low: parse(low)?,
+        high: parse(high)?,
+    })
+}
+
+fn parse_molecule_arg(arg: &str) -> StdResult<(String, String), String> {
+    match arg.split_once('=') {
+        Some((param, eqn)) => Ok((param.to_owned(), eqn.to_owned())),
+        None => Err(format!("expected PARAM=EQN, not '{}'", arg)),
+    }
+}
+
+fn parse_position(arg: &str) -> StdResult<(f64, f64), String> {
+    let bad = || format!("expected X,Y, not '{}'", arg);
+    let (x, y) = arg.split_once(',').ok_or_else(bad)?;
+    let x = x.trim().parse().map_err(|_| bad())?;
+    let y = y.trim().parse().map_err(|_| bad())?;
+    Ok((x, y))
 }
 
 fn parse_args() -> StdResult<Args, Box<dyn std::error::Error>> {
@@ -99,6 +596,86 @@ fn parse_args() -> StdResult<Args, Box<dyn std::error::Error>> {
         args.is_equations = true;
     } else if subcommand == "debug" {
         args.is_debug = true;
+    } else if subcommand == "grep" {
+        args.is_grep = true;
+    } else if subcommand == "test" {
+        args.is_test = true;
+    } else if subcommand == "tree" {
+        args.is_tree = true;
+    } else if subcommand == "graph" {
+        args.is_graph = true;
+    } else if subcommand == "plot" {
+        args.is_plot = true;
+    } else if subcommand == "units" {
+        args.is_units = true;
+    } else if subcommand == "sensitivity" {
+        args.is_sensitivity = true;
+    } else if subcommand == "export-db" {
+        args.is_export_db = true;
+    } else if subcommand == "calibrate" {
+        // the fitted project is written out like by convert
+        args.calibrate_observed = Some(Default::default());
+        args.is_convert = true;
+    } else if subcommand == "stats" {
+        args.is_stats = true;
+    } else if subcommand == "lint" {
+        args.is_lint = true;
+    } else if subcommand == "replace" {
+        // the edited project is written out like by convert
+        args.is_replace = true;
+        args.is_convert = true;
+    } else if subcommand == "set-data" {
+        // the edited project is written out like by convert
+        args.set_data = Some(Default::default());
+        args.is_convert = true;
+    } else if subcommand == "capabilities" {
+        args.is_capabilities = true;
+    } else if subcommand == "molecules" {
+        match parsed.subcommand()?.as_deref() {
+            Some("list") => {
+                args.is_molecules_list = true;
+                return Ok(args);
+            }
+            // the project with the molecule added is written out like by
+            // convert
+            Some("insert") => {
+                args.is_molecules_insert = true;
+                args.is_convert = true;
+            }
+            _ => {
+                eprintln!("error: molecules needs list or insert");
+                usage();
+            }
+        }
+    } else if subcommand == "workspace" {
+        match parsed.subcommand()?.as_deref() {
+            Some("new") => args.is_workspace_new = true,
+            Some("run") => args.is_workspace_run = true,
+            _ => {
+                eprintln!("error: workspace needs new or run");
+                usage();
+            }
+        }
+    } else if subcommand == "new" {
+        args.new_template = parsed.opt_value_from_str("--template")?;
+        if args.new_template.is_none() {
+            eprintln!("error: --template required");
+            usage();
+        }
+        args.output = parsed.value_from_str("--output").ok();
+        let mut free_arguments = parsed.finish();
+        if !free_arguments.is_empty() {
+            args.new_project_name = free_arguments.remove(0).to_str().map(|s| s.to_owned());
+        }
+        return Ok(args);
+    } else if subcommand == "explain-error" {
+        let mut free_arguments = parsed.finish();
+        if free_arguments.is_empty() {
+            eprintln!("error: error code required");
+            usage();
+        }
+        args.explain_error = free_arguments.remove(0).to_str().map(|s| s.to_owned());
+        return Ok(args);
     } else {
         eprintln!("error: unknown subcommand {}", subcommand);
         usage();
@@ -107,12 +684,171 @@ fn parse_args() -> StdResult<Args, Box<dyn std::error::Error>> {
     args.output = parsed.value_from_str("--output").ok();
     args.reference = parsed.value_from_str("--reference").ok();
     args.is_no_output = parsed.contains("--no-output");
+    args.is_all_variables = parsed.contains("--all-variables");
+    args.is_no_supplementary = parsed.contains("--no-supplementary");
     args.is_model_only = parsed.contains("--model-only");
+    args.is_canonical = parsed.contains("--canonical");
+    args.is_anonymize = parsed.contains("--anonymize");
     args.is_to_xmile = parsed.contains("--to-xmile");
+    match parsed.opt_value_from_str::<_, String>("--to")?.as_deref() {
+        Some("xmile") => args.is_to_xmile = true,
+        Some("json") => args.is_to_json = true,
+        Some("protobuf") | None => {}
+        Some(format) => {
+            eprintln!("error: unknown output format {}", format);
+            usage();
+        }
+    }
     args.is_vensim = parsed.contains("--vensim");
     args.is_pb_input = parsed.contains("--pb-input");
+    args.is_json_input = parsed.contains("--json-input");
+    args.is_strict = parsed.contains("--strict");
+    args.allow_errors = parsed.contains("--allow-errors");
+    args.overrides = parsed.values_from_fn("-p", parse_override)?;
+    args.schedule = Schedule {
+        changes: parsed.values_from_fn("--at", parse_change)?,
+    };
+    args.data = parsed.values_from_str("--data")?;
+    args.smoothing = parsed
+        .opt_value_from_str::<_, f64>("--smooth")?
+        .map(|width| Smoothing {
+            width,
+            step_width: width,
+        });
+    args.tree_depth = parsed.opt_value_from_str("--depth")?.unwrap_or(5);
+    let default_limits = ComplexityLimits::default();
+    args.complexity_limits = ComplexityLimits {
+        max_depth: parsed
+            .opt_value_from_str("--max-depth")?
+            .unwrap_or(default_limits.max_depth),
+        max_operands: parsed
+            .opt_value_from_str("--max-operands")?
+            .unwrap_or(default_limits.max_operands),
+        max_units: parsed
+            .opt_value_from_str("--max-units")?
+            .unwrap_or(default_limits.max_units),
+    };
+    args.graph_kind = if parsed.contains("--combined") {
+        DependencyKind::Combined
+    } else if parsed.contains("--initial") {
+        DependencyKind::Initial
+    } else {
+        DependencyKind::Dt
+    };
+    args.plot_vars = parsed.values_from_str("--var")?;
+    args.plot_graph = parsed.opt_value_from_str("--graph")?.unwrap_or(0);
+    args.plot_title = parsed.opt_value_from_str("--title")?;
+    args.plot_sweep = parsed.opt_value_from_fn("--sweep", parse_sweep)?;
+    args.plot_runs = parsed.opt_value_from_str("--runs")?.unwrap_or(10);
+    if args.plot_runs < 2 {
+        eprintln!("error: --runs must be at least 2");
+        usage();
+    }
+    args.units_explain = parsed.opt_value_from_str("--explain")?;
+    args.sensitivity_spec = parsed.opt_value_from_str("--spec")?;
+    if args.is_sensitivity && args.sensitivity_spec.is_none() {
+        eprintln!("error: sensitivity needs --spec FILE");
+        usage();
+    }
+    if args.calibrate_observed.is_some() {
+        args.calibrate_observed = parsed.opt_value_from_str("--observed")?;
+        args.calibrate_fit = parsed.values_from_fn("--fit", parse_sweep)?;
+        if args.calibrate_observed.is_none() || args.calibrate_fit.is_empty() {
+            eprintln!("error: calibrate needs --observed FILE and --fit PATH=LOW:HIGH");
+            usage();
+        }
+        args.calibrate_metric = match parsed.opt_value_from_str::<_, String>("--metric")? {
+            None => ErrorMetric::Mse,
+            Some(name) => match ErrorMetric::from_name(&name) {
+                Some(metric) => metric,
+                None => {
+                    eprintln!("error: unknown error metric '{}'", name);
+                    usage();
+                }
+            },
+        };
+    }
+    if args.is_export_db && is_stdio(args.output.as_deref()) {
+        eprintln!("error: export-db needs --output FILE");
+        usage();
+    }
+    args.find = match parsed.opt_value_from_str::<_, String>("--regex")? {
+        Some(pattern) => Some(Find::regex(&pattern)?),
+        None => parsed
+            .opt_value_from_str::<_, String>("--find")?
+            .map(Find::Ident),
+    };
+    args.replacement = parsed.opt_value_from_str("--with")?.unwrap_or_default();
+    args.is_dry_run = parsed.contains("--dry-run");
+    if args.is_replace && args.find.is_none() {
+        eprintln!("error: replace needs --find IDENT or --regex PATTERN");
+        usage();
+    }
+    args.molecule_prefix = parsed.opt_value_from_str("--prefix")?.unwrap_or_default();
+    args.molecule_args = parsed.values_from_fn("--arg", parse_molecule_arg)?;
+    args.molecule_at = parsed.opt_value_from_fn("--position", parse_position)?;
+    args.query = Query {
+        kind: match parsed.opt_value_from_str::<_, String>("--type")? {
+            None => None,
+            Some(kind) => match VariableKind::from_name(&kind) {
+                Some(kind) => Some(kind),
+                None => {
+                    eprintln!("error: unknown variable type '{}'", kind);
+                    usage();
+                }
+            },
+        },
+        name_contains: parsed.opt_value_from_str("--name")?,
+        equation_contains: parsed.opt_value_from_str("--eqn")?,
+        dimension: parsed.opt_value_from_str("--dimension")?,
+        missing_units: parsed.contains("--missing-units"),
+    };
+    args.resample = match parsed.opt_value_from_str::<_, String>("--resample")? {
+        None => Interpolation::Linear,
+        Some(method) if method == "linear" => Interpolation::Linear,
+        Some(method) if method == "hold" => Interpolation::Hold,
+        Some(method) => {
+            eprintln!("error: unknown resampling method '{}'", method);
+            usage();
+        }
+    };
+    args.error_format = match parsed.opt_value_from_str::<_, String>("--error-format")? {
+        None => ErrorFormat::Text,
+        Some(format) if format == "text" => ErrorFormat::Text,
+        Some(format) if format == "json" => ErrorFormat::Json,
+        Some(format) => {
+            eprintln!("error: unknown error format '{}'", format);
+            usage();
+        }
+    };
 
-    let free_arguments = parsed.finish();
+    let mut free_arguments = parsed.finish();
+    if args.is_tree {
+        if free_arguments.is_empty() {
+            eprintln!("error: variable required");
+            usage();
+        }
+        args.tree_var = free_arguments.remove(0).to_str().map(|s| s.to_owned());
+    }
+    if args.is_molecules_insert {
+        if free_arguments.is_empty() {
+            eprintln!("error: molecule name required");
+            usage();
+        }
+        args.molecule = free_arguments.remove(0).to_str().map(|s| s.to_owned());
+    }
+    if args.set_data.is_some() {
+        if free_arguments.len() < 2 {
+            eprintln!("error: variable and data file required");
+            usage();
+        }
+        let var = free_arguments.remove(0).to_string_lossy().into_owned();
+        let data_path = free_arguments.remove(0).to_string_lossy().into_owned();
+        args.set_data = Some((var, data_path));
+    }
+    if args.is_capabilities {
+        return Ok(args);
+    }
     if free_arguments.is_empty() {
         eprintln!("error: input path required");
         usage();
@@ -123,13 +859,16 @@ fn parse_args() -> StdResult<Args, Box<dyn std::error::Error>> {
     Ok(args)
 }
 
+/// open_binary reads a protobuf-encoded project, as written by the
+/// convert subcommand.  The encoding is binary and may contain NUL bytes,
+/// so the whole stream is read before decoding.
 fn open_binary(reader: &mut dyn BufRead) -> Result<datamodel::Project> {
     let mut contents_buf: Vec<u8> = vec![];
-    reader.read_until(0, &mut contents_buf).map_err(|_err| {
+    reader.read_to_end(&mut contents_buf).map_err(|err| {
         Error::new(
             ErrorKind::Import,
-            ErrorCode::VensimConversion,
-            Some("1".to_owned()),
+            ErrorCode::ProtobufDecode,
+            Some(format!("{}", err)),
         )
     })?;
 
@@ -138,7 +877,7 @@ fn open_binary(reader: &mut dyn BufRead) -> Result<datamodel::Project> {
         Err(err) => {
             return Err(Error::new(
                 ErrorKind::Import,
-                ErrorCode::VensimConversion,
+                ErrorCode::ProtobufDecode,
                 Some(format!("{}", err)),
             ));
         }
@@ -146,53 +885,768 @@ fn open_binary(reader: &mut dyn BufRead) -> Result<datamodel::Project> {
     Ok(project)
 }
 
-fn simulate(project: &DatamodelProject) -> Results {
-    let sim = build_sim_with_stderrors(project).unwrap();
-    let compiled = sim.compile().unwrap();
-    let mut vm = Vm::new(compiled).unwrap();
-    vm.run_to_end().unwrap();
-    vm.into_results()
+/// open_json reads a project written by `convert --to json`.
+fn open_json(reader: &mut dyn BufRead) -> Result<datamodel::Project> {
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents).map_err(|err| {
+        Error::new(
+            ErrorKind::Import,
+            ErrorCode::JsonDeserialization,
+            Some(format!("{}", err)),
+        )
+    })?;
+    json::deserialize(&contents)
 }
 
-fn main() {
-    let args = match parse_args() {
-        Ok(args) => args,
-        Err(err) => {
-            eprintln!("error: {}", err);
-            usage();
+/// collect_variable_errors gathers equation and unit errors for all
+/// (non-stdlib) models in the project.
+fn collect_variable_errors(project: &Project) -> Vec<VariableError> {
+    let mut errors = vec![];
+    let mut model_names: Vec<_> = project
+        .models
+        .iter()
+        .filter(|(_, model)| !model.implicit)
+        .map(|(name, _)| name.as_str())
+        .collect();
+    model_names.sort_unstable();
+    for model_name in model_names {
+        let model = &project.models[model_name];
+        let mut var_errors: Vec<_> = model.get_variable_errors().into_iter().collect();
+        var_errors.sort_by(|a, b| a.0.cmp(&b.0));
+        for (ident, var_errors) in var_errors {
+            for err in var_errors {
+                errors.push(VariableError {
+                    model: model_name.to_owned(),
+                    variable: ident.clone(),
+                    code: err.code,
+                    start: err.start,
+                    end: err.end,
+                    details: None,
+                });
+            }
         }
+        let mut unit_errors: Vec<_> = model.get_unit_errors().into_iter().collect();
+        unit_errors.sort_by(|a, b| a.0.cmp(&b.0));
+        for (ident, unit_errors) in unit_errors {
+            for err in unit_errors {
+                let (code, start, end, details) = match err {
+                    UnitError::DefinitionError(err, details) => {
+                        (err.code, err.start, err.end, details)
+                    }
+                    UnitError::ConsistencyError(code, loc, details) => {
+                        (code, loc.start, loc.end, details)
+                    }
+                };
+                errors.push(VariableError {
+                    model: model_name.to_owned(),
+                    variable: ident.clone(),
+                    code,
+                    start,
+                    end,
+                    details,
+                });
+            }
+        }
+    }
+    errors
+}
+
+fn build_sim(project: &DatamodelProject, format: ErrorFormat) -> StdResult<Simulation, CliError> {
+    let not_simulatable = || {
+        CliError::new(
+            FailureKind::Model,
+            Some(ErrorCode::NotSimulatable),
+            "model has errors and can't be simulated".to_owned(),
+        )
     };
-    let file_path = args.path.unwrap_or_else(|| "/dev/stdin".to_string());
-    let file = File::open(&file_path).unwrap();
-    let mut reader = BufReader::new(file);
+    if format == ErrorFormat::Text {
+        // errors are printed to stderr with context as they are found
+        return build_sim_with_stderrors(project).ok_or_else(not_simulatable);
+    }
+
+    let project = Rc::new(Project::from(project.clone()));
+    Simulation::new(&project, "main").map_err(|err| {
+        let mut cli_err = CliError::engine(FailureKind::Model, &err);
+        cli_err.variable_errors = collect_variable_errors(&project);
+        cli_err
+    })
+}
+
+/// build_stubbed_sim builds the simulation of the main model with any
+/// overrides, scheduled changes and data inputs applied.  If
+/// `allow_errors` is set, variables with errors are simulated as NaN
+/// rather than preventing simulation.
+fn build_stubbed_sim(
+    project: &DatamodelProject,
+    format: ErrorFormat,
+    overrides: &[(String, f64)],
+    schedule: &Schedule,
+    inputs: &[(String, Vec<(f64, f64)>)],
+    allow_errors: bool,
+) -> StdResult<Simulation, CliError> {
+    let mut stubs = Stubs::new();
+    if allow_errors {
+        let (broken_stubs, broken) = stub_broken_variables(project, f64::NAN);
+        for var in broken.iter() {
+            eprintln!(
+                "warning: simulating variable '{}' in model '{}' as NaN, as it has errors",
+                var.ident, var.model_name
+            );
+        }
+        stubs = broken_stubs;
+    }
+    if stubs.is_empty() {
+        // building the model as-is reports any errors in detail
+        let sim = build_sim(project, format)?;
+        if overrides.is_empty() && schedule.is_empty() && inputs.is_empty() {
+            return Ok(sim);
+        }
+    }
+    for (path, series) in inputs.iter() {
+        stubs.stub_path("main", path, Stub::Series(series.clone()));
+    }
+    for (path, value) in overrides.iter() {
+        stubs.stub_path("main", path, Stub::Constant(*value));
+    }
+    schedule
+        .stub("main", &mut stubs)
+        .map_err(|err| CliError::engine(FailureKind::Model, &err))?;
+    let project = Project::from_with_stubs(project.clone(), &stubs)
+        .map_err(|err| CliError::engine(FailureKind::Model, &err))?;
+    let mut sim = Simulation::new(&project, "main")
+        .map_err(|err| CliError::engine(FailureKind::Model, &err))?;
+    sim.set_event_times(&schedule.times());
+    Ok(sim)
+}
+
+fn simulate(
+    project: &DatamodelProject,
+    format: ErrorFormat,
+    overrides: &[(String, f64)],
+    schedule: &Schedule,
+    inputs: &[(String, Vec<(f64, f64)>)],
+    allow_errors: bool,
+    smoothing: Option<Smoothing>,
+) -> StdResult<Results, CliError> {
+    let mut sim = build_stubbed_sim(project, format, overrides, schedule, inputs, allow_errors)?;
+    sim.set_smoothing(smoothing);
+    let compiled = sim
+        .compile()
+        .map_err(|err| CliError::engine(FailureKind::Simulation, &err))?;
+    let mut vm =
+        Vm::new(compiled).map_err(|err| CliError::engine(FailureKind::Simulation, &err))?;
+    vm.run_to_end()
+        .map_err(|err| CliError::engine(FailureKind::Simulation, &err))?;
+    Ok(vm.into_results())
+}
+
+/// is_stdio returns true if the given path refers to stdin or stdout
+/// rather than a file on disk.
+fn is_stdio(path: Option<&str>) -> bool {
+    matches!(path, None | Some("-"))
+}
+
+fn display_path(path: Option<&str>, stdio_name: &str) -> String {
+    match path {
+        Some(path) if !is_stdio(Some(path)) => path.to_owned(),
+        _ => stdio_name.to_owned(),
+    }
+}
+
+// open_input reads the whole input up front, so that failing to read it
+// (like when the path is a directory) is reported as an IO error rather
+// than as a malformed model.
+fn open_input(path: &str) -> StdResult<Box<dyn BufRead>, CliError> {
+    let mut contents = vec![];
+    if is_stdio(Some(path)) {
+        std::io::stdin()
+            .read_to_end(&mut contents)
+            .map_err(|err| CliError::io("<stdin>", err))?;
+    } else {
+        let mut file = File::open(path).map_err(|err| CliError::io(path, err))?;
+        file.read_to_end(&mut contents)
+            .map_err(|err| CliError::io(path, err))?;
+    }
+    Ok(Box::new(Cursor::new(contents)))
+}
+
+fn create_output(path: Option<&str>) -> StdResult<Box<dyn Write>, CliError> {
+    match path {
+        Some(path) if !is_stdio(Some(path)) => {
+            let file = File::create(path).map_err(|err| CliError::io(path, err))?;
+            Ok(Box::new(BufWriter::new(file)))
+        }
+        _ => Ok(Box::new(std::io::stdout())),
+    }
+}
+
+/// read_spec reads a sensitivity spec from the JSON file at `path`.
+fn read_spec(path: &str) -> StdResult<Spec, CliError> {
+    let contents = std::fs::read_to_string(path).map_err(|err| CliError::io(path, err))?;
+    Spec::from_json(&contents).map_err(|err| CliError::engine(FailureKind::Parse, &err))
+}
+
+/// read_sensitivity_spec is read_spec for the sensitivity subcommand,
+/// which reports the spread of the spec's variables and so needs at
+/// least one.
+fn read_sensitivity_spec(path: &str) -> StdResult<Spec, CliError> {
+    let spec = read_spec(path)?;
+    if spec.variables.is_empty() {
+        return Err(CliError::new(
+            FailureKind::Parse,
+            None,
+            format!("{}: no variables to report", path),
+        ));
+    }
+    Ok(spec)
+}
+
+/// write_test_results prints whether each test passed, along with why
+/// the ones that didn't failed.  It is an error if any test failed.
+fn write_test_results(results: &[TestResult], output: Option<&str>) -> StdResult<(), CliError> {
+    let output_path = display_path(output, "<stdout>");
+    let write_err = |err| CliError::io(&output_path, err);
+
+    let mut output_file = create_output(output)?;
+    let mut failed = 0;
+    for result in results.iter() {
+        if result.is_pass() {
+            output_file
+                .write_fmt(format_args!("PASS {}\n", result.name))
+                .map_err(write_err)?;
+            continue;
+        }
+        failed += 1;
+        output_file
+            .write_fmt(format_args!("FAIL {}\n", result.name))
+            .map_err(write_err)?;
+        if let Some(ref err) = result.error {
+            output_file
+                .write_fmt(format_args!("    {}\n", err))
+                .map_err(write_err)?;
+        }
+        for failure in result.failures.iter() {
+            let expected = &failure.expectation;
+            let actual = failure
+                .actual
+                .map(|actual| format!("{}", actual))
+                .unwrap_or_else(|| "no value".to_owned());
+            output_file
+                .write_fmt(format_args!(
+                    "    {} at time {}: expected {} ± {}, got {}\n",
+                    expected.ident, expected.time, expected.value, expected.tolerance, actual
+                ))
+                .map_err(write_err)?;
+        }
+    }
+    output_file.flush().map_err(write_err)?;
+    if failed > 0 {
+        return Err(CliError::new(
+            FailureKind::Test,
+            None,
+            format!("{} of {} tests failed", failed, results.len()),
+        ));
+    }
+    Ok(())
+}
+
+/// load_failure_kind classifies an error from reading a workspace or a
+/// data file: files that can't be read are IO errors, engine errors are
+/// classified by the stage that raised them, and anything else is a file
+/// that was read but couldn't be parsed.
+fn load_failure_kind(err: &(dyn std::error::Error + 'static)) -> FailureKind {
+    if err.is::<std::io::Error>() {
+        return FailureKind::Io;
+    }
+    match err.downcast_ref::<Error>().map(|err| &err.kind) {
+        Some(ErrorKind::Simulation) => FailureKind::Simulation,
+        Some(ErrorKind::Model) | Some(ErrorKind::Variable) => FailureKind::Model,
+        Some(ErrorKind::Import) | None => FailureKind::Parse,
+    }
+}
+
+/// run_workspace writes a new workspace referring to the model at
+/// `path`, or simulates each of the saved runs of the workspace at
+/// `path`, writing their results, and then runs its tests.
+fn run_workspace(args: &Args, path: &str) -> StdResult<(), CliError> {
+    if args.is_workspace_new {
+        let output_path = display_path(args.output.as_deref(), "<stdout>");
+        let mut output_file = create_output(args.output.as_deref())?;
+        writeln!(output_file, "{}", Workspace::new(path).to_json())
+            .and_then(|_| output_file.flush())
+            .map_err(|err| CliError::io(&output_path, err))?;
+        return Ok(());
+    }
+
+    let load_err = |err: Box<dyn std::error::Error>| CliError::load(err.as_ref(), err.to_string());
+    let (workspace, project) = open_workspace(path).map_err(load_err)?;
+    let data = load_data(path, &workspace).map_err(load_err)?;
+    for run in workspace.runs.iter() {
+        let results = workspace
+            .simulate(&project, run.scenario.as_deref(), &data)
+            .map_err(|err| CliError::engine(FailureKind::Simulation, &err))?;
+        let run_path = resolve(path, &run.path);
+        let run_path = run_path.to_string_lossy();
+        let mut output_file = create_output(Some(&run_path))?;
+        results
+            .write_tsv(&mut output_file)
+            .and_then(|_| output_file.flush())
+            .map_err(|err| CliError::io(&run_path, err))?;
+    }
+
+    let results = workspace
+        .run_tests(&project, &data)
+        .map_err(|err| CliError::engine(FailureKind::Model, &err))?;
+    write_test_results(&results, args.output.as_deref())
+}
+
+fn run(args: Args, file_path: &str) -> StdResult<(), CliError> {
+    let mut reader = open_input(file_path)?;
 
     let project = if args.is_vensim {
         open_vensim(&mut reader)
     } else if args.is_pb_input {
         open_binary(&mut reader)
+    } else if args.is_json_input {
+        open_json(&mut reader)
     } else {
-        open_xmile(&mut reader)
+        let strictness = if args.is_strict {
+            Strictness::Strict
+        } else {
+            Strictness::Permissive
+        };
+        open_xmile_with_strictness(&mut reader, strictness)
     };
 
-    if project.is_err() {
-        eprintln!("model '{}' error: {}", &file_path, project.err().unwrap());
-        return;
-    };
+    let mut project = project.map_err(|err| CliError::engine(FailureKind::Parse, &err))?;
+    report_import_issues(
+        &project.import_report,
+        args.error_format,
+        &display_path(Some(file_path), "<stdin>"),
+    );
+
+    // the series from each --data file, by the variable they drive
+    let mut inputs = vec![];
+    for data_path in args.data.iter() {
+        let run = load_run(data_path)
+            .map_err(|err| CliError::load(err.as_ref(), format!("{}: {}", data_path, err)))?;
+        inputs.extend(input_series(&run));
+    }
 
-    let project = project.unwrap();
+    let output_path = display_path(args.output.as_deref(), "<stdout>");
+    let write_err = |err| CliError::io(&output_path, err);
 
-    if args.is_equations {
-        let mut output_file =
-            File::create(args.output.unwrap_or_else(|| "/dev/stdout".to_string())).unwrap();
+    if args.is_replace {
+        let find = args.find.as_ref().unwrap();
+        if args.is_dry_run {
+            let mut output_file = create_output(args.output.as_deref())?;
+            for edit in project.preview_replace(find, &args.replacement) {
+                output_file
+                    .write_fmt(format_args!("{}", edit))
+                    .map_err(write_err)?;
+            }
+            output_file.flush().map_err(write_err)?;
+            return Ok(());
+        }
+        project.replace_in_equations(find, &args.replacement);
+    }
+
+    if let Some(ref name) = args.molecule {
+        let template = match molecule(name) {
+            Some(template) => template,
+            None => {
+                return Err(CliError::new(
+                    FailureKind::Model,
+                    Some(ErrorCode::DoesNotExist),
+                    format!("unknown molecule '{}'", name),
+                ))
+            }
+        };
+        let molecule_args = args.molecule_args.iter().cloned().collect();
+        project
+            .insert_template(
+                "main",
+                &template,
+                &args.molecule_prefix,
+                &molecule_args,
+                args.molecule_at,
+            )
+            .map_err(|err| CliError::engine(FailureKind::Model, &err))?;
+    }
+
+    if let Some((ref var, ref data_path)) = args.set_data {
+        let mut data = File::open(data_path).map_err(|err| CliError::io(data_path, err))?;
+        set_array_data(&mut project, "main", var, &mut data)
+            .map_err(|err| CliError::engine(FailureKind::Model, &err))?;
+    }
+
+    if let Some(ref observed_path) = args.calibrate_observed {
+        let observed = load_run(observed_path)
+            .map_err(|err| CliError::load(err.as_ref(), format!("{}: {}", observed_path, err)))?;
+        let params: Vec<Parameter> = args
+            .calibrate_fit
+            .iter()
+            .map(|fit| Parameter {
+                ident: fit.path.clone(),
+                min: fit.low,
+                max: fit.high,
+            })
+            .collect();
+        let options = CalibrationOptions {
+            metric: args.calibrate_metric,
+            ..Default::default()
+        };
+        let calibration = calibrate(
+            &project,
+            "main",
+            &params,
+            &observed,
+            &args.plot_vars,
+            &options,
+        )
+        .map_err(|err| CliError::engine(FailureKind::Simulation, &err))?;
+        for (param, value) in params.iter().zip(calibration.values.iter()) {
+            eprintln!("{}\t{}", param.ident, value);
+        }
+        eprintln!(
+            "error\t{} after {} runs",
+            calibration.error, calibration.evaluations
+        );
+        project = calibration.project;
+    }
+
+    if args.is_test {
+        let results = run_tests(&project);
+        write_test_results(&results, args.output.as_deref())?;
+    } else if args.is_tree {
+        let var = args.tree_var.unwrap_or_default();
+        let project = Project::from(project);
+        let tree = dependency_tree(&project, "main", &var, args.tree_depth)
+            .map_err(|err| CliError::engine(FailureKind::Model, &err))?;
+        let mut output_file = create_output(args.output.as_deref())?;
+        output_file.write_all(tree.as_bytes()).map_err(write_err)?;
+        output_file.flush().map_err(write_err)?;
+    } else if args.is_graph {
+        let project = Project::from(project);
+        let graph = dependency_graph(&project, "main", args.graph_kind)
+            .map_err(|err| CliError::engine(FailureKind::Model, &err))?;
+        let mut output_file = create_output(args.output.as_deref())?;
+        output_file.write_all(graph.as_bytes()).map_err(write_err)?;
+        output_file.flush().map_err(write_err)?;
+    } else if args.is_plot {
+        let results = simulate(
+            &project,
+            args.error_format,
+            &args.overrides,
+            &args.schedule,
+            &inputs,
+            args.allow_errors,
+            args.smoothing,
+        )?;
+        let reference =
+            match args.reference {
+                Some(ref ref_path) => Some(load_run(ref_path).map_err(|err| {
+                    CliError::load(err.as_ref(), format!("{}: {}", ref_path, err))
+                })?),
+                None => None,
+            };
+        let sweep = match args.plot_sweep {
+            Some(ref sweep) => {
+                let mut runs = vec![];
+                for i in 0..args.plot_runs {
+                    let value = sweep.low
+                        + (sweep.high - sweep.low) * i as f64 / (args.plot_runs - 1) as f64;
+                    let mut overrides = args.overrides.clone();
+                    overrides.push((sweep.path.clone(), value));
+                    runs.push(simulate(
+                        &project,
+                        args.error_format,
+                        &overrides,
+                        &args.schedule,
+                        &inputs,
+                        args.allow_errors,
+                        args.smoothing,
+                    )?);
+                }
+                Some(runs)
+            }
+            None => None,
+        };
+
+        let not_found = |err: Error| CliError::engine(FailureKind::Model, &err);
+        // without --var, draw one of the graphs saved with the model
+        let mut chart = if args.plot_vars.is_empty() {
+            let graph = project
+                .get_model("main")
+                .and_then(|model| model.graphs.get(args.plot_graph));
+            let graph = match graph {
+                Some(graph) => graph,
+                None => {
+                    return Err(CliError::new(
+                        FailureKind::Model,
+                        None,
+                        format!("model has no saved graph {}", args.plot_graph),
+                    ))
+                }
+            };
+            Chart::from_graph(graph, &results).map_err(not_found)?
+        } else {
+            let mut chart = Chart::new(&project.name);
+            for var in args.plot_vars.iter() {
+                chart.add_series(var, &results, var).map_err(not_found)?;
+            }
+            chart
+        };
+        if let Some(ref title) = args.plot_title {
+            chart.title = title.clone();
+        }
+        let vars: Vec<String> = chart.lines.iter().map(|line| line.path.clone()).collect();
+        for var in vars.iter() {
+            if let (Some(runs), Some(sweep)) = (sweep.as_ref(), args.plot_sweep.as_ref()) {
+                let label = format!("{} ({} {}-{})", var, sweep.path, sweep.low, sweep.high);
+                chart.add_envelope(&label, runs, var).map_err(not_found)?;
+            }
+            if let Some(ref reference) = reference {
+                let label = format!("{} (reference)", var);
+                chart
+                    .add_comparison(&label, reference, var)
+                    .map_err(not_found)?;
+            }
+        }
+        let mut output_file = create_output(args.output.as_deref())?;
+        output_file
+            .write_all(chart.to_svg().as_bytes())
+            .map_err(write_err)?;
+        output_file.flush().map_err(write_err)?;
+    } else if args.is_sensitivity {
+        let spec = read_sensitivity_spec(args.sensitivity_spec.as_deref().unwrap())?;
+        let (_, envelopes) = run_sensitivity(&project, "main", &spec)
+            .map_err(|err| CliError::engine(FailureKind::Simulation, &err))?;
+
+        let mut output_file = create_output(args.output.as_deref())?;
+        let mut header = vec!["time".to_owned()];
+        for envelope in envelopes.iter() {
+            for pct in envelope.percentiles.iter() {
+                header.push(format!("{} p{}", envelope.ident, pct));
+            }
+        }
+        writeln!(output_file, "{}", header.join(",")).map_err(write_err)?;
+        for (t, time) in envelopes[0].times.iter().enumerate() {
+            let mut row = vec![time.to_string()];
+            for envelope in envelopes.iter() {
+                row.extend(envelope.bands.iter().map(|band| band[t].to_string()));
+            }
+            writeln!(output_file, "{}", row.join(",")).map_err(write_err)?;
+        }
+        output_file.flush().map_err(write_err)?;
+    } else if args.is_export_db {
+        let sim_err = |err: Error| CliError::engine(FailureKind::Simulation, &err);
+        let (manifest, results) = match args.sensitivity_spec {
+            Some(ref spec_path) => {
+                let spec = read_spec(spec_path)?;
+                let runs = sample_runs(&spec.parameters, spec.runs, spec.seed, spec.sampling);
+                run_ensemble(&project, "main", runs).map_err(sim_err)?
+            }
+            None => {
+                let results = simulate(
+                    &project,
+                    args.error_format,
+                    &args.overrides,
+                    &args.schedule,
+                    &inputs,
+                    args.allow_errors,
+                    args.smoothing,
+                )?;
+                let run = Run {
+                    seed: 0,
+                    overrides: args
+                        .overrides
+                        .iter()
+                        .map(|(ident, value)| Override {
+                            ident: ident.clone(),
+                            value: *value,
+                        })
+                        .collect(),
+                };
+                (Manifest::new(&project, "main", vec![run]), vec![results])
+            }
+        };
+        let db_path = args.output.as_deref().unwrap();
+        export_runs(Path::new(db_path), &manifest, &results)
+            .map_err(|err| CliError::engine(FailureKind::Io, &err))?;
+    } else if args.is_units {
+        let project = Project::from(project);
+        let show = |units: Option<&UnitMap>| match units {
+            Some(units) => units.to_string(),
+            None => "-".to_owned(),
+        };
+        let mut out = String::new();
+        match args.units_explain {
+            Some(var) => {
+                let explanation = project
+                    .explain_units("main", &var)
+                    .map_err(|err| CliError::engine(FailureKind::Model, &err))?;
+                out.push_str(&format!(
+                    "declared: {}\ninferred: {}\n",
+                    show(explanation.declared.as_ref()),
+                    show(explanation.inferred.as_ref())
+                ));
+                if !explanation.chain.is_empty() {
+                    out.push_str("inferred from:\n");
+                    for step in explanation.chain.iter() {
+                        out.push_str(&format!("    {}\n", step));
+                    }
+                }
+                for mismatch in explanation.mismatches.iter() {
+                    out.push_str(&format!(
+                        "mismatch: units differ by {}, from:\n",
+                        mismatch.residual
+                    ));
+                    for step in mismatch.chain.iter() {
+                        out.push_str(&format!("    {}\n", step));
+                    }
+                }
+            }
+            None => {
+                let explanations = project
+                    .explain_all_units("main")
+                    .map_err(|err| CliError::engine(FailureKind::Model, &err))?;
+                out.push_str("variable\tdeclared\tinferred\n");
+                for (ident, explanation) in explanations.iter() {
+                    out.push_str(&format!(
+                        "{}\t{}\t{}\n",
+                        ident,
+                        show(explanation.declared.as_ref()),
+                        show(explanation.inferred.as_ref())
+                    ));
+                }
+            }
+        }
+        let mut output_file = create_output(args.output.as_deref())?;
+        output_file.write_all(out.as_bytes()).map_err(write_err)?;
+        output_file.flush().map_err(write_err)?;
+    } else if args.is_lint {
+        let mut output_file = create_output(args.output.as_deref())?;
+        for group in project.find_duplicate_equations() {
+            let kind = if group.is_exact {
+                "duplicate"
+            } else {
+                "near-duplicate"
+            };
+            let idents: Vec<String> = group.idents.iter().map(|id| quoteize(id)).collect();
+            writeln!(
+                output_file,
+                "{}: {} equations: {} = {}",
+                group.model_name,
+                kind,
+                idents.join(", "),
+                group.equation
+            )
+            .map_err(write_err)?;
+        }
+        let limits = args.complexity_limits;
+        for complex in project.find_complex_equations(&limits) {
+            let mut reasons = vec![];
+            if complex.depth > limits.max_depth {
+                reasons.push(format!("nested {} deep", complex.depth));
+            }
+            if complex.operands > limits.max_operands {
+                reasons.push(format!("{} operands", complex.operands));
+            }
+            if complex.units.len() > limits.max_units {
+                reasons.push(format!("mixes units {}", complex.units.join(", ")));
+            }
+            let element = match complex.element {
+                Some(ref element) => format!("[{}]", element),
+                None => "".to_owned(),
+            };
+            writeln!(
+                output_file,
+                "{}.{}{}: complex equation: {}",
+                complex.model_name,
+                quoteize(&complex.ident),
+                element,
+                reasons.join("; ")
+            )
+            .map_err(write_err)?;
+            for split_point in complex.split_points.iter() {
+                writeln!(output_file, "    consider a variable for: {}", split_point)
+                    .map_err(write_err)?;
+            }
+        }
+        for switch in project.find_time_switches() {
+            let element = match switch.element {
+                Some(ref element) => format!("[{}]", element),
+                None => "".to_owned(),
+            };
+            writeln!(
+                output_file,
+                "{}.{}{}: IF condition depends on TIME, so under RK4 results change with dt: {}",
+                switch.model_name,
+                quoteize(&switch.ident),
+                element,
+                switch.condition
+            )
+            .map_err(write_err)?;
+        }
+        output_file.flush().map_err(write_err)?;
+    } else if args.is_stats {
+        let sim = build_stubbed_sim(
+            &project,
+            args.error_format,
+            &args.overrides,
+            &args.schedule,
+            &inputs,
+            args.allow_errors,
+        )?;
+        let stats = sim
+            .compile()
+            .map_err(|err| CliError::engine(FailureKind::Simulation, &err))?
+            .stats();
+        let mut output_file = create_output(args.output.as_deref())?;
+        output_file
+            .write_fmt(format_args!(
+                "slots per step\t{}\n\
+                 hidden slots\t{}\n\
+                 bytecode bytes\t{}\n\
+                 flops per step\t{}\n\
+                 time steps\t{}\n\
+                 saved steps\t{}\n\
+                 result bytes\t{}\n",
+                stats.n_slots,
+                stats.hidden_slots,
+                stats.bytecode_bytes,
+                stats.flops_per_step,
+                stats.time_steps,
+                stats.saved_steps,
+                stats.result_bytes
+            ))
+            .map_err(write_err)?;
+        output_file.flush().map_err(write_err)?;
+    } else if args.is_grep {
+        let mut output_file = create_output(args.output.as_deref())?;
+        for m in project.query(&args.query) {
+            output_file
+                .write_fmt(format_args!(
+                    "{}: {} ({})\n",
+                    m.model_name,
+                    m.ident,
+                    m.kind.name()
+                ))
+                .map_err(write_err)?;
+        }
+        output_file.flush().map_err(write_err)?;
+    } else if args.is_equations {
+        let mut output_file = create_output(args.output.as_deref())?;
 
         let project = Rc::new(Project::from(project));
         for (model_name, model) in project.models.iter().filter(|(_, model)| !model.implicit) {
             output_file
                 .write_fmt(format_args!("% {}\n", model_name))
-                .unwrap();
+                .map_err(write_err)?;
             output_file
                 .write_fmt(format_args!("\\begin{{align*}}\n"))
-                .unwrap();
+                .map_err(write_err)?;
 
             let var_count = model.variables.len();
             for (i, (var_name, var)) in model.variables.iter().enumerate() {
@@ -212,7 +1666,7 @@ fn main() {
                         "\\mathrm{{{}}}{} & = {}{}\n",
                         var_name, subscript, eqn, continuation
                     ))
-                    .unwrap();
+                    .map_err(write_err)?;
 
                 if var.is_stock() {
                     if let Variable::Stock {
@@ -252,64 +1706,328 @@ fn main() {
                                 "\\mathrm{{{}}}(t) & = \\mathrm{{{}}}(t - dt) + {} dt{}\n",
                                 var_name, var_name, eqn, continuation
                             ))
-                            .unwrap();
+                            .map_err(write_err)?;
                     }
                 }
             }
 
             output_file
                 .write_fmt(format_args!("\\end{{align*}}\n"))
-                .unwrap();
+                .map_err(write_err)?;
         }
+        output_file.flush().map_err(write_err)?;
     } else if args.is_convert {
-        let pb_project = serde::serialize(&project);
+        let project = if args.is_anonymize {
+            project.anonymized()
+        } else {
+            project
+        };
+        let pb_project = if args.is_canonical {
+            serde::serialize_canonical(&project)
+        } else {
+            serde::serialize(&project)
+        };
 
         let mut buf: Vec<u8> = if args.is_model_only {
             if pb_project.models.len() != 1 {
                 die!("--model-only specified, but more than 1 model in this project");
             }
-            let mut buf = Vec::with_capacity(pb_project.models[0].encoded_len());
-            pb_project.models[0].encode(&mut buf).unwrap();
-            buf
+            pb_project.models[0].encode_to_vec()
         } else {
-            let mut buf = Vec::with_capacity(pb_project.encoded_len());
-            pb_project.encode(&mut buf).unwrap();
-            buf
+            pb_project.encode_to_vec()
         };
 
-        if args.is_to_xmile {
+        if args.is_to_json {
+            buf = json::serialize(&project).into_bytes();
+            buf.push(b'\n');
+        } else if args.is_to_xmile {
             match to_xmile(&project) {
                 Ok(s) => {
                     buf = s.into_bytes();
                     buf.push(b'\n');
                 }
                 Err(err) => {
-                    die!("error converting to XMILE: {}", err);
+                    return Err(CliError::engine(FailureKind::Parse, &err));
                 }
             }
         }
 
-        let mut output_file =
-            File::create(args.output.unwrap_or_else(|| "/dev/stdout".to_string())).unwrap();
-        output_file.write_all(&buf).unwrap();
+        let mut output_file = create_output(args.output.as_deref())?;
+        output_file.write_all(&buf).map_err(write_err)?;
+        output_file.flush().map_err(write_err)?;
     } else if args.is_debug {
         if args.reference.is_none() {
             eprintln!("missing required argument --reference FILE");
             std::process::exit(1);
         }
         let ref_path = args.reference.unwrap();
-        let reference = if ref_path.ends_with(".dat") {
-            load_dat(&ref_path).unwrap()
-        } else {
-            load_csv(&ref_path, b'\t').unwrap()
-        };
-        let results = simulate(&project);
+        let reference = load_run(&ref_path)
+            .map_err(|err| CliError::load(err.as_ref(), format!("{}: {}", ref_path, err)))?;
+        let results = simulate(
+            &project,
+            args.error_format,
+            &args.overrides,
+            &args.schedule,
+            &inputs,
+            args.allow_errors,
+            args.smoothing,
+        )?;
 
-        results.print_tsv_comparison(Some(&reference));
+        // compare step by step, even if the reference was saved at
+        // different times
+        let reference = reference.aligned_with(&results, args.resample);
+        results
+            .write_tsv_comparison(&mut std::io::stdout().lock(), Some(&reference))
+            .map_err(|err| CliError::io("<stdout>", err))?;
     } else {
-        let results = simulate(&project);
+        let results = simulate(
+            &project,
+            args.error_format,
+            &args.overrides,
+            &args.schedule,
+            &inputs,
+            args.allow_errors,
+            args.smoothing,
+        )?;
+        // by default, print the model's saved table (if it has one)
+        // rather than every variable
+        let main = project.get_model("main");
+        let table = main.and_then(|model| {
+            model
+                .graphs
+                .iter()
+                .find(|graph| graph.kind == GraphKind::Table)
+        });
+        let results = match main {
+            Some(model) if args.is_no_supplementary => {
+                let supplementary: Vec<&str> = model
+                    .variables
+                    .iter()
+                    .filter(|var| var.is_supplementary())
+                    .map(|var| var.get_ident())
+                    .collect();
+                results.without(&supplementary)
+            }
+            _ => results,
+        };
         if !args.is_no_output {
-            results.print_tsv();
+            let stdout = &mut std::io::stdout().lock();
+            match table {
+                Some(table) if !args.is_all_variables => results.write_table(stdout, table),
+                _ => results.write_tsv(stdout),
+            }
+            .map_err(|err| CliError::io("<stdout>", err))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            usage();
+        }
+    };
+    if args.is_capabilities {
+        for name in capabilities().names() {
+            println!("{}", name);
+        }
+        return;
+    }
+    if args.is_molecules_list {
+        for template in molecules() {
+            println!("{}: {}", template.name, template.documentation);
+            for param in template.params.iter() {
+                match param.default {
+                    Some(ref default) => println!(
+                        "    {} (default {}): {}",
+                        param.name, default, param.documentation
+                    ),
+                    None => println!("    {}: {}", param.name, param.documentation),
+                }
+            }
         }
+        return;
     }
+    if let Some(ref template) = args.new_template {
+        let project_name = args.new_project_name.as_deref().unwrap_or(template);
+        let xmile = match new_project(template, project_name).and_then(|p| to_xmile(&p)) {
+            Ok(xmile) => xmile,
+            Err(err) => {
+                let names: Vec<&str> = scaffold_names().into_iter().map(|(name, _)| name).collect();
+                die!("error: {}: templates are {}", err, names.join(", "));
+            }
+        };
+        let output_path = args.output.as_deref();
+        let written = create_output(output_path).and_then(|mut output| {
+            writeln!(output, "{}", xmile)
+                .and_then(|_| output.flush())
+                .map_err(|err| CliError::io(&display_path(output_path, "<stdout>"), err))
+        });
+        if let Err(err) = written {
+            report_error(
+                &err,
+                args.error_format,
+                &display_path(output_path, "<stdout>"),
+            );
+            std::process::exit(err.kind.exit_code());
+        }
+        return;
+    }
+    if let Some(id) = args.explain_error {
+        match ErrorCode::from_id(&id) {
+            Some(code) => println!("{} {}\n\n{}", code.id(), code, code.explanation()),
+            None => die!("error: unknown error code '{}'", id),
+        }
+        return;
+    }
+    let error_format = args.error_format;
+    let file_path = args.path.clone().unwrap_or_else(|| "-".to_string());
+
+    let result = if args.is_workspace_new || args.is_workspace_run {
+        run_workspace(&args, &file_path)
+    } else {
+        run(args, &file_path)
+    };
+    if let Err(err) = result {
+        report_error(
+            &err,
+            error_format,
+            &display_path(Some(&file_path), "<stdin>"),
+        );
+        std::process::exit(err.kind.exit_code());
+    }
+}
+
+#[cfg(test)]
+fn temp_path(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("simlin-cli-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.join(name)
+}
+
+#[test]
+fn test_open_input_errors() {
+    let missing = temp_path("missing.stmx");
+    let missing = missing.to_str().unwrap();
+    let err = open_input(missing).err().unwrap();
+    assert_eq!(FailureKind::Io, err.kind);
+    assert_eq!(EXIT_IO_ERROR, err.kind.exit_code());
+    assert!(err.message.starts_with(&format!("{}: ", missing)));
+
+    // a directory can be opened, but not read
+    let dir = temp_path("");
+    let dir = dir.to_str().unwrap();
+    let err = open_input(dir).err().unwrap();
+    assert_eq!(FailureKind::Io, err.kind);
+    assert!(err.message.starts_with(&format!("{}: ", dir)));
+}
+
+#[test]
+fn test_load_failure_kind() {
+    let kind = |kind: ErrorKind, code: ErrorCode| load_failure_kind(&Error::new(kind, code, None));
+    assert_eq!(
+        FailureKind::Parse,
+        kind(ErrorKind::Import, ErrorCode::Generic)
+    );
+    assert_eq!(
+        FailureKind::Parse,
+        kind(ErrorKind::Import, ErrorCode::JsonDeserialization)
+    );
+    assert_eq!(
+        FailureKind::Model,
+        kind(ErrorKind::Model, ErrorCode::DoesNotExist)
+    );
+
+    let missing = temp_path("missing.csv");
+    let missing = missing.to_str().unwrap();
+    let err = load_run(missing).unwrap_err();
+    assert_eq!(FailureKind::Io, load_failure_kind(err.as_ref()));
+    let err = CliError::load(err.as_ref(), format!("{}: {}", missing, err));
+    assert_eq!(FailureKind::Io, err.kind);
+    assert_eq!(None, err.code);
+
+    let malformed = temp_path("malformed.csv");
+    std::fs::write(&malformed, "time,a\n0,lots\n").unwrap();
+    let err = load_run(malformed.to_str().unwrap()).unwrap_err();
+    assert_eq!(FailureKind::Parse, load_failure_kind(err.as_ref()));
+    std::fs::remove_file(&malformed).unwrap();
+}
+
+#[test]
+fn test_create_output_errors() {
+    let in_missing_dir = temp_path("missing/out.pb");
+    let in_missing_dir = in_missing_dir.to_str().unwrap();
+    let err = create_output(Some(in_missing_dir)).err().unwrap();
+    assert_eq!(FailureKind::Io, err.kind);
+    assert!(err.message.starts_with(&format!("{}: ", in_missing_dir)));
+
+    let readonly = temp_path("readonly.pb");
+    std::fs::write(&readonly, b"").unwrap();
+    let mut permissions = std::fs::metadata(&readonly).unwrap().permissions();
+    permissions.set_readonly(true);
+    std::fs::set_permissions(&readonly, permissions).unwrap();
+    // root can write to read-only files, so only check where the OS
+    // actually refuses
+    if std::fs::OpenOptions::new()
+        .write(true)
+        .open(&readonly)
+        .is_err()
+    {
+        let readonly = readonly.to_str().unwrap();
+        let err = create_output(Some(readonly)).err().unwrap();
+        assert_eq!(FailureKind::Io, err.kind);
+        assert!(err.message.starts_with(&format!("{}: ", readonly)));
+    }
+}
+
+#[test]
+fn test_read_sensitivity_spec() {
+    let path = temp_path("spec.json");
+    let path = path.to_str().unwrap();
+    std::fs::write(path, r#"{"variables": ["population"]}"#).unwrap();
+    assert_eq!(
+        vec!["population"],
+        read_sensitivity_spec(path).unwrap().variables
+    );
+
+    std::fs::write(path, r#"{"parameters": [], "variables": []}"#).unwrap();
+    let err = read_sensitivity_spec(path).err().unwrap();
+    assert_eq!(FailureKind::Parse, err.kind);
+    assert!(err.message.contains("no variables"));
+    // export-db samples runs from the same spec, without variables
+    assert!(read_spec(path).is_ok());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_error_to_json() {
+    let mut err = CliError::new(
+        FailureKind::Model,
+        Some(ErrorCode::DoesNotExist),
+        "unknown \"x\"\n".to_owned(),
+    );
+    err.variable_errors.push(VariableError {
+        model: "main".to_owned(),
+        variable: "y".to_owned(),
+        code: ErrorCode::UnknownDependency,
+        start: 2,
+        end: 3,
+        details: None,
+    });
+
+    let json: serde_json::Value = serde_json::from_str(&err.to_json("model.stmx")).unwrap();
+    assert_eq!("model", json["kind"]);
+    assert_eq!(EXIT_MODEL_ERROR, json["exit_code"]);
+    assert_eq!(ErrorCode::DoesNotExist.id(), json["code_id"]);
+    assert_eq!("unknown \"x\"\n", json["message"]);
+    assert_eq!("model.stmx", json["path"]);
+    let var_err = &json["variable_errors"][0];
+    assert_eq!("y", var_err["variable"]);
+    assert_eq!(ErrorCode::UnknownDependency.to_string(), var_err["code"]);
+    assert_eq!(3, var_err["end"]);
+    assert!(var_err["details"].is_null());
 }
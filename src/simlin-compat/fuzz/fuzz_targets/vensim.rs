@@ -0,0 +1,12 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use simlin_compat::fuzz;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = fuzz::import_vensim(data);
+});
@@ -0,0 +1,71 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Entry points for fuzzing the importers.  Each takes arbitrary bytes
+//! and must return an error for malformed input, never panic.
+
+use simlin_engine::datamodel::Project;
+pub use simlin_engine::fuzz::parse_equation;
+
+use crate::{xmile, Result, Strictness};
+
+/// import_xmile imports `data` as an XMILE file.
+pub fn import_xmile(data: &[u8]) -> Result<Project> {
+    xmile::project_from_reader(&mut &data[..])
+}
+
+/// import_xmile_strict imports `data` as an XMILE file, checking it
+/// against the spec.
+pub fn import_xmile_strict(data: &[u8]) -> Result<Project> {
+    xmile::project_from_reader_with_strictness(&mut &data[..], Strictness::Strict)
+}
+
+/// import_vensim imports `data` as a Vensim MDL file.
+#[cfg(feature = "vensim")]
+pub fn import_vensim(data: &[u8]) -> Result<Project> {
+    crate::open_vensim(&mut &data[..])
+}
+
+#[test]
+fn test_import_malformed() {
+    // each of these used to panic: a view of a stock that isn't in the
+    // model, a flow without points, a stock listing an aux as an inflow,
+    // and a group alongside the variables
+    let input = r#"<xmile version="1.0" xmlns="http://docs.oasis-open.org/xmile/ns/XMILE/v1.0">
+    <sim_specs>
+        <start>0</start>
+        <stop>10</stop>
+    </sim_specs>
+    <model>
+        <variables>
+            <stock name="s">
+                <eqn>1</eqn>
+                <inflow>a</inflow>
+                <outflow>f</outflow>
+            </stock>
+            <flow name="f"><eqn>1</eqn></flow>
+            <aux name="a"><eqn>1</eqn></aux>
+            <group name="g"/>
+        </variables>
+        <views>
+            <view>
+                <stock name="s" x="0" y="0"/>
+                <stock name="missing" x="100" y="0"/>
+                <flow name="f" x="50" y="0"/>
+                <aux name="a" x="0" y="50"/>
+            </view>
+        </views>
+    </model>
+</xmile>"#;
+
+    let project = import_xmile(input.as_bytes()).unwrap();
+    assert_eq!(3, project.models[0].variables.len());
+
+    // truncated files are errors, not panics
+    for end in 0..input.len() {
+        let _ = import_xmile(&input.as_bytes()[..end]);
+        let _ = import_xmile_strict(&input.as_bytes()[..end]);
+    }
+    assert!(import_xmile(b"\xff\x00<xmile").is_err());
+}
@@ -5,15 +5,30 @@
 use std::collections::HashMap;
 use std::error::Error;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Read};
 use std::result::Result as StdResult;
 
-use simlin_engine::datamodel::Project;
+use simlin_engine::common::{Error as EngineError, ErrorCode, ErrorKind};
+use simlin_engine::datamodel::{Equation, Project};
 pub use simlin_engine::{self as engine, prost, Result, Results};
 use simlin_engine::{canonicalize, quoteize, Method, SimSpecs};
 
+pub mod fuzz;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod workspace;
 pub mod xmile;
 
+pub use xmile::Strictness;
+
+// model_err is like the engine's macro of the same name, which can't be
+// used outside of it as Error's fields are private.
+macro_rules! model_err(
+    ($code:tt, $str:expr) => {{
+        Err(EngineError::new(ErrorKind::Model, ErrorCode::$code, Some($str)))
+    }}
+);
+
 pub fn to_xmile(project: &Project) -> Result<String> {
     xmile::project_to_xmile(project)
 }
@@ -21,30 +36,64 @@ pub fn to_xmile(project: &Project) -> Result<String> {
 #[cfg(feature = "vensim")]
 pub fn open_vensim(reader: &mut dyn BufRead) -> Result<Project> {
     use simlin_engine::common::{Error, ErrorCode, ErrorKind};
+    use simlin_engine::datamodel::ImportIssue;
     use xmutil::convert_vensim_mdl;
 
     let mut contents_buf: Vec<u8> = vec![];
     reader
         .read_until(0, &mut contents_buf)
         .map_err(|_err| Error::new(ErrorKind::Import, ErrorCode::VensimConversion, None))?;
-    let contents: String = String::from_utf8(contents_buf).unwrap();
-    let (xmile_src, _logs) = convert_vensim_mdl(&contents, false);
-    if xmile_src.is_none() {
-        return Err(Error::new(
-            ErrorKind::Import,
-            ErrorCode::VensimConversion,
-            Some("unknown xmutil error".to_owned()),
-        ));
-    }
-    let xmile_src = xmile_src.unwrap();
+    let contents: String = match String::from_utf8(contents_buf) {
+        Ok(contents) => contents,
+        Err(err) => {
+            return Err(Error::new(
+                ErrorKind::Import,
+                ErrorCode::VensimConversion,
+                Some(err.to_string()),
+            ));
+        }
+    };
+    let (xmile_src, logs) = convert_vensim_mdl(&contents, false);
+    let xmile_src = match xmile_src {
+        Some(xmile_src) => xmile_src,
+        None => {
+            return Err(Error::new(
+                ErrorKind::Import,
+                ErrorCode::VensimConversion,
+                Some("unknown xmutil error".to_owned()),
+            ));
+        }
+    };
     let mut f = BufReader::new(xmile_src.as_bytes());
-    xmile::project_from_reader(&mut f)
+    let mut project = xmile::project_from_reader(&mut f)?;
+    // xmutil logs what it couldn't convert; locations in the report refer
+    // to the converted XMILE
+    let conversion_issues = logs
+        .as_deref()
+        .unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| ImportIssue {
+            construct: "vensim".to_owned(),
+            location: "".to_owned(),
+            reason: line.to_owned(),
+        });
+    project.import_report.splice(0..0, conversion_issues);
+    Ok(project)
 }
 
 pub fn open_xmile(reader: &mut dyn BufRead) -> Result<Project> {
     xmile::project_from_reader(reader)
 }
 
+pub fn open_xmile_with_strictness(
+    reader: &mut dyn BufRead,
+    strictness: Strictness,
+) -> Result<Project> {
+    xmile::project_from_reader_with_strictness(reader, strictness)
+}
+
 pub fn load_dat(file_path: &str) -> StdResult<Results, Box<dyn Error>> {
     use float_cmp::approx_eq;
 
@@ -68,8 +117,7 @@ pub fn load_dat(file_path: &str) -> StdResult<Results, Box<dyn Error>> {
                 if let Some(id) = ident.take() {
                     assert!(unprocessed.insert(id, std::mem::take(&mut curr)).is_none());
                 }
-                let name = canonicalize(line.trim());
-                ident = Some(quoteize(&name));
+                ident = Some(result_ident(&line));
             }
         }
         if let Some(id) = ident.take() {
@@ -84,9 +132,15 @@ pub fn load_dat(file_path: &str) -> StdResult<Results, Box<dyn Error>> {
         .map(|(i, r)| (r.clone(), i))
         .collect();
 
-    let initial_time = unprocessed["initial_time"][0].1;
-    let final_time = unprocessed["final_time"][0].1;
-    let saveper = unprocessed["saveper"][0].1;
+    let control = |name: &str| -> StdResult<f64, Box<dyn Error>> {
+        match unprocessed.get(name).and_then(|data| data.first()) {
+            Some((_, value)) => Ok(*value),
+            None => Err(format!("missing {}", name).into()),
+        }
+    };
+    let initial_time = control("initial_time")?;
+    let final_time = control("final_time")?;
+    let saveper = control("saveper")?;
 
     let step_size = unprocessed.len();
     let step_count = ((final_time - initial_time) / saveper).ceil() as usize + 1;
@@ -144,25 +198,49 @@ pub fn load_dat(file_path: &str) -> StdResult<Results, Box<dyn Error>> {
             dt: 0.0,
             save_step: 0.0,
             method: Method::Euler,
+            tolerances: Default::default(),
+            dt_reciprocal: None,
+            event_times: vec![],
         },
         is_vensim: true,
     })
 }
 
+/// result_ident converts a column name from another tool's results into
+/// the name of the same series in ours.  Array elements, like Stella's
+/// `Population[Boston, Young]`, have each subscript canonicalized.
+fn result_ident(name: &str) -> String {
+    let name = name.trim();
+    match name.strip_suffix(']').and_then(|name| name.split_once('[')) {
+        Some((base, subscripts)) => {
+            let subscripts: Vec<String> = subscripts.split(',').map(canonicalize).collect();
+            format!(
+                "{}[{}]",
+                quoteize(&canonicalize(base)),
+                subscripts.join(",")
+            )
+        }
+        None => quoteize(&canonicalize(name)),
+    }
+}
+
 pub fn load_csv(file_path: &str, delimiter: u8) -> StdResult<Results, Box<dyn Error>> {
+    read_csv(&mut File::open(file_path)?, delimiter)
+}
+
+fn read_csv(reader: &mut dyn Read, delimiter: u8) -> StdResult<Results, Box<dyn Error>> {
     let mut rdr = csv::ReaderBuilder::new()
         .delimiter(delimiter)
-        .from_path(file_path)?;
+        .from_reader(reader);
 
-    let header = rdr.headers().unwrap();
+    let header = rdr.headers()?;
     let offsets: HashMap<String, usize> = header
         .iter()
         .enumerate()
         .map(|(i, r)| {
             // stella outputs the first 'time' column as the time _units_, which is bonkers
             let name = if i == 0 { "time" } else { r };
-            let ident = canonicalize(name);
-            (quoteize(&ident), i)
+            (result_ident(name), i)
         })
         .collect();
 
@@ -176,6 +254,15 @@ pub fn load_csv(file_path: &str, delimiter: u8) -> StdResult<Results, Box<dyn Er
         let mut row = vec![0.0; step_size];
         for (i, field) in record.iter().enumerate() {
             use std::str::FromStr;
+            // stella leaves the cells of a constant empty after its
+            // first value
+            if field.trim().is_empty() {
+                row[i] = match step_data.last() {
+                    Some(prev) => prev[i],
+                    None => f64::NAN,
+                };
+                continue;
+            }
             row[i] = match f64::from_str(field.trim()) {
                 Ok(n) => n,
                 Err(err) => {
@@ -201,7 +288,380 @@ pub fn load_csv(file_path: &str, delimiter: u8) -> StdResult<Results, Box<dyn Er
             dt: 0.0,
             save_step: 0.0,
             method: Method::Euler,
+            tolerances: Default::default(),
+            dt_reciprocal: None,
+            event_times: vec![],
         },
         is_vensim: false,
     })
 }
+
+/// load_run reads results exported by another tool, picking the reader
+/// from the file's extension: Vensim's `.dat` and `.tab` (or `.out`)
+/// exports, comma-separated `.csv`, and tab-separated anything else.
+pub fn load_run(file_path: &str) -> StdResult<Results, Box<dyn Error>> {
+    let extension = std::path::Path::new(file_path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("dat") => load_dat(file_path),
+        Some("tab") | Some("out") => load_vensim_tab(file_path),
+        Some("csv") => load_csv(file_path, b','),
+        _ => load_csv(file_path, b'\t'),
+    }
+}
+
+/// input_series splits a loaded run into the (time, value) series of
+/// each of its columns other than time, for driving variables with
+/// data.  Missing values are left out, rather than kept as NaN.
+pub fn input_series(run: &Results) -> Vec<(String, Vec<(f64, f64)>)> {
+    let times = run.time_points();
+    run.var_names()
+        .into_iter()
+        .filter(|name| *name != "time")
+        .map(|name| {
+            let series = times
+                .iter()
+                .zip(run.get_series(name).unwrap())
+                .filter(|(_, value)| !value.is_nan())
+                .map(|(time, value)| (*time, value))
+                .collect();
+            (name.to_owned(), series)
+        })
+        .collect()
+}
+
+/// set_array_data sets the equation of each element of the arrayed
+/// variable `ident` to a constant from a CSV matrix.  The header row
+/// names the elements of the variable's last dimension, and each row
+/// after it starts with the element's other subscripts (comma-separated,
+/// in quotes, if there are several).  A variable with one dimension has
+/// no header, just a row per element: its subscript, then its value.
+/// Empty cells leave an element's equation as it was.
+pub fn set_array_data(
+    project: &mut Project,
+    model_name: &str,
+    ident: &str,
+    reader: &mut dyn Read,
+) -> Result<()> {
+    let dimensions = project.dimensions.clone();
+    let var = match project
+        .get_model_mut(model_name)
+        .and_then(|model| model.get_variable_mut(&canonicalize(ident)))
+    {
+        Some(var) => var,
+        None => return model_err!(DoesNotExist, ident.to_owned()),
+    };
+    let n_dims = match var.get_equation() {
+        Some(Equation::ApplyToAll(dim_names, _, _)) | Some(Equation::Arrayed(dim_names, _)) => {
+            dim_names.len()
+        }
+        _ => return model_err!(MismatchedDimensions, format!("{} is a scalar", ident)),
+    };
+
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(reader);
+    let mut rows = vec![];
+    for record in rdr.records() {
+        rows.push(record.map_err(|err| {
+            EngineError::new(ErrorKind::Import, ErrorCode::Generic, Some(err.to_string()))
+        })?);
+    }
+
+    // (element, value) pairs, with multi-dimensional elements like "a,x"
+    let mut values: Vec<(String, &str)> = vec![];
+    if n_dims == 1 {
+        for row in rows.iter() {
+            if row.len() != 2 {
+                return model_err!(
+                    MismatchedDimensions,
+                    format!("expected 'element,value' rows for {}", ident)
+                );
+            }
+            values.push((row[0].trim().to_owned(), row[1].trim()));
+        }
+    } else {
+        let (header, rows) = match rows.split_first() {
+            Some(split) => split,
+            None => return model_err!(MismatchedDimensions, format!("no data for {}", ident)),
+        };
+        for row in rows.iter() {
+            if row.len() != header.len() {
+                return model_err!(
+                    MismatchedDimensions,
+                    format!(
+                        "row {} has {} columns, not {}",
+                        &row[0],
+                        row.len(),
+                        header.len()
+                    )
+                );
+            }
+            for (element, value) in header.iter().zip(row.iter()).skip(1) {
+                values.push((
+                    format!("{},{}", row[0].trim(), element.trim()),
+                    value.trim(),
+                ));
+            }
+        }
+    }
+
+    for (element, value) in values.into_iter() {
+        if value.is_empty() {
+            continue;
+        }
+        if value.parse::<f64>().is_err() {
+            return model_err!(
+                ExpectedNumber,
+                format!("{}[{}] = '{}'", ident, element, value)
+            );
+        }
+        var.set_element_equation(&dimensions, &element, value)?;
+    }
+    Ok(())
+}
+
+/// load_vensim_tab reads a tab-separated dataset exported by Vensim,
+/// with time running either down the first column or across the first
+/// row.
+pub fn load_vensim_tab(file_path: &str) -> StdResult<Results, Box<dyn Error>> {
+    let file = File::open(file_path)?;
+    read_vensim_tab(&mut BufReader::new(file))
+}
+
+fn read_vensim_tab(reader: &mut dyn BufRead) -> StdResult<Results, Box<dyn Error>> {
+    use std::str::FromStr;
+
+    let mut rows: Vec<Vec<String>> = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            continue;
+        }
+        rows.push(line.split('\t').map(|s| s.trim().to_owned()).collect());
+    }
+    if rows.is_empty() {
+        return Err("empty dataset".into());
+    }
+
+    // when time runs across, the header is the times themselves, and
+    // each following row is a variable
+    let is_time_across = rows[0].len() > 1 && f64::from_str(&rows[0][1]).is_ok();
+    let columns: Vec<Vec<String>> = if is_time_across {
+        let width = rows[0].len();
+        (0..width)
+            .map(|i| {
+                rows.iter()
+                    .map(|row| row.get(i).cloned().unwrap_or_default())
+                    .collect()
+            })
+            .collect()
+    } else {
+        rows
+    };
+    let (header, records) = columns.split_first().unwrap();
+
+    // Vensim writes constants once, leaving the rest of their cells
+    // empty, and marks missing data with :NA:
+    let mut prev: Vec<f64> = vec![f64::NAN; header.len()];
+    let mut step_data: Vec<f64> = Vec::with_capacity(records.len() * header.len());
+    for record in records.iter() {
+        for (i, prev) in prev.iter_mut().enumerate() {
+            let field = record.get(i).map(|s| s.as_str()).unwrap_or("");
+            let value = match field {
+                "" => *prev,
+                ":NA:" => f64::NAN,
+                _ => {
+                    f64::from_str(field).map_err(|err| format!("bad value '{}': {}", field, err))?
+                }
+            };
+            *prev = value;
+            step_data.push(value);
+        }
+    }
+
+    let offsets: HashMap<String, usize> = header
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let name = if i == 0 { "time" } else { name.as_str() };
+            (result_ident(name), i)
+        })
+        .collect();
+
+    Ok(Results {
+        offsets,
+        data: step_data.into_boxed_slice(),
+        step_size: header.len(),
+        step_count: records.len(),
+        specs: SimSpecs {
+            start: 0.0,
+            stop: 0.0,
+            dt: 0.0,
+            save_step: 0.0,
+            method: Method::Euler,
+            tolerances: Default::default(),
+            dt_reciprocal: None,
+            event_times: vec![],
+        },
+        is_vensim: true,
+    })
+}
+
+#[test]
+fn test_read_vensim_tab() {
+    let down = "Time\tPopulation\tBirth Rate\n0\t100\t0.1\n1\t110\t\n2\t121\t:NA:\n";
+    let across = "Time\t0\t1\t2\nPopulation\t100\t110\t121\nBirth Rate\t0.1\n";
+    for (i, src) in [down, across].iter().enumerate() {
+        let results = read_vensim_tab(&mut std::io::Cursor::new(src)).unwrap();
+        assert_eq!(3, results.step_size);
+        assert_eq!(3, results.step_count);
+        assert_eq!(0, results.offsets["time"]);
+        let rows: Vec<&[f64]> = results.iter_rows().collect();
+        let population = results.offsets["population"];
+        let birth_rate = results.offsets["birth_rate"];
+        assert_eq!(2.0, rows[2][0]);
+        assert_eq!(121.0, rows[2][population]);
+        assert_eq!(0.1, rows[1][birth_rate]);
+        if i == 0 {
+            assert!(rows[2][birth_rate].is_nan());
+        } else {
+            assert_eq!(0.1, rows[2][birth_rate]);
+        }
+    }
+
+    assert!(read_vensim_tab(&mut std::io::Cursor::new("")).is_err());
+    assert!(read_vensim_tab(&mut std::io::Cursor::new("Time\tx\n0\tbogus\n")).is_err());
+}
+
+#[test]
+fn test_read_stella_csv() {
+    let src = "Months,Population[Boston],\"Population[New York, Young]\",Hares.Birth Rate\n\
+               0,100,10,0.5\n\
+               1,110,11,\n";
+    let results = read_csv(&mut std::io::Cursor::new(src), b',').unwrap();
+    assert_eq!(4, results.step_size);
+    assert_eq!(2, results.step_count);
+    let rows: Vec<&[f64]> = results.iter_rows().collect();
+    assert_eq!(1.0, rows[1][results.offsets["time"]]);
+    assert_eq!(110.0, rows[1][results.offsets["population[boston]"]]);
+    assert_eq!(11.0, rows[1][results.offsets["population[new_york,young]"]]);
+    assert_eq!(0.5, rows[1][results.offsets["hares.birth_rate"]]);
+}
+
+#[test]
+fn test_input_series() {
+    let src = "Time,Demand,Price\n0,10,1\n1,,2\n2,NaN,3\n";
+    let results = read_csv(&mut std::io::Cursor::new(src), b',').unwrap();
+    let inputs = input_series(&results);
+    assert_eq!(
+        vec![
+            // an empty cell repeats the value above it, NaN is left out
+            ("demand".to_owned(), vec![(0.0, 10.0), (1.0, 10.0)]),
+            ("price".to_owned(), vec![(0.0, 1.0), (1.0, 2.0), (2.0, 3.0)]),
+        ],
+        inputs
+    );
+}
+
+#[test]
+fn test_set_array_data() {
+    use simlin_engine::datamodel::{Aux, Dimension, Model, Variable, Visibility};
+
+    let aux = |ident: &str, dim_names: &[&str]| {
+        Variable::Aux(Aux {
+            ident: ident.to_owned(),
+            equation: Equation::ApplyToAll(
+                dim_names.iter().map(|name| name.to_string()).collect(),
+                "0".to_owned(),
+                None,
+            ),
+            documentation: "".to_owned(),
+            units: None,
+            gf: None,
+            can_be_module_input: false,
+            supplementary: false,
+            visibility: Visibility::Private,
+        })
+    };
+    let mut project = Project {
+        name: "test".to_owned(),
+        sim_specs: Default::default(),
+        dimensions: vec![
+            Dimension::Named(
+                "City".to_owned(),
+                vec!["Boston".to_owned(), "New York".to_owned()],
+            ),
+            Dimension::Named("Age".to_owned(), vec!["Young".to_owned(), "Old".to_owned()]),
+        ],
+        units: vec![],
+        models: vec![Model {
+            name: "main".to_owned(),
+            variables: vec![aux("population", &["City", "Age"]), aux("area", &["City"])],
+            views: vec![],
+            graphs: vec![],
+            groups: vec![],
+            variable_index: Default::default(),
+        }],
+        constants: vec![],
+        tests: vec![],
+        source: None,
+        import_report: vec![],
+    };
+
+    let matrix = "city,young,old\nBoston,10,20\nnew york,30,\n";
+    set_array_data(&mut project, "main", "Population", &mut matrix.as_bytes()).unwrap();
+    let model = project.get_model("main").unwrap();
+    assert_eq!(
+        Some(&Equation::Arrayed(
+            vec!["City".to_owned(), "Age".to_owned()],
+            vec![
+                ("Boston,Young".to_owned(), "10".to_owned(), None),
+                ("Boston,Old".to_owned(), "20".to_owned(), None),
+                ("New York,Young".to_owned(), "30".to_owned(), None),
+                ("New York,Old".to_owned(), "0".to_owned(), None),
+            ]
+        )),
+        model.get_variable("population").unwrap().get_equation()
+    );
+
+    set_array_data(
+        &mut project,
+        "main",
+        "area",
+        &mut "Boston,1.5\nNew York,2\n".as_bytes(),
+    )
+    .unwrap();
+    let model = project.get_model("main").unwrap();
+    assert_eq!(
+        Some(&Equation::Arrayed(
+            vec!["City".to_owned()],
+            vec![
+                ("Boston".to_owned(), "1.5".to_owned(), None),
+                ("New York".to_owned(), "2".to_owned(), None),
+            ]
+        )),
+        model.get_variable("area").unwrap().get_equation()
+    );
+
+    let code = |ident: &str, csv: &str| {
+        set_array_data(&mut project.clone(), "main", ident, &mut csv.as_bytes())
+            .unwrap_err()
+            .code
+    };
+    assert_eq!(ErrorCode::DoesNotExist, code("missing", "a,1\n"));
+    assert_eq!(ErrorCode::UnknownSubscript, code("area", "Chicago,1\n"));
+    assert_eq!(ErrorCode::ExpectedNumber, code("area", "Boston,lots\n"));
+    assert_eq!(
+        ErrorCode::MismatchedDimensions,
+        code("area", "Boston,1,2\n")
+    );
+    assert_eq!(
+        ErrorCode::MismatchedDimensions,
+        code("population", "city,young,old\nBoston,10\n")
+    );
+}
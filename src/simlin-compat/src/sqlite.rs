@@ -0,0 +1,228 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Writing the results of an ensemble of runs to a SQLite database, so
+//! that they can be queried with SQL rather than parsed from TSV.
+//!
+//! The database has four tables:
+//!
+//! - `runs`: one row per run, with the seed it was drawn with, the
+//!   model simulated and the hash of the project it came from.
+//! - `overrides`: the constants each run set, keyed by `run_id`.
+//! - `variables`: the name of each variable, like `hares.births`.
+//! - `"values"`: the value of each variable at each saved time in each
+//!   run.  NaN values are stored as NULL.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use rusqlite::{params, Connection};
+use simlin_engine::common::{Error, ErrorCode, ErrorKind};
+use simlin_engine::ensemble::Manifest;
+
+use crate::{Result, Results};
+
+const SCHEMA: &str = "
+CREATE TABLE runs (
+    id INTEGER PRIMARY KEY,
+    seed INTEGER NOT NULL,
+    model TEXT NOT NULL,
+    project_hash TEXT NOT NULL,
+    engine_version TEXT NOT NULL
+);
+CREATE TABLE overrides (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    variable TEXT NOT NULL,
+    value REAL NOT NULL
+);
+CREATE TABLE variables (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE
+);
+CREATE TABLE \"values\" (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    variable_id INTEGER NOT NULL REFERENCES variables(id),
+    time REAL NOT NULL,
+    value REAL,
+    PRIMARY KEY (run_id, variable_id, time)
+);
+";
+
+fn db_error(path: &Path, err: impl std::fmt::Display) -> Error {
+    Error::new(
+        ErrorKind::Import,
+        ErrorCode::Generic,
+        Some(format!("{}: {}", path.display(), err)),
+    )
+}
+
+/// export_runs writes each run in `manifest`, along with its results,
+/// to a new SQLite database at `path`, replacing any file already
+/// there.  Runs are numbered from 1 in the order they are listed.
+pub fn export_runs(path: &Path, manifest: &Manifest, results: &[Results]) -> Result<()> {
+    if manifest.runs.len() != results.len() {
+        return Err(Error::new(
+            ErrorKind::Simulation,
+            ErrorCode::Generic,
+            Some(format!(
+                "{} runs but {} results",
+                manifest.runs.len(),
+                results.len()
+            )),
+        ));
+    }
+    if path.exists() {
+        fs::remove_file(path).map_err(|err| db_error(path, err))?;
+    }
+    let mut conn = Connection::open(path).map_err(|err| db_error(path, err))?;
+    write_runs(&mut conn, manifest, results).map_err(|err| db_error(path, err))
+}
+
+fn write_runs(
+    conn: &mut Connection,
+    manifest: &Manifest,
+    results: &[Results],
+) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute_batch(SCHEMA)?;
+    {
+        let mut insert_run = tx.prepare(
+            "INSERT INTO runs (id, seed, model, project_hash, engine_version)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        let mut insert_override =
+            tx.prepare("INSERT INTO overrides (run_id, variable, value) VALUES (?1, ?2, ?3)")?;
+        let mut insert_variable = tx.prepare("INSERT INTO variables (id, name) VALUES (?1, ?2)")?;
+        let mut insert_value = tx.prepare(
+            "INSERT INTO \"values\" (run_id, variable_id, time, value) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+
+        let project_hash = format!("{:016x}", manifest.project_hash);
+        let mut variable_ids: HashMap<&str, i64> = HashMap::new();
+        for (i, (run, results)) in manifest.runs.iter().zip(results.iter()).enumerate() {
+            let run_id = i as i64 + 1;
+            // SQLite integers are signed; the seed's bits are kept as-is
+            insert_run.execute(params![
+                run_id,
+                run.seed as i64,
+                manifest.model_name,
+                project_hash,
+                manifest.engine_version
+            ])?;
+            for o in run.overrides.iter() {
+                insert_override.execute(params![run_id, o.ident, o.value])?;
+            }
+
+            let times = results.time_points();
+            for name in results.var_names() {
+                if name == "time" {
+                    continue;
+                }
+                let variable_id = match variable_ids.get(name) {
+                    Some(id) => *id,
+                    None => {
+                        let id = variable_ids.len() as i64 + 1;
+                        insert_variable.execute(params![id, name])?;
+                        variable_ids.insert(name, id);
+                        id
+                    }
+                };
+                let off = results.offsets[name];
+                for (time, step) in times.iter().zip(results.iter_rows()) {
+                    let value = Some(step[off]).filter(|value| !value.is_nan());
+                    insert_value.execute(params![run_id, variable_id, time, value])?;
+                }
+            }
+        }
+    }
+    tx.commit()
+}
+
+#[test]
+fn test_export_runs() {
+    use simlin_engine::datamodel::{Aux, Equation, Model, Project, SimSpecs, Variable, Visibility};
+    use simlin_engine::ensemble::{run_ensemble, Override, Run};
+
+    let aux = |ident: &str, eqn: &str| {
+        Variable::Aux(Aux {
+            ident: ident.to_owned(),
+            equation: Equation::Scalar(eqn.to_owned(), None),
+            documentation: "".to_owned(),
+            units: None,
+            gf: None,
+            can_be_module_input: false,
+            supplementary: false,
+            visibility: Visibility::Private,
+        })
+    };
+    let project = Project {
+        name: "test".to_owned(),
+        sim_specs: SimSpecs {
+            stop: 2.0,
+            ..Default::default()
+        },
+        dimensions: vec![],
+        units: vec![],
+        models: vec![Model {
+            name: "main".to_owned(),
+            variables: vec![aux("rate", "1"), aux("output", "rate * time")],
+            views: vec![],
+            graphs: vec![],
+            groups: vec![],
+            variable_index: Default::default(),
+        }],
+        constants: vec![],
+        tests: vec![],
+        source: None,
+        import_report: vec![],
+    };
+    let runs = [2.0, 3.0]
+        .iter()
+        .map(|rate| Run {
+            seed: 9,
+            overrides: vec![Override {
+                ident: "rate".to_owned(),
+                value: *rate,
+            }],
+        })
+        .collect();
+    let (manifest, results) = run_ensemble(&project, "main", runs).unwrap();
+
+    let dir = std::env::temp_dir().join(format!("simlin-sqlite-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("runs.db");
+    // an existing database is replaced rather than added to
+    export_runs(&path, &manifest, &results).unwrap();
+    export_runs(&path, &manifest, &results).unwrap();
+
+    let conn = Connection::open(&path).unwrap();
+    let count = |sql: &str| -> i64 { conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+    assert_eq!(2, count("SELECT COUNT(*) FROM runs"));
+    assert_eq!(2, count("SELECT COUNT(*) FROM overrides"));
+    // dt, initial_time and final_time are saved along with the model's
+    // own variables, like in a TSV export
+    assert_eq!(5, count("SELECT COUNT(*) FROM variables"));
+    assert_eq!(9, count("SELECT seed FROM runs WHERE id = 2"));
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT o.value, v.time, v.value FROM \"values\" v
+             JOIN variables ON variables.id = v.variable_id
+             JOIN overrides o ON o.run_id = v.run_id
+             WHERE variables.name = 'output' AND v.time = 2
+             ORDER BY v.run_id",
+        )
+        .unwrap();
+    let rows: Vec<(f64, f64, f64)> = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .unwrap()
+        .map(|row| row.unwrap())
+        .collect();
+    assert_eq!(vec![(2.0, 2.0, 4.0), (3.0, 2.0, 6.0)], rows);
+
+    assert!(export_runs(&path, &manifest, &results[..1]).is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,201 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Reading and writing workspace files, along with the model and data
+//! files they refer to.  Paths in a workspace are relative to the
+//! directory the workspace file is in.  Errors are boxed so that a file
+//! that couldn't be read stays an `io::Error`, distinct from the engine
+//! errors for files that could be read but not parsed.
+
+use std::error::Error as StdError;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::result::Result as StdResult;
+
+use simlin_engine::common::{Error, ErrorCode, ErrorKind};
+use simlin_engine::datamodel::Project;
+use simlin_engine::json;
+use simlin_engine::workspace::Workspace;
+
+use crate::{load_run, open_xmile};
+
+type Result<T> = StdResult<T, Box<dyn StdError>>;
+
+fn io_error(path: &Path, err: io::Error) -> io::Error {
+    io::Error::new(err.kind(), format!("{}: {}", path.display(), err))
+}
+
+fn import_error(path: &Path, code: ErrorCode, err: impl std::fmt::Display) -> Box<dyn StdError> {
+    Box::new(Error::new(
+        ErrorKind::Import,
+        code,
+        Some(format!("{}: {}", path.display(), err)),
+    ))
+}
+
+/// resolve returns the location of `path`, taken from the workspace at
+/// `workspace_path`.  Absolute paths are returned as-is.
+pub fn resolve(workspace_path: &str, path: &str) -> PathBuf {
+    let dir = Path::new(workspace_path).parent().unwrap_or(Path::new(""));
+    dir.join(path)
+}
+
+/// open_model reads a model file, picking the reader from its
+/// extension: `.mdl` for Vensim, `.json` for the output of `convert
+/// --to json`, and XMILE for anything else.
+pub fn open_model(path: &Path) -> Result<Project> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    if extension.as_deref() == Some("json") {
+        let contents = fs::read_to_string(path).map_err(|err| io_error(path, err))?;
+        return Ok(json::deserialize(&contents)?);
+    }
+    let file = File::open(path).map_err(|err| io_error(path, err))?;
+    let mut reader = BufReader::new(file);
+    let project = match extension.as_deref() {
+        #[cfg(feature = "vensim")]
+        Some("mdl") => crate::open_vensim(&mut reader)?,
+        _ => open_xmile(&mut reader)?,
+    };
+    Ok(project)
+}
+
+/// open_workspace reads the workspace at `path` along with its model.
+pub fn open_workspace(path: &str) -> Result<(Workspace, Project)> {
+    let contents = fs::read_to_string(path).map_err(|err| io_error(Path::new(path), err))?;
+    let workspace = Workspace::from_json(&contents)?;
+    let project = open_model(&resolve(path, &workspace.model))?;
+    Ok((workspace, project))
+}
+
+pub fn save_workspace(path: &str, workspace: &Workspace) -> io::Result<()> {
+    let mut contents = workspace.to_json();
+    contents.push('\n');
+    fs::write(path, contents).map_err(|err| io_error(Path::new(path), err))
+}
+
+/// load_data reads the (time, value) series for each of the workspace's
+/// data bindings, in order, skipping times without a value.
+pub fn load_data(path: &str, workspace: &Workspace) -> Result<Vec<Vec<(f64, f64)>>> {
+    workspace
+        .data
+        .iter()
+        .map(|binding| {
+            let data_path = resolve(path, &binding.path);
+            let run =
+                load_run(&data_path.to_string_lossy()).map_err(|err| -> Box<dyn StdError> {
+                    match err.downcast::<io::Error>() {
+                        Ok(err) => Box::new(io_error(&data_path, *err)),
+                        Err(err) => import_error(&data_path, ErrorCode::Generic, err),
+                    }
+                })?;
+            let column = binding.column.as_deref().unwrap_or(&binding.ident);
+            let values = match run.get_series(column) {
+                Some(values) => values,
+                None => return Err(import_error(&data_path, ErrorCode::DoesNotExist, column)),
+            };
+            Ok(run
+                .time_points()
+                .into_iter()
+                .zip(values)
+                .filter(|(_, value)| !value.is_nan())
+                .collect())
+        })
+        .collect()
+}
+
+#[test]
+fn test_workspace_files() {
+    use simlin_engine::datamodel::{Aux, Equation, Model, SimSpecs, Variable, Visibility};
+    use simlin_engine::workspace::DataBinding;
+
+    let dir = std::env::temp_dir().join(format!("simlin-workspace-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let aux = |ident: &str, eqn: &str| {
+        Variable::Aux(Aux {
+            ident: ident.to_owned(),
+            equation: Equation::Scalar(eqn.to_owned(), None),
+            documentation: "".to_owned(),
+            units: None,
+            gf: None,
+            can_be_module_input: false,
+            supplementary: false,
+            visibility: Visibility::Private,
+        })
+    };
+    let project = Project {
+        name: "test".to_owned(),
+        sim_specs: SimSpecs {
+            stop: 2.0,
+            ..Default::default()
+        },
+        dimensions: vec![],
+        units: vec![],
+        models: vec![Model {
+            name: "main".to_owned(),
+            variables: vec![aux("demand", "1"), aux("double_demand", "2 * demand")],
+            views: vec![],
+            graphs: vec![],
+            groups: vec![],
+            variable_index: Default::default(),
+        }],
+        constants: vec![],
+        tests: vec![],
+        source: None,
+        import_report: vec![],
+    };
+    fs::write(dir.join("model.json"), json::serialize(&project)).unwrap();
+    fs::write(dir.join("demand.csv"), "Time,Orders\n0,1\n2,3\n").unwrap();
+
+    let mut workspace = Workspace::new("model.json");
+    workspace.data.push(DataBinding {
+        ident: "demand".to_owned(),
+        path: "demand.csv".to_owned(),
+        column: Some("Orders".to_owned()),
+    });
+    let path = dir.join("analysis.json");
+    let path = path.to_str().unwrap();
+    save_workspace(path, &workspace).unwrap();
+
+    let (opened, opened_project) = open_workspace(path).unwrap();
+    assert_eq!(workspace, opened);
+    assert_eq!(project, opened_project);
+    let data = load_data(path, &opened).unwrap();
+    assert_eq!(vec![vec![(0.0, 1.0), (2.0, 3.0)]], data);
+    let results = opened.simulate(&opened_project, None, &data).unwrap();
+    assert_eq!(
+        Some(vec![2.0, 4.0, 6.0]),
+        results.get_series("double_demand")
+    );
+
+    let code = |err: Box<dyn StdError>| err.downcast_ref::<Error>().map(|err| err.code);
+    workspace.data[0].column = None;
+    assert_eq!(
+        Some(ErrorCode::DoesNotExist),
+        code(load_data(path, &workspace).unwrap_err())
+    );
+    // files that can't be parsed are told apart from ones that can't be read
+    fs::write(
+        dir.join("demand.csv"),
+        "Time,Demand
+0,lots
+",
+    )
+    .unwrap();
+    assert_eq!(
+        Some(ErrorCode::Generic),
+        code(load_data(path, &workspace).unwrap_err())
+    );
+    fs::remove_file(dir.join("demand.csv")).unwrap();
+    assert!(load_data(path, &workspace).unwrap_err().is::<io::Error>());
+    workspace.model = "missing.stmx".to_owned();
+    save_workspace(path, &workspace).unwrap();
+    assert!(open_workspace(path).is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
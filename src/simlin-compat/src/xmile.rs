@@ -14,7 +14,7 @@ use crate::engine::datamodel::Visibility;
 use crate::xmile::view_element::LinkEnd;
 use simlin_engine::common::{canonicalize, quoteize, Result};
 use simlin_engine::datamodel;
-use simlin_engine::datamodel::{Equation, Rect, ViewElement};
+use simlin_engine::datamodel::{Equation, ImportIssue, Rect, ViewElement};
 
 trait ToXml<W: Clone + Write> {
     fn write_xml(&self, writer: &mut Writer<W>) -> Result<()>;
@@ -131,6 +131,8 @@ impl From<File> for datamodel::Project {
                 save_step: None,
                 method: None,
                 time_units: None,
+                rel_tolerance: None,
+                abs_tolerance: None,
             })),
             dimensions: match file.dimensions {
                 None => vec![],
@@ -155,7 +157,10 @@ impl From<File> for datamodel::Project {
                 .into_iter()
                 .map(datamodel::Model::from)
                 .collect(),
+            constants: vec![],
+            tests: vec![],
             source: None,
+            import_report: vec![],
         }
     }
 }
@@ -433,6 +438,12 @@ pub struct SimSpecs {
     pub method: Option<String>,
     #[serde(rename = "@time_units")]
     pub time_units: Option<String>,
+    // the Rk45 tolerances, written as simlin: attributes when they
+    // aren't the defaults
+    #[serde(rename = "@rel_tolerance")]
+    pub rel_tolerance: Option<f64>,
+    #[serde(rename = "@abs_tolerance")]
+    pub abs_tolerance: Option<f64>,
 }
 
 impl ToXml<XmlWriter> for SimSpecs {
@@ -448,6 +459,14 @@ impl ToXml<XmlWriter> for SimSpecs {
             let save_interval = format!("{}", save_step);
             elem.push_attribute(("isee:save_interval", save_interval.as_str()));
         }
+        if let Some(rel_tolerance) = self.rel_tolerance {
+            let rel_tolerance = format!("{}", rel_tolerance);
+            elem.push_attribute(("simlin:rel_tolerance", rel_tolerance.as_str()));
+        }
+        if let Some(abs_tolerance) = self.abs_tolerance {
+            let abs_tolerance = format!("{}", abs_tolerance);
+            elem.push_attribute(("simlin:abs_tolerance", abs_tolerance.as_str()));
+        }
         writer.write_event(Event::Start(elem)).map_err(xml_error)?;
 
         let start = format!("{}", self.start);
@@ -486,8 +505,16 @@ impl From<SimSpecs> for datamodel::SimSpecs {
             sim_method: match sim_method.as_str() {
                 "euler" => datamodel::SimMethod::Euler,
                 "rk4" => datamodel::SimMethod::RungeKutta4,
+                "rk45" => datamodel::SimMethod::Rk45,
                 _ => datamodel::SimMethod::Euler,
             },
+            tolerances: {
+                let defaults = datamodel::Tolerances::default();
+                datamodel::Tolerances {
+                    relative: sim_specs.rel_tolerance.unwrap_or(defaults.relative),
+                    absolute: sim_specs.abs_tolerance.unwrap_or(defaults.absolute),
+                }
+            },
             time_units: sim_specs.time_units,
         }
     }
@@ -495,6 +522,8 @@ impl From<SimSpecs> for datamodel::SimSpecs {
 
 impl From<datamodel::SimSpecs> for SimSpecs {
     fn from(sim_specs: datamodel::SimSpecs) -> Self {
+        let tolerances = Some(sim_specs.tolerances)
+            .filter(|tolerances| *tolerances != datamodel::Tolerances::default());
         SimSpecs {
             start: sim_specs.start,
             stop: sim_specs.stop,
@@ -509,8 +538,11 @@ impl From<datamodel::SimSpecs> for SimSpecs {
             method: Some(match sim_specs.sim_method {
                 datamodel::SimMethod::Euler => "euler".to_string(),
                 datamodel::SimMethod::RungeKutta4 => "rk4".to_string(),
+                datamodel::SimMethod::Rk45 => "rk45".to_string(),
             }),
             time_units: sim_specs.time_units,
+            rel_tolerance: tolerances.map(|tolerances| tolerances.relative),
+            abs_tolerance: tolerances.map(|tolerances| tolerances.absolute),
         }
     }
 }
@@ -974,6 +1006,19 @@ impl ToXml<XmlWriter> for Model {
 
 impl From<Model> for datamodel::Model {
     fn from(model: Model) -> Self {
+        // graphs and tables can be in any kind of view, and usually are
+        // in an interface view
+        let graphs = model
+            .views
+            .iter()
+            .flat_map(|views| views.view.iter().flatten())
+            .flat_map(|view| view.objects.iter())
+            .filter_map(|object| match object {
+                ViewObject::Graph(graph) => Some(datamodel::Graph::from(graph.clone())),
+                ViewObject::Table(table) => Some(datamodel::Graph::from(table.clone())),
+                _ => None,
+            })
+            .collect();
         let views = model
             .views
             .clone()
@@ -988,19 +1033,31 @@ impl From<Model> for datamodel::Model {
                 datamodel::View::from(v)
             })
             .collect();
+        let (groups, variables): (Vec<Var>, Vec<Var>) = model
+            .variables
+            .map(|vars| vars.variables)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|v| !matches!(v, Var::Unhandled))
+            .partition(|v| matches!(v, Var::Group(_)));
+        let variables: Vec<datamodel::Variable> = variables
+            .into_iter()
+            .map(datamodel::Variable::from)
+            .collect();
+        let groups = groups
+            .into_iter()
+            .filter_map(|v| match v {
+                Var::Group(group) => Some(group.into_datamodel(&variables)),
+                _ => None,
+            })
+            .collect();
         datamodel::Model {
             name: canonicalize(model.name.as_deref().unwrap_or("main")),
-            variables: match model.variables {
-                Some(Variables {
-                    variables: vars, ..
-                }) => vars
-                    .into_iter()
-                    .filter(|v| !matches!(v, Var::Unhandled))
-                    .map(datamodel::Variable::from)
-                    .collect(),
-                _ => vec![],
-            },
+            variables,
             views,
+            graphs,
+            groups,
+            variable_index: Default::default(),
         }
     }
 }
@@ -1012,18 +1069,42 @@ impl From<datamodel::Model> for Model {
             namespaces: None,
             resource: None,
             sim_specs: None,
-            variables: if model.variables.is_empty() {
+            variables: if model.variables.is_empty() && model.groups.is_empty() {
                 None
             } else {
-                let variables = model.variables.into_iter().map(Var::from).collect();
+                let variables = model
+                    .variables
+                    .into_iter()
+                    .map(Var::from)
+                    .chain(
+                        model
+                            .groups
+                            .into_iter()
+                            .map(|group| Var::Group(Group::from(group))),
+                    )
+                    .collect();
                 Some(Variables { variables })
             },
-            views: if model.views.is_empty() {
+            views: if model.views.is_empty() && model.graphs.is_empty() {
                 None
             } else {
-                Some(Views {
-                    view: Some(model.views.into_iter().map(View::from).collect()),
-                })
+                let mut views: Vec<View> = model.views.into_iter().map(View::from).collect();
+                if !model.graphs.is_empty() {
+                    if views.is_empty() {
+                        views.push(View::from(datamodel::View::StockFlow(
+                            datamodel::StockFlow {
+                                elements: vec![],
+                                view_box: Default::default(),
+                                zoom: 1.0,
+                            },
+                        )));
+                    }
+                    // saved graphs are written into the first view
+                    views[0]
+                        .objects
+                        .extend(model.graphs.into_iter().map(view_element::graph_object));
+                }
+                Some(Views { view: Some(views) })
             },
         }
     }
@@ -1087,12 +1168,12 @@ pub mod view_element {
     use crate::engine::datamodel::StockFlow;
     use crate::xmile::{
         write_tag, write_tag_end, write_tag_start, write_tag_start_with_attrs, write_tag_text,
-        write_tag_with_attrs, ToXml, XmlWriter, STOCK_HEIGHT, STOCK_WIDTH,
+        write_tag_with_attrs, GraphicalFunctionScale, ToXml, XmlWriter, STOCK_HEIGHT, STOCK_WIDTH,
     };
     use quick_xml::Writer;
     use serde::{Deserialize, Deserializer, Serialize};
-    use simlin_engine::common::Result;
-    use simlin_engine::datamodel::view_element::LinkShape;
+    use simlin_engine::common::{canonicalize, Result};
+    use simlin_engine::datamodel::view_element::{LinkPolarity, LinkShape};
 
     // converts an angle associated with a connector (in degrees) into an
     // angle in the coordinate system of SVG canvases where the origin is
@@ -1701,6 +1782,10 @@ pub mod view_element {
         pub angle: Option<f64>,
         #[serde(rename = "@is_straight")]
         pub is_straight: Option<bool>,
+        #[serde(rename = "@polarity")]
+        pub polarity: Option<String>, // '+', '-' or 'none'
+        #[serde(rename = "@delay_mark")]
+        pub delay_mark: Option<bool>,
         #[serde(rename = "pts")]
         pub points: Option<Points>, // for multi-point connectors
     }
@@ -1727,10 +1812,16 @@ pub mod view_element {
         fn write_xml(&self, writer: &mut Writer<XmlWriter>) -> Result<()> {
             let angle = self.angle.map(|angle| format!("{}", angle));
 
-            let mut attrs = Vec::with_capacity(1);
+            let mut attrs = Vec::with_capacity(3);
             if let Some(ref angle) = angle {
                 attrs.push(("angle", angle.as_str()));
             }
+            if let Some(ref polarity) = self.polarity {
+                attrs.push(("polarity", polarity.as_str()));
+            }
+            if self.delay_mark == Some(true) {
+                attrs.push(("delay_mark", "true"));
+            }
             write_tag_start_with_attrs(writer, "connector", &attrs)?;
 
             write_tag_start(writer, "from")?;
@@ -1792,11 +1883,18 @@ pub mod view_element {
                     v.angle.unwrap_or(0.0),
                 ))
             };
+            let polarity = match v.polarity.as_deref() {
+                Some("+") => Some(LinkPolarity::Positive),
+                Some("-") => Some(LinkPolarity::Negative),
+                _ => None,
+            };
             datamodel::view_element::Link {
                 uid: v.uid.unwrap_or(-1),
                 from_uid: v.from_uid.unwrap_or(-1),
                 to_uid: v.to_uid.unwrap_or(-1),
                 shape,
+                polarity,
+                delay_mark: v.delay_mark.unwrap_or(false),
             }
         }
     }
@@ -1834,6 +1932,14 @@ pub mod view_element {
                 to_uid: Some(v.to_uid),
                 angle,
                 is_straight,
+                polarity: v.polarity.map(|polarity| {
+                    match polarity {
+                        LinkPolarity::Positive => "+",
+                        LinkPolarity::Negative => "-",
+                    }
+                    .to_owned()
+                }),
+                delay_mark: if v.delay_mark { Some(true) } else { None },
                 points,
             }
         }
@@ -1847,12 +1953,16 @@ pub mod view_element {
                 from_uid: 45,
                 to_uid: 67,
                 shape: LinkShape::Straight,
+                polarity: None,
+                delay_mark: false,
             },
             datamodel::view_element::Link {
                 uid: 33,
                 from_uid: 45,
                 to_uid: 67,
                 shape: LinkShape::Arc(351.3),
+                polarity: Some(LinkPolarity::Negative),
+                delay_mark: true,
             },
             datamodel::view_element::Link {
                 uid: 33,
@@ -1863,6 +1973,8 @@ pub mod view_element {
                     y: 2.2,
                     attached_to_uid: None,
                 }]),
+                polarity: Some(LinkPolarity::Positive),
+                delay_mark: false,
             },
         ];
         let view = StockFlow {
@@ -2083,6 +2195,262 @@ pub mod view_element {
             assert_eq!(expected, actual);
         }
     }
+
+    #[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+    pub struct Entity {
+        #[serde(rename = "@name")]
+        pub name: String,
+    }
+
+    #[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+    pub struct Plot {
+        #[serde(rename = "@color")]
+        pub color: Option<String>,
+        pub entity: Option<Entity>,
+        pub scale: Option<GraphicalFunctionScale>,
+    }
+
+    #[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+    pub struct Graph {
+        #[serde(rename = "@type")]
+        pub kind: Option<String>,
+        #[serde(rename = "@title")]
+        pub title: Option<String>,
+        #[serde(rename = "plot", default)]
+        pub plots: Vec<Plot>,
+    }
+
+    impl ToXml<XmlWriter> for Graph {
+        fn write_xml(&self, writer: &mut Writer<XmlWriter>) -> Result<()> {
+            let mut attrs = vec![("type", self.kind.as_deref().unwrap_or("time_series"))];
+            if let Some(ref title) = self.title {
+                attrs.push(("title", title.as_str()));
+            }
+            write_tag_start_with_attrs(writer, "graph", &attrs)?;
+
+            for (i, plot) in self.plots.iter().enumerate() {
+                let index = format!("{}", i);
+                let mut attrs = vec![("index", index.as_str())];
+                if let Some(ref color) = plot.color {
+                    attrs.push(("color", color.as_str()));
+                }
+                write_tag_start_with_attrs(writer, "plot", &attrs)?;
+                if let Some(ref entity) = plot.entity {
+                    write_tag_with_attrs(writer, "entity", "", &[("name", entity.name.as_str())])?;
+                }
+                if let Some(ref scale) = plot.scale {
+                    let min = format!("{}", scale.min);
+                    let max = format!("{}", scale.max);
+                    let attrs = &[("min", min.as_str()), ("max", max.as_str())];
+                    write_tag_with_attrs(writer, "scale", "", attrs)?;
+                }
+                write_tag_end(writer, "plot")?;
+            }
+
+            write_tag_end(writer, "graph")
+        }
+    }
+
+    impl From<Graph> for datamodel::Graph {
+        fn from(v: Graph) -> Self {
+            datamodel::Graph {
+                title: v.title.unwrap_or_default(),
+                kind: match v.kind.as_deref() {
+                    Some("scatter") => datamodel::GraphKind::Scatter,
+                    Some("bar") => datamodel::GraphKind::Bar,
+                    _ => datamodel::GraphKind::TimeSeries,
+                },
+                plots: v
+                    .plots
+                    .into_iter()
+                    .filter_map(|plot| {
+                        Some(datamodel::Plot {
+                            ident: canonicalize(&plot.entity?.name),
+                            color: plot.color,
+                            scale: plot.scale.map(datamodel::GraphicalFunctionScale::from),
+                            precision: None,
+                        })
+                    })
+                    .collect(),
+                interval: None,
+            }
+        }
+    }
+
+    #[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+    pub struct TableItem {
+        /// `time` for the column of times; otherwise the item is a variable
+        #[serde(rename = "@type")]
+        pub kind: Option<String>,
+        #[serde(rename = "@precision")]
+        pub precision: Option<usize>,
+        pub entity: Option<Entity>,
+    }
+
+    impl TableItem {
+        fn is_time(&self) -> bool {
+            self.kind.as_deref() == Some("time")
+        }
+    }
+
+    #[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+    pub struct Table {
+        #[serde(rename = "@title")]
+        pub title: Option<String>,
+        /// the time between rows, or `DT` for every time step
+        #[serde(rename = "@interval")]
+        pub interval: Option<String>,
+        #[serde(rename = "item", default)]
+        pub items: Vec<TableItem>,
+    }
+
+    impl ToXml<XmlWriter> for Table {
+        fn write_xml(&self, writer: &mut Writer<XmlWriter>) -> Result<()> {
+            let mut attrs = vec![];
+            if let Some(ref title) = self.title {
+                attrs.push(("title", title.as_str()));
+            }
+            if let Some(ref interval) = self.interval {
+                attrs.push(("interval", interval.as_str()));
+            }
+            write_tag_start_with_attrs(writer, "table", &attrs)?;
+
+            for item in self.items.iter() {
+                let precision = item.precision.map(|precision| format!("{}", precision));
+                let mut attrs = vec![];
+                if let Some(ref kind) = item.kind {
+                    attrs.push(("type", kind.as_str()));
+                }
+                if let Some(ref precision) = precision {
+                    attrs.push(("precision", precision.as_str()));
+                }
+                write_tag_start_with_attrs(writer, "item", &attrs)?;
+                if let Some(ref entity) = item.entity {
+                    write_tag_with_attrs(writer, "entity", "", &[("name", entity.name.as_str())])?;
+                }
+                write_tag_end(writer, "item")?;
+            }
+
+            write_tag_end(writer, "table")
+        }
+    }
+
+    impl From<Table> for datamodel::Graph {
+        fn from(v: Table) -> Self {
+            datamodel::Graph {
+                title: v.title.unwrap_or_default(),
+                kind: datamodel::GraphKind::Table,
+                plots: v
+                    .items
+                    .into_iter()
+                    .filter(|item| !item.is_time())
+                    .filter_map(|item| {
+                        Some(datamodel::Plot {
+                            ident: canonicalize(&item.entity?.name),
+                            color: None,
+                            scale: None,
+                            precision: item.precision,
+                        })
+                    })
+                    .collect(),
+                // an interval of DT (or anything else that isn't a
+                // number) is every saved step
+                interval: v.interval.and_then(|interval| interval.trim().parse().ok()),
+            }
+        }
+    }
+
+    /// graph_object returns the XMILE element for a saved graph: a
+    /// `<table>` for tables, and a `<graph>` for everything else.
+    pub fn graph_object(graph: datamodel::Graph) -> super::ViewObject {
+        let title = if graph.title.is_empty() {
+            None
+        } else {
+            Some(graph.title)
+        };
+        let kind = match graph.kind {
+            datamodel::GraphKind::TimeSeries => "time_series",
+            datamodel::GraphKind::Scatter => "scatter",
+            datamodel::GraphKind::Bar => "bar",
+            datamodel::GraphKind::Table => {
+                let time = TableItem {
+                    kind: Some("time".to_owned()),
+                    precision: None,
+                    entity: None,
+                };
+                let items = graph.plots.into_iter().map(|plot| TableItem {
+                    kind: None,
+                    precision: plot.precision,
+                    entity: Some(Entity { name: plot.ident }),
+                });
+                return super::ViewObject::Table(Table {
+                    title,
+                    interval: graph.interval.map(|interval| format!("{}", interval)),
+                    items: std::iter::once(time).chain(items).collect(),
+                });
+            }
+        };
+        super::ViewObject::Graph(Graph {
+            kind: Some(kind.to_owned()),
+            title,
+            plots: graph
+                .plots
+                .into_iter()
+                .map(|plot| Plot {
+                    color: plot.color,
+                    entity: Some(Entity { name: plot.ident }),
+                    scale: plot.scale.map(GraphicalFunctionScale::from),
+                })
+                .collect(),
+        })
+    }
+
+    #[test]
+    fn test_graph_roundtrip() {
+        let cases: &[_] = &[
+            datamodel::Graph {
+                title: "Population".to_owned(),
+                kind: datamodel::GraphKind::TimeSeries,
+                plots: vec![
+                    datamodel::Plot {
+                        ident: "population".to_owned(),
+                        color: Some("blue".to_owned()),
+                        scale: Some(datamodel::GraphicalFunctionScale {
+                            min: 0.0,
+                            max: 100.0,
+                        }),
+                        precision: None,
+                    },
+                    datamodel::Plot {
+                        ident: "births".to_owned(),
+                        color: None,
+                        scale: None,
+                        precision: None,
+                    },
+                ],
+                interval: None,
+            },
+            datamodel::Graph {
+                title: "".to_owned(),
+                kind: datamodel::GraphKind::Table,
+                plots: vec![datamodel::Plot {
+                    ident: "population".to_owned(),
+                    color: None,
+                    scale: None,
+                    precision: Some(1),
+                }],
+                interval: Some(0.25),
+            },
+        ];
+        for expected in cases {
+            let actual = match graph_object(expected.clone()) {
+                super::ViewObject::Graph(graph) => datamodel::Graph::from(graph),
+                super::ViewObject::Table(table) => datamodel::Graph::from(table),
+                _ => unreachable!(),
+            };
+            assert_eq!(*expected, actual);
+        }
+    }
 }
 
 #[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
@@ -2096,6 +2464,8 @@ pub enum ViewObject {
     Module(view_element::Module),
     Cloud(view_element::Cloud),
     Alias(view_element::Alias),
+    Graph(view_element::Graph),
+    Table(view_element::Table),
     // Style(Style),
     #[serde(other)]
     Unhandled,
@@ -2114,6 +2484,8 @@ impl ToXml<XmlWriter> for ViewObject {
                 Ok(())
             }
             ViewObject::Alias(alias) => alias.write_xml(writer),
+            ViewObject::Graph(graph) => graph.write_xml(writer),
+            ViewObject::Table(table) => table.write_xml(writer),
             ViewObject::Unhandled => {
                 // explicitly ignore unhandled things
                 Ok(())
@@ -2123,6 +2495,15 @@ impl ToXml<XmlWriter> for ViewObject {
 }
 
 impl ViewObject {
+    /// is_element is whether the object is drawn on the stock and flow
+    /// diagram, rather than being a graph, table or something unknown.
+    fn is_element(&self) -> bool {
+        !matches!(
+            self,
+            ViewObject::Graph(_) | ViewObject::Table(_) | ViewObject::Unhandled
+        )
+    }
+
     pub fn set_uid(&mut self, uid: i32) -> bool {
         match self {
             ViewObject::Aux(aux) => aux.uid = Some(uid),
@@ -2132,7 +2513,8 @@ impl ViewObject {
             ViewObject::Module(module) => module.uid = Some(uid),
             ViewObject::Cloud(cloud) => cloud.uid = uid,
             ViewObject::Alias(alias) => alias.uid = Some(uid),
-            ViewObject::Unhandled => {
+            // graphs and tables aren't diagram elements, and have no uid
+            ViewObject::Graph(_) | ViewObject::Table(_) | ViewObject::Unhandled => {
                 return false;
             }
         };
@@ -2148,7 +2530,7 @@ impl ViewObject {
             ViewObject::Module(module) => module.uid,
             ViewObject::Cloud(cloud) => Some(cloud.uid),
             ViewObject::Alias(alias) => alias.uid,
-            ViewObject::Unhandled => None,
+            ViewObject::Graph(_) | ViewObject::Table(_) | ViewObject::Unhandled => None,
         }
     }
 
@@ -2161,7 +2543,7 @@ impl ViewObject {
             ViewObject::Module(module) => Some(canonicalize(&module.name)),
             ViewObject::Cloud(_cloud) => None,
             ViewObject::Alias(_alias) => None,
-            ViewObject::Unhandled => None,
+            ViewObject::Graph(_) | ViewObject::Table(_) | ViewObject::Unhandled => None,
         }
     }
 }
@@ -2190,7 +2572,9 @@ impl From<ViewObject> for datamodel::ViewElement {
             ViewObject::Alias(v) => {
                 datamodel::ViewElement::Alias(datamodel::view_element::Alias::from(v))
             }
-            ViewObject::Unhandled => unreachable!("must filter out unhandled"),
+            ViewObject::Graph(_) | ViewObject::Table(_) | ViewObject::Unhandled => {
+                unreachable!("must filter out graphs, tables and unhandled")
+            }
         }
     }
 }
@@ -2267,20 +2651,23 @@ enum CloudPosition {
 
 fn cloud_for(flow: &ViewObject, pos: CloudPosition, uid: i32) -> ViewObject {
     if let ViewObject::Flow(flow) = flow {
-        let (x, y) = match pos {
-            CloudPosition::Source => {
-                let point = flow.points.as_ref().unwrap().points.first().unwrap();
-                (point.x, point.y)
-            }
-            CloudPosition::Sink => {
-                let point = flow.points.as_ref().unwrap().points.last().unwrap();
-                (point.x, point.y)
-            }
+        let points = flow
+            .points
+            .as_ref()
+            .map(|points| points.points.as_slice())
+            .unwrap_or_default();
+        let point = match pos {
+            CloudPosition::Source => points.first(),
+            CloudPosition::Sink => points.last(),
         };
+        // flows without points get clouds at their valve
+        let (x, y) = point
+            .map(|point| (point.x, point.y))
+            .unwrap_or((flow.x, flow.y));
 
         ViewObject::Cloud(view_element::Cloud {
             uid,
-            flow_uid: flow.uid.unwrap(),
+            flow_uid: flow.uid.unwrap_or(-1),
             x,
             y,
         })
@@ -2356,12 +2743,17 @@ impl View {
             .collect();
         let mut result: HashMap<i32, (Option<i32>, Option<i32>)> = display_flows
             .iter()
-            .map(|v| (v.uid().unwrap(), (None, None)))
+            .filter_map(|v| v.uid())
+            .map(|uid| (uid, (None, None)))
             .collect();
 
         for element in display_stocks {
-            let ident = element.ident().unwrap();
-            if let Var::Stock(stock) = model.get_var(&ident).unwrap() {
+            let ident = match element.ident() {
+                Some(ident) => ident,
+                None => continue,
+            };
+            // the view can refer to stocks that aren't in the model
+            if let Some(Var::Stock(stock)) = model.get_var(&ident) {
                 if stock.outflows.is_some() {
                     for outflow in stock.outflows.as_ref().unwrap() {
                         let outflow_ident = canonicalize(outflow);
@@ -2369,8 +2761,10 @@ impl View {
                             continue;
                         }
                         let outflow_uid = uid_map[&outflow_ident];
-                        let end = result.get_mut(&outflow_uid).unwrap();
-                        end.0 = Some(uid_map[&ident]);
+                        // and stocks can list flows that aren't flows
+                        if let Some(end) = result.get_mut(&outflow_uid) {
+                            end.0 = uid_map.get(&ident).copied();
+                        }
                     }
                 }
                 if stock.inflows.is_some() {
@@ -2380,8 +2774,9 @@ impl View {
                             continue;
                         }
                         let inflow_uid = uid_map[&inflow_ident];
-                        let end = result.get_mut(&inflow_uid).unwrap();
-                        end.1 = Some(uid_map[&ident]);
+                        if let Some(end) = result.get_mut(&inflow_uid) {
+                            end.1 = uid_map.get(&ident).copied();
+                        }
                     }
                 }
             }
@@ -2405,7 +2800,10 @@ impl View {
             .collect();
 
         for flow in display_flows {
-            let ends = &flow_ends[&flow.uid().unwrap()];
+            let ends = match flow.uid().and_then(|uid| flow_ends.get(&uid)) {
+                Some(ends) => ends,
+                None => continue,
+            };
             let source_uid = ends.0.unwrap_or_else(|| {
                 let uid = self.next_uid.unwrap();
                 self.next_uid = Some(uid + 1);
@@ -2519,7 +2917,7 @@ impl From<View> for datamodel::View {
                 elements: v
                     .objects
                     .into_iter()
-                    .filter(|v| !matches!(v, ViewObject::Unhandled))
+                    .filter(|v| v.is_element())
                     .map(datamodel::ViewElement::from)
                     .collect(),
                 view_box,
@@ -2792,6 +3190,8 @@ pub struct Stock {
     pub elements: Option<Vec<VarElement>>,
     #[serde(rename = "@access")]
     pub access: Option<String>,
+    #[serde(rename = "@supplementary")]
+    pub supplementary: Option<bool>,
 }
 
 impl ToXml<XmlWriter> for Stock {
@@ -2800,6 +3200,9 @@ impl ToXml<XmlWriter> for Stock {
         if let Some(access) = self.access.as_ref() {
             attrs.push(("access", access.as_str()));
         }
+        if self.supplementary == Some(true) {
+            attrs.push(("simlin:supplementary", "true"));
+        }
         write_tag_start_with_attrs(writer, "stock", &attrs)?;
 
         if let Some(VarDimensions {
@@ -2854,7 +3257,7 @@ macro_rules! convert_equation(
     ($var:expr) => {{
         if let Some(elements) = $var.elements {
             let dimensions = match $var.dimensions {
-                Some(dimensions) => dimensions.dimensions.unwrap().into_iter().map(|e| canonicalize(&e.name)).collect(),
+                Some(dimensions) => dimensions.dimensions.unwrap_or_default().into_iter().map(|e| canonicalize(&e.name)).collect(),
                 None => vec![],
             };
             let elements = elements.into_iter().map(|e| {
@@ -2876,7 +3279,7 @@ macro_rules! convert_stock_equation(
     ($var:expr) => {{
         if let Some(elements) = $var.elements {
             let dimensions = match $var.dimensions {
-                Some(dimensions) => dimensions.dimensions.unwrap().into_iter().map(|e| canonicalize(&e.name)).collect(),
+                Some(dimensions) => dimensions.dimensions.unwrap_or_default().into_iter().map(|e| canonicalize(&e.name)).collect(),
                 None => vec![],
             };
             let elements = elements.into_iter().map(|e| {
@@ -2915,7 +3318,9 @@ impl From<Stock> for datamodel::Stock {
             inflows,
             outflows,
             non_negative: stock.non_negative.is_some(),
+            force_euler: false,
             can_be_module_input: can_be_module_input(&stock.access),
+            supplementary: stock.supplementary.unwrap_or(false),
             visibility: visibility(&stock.access),
         }
     }
@@ -2995,6 +3400,7 @@ impl From<datamodel::Stock> for Stock {
                 ),
             },
             access: access_from(stock.visibility, stock.can_be_module_input),
+            supplementary: stock.supplementary.then_some(true),
         }
     }
 }
@@ -3015,6 +3421,8 @@ pub struct Flow {
     pub elements: Option<Vec<VarElement>>,
     #[serde(rename = "@access")]
     pub access: Option<String>,
+    #[serde(rename = "@supplementary")]
+    pub supplementary: Option<bool>,
 }
 
 impl ToXml<XmlWriter> for Flow {
@@ -3023,6 +3431,9 @@ impl ToXml<XmlWriter> for Flow {
         if let Some(access) = self.access.as_ref() {
             attrs.push(("access", access.as_str()));
         }
+        if self.supplementary == Some(true) {
+            attrs.push(("simlin:supplementary", "true"));
+        }
         write_tag_start_with_attrs(writer, "flow", &attrs)?;
 
         if let Some(VarDimensions {
@@ -3077,6 +3488,7 @@ impl From<Flow> for datamodel::Flow {
             gf: flow.gf.map(datamodel::GraphicalFunction::from),
             non_negative: flow.non_negative.is_some(),
             can_be_module_input: can_be_module_input(&flow.access),
+            supplementary: flow.supplementary.unwrap_or(false),
             visibility: visibility(&flow.access),
         }
     }
@@ -3152,6 +3564,7 @@ impl From<datamodel::Flow> for Flow {
                 ),
             },
             access: access_from(flow.visibility, flow.can_be_module_input),
+            supplementary: flow.supplementary.then_some(true),
         }
     }
 }
@@ -3171,6 +3584,8 @@ pub struct Aux {
     pub elements: Option<Vec<VarElement>>,
     #[serde(rename = "@access")]
     pub access: Option<String>,
+    #[serde(rename = "@supplementary")]
+    pub supplementary: Option<bool>,
 }
 
 impl ToXml<XmlWriter> for Aux {
@@ -3179,6 +3594,9 @@ impl ToXml<XmlWriter> for Aux {
         if let Some(access) = self.access.as_ref() {
             attrs.push(("access", access.as_str()));
         }
+        if self.supplementary == Some(true) {
+            attrs.push(("simlin:supplementary", "true"));
+        }
         write_tag_start_with_attrs(writer, "aux", &attrs)?;
 
         if let Some(VarDimensions {
@@ -3229,6 +3647,7 @@ impl From<Aux> for datamodel::Aux {
             units: aux.units,
             gf: aux.gf.map(datamodel::GraphicalFunction::from),
             can_be_module_input: can_be_module_input(&aux.access),
+            supplementary: aux.supplementary.unwrap_or(false),
             visibility: visibility(&aux.access),
         }
     }
@@ -3299,6 +3718,69 @@ impl From<datamodel::Aux> for Aux {
                 ),
             },
             access: access_from(aux.visibility, aux.can_be_module_input),
+            supplementary: aux.supplementary.then_some(true),
+        }
+    }
+}
+
+/// Group is a named set of variables, like a sector of a model.
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct Group {
+    #[serde(rename = "@name")]
+    pub name: String,
+    pub doc: Option<String>,
+    #[serde(rename = "entity", default)]
+    pub entities: Vec<view_element::Entity>,
+}
+
+impl ToXml<XmlWriter> for Group {
+    fn write_xml(&self, writer: &mut Writer<XmlWriter>) -> Result<()> {
+        write_tag_start_with_attrs(writer, "group", &[("name", self.name.as_str())])?;
+        if let Some(ref doc) = self.doc {
+            write_tag(writer, "doc", doc)?;
+        }
+        for entity in self.entities.iter() {
+            write_tag_with_attrs(writer, "entity", "", &[("name", entity.name.as_str())])?;
+        }
+        write_tag_end(writer, "group")
+    }
+}
+
+impl Group {
+    /// into_datamodel converts the group, leaving out members that
+    /// aren't among `variables` (like Vensim's subscript ranges, which
+    /// xmutil lists in groups).
+    fn into_datamodel(self, variables: &[datamodel::Variable]) -> datamodel::Group {
+        datamodel::Group {
+            name: self.name,
+            documentation: self
+                .doc
+                .map(|doc| doc.trim().to_owned())
+                .unwrap_or_default(),
+            members: self
+                .entities
+                .into_iter()
+                .map(|entity| canonicalize(&entity.name))
+                .filter(|ident| variables.iter().any(|var| var.get_ident() == ident))
+                .collect(),
+        }
+    }
+}
+
+impl From<datamodel::Group> for Group {
+    fn from(group: datamodel::Group) -> Self {
+        Group {
+            name: group.name,
+            doc: if group.documentation.is_empty() {
+                None
+            } else {
+                Some(group.documentation)
+            },
+            entities: group
+                .members
+                .into_iter()
+                .map(|name| view_element::Entity { name })
+                .collect(),
         }
     }
 }
@@ -3310,6 +3792,7 @@ pub enum Var {
     Flow(Flow),
     Aux(Aux),
     Module(Module),
+    Group(Group),
     // for things we don't care about like 'isee:dependencies'
     #[serde(other)]
     Unhandled,
@@ -3322,7 +3805,8 @@ impl Var {
             Var::Flow(flow) => flow.name.as_str(),
             Var::Aux(aux) => aux.name.as_str(),
             Var::Module(module) => module.name.as_str(),
-            Var::Unhandled => unreachable!(),
+            // groups can share a name with a variable
+            Var::Group(_) | Var::Unhandled => "",
         }
     }
 }
@@ -3334,6 +3818,7 @@ impl ToXml<XmlWriter> for Var {
             Var::Flow(flow) => flow.write_xml(writer),
             Var::Aux(aux) => aux.write_xml(writer),
             Var::Module(module) => module.write_xml(writer),
+            Var::Group(group) => group.write_xml(writer),
             Var::Unhandled => Ok(()),
         }
     }
@@ -3346,7 +3831,7 @@ impl From<Var> for datamodel::Variable {
             Var::Flow(flow) => datamodel::Variable::Flow(datamodel::Flow::from(flow)),
             Var::Aux(aux) => datamodel::Variable::Aux(datamodel::Aux::from(aux)),
             Var::Module(module) => datamodel::Variable::Module(datamodel::Module::from(module)),
-            Var::Unhandled => unreachable!(),
+            Var::Group(_) | Var::Unhandled => unreachable!(),
         }
     }
 }
@@ -3380,6 +3865,7 @@ fn test_canonicalize_stock_inflows() {
         dimensions: None,
         elements: None,
         access: None,
+        supplementary: None,
     });
 
     let expected = datamodel::Variable::Stock(datamodel::Stock {
@@ -3390,7 +3876,9 @@ fn test_canonicalize_stock_inflows() {
         inflows: vec!["solar_radiation".to_string()],
         outflows: vec!["succumbing".to_string(), "succumbing_2".to_string()],
         non_negative: false,
+        force_euler: false,
         can_be_module_input: false,
+        supplementary: false,
         visibility: Visibility::Private,
     });
 
@@ -3421,22 +3909,726 @@ pub fn project_to_xmile(project: &datamodel::Project) -> Result<String> {
     })
 }
 
+/// serialize returns the project as an XMILE document, for saving an
+/// edited or imported project back out for other XMILE tools.  The
+/// document is written to memory, which can't fail.
+pub fn serialize(project: &datamodel::Project) -> String {
+    project_to_xmile(project).expect("writing XMILE to memory can't fail")
+}
+
+/// Strictness controls how closely an XMILE file must follow the
+/// XMILE v1.0 specification in order to be imported.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strictness {
+    /// accept vendor quirks, like a missing version or namespace
+    #[default]
+    Permissive,
+    /// reject anything outside of the XMILE v1.0 spec
+    Strict,
+}
+
 pub fn project_from_reader(reader: &mut dyn BufRead) -> Result<datamodel::Project> {
+    project_from_reader_with_strictness(reader, Strictness::Permissive)
+}
+
+pub fn project_from_reader_with_strictness(
+    reader: &mut dyn BufRead,
+    strictness: Strictness,
+) -> Result<datamodel::Project> {
     use quick_xml::de;
-    let file: File = match de::from_reader(reader) {
+    let mut contents: Vec<u8> = vec![];
+    if let Err(err) = reader.read_to_end(&mut contents) {
+        return import_err!(XmlDeserialization, err.to_string());
+    }
+    if strictness == Strictness::Strict {
+        check_spec_conformance(&contents)?;
+    }
+    let file: File = match de::from_reader(contents.as_slice()) {
         Ok(file) => file,
         Err(err) => {
             return import_err!(XmlDeserialization, err.to_string());
         }
     };
 
-    Ok(convert_file_to_project(&file))
+    let mut project = convert_file_to_project(&file);
+    project.import_report = find_dropped_content(&contents);
+    Ok(project)
+}
+
+/// dropped_reason returns why the element `name` at the given path is
+/// left out when importing, or None if it is imported (or is only
+/// presentational, like a variable's display format).
+fn dropped_reason(path: &[&str], name: &str) -> Option<&'static str> {
+    let reason = match (path, name) {
+        (["xmile"], "macro") => "macros aren't supported",
+        (["xmile"], "data") => "importing and exporting data isn't supported",
+        (["xmile"], "behavior") | (["xmile", "model"], "behavior") => {
+            "default behaviors aren't supported; set them on each variable instead"
+        }
+        (["xmile", "model", "variables"], name) if !SPEC_VARIABLES.contains(&name) => {
+            "unknown kind of variable"
+        }
+        (["xmile", "model", "variables", "stock"], "conveyor" | "queue") => {
+            "imported as an ordinary stock"
+        }
+        (["xmile", "model", "variables", "flow"], "leak" | "overflow" | "multiplier") => {
+            "imported as an ordinary flow"
+        }
+        (["xmile", "model", "variables", _], "event_poster") => "event posters aren't supported",
+        _ => return None,
+    };
+    Some(reason)
+}
+
+/// find_dropped_content walks the raw XML and reports the constructs that
+/// importing the file leaves out of the project.  Vendor extensions (like
+/// `isee:` elements) are expected to be dropped, and aren't reported.
+fn find_dropped_content(contents: &[u8]) -> Vec<ImportIssue> {
+    use quick_xml::Reader;
+
+    let line_at = |pos: u64| {
+        let pos = (pos as usize).min(contents.len());
+        contents[..pos].iter().filter(|&&b| b == b'\n').count() + 1
+    };
+
+    let mut issues = vec![];
+    let mut stack: Vec<String> = vec![];
+    // the name of the variable we are in, if any
+    let mut var_name: Option<String> = None;
+
+    let mut reader = Reader::from_reader(contents);
+    let mut buf = vec![];
+    loop {
+        let line = line_at(reader.buffer_position());
+        let (elem, is_empty) = match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(elem)) => (elem, false),
+            Ok(Event::Empty(elem)) => (elem, true),
+            Ok(Event::End(_)) => {
+                stack.pop();
+                if stack.len() < 4 {
+                    var_name = None;
+                }
+                buf.clear();
+                continue;
+            }
+            Ok(Event::Eof) => break,
+            Ok(_) => {
+                buf.clear();
+                continue;
+            }
+            // the file was already deserialized, so this shouldn't happen
+            Err(_) => break,
+        };
+
+        let name = String::from_utf8_lossy(elem.name().as_ref()).into_owned();
+        let attr = |key: &str| -> Option<String> {
+            elem.try_get_attribute(key)
+                .ok()
+                .flatten()
+                .and_then(|attr| attr.unescape_value().ok().map(|v| v.into_owned()))
+        };
+
+        let path: Vec<&str> = stack.iter().map(|n| n.as_str()).collect();
+        let is_vendor = name.contains(':') || path.iter().any(|n| n.contains(':'));
+        if !is_vendor {
+            if path.as_slice() == ["xmile", "model", "variables"] {
+                var_name = attr("name");
+            }
+            let reason = match (path.as_slice(), name.as_str()) {
+                (["xmile", "model", "views"], "view") => match attr("type").as_deref() {
+                    None | Some("stock_flow") => None,
+                    Some(_) => Some("only stock and flow views are imported"),
+                },
+                (path, name) => dropped_reason(path, name),
+            };
+            if let Some(reason) = reason {
+                let location = match var_name {
+                    Some(ref var_name) => format!("line {} (variable '{}')", line, var_name),
+                    None => format!("line {}", line),
+                };
+                issues.push(ImportIssue {
+                    construct: name.clone(),
+                    location,
+                    reason: reason.to_owned(),
+                });
+            }
+        }
+
+        if !is_empty {
+            stack.push(name);
+        } else if stack.len() < 4 {
+            var_name = None;
+        }
+        buf.clear();
+    }
+
+    issues
+}
+
+const SPEC_TOP_LEVEL: &[&str] = &[
+    "header",
+    "sim_specs",
+    "model_units",
+    "dimensions",
+    "behavior",
+    "style",
+    "data",
+    "model",
+    "macro",
+];
+const SPEC_HEADER: &[&str] = &[
+    "vendor",
+    "product",
+    "options",
+    "name",
+    "version",
+    "caption",
+    "image",
+    "author",
+    "affiliation",
+    "client",
+    "copyright",
+    "created",
+    "modified",
+    "uuid",
+    "includes",
+];
+const SPEC_SIM_SPECS: &[&str] = &["start", "stop", "dt"];
+const SPEC_MODEL: &[&str] = &["sim_specs", "behavior", "style", "variables", "views"];
+const SPEC_VARIABLES: &[&str] = &["stock", "flow", "aux", "module", "group"];
+const SPEC_STOCK: &[&str] = &[
+    "eqn",
+    "mathml",
+    "inflow",
+    "outflow",
+    "non_negative",
+    "conveyor",
+    "queue",
+    "doc",
+    "units",
+    "dimensions",
+    "element",
+    "range",
+    "scale",
+    "format",
+    "event_poster",
+];
+const SPEC_FLOW: &[&str] = &[
+    "eqn",
+    "mathml",
+    "gf",
+    "non_negative",
+    "multiplier",
+    "overflow",
+    "leak",
+    "leak_integers",
+    "leakage",
+    "doc",
+    "units",
+    "dimensions",
+    "element",
+    "range",
+    "scale",
+    "format",
+    "event_poster",
+];
+const SPEC_AUX: &[&str] = &[
+    "eqn",
+    "mathml",
+    "gf",
+    "doc",
+    "units",
+    "dimensions",
+    "element",
+    "range",
+    "scale",
+    "format",
+    "event_poster",
+];
+const SPEC_MODULE: &[&str] = &["connect", "doc", "units"];
+
+/// spec_children returns the elements the XMILE spec allows as children
+/// of the element at the given path, or None if we don't check it.
+fn spec_children(path: &[&str]) -> Option<&'static [&'static str]> {
+    match path {
+        [] => Some(&["xmile"]),
+        ["xmile"] => Some(SPEC_TOP_LEVEL),
+        ["xmile", "header"] => Some(SPEC_HEADER),
+        ["xmile", "sim_specs"] | ["xmile", "model", "sim_specs"] => Some(SPEC_SIM_SPECS),
+        ["xmile", "model"] => Some(SPEC_MODEL),
+        ["xmile", "model", "variables"] => Some(SPEC_VARIABLES),
+        ["xmile", "model", "variables", "stock"] => Some(SPEC_STOCK),
+        ["xmile", "model", "variables", "flow"] => Some(SPEC_FLOW),
+        ["xmile", "model", "variables", "aux"] => Some(SPEC_AUX),
+        ["xmile", "model", "variables", "module"] => Some(SPEC_MODULE),
+        _ => None,
+    }
+}
+
+/// spec_required_children returns the children the XMILE spec requires
+/// the element at the given path to have.
+fn spec_required_children(path: &[&str]) -> &'static [&'static str] {
+    match path {
+        ["xmile"] => &["header", "sim_specs", "model"],
+        ["xmile", "header"] => &["vendor", "product"],
+        ["xmile", "sim_specs"] => &["start", "stop"],
+        _ => &[],
+    }
+}
+
+struct OpenElement {
+    name: String,
+    line: usize,
+    children: Vec<String>,
+}
+
+/// check_spec_conformance walks the raw XML and reports everything outside
+/// of the XMILE v1.0 spec, one violation per line of the error details.
+/// Elements in a vendor namespace (like `isee:`) are allowed by the spec
+/// as extensions, and are skipped along with their contents.
+fn check_spec_conformance(contents: &[u8]) -> Result<()> {
+    use quick_xml::Reader;
+    use std::collections::HashSet;
+
+    let line_at = |pos: u64| {
+        let pos = (pos as usize).min(contents.len());
+        contents[..pos].iter().filter(|&&b| b == b'\n').count() + 1
+    };
+
+    let mut violations: Vec<String> = vec![];
+    let mut stack: Vec<OpenElement> = vec![];
+    let mut var_names: HashSet<String> = HashSet::new();
+
+    let mut reader = Reader::from_reader(contents);
+    let mut buf = vec![];
+    loop {
+        let line = line_at(reader.buffer_position());
+        let (elem, is_empty) = match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(elem)) => (elem, false),
+            Ok(Event::Empty(elem)) => (elem, true),
+            Ok(Event::End(_)) => {
+                let elem = match stack.pop() {
+                    Some(elem) => elem,
+                    None => break,
+                };
+                check_required_children(&stack, &elem, &mut violations);
+                buf.clear();
+                continue;
+            }
+            Ok(Event::Eof) => break,
+            Ok(_) => {
+                buf.clear();
+                continue;
+            }
+            Err(err) => {
+                return import_err!(
+                    XmlDeserialization,
+                    format!("line {}: {}", line_at(reader.error_position()), err)
+                );
+            }
+        };
+
+        let name = String::from_utf8_lossy(elem.name().as_ref()).into_owned();
+        let attr = |key: &str| -> Option<String> {
+            elem.try_get_attribute(key)
+                .ok()
+                .flatten()
+                .and_then(|attr| attr.unescape_value().ok().map(|v| v.into_owned()))
+        };
+
+        let path: Vec<&str> = stack.iter().map(|e| e.name.as_str()).collect();
+        let is_vendor = name.contains(':') || path.iter().any(|n| n.contains(':'));
+        if !is_vendor {
+            if let Some(allowed) = spec_children(&path) {
+                if !allowed.contains(&name.as_str()) {
+                    let parent = path.last().copied().unwrap_or("document");
+                    violations.push(format!(
+                        "line {}: <{}> is not allowed in <{}>",
+                        line, name, parent
+                    ));
+                }
+            }
+
+            match path.as_slice() {
+                [] if name == "xmile" => {
+                    if attr("version").as_deref() != Some(XMILE_VERSION) {
+                        violations.push(format!(
+                            "line {}: <xmile> must have version=\"{}\"",
+                            line, XMILE_VERSION
+                        ));
+                    }
+                    if attr("xmlns").as_deref() != Some(XML_NS_HTTP) {
+                        violations.push(format!(
+                            "line {}: <xmile> must have xmlns=\"{}\"",
+                            line, XML_NS_HTTP
+                        ));
+                    }
+                }
+                ["xmile"] if name == "model" => {
+                    var_names.clear();
+                }
+                ["xmile", "model", "variables"] if name != "group" => match attr("name") {
+                    Some(var_name) => {
+                        if !var_names.insert(canonicalize(&var_name)) {
+                            violations.push(format!(
+                                "line {}: duplicate variable name '{}'",
+                                line, var_name
+                            ));
+                        }
+                    }
+                    None => {
+                        violations.push(format!(
+                            "line {}: <{}> is missing the required name attribute",
+                            line, name
+                        ));
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        if let Some(parent) = stack.last_mut() {
+            parent.children.push(name.clone());
+        }
+        let elem = OpenElement {
+            name,
+            line,
+            children: vec![],
+        };
+        if is_empty {
+            check_required_children(&stack, &elem, &mut violations);
+        } else {
+            stack.push(elem);
+        }
+        buf.clear();
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        import_err!(XmileSpecViolation, violations.join("\n"))
+    }
+}
+
+fn check_required_children(
+    ancestors: &[OpenElement],
+    elem: &OpenElement,
+    violations: &mut Vec<String>,
+) {
+    let mut path: Vec<&str> = ancestors.iter().map(|e| e.name.as_str()).collect();
+    path.push(elem.name.as_str());
+    for required in spec_required_children(&path) {
+        if !elem.children.iter().any(|child| child == required) {
+            violations.push(format!(
+                "line {}: <{}> is missing required <{}>",
+                elem.line, elem.name, required
+            ));
+        }
+    }
 }
 
 pub fn convert_file_to_project(file: &File) -> datamodel::Project {
     datamodel::Project::from(file.clone())
 }
 
+#[test]
+fn test_strictness() {
+    use simlin_engine::common::ErrorCode;
+
+    let conformant = r#"<?xml version="1.0" encoding="utf-8"?>
+<xmile version="1.0" xmlns="http://docs.oasis-open.org/xmile/ns/XMILE/v1.0" xmlns:isee="http://iseesystems.com/XMILE">
+    <header>
+        <vendor>Simlin</vendor>
+        <product version="0.1.0">Simlin</product>
+    </header>
+    <sim_specs method="Euler" time_units="months">
+        <start>0</start>
+        <stop>10</stop>
+        <dt>1</dt>
+    </sim_specs>
+    <model>
+        <variables>
+            <aux name="rate">
+                <eqn>0.1</eqn>
+                <isee:delay_aux/>
+            </aux>
+        </variables>
+    </model>
+</xmile>"#;
+
+    let project =
+        project_from_reader_with_strictness(&mut conformant.as_bytes(), Strictness::Strict)
+            .unwrap();
+    assert_eq!(1, project.models[0].variables.len());
+
+    let quirky = r#"<xmile>
+    <sim_specs>
+        <start>0</start>
+        <stop>10</stop>
+    </sim_specs>
+    <model>
+        <variables>
+            <aux name="rate">
+                <eqn>0.1</eqn>
+                <comment>not in the spec</comment>
+            </aux>
+            <aux name="Rate">
+                <eqn>0.2</eqn>
+            </aux>
+        </variables>
+    </model>
+</xmile>"#;
+
+    // permissive mode (the default) accepts the quirks
+    assert!(project_from_reader(&mut quirky.as_bytes()).is_ok());
+
+    let err = project_from_reader_with_strictness(&mut quirky.as_bytes(), Strictness::Strict)
+        .unwrap_err();
+    assert_eq!(ErrorCode::XmileSpecViolation, err.code);
+    let expected = [
+        "line 1: <xmile> must have version=\"1.0\"",
+        "line 1: <xmile> must have xmlns=\"http://docs.oasis-open.org/xmile/ns/XMILE/v1.0\"",
+        "line 10: <comment> is not allowed in <aux>",
+        "line 12: duplicate variable name 'Rate'",
+        "line 1: <xmile> is missing required <header>",
+    ];
+    assert_eq!(expected.join("\n"), err.get_details().unwrap());
+}
+
+#[test]
+fn test_import_report() {
+    let input = r#"<xmile version="1.0" xmlns="http://docs.oasis-open.org/xmile/ns/XMILE/v1.0">
+    <sim_specs>
+        <start>0</start>
+        <stop>10</stop>
+    </sim_specs>
+    <model>
+        <variables>
+            <stock name="pipeline">
+                <eqn>0</eqn>
+                <conveyor><len>3</len></conveyor>
+            </stock>
+            <aux name="rate">
+                <eqn>0.1</eqn>
+                <isee:delay_aux/>
+            </aux>
+        </variables>
+        <views>
+            <view type="interface"/>
+        </views>
+    </model>
+    <macro name="double"/>
+</xmile>"#;
+
+    let project = project_from_reader(&mut input.as_bytes()).unwrap();
+    assert_eq!(2, project.models[0].variables.len());
+    assert_eq!(
+        vec![
+            ImportIssue {
+                construct: "conveyor".to_owned(),
+                location: "line 10 (variable 'pipeline')".to_owned(),
+                reason: "imported as an ordinary stock".to_owned(),
+            },
+            ImportIssue {
+                construct: "view".to_owned(),
+                location: "line 18".to_owned(),
+                reason: "only stock and flow views are imported".to_owned(),
+            },
+            ImportIssue {
+                construct: "macro".to_owned(),
+                location: "line 21".to_owned(),
+                reason: "macros aren't supported".to_owned(),
+            },
+        ],
+        project.import_report
+    );
+}
+
+#[test]
+fn test_graphs() {
+    let input = r#"<xmile version="1.0" xmlns="http://docs.oasis-open.org/xmile/ns/XMILE/v1.0">
+    <sim_specs>
+        <start>0</start>
+        <stop>10</stop>
+    </sim_specs>
+    <model>
+        <variables>
+            <aux name="Birth Rate">
+                <eqn>0.1</eqn>
+            </aux>
+        </variables>
+        <views>
+            <view type="stock_flow">
+                <aux name="Birth Rate" x="10" y="10"/>
+                <table title="Rates" interval="2">
+                    <item type="time"/>
+                    <item precision="3"><entity name="Birth Rate"/></item>
+                </table>
+            </view>
+            <view type="interface">
+                <graph type="time_series" title="Births">
+                    <plot index="0" color="blue">
+                        <entity name="Birth Rate"/>
+                        <scale min="0" max="1"/>
+                    </plot>
+                </graph>
+            </view>
+        </views>
+    </model>
+</xmile>"#;
+
+    let project = project_from_reader(&mut input.as_bytes()).unwrap();
+    let model = &project.models[0];
+    assert_eq!(
+        vec![
+            datamodel::Graph {
+                title: "Rates".to_owned(),
+                kind: datamodel::GraphKind::Table,
+                plots: vec![datamodel::Plot {
+                    ident: "birth_rate".to_owned(),
+                    color: None,
+                    scale: None,
+                    precision: Some(3),
+                }],
+                interval: Some(2.0),
+            },
+            datamodel::Graph {
+                title: "Births".to_owned(),
+                kind: datamodel::GraphKind::TimeSeries,
+                plots: vec![datamodel::Plot {
+                    ident: "birth_rate".to_owned(),
+                    color: Some("blue".to_owned()),
+                    scale: Some(datamodel::GraphicalFunctionScale { min: 0.0, max: 1.0 }),
+                    precision: None,
+                }],
+                interval: None,
+            },
+        ],
+        model.graphs
+    );
+    // graphs aren't diagram elements
+    let datamodel::View::StockFlow(view) = &model.views[0];
+    assert_eq!(1, view.elements.len());
+
+    let xmile = project_to_xmile(&project).unwrap();
+    let reread = project_from_reader(&mut xmile.as_bytes()).unwrap();
+    assert_eq!(model.graphs, reread.models[0].graphs);
+    let datamodel::View::StockFlow(view) = &reread.models[0].views[0];
+    assert_eq!(1, view.elements.len());
+}
+
+#[test]
+fn test_groups() {
+    let input = r#"<xmile version="1.0" xmlns="http://docs.oasis-open.org/xmile/ns/XMILE/v1.0">
+    <sim_specs>
+        <start>0</start>
+        <stop>10</stop>
+    </sim_specs>
+    <model>
+        <variables>
+            <aux name="Birth Rate">
+                <eqn>0.1</eqn>
+            </aux>
+            <group name="Control">
+                <doc>
+                    Simulation control parameters
+                </doc>
+                <entity name="Birth_Rate"/>
+                <entity name="Cities"/>
+            </group>
+            <group name="Empty"/>
+        </variables>
+    </model>
+</xmile>"#;
+
+    let project = project_from_reader(&mut input.as_bytes()).unwrap();
+    let model = &project.models[0];
+    assert_eq!(1, model.variables.len());
+    assert!(project.import_report.is_empty());
+    assert_eq!(
+        vec![
+            datamodel::Group {
+                name: "Control".to_owned(),
+                documentation: "Simulation control parameters".to_owned(),
+                members: vec!["birth_rate".to_owned()],
+            },
+            datamodel::Group {
+                name: "Empty".to_owned(),
+                documentation: "".to_owned(),
+                members: vec![],
+            },
+        ],
+        model.groups
+    );
+
+    let xmile = project_to_xmile(&project).unwrap();
+    let reread = project_from_reader(&mut xmile.as_bytes()).unwrap();
+    assert_eq!(model.groups, reread.models[0].groups);
+    assert_eq!(model.variables, reread.models[0].variables);
+}
+
+#[test]
+fn test_supplementary() {
+    let input = r#"<xmile version="1.0" xmlns="http://docs.oasis-open.org/xmile/ns/XMILE/v1.0" xmlns:simlin="https://simlin.com/XMILE/v1.0">
+    <sim_specs>
+        <start>0</start>
+        <stop>10</stop>
+    </sim_specs>
+    <model>
+        <variables>
+            <aux name="births">
+                <eqn>1</eqn>
+            </aux>
+            <aux name="birth report" simlin:supplementary="true">
+                <eqn>births * 100</eqn>
+            </aux>
+        </variables>
+    </model>
+</xmile>"#;
+
+    let project = project_from_reader(&mut input.as_bytes()).unwrap();
+    let model = &project.models[0];
+    assert!(!model.get_variable("births").unwrap().is_supplementary());
+    assert!(model
+        .get_variable("birth_report")
+        .unwrap()
+        .is_supplementary());
+
+    let xmile = project_to_xmile(&project).unwrap();
+    assert!(xmile.contains("simlin:supplementary=\"true\""));
+    let reread = project_from_reader(&mut xmile.as_bytes()).unwrap();
+    assert_eq!(model.variables, reread.models[0].variables);
+}
+
+#[test]
+fn test_serialize() {
+    let input = r#"<xmile version="1.0" xmlns="http://docs.oasis-open.org/xmile/ns/XMILE/v1.0">
+    <sim_specs>
+        <start>0</start>
+        <stop>10</stop>
+        <dt>0.5</dt>
+    </sim_specs>
+    <model>
+        <variables>
+            <stock name="population">
+                <eqn>100</eqn>
+                <inflow>births</inflow>
+            </stock>
+            <flow name="births">
+                <eqn>population * 0.1</eqn>
+            </flow>
+        </variables>
+    </model>
+</xmile>"#;
+
+    let project = project_from_reader(&mut input.as_bytes()).unwrap();
+    let xmile = serialize(&project);
+    assert!(xmile.starts_with("<?xml"));
+    let reread = project_from_reader(&mut xmile.as_bytes()).unwrap();
+    assert_eq!(project.sim_specs, reread.sim_specs);
+    assert_eq!(project.models[0].variables, reread.models[0].variables);
+}
+
 #[test]
 fn test_bad_xml() {
     let input = "<stock name=\"susceptible\">
@@ -3471,6 +4663,7 @@ fn test_xml_stock_parsing() {
         dimensions: None,
         elements: None,
         access: None,
+        supplementary: None,
     };
 
     use quick_xml::de;
@@ -3498,6 +4691,7 @@ fn test_xml_gt_parsing() {
         dimensions: None,
         elements: None,
         access: None,
+        supplementary: None,
     };
 
     use quick_xml::de;
@@ -3542,6 +4736,7 @@ fn test_xml_gf_parsing() {
         dimensions: None,
         elements: None,
         access: Some("input".to_owned()),
+        supplementary: None,
     };
 
     use quick_xml::de;
@@ -3647,6 +4842,8 @@ fn test_sim_specs_parsing() {
         save_step: Some(1.0),
         method: Some("euler".to_string()),
         time_units: Some("Time".to_string()),
+        rel_tolerance: None,
+        abs_tolerance: None,
     };
 
     use quick_xml::de;
@@ -3656,3 +4853,37 @@ fn test_sim_specs_parsing() {
     let roundtripped = SimSpecs::from(datamodel::SimSpecs::from(actual.clone()));
     assert_eq!(roundtripped, actual);
 }
+
+#[test]
+fn test_sim_specs_tolerances() {
+    let sim_specs = datamodel::SimSpecs {
+        stop: 10.0,
+        sim_method: datamodel::SimMethod::Rk45,
+        tolerances: datamodel::Tolerances {
+            relative: 1e-3,
+            absolute: 1e-5,
+        },
+        ..Default::default()
+    };
+
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    SimSpecs::from(sim_specs.clone())
+        .write_xml(&mut writer)
+        .unwrap();
+    let xml = String::from_utf8(writer.into_inner().into_inner()).unwrap();
+    assert!(xml.contains("simlin:rel_tolerance=\"0.001\""));
+    assert!(xml.contains("simlin:abs_tolerance=\"0.00001\""));
+
+    use quick_xml::de;
+    let read: SimSpecs = de::from_reader(xml.as_bytes()).unwrap();
+    assert_eq!(sim_specs, datamodel::SimSpecs::from(read));
+
+    // the defaults aren't written out
+    let sim_specs = datamodel::SimSpecs {
+        tolerances: Default::default(),
+        ..sim_specs
+    };
+    let xml_specs = SimSpecs::from(sim_specs.clone());
+    assert_eq!(None, xml_specs.rel_tolerance);
+    assert_eq!(sim_specs, datamodel::SimSpecs::from(xml_specs));
+}
@@ -113,12 +113,12 @@ fn load_expected_results(xmile_path: &str) -> Option<Results> {
 
 fn ensure_results(expected: &Results, results: &Results) {
     assert_eq!(expected.step_count, results.step_count);
-    assert_eq!(expected.iter().len(), results.iter().len());
+    assert_eq!(expected.iter_rows().len(), results.iter_rows().len());
 
     let expected_results = expected;
 
     let mut step = 0;
-    for (expected_row, results_row) in expected.iter().zip(results.iter()) {
+    for (expected_row, results_row) in expected.iter_rows().zip(results.iter_rows()) {
         for ident in expected.offsets.keys() {
             let expected = expected_row[expected.offsets[ident]];
             if !results.offsets.contains_key(ident) && IGNORABLE_COLS.contains(&ident.as_str()) {
@@ -328,6 +328,36 @@ fn simulates_active_initial() {
     simulate_path("../../test/sdeverywhere/models/active_initial/active_initial.xmile");
 }
 
+#[test]
+fn simulates_initial() {
+    simulate_path("../../test/sdeverywhere/models/initial/initial.xmile");
+}
+
+/// simulate_mdl_path imports a Vensim model through xmutil, rather than
+/// from an already-converted XMILE file, and checks it against Vensim's
+/// results.
+#[cfg(feature = "vensim")]
+fn simulate_mdl_path(mdl_path: &str) {
+    eprintln!("model: {}", mdl_path);
+
+    let f = File::open(mdl_path).unwrap();
+    let datamodel_project = simlin_compat::open_vensim(&mut BufReader::new(f)).unwrap();
+    let sim = build_sim_with_stderrors(&datamodel_project).unwrap();
+    let mut vm = Vm::new(sim.compile().unwrap()).unwrap();
+    vm.run_to_end().unwrap();
+
+    let expected = load_dat(&mdl_path.replace(".mdl", ".dat")).unwrap();
+    ensure_results(&expected, &vm.into_results());
+}
+
+// ACTIVE INITIAL, INITIAL, and a stock whose initial value is computed
+#[cfg(feature = "vensim")]
+#[test]
+fn simulates_vensim_initial_idioms() {
+    simulate_mdl_path("../../test/sdeverywhere/models/active_initial/active_initial.mdl");
+    simulate_mdl_path("../../test/sdeverywhere/models/initial/initial.mdl");
+}
+
 #[test]
 #[ignore]
 fn simulates_except() {
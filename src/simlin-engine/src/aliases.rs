@@ -0,0 +1,264 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+use std::collections::HashSet;
+
+use crate::common::canonicalize;
+use crate::datamodel::view_element::{Alias, Cloud, LabelSide};
+use crate::datamodel::{StockFlow, ViewElement};
+
+impl StockFlow {
+    pub(crate) fn next_uid(&self) -> i32 {
+        self.elements
+            .iter()
+            .map(|e| e.get_uid())
+            .max()
+            .map(|uid| uid + 1)
+            .unwrap_or(1)
+    }
+
+    /// find_uid returns the uid of the element displaying the variable
+    /// with the given name, not counting its aliases.
+    pub fn find_uid(&self, ident: &str) -> Option<i32> {
+        let ident = canonicalize(ident);
+        self.elements
+            .iter()
+            .find(|e| e.get_name().map(canonicalize).as_deref() == Some(ident.as_str()))
+            .map(|e| e.get_uid())
+    }
+
+    /// aliases_of returns the uids of the aliases of the element `uid`.
+    pub fn aliases_of(&self, uid: i32) -> Vec<i32> {
+        self.elements
+            .iter()
+            .filter_map(|e| match e {
+                ViewElement::Alias(alias) if alias.alias_of_uid == uid => Some(alias.uid),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// add_alias adds an alias of the variable with the given name at
+    /// (x, y), returning its uid, or None if the variable isn't displayed
+    /// in this view.
+    pub fn add_alias(&mut self, ident: &str, x: f64, y: f64) -> Option<i32> {
+        let alias_of_uid = self.find_uid(ident)?;
+        let uid = self.next_uid();
+        self.elements.push(ViewElement::Alias(Alias {
+            uid,
+            alias_of_uid,
+            x,
+            y,
+            label_side: LabelSide::Bottom,
+        }));
+        Some(uid)
+    }
+
+    /// remove_alias removes the alias `uid` along with links to and from
+    /// it.  It returns false if `uid` isn't an alias.
+    pub fn remove_alias(&mut self, uid: i32) -> bool {
+        let is_alias = self
+            .elements
+            .iter()
+            .any(|e| matches!(e, ViewElement::Alias(alias) if alias.uid == uid));
+        if is_alias {
+            self.remove_uids(&[uid].into_iter().collect());
+        }
+        is_alias
+    }
+
+    /// promote_alias moves the original element of the alias `uid` to the
+    /// alias's position, replacing it: links to the alias are redirected
+    /// to the original.  Only auxiliaries and modules can be moved this
+    /// way; stocks and flows have pipes attached to their positions.
+    pub fn promote_alias(&mut self, uid: i32) -> bool {
+        let (alias_of_uid, x, y) = match self.elements.iter().find(|e| e.get_uid() == uid) {
+            Some(ViewElement::Alias(alias)) => (alias.alias_of_uid, alias.x, alias.y),
+            _ => return false,
+        };
+        let original = self
+            .elements
+            .iter_mut()
+            .find(|e| e.get_uid() == alias_of_uid);
+        match original {
+            Some(ViewElement::Aux(aux)) => {
+                aux.x = x;
+                aux.y = y;
+            }
+            Some(ViewElement::Module(module)) => {
+                module.x = x;
+                module.y = y;
+            }
+            _ => return false,
+        }
+
+        self.elements.retain(|e| e.get_uid() != uid);
+        for element in self.elements.iter_mut() {
+            if let ViewElement::Link(link) = element {
+                if link.from_uid == uid {
+                    link.from_uid = alias_of_uid;
+                }
+                if link.to_uid == uid {
+                    link.to_uid = alias_of_uid;
+                }
+            }
+        }
+        // the original may have already been linked to the same element
+        // the alias was, leaving duplicate links
+        let mut seen = HashSet::new();
+        self.elements.retain(|e| match e {
+            ViewElement::Link(link) => seen.insert((link.from_uid, link.to_uid)),
+            _ => true,
+        });
+        true
+    }
+
+    /// remove_element removes the element `uid` from the view along with
+    /// everything that depends on it: its aliases, links to and from any
+    /// of them, and a flow's clouds.  Flows attached to a removed stock
+    /// are instead attached to new clouds.
+    pub fn remove_element(&mut self, uid: i32) {
+        let mut removed: HashSet<i32> = self.aliases_of(uid).into_iter().collect();
+        removed.insert(uid);
+        self.remove_uids(&removed);
+    }
+
+    fn remove_uids(&mut self, removed: &HashSet<i32>) {
+        let mut removed = removed.clone();
+        for element in self.elements.iter() {
+            match element {
+                ViewElement::Link(link)
+                    if removed.contains(&link.from_uid) || removed.contains(&link.to_uid) =>
+                {
+                    removed.insert(link.uid);
+                }
+                ViewElement::Cloud(cloud) if removed.contains(&cloud.flow_uid) => {
+                    removed.insert(cloud.uid);
+                }
+                _ => {}
+            }
+        }
+        self.elements.retain(|e| !removed.contains(&e.get_uid()));
+
+        let mut next_uid = self.next_uid();
+        let mut clouds = vec![];
+        for element in self.elements.iter_mut() {
+            if let ViewElement::Flow(flow) = element {
+                let flow_uid = flow.uid;
+                for pt in flow.points.iter_mut() {
+                    if pt.attached_to_uid.map(|uid| removed.contains(&uid)) == Some(true) {
+                        clouds.push(ViewElement::Cloud(Cloud {
+                            uid: next_uid,
+                            flow_uid,
+                            x: pt.x,
+                            y: pt.y,
+                        }));
+                        pt.attached_to_uid = Some(next_uid);
+                        next_uid += 1;
+                    }
+                }
+            }
+        }
+        self.elements.extend(clouds);
+    }
+}
+
+#[test]
+fn test_aliases() {
+    use crate::datamodel::view_element::{self, FlowPoint, LinkShape};
+
+    let link = |uid: i32, from_uid: i32, to_uid: i32| {
+        ViewElement::Link(view_element::Link {
+            uid,
+            from_uid,
+            to_uid,
+            shape: LinkShape::Straight,
+            polarity: None,
+            delay_mark: false,
+        })
+    };
+    let mut view = StockFlow {
+        elements: vec![
+            ViewElement::Stock(view_element::Stock {
+                name: "Population".to_owned(),
+                uid: 1,
+                x: 100.0,
+                y: 100.0,
+                label_side: LabelSide::Bottom,
+            }),
+            ViewElement::Flow(view_element::Flow {
+                name: "births".to_owned(),
+                uid: 2,
+                x: 50.0,
+                y: 100.0,
+                label_side: LabelSide::Bottom,
+                points: vec![
+                    FlowPoint {
+                        x: 0.0,
+                        y: 100.0,
+                        attached_to_uid: Some(3),
+                    },
+                    FlowPoint {
+                        x: 77.5,
+                        y: 100.0,
+                        attached_to_uid: Some(1),
+                    },
+                ],
+            }),
+            ViewElement::Cloud(view_element::Cloud {
+                uid: 3,
+                flow_uid: 2,
+                x: 0.0,
+                y: 100.0,
+            }),
+            ViewElement::Aux(view_element::Aux {
+                name: "birth rate".to_owned(),
+                uid: 4,
+                x: 50.0,
+                y: 50.0,
+                label_side: LabelSide::Bottom,
+            }),
+            link(5, 4, 2),
+        ],
+        view_box: Default::default(),
+        zoom: 1.0,
+    };
+
+    assert_eq!(None, view.add_alias("deaths", 0.0, 0.0));
+    assert_eq!(Some(6), view.add_alias("population", 300.0, 100.0));
+    assert_eq!(Some(7), view.add_alias("Birth_Rate", 300.0, 50.0));
+    view.elements.push(link(8, 6, 7));
+    view.elements.push(link(9, 7, 2));
+    assert_eq!(vec![6], view.aliases_of(1));
+
+    assert!(!view.remove_alias(4));
+    assert!(view.promote_alias(7));
+    match &view.elements[3] {
+        ViewElement::Aux(aux) => assert_eq!((300.0, 50.0), (aux.x, aux.y)),
+        _ => unreachable!(),
+    }
+    // the link from the alias duplicated 5, and the link to it now
+    // starts at the original
+    let links: Vec<(i32, i32)> = view
+        .elements
+        .iter()
+        .filter_map(|e| match e {
+            ViewElement::Link(link) => Some((link.from_uid, link.to_uid)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(vec![(4, 2), (6, 4)], links);
+
+    view.remove_element(1);
+    let uids: Vec<i32> = view.elements.iter().map(|e| e.get_uid()).collect();
+    assert_eq!(vec![2, 3, 4, 5, 6], uids);
+    match &view.elements[0] {
+        ViewElement::Flow(flow) => assert_eq!(Some(6), flow.points[1].attached_to_uid),
+        _ => unreachable!(),
+    }
+    assert!(matches!(
+        &view.elements[4],
+        ViewElement::Cloud(cloud) if cloud.flow_uid == 2
+    ));
+}
@@ -0,0 +1,291 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Renaming a project's variables to opaque identifiers, so a model can
+//! be shared for debugging or benchmarking without revealing what it
+//! is about.
+
+use std::collections::HashMap;
+
+use crate::common::{canonicalize, quoteize};
+use crate::datamodel::{Project, Variable, View, ViewElement};
+use crate::replace::{replace_idents, texts_mut, Field};
+
+/// Renames maps the names in a project to their anonymized versions.
+#[derive(Default)]
+struct Renames {
+    models: HashMap<String, String>,
+    /// model name -> variable ident -> new ident
+    idents: HashMap<String, HashMap<String, String>>,
+    /// model name -> module ident -> the name of the module's model
+    modules: HashMap<String, HashMap<String, String>>,
+    constants: HashMap<String, String>,
+}
+
+impl Renames {
+    fn new(project: &Project) -> Self {
+        let mut renames = Renames::default();
+        for (i, model) in project.models.iter().enumerate() {
+            // the root model keeps its name, so the project simulates
+            // the same way
+            let name = if model.name == "main" || model.name.is_empty() {
+                model.name.clone()
+            } else {
+                format!("model{}", i + 1)
+            };
+            renames.models.insert(model.name.clone(), name);
+
+            let mut counts = [0; 4];
+            let idents = renames.idents.entry(model.name.clone()).or_default();
+            for var in model.variables.iter() {
+                let (kind, prefix) = match var {
+                    Variable::Stock(_) => (0, "s"),
+                    Variable::Flow(_) => (1, "f"),
+                    Variable::Aux(_) => (2, "a"),
+                    Variable::Module(module) => {
+                        renames
+                            .modules
+                            .entry(model.name.clone())
+                            .or_default()
+                            .insert(canonicalize(&module.ident), module.model_name.clone());
+                        (3, "m")
+                    }
+                };
+                counts[kind] += 1;
+                idents.insert(
+                    canonicalize(var.get_ident()),
+                    format!("{}{}", prefix, counts[kind]),
+                );
+            }
+        }
+        for (i, constant) in project.constants.iter().enumerate() {
+            renames
+                .constants
+                .insert(canonicalize(&constant.ident), format!("c{}", i + 1));
+        }
+        renames
+    }
+
+    fn model(&self, model_name: &str) -> String {
+        self.models
+            .get(model_name)
+            .cloned()
+            .unwrap_or_else(|| model_name.to_owned())
+    }
+
+    /// path returns the new canonical name of `path`, a variable in
+    /// `model_name` or, like `hares·births`, inside one of its modules.
+    fn path(&self, model_name: &str, path: &str) -> Option<String> {
+        let path = canonicalize(path);
+        if !path.contains('·') {
+            if let Some(ident) = self.idents.get(model_name).and_then(|m| m.get(&path)) {
+                return Some(ident.clone());
+            }
+            return self.constants.get(&path).cloned();
+        }
+
+        let mut model_name = model_name;
+        let mut parts: Vec<&str> = vec![];
+        for part in path.split('·') {
+            parts.push(self.idents.get(model_name)?.get(part)?);
+            if let Some(module_model) = self.modules.get(model_name).and_then(|m| m.get(part)) {
+                model_name = module_model.as_str();
+            }
+        }
+        Some(parts.join("·"))
+    }
+
+    fn path_or_same(&self, model_name: &str, path: &str) -> String {
+        self.path(model_name, path)
+            .unwrap_or_else(|| path.to_owned())
+    }
+}
+
+impl Project {
+    /// anonymized returns a copy of the project with the same structure
+    /// and behavior, but with its models, variables, constants, groups
+    /// and tests given opaque names (like `s1` for the first stock in a
+    /// model), and with documentation, graph titles and the source file
+    /// it was imported from left out.  Dimensions and units keep their
+    /// names, as equations depend on them.
+    pub fn anonymized(&self) -> Project {
+        let renames = Renames::new(self);
+        let mut project = self.clone();
+        project.name = "anonymized".to_owned();
+        project.source = None;
+        project.import_report.clear();
+
+        for model in project.models.iter_mut() {
+            let model_name = model.name.clone();
+            let rename_text = |text: &str, field: Field| {
+                if field == Field::Units {
+                    return text.to_owned();
+                }
+                replace_idents(text, field.lexer_type(), |id| {
+                    renames.path(&model_name, id).map(|id| quoteize(&id))
+                })
+            };
+
+            for var in model.variables.iter_mut() {
+                var.set_ident(renames.path_or_same(&model_name, var.get_ident()));
+                var.set_documentation("");
+                for (_, field, text) in texts_mut(var) {
+                    *text = rename_text(text, field);
+                }
+                match var {
+                    Variable::Stock(stock) => {
+                        for flow in stock.inflows.iter_mut().chain(stock.outflows.iter_mut()) {
+                            *flow = renames.path_or_same(&model_name, flow);
+                        }
+                    }
+                    Variable::Module(module) => {
+                        let module_model = module.model_name.clone();
+                        module.model_name = renames.model(&module_model);
+                        for reference in module.references.iter_mut() {
+                            reference.src = renames.path_or_same(&model_name, &reference.src);
+                            reference.dst = renames.path_or_same(&model_name, &reference.dst);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            for view in model.views.iter_mut() {
+                let View::StockFlow(view) = view;
+                for element in view.elements.iter_mut() {
+                    let name = match element {
+                        ViewElement::Aux(aux) => &mut aux.name,
+                        ViewElement::Stock(stock) => &mut stock.name,
+                        ViewElement::Flow(flow) => &mut flow.name,
+                        ViewElement::Module(module) => &mut module.name,
+                        _ => continue,
+                    };
+                    *name = renames.path_or_same(&model_name, name);
+                }
+            }
+            for graph in model.graphs.iter_mut() {
+                graph.title.clear();
+                for plot in graph.plots.iter_mut() {
+                    plot.ident = renames.path_or_same(&model_name, &plot.ident);
+                }
+            }
+            for (i, group) in model.groups.iter_mut().enumerate() {
+                group.name = format!("group{}", i + 1);
+                group.documentation.clear();
+                for member in group.members.iter_mut() {
+                    *member = renames.path_or_same(&model_name, member);
+                }
+            }
+
+            model.name = renames.model(&model_name);
+        }
+
+        let constants = std::mem::take(&mut project.constants);
+        for mut constant in constants {
+            if let Some(ident) = renames.constants.get(&canonicalize(&constant.ident)) {
+                constant.ident = ident.clone();
+            }
+            let mut var = Variable::Aux(constant);
+            var.set_documentation("");
+            for (_, field, text) in texts_mut(&mut var) {
+                if field != Field::Units {
+                    *text = replace_idents(text, field.lexer_type(), |id| {
+                        renames.constants.get(id).map(|id| quoteize(id))
+                    });
+                }
+            }
+            if let Variable::Aux(constant) = var {
+                project.constants.push(constant);
+            }
+        }
+
+        for (i, test) in project.tests.iter_mut().enumerate() {
+            test.name = format!("test{}", i + 1);
+            let model_name = test.model_name.clone();
+            test.model_name = renames.model(&model_name);
+            for o in test.overrides.iter_mut() {
+                o.ident = renames.path_or_same(&model_name, &o.ident);
+            }
+            for expectation in test.expectations.iter_mut() {
+                expectation.ident = renames.path_or_same(&model_name, &expectation.ident);
+            }
+        }
+
+        project
+    }
+}
+
+#[test]
+fn test_anonymized() {
+    use crate::testutils::{
+        sim_specs_with_units, x_aux, x_flow, x_model, x_module, x_project, x_stock,
+    };
+    use crate::vm::Vm;
+    use crate::Simulation;
+
+    let constant = |ident: &str, eqn: &str| match x_aux(ident, eqn, None) {
+        Variable::Aux(aux) => aux,
+        _ => unreachable!(),
+    };
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[
+            x_model(
+                "main",
+                vec![
+                    x_stock("population", "10", &["births"], &[], None),
+                    x_flow("births", "population * fertility.rate", None),
+                    x_module("fertility", &[("food", "fertility.food")], None),
+                    x_aux("food", "growth_rate * 4", None),
+                ],
+            ),
+            x_model(
+                "fertility",
+                vec![x_aux("food", "0", None), x_aux("rate", "food / 100", None)],
+            ),
+        ],
+    );
+    project.sim_specs.stop = 5.0;
+    project.constants = vec![constant("growth_rate", "0.5")];
+    project.models[0].variables[0].set_documentation("secret");
+
+    let anonymized = project.anonymized();
+    let main = &anonymized.models[0];
+    let idents: Vec<&str> = main.variables.iter().map(|v| v.get_ident()).collect();
+    assert_eq!(vec!["s1", "f1", "m1", "a1"], idents);
+    assert_eq!("model2", anonymized.models[1].name);
+    assert_eq!("c1", anonymized.constants[0].ident);
+    match &main.variables[1] {
+        Variable::Flow(flow) => assert_eq!(
+            crate::datamodel::Equation::Scalar("s1 * m1.a2".to_owned(), None),
+            flow.equation
+        ),
+        _ => unreachable!(),
+    }
+    match &main.variables[2] {
+        Variable::Module(module) => {
+            assert_eq!("model2", module.model_name);
+            assert_eq!("a1", module.references[0].src);
+            assert_eq!("m1·a1", module.references[0].dst);
+        }
+        _ => unreachable!(),
+    }
+    match &main.variables[0] {
+        Variable::Stock(stock) => {
+            assert_eq!(vec!["f1".to_owned()], stock.inflows);
+            assert!(stock.documentation.is_empty());
+        }
+        _ => unreachable!(),
+    }
+
+    let run = |project: Project, ident: &str| {
+        let project = crate::Project::from(project);
+        let sim = Simulation::new(&project, "main").unwrap();
+        let mut vm = Vm::new(sim.compile().unwrap()).unwrap();
+        vm.run_to_end().unwrap();
+        vm.into_results().get_series(ident).unwrap()
+    };
+    assert_eq!(run(project, "population"), run(anonymized, "s1"));
+}
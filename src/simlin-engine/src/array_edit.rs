@@ -0,0 +1,580 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+use crate::common::{canonicalize, Result};
+use crate::datamodel::{Dimension, Equation, Project, Variable};
+use crate::model_err;
+use crate::token::{Lexer, LexerType, Token};
+
+/// element_names returns the names of every element of an array with
+/// the given dimensions, in row-major order.  Elements of
+/// multi-dimensional arrays are comma-separated, like "a,x".
+pub fn element_names(dimensions: &[Dimension], dim_names: &[String]) -> Result<Vec<String>> {
+    let mut names = vec![String::new()];
+    for (i, dim_name) in dim_names.iter().enumerate() {
+        let dim = match dimensions
+            .iter()
+            .find(|dim| canonicalize(dim.name()) == canonicalize(dim_name))
+        {
+            Some(dim) => dim,
+            None => return model_err!(BadDimensionName, dim_name.clone()),
+        };
+        let elements: Vec<String> = match dim {
+            Dimension::Indexed(_, size) => (1..=*size).map(|n| n.to_string()).collect(),
+            Dimension::Named(_, elements) => elements.clone(),
+        };
+        names = names
+            .iter()
+            .flat_map(|prefix| {
+                elements.iter().map(move |element| {
+                    if i == 0 {
+                        element.clone()
+                    } else {
+                        format!("{},{}", prefix, element)
+                    }
+                })
+            })
+            .collect();
+    }
+    Ok(names)
+}
+
+fn equation_mut(var: &mut Variable) -> Result<&mut Equation> {
+    match var {
+        Variable::Stock(stock) => Ok(&mut stock.equation),
+        Variable::Flow(flow) => Ok(&mut flow.equation),
+        Variable::Aux(aux) => Ok(&mut aux.equation),
+        Variable::Module(module) => {
+            model_err!(
+                MismatchedDimensions,
+                format!("{} is a module", module.ident)
+            )
+        }
+    }
+}
+
+// same_element compares element names ignoring case and whitespace
+// around the commas separating subscripts.
+fn same_element(a: &str, b: &str) -> bool {
+    let a = a.split(',').map(canonicalize);
+    let b = b.split(',').map(canonicalize);
+    a.eq(b)
+}
+
+impl Variable {
+    /// to_arrayed converts an apply-to-all equation into an arrayed
+    /// equation where every element has the same equation.  Arrayed
+    /// equations are reordered to match the dimensions, with any missing
+    /// elements given empty equations.
+    pub fn to_arrayed(&mut self, dimensions: &[Dimension]) -> Result<()> {
+        let ident = self.get_ident().to_owned();
+        let equation = equation_mut(self)?;
+        let (dim_names, elements) = match equation {
+            Equation::Scalar(_, _) => {
+                return model_err!(MismatchedDimensions, format!("{} is a scalar", ident));
+            }
+            Equation::ApplyToAll(dim_names, eqn, init_eqn) => {
+                let elements = element_names(dimensions, dim_names)?
+                    .into_iter()
+                    .map(|name| (name, eqn.clone(), init_eqn.clone()))
+                    .collect();
+                (dim_names.clone(), elements)
+            }
+            Equation::Arrayed(dim_names, existing) => {
+                let elements = element_names(dimensions, dim_names)?
+                    .into_iter()
+                    .map(
+                        |name| match existing.iter().find(|(el, _, _)| same_element(el, &name)) {
+                            Some((_, eqn, init_eqn)) => (name, eqn.clone(), init_eqn.clone()),
+                            None => (name, "".to_owned(), None),
+                        },
+                    )
+                    .collect();
+                (dim_names.clone(), elements)
+            }
+        };
+        *equation = Equation::Arrayed(dim_names, elements);
+        Ok(())
+    }
+
+    /// set_element_equation overrides the equation of a single element
+    /// of an array, converting an apply-to-all equation to an arrayed one
+    /// first if necessary.
+    pub fn set_element_equation(
+        &mut self,
+        dimensions: &[Dimension],
+        element: &str,
+        eqn: &str,
+    ) -> Result<()> {
+        self.to_arrayed(dimensions)?;
+        if let Ok(Equation::Arrayed(_, elements)) = equation_mut(self) {
+            if let Some(el) = elements
+                .iter_mut()
+                .find(|(name, _, _)| same_element(name, element))
+            {
+                el.1 = eqn.to_owned();
+                return Ok(());
+            }
+        }
+        model_err!(UnknownSubscript, element.to_owned())
+    }
+
+    /// to_apply_to_all converts an arrayed equation back to an
+    /// apply-to-all equation, if every element has the same equation.
+    /// It returns false (leaving the equation unchanged) otherwise.
+    pub fn to_apply_to_all(&mut self) -> bool {
+        let equation = match equation_mut(self) {
+            Ok(equation) => equation,
+            Err(_) => return false,
+        };
+        let (dim_names, elements) = match equation {
+            Equation::Arrayed(dim_names, elements) => (dim_names, elements),
+            Equation::ApplyToAll(_, _, _) => return true,
+            Equation::Scalar(_, _) => return false,
+        };
+        let (eqn, init_eqn) = match elements.first() {
+            Some((_, eqn, init_eqn)) => (eqn.clone(), init_eqn.clone()),
+            None => return false,
+        };
+        if elements
+            .iter()
+            .any(|(_, el_eqn, el_init_eqn)| *el_eqn != eqn || *el_init_eqn != init_eqn)
+        {
+            return false;
+        }
+        *equation = Equation::ApplyToAll(dim_names.clone(), eqn, init_eqn);
+        true
+    }
+}
+
+/// ElementReference is a variable whose equation subscripts an element
+/// that was removed from a dimension.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ElementReference {
+    pub model_name: String,
+    pub ident: String,
+}
+
+impl Dimension {
+    fn named_elements_mut(&mut self) -> Result<&mut Vec<String>> {
+        match self {
+            Dimension::Indexed(name, _) => {
+                model_err!(
+                    BadDimensionName,
+                    format!("{} is an indexed dimension", name)
+                )
+            }
+            Dimension::Named(_, elements) => Ok(elements),
+        }
+    }
+
+    fn element_offset(&self, element: &str) -> Result<usize> {
+        let found = match self {
+            Dimension::Indexed(_, _) => None,
+            Dimension::Named(_, elements) => elements
+                .iter()
+                .position(|el| canonicalize(el) == canonicalize(element)),
+        };
+        match found {
+            Some(off) => Ok(off),
+            None => model_err!(UnknownSubscript, format!("{}.{}", self.name(), element)),
+        }
+    }
+
+    /// add_element appends an element to a named dimension.
+    pub fn add_element(&mut self, element: &str) -> Result<()> {
+        if self.element_offset(element).is_ok() {
+            return model_err!(
+                Generic,
+                format!("duplicate element {}.{}", self.name(), element)
+            );
+        }
+        self.named_elements_mut()?.push(element.to_owned());
+        Ok(())
+    }
+
+    /// remove_element removes an element from a named dimension.
+    pub fn remove_element(&mut self, element: &str) -> Result<()> {
+        let off = self.element_offset(element)?;
+        self.named_elements_mut()?.remove(off);
+        Ok(())
+    }
+
+    /// rename_element renames an element of a named dimension, keeping
+    /// its position.
+    pub fn rename_element(&mut self, old: &str, new: &str) -> Result<()> {
+        let off = self.element_offset(old)?;
+        if canonicalize(old) != canonicalize(new) && self.element_offset(new).is_ok() {
+            return model_err!(
+                Generic,
+                format!("duplicate element {}.{}", self.name(), new)
+            );
+        }
+        self.named_elements_mut()?[off] = new.to_owned();
+        Ok(())
+    }
+}
+
+fn equation_texts_mut(equation: &mut Equation) -> Vec<&mut String> {
+    match equation {
+        Equation::Scalar(eqn, init_eqn) | Equation::ApplyToAll(_, eqn, init_eqn) => {
+            std::iter::once(eqn).chain(init_eqn.as_mut()).collect()
+        }
+        Equation::Arrayed(_, elements) => elements
+            .iter_mut()
+            .flat_map(|(_, eqn, init_eqn)| std::iter::once(eqn).chain(init_eqn.as_mut()))
+            .collect(),
+    }
+}
+
+// element_spans returns the byte ranges of identifiers inside square
+// brackets that refer to the element, either bare or qualified with the
+// dimension name.
+fn element_spans(eqn: &str, dim_name: &str, element: &str) -> Vec<(usize, usize)> {
+    let element = canonicalize(element);
+    let qualified = format!("{}·{}", canonicalize(dim_name), element);
+    let mut depth = 0;
+    let mut spans = vec![];
+    // equations that don't lex are left for the parser to report
+    for (start, token, end) in Lexer::new(eqn, LexerType::Equation).flatten() {
+        match token {
+            Token::LBracket => depth += 1,
+            Token::RBracket => depth -= 1,
+            Token::Ident(id) if depth > 0 => {
+                let id = canonicalize(id);
+                if id == element || id == qualified {
+                    spans.push((start, end));
+                }
+            }
+            _ => {}
+        }
+    }
+    spans
+}
+
+impl Project {
+    // update_arrays calls f with each arrayed variable that uses the
+    // dimension, along with the dimension's position in the variable's
+    // dimensions, then puts the variable's elements in dimension order.
+    fn update_arrays<F>(&mut self, dim_name: &str, mut f: F) -> Result<()>
+    where
+        F: FnMut(usize, &mut Vec<(String, String, Option<String>)>),
+    {
+        let dimensions = self.dimensions.clone();
+        for model in self.models.iter_mut() {
+            for var in model.variables.iter_mut() {
+                match equation_mut(var) {
+                    Ok(Equation::Arrayed(dim_names, elements)) => {
+                        match dim_names
+                            .iter()
+                            .position(|name| canonicalize(name) == canonicalize(dim_name))
+                        {
+                            Some(pos) => f(pos, elements),
+                            None => continue,
+                        }
+                    }
+                    _ => continue,
+                };
+                var.to_arrayed(&dimensions)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn dimension_mut(&mut self, dim_name: &str) -> Result<&mut Dimension> {
+        match self
+            .dimensions
+            .iter_mut()
+            .find(|dim| canonicalize(dim.name()) == canonicalize(dim_name))
+        {
+            Some(dim) => Ok(dim),
+            None => model_err!(BadDimensionName, dim_name.to_owned()),
+        }
+    }
+
+    /// add_dimension_element adds an element to a dimension, giving every
+    /// arrayed equation over the dimension an empty equation for it.
+    pub fn add_dimension_element(&mut self, dim_name: &str, element: &str) -> Result<()> {
+        self.dimension_mut(dim_name)?.add_element(element)?;
+        self.update_arrays(dim_name, |_, _| {})
+    }
+
+    /// remove_dimension_element removes an element from a dimension and
+    /// from every arrayed equation over the dimension.  Equations that
+    /// subscript the removed element are returned, as they no longer
+    /// refer to anything.
+    pub fn remove_dimension_element(
+        &mut self,
+        dim_name: &str,
+        element: &str,
+    ) -> Result<Vec<ElementReference>> {
+        self.dimension_mut(dim_name)?.remove_element(element)?;
+        self.update_arrays(dim_name, |_, _| {})?;
+
+        let mut refs = vec![];
+        for model in self.models.iter_mut() {
+            for var in model.variables.iter_mut() {
+                let ident = var.get_ident().to_owned();
+                let is_referenced = match equation_mut(var) {
+                    Ok(equation) => equation_texts_mut(equation)
+                        .into_iter()
+                        .any(|eqn| !element_spans(eqn, dim_name, element).is_empty()),
+                    Err(_) => false,
+                };
+                if is_referenced {
+                    refs.push(ElementReference {
+                        model_name: model.name.clone(),
+                        ident,
+                    });
+                }
+            }
+        }
+        Ok(refs)
+    }
+
+    /// rename_dimension_element renames an element of a dimension,
+    /// updating arrayed equations over the dimension and subscripts that
+    /// refer to the element.
+    pub fn rename_dimension_element(&mut self, dim_name: &str, old: &str, new: &str) -> Result<()> {
+        let dim = self.dimension_mut(dim_name)?;
+        dim.rename_element(old, new)?;
+        let qualified_dim_name = dim.name().to_owned();
+
+        self.update_arrays(dim_name, |pos, elements| {
+            for (name, _, _) in elements.iter_mut() {
+                let mut subscripts: Vec<String> =
+                    name.split(',').map(|s| s.trim().to_owned()).collect();
+                if subscripts.len() > pos && canonicalize(&subscripts[pos]) == canonicalize(old) {
+                    subscripts[pos] = new.to_owned();
+                    *name = subscripts.join(",");
+                }
+            }
+        })?;
+
+        for model in self.models.iter_mut() {
+            for var in model.variables.iter_mut() {
+                let equation = match equation_mut(var) {
+                    Ok(equation) => equation,
+                    Err(_) => continue,
+                };
+                for eqn in equation_texts_mut(equation) {
+                    // replace from the end so earlier spans stay valid
+                    for (start, end) in element_spans(eqn, dim_name, old).into_iter().rev() {
+                        let replacement = if eqn[start..end].contains('.') {
+                            format!("{}.{}", qualified_dim_name, new)
+                        } else {
+                            new.to_owned()
+                        };
+                        eqn.replace_range(start..end, &replacement);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_element_equations() {
+    use crate::common::ErrorCode;
+    use crate::datamodel::{Aux, Visibility};
+
+    let dimensions = vec![
+        Dimension::Named("Letters".to_owned(), vec!["a".to_owned(), "b".to_owned()]),
+        Dimension::Indexed("Size".to_owned(), 3),
+    ];
+    assert_eq!(
+        vec!["a,1", "a,2", "a,3", "b,1", "b,2", "b,3"],
+        element_names(&dimensions, &["letters".to_owned(), "size".to_owned()]).unwrap()
+    );
+    assert_eq!(
+        ErrorCode::BadDimensionName,
+        element_names(&dimensions, &["numbers".to_owned()])
+            .unwrap_err()
+            .code
+    );
+
+    let mut var = Variable::Aux(Aux {
+        ident: "x".to_owned(),
+        equation: Equation::ApplyToAll(vec!["Letters".to_owned()], "1".to_owned(), None),
+        documentation: "".to_owned(),
+        units: None,
+        gf: None,
+        can_be_module_input: false,
+        supplementary: false,
+        visibility: Visibility::Private,
+    });
+    let original = var.clone();
+
+    var.set_element_equation(&dimensions, "B", "2").unwrap();
+    assert_eq!(
+        Some(&Equation::Arrayed(
+            vec!["Letters".to_owned()],
+            vec![
+                ("a".to_owned(), "1".to_owned(), None),
+                ("b".to_owned(), "2".to_owned(), None),
+            ]
+        )),
+        var.get_equation()
+    );
+    assert!(!var.to_apply_to_all());
+    assert_eq!(
+        ErrorCode::UnknownSubscript,
+        var.set_element_equation(&dimensions, "c", "3")
+            .unwrap_err()
+            .code
+    );
+
+    var.set_element_equation(&dimensions, "b", "1").unwrap();
+    assert!(var.to_apply_to_all());
+    assert_eq!(original, var);
+
+    // arrayed equations are put in dimension order
+    if let Variable::Aux(aux) = &mut var {
+        aux.equation = Equation::Arrayed(
+            vec!["Letters".to_owned()],
+            vec![("b".to_owned(), "2".to_owned(), None)],
+        );
+    }
+    var.to_arrayed(&dimensions).unwrap();
+    assert_eq!(
+        Some(&Equation::Arrayed(
+            vec!["Letters".to_owned()],
+            vec![
+                ("a".to_owned(), "".to_owned(), None),
+                ("b".to_owned(), "2".to_owned(), None),
+            ]
+        )),
+        var.get_equation()
+    );
+
+    let mut scalar = Variable::Aux(Aux {
+        equation: Equation::Scalar("1".to_owned(), None),
+        ..match original {
+            Variable::Aux(aux) => aux,
+            _ => unreachable!(),
+        }
+    });
+    assert_eq!(
+        ErrorCode::MismatchedDimensions,
+        scalar.to_arrayed(&dimensions).unwrap_err().code
+    );
+}
+
+#[test]
+fn test_dimension_editing() {
+    use crate::common::ErrorCode;
+    use crate::datamodel::{Aux, Visibility};
+    use crate::testutils::{sim_specs_with_units, x_aux, x_model, x_project};
+
+    let arrayed = |ident: &str, dims: &[&str], elements: &[(&str, &str)]| {
+        Variable::Aux(Aux {
+            ident: ident.to_owned(),
+            equation: Equation::Arrayed(
+                dims.iter().map(|d| d.to_string()).collect(),
+                elements
+                    .iter()
+                    .map(|(el, eqn)| (el.to_string(), eqn.to_string(), None))
+                    .collect(),
+            ),
+            documentation: "".to_owned(),
+            units: None,
+            gf: None,
+            can_be_module_input: false,
+            supplementary: false,
+            visibility: Visibility::Private,
+        })
+    };
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                arrayed("x", &["Letters"], &[("a", "1"), ("b", "2"), ("c", "3")]),
+                arrayed(
+                    "grid",
+                    &["Region", "Letters"],
+                    &[("north,b", "4"), ("south,b", "5")],
+                ),
+                x_aux("total", "x[b] + x[Letters.b] + grid[north, a]", None),
+                x_aux("first", "x[a]", None),
+            ],
+        )],
+    );
+    project.dimensions = vec![
+        Dimension::Named(
+            "Letters".to_owned(),
+            vec!["a".to_owned(), "b".to_owned(), "c".to_owned()],
+        ),
+        Dimension::Named(
+            "Region".to_owned(),
+            vec!["north".to_owned(), "south".to_owned()],
+        ),
+    ];
+    let equation = |project: &Project, ident: &str| {
+        project.models[0]
+            .get_variable(ident)
+            .unwrap()
+            .get_equation()
+            .unwrap()
+            .clone()
+    };
+    let element_eqns = |project: &Project, ident: &str| match equation(project, ident) {
+        Equation::Arrayed(_, elements) => elements
+            .into_iter()
+            .map(|(el, eqn, _)| format!("{}={}", el, eqn))
+            .collect::<Vec<_>>(),
+        eqn => panic!("expected arrayed equation, not {:?}", eqn),
+    };
+
+    project.add_dimension_element("letters", "d").unwrap();
+    assert_eq!(vec!["a=1", "b=2", "c=3", "d="], element_eqns(&project, "x"));
+    assert!(project.add_dimension_element("letters", "D").is_err());
+
+    project
+        .rename_dimension_element("Letters", "b", "bee")
+        .unwrap();
+    assert_eq!(
+        vec!["a=1", "bee=2", "c=3", "d="],
+        element_eqns(&project, "x")
+    );
+    assert_eq!(
+        vec![
+            "north,a=",
+            "north,bee=4",
+            "north,c=",
+            "north,d=",
+            "south,a=",
+            "south,bee=5",
+            "south,c=",
+            "south,d="
+        ],
+        element_eqns(&project, "grid")
+    );
+    assert_eq!(
+        Equation::Scalar("x[bee] + x[Letters.bee] + grid[north, a]".to_owned(), None),
+        equation(&project, "total")
+    );
+
+    let refs = project.remove_dimension_element("Letters", "a").unwrap();
+    assert_eq!(
+        vec!["total", "first"],
+        refs.iter().map(|r| r.ident.as_str()).collect::<Vec<_>>()
+    );
+    assert_eq!(vec!["bee=2", "c=3", "d="], element_eqns(&project, "x"));
+    assert_eq!(
+        ErrorCode::UnknownSubscript,
+        project
+            .remove_dimension_element("Letters", "a")
+            .unwrap_err()
+            .code
+    );
+    assert_eq!(
+        ErrorCode::BadDimensionName,
+        project
+            .add_dimension_element("Numbers", "1")
+            .unwrap_err()
+            .code
+    );
+}
@@ -273,6 +273,83 @@ impl IndexExpr {
             IndexExpr::Expr(e) => e.get_var_loc(ident),
         }
     }
+
+    /// check_element ensures this index refers to an element of `dim`
+    /// (or to something that is resolved at runtime, like a variable).
+    fn check_element(
+        &self,
+        scope: &ScopeStage0,
+        model_name: &str,
+        dim: &Dimension,
+    ) -> EquationResult<()> {
+        match self {
+            IndexExpr::Wildcard(_) => Ok(()),
+            IndexExpr::StarRange(name, loc) => {
+                if scope.dimensions.is_dimension(name) {
+                    Ok(())
+                } else {
+                    eqn_err!(BadDimensionName, loc.start, loc.end)
+                }
+            }
+            IndexExpr::Range(l, r, _) => {
+                check_index_element(l, scope, model_name, dim)?;
+                check_index_element(r, scope, model_name, dim)
+            }
+            IndexExpr::Expr(e) => check_index_element(e, scope, model_name, dim),
+        }
+    }
+
+    fn check_subscripts(&self, scope: &ScopeStage0, model_name: &str) -> EquationResult<()> {
+        match self {
+            IndexExpr::Wildcard(_) | IndexExpr::StarRange(_, _) => Ok(()),
+            IndexExpr::Range(l, r, _) => {
+                l.check_subscripts(scope, model_name)?;
+                r.check_subscripts(scope, model_name)
+            }
+            IndexExpr::Expr(e) => e.check_subscripts(scope, model_name),
+        }
+    }
+}
+
+fn check_index_element(
+    expr: &Expr,
+    scope: &ScopeStage0,
+    model_name: &str,
+    dim: &Dimension,
+) -> EquationResult<()> {
+    match expr {
+        Expr::Var(id, loc) => {
+            let is_variable = id.contains('·')
+                || scope
+                    .models
+                    .get(model_name)
+                    .map(|model| model.variables.contains_key(id))
+                    .unwrap_or(false);
+            if dim.get_offset(id).is_some() || scope.dimensions.is_dimension(id) || is_variable {
+                Ok(())
+            } else {
+                eqn_err!(UnknownSubscript, loc.start, loc.end)
+            }
+        }
+        // a qualified element reference like `location.boston`
+        // has already been turned into a constant by constify_dimensions
+        Expr::Const(id, _, loc) if id.contains('·') => {
+            let dim_name = &id[..id.find('·').unwrap()];
+            if dim_name == dim.name() {
+                Ok(())
+            } else {
+                eqn_err!(MismatchedDimensions, loc.start, loc.end)
+            }
+        }
+        Expr::Const(_, n, loc) => {
+            if *n >= 1.0 && n.trunc() == *n && (*n as usize) <= dim.len() {
+                Ok(())
+            } else {
+                eqn_err!(UnknownSubscript, loc.start, loc.end)
+            }
+        }
+        _ => Ok(()),
+    }
 }
 
 impl Default for Expr0 {
@@ -391,6 +468,14 @@ impl Expr {
                     "sqrt" => check_arity!(Sqrt, 1),
                     "step" => check_arity!(Step, 2),
                     "tan" => check_arity!(Tan, 1),
+                    "white_noise" => check_arity!(WhiteNoise, 1),
+                    "transpose" => {
+                        if let (1, Some(Expr::Var(ident, loc))) = (args.len(), args.first()) {
+                            BuiltinFn::Transpose(ident.clone(), *loc)
+                        } else {
+                            return eqn_err!(ExpectedIdent, loc.start, loc.end);
+                        }
+                    }
                     "time" => check_arity!(Time, 0),
                     "time_step" | "dt" => check_arity!(TimeStep, 0),
                     "initial_time" => check_arity!(StartTime, 0),
@@ -461,6 +546,9 @@ impl Expr {
                     BuiltinFn::Sin(a) => BuiltinFn::Sin(Box::new(a.constify_dimensions(scope))),
                     BuiltinFn::Sqrt(a) => BuiltinFn::Sqrt(Box::new(a.constify_dimensions(scope))),
                     BuiltinFn::Tan(a) => BuiltinFn::Tan(Box::new(a.constify_dimensions(scope))),
+                    BuiltinFn::WhiteNoise(a) => {
+                        BuiltinFn::WhiteNoise(Box::new(a.constify_dimensions(scope)))
+                    }
                     BuiltinFn::Mean(args) => BuiltinFn::Mean(
                         args.into_iter()
                             .map(|arg| arg.constify_dimensions(scope))
@@ -479,6 +567,7 @@ impl Expr {
                         Box::new(b.constify_dimensions(scope)),
                     ),
                     BuiltinFn::IsModuleInput(id, loc) => BuiltinFn::IsModuleInput(id, loc),
+                    BuiltinFn::Transpose(id, loc) => BuiltinFn::Transpose(id, loc),
                     BuiltinFn::Lookup(id, arg, loc) => {
                         BuiltinFn::Lookup(id, Box::new(arg.constify_dimensions(scope)), loc)
                     }
@@ -576,6 +665,58 @@ impl Expr {
                 .or_else(|| f.get_var_loc(ident)),
         }
     }
+
+    /// check_subscripts validates element names, ranges and wildcards
+    /// against the dimensions of the variable they subscript.
+    pub(crate) fn check_subscripts(
+        &self,
+        scope: &ScopeStage0,
+        model_name: &str,
+    ) -> EquationResult<()> {
+        match self {
+            Expr::Const(_, _, _) | Expr::Var(_, _) => Ok(()),
+            Expr::App(builtin, _) => {
+                let mut result = Ok(());
+                walk_builtin_expr(builtin, |contents| {
+                    if let BuiltinContents::Expr(expr) = contents {
+                        if result.is_ok() {
+                            result = expr.check_subscripts(scope, model_name);
+                        }
+                    }
+                });
+                result
+            }
+            Expr::Subscript(id, args, loc) => {
+                for arg in args.iter() {
+                    arg.check_subscripts(scope, model_name)?;
+                }
+                let dims = scope
+                    .models
+                    .get(model_name)
+                    .and_then(|model| model.variables.get(id))
+                    .and_then(|var| var.get_dimensions());
+                if let Some(dims) = dims {
+                    if dims.len() != args.len() {
+                        return eqn_err!(MismatchedDimensions, loc.start, loc.end);
+                    }
+                    for (dim, arg) in dims.iter().zip(args.iter()) {
+                        arg.check_element(scope, model_name, dim)?;
+                    }
+                }
+                Ok(())
+            }
+            Expr::Op1(_, l, _) => l.check_subscripts(scope, model_name),
+            Expr::Op2(_, l, r, _) => {
+                l.check_subscripts(scope, model_name)?;
+                r.check_subscripts(scope, model_name)
+            }
+            Expr::If(cond, t, f, _) => {
+                cond.check_subscripts(scope, model_name)?;
+                t.check_subscripts(scope, model_name)?;
+                f.check_subscripts(scope, model_name)
+            }
+        }
+    }
 }
 
 impl Default for Expr {
@@ -844,22 +985,25 @@ impl Ast<Expr> {
     }
 }
 
-pub(crate) fn lower_ast(scope: &ScopeStage0, ast: Ast<Expr0>) -> EquationResult<Ast<Expr>> {
+pub(crate) fn lower_ast(
+    scope: &ScopeStage0,
+    model_name: &str,
+    ast: Ast<Expr0>,
+) -> EquationResult<Ast<Expr>> {
+    let lower = |expr: Expr0| -> EquationResult<Expr> {
+        let expr = Expr::from(expr)?.constify_dimensions(scope);
+        expr.check_subscripts(scope, model_name)?;
+        Ok(expr)
+    };
     match ast {
-        Ast::Scalar(expr) => Expr::from(expr)
-            .map(|expr| expr.constify_dimensions(scope))
-            .map(Ast::Scalar),
-        Ast::ApplyToAll(dims, expr) => Expr::from(expr)
-            .map(|expr| expr.constify_dimensions(scope))
-            .map(|expr| Ast::ApplyToAll(dims, expr)),
+        Ast::Scalar(expr) => lower(expr).map(Ast::Scalar),
+        Ast::ApplyToAll(dims, expr) => lower(expr).map(|expr| Ast::ApplyToAll(dims, expr)),
         Ast::Arrayed(dims, elements) => {
             let elements: EquationResult<HashMap<ElementName, Expr>> = elements
                 .into_iter()
-                .map(|(id, expr)| {
-                    match Expr::from(expr).map(|expr| expr.constify_dimensions(scope)) {
-                        Ok(expr) => Ok((id, expr)),
-                        Err(err) => Err(err),
-                    }
+                .map(|(id, expr)| match lower(expr) {
+                    Ok(expr) => Ok((id, expr)),
+                    Err(err) => Err(err),
                 })
                 .collect();
             match elements {
@@ -0,0 +1,292 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+use crate::common::Ident;
+use crate::vm::Results;
+
+// series whose range is smaller than this fraction of their magnitude
+// are considered constant, and changes smaller than this fraction of the
+// range are considered flat
+const RELATIVE_EPSILON: f64 = 1e-6;
+// how much the rate of change can vary and still count as linear
+const LINEAR_TOLERANCE: f64 = 0.05;
+// the fraction of the run at either end where a maximum rate of change
+// indicates exponential growth (at the end) or goal seeking (at the start)
+const EDGE_FRACTION: f64 = 0.1;
+
+/// BehaviorPattern is the qualitative shape of a time series, with the
+/// parameters of the characteristic curve fitted to it.
+#[derive(Clone, PartialEq, Debug)]
+pub enum BehaviorPattern {
+    Constant {
+        value: f64,
+    },
+    Linear {
+        slope: f64,
+    },
+    /// dx/dt = growth_rate * x
+    ExponentialGrowth {
+        growth_rate: f64,
+    },
+    /// dx/dt = (goal - x) / time_constant
+    GoalSeeking {
+        goal: f64,
+        time_constant: f64,
+    },
+    /// dx/dt = r * x * (1 - x / capacity)
+    SShaped {
+        capacity: f64,
+        inflection_time: f64,
+    },
+    /// damping_ratio is the size of the last swing relative to the first:
+    /// less than 1 if the oscillation is dying out
+    Oscillation {
+        period: f64,
+        damping_ratio: f64,
+    },
+    OvershootAndCollapse {
+        peak: f64,
+        peak_time: f64,
+    },
+    Unclassified,
+}
+
+// fit_line returns the (intercept, slope) of the least-squares line
+// through the points.
+fn fit_line(points: &[(f64, f64)]) -> (f64, f64) {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let cov: f64 = points
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum();
+    let var: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    let slope = if var == 0.0 { 0.0 } else { cov / var };
+    (mean_y - slope * mean_x, slope)
+}
+
+/// classify returns the behavior pattern of a series of values at the
+/// given times.
+pub fn classify(times: &[f64], values: &[f64]) -> BehaviorPattern {
+    let n = times.len().min(values.len());
+    if n < 3 {
+        return BehaviorPattern::Unclassified;
+    }
+    let (times, values) = (&times[..n], &values[..n]);
+
+    let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    if !min.is_finite() || !max.is_finite() {
+        return BehaviorPattern::Unclassified;
+    }
+    let range = max - min;
+    if range <= RELATIVE_EPSILON * max.abs().max(min.abs()).max(1.0) {
+        return BehaviorPattern::Constant { value: values[0] };
+    }
+
+    // rates of change, as (value, rate) at the start of each step
+    let rates: Vec<(f64, f64)> = (0..n - 1)
+        .map(|i| {
+            let dt = times[i + 1] - times[i];
+            (values[i], (values[i + 1] - values[i]) / dt)
+        })
+        .collect();
+
+    // turning points are where the series changes direction; flat steps
+    // don't count as a change
+    let flat = RELATIVE_EPSILON * range;
+    let mut extrema = vec![];
+    let mut direction = 0.0;
+    for i in 0..n - 1 {
+        let d = values[i + 1] - values[i];
+        if d.abs() <= flat {
+            continue;
+        }
+        if direction != 0.0 && d.signum() != direction {
+            extrema.push(i);
+        }
+        direction = d.signum();
+    }
+
+    if extrema.len() >= 2 {
+        let first = extrema[0];
+        let last = extrema[extrema.len() - 1];
+        let period = 2.0 * (times[last] - times[first]) / (extrema.len() - 1) as f64;
+        let swing = |i: usize| (values[extrema[i + 1]] - values[extrema[i]]).abs();
+        return BehaviorPattern::Oscillation {
+            period,
+            damping_ratio: swing(extrema.len() - 2) / swing(0),
+        };
+    }
+
+    if extrema.len() == 1 {
+        let peak = extrema[0];
+        let rise = values[peak] - values[0];
+        let fall = values[peak] - values[n - 1];
+        // a peak (rather than a trough) that gives back most of its rise
+        if rise > 0.0 && fall > rise / 2.0 {
+            return BehaviorPattern::OvershootAndCollapse {
+                peak: values[peak],
+                peak_time: times[peak],
+            };
+        }
+        return BehaviorPattern::Unclassified;
+    }
+
+    // monotonic: classify by how the speed of change evolves
+    let speeds: Vec<f64> = rates.iter().map(|(_, rate)| rate.abs()).collect();
+    let max_speed = speeds.iter().cloned().fold(0.0, f64::max);
+    let min_speed = speeds.iter().cloned().fold(f64::INFINITY, f64::min);
+    if max_speed - min_speed <= LINEAR_TOLERANCE * max_speed {
+        let (_, slope) = fit_line(
+            &times
+                .iter()
+                .cloned()
+                .zip(values.iter().cloned())
+                .collect::<Vec<_>>(),
+        );
+        return BehaviorPattern::Linear { slope };
+    }
+
+    let fastest = speeds
+        .iter()
+        .enumerate()
+        .fold(0, |best, (i, s)| if *s > speeds[best] { i } else { best });
+    let position = fastest as f64 / (speeds.len() - 1) as f64;
+    if position >= 1.0 - EDGE_FRACTION {
+        let (_, growth_rate) = fit_line(&rates);
+        BehaviorPattern::ExponentialGrowth { growth_rate }
+    } else if position <= EDGE_FRACTION {
+        let (intercept, slope) = fit_line(&rates);
+        if slope >= 0.0 {
+            return BehaviorPattern::Unclassified;
+        }
+        BehaviorPattern::GoalSeeking {
+            goal: -intercept / slope,
+            time_constant: -1.0 / slope,
+        }
+    } else {
+        // the per-capita rate of logistic growth falls linearly with x
+        let per_capita: Vec<(f64, f64)> = rates
+            .iter()
+            .filter(|(x, _)| *x != 0.0)
+            .map(|(x, rate)| (*x, rate / x))
+            .collect();
+        let (intercept, slope) = fit_line(&per_capita);
+        if slope == 0.0 {
+            return BehaviorPattern::Unclassified;
+        }
+        BehaviorPattern::SShaped {
+            capacity: -intercept / slope,
+            inflection_time: times[fastest],
+        }
+    }
+}
+
+impl Results {
+    /// classify_behaviors returns the behavior pattern of every variable
+    /// in the results, sorted by name.
+    pub fn classify_behaviors(&self) -> Vec<(Ident, BehaviorPattern)> {
+        let time_off = match self.offsets.get("time") {
+            Some(off) => *off,
+            None => return vec![],
+        };
+        let times: Vec<f64> = self.iter_rows().map(|step| step[time_off]).collect();
+
+        let mut idents: Vec<&String> = self
+            .offsets
+            .keys()
+            .filter(|id| !matches!(id.as_str(), "time" | "dt" | "initial_time" | "final_time"))
+            .collect();
+        idents.sort_unstable();
+        idents
+            .into_iter()
+            .map(|ident| {
+                let off = self.offsets[ident];
+                let values: Vec<f64> = self.iter_rows().map(|step| step[off]).collect();
+                (ident.clone(), classify(&times, &values))
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_classify() {
+    let dt = 0.25;
+    let times: Vec<f64> = (0..=400).map(|i| i as f64 * dt).collect();
+    // integrates dx/dt = f(x) with Euler's method, like the simulator
+    let euler = |x0: f64, f: &dyn Fn(f64) -> f64| {
+        let mut x = x0;
+        times
+            .iter()
+            .map(|_| {
+                let curr = x;
+                x += f(x) * dt;
+                curr
+            })
+            .collect::<Vec<_>>()
+    };
+    let approx = |a: f64, b: f64| (a - b).abs() < 1e-6 * b.abs().max(1.0);
+
+    assert_eq!(
+        BehaviorPattern::Constant { value: 3.0 },
+        classify(&times, &vec![3.0; times.len()])
+    );
+    match classify(&times, &euler(1.0, &|_| 2.0)) {
+        BehaviorPattern::Linear { slope } => assert!(approx(slope, 2.0)),
+        b => panic!("expected linear, not {:?}", b),
+    }
+    match classify(&times, &euler(10.0, &|x| 0.05 * x)) {
+        BehaviorPattern::ExponentialGrowth { growth_rate } => assert!(approx(growth_rate, 0.05)),
+        b => panic!("expected exponential growth, not {:?}", b),
+    }
+    match classify(&times, &euler(10.0, &|x| (50.0 - x) / 8.0)) {
+        BehaviorPattern::GoalSeeking {
+            goal,
+            time_constant,
+        } => {
+            assert!(approx(goal, 50.0));
+            assert!(approx(time_constant, 8.0));
+        }
+        b => panic!("expected goal seeking, not {:?}", b),
+    }
+    match classify(&times, &euler(1.0, &|x| 0.2 * x * (1.0 - x / 1000.0))) {
+        BehaviorPattern::SShaped {
+            capacity,
+            inflection_time,
+        } => {
+            assert!(approx(capacity, 1000.0));
+            assert!(inflection_time > 30.0 && inflection_time < 40.0);
+        }
+        b => panic!("expected s-shaped growth, not {:?}", b),
+    }
+
+    let cosine: Vec<f64> = times
+        .iter()
+        .map(|t| (-0.01 * t).exp() * (2.0 * std::f64::consts::PI * t / 20.0).cos())
+        .collect();
+    match classify(&times, &cosine) {
+        BehaviorPattern::Oscillation {
+            period,
+            damping_ratio,
+        } => {
+            assert!((period - 20.0).abs() < 0.5);
+            assert!(damping_ratio < 1.0);
+        }
+        b => panic!("expected oscillation, not {:?}", b),
+    }
+
+    let bump: Vec<f64> = times
+        .iter()
+        .map(|t| (-(t - 30.0).powi(2) / 50.0).exp())
+        .collect();
+    assert_eq!(
+        BehaviorPattern::OvershootAndCollapse {
+            peak: 1.0,
+            peak_time: 30.0
+        },
+        classify(&times, &bump)
+    );
+}
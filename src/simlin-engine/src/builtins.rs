@@ -76,6 +76,9 @@ pub enum BuiltinFn<Expr> {
     Sqrt(Box<Expr>),
     Step(Box<Expr>, Box<Expr>),
     Tan(Box<Expr>),
+    Transpose(String, Loc),
+    /// a standard normal draw that depends only on the seed and the time
+    WhiteNoise(Box<Expr>),
     Time,
     TimeStep,
     StartTime,
@@ -108,6 +111,8 @@ impl<Expr> BuiltinFn<Expr> {
             BuiltinFn::Sqrt(_) => "sqrt",
             BuiltinFn::Step(_, _) => "step",
             BuiltinFn::Tan(_) => "tan",
+            BuiltinFn::Transpose(_, _) => "transpose",
+            BuiltinFn::WhiteNoise(_) => "white_noise",
             BuiltinFn::Time => "time",
             BuiltinFn::TimeStep => "time_step",
             BuiltinFn::StartTime => "initial_time",
@@ -148,6 +153,8 @@ pub fn is_builtin_fn(name: &str) -> bool {
                 | "sqrt"
                 | "step"
                 | "tan"
+                | "transpose"
+                | "white_noise"
         )
 }
 
@@ -167,7 +174,9 @@ where
         | BuiltinFn::TimeStep
         | BuiltinFn::StartTime
         | BuiltinFn::FinalTime => {}
-        BuiltinFn::IsModuleInput(id, loc) => cb(BuiltinContents::Ident(id, *loc)),
+        BuiltinFn::IsModuleInput(id, loc) | BuiltinFn::Transpose(id, loc) => {
+            cb(BuiltinContents::Ident(id, *loc))
+        }
         BuiltinFn::Lookup(id, a, loc) => {
             cb(BuiltinContents::Ident(id, *loc));
             cb(BuiltinContents::Expr(a));
@@ -183,7 +192,8 @@ where
         | BuiltinFn::Log10(a)
         | BuiltinFn::Sin(a)
         | BuiltinFn::Sqrt(a)
-        | BuiltinFn::Tan(a) => cb(BuiltinContents::Expr(a)),
+        | BuiltinFn::Tan(a)
+        | BuiltinFn::WhiteNoise(a) => cb(BuiltinContents::Expr(a)),
         BuiltinFn::Mean(args) => {
             args.iter().for_each(|a| cb(BuiltinContents::Expr(a)));
         }
@@ -4,10 +4,12 @@
 
 use std::collections::HashMap;
 
-use crate::ast::{print_eqn, Ast, Expr0, IndexExpr0};
+use crate::ast::{print_eqn, Ast, Expr0, IndexExpr0, Loc};
 use crate::builtins::{is_builtin_fn, UntypedBuiltinFn};
 use crate::common::{EquationError, Ident};
-use crate::datamodel::Visibility;
+use crate::complexity::children;
+use crate::datamodel::{Dimension, Visibility};
+use crate::vm::SubscriptIterator;
 use crate::{datamodel, eqn_err};
 
 fn stdlib_args(name: &str) -> Option<&'static [&'static str]> {
@@ -24,11 +26,103 @@ fn stdlib_args(name: &str) -> Option<&'static [&'static str]> {
     Some(args)
 }
 
+// stdlib_alias returns the stdlib model behind a builtin better known
+// by its Vensim name, like SMOOTH for SMTH1.  The I variants take the
+// initial value as a third argument, which the stdlib models accept too.
+fn stdlib_alias(name: &str) -> Option<&'static str> {
+    let model = match name {
+        "smooth" | "smoothi" => "smth1",
+        "smooth3" | "smooth3i" => "smth3",
+        _ => {
+            return None;
+        }
+    };
+    Some(model)
+}
+
+/// is_implicit_builtin returns true if `name` is a builtin that is
+/// turned into implicit variables, rather than evaluated directly.
+#[cfg(test)]
+pub(crate) fn is_implicit_builtin(name: &str) -> bool {
+    matches!(name, "delayn" | "pink_noise" | "random_walk")
+        || stdlib_alias(name).is_some()
+        || crate::stdlib::MODEL_NAMES.contains(&name)
+}
+
+// is_module_builtin returns true if `name` is a builtin that becomes a
+// module, like DELAY1 or SMOOTH.
+fn is_module_builtin(name: &str) -> bool {
+    stdlib_alias(name).is_some() || crate::stdlib::MODEL_NAMES.contains(&name)
+}
+
+fn has_module_builtin(expr: &Expr0) -> bool {
+    if let Expr0::App(UntypedBuiltinFn(func, _), _) = expr {
+        if is_module_builtin(func) {
+            return true;
+        }
+    }
+    children(expr).into_iter().any(has_module_builtin)
+}
+
+fn implicit_aux(ident: &str, equation: datamodel::Equation) -> datamodel::Variable {
+    datamodel::Variable::Aux(datamodel::Aux {
+        ident: ident.to_owned(),
+        equation,
+        documentation: "".to_string(),
+        units: None,
+        gf: None,
+        can_be_module_input: false,
+        supplementary: false,
+        visibility: datamodel::Visibility::Private,
+    })
+}
+
+fn implicit_flow(ident: &str, equation: datamodel::Equation) -> datamodel::Variable {
+    datamodel::Variable::Flow(datamodel::Flow {
+        ident: ident.to_owned(),
+        equation,
+        documentation: "".to_string(),
+        units: None,
+        gf: None,
+        non_negative: false,
+        can_be_module_input: false,
+        supplementary: false,
+        visibility: Visibility::Private,
+    })
+}
+
+fn implicit_stock(
+    ident: &str,
+    equation: datamodel::Equation,
+    inflows: Vec<String>,
+    outflows: Vec<String>,
+) -> datamodel::Variable {
+    datamodel::Variable::Stock(datamodel::Stock {
+        ident: ident.to_owned(),
+        equation,
+        documentation: "".to_string(),
+        units: None,
+        inflows,
+        outflows,
+        non_negative: false,
+        force_euler: false,
+        can_be_module_input: false,
+        supplementary: false,
+        visibility: Visibility::Private,
+    })
+}
+
 pub struct BuiltinVisitor<'a> {
     variable_name: &'a str,
     vars: HashMap<Ident, datamodel::Variable>,
     n: usize,
     self_allowed: bool,
+    // the dimensions of an arrayed equation, whose implicit variables
+    // are arrayed over them too
+    dims: Vec<Dimension>,
+    // the element being walked, when an arrayed equation needs a module
+    // for each of its elements
+    element: Option<Vec<String>>,
 }
 
 impl<'a> BuiltinVisitor<'a> {
@@ -38,7 +132,188 @@ impl<'a> BuiltinVisitor<'a> {
             vars: Default::default(),
             n: 0,
             self_allowed: false,
+            dims: vec![],
+            element: None,
+        }
+    }
+
+    // arg_ident returns the variable holding the value of a builtin's
+    // argument, adding an aux for it if it isn't already a variable.  In
+    // an arrayed equation the aux is arrayed over the same dimensions,
+    // so the argument can refer to the element being computed.
+    fn arg_ident(&mut self, i: usize, arg: Expr0) -> Ident {
+        if let Expr0::Var(id, _loc) = arg {
+            return id;
+        }
+        let id = format!("$⁚{}⁚{}⁚arg{}", self.variable_name, self.n, i);
+        let eqn = print_eqn(&arg);
+        let equation = if self.dims.is_empty() {
+            datamodel::Equation::Scalar(eqn, None)
+        } else {
+            let names = self.dims.iter().map(|dim| dim.name().to_owned()).collect();
+            datamodel::Equation::ApplyToAll(names, eqn, None)
+        };
+        self.vars.insert(id.clone(), implicit_aux(&id, equation));
+        id
+    }
+
+    // element_arg_ident returns a scalar aux holding one element of a
+    // builtin's argument in an arrayed equation, as modules can only
+    // take scalar inputs.
+    fn element_arg_ident(&mut self, i: usize, arg: Expr0, element: &[String]) -> Ident {
+        let arrayed = format!("$⁚{}⁚{}⁚arg{}", self.variable_name, self.n, i);
+        let eqn = print_eqn(&arg);
+        let names = self.dims.iter().map(|dim| dim.name().to_owned()).collect();
+        let equation = datamodel::Equation::ApplyToAll(names, eqn, None);
+        self.vars
+            .insert(arrayed.clone(), implicit_aux(&arrayed, equation));
+
+        let id = format!("{}⁚{}", arrayed, element.join("⁚"));
+        let subscripts: Vec<String> = element.iter().map(|e| format!("\"{}\"", e)).collect();
+        let eqn = format!("\"{}\"[{}]", arrayed, subscripts.join(", "));
+        self.vars.insert(
+            id.clone(),
+            implicit_aux(&id, datamodel::Equation::Scalar(eqn, None)),
+        );
+        id
+    }
+
+    // arg_text is like arg_ident, but returns a reference to the variable
+    // that can be used in an equation.
+    fn arg_text(&mut self, i: usize, arg: Expr0) -> String {
+        let id = self.arg_ident(i, arg);
+        if id.starts_with('$') {
+            format!("\"{}\"", id)
+        } else {
+            id
+        }
+    }
+
+    // implicit_equation returns the equation of an implicit stock or
+    // flow.  In an arrayed equation each element gets its own, so they
+    // are arrayed over the same dimensions.
+    fn implicit_equation(&self, eqn: String) -> datamodel::Equation {
+        if self.dims.is_empty() {
+            datamodel::Equation::Scalar(eqn, None)
+        } else {
+            let names = self.dims.iter().map(|dim| dim.name().to_owned()).collect();
+            datamodel::Equation::ApplyToAll(names, eqn, None)
+        }
+    }
+
+    // delayn expands `DELAYN(input, delay_time, n[, initial_value])` into a
+    // chain of n stocks, each draining into the next over delay_time / n.
+    // Unlike the other delays it can't be a stdlib model, as the number
+    // of stocks depends on the call, so n has to be a constant.
+    fn delayn(&mut self, args: Vec<Expr0>, loc: Loc) -> Result<Expr0, EquationError> {
+        if args.len() != 3 && args.len() != 4 {
+            return eqn_err!(BadBuiltinArgs, loc.start, loc.end);
+        }
+        let order = match args[2] {
+            Expr0::Const(_, n, _) if n >= 1.0 && n.fract() == 0.0 => n as usize,
+            _ => return eqn_err!(ExpectedInteger, loc.start, loc.end),
+        };
+
+        let mut args = args.into_iter().enumerate();
+        let (i, input) = args.next().unwrap();
+        let input = self.arg_text(i, input);
+        let (i, delay_time) = args.next().unwrap();
+        let delay_time = self.arg_text(i, delay_time);
+        let initial_value = match args.nth(1) {
+            Some((i, initial_value)) => self.arg_text(i, initial_value),
+            None => input.clone(),
+        };
+        let stage_time = format!("({} / {})", delay_time, order);
+
+        let prefix = format!("$⁚{}⁚{}⁚delayn", self.variable_name, self.n);
+        let flow_ident = |i: usize| format!("{}⁚flow{}", prefix, i);
+
+        let inflow = implicit_flow(&flow_ident(0), self.implicit_equation(input));
+        self.vars.insert(flow_ident(0), inflow);
+        for i in 1..=order {
+            let stock_ident = format!("{}⁚stock{}", prefix, i);
+            let outflow = implicit_flow(
+                &flow_ident(i),
+                self.implicit_equation(format!("\"{}\" / {}", stock_ident, stage_time)),
+            );
+            self.vars.insert(flow_ident(i), outflow);
+            let stock = implicit_stock(
+                &stock_ident,
+                self.implicit_equation(format!("{} * {}", initial_value, stage_time)),
+                vec![flow_ident(i - 1)],
+                vec![flow_ident(i)],
+            );
+            self.vars.insert(stock_ident, stock);
+        }
+
+        self.n += 1;
+        Ok(Expr0::Var(flow_ident(order), loc))
+    }
+
+    // noise_stock adds a stock starting at `initial_value` and changed
+    // by a flow, whose equation `change` builds from a reference to the
+    // stock.  It returns the stock.
+    fn noise_stock<F>(&mut self, func: &str, initial_value: &str, change: F, loc: Loc) -> Expr0
+    where
+        F: FnOnce(&str) -> String,
+    {
+        let prefix = format!("$⁚{}⁚{}⁚{}", self.variable_name, self.n, func);
+        let stock_ident = format!("{}⁚level", prefix);
+        let flow_ident = format!("{}⁚change", prefix);
+        let change = change(&format!("\"{}\"", stock_ident));
+        let flow = implicit_flow(&flow_ident, self.implicit_equation(change));
+        self.vars.insert(flow_ident.clone(), flow);
+        let stock = implicit_stock(
+            &stock_ident,
+            self.implicit_equation(initial_value.to_owned()),
+            vec![flow_ident],
+            vec![],
+        );
+        self.vars.insert(stock_ident.clone(), stock);
+        self.n += 1;
+        Expr0::Var(stock_ident, loc)
+    }
+
+    // pink_noise expands `PINK_NOISE(mean, sd, correlation_time, seed)`
+    // into white noise smoothed over the correlation time.  The white
+    // noise is scaled so the smoothed noise settles to a standard
+    // deviation of sd, whatever dt is.
+    fn pink_noise(&mut self, args: Vec<Expr0>, loc: Loc) -> Result<Expr0, EquationError> {
+        if args.len() != 4 {
+            return eqn_err!(BadBuiltinArgs, loc.start, loc.end);
         }
+        let args: Vec<String> = args
+            .into_iter()
+            .enumerate()
+            .map(|(i, arg)| self.arg_text(i, arg))
+            .collect();
+        let (mean, sd, tc, seed) = (&args[0], &args[1], &args[2], &args[3]);
+
+        let white = format!(
+            "{} + {} * SQRT((2 - TIME_STEP / {}) * {} / TIME_STEP) * WHITE_NOISE({})",
+            mean, sd, tc, tc, seed
+        );
+        let change = |level: &str| format!("(({}) - {}) / {}", white, level, tc);
+        Ok(self.noise_stock("pink_noise", mean, change, loc))
+    }
+
+    // random_walk expands `RANDOM_WALK(sd, seed[, initial_value])` into a
+    // stock changed by white noise each step.  Its variance grows by sd
+    // squared each time unit, whatever dt is.
+    fn random_walk(&mut self, args: Vec<Expr0>, loc: Loc) -> Result<Expr0, EquationError> {
+        if args.len() != 2 && args.len() != 3 {
+            return eqn_err!(BadBuiltinArgs, loc.start, loc.end);
+        }
+        let args: Vec<String> = args
+            .into_iter()
+            .enumerate()
+            .map(|(i, arg)| self.arg_text(i, arg))
+            .collect();
+        let (sd, seed) = (&args[0], &args[1]);
+        let initial_value = args.get(2).map(|v| v.as_str()).unwrap_or("0");
+
+        let change = |_: &str| format!("{} * WHITE_NOISE({}) / SQRT(TIME_STEP)", sd, seed);
+        Ok(self.noise_stock("random_walk", initial_value, change, loc))
     }
 
     fn walk_index(&mut self, expr: IndexExpr0) -> Result<IndexExpr0, EquationError> {
@@ -76,6 +351,17 @@ impl<'a> BuiltinVisitor<'a> {
                     return Ok(App(UntypedBuiltinFn(func, args), loc));
                 }
 
+                match func.as_str() {
+                    "delayn" => return self.delayn(args, loc),
+                    "pink_noise" => return self.pink_noise(args, loc),
+                    "random_walk" => return self.random_walk(args, loc),
+                    _ => {}
+                }
+                let func = match stdlib_alias(&func) {
+                    Some(model) => model.to_owned(),
+                    None => func,
+                };
+
                 // TODO: make this a function call/hash lookup
                 if !crate::stdlib::MODEL_NAMES.contains(&func.as_str()) {
                     return eqn_err!(UnknownBuiltin, loc.start, loc.end);
@@ -83,33 +369,20 @@ impl<'a> BuiltinVisitor<'a> {
 
                 let stdlib_model_inputs = stdlib_args(&func).unwrap();
 
-                let module_name = format!("$⁚{}⁚{}⁚{}", self.variable_name, self.n, func);
-
-                let ident_args = args.into_iter().enumerate().map(|(i, arg)| {
-                    if let Var(id, _loc) = arg {
-                        id
-                    } else {
-                        let id = format!("$⁚{}⁚{}⁚arg{}", self.variable_name, self.n, i);
-                        let eqn = print_eqn(&arg);
-                        let x_var = datamodel::Variable::Aux(datamodel::Aux {
-                            ident: id.clone(),
-                            equation: datamodel::Equation::Scalar(eqn, None),
-                            documentation: "".to_string(),
-                            units: None,
-                            gf: None,
-                            can_be_module_input: false,
-                            visibility: datamodel::Visibility::Private,
-                        });
-                        self.vars.insert(id.clone(), x_var);
-                        id
-                    }
-                });
+                let mut module_name = format!("$⁚{}⁚{}⁚{}", self.variable_name, self.n, func);
+                let element = self.element.clone();
+                if let Some(element) = element.as_ref() {
+                    module_name = format!("{}⁚{}", module_name, element.join("⁚"));
+                }
 
-                let references: Vec<_> = ident_args
+                let references: Vec<_> = args
                     .into_iter()
                     .enumerate()
-                    .map(|(i, src)| datamodel::ModuleReference {
-                        src,
+                    .map(|(i, arg)| datamodel::ModuleReference {
+                        src: match element.as_ref() {
+                            Some(element) => self.element_arg_ident(i, arg, element),
+                            None => self.arg_ident(i, arg),
+                        },
                         dst: format!("{}.{}", module_name, stdlib_model_inputs[i]),
                     })
                     .collect();
@@ -158,6 +431,181 @@ impl<'a> BuiltinVisitor<'a> {
 #[test]
 fn test_builtin_visitor() {}
 
+#[test]
+fn test_smooth_aliases() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_model, x_project};
+    use crate::vm::Vm;
+    use crate::{Project, Simulation};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_aux("input", "STEP(6, 1) + 2", None),
+                x_aux("smth1", "SMTH1(input, 3)", None),
+                x_aux("smooth", "SMOOTH(input, 3)", None),
+                x_aux("smth1_init", "SMTH1(input, 3, 5)", None),
+                x_aux("smoothi", "SMOOTHI(input, 3, 5)", None),
+                x_aux("smth3", "SMTH3(input, 3)", None),
+                x_aux("smooth3", "SMOOTH3(input, 3)", None),
+                x_aux("smth3_init", "SMTH3(input, 3, 5)", None),
+                x_aux("smooth3i", "SMOOTH3I(input, 3, 5)", None),
+            ],
+        )],
+    );
+    project.sim_specs.stop = 10.0;
+    let project = Project::from(project);
+    let sim = Simulation::new(&project, "main").unwrap();
+    let mut vm = Vm::new(sim.compile().unwrap()).unwrap();
+    vm.run_to_end().unwrap();
+    let results = vm.into_results();
+
+    assert_eq!(Some(5.0), results.get_series("smoothi").map(|s| s[0]));
+    for (a, b) in [
+        ("smth1", "smooth"),
+        ("smth1_init", "smoothi"),
+        ("smth3", "smooth3"),
+        ("smth3_init", "smooth3i"),
+    ] {
+        assert_eq!(results.get_series(a), results.get_series(b), "{}", b);
+    }
+}
+
+#[test]
+fn test_delayn() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_model, x_project};
+    use crate::vm::Vm;
+    use crate::{Project, Simulation};
+
+    // a stdlib model is compiled once per simulation, so DELAY3 with and
+    // without an initial value are simulated separately
+    let run = |third: &str, nth: &str| {
+        let mut project = x_project(
+            sim_specs_with_units("time"),
+            &[x_model(
+                "main",
+                vec![
+                    x_aux("input", "STEP(6, 1) + 2", None),
+                    x_aux("third", third, None),
+                    x_aux("nth", nth, None),
+                ],
+            )],
+        );
+        project.sim_specs.stop = 10.0;
+        let project = Project::from(project);
+        let sim = Simulation::new(&project, "main").unwrap();
+        let mut vm = Vm::new(sim.compile().unwrap()).unwrap();
+        vm.run_to_end().unwrap();
+        let results = vm.into_results();
+        (
+            results.get_series("third").unwrap(),
+            results.get_series("nth").unwrap(),
+        )
+    };
+
+    let (third, nth) = run("DELAY3(input, 4)", "DELAYN(input, 4, 3)");
+    assert_eq!(2.0, nth[0]);
+    assert!(nth[10] > 2.0 && nth[10] < 8.0);
+    let (third_init, nth_init) = run("DELAY3(input, 2 + 2, 5)", "DELAYN(input, 2 + 2, 3, 5)");
+    assert_eq!(5.0, nth_init[0]);
+    for (a, b) in [(third, nth), (third_init, nth_init)] {
+        assert!(a.iter().zip(b.iter()).all(|(a, b)| (a - b).abs() < 1e-9));
+    }
+
+    let visit = |eqn: &str| {
+        let ast = crate::ast::Ast::Scalar(
+            Expr0::new(eqn, crate::token::LexerType::Equation)
+                .unwrap()
+                .unwrap(),
+        );
+        instantiate_implicit_modules("x", ast)
+    };
+    let (_, vars) = visit("DELAYN(input, 4, 2)").unwrap();
+    // an aux for the delay time, an inflow, and a stock and outflow for
+    // each stage
+    assert_eq!(6, vars.len());
+    assert!(visit("DELAYN(input, 4, order)").is_err());
+    assert!(visit("DELAYN(input, 4, 0.5)").is_err());
+    assert!(visit("DELAYN(input, 4)").is_err());
+}
+
+#[test]
+fn test_noise() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_model, x_project};
+    use crate::vm::Vm;
+    use crate::{Project, Simulation};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_aux("white", "WHITE_NOISE(1)", None),
+                x_aux("other_white", "WHITE_NOISE(2)", None),
+                x_aux("pink", "PINK_NOISE(10, 2, 3, 7)", None),
+                x_aux("walk", "RANDOM_WALK(1, 5)", None),
+                x_aux("walk_init", "RANDOM_WALK(0.5 + 0.5, 5, 100)", None),
+            ],
+        )],
+    );
+    project.sim_specs.stop = 2000.0;
+    project.sim_specs.dt = datamodel::Dt::Dt(0.25);
+    let project = Project::from(project);
+    let sim = Simulation::new(&project, "main").unwrap();
+    let mut vm = Vm::new(sim.compile().unwrap()).unwrap();
+    vm.run_to_end().unwrap();
+    let results = vm.into_results();
+
+    let stats = |ident: &str| {
+        let series = results.get_series(ident).unwrap();
+        let n = series.len() as f64;
+        let mean = series.iter().sum::<f64>() / n;
+        let var = series.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+        (mean, var.sqrt())
+    };
+    let (mean, sd) = stats("white");
+    assert!(mean.abs() < 0.1 && (sd - 1.0).abs() < 0.1);
+    assert_ne!(
+        results.get_series("white"),
+        results.get_series("other_white")
+    );
+    let (mean, sd) = stats("pink");
+    assert!((mean - 10.0).abs() < 0.5 && (sd - 2.0).abs() < 0.4);
+    assert_eq!(Some(10.0), results.get_series("pink").map(|s| s[0]));
+
+    // the same seed gives the same steps
+    let walk = results.get_series("walk").unwrap();
+    let walk_init = results.get_series("walk_init").unwrap();
+    assert_eq!(0.0, walk[0]);
+    assert!(walk
+        .iter()
+        .zip(walk_init.iter())
+        .all(|(a, b)| (b - a - 100.0).abs() < 1e-9));
+
+    // the interpreter draws the same noise
+    let interpreted = sim.run_to_end().unwrap();
+    for ident in ["white", "pink", "walk"] {
+        let a = results.get_series(ident).unwrap();
+        let b = interpreted.get_series(ident).unwrap();
+        assert!(a.iter().zip(b.iter()).all(|(a, b)| (a - b).abs() < 1e-9));
+    }
+
+    let visit = |eqn: &str| {
+        let ast = crate::ast::Ast::Scalar(
+            Expr0::new(eqn, crate::token::LexerType::Equation)
+                .unwrap()
+                .unwrap(),
+        );
+        instantiate_implicit_modules("x", ast)
+    };
+    let (_, vars) = visit("PINK_NOISE(0, 1, 2, 3)").unwrap();
+    // an aux for each argument, and a stock and the flow changing it
+    assert_eq!(6, vars.len());
+    assert!(visit("PINK_NOISE(0, 1, 2)").is_err());
+    assert!(visit("RANDOM_WALK(1)").is_err());
+}
+
 pub fn instantiate_implicit_modules(
     variable_name: &str,
     ast: Ast<Expr0>,
@@ -165,11 +613,31 @@ pub fn instantiate_implicit_modules(
     let mut builtin_visitor = BuiltinVisitor::new(variable_name);
     let ast = match ast {
         Ast::Scalar(ast) => Ast::Scalar(builtin_visitor.walk(ast)?),
-        Ast::ApplyToAll(dimensions, ast) => Ast::ApplyToAll(dimensions, builtin_visitor.walk(ast)?),
+        Ast::ApplyToAll(dimensions, ast) if has_module_builtin(&ast) => {
+            // modules are scalar, so an equation using one is split into
+            // an equation per element, each with a module of its own.
+            // Every element walks the same calls, so shares their numbers.
+            builtin_visitor.dims = dimensions.clone();
+            let n = builtin_visitor.n;
+            let mut elements = HashMap::new();
+            for subscripts in SubscriptIterator::new(&dimensions) {
+                builtin_visitor.n = n;
+                builtin_visitor.element = Some(subscripts.iter().map(|s| s.to_string()).collect());
+                elements.insert(subscripts.join(","), builtin_visitor.walk(ast.clone())?);
+            }
+            Ast::Arrayed(dimensions, elements)
+        }
+        Ast::ApplyToAll(dimensions, ast) => {
+            builtin_visitor.dims = dimensions.clone();
+            Ast::ApplyToAll(dimensions, builtin_visitor.walk(ast)?)
+        }
         Ast::Arrayed(dimensions, elements) => {
+            builtin_visitor.dims = dimensions.clone();
             let elements: std::result::Result<HashMap<_, _>, EquationError> = elements
                 .into_iter()
                 .map(|(subscript, equation)| {
+                    builtin_visitor.element =
+                        Some(subscript.split(',').map(|s| s.to_owned()).collect());
                     builtin_visitor.walk(equation).map(|ast| (subscript, ast))
                 })
                 .collect();
@@ -12,6 +12,8 @@ pub type ModuleId = u16;
 pub type VariableOffset = u16;
 pub type ModuleInputOffset = u16;
 pub type GraphicalFunctionId = u8;
+pub type BlockId = u16;
+pub type JumpOffset = u16;
 
 #[derive(Copy, Clone, Debug)]
 pub(crate) enum BuiltinId {
@@ -35,6 +37,7 @@ pub(crate) enum BuiltinId {
     Sqrt,
     Step,
     Tan,
+    WhiteNoise,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -63,14 +66,16 @@ pub(crate) enum Opcode {
     LoadGlobalVar { off: VariableOffset },
     PushSubscriptIndex { bounds: VariableOffset },
     LoadSubscript { off: VariableOffset },
-    SetCond {},
-    If {},
+    // pop a condition, and skip the next `skip` opcodes if it is false
+    JumpIfFalse { skip: JumpOffset },
+    Jump { skip: JumpOffset },
     LoadModuleInput { input: ModuleInputOffset },
     EvalModule { id: ModuleId, n_inputs: u8 },
     AssignCurr { off: VariableOffset },
     AssignNext { off: VariableOffset },
     Apply { func: BuiltinId },
     Lookup { gf: GraphicalFunctionId },
+    EvalParallel { id: BlockId },
     Ret,
 }
 
@@ -87,10 +92,57 @@ pub struct ByteCodeContext {
     pub(crate) modules: Vec<ModuleDeclaration>,
 }
 
+/// ParallelBlock is the code for the elements of an apply-to-all
+/// variable, which don't depend on each other and so can be evaluated
+/// in chunks on separate threads.  Each element's code ends by
+/// assigning to its slot, counting up from `off`.
+#[derive(Clone, Debug, Default)]
+pub struct ParallelBlock {
+    pub(crate) off: VariableOffset,
+    pub(crate) code: Vec<Opcode>,
+    // where the code for each element starts
+    pub(crate) starts: Vec<usize>,
+}
+
+impl ParallelBlock {
+    /// element_code returns the code for elements `from` up to `to`.
+    pub(crate) fn element_code(&self, from: usize, to: usize) -> &[Opcode] {
+        let end = self.starts.get(to).copied().unwrap_or(self.code.len());
+        &self.code[self.starts[from]..end]
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct ByteCode {
     pub(crate) literals: Vec<f64>,
     pub(crate) code: Vec<Opcode>,
+    pub(crate) blocks: Vec<ParallelBlock>,
+}
+
+impl ByteCode {
+    /// size returns the number of bytes taken by the code and literals.
+    pub(crate) fn size(&self) -> usize {
+        let code_len = self.code.len() + self.blocks.iter().map(|b| b.code.len()).sum::<usize>();
+        code_len * std::mem::size_of::<Opcode>() + self.literals.len() * std::mem::size_of::<f64>()
+    }
+
+    /// flops estimates the floating point operations of one run through
+    /// the code, not including the code of any modules it evaluates.
+    pub(crate) fn flops(&self) -> usize {
+        self.code
+            .iter()
+            .chain(self.blocks.iter().flat_map(|b| b.code.iter()))
+            .filter(|op| {
+                matches!(
+                    op,
+                    Opcode::Op2 { .. }
+                        | Opcode::Not {}
+                        | Opcode::Apply { .. }
+                        | Opcode::Lookup { .. }
+                )
+            })
+            .count()
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -115,6 +167,40 @@ impl ByteCodeBuilder {
         self.bytecode.code.push(op)
     }
 
+    /// push_jump pushes a jump whose `skip` is filled in later by
+    /// `patch_jump`, returning where it is in the code.
+    pub(crate) fn push_jump(&mut self, op: Opcode) -> usize {
+        self.push_opcode(op);
+        self.bytecode.code.len() - 1
+    }
+
+    /// patch_jump makes the jump at `at` skip to the end of the code
+    /// pushed so far.
+    pub(crate) fn patch_jump(&mut self, at: usize) {
+        let len = self.bytecode.code.len() - at - 1;
+        match &mut self.bytecode.code[at] {
+            Opcode::Jump { skip } | Opcode::JumpIfFalse { skip } => *skip = len as JumpOffset,
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn code_len(&self) -> usize {
+        self.bytecode.code.len()
+    }
+
+    /// push_block moves the code from `starts[0]` on, for the elements
+    /// starting at each of `starts`, into a new parallel block.
+    pub(crate) fn push_block(&mut self, off: VariableOffset, starts: &[usize]) -> BlockId {
+        let first = starts[0];
+        let code = self.bytecode.code.split_off(first);
+        self.bytecode.blocks.push(ParallelBlock {
+            off,
+            code,
+            starts: starts.iter().map(|start| start - first).collect(),
+        });
+        (self.bytecode.blocks.len() - 1) as BlockId
+    }
+
     pub(crate) fn finish(self) -> ByteCode {
         self.bytecode
     }
@@ -150,6 +236,7 @@ fn test_opcode_size() {
 pub struct CompiledModule {
     pub(crate) ident: String,
     pub(crate) n_slots: usize,
+    pub(crate) implicit: bool,
     pub(crate) context: Rc<ByteCodeContext>,
     pub(crate) compiled_initials: Rc<ByteCode>,
     pub(crate) compiled_flows: Rc<ByteCode>,
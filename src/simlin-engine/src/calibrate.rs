@@ -0,0 +1,407 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Calibrating a model against observed data: finding the values of
+//! uncertain constants, each within bounds, for which the model's
+//! behavior best matches what was observed.  The search is Nelder-Mead,
+//! which needs only the error of each run and no derivatives, run in
+//! coordinates scaled so that each parameter's bounds are 0 and 1.
+
+use crate::common::Result;
+use crate::datamodel::{self, Equation};
+use crate::freeze::simulate_with_stubs;
+use crate::optimize::{valid_range, Parameter};
+use crate::resample::Interpolation;
+use crate::sim_err;
+use crate::stubs::{Stub, Stubs};
+use crate::vm::Results;
+
+// the coefficients of the standard Nelder-Mead moves
+const REFLECTION: f64 = 1.0;
+const EXPANSION: f64 = 2.0;
+const CONTRACTION: f64 = 0.5;
+const SHRINK: f64 = 0.5;
+// the size of the initial simplex, as a fraction of each range
+const INITIAL_STEP: f64 = 0.25;
+
+/// ErrorMetric is how the differences between the simulated and the
+/// observed values are summed up.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ErrorMetric {
+    /// mean squared error
+    #[default]
+    Mse,
+    /// mean absolute error
+    Mae,
+}
+
+impl ErrorMetric {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "mse" => Some(ErrorMetric::Mse),
+            "mae" => Some(ErrorMetric::Mae),
+            _ => None,
+        }
+    }
+}
+
+/// CalibrationOptions bounds the search, which stops after
+/// `max_evaluations` runs or once the error of every point of the
+/// simplex is within `tolerance` of the best.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CalibrationOptions {
+    pub metric: ErrorMetric,
+    pub max_evaluations: usize,
+    pub tolerance: f64,
+}
+
+impl Default for CalibrationOptions {
+    fn default() -> Self {
+        CalibrationOptions {
+            metric: ErrorMetric::Mse,
+            max_evaluations: 500,
+            tolerance: 1e-10,
+        }
+    }
+}
+
+/// Calibration is the best fit found: the value of each parameter, in
+/// the order they were given, the error with those values, and the
+/// project with each parameter's equation set to its value.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Calibration {
+    pub values: Vec<f64>,
+    pub error: f64,
+    pub evaluations: usize,
+    pub project: datamodel::Project,
+}
+
+struct Fit<'a> {
+    project: &'a datamodel::Project,
+    model_name: &'a str,
+    params: &'a [Parameter],
+    observed: &'a Results,
+    times: Vec<f64>,
+    variables: Vec<String>,
+    metric: ErrorMetric,
+    evaluations: usize,
+}
+
+impl Fit<'_> {
+    fn values(&self, x: &[f64]) -> Vec<f64> {
+        self.params
+            .iter()
+            .zip(x.iter())
+            .map(|(param, x)| param.min + x * (param.max - param.min))
+            .collect()
+    }
+
+    // error simulates the model with the parameters at the scaled point
+    // `x`, returning infinity for runs that produce NaNs.
+    fn error(&mut self, x: &[f64]) -> Result<f64> {
+        self.evaluations += 1;
+        let mut stubs = Stubs::new();
+        for (param, value) in self.params.iter().zip(self.values(x)) {
+            stubs.stub(self.model_name, &param.ident, Stub::Constant(value));
+        }
+        let results = simulate_with_stubs(self.project, self.model_name, &stubs)?
+            .resample(&self.times, Interpolation::Linear);
+
+        let mut sum = 0.0;
+        let mut n = 0;
+        for ident in self.variables.iter() {
+            let simulated = results.get_series(ident).unwrap();
+            let observed = self.observed.get_series(ident).unwrap();
+            for (simulated, observed) in simulated.iter().zip(observed.iter()) {
+                if observed.is_nan() {
+                    continue;
+                }
+                let diff = simulated - observed;
+                sum += match self.metric {
+                    ErrorMetric::Mse => diff * diff,
+                    ErrorMetric::Mae => diff.abs(),
+                };
+                n += 1;
+            }
+        }
+        let error = sum / n as f64;
+        Ok(if error.is_nan() { f64::INFINITY } else { error })
+    }
+}
+
+// initial_point returns where a parameter's search starts: its value in
+// the model if it is a constant within bounds, and otherwise the middle
+// of its range.
+fn initial_point(model: &datamodel::Model, param: &Parameter) -> f64 {
+    let value = match model
+        .get_variable(&param.ident)
+        .and_then(|v| v.get_equation())
+    {
+        Some(Equation::Scalar(eqn, _)) => eqn.trim().parse::<f64>().ok(),
+        _ => None,
+    };
+    match value {
+        Some(value) if param.min < param.max && (param.min..=param.max).contains(&value) => {
+            (value - param.min) / (param.max - param.min)
+        }
+        _ => 0.5,
+    }
+}
+
+// towards returns the point `coefficient` of the way from `from` past
+// `to`, kept within the unit box.
+fn towards(from: &[f64], to: &[f64], coefficient: f64) -> Vec<f64> {
+    from.iter()
+        .zip(to.iter())
+        .map(|(a, b)| (a + coefficient * (b - a)).clamp(0.0, 1.0))
+        .collect()
+}
+
+/// calibrate searches for the values of `params` that make the model
+/// `model_name` best match `observed`.  Each of `variables` is compared
+/// at the times it was observed, skipping missing (NaN) values, and the
+/// error is pooled over all of them.  With no `variables`, every
+/// observed column the model also has is compared.
+pub fn calibrate(
+    project: &datamodel::Project,
+    model_name: &str,
+    params: &[Parameter],
+    observed: &Results,
+    variables: &[String],
+    options: &CalibrationOptions,
+) -> Result<Calibration> {
+    if params.is_empty() {
+        return sim_err!(
+            Generic,
+            "calibration needs at least one parameter".to_owned()
+        );
+    }
+    let model = match project.get_model(model_name) {
+        Some(model) => model,
+        None => return sim_err!(BadModelName, model_name.to_owned()),
+    };
+    for param in params.iter() {
+        valid_range(&param.ident, param.min, param.max)?;
+        if model.get_variable(&param.ident).is_none() {
+            return sim_err!(DoesNotExist, param.ident.clone());
+        }
+    }
+
+    // a baseline run tells us which of the observed columns the model
+    // can be compared on
+    let baseline = simulate_with_stubs(project, model_name, &Stubs::new())?;
+    let variables: Vec<String> = if variables.is_empty() {
+        observed
+            .var_names()
+            .into_iter()
+            .filter(|ident| *ident != "time" && baseline.offset(ident).is_some())
+            .map(|ident| ident.to_owned())
+            .collect()
+    } else {
+        for ident in variables.iter() {
+            if observed.offset(ident).is_none() || baseline.offset(ident).is_none() {
+                return sim_err!(DoesNotExist, ident.clone());
+            }
+        }
+        variables.to_vec()
+    };
+    if variables.is_empty() {
+        return sim_err!(
+            Generic,
+            "none of the observed variables are in the model".to_owned()
+        );
+    }
+
+    let mut fit = Fit {
+        project,
+        model_name,
+        params,
+        observed,
+        times: observed.time_points(),
+        variables,
+        metric: options.metric,
+        evaluations: 0,
+    };
+
+    let x0: Vec<f64> = params.iter().map(|p| initial_point(model, p)).collect();
+    let mut simplex = vec![];
+    for i in 0..=params.len() {
+        let mut x = x0.clone();
+        if i > 0 {
+            let x = &mut x[i - 1];
+            *x = if *x + INITIAL_STEP <= 1.0 {
+                *x + INITIAL_STEP
+            } else {
+                *x - INITIAL_STEP
+            };
+        }
+        let error = fit.error(&x)?;
+        simplex.push((x, error));
+    }
+
+    while fit.evaluations < options.max_evaluations {
+        simplex.sort_by(|a, b| a.1.total_cmp(&b.1));
+        let best = simplex[0].1;
+        let worst = simplex[simplex.len() - 1].1;
+        if worst - best <= options.tolerance {
+            break;
+        }
+
+        let n = simplex.len() - 1;
+        let mut centroid = vec![0.0; params.len()];
+        for (x, _) in simplex[..n].iter() {
+            for (c, x) in centroid.iter_mut().zip(x.iter()) {
+                *c += x / n as f64;
+            }
+        }
+        let (worst_x, worst) = simplex[n].clone();
+
+        let reflected = towards(&centroid, &worst_x, -REFLECTION);
+        let reflected_error = fit.error(&reflected)?;
+        if reflected_error < best {
+            let expanded = towards(&centroid, &worst_x, -EXPANSION);
+            let expanded_error = fit.error(&expanded)?;
+            simplex[n] = if expanded_error < reflected_error {
+                (expanded, expanded_error)
+            } else {
+                (reflected, reflected_error)
+            };
+        } else if reflected_error < simplex[n - 1].1 {
+            simplex[n] = (reflected, reflected_error);
+        } else {
+            // contract towards the better of the worst point and its
+            // reflection
+            let (from, from_error) = if reflected_error < worst {
+                (&reflected, reflected_error)
+            } else {
+                (&worst_x, worst)
+            };
+            let contracted = towards(&centroid, from, CONTRACTION);
+            let contracted_error = fit.error(&contracted)?;
+            if contracted_error < from_error {
+                simplex[n] = (contracted, contracted_error);
+            } else {
+                let best_x = simplex[0].0.clone();
+                for vertex in simplex[1..].iter_mut() {
+                    let x = towards(&best_x, &vertex.0, SHRINK);
+                    let error = fit.error(&x)?;
+                    *vertex = (x, error);
+                }
+            }
+        }
+    }
+
+    simplex.sort_by(|a, b| a.1.total_cmp(&b.1));
+    let (best_x, error) = simplex.swap_remove(0);
+    let values = fit.values(&best_x);
+    let mut fitted = project.clone();
+    let model = fitted.get_model_mut(model_name).unwrap();
+    for (param, value) in params.iter().zip(values.iter()) {
+        if let Some(var) = model.get_variable_mut(&param.ident) {
+            var.set_scalar_equation(&value.to_string());
+        }
+    }
+
+    Ok(Calibration {
+        values,
+        error,
+        evaluations: fit.evaluations,
+        project: fitted,
+    })
+}
+
+#[test]
+fn test_calibrate() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
+
+    let model = |rate: &str, initial: &str| {
+        let mut project = x_project(
+            sim_specs_with_units("year"),
+            &[x_model(
+                "main",
+                vec![
+                    x_stock("population", "initial_population", &["births"], &[], None),
+                    x_flow("births", "population * birth_rate", None),
+                    x_aux("birth_rate", rate, None),
+                    x_aux("initial_population", initial, None),
+                ],
+            )],
+        );
+        project.sim_specs.stop = 10.0;
+        project.sim_specs.dt = datamodel::Dt::Dt(0.25);
+        project
+    };
+
+    // observations taken between the model's time steps, with a gap
+    let mut observed = simulate_with_stubs(&model("0.1", "50"), "main", &Stubs::new())
+        .unwrap()
+        .resample(&[0.0, 2.5, 5.0, 7.5, 10.0], Interpolation::Linear);
+    let population = observed.offsets["population"];
+    observed.data[observed.step_size + population] = f64::NAN;
+
+    let project = model("0.3", "20");
+    let params = vec![
+        Parameter {
+            ident: "birth_rate".to_owned(),
+            min: 0.0,
+            max: 0.5,
+        },
+        Parameter {
+            ident: "initial_population".to_owned(),
+            min: 0.0,
+            max: 100.0,
+        },
+    ];
+    let variables = vec!["population".to_owned()];
+    for metric in [ErrorMetric::Mse, ErrorMetric::Mae] {
+        let options = CalibrationOptions {
+            metric,
+            ..Default::default()
+        };
+        let fit = calibrate(&project, "main", &params, &observed, &variables, &options).unwrap();
+        assert!((fit.values[0] - 0.1).abs() < 1e-3, "{:?}", fit.values);
+        assert!((fit.values[1] - 50.0).abs() < 0.1, "{:?}", fit.values);
+        assert!(fit.error < 1e-3, "{}", fit.error);
+        assert!(fit.evaluations <= options.max_evaluations + params.len() + 1);
+
+        // the fitted project has the values as its equations
+        let fitted = fit.project.get_model("main").unwrap();
+        assert_eq!(
+            Some(&Equation::Scalar(fit.values[0].to_string(), None)),
+            fitted.get_variable("birth_rate").unwrap().get_equation()
+        );
+    }
+
+    // without variables, every observed column in the model is compared
+    let fit = calibrate(
+        &project,
+        "main",
+        &params,
+        &observed,
+        &[],
+        &Default::default(),
+    )
+    .unwrap();
+    assert!((fit.values[0] - 0.1).abs() < 1e-3, "{:?}", fit.values);
+
+    let bad = |params: &[Parameter], variables: &[String]| {
+        calibrate(
+            &project,
+            "main",
+            params,
+            &observed,
+            variables,
+            &Default::default(),
+        )
+        .is_err()
+    };
+    assert!(bad(&[], &variables));
+    assert!(bad(&params, &["nope".to_owned()]));
+    let mut backwards = params.clone();
+    backwards[0].min = 1.0;
+    assert!(bad(&backwards, &variables));
+    let mut missing = params.clone();
+    missing[0].ident = "nope".to_owned();
+    assert!(bad(&missing, &variables));
+}
@@ -0,0 +1,160 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Which optional features this build of the engine supports, so that
+//! front-ends can adapt their UI and test runners can explain skips.
+
+use crate::datamodel::SimMethod;
+
+/// BuiltinFamily is a group of related builtin functions.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BuiltinFamily {
+    pub name: &'static str,
+    pub functions: &'static [&'static str],
+}
+
+const BUILTIN_FAMILIES: &[BuiltinFamily] = &[
+    BuiltinFamily {
+        name: "math",
+        functions: &[
+            "abs", "exp", "inf", "int", "ln", "log10", "max", "min", "pi", "safediv", "sqrt",
+        ],
+    },
+    BuiltinFamily {
+        name: "trig",
+        functions: &["arccos", "arcsin", "arctan", "cos", "sin", "tan"],
+    },
+    BuiltinFamily {
+        name: "time",
+        functions: &["dt", "final_time", "initial_time", "time", "time_step"],
+    },
+    BuiltinFamily {
+        name: "inputs",
+        functions: &["pulse", "ramp", "step"],
+    },
+    BuiltinFamily {
+        name: "lookups",
+        functions: &["lookup"],
+    },
+    BuiltinFamily {
+        name: "arrays",
+        functions: &["mean", "transpose"],
+    },
+    BuiltinFamily {
+        name: "delays",
+        functions: &[
+            "delay1", "delay3", "delayn", "smooth", "smooth3", "smooth3i", "smoothi", "smth1",
+            "smth3", "trend",
+        ],
+    },
+    BuiltinFamily {
+        name: "memory",
+        functions: &["init", "previous"],
+    },
+    BuiltinFamily {
+        name: "noise",
+        functions: &["pink_noise", "random_walk", "white_noise"],
+    },
+];
+
+/// Capabilities lists the optional features of this build.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Capabilities {
+    pub arrays: bool,
+    pub units: bool,
+    /// whether models are compiled to native code rather than bytecode
+    pub jit: bool,
+    /// whether the elements of large arrays are evaluated on several
+    /// threads at once
+    pub parallel: bool,
+    /// whether this is the build used by the JavaScript front-end
+    pub wasm: bool,
+    pub sim_methods: Vec<SimMethod>,
+    pub builtins: Vec<BuiltinFamily>,
+}
+
+impl Capabilities {
+    /// has_builtin returns true if the (canonicalized) function `name`
+    /// can be used in equations.
+    pub fn has_builtin(&self, name: &str) -> bool {
+        self.builtins
+            .iter()
+            .any(|family| family.functions.contains(&name))
+    }
+
+    /// names returns the supported capabilities as flat names, like
+    /// `arrays`, `sim_method.rk4` and `builtins.delays`.
+    pub fn names(&self) -> Vec<String> {
+        let flags = [
+            ("arrays", self.arrays),
+            ("units", self.units),
+            ("jit", self.jit),
+            ("parallel", self.parallel),
+            ("wasm", self.wasm),
+        ];
+        let mut names: Vec<String> = flags
+            .iter()
+            .filter(|(_, supported)| *supported)
+            .map(|(name, _)| name.to_string())
+            .collect();
+        names.extend(self.sim_methods.iter().map(|method| {
+            let method = match method {
+                SimMethod::Euler => "euler",
+                SimMethod::RungeKutta4 => "rk4",
+                SimMethod::Rk45 => "rk45",
+            };
+            format!("sim_method.{}", method)
+        }));
+        names.extend(
+            self.builtins
+                .iter()
+                .map(|family| format!("builtins.{}", family.name)),
+        );
+        names
+    }
+}
+
+/// capabilities returns the optional features this build supports.
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        arrays: true,
+        units: true,
+        jit: false,
+        parallel: cfg!(all(feature = "parallel", not(target_arch = "wasm32"))),
+        wasm: cfg!(feature = "wasm"),
+        sim_methods: vec![SimMethod::Euler, SimMethod::RungeKutta4, SimMethod::Rk45],
+        builtins: BUILTIN_FAMILIES.to_vec(),
+    }
+}
+
+#[test]
+fn test_builtin_families() {
+    use crate::builtins::is_builtin_fn;
+    use crate::builtins_visitor::is_implicit_builtin;
+
+    let caps = capabilities();
+    // every family member is a real builtin, either implemented directly
+    // or turned into implicit variables, like the stdlib models
+    for family in caps.builtins.iter() {
+        for name in family.functions.iter() {
+            assert!(
+                is_builtin_fn(name) || is_implicit_builtin(name),
+                "{} isn't a builtin",
+                name
+            );
+        }
+    }
+    for name in crate::stdlib::MODEL_NAMES.iter() {
+        assert!(caps.has_builtin(name), "{} is missing a family", name);
+    }
+    assert!(caps.has_builtin("smth1"));
+    assert!(!caps.has_builtin("ismoduleinput"));
+    assert!(!caps.has_builtin("delay_fixed"));
+
+    let names = caps.names();
+    assert!(names.contains(&"arrays".to_owned()));
+    assert!(names.contains(&"sim_method.rk4".to_owned()));
+    assert!(names.contains(&"builtins.delays".to_owned()));
+    assert!(!names.contains(&"jit".to_owned()));
+}
@@ -71,6 +71,11 @@ pub enum ErrorCode {
     TodoWildcard,
     TodoStarRange,
     TodoRange,
+    UnknownSubscript,
+    XmileSpecViolation,
+    ManifestMismatch,
+    JsonDeserialization,
+    QuotaExceeded,
 }
 
 impl fmt::Display for ErrorCode {
@@ -125,12 +130,217 @@ impl fmt::Display for ErrorCode {
             TodoWildcard => "todo_wildcard",
             TodoStarRange => "todo_star_range",
             TodoRange => "todo_range",
+            UnknownSubscript => "unknown_subscript",
+            XmileSpecViolation => "xmile_spec_violation",
+            ManifestMismatch => "manifest_mismatch",
+            JsonDeserialization => "json_deserialization",
+            QuotaExceeded => "quota_exceeded",
         };
 
         write!(f, "{}", name)
     }
 }
 
+impl ErrorCode {
+    /// ALL lists every error code, in order.
+    pub const ALL: &'static [ErrorCode] = &[
+        ErrorCode::NoError,
+        ErrorCode::DoesNotExist,
+        ErrorCode::XmlDeserialization,
+        ErrorCode::VensimConversion,
+        ErrorCode::ProtobufDecode,
+        ErrorCode::InvalidToken,
+        ErrorCode::UnrecognizedEof,
+        ErrorCode::UnrecognizedToken,
+        ErrorCode::ExtraToken,
+        ErrorCode::UnclosedComment,
+        ErrorCode::UnclosedQuotedIdent,
+        ErrorCode::ExpectedNumber,
+        ErrorCode::UnknownBuiltin,
+        ErrorCode::BadBuiltinArgs,
+        ErrorCode::EmptyEquation,
+        ErrorCode::BadModuleInputDst,
+        ErrorCode::BadModuleInputSrc,
+        ErrorCode::NotSimulatable,
+        ErrorCode::BadTable,
+        ErrorCode::BadSimSpecs,
+        ErrorCode::NoAbsoluteReferences,
+        ErrorCode::CircularDependency,
+        ErrorCode::ArraysNotImplemented,
+        ErrorCode::MultiDimensionalArraysNotImplemented,
+        ErrorCode::BadDimensionName,
+        ErrorCode::BadModelName,
+        ErrorCode::MismatchedDimensions,
+        ErrorCode::ArrayReferenceNeedsExplicitSubscripts,
+        ErrorCode::DuplicateVariable,
+        ErrorCode::UnknownDependency,
+        ErrorCode::VariablesHaveErrors,
+        ErrorCode::UnitDefinitionErrors,
+        ErrorCode::Generic,
+        ErrorCode::NoAppInUnits,
+        ErrorCode::NoSubscriptInUnits,
+        ErrorCode::NoIfInUnits,
+        ErrorCode::NoUnaryOpInUnits,
+        ErrorCode::BadBinaryOpInUnits,
+        ErrorCode::NoConstInUnits,
+        ErrorCode::ExpectedInteger,
+        ErrorCode::ExpectedIntegerOne,
+        ErrorCode::DuplicateUnit,
+        ErrorCode::ExpectedModule,
+        ErrorCode::ExpectedIdent,
+        ErrorCode::UnitMismatch,
+        ErrorCode::TodoWildcard,
+        ErrorCode::TodoStarRange,
+        ErrorCode::TodoRange,
+        ErrorCode::UnknownSubscript,
+        ErrorCode::XmileSpecViolation,
+        ErrorCode::ManifestMismatch,
+        ErrorCode::JsonDeserialization,
+        ErrorCode::QuotaExceeded,
+    ];
+
+    /// id returns the stable ID of the code, like `E0021`.  IDs are
+    /// never reused, as new codes are only ever added at the end.
+    pub fn id(&self) -> String {
+        format!("E{:04}", *self as u32)
+    }
+
+    /// from_id looks up a code by its ID (`E0021`) or name
+    /// (`circular_dependency`).
+    pub fn from_id(id: &str) -> Option<ErrorCode> {
+        let id = id.trim();
+        ErrorCode::ALL
+            .iter()
+            .find(|code| code.id().eq_ignore_ascii_case(id) || code.to_string() == id)
+            .copied()
+    }
+
+    /// explanation describes what causes the error and how to fix it.
+    pub fn explanation(&self) -> &'static str {
+        use ErrorCode::*;
+        match self {
+            NoError => "No error occurred.",
+            DoesNotExist => {
+                "The named model, variable or file doesn't exist.  Check the spelling, \
+                 and for variables inside modules use a dotted path like `hares.births`."
+            }
+            XmlDeserialization => {
+                "The XMILE file couldn't be parsed.  Make sure it is well-formed XML \
+                 and was saved by a tool that writes XMILE."
+            }
+            VensimConversion => {
+                "The Vensim model couldn't be converted.  Some Vensim features, like \
+                 macros, aren't supported."
+            }
+            ProtobufDecode => {
+                "The binary project couldn't be decoded.  Make sure it was written by \
+                 the convert subcommand of a compatible version."
+            }
+            InvalidToken => "An equation contains a character or symbol that isn't allowed.",
+            UnrecognizedEof => {
+                "An equation ended unexpectedly, like with an unclosed parenthesis or \
+                 a trailing operator."
+            }
+            UnrecognizedToken => {
+                "An equation has a token where it doesn't belong, like two operators \
+                 in a row."
+            }
+            ExtraToken => "An equation has extra text after a complete expression.",
+            UnclosedComment => "An equation has a `{` comment without a closing `}`.",
+            UnclosedQuotedIdent => {
+                "An equation has a quoted variable name without a closing quote."
+            }
+            ExpectedNumber => "A number was expected, like in a graphical function or constant.",
+            UnknownBuiltin => {
+                "An equation calls a function that doesn't exist.  Check the spelling \
+                 against the list of builtin functions."
+            }
+            BadBuiltinArgs => "A builtin function was called with the wrong number of arguments.",
+            EmptyEquation => "A variable has no equation.  Give it an equation or a constant.",
+            BadModuleInputDst => {
+                "A module input connects to a variable that doesn't exist in the module's model."
+            }
+            BadModuleInputSrc => "A module input comes from a variable that doesn't exist.",
+            NotSimulatable => {
+                "The model can't be simulated, usually because some variables have \
+                 errors.  Fix the errors reported for the model's variables first."
+            }
+            BadTable => {
+                "A graphical function is malformed, like having a different number of \
+                 x and y values, or LOOKUP was called with something other than the \
+                 name of a variable with a graphical function."
+            }
+            BadSimSpecs => {
+                "The simulation specs are invalid.  The stop time must come after the \
+                 start time, and dt and the save step must be positive."
+            }
+            NoAbsoluteReferences => "Absolute references to variables aren't supported.",
+            CircularDependency => {
+                "Variables depend on each other in a loop without a stock to break it. \
+                 Every feedback loop must pass through a stock."
+            }
+            ArraysNotImplemented => "This use of arrays isn't supported yet.",
+            MultiDimensionalArraysNotImplemented => {
+                "This use of multi-dimensional arrays isn't supported yet."
+            }
+            BadDimensionName => "A subscript refers to a dimension that isn't defined.",
+            BadModelName => "A module refers to a model that doesn't exist in the project.",
+            MismatchedDimensions => {
+                "An equation combines arrays with different dimensions.  Subscript them \
+                 so the dimensions line up."
+            }
+            ArrayReferenceNeedsExplicitSubscripts => {
+                "An arrayed variable is used where a single value is needed.  Add \
+                 subscripts, like `population[region]`."
+            }
+            DuplicateVariable => "Two variables in the same model have the same name.",
+            UnknownDependency => {
+                "An equation refers to a variable that doesn't exist.  Check the \
+                 spelling, or add the variable."
+            }
+            VariablesHaveErrors => "Some variables have errors; see the errors for each variable.",
+            UnitDefinitionErrors => "Some of the project's unit definitions have errors.",
+            Generic => "An error occurred; see the details for more information.",
+            NoAppInUnits => "Units can't contain function calls.",
+            NoSubscriptInUnits => "Units can't contain subscripts.",
+            NoIfInUnits => "Units can't contain if expressions.",
+            NoUnaryOpInUnits => "Units can't contain unary operators like negation.",
+            BadBinaryOpInUnits => "Units can only be combined with `*`, `/` and `^`.",
+            NoConstInUnits => "Units can't contain numbers other than 1, like `1/year`.",
+            ExpectedInteger => "An integer was expected, like in a unit exponent.",
+            ExpectedIntegerOne => "The only number allowed in units is 1, like `1/year`.",
+            DuplicateUnit => "The same unit is defined more than once.",
+            ExpectedModule => "A module was expected, like for a dotted path.",
+            ExpectedIdent => "A variable name was expected.",
+            UnitMismatch => {
+                "An equation's units don't match the variable's declared units, or \
+                 it adds or compares quantities with different units."
+            }
+            TodoWildcard => "Wildcard subscripts (`*`) aren't supported here yet.",
+            TodoStarRange => "Star ranges in subscripts aren't supported yet.",
+            TodoRange => "Subscript ranges aren't supported yet.",
+            UnknownSubscript => "A subscript refers to an element its dimension doesn't have.",
+            XmileSpecViolation => {
+                "The XMILE file doesn't follow the v1.0 spec.  Load it without strict \
+                 mode to accept it anyway."
+            }
+            ManifestMismatch => {
+                "An ensemble manifest was run against a different project than the \
+                 one it was created from.  Recreate the manifest for this project."
+            }
+            JsonDeserialization => {
+                "The JSON project couldn't be parsed.  Make sure it was written by the \
+                 convert subcommand of a compatible version."
+            }
+            QuotaExceeded => {
+                "The simulation would use more variables, time steps or memory for \
+                 results than the host allows.  Simplify the model, shorten the run, or \
+                 use a larger dt or save step."
+            }
+        }
+    }
+}
+
 #[cfg_attr(feature = "wasm", wasm_bindgen)]
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct EquationError {
@@ -363,15 +573,49 @@ fn test_canonicalize() {
     assert_eq!("a·b", canonicalize("a.b"));
 }
 
+#[test]
+fn test_error_code_ids() {
+    // ALL is in discriminant order, so IDs are the position in the list
+    for (i, code) in ErrorCode::ALL.iter().enumerate() {
+        assert_eq!(i, *code as usize);
+        assert_eq!(Some(*code), ErrorCode::from_id(&code.id()));
+        assert_eq!(Some(*code), ErrorCode::from_id(&code.to_string()));
+        assert!(!code.explanation().is_empty());
+    }
+    assert_eq!("E0021", ErrorCode::CircularDependency.id());
+    assert_eq!(
+        Some(ErrorCode::CircularDependency),
+        ErrorCode::from_id("e0021")
+    );
+    assert_eq!(None, ErrorCode::from_id("E9999"));
+}
+
+/// quoteize returns the display name of a canonical identifier.  `.`
+/// separates module instances from the variables inside them (so
+/// `hares·births` is displayed as `hares.births`), and parts containing a
+/// literal `.` are quoted, so `canonicalize(quoteize(ident)) == ident`.
 pub fn quoteize(ident: &str) -> String {
-    // FIXME: this needs to be smarter
-    ident.replace('·', ".")
+    ident
+        .split('·')
+        .map(|part| {
+            if part.contains('.') {
+                Cow::Owned(format!("\"{}\"", part))
+            } else {
+                Cow::Borrowed(part)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(".")
 }
 
 #[test]
 fn test_quoteize() {
     assert_eq!("a_b", quoteize("a_b"));
     assert_eq!("a.b", quoteize("a·b"));
+    assert_eq!("hares.\"b.c\"", quoteize("hares·b.c"));
+    for ident in ["a_b", "a·b", "hares·b.c", "a.b·c·d"] {
+        assert_eq!(ident, canonicalize(&quoteize(ident)));
+    }
 }
 
 pub fn topo_sort<'out>(
@@ -0,0 +1,206 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! A cache of compiled simulations for hosts that simulate the same
+//! models over and over, like a service running published models for
+//! many users.  Models are keyed by the semantic content hash of their
+//! project, so edits that only move things around on the diagram don't
+//! cause a recompile, and each request gets its own VM built from the
+//! shared compiled model.
+
+use std::collections::HashMap;
+
+use crate::common::Result;
+use crate::datamodel;
+use crate::vm::CompiledSimulation;
+use crate::{Project, Simulation, Vm};
+
+/// CacheStats counts how well a cache is doing.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+struct Entry {
+    sim: CompiledSimulation,
+    bytes: usize,
+    last_used: u64,
+}
+
+/// CompileCache holds compiled simulations up to a total size, evicting
+/// the least recently used when a new one doesn't fit.
+pub struct CompileCache {
+    max_bytes: usize,
+    bytes: usize,
+    clock: u64,
+    entries: HashMap<(u64, String), Entry>,
+    stats: CacheStats,
+}
+
+// weight estimates the memory a compiled simulation holds on to: its
+// bytecode, plus the offset of each of its slots.
+fn weight(sim: &CompiledSimulation) -> usize {
+    let stats = sim.stats();
+    stats.bytecode_bytes + stats.n_slots * std::mem::size_of::<(String, usize)>()
+}
+
+impl CompileCache {
+    pub fn new(max_bytes: usize) -> Self {
+        CompileCache {
+            max_bytes,
+            bytes: 0,
+            clock: 0,
+            entries: HashMap::new(),
+            stats: Default::default(),
+        }
+    }
+
+    /// vm returns a new VM for the model `model_name` in `project`,
+    /// compiling it only if an identical project hasn't been compiled
+    /// already.  Compiled models bigger than the whole cache are
+    /// returned without being kept.
+    pub fn vm(&mut self, project: &datamodel::Project, model_name: &str) -> Result<Vm> {
+        Vm::new(self.compiled(project, model_name)?)
+    }
+
+    /// compiled is like `vm`, but returns the compiled simulation, so
+    /// that it can be checked (like against a quota) before a VM is
+    /// built for it.
+    pub fn compiled(
+        &mut self,
+        project: &datamodel::Project,
+        model_name: &str,
+    ) -> Result<CompiledSimulation> {
+        self.clock += 1;
+        let key = (project.content_hash().semantic, model_name.to_owned());
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.last_used = self.clock;
+            self.stats.hits += 1;
+            return Ok(entry.sim.clone());
+        }
+
+        self.stats.misses += 1;
+        let compiled = Simulation::new(&Project::from(project.clone()), model_name)?.compile()?;
+        let bytes = weight(&compiled);
+        if bytes <= self.max_bytes {
+            while self.bytes + bytes > self.max_bytes {
+                self.evict_oldest();
+            }
+            self.bytes += bytes;
+            self.entries.insert(
+                key,
+                Entry {
+                    sim: compiled.clone(),
+                    bytes,
+                    last_used: self.clock,
+                },
+            );
+        }
+        Ok(compiled)
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = oldest {
+            let entry = self.entries.remove(&key).unwrap();
+            self.bytes -= entry.bytes;
+            self.stats.evictions += 1;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// bytes is the estimated size of everything in the cache.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.bytes = 0;
+    }
+}
+
+#[test]
+fn test_compile_cache() {
+    use crate::datamodel::{Graph, GraphKind};
+    use crate::testutils::{sim_specs_with_units, x_aux, x_model, x_project};
+
+    let project = |eqn: &str| {
+        x_project(
+            sim_specs_with_units("time"),
+            &[x_model("main", vec![x_aux("a", eqn, None)])],
+        )
+    };
+    let run = |cache: &mut CompileCache, project: &datamodel::Project| {
+        let mut vm = cache.vm(project, "main").unwrap();
+        vm.run_to_end().unwrap();
+        vm.into_results().get_series("a").unwrap()[0]
+    };
+
+    let one = project("1");
+    let two = project("2");
+    let mut cache = CompileCache::new(usize::MAX);
+    assert_eq!(1.0, run(&mut cache, &one));
+    assert_eq!(1.0, run(&mut cache, &one));
+    assert_eq!(2.0, run(&mut cache, &two));
+    assert_eq!(
+        CacheStats {
+            hits: 1,
+            misses: 2,
+            evictions: 0
+        },
+        cache.stats()
+    );
+    assert_eq!(2, cache.len());
+
+    // layout changes, like saving a graph, don't need a recompile
+    let mut layout = one.clone();
+    layout.models[0].graphs.push(Graph {
+        title: "a".to_owned(),
+        kind: GraphKind::TimeSeries,
+        plots: vec![],
+        interval: None,
+    });
+    assert_eq!(1.0, run(&mut cache, &layout));
+    assert_eq!(2, cache.stats().hits);
+
+    // with room for only two models, the least recently used goes
+    let per_model = cache.bytes() / 2;
+    let mut cache = CompileCache::new(2 * per_model);
+    run(&mut cache, &one);
+    run(&mut cache, &two);
+    run(&mut cache, &one);
+    assert_eq!(3.0, run(&mut cache, &project("3")));
+    assert_eq!(1, cache.stats().evictions);
+    assert_eq!(2, cache.len());
+    run(&mut cache, &one);
+    assert_eq!(2, cache.stats().hits);
+    run(&mut cache, &two);
+    assert_eq!(4, cache.stats().misses);
+
+    // a model too big to fit is still compiled, but not kept
+    let mut cache = CompileCache::new(0);
+    assert_eq!(1.0, run(&mut cache, &one));
+    assert!(cache.is_empty());
+    assert_eq!(0, cache.bytes());
+
+    assert!(cache.vm(&one, "nope").is_err());
+}
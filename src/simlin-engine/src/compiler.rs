@@ -4,6 +4,7 @@
 
 use std::borrow::BorrowMut;
 use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ops::Range;
 use std::rc::Rc;
 
 use float_cmp::approx_eq;
@@ -16,15 +17,23 @@ use crate::bytecode::{
 use crate::common::{quoteize, ErrorCode, ErrorKind, Ident, Result};
 use crate::datamodel::{self, Dimension};
 use crate::interpreter::UnaryOp;
+use crate::math;
 use crate::model::{enumerate_modules, ModelStage1};
 use crate::project::Project;
+use crate::smoothing::Smoothing;
 use crate::variable::Variable;
 use crate::vm::{
-    is_truthy, pulse, ramp, step, CompiledSimulation, Results, Specs, StepPart, SubscriptIterator,
-    DT_OFF, FINAL_TIME_OFF, IMPLICIT_VAR_COUNT, INITIAL_TIME_OFF, TIME_OFF,
+    is_truthy, pulse, ramp, step, white_noise, CompiledSimulation, Integrator, Results, Specs,
+    StepPart, StockSlot, SubscriptIterator, DT_OFF, FINAL_TIME_OFF, IMPLICIT_VAR_COUNT,
+    INITIAL_TIME_OFF, TIME_OFF,
 };
 use crate::{sim_err, Error};
 
+// apply-to-all variables with at least this many elements have them
+// evaluated in parallel, when built with the `parallel` feature.
+// Smaller arrays aren't worth the cost of handing work to threads.
+pub(crate) const PARALLEL_MIN_ELEMENTS: usize = 4096;
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Table {
     pub data: Vec<(f64, f64)>,
@@ -102,6 +111,7 @@ impl Expr {
                     | BuiltinFn::StartTime
                     | BuiltinFn::FinalTime => builtin,
                     BuiltinFn::IsModuleInput(id, _loc) => BuiltinFn::IsModuleInput(id, loc),
+                    BuiltinFn::Transpose(id, _loc) => BuiltinFn::Transpose(id, loc),
                     BuiltinFn::Lookup(id, a, _loc) => {
                         BuiltinFn::Lookup(id, Box::new(a.strip_loc()), loc)
                     }
@@ -120,6 +130,7 @@ impl Expr {
                     BuiltinFn::Sin(a) => BuiltinFn::Sin(Box::new(a.strip_loc())),
                     BuiltinFn::Sqrt(a) => BuiltinFn::Sqrt(Box::new(a.strip_loc())),
                     BuiltinFn::Tan(a) => BuiltinFn::Tan(Box::new(a.strip_loc())),
+                    BuiltinFn::WhiteNoise(a) => BuiltinFn::WhiteNoise(Box::new(a.strip_loc())),
                     BuiltinFn::Max(a, b) => {
                         BuiltinFn::Max(Box::new(a.strip_loc()), Box::new(b.strip_loc()))
                     }
@@ -218,28 +229,62 @@ impl<'a> Context<'a> {
             return sim_err!(MismatchedDimensions, ident.to_owned());
         }
 
-        // goal: if this is a valid equation, dims will be a subset of active_dims (order preserving)
+        // goal: if this is a valid equation, dims will be a subset of active_dims.
+        // dimensions are matched by name rather than position, so a variable
+        // defined over `[b, a]` is correctly aligned when referenced from an
+        // equation over `[a, b]`.
 
         let mut subscripts: Vec<&str> = Vec::with_capacity(dims.len());
+        let mut used = vec![false; active_dims.len()];
 
-        let mut active_off = 0;
         for dim in dims.iter() {
-            while active_off < active_dims.len() {
-                let off = active_off;
-                active_off += 1;
-                let candidate = &active_dims[off];
-                if candidate.name() == dim.name() {
+            let off = active_dims
+                .iter()
+                .enumerate()
+                .position(|(i, candidate)| !used[i] && candidate.name() == dim.name());
+            match off {
+                Some(off) => {
+                    used[off] = true;
                     subscripts.push(active_subscripts[off]);
-                    break;
+                }
+                None => {
+                    return sim_err!(MismatchedDimensions, ident.to_owned());
                 }
             }
         }
 
-        if subscripts.len() != dims.len() {
+        Ok(subscripts)
+    }
+
+    /// get_transposed_offset returns the offset of the element of `ident`
+    /// whose subscripts are the active subscripts in reverse order.
+    fn get_transposed_offset(&self, ident: &str) -> Result<usize> {
+        let metadata = self.get_metadata(ident)?;
+        let dims = match metadata.var.get_dimensions() {
+            Some(dims) => dims,
+            None => return sim_err!(MismatchedDimensions, ident.to_owned()),
+        };
+        if self.active_dimension.is_none() {
+            return sim_err!(ArrayReferenceNeedsExplicitSubscripts, ident.to_owned());
+        }
+        let active_dims = self.active_dimension.as_ref().unwrap();
+        let active_subscripts = self.active_subscript.as_ref().unwrap();
+        if dims.len() != active_dims.len() {
             return sim_err!(MismatchedDimensions, ident.to_owned());
         }
 
-        Ok(subscripts)
+        let mut off = 0;
+        for (dim, (active_dim, subscript)) in dims
+            .iter()
+            .zip(active_dims.iter().rev().zip(active_subscripts.iter().rev()))
+        {
+            if dim.name() != active_dim.name() {
+                return sim_err!(MismatchedDimensions, ident.to_owned());
+            }
+            off = off * dim.len() + dim.get_offset(subscript).unwrap();
+        }
+
+        Ok(self.get_base_offset(ident)? + off)
     }
 
     fn get_implicit_subscript_off(&self, dims: &[Dimension], ident: &str) -> Result<usize> {
@@ -304,6 +349,89 @@ impl<'a> Context<'a> {
         }
     }
 
+    /// lower_index lowers a single subscript, converting explicit element
+    /// and dimension names to (1-based) constant offsets.
+    fn lower_index(&self, dim: &Dimension, arg: &ast::Expr) -> Result<Expr> {
+        if let ast::Expr::Var(ident, loc) = arg {
+            if let Some(subscript_off) = dim.get_offset(ident) {
+                return Ok(Expr::Const((subscript_off + 1) as f64, *loc));
+            } else if let Some(subscript_off) = self.get_dimension_name_subscript(ident) {
+                // some modelers do `Variable[SubscriptName]` in their A2A equations
+                return Ok(Expr::Const((subscript_off + 1) as f64, *loc));
+            }
+        }
+        self.lower(arg)
+    }
+
+    /// lower_range_bound resolves one end of a subscript range like
+    /// `a[boston:chicago]` to a constant (1-based) offset.
+    fn lower_range_bound(&self, id: &str, dim: &Dimension, arg: &ast::Expr) -> Result<usize> {
+        match self.lower_index(dim, arg)? {
+            Expr::Const(n, _) if n >= 1.0 && n as usize <= dim.len() => Ok(n as usize),
+            _ => sim_err!(TodoRange, id.to_owned()),
+        }
+    }
+
+    /// lower_array_selection expands a subscript containing wildcards or
+    /// ranges (e.g. `mean(a[*, boston:chicago])`) into the list of
+    /// individual elements it selects.
+    fn lower_array_selection(&self, id: &str, args: &[IndexExpr], loc: Loc) -> Result<Vec<Expr>> {
+        let off = self.get_base_offset(id)?;
+        let metadata = self.get_metadata(id)?;
+        let dims = match metadata.var.get_dimensions() {
+            Some(dims) => dims,
+            None => return sim_err!(MismatchedDimensions, id.to_owned()),
+        };
+        if args.len() != dims.len() {
+            return sim_err!(MismatchedDimensions, id.to_owned());
+        }
+
+        let mut selections: Vec<Vec<Expr>> = Vec::with_capacity(dims.len());
+        for (arg, dim) in args.iter().zip(dims.iter()) {
+            let all = || {
+                (1..=dim.len())
+                    .map(|i| Expr::Const(i as f64, loc))
+                    .collect()
+            };
+            let selection = match arg {
+                IndexExpr::Wildcard(_) => all(),
+                IndexExpr::StarRange(dim_name, _) => {
+                    if dim_name != dim.name() {
+                        return sim_err!(MismatchedDimensions, id.to_owned());
+                    }
+                    all()
+                }
+                IndexExpr::Range(l, r, _) => {
+                    let l = self.lower_range_bound(id, dim, l)?;
+                    let r = self.lower_range_bound(id, dim, r)?;
+                    (l..=r).map(|i| Expr::Const(i as f64, loc)).collect()
+                }
+                IndexExpr::Expr(arg) => vec![self.lower_index(dim, arg)?],
+            };
+            selections.push(selection);
+        }
+
+        let bounds: Vec<usize> = dims.iter().map(|dim| dim.len()).collect();
+        let mut exprs: Vec<Vec<Expr>> = vec![vec![]];
+        for selection in selections.into_iter() {
+            exprs = exprs
+                .into_iter()
+                .flat_map(|prefix| {
+                    selection.iter().map(move |index| {
+                        let mut indices = prefix.clone();
+                        indices.push(index.clone());
+                        indices
+                    })
+                })
+                .collect();
+        }
+
+        Ok(exprs
+            .into_iter()
+            .map(|indices| Expr::Subscript(off, indices, bounds.clone(), loc))
+            .collect())
+    }
+
     fn lower(&self, expr: &ast::Expr) -> Result<Expr> {
         let expr = match expr {
             ast::Expr::Const(_, n, loc) => Expr::Const(*n, *loc),
@@ -339,17 +467,31 @@ impl<'a> Context<'a> {
                     BFn::Inf => BuiltinFn::Inf,
                     BFn::Int(a) => BuiltinFn::Int(Box::new(self.lower(a)?)),
                     BFn::IsModuleInput(id, loc) => BuiltinFn::IsModuleInput(id.clone(), *loc),
+                    BFn::Transpose(id, _) => {
+                        // transposed references are resolved to a specific
+                        // element of the array at compile time
+                        return Ok(Expr::Var(self.get_transposed_offset(id)?, *loc));
+                    }
                     BFn::Ln(a) => BuiltinFn::Ln(Box::new(self.lower(a)?)),
                     BFn::Log10(a) => BuiltinFn::Log10(Box::new(self.lower(a)?)),
                     BFn::Max(a, b) => {
                         BuiltinFn::Max(Box::new(self.lower(a)?), Box::new(self.lower(b)?))
                     }
                     BFn::Mean(args) => {
-                        let args = args
-                            .iter()
-                            .map(|arg| self.lower(arg))
-                            .collect::<Result<Vec<Expr>>>();
-                        BuiltinFn::Mean(args?)
+                        let mut lowered = Vec::with_capacity(args.len());
+                        for arg in args.iter() {
+                            match arg {
+                                ast::Expr::Subscript(id, indices, loc)
+                                    if indices
+                                        .iter()
+                                        .any(|index| !matches!(index, IndexExpr::Expr(_))) =>
+                                {
+                                    lowered.extend(self.lower_array_selection(id, indices, *loc)?);
+                                }
+                                _ => lowered.push(self.lower(arg)?),
+                            }
+                        }
+                        BuiltinFn::Mean(lowered)
                     }
                     BFn::Min(a, b) => {
                         BuiltinFn::Min(Box::new(self.lower(a)?), Box::new(self.lower(b)?))
@@ -382,6 +524,7 @@ impl<'a> Context<'a> {
                         BuiltinFn::Step(Box::new(self.lower(a)?), Box::new(self.lower(b)?))
                     }
                     BFn::Tan(a) => BuiltinFn::Tan(Box::new(self.lower(a)?)),
+                    BFn::WhiteNoise(a) => BuiltinFn::WhiteNoise(Box::new(self.lower(a)?)),
                     BFn::Time => BuiltinFn::Time,
                     BFn::TimeStep => BuiltinFn::TimeStep,
                     BFn::StartTime => BuiltinFn::StartTime,
@@ -398,33 +541,31 @@ impl<'a> Context<'a> {
                 }
                 let args: Result<Vec<_>> = args
                     .iter()
-                    .enumerate()
-                    .map(|(i, arg)| {
-                        match arg {
-                            IndexExpr::Wildcard(_loc) => sim_err!(TodoWildcard, id.clone()),
-                            IndexExpr::StarRange(_id, _loc) => sim_err!(TodoStarRange, id.clone()),
-                            IndexExpr::Range(_l, _r, _loc) => sim_err!(TodoRange, id.clone()),
-                            IndexExpr::Expr(arg) => {
-                                let expr = if let ast::Expr::Var(ident, loc) = arg {
-                                    let dim = &dims[i];
-                                    // we need to check to make sure that any explicit subscript names are
-                                    // converted to offsets here and not passed to self.lower
-                                    if let Some(subscript_off) = dim.get_offset(ident) {
-                                        Expr::Const((subscript_off + 1) as f64, *loc)
-                                    } else if let Some(subscript_off) =
-                                        self.get_dimension_name_subscript(ident)
-                                    {
-                                        // some modelers do `Variable[SubscriptName]` in their A2A equations
-                                        Expr::Const((subscript_off + 1) as f64, *loc)
-                                    } else {
-                                        self.lower(arg)?
-                                    }
-                                } else {
-                                    self.lower(arg)?
-                                };
-                                Ok(expr)
+                    .zip(dims.iter())
+                    .map(|(arg, dim)| match arg {
+                        IndexExpr::Wildcard(_loc) => {
+                            // in an A2A equation `a[*]` refers to the element
+                            // of the dimension currently being evaluated
+                            match self.get_dimension_name_subscript(dim.name()) {
+                                Some(subscript_off) => {
+                                    Ok(Expr::Const((subscript_off + 1) as f64, *loc))
+                                }
+                                None => sim_err!(TodoWildcard, id.clone()),
                             }
                         }
+                        IndexExpr::StarRange(dim_name, _loc) if dim_name == dim.name() => {
+                            // like `a[*]`, `a[*:dim]` in an A2A equation is
+                            // the element currently being evaluated
+                            match self.get_dimension_name_subscript(dim_name) {
+                                Some(subscript_off) => {
+                                    Ok(Expr::Const((subscript_off + 1) as f64, *loc))
+                                }
+                                None => sim_err!(TodoStarRange, id.clone()),
+                            }
+                        }
+                        IndexExpr::StarRange(_, _) => sim_err!(MismatchedDimensions, id.clone()),
+                        IndexExpr::Range(_l, _r, _loc) => sim_err!(TodoRange, id.clone()),
+                        IndexExpr::Expr(arg) => self.lower_index(dim, arg),
                     })
                     .collect();
                 let bounds = dims.iter().map(|dim| dim.len()).collect();
@@ -939,18 +1080,19 @@ impl Var {
                     if ast.is_none() {
                         return sim_err!(EmptyEquation, var.ident().to_string());
                     }
+                    // every element of an arrayed variable shares the
+                    // variable's single graphical function
+                    let lookup = |expr: Expr| {
+                        if table.is_some() {
+                            let loc = expr.get_loc();
+                            Expr::App(BuiltinFn::Lookup(ident.clone(), Box::new(expr), loc), loc)
+                        } else {
+                            expr
+                        }
+                    };
                     match ast.as_ref().unwrap() {
                         Ast::Scalar(ast) => {
-                            let expr = ctx.lower(ast)?;
-                            let expr = if table.is_some() {
-                                let loc = expr.get_loc();
-                                Expr::App(
-                                    BuiltinFn::Lookup(ident.clone(), Box::new(expr), loc),
-                                    loc,
-                                )
-                            } else {
-                                expr
-                            };
+                            let expr = lookup(ctx.lower(ast)?);
                             vec![Expr::AssignCurr(off, Box::new(expr))]
                         }
                         Ast::ApplyToAll(dims, ast) => {
@@ -961,7 +1103,7 @@ impl Var {
                                     ctx.active_dimension = Some(dims.clone());
                                     ctx.active_subscript = Some(subscripts);
                                     ctx.lower(ast)
-                                        .map(|ast| Expr::AssignCurr(off + i, Box::new(ast)))
+                                        .map(|ast| Expr::AssignCurr(off + i, Box::new(lookup(ast))))
                                 })
                                 .collect();
                             exprs?
@@ -976,7 +1118,7 @@ impl Var {
                                     ctx.active_dimension = Some(dims.clone());
                                     ctx.active_subscript = Some(subscripts);
                                     ctx.lower(ast)
-                                        .map(|ast| Expr::AssignCurr(off + i, Box::new(ast)))
+                                        .map(|ast| Expr::AssignCurr(off + i, Box::new(lookup(ast))))
                                 })
                                 .collect();
                             exprs?
@@ -992,6 +1134,16 @@ impl Var {
     }
 }
 
+/// Runlists is the order in which the variables of a model are evaluated,
+/// once at the start of the simulation (initials) and then every dt
+/// (flows and auxiliaries, followed by stocks).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Runlists {
+    pub initials: Vec<Ident>,
+    pub flows: Vec<Ident>,
+    pub stocks: Vec<Ident>,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Module {
     pub(crate) ident: Ident,
@@ -1000,9 +1152,17 @@ pub struct Module {
     pub(crate) runlist_initials: Vec<Expr>,
     pub(crate) runlist_flows: Vec<Expr>,
     pub(crate) runlist_stocks: Vec<Expr>,
+    // the ranges of runlist_flows assigning the elements of large
+    // apply-to-all variables, which can be evaluated in parallel
+    parallel_flows: Vec<Range<usize>>,
+    // the stocks updated by runlist_stocks, not including those in
+    // submodules
+    stocks: Vec<StockSlot>,
     pub(crate) offsets: HashMap<Ident, HashMap<Ident, (usize, usize)>>,
-    pub(crate) runlist_order: Vec<Ident>,
+    pub(crate) runlists: Runlists,
     tables: HashMap<Ident, Table>,
+    // true for stdlib models, like the ones behind SMTH1 and DELAY3
+    implicit: bool,
 }
 
 // calculate a mapping of module variable name -> module model name
@@ -1197,7 +1357,7 @@ fn calc_flattened_offsets(project: &Project, model_name: &str) -> HashMap<Ident,
             for sub_name in sub_var_names {
                 let (sub_off, sub_size) = sub_offsets[sub_name];
                 offsets.insert(
-                    format!("{}.{}", quoteize(ident), quoteize(sub_name)),
+                    format!("{}.{}", quoteize(ident), sub_name),
                     (i + sub_off, sub_size),
                 );
             }
@@ -1232,18 +1392,25 @@ fn calc_flattened_order(sim: &Simulation, model_name: &str) -> Vec<Ident> {
 
     let module = &sim.modules[model_name];
 
-    let mut offsets: Vec<Ident> = Vec::with_capacity(module.runlist_order.len() + 1);
+    let runlist_order = module
+        .runlists
+        .flows
+        .iter()
+        .chain(module.runlists.stocks.iter());
+
+    let mut offsets: Vec<Ident> =
+        Vec::with_capacity(module.runlists.flows.len() + module.runlists.stocks.len() + 1);
 
     if is_root {
         offsets.push("time".to_owned());
     }
 
-    for ident in module.runlist_order.iter() {
+    for ident in runlist_order {
         // FIXME: this isnt' quite right (assumes no regular var has same name as module)
         if sim.modules.contains_key(ident) {
             let sub_var_names = calc_flattened_order(sim, ident);
             for sub_name in sub_var_names.iter() {
-                offsets.push(format!("{}.{}", quoteize(ident), quoteize(sub_name)));
+                offsets.push(format!("{}.{}", quoteize(ident), sub_name));
             }
         } else {
             offsets.push(quoteize(ident));
@@ -1332,9 +1499,44 @@ impl Module {
             .map(|ident| build_var(ident, false))
             .collect::<Result<Vec<Var>>>()?;
 
-        let mut runlist_order = Vec::with_capacity(runlist_flows.len() + runlist_stocks.len());
-        runlist_order.extend(runlist_flows.iter().map(|v| v.ident.clone()));
-        runlist_order.extend(runlist_stocks.iter().map(|v| v.ident.clone()));
+        let runlists = Runlists {
+            initials: runlist_initials.iter().map(|v| v.ident.clone()).collect(),
+            flows: runlist_flows.iter().map(|v| v.ident.clone()).collect(),
+            stocks: runlist_stocks.iter().map(|v| v.ident.clone()).collect(),
+        };
+
+        let stocks = runlist_stocks
+            .iter()
+            .filter_map(|v| match &model.variables[&v.ident] {
+                Variable::Stock { force_euler, .. } => {
+                    let meta = &metadata[model_name][&v.ident];
+                    let force_euler = *force_euler;
+                    Some(
+                        (meta.offset..meta.offset + meta.size)
+                            .map(move |off| StockSlot { off, force_euler }),
+                    )
+                }
+                _ => None,
+            })
+            .flatten()
+            .collect();
+
+        // the elements of an apply-to-all variable can't depend on each
+        // other (that would be a circular dependency), so they can be
+        // evaluated in any order
+        let mut parallel_flows = vec![];
+        let mut start = 0;
+        for var in runlist_flows.iter() {
+            let end = start + var.ast.len();
+            let variable = &model.variables[&var.ident];
+            if var.ast.len() >= PARALLEL_MIN_ELEMENTS
+                && matches!(variable, Variable::Var { .. })
+                && matches!(variable.ast(), Some(Ast::ApplyToAll(_, _)))
+            {
+                parallel_flows.push(start..end);
+            }
+            start = end;
+        }
 
         // flatten out the variables so that we're just dealing with lists of expressions
         let runlist_initials = runlist_initials.into_iter().flat_map(|v| v.ast).collect();
@@ -1372,9 +1574,12 @@ impl Module {
             runlist_initials,
             runlist_flows,
             runlist_stocks,
+            parallel_flows,
+            stocks,
             offsets,
-            runlist_order,
+            runlists,
             tables,
+            implicit: model.implicit,
         })
     }
 
@@ -1387,6 +1592,9 @@ struct Compiler<'module> {
     module: &'module Module,
     module_decls: Vec<ModuleDeclaration>,
     graphical_functions: Vec<Vec<(f64, f64)>>,
+    // lookups of the same table (like from each element of an arrayed
+    // variable) share a single graphical function
+    graphical_function_ids: HashMap<Ident, GraphicalFunctionId>,
     curr_code: ByteCodeBuilder,
 }
 
@@ -1396,12 +1604,31 @@ impl<'module> Compiler<'module> {
             module,
             module_decls: vec![],
             graphical_functions: vec![],
+            graphical_function_ids: HashMap::new(),
             curr_code: ByteCodeBuilder::default(),
         }
     }
 
-    fn walk(&mut self, exprs: &[Expr]) -> Result<ByteCode> {
-        for expr in exprs.iter() {
+    fn walk(&mut self, exprs: &[Expr], parallel: &[Range<usize>]) -> Result<ByteCode> {
+        let mut i = 0;
+        for range in parallel.iter() {
+            for expr in exprs[i..range.start].iter() {
+                self.walk_expr(expr)?;
+            }
+            let mut starts = Vec::with_capacity(range.len());
+            for expr in exprs[range.clone()].iter() {
+                starts.push(self.curr_code.code_len());
+                self.walk_expr(expr)?;
+            }
+            let off = match exprs[range.start] {
+                Expr::AssignCurr(off, _) => off as VariableOffset,
+                _ => unreachable!(),
+            };
+            let id = self.curr_code.push_block(off, &starts);
+            self.push(Opcode::EvalParallel { id });
+            i = range.end;
+        }
+        for expr in exprs[i..].iter() {
             self.walk_expr(expr)?;
         }
         self.push(Opcode::Ret);
@@ -1445,9 +1672,16 @@ impl<'module> Compiler<'module> {
             Expr::App(builtin, _) => {
                 // lookups are special
                 if let BuiltinFn::Lookup(ident, index, _loc) = builtin {
-                    let table = &self.module.tables[ident];
-                    self.graphical_functions.push(table.data.clone());
-                    let gf = (self.graphical_functions.len() - 1) as GraphicalFunctionId;
+                    let gf = match self.graphical_function_ids.get(ident) {
+                        Some(gf) => *gf,
+                        None => {
+                            let table = &self.module.tables[ident];
+                            self.graphical_functions.push(table.data.clone());
+                            let gf = (self.graphical_functions.len() - 1) as GraphicalFunctionId;
+                            self.graphical_function_ids.insert(ident.clone(), gf);
+                            gf
+                        }
+                    };
                     self.walk_expr(index)?.unwrap();
                     self.push(Opcode::Lookup { gf });
                     return Ok(Some(()));
@@ -1479,7 +1713,9 @@ impl<'module> Compiler<'module> {
                         self.push(Opcode::LoadGlobalVar { off });
                         return Ok(Some(()));
                     }
-                    BuiltinFn::Lookup(_, _, _) | BuiltinFn::IsModuleInput(_, _) => unreachable!(),
+                    BuiltinFn::Lookup(_, _, _)
+                    | BuiltinFn::IsModuleInput(_, _)
+                    | BuiltinFn::Transpose(_, _) => unreachable!(),
                     BuiltinFn::Inf | BuiltinFn::Pi => {
                         let lit = match builtin {
                             BuiltinFn::Inf => f64::INFINITY,
@@ -1501,7 +1737,8 @@ impl<'module> Compiler<'module> {
                     | BuiltinFn::Log10(a)
                     | BuiltinFn::Sin(a)
                     | BuiltinFn::Sqrt(a)
-                    | BuiltinFn::Tan(a) => {
+                    | BuiltinFn::Tan(a)
+                    | BuiltinFn::WhiteNoise(a) => {
                         self.walk_expr(a)?.unwrap();
                         let id = self.curr_code.intern_literal(0.0);
                         self.push(Opcode::LoadConstant { id });
@@ -1582,6 +1819,8 @@ impl<'module> Compiler<'module> {
                     BuiltinFn::Sqrt(_) => BuiltinId::Sqrt,
                     BuiltinFn::Step(_, _) => BuiltinId::Step,
                     BuiltinFn::Tan(_) => BuiltinId::Tan,
+                    BuiltinFn::Transpose(_, _) => unreachable!(),
+                    BuiltinFn::WhiteNoise(_) => BuiltinId::WhiteNoise,
                     // handled above; we exit early
                     BuiltinFn::Time
                     | BuiltinFn::TimeStep
@@ -1648,11 +1887,15 @@ impl<'module> Compiler<'module> {
                 Some(())
             }
             Expr::If(cond, t, f, _) => {
+                // only the branch that is taken is evaluated, so the
+                // other can safely divide by zero or index out of bounds
+                self.walk_expr(cond)?.unwrap();
+                let to_false = self.curr_code.push_jump(Opcode::JumpIfFalse { skip: 0 });
                 self.walk_expr(t)?.unwrap();
+                let to_end = self.curr_code.push_jump(Opcode::Jump { skip: 0 });
+                self.curr_code.patch_jump(to_false);
                 self.walk_expr(f)?.unwrap();
-                self.walk_expr(cond)?.unwrap();
-                self.push(Opcode::SetCond {});
-                self.push(Opcode::If {});
+                self.curr_code.patch_jump(to_end);
                 Some(())
             }
             Expr::AssignCurr(off, rhs) => {
@@ -1678,13 +1921,20 @@ impl<'module> Compiler<'module> {
     }
 
     fn compile(mut self) -> Result<CompiledModule> {
-        let compiled_initials = Rc::new(self.walk(&self.module.runlist_initials)?);
-        let compiled_flows = Rc::new(self.walk(&self.module.runlist_flows)?);
-        let compiled_stocks = Rc::new(self.walk(&self.module.runlist_stocks)?);
+        let parallel_flows: &[Range<usize>] =
+            if cfg!(all(feature = "parallel", not(target_arch = "wasm32"))) {
+                &self.module.parallel_flows
+            } else {
+                &[]
+            };
+        let compiled_initials = Rc::new(self.walk(&self.module.runlist_initials, &[])?);
+        let compiled_flows = Rc::new(self.walk(&self.module.runlist_flows, parallel_flows)?);
+        let compiled_stocks = Rc::new(self.walk(&self.module.runlist_stocks, &[])?);
 
         Ok(CompiledModule {
             ident: self.module.ident.clone(),
             n_slots: self.module.n_slots,
+            implicit: self.module.implicit,
             context: Rc::new(ByteCodeContext {
                 graphical_functions: self.graphical_functions,
                 modules: self.module_decls,
@@ -1783,7 +2033,7 @@ impl<'a> ModuleEvaluator<'a> {
                 match op {
                     BinaryOp::Add => l + r,
                     BinaryOp::Sub => l - r,
-                    BinaryOp::Exp => l.powf(r),
+                    BinaryOp::Exp => math::pow(l, r),
                     BinaryOp::Mul => l * r,
                     BinaryOp::Div => l / r,
                     BinaryOp::Mod => l.rem_euclid(r),
@@ -1813,21 +2063,22 @@ impl<'a> ModuleEvaluator<'a> {
                         self.curr[off]
                     }
                     BuiltinFn::Abs(a) => self.eval(a).abs(),
-                    BuiltinFn::Cos(a) => self.eval(a).cos(),
-                    BuiltinFn::Sin(a) => self.eval(a).sin(),
-                    BuiltinFn::Tan(a) => self.eval(a).tan(),
-                    BuiltinFn::Arccos(a) => self.eval(a).acos(),
-                    BuiltinFn::Arcsin(a) => self.eval(a).asin(),
-                    BuiltinFn::Arctan(a) => self.eval(a).atan(),
-                    BuiltinFn::Exp(a) => self.eval(a).exp(),
+                    BuiltinFn::Cos(a) => math::cos(self.eval(a)),
+                    BuiltinFn::Sin(a) => math::sin(self.eval(a)),
+                    BuiltinFn::Tan(a) => math::tan(self.eval(a)),
+                    BuiltinFn::Transpose(_, _) => unreachable!(),
+                    BuiltinFn::Arccos(a) => math::acos(self.eval(a)),
+                    BuiltinFn::Arcsin(a) => math::asin(self.eval(a)),
+                    BuiltinFn::Arctan(a) => math::atan(self.eval(a)),
+                    BuiltinFn::Exp(a) => math::exp(self.eval(a)),
                     BuiltinFn::Inf => f64::INFINITY,
                     BuiltinFn::Pi => std::f64::consts::PI,
                     BuiltinFn::Int(a) => self.eval(a).floor(),
                     BuiltinFn::IsModuleInput(ident, _) => {
                         self.module.inputs.contains(ident) as i8 as f64
                     }
-                    BuiltinFn::Ln(a) => self.eval(a).ln(),
-                    BuiltinFn::Log10(a) => self.eval(a).log10(),
+                    BuiltinFn::Ln(a) => math::ln(self.eval(a)),
+                    BuiltinFn::Log10(a) => math::log10(self.eval(a)),
                     BuiltinFn::SafeDiv(a, b, default) => {
                         let a = self.eval(a);
                         let b = self.eval(b);
@@ -1841,6 +2092,7 @@ impl<'a> ModuleEvaluator<'a> {
                         }
                     }
                     BuiltinFn::Sqrt(a) => self.eval(a).sqrt(),
+                    BuiltinFn::WhiteNoise(a) => white_noise(self.eval(a), self.curr[TIME_OFF]),
                     BuiltinFn::Min(a, b) => {
                         let a = self.eval(a);
                         let b = self.eval(b);
@@ -2069,6 +2321,8 @@ pub fn pretty(expr: &Expr) -> String {
                 format!("step({}, {})", pretty(a), pretty(b))
             }
             BuiltinFn::Tan(l) => format!("tan({})", pretty(l)),
+            BuiltinFn::Transpose(ident, _loc) => format!("transpose({})", ident),
+            BuiltinFn::WhiteNoise(l) => format!("white_noise({})", pretty(l)),
         },
         Expr::EvalModule(module, model_name, args) => {
             let args: Vec<_> = args.iter().map(pretty).collect();
@@ -2121,6 +2375,7 @@ pub struct Simulation {
     specs: Specs,
     root: String,
     offsets: HashMap<Ident, usize>,
+    smoothing: Option<Smoothing>,
 }
 
 impl Simulation {
@@ -2132,6 +2387,10 @@ impl Simulation {
             );
         }
 
+        if let Some(err) = project.datamodel.sim_specs.check().into_iter().next() {
+            return Err(err);
+        }
+
         let modules = {
             let project_models: HashMap<_, _> = project
                 .models
@@ -2173,14 +2432,44 @@ impl Simulation {
             specs,
             root: main_model_name.to_string(),
             offsets,
+            smoothing: None,
         })
     }
 
+    /// set_event_times makes the simulation take partial time steps so
+    /// that it is evaluated exactly at each of `times`, even when they
+    /// fall between time steps.
+    pub fn set_event_times(&mut self, times: &[f64]) {
+        let mut times: Vec<f64> = times.iter().copied().filter(|t| t.is_finite()).collect();
+        times.sort_by(f64::total_cmp);
+        times.dedup();
+        self.specs.event_times = times;
+    }
+
+    /// set_smoothing makes `compile` replace discontinuous functions like
+    /// STEP and MAX with smooth approximations, or go back to the exact
+    /// functions when `smoothing` is None.  It doesn't affect
+    /// `run_to_end`.
+    pub fn set_smoothing(&mut self, smoothing: Option<Smoothing>) {
+        self.smoothing = smoothing;
+    }
+
     pub fn compile(&self) -> Result<CompiledSimulation> {
+        if let Some(smoothing) = self.smoothing {
+            if !smoothing.is_valid() {
+                return sim_err!(Generic, "smoothing widths must be positive".to_owned());
+            }
+        }
         let modules: Result<HashMap<String, CompiledModule>> = self
             .modules
             .iter()
-            .map(|(name, module)| module.compile().map(|module| (name.clone(), module)))
+            .map(|(name, module)| {
+                let compiled = match self.smoothing {
+                    Some(smoothing) => smoothing.module(module).compile(),
+                    None => module.compile(),
+                };
+                compiled.map(|module| (name.clone(), module))
+            })
             .collect();
 
         Ok(CompiledSimulation {
@@ -2188,13 +2477,42 @@ impl Simulation {
             specs: self.specs.clone(),
             root: self.root.clone(),
             offsets: self.offsets.clone(),
+            stocks: self.stocks(),
         })
     }
 
+    // stocks returns every stock slot in the simulation, so that the
+    // integrator can combine stock updates.
+    fn stocks(&self) -> Vec<StockSlot> {
+        let mut stocks = vec![];
+        self.module_stocks(&self.root, 0, &mut stocks);
+        stocks
+    }
+
+    fn module_stocks(&self, model_name: &str, module_off: usize, stocks: &mut Vec<StockSlot>) {
+        let module = &self.modules[model_name];
+        stocks.extend(module.stocks.iter().map(|slot| StockSlot {
+            off: module_off + slot.off,
+            ..*slot
+        }));
+        for expr in module.runlist_stocks.iter() {
+            if let Expr::EvalModule(ident, model_name, _) = expr {
+                let off = module.offsets[&module.ident][ident].0;
+                self.module_stocks(model_name, module_off + off, stocks);
+            }
+        }
+    }
+
     pub fn runlist_order(&self) -> Vec<Ident> {
         calc_flattened_order(self, "main")
     }
 
+    /// runlists returns the evaluation order chosen for the given model,
+    /// or None if the model isn't part of this simulation.
+    pub fn runlists(&self, model_name: &str) -> Option<&Runlists> {
+        self.modules.get(model_name).map(|module| &module.runlists)
+    }
+
     pub fn debug_print_runlists(&self, _model_name: &str) {
         let mut model_names: Vec<_> = self.modules.keys().collect();
         model_names.sort_unstable();
@@ -2267,13 +2585,8 @@ impl Simulation {
         if spec.stop < spec.start {
             return sim_err!(BadSimSpecs, "".to_string());
         }
-        let save_step = if spec.save_step > spec.dt {
-            spec.save_step
-        } else {
-            spec.dt
-        };
-        let n_chunks: usize = ((spec.stop - spec.start) / save_step + 1.0) as usize;
-        let save_every = std::cmp::max(1, (spec.save_step / spec.dt + 0.5).floor() as usize);
+        let n_chunks = spec.n_chunks();
+        let save_every = spec.save_every();
 
         let dt = spec.dt;
         let stop = spec.stop;
@@ -2281,6 +2594,7 @@ impl Simulation {
         let n_slots = self.n_slots(&self.root);
 
         let module = &self.modules[&self.root];
+        let stocks = self.stocks();
 
         let slab: Vec<f64> = vec![0.0; n_slots * (n_chunks + 1)];
         let mut boxed_slab = slab.into_boxed_slice();
@@ -2300,10 +2614,18 @@ impl Simulation {
             self.calc(StepPart::Initials, module, 0, module_inputs, curr, next);
             let mut is_initial_timestep = true;
             let mut step = 0;
+            let mut n = 0;
+            let mut integrator = Integrator::new(spec, &stocks);
+            let mut eval = |part: StepPart, curr: &mut [f64], next: &mut [f64]| {
+                self.calc(part, module, 0, module_inputs, curr, next);
+            };
             loop {
-                self.calc(StepPart::Flows, module, 0, module_inputs, curr, next);
-                self.calc(StepPart::Stocks, module, 0, module_inputs, curr, next);
-                next[TIME_OFF] = curr[TIME_OFF] + dt;
+                eval(StepPart::Flows, curr, next);
+                let end_time = spec.time(n + 1);
+                let events = spec.events_between(curr[TIME_OFF], end_time);
+                integrator.advance(curr, next, events, end_time, &mut eval);
+                n += 1;
+                next[TIME_OFF] = spec.time(n);
                 next[DT_OFF] = dt;
                 curr[INITIAL_TIME_OFF] = self.specs.start;
                 curr[FINAL_TIME_OFF] = self.specs.stop;
@@ -2344,12 +2666,16 @@ fn test_arrays() {
         Project {
             name: "arrays".to_owned(),
             source: None,
+            constants: vec![],
+            tests: vec![],
+            import_report: vec![],
             sim_specs: SimSpecs {
                 start: 0.0,
                 stop: 12.0,
                 dt: Dt::Dt(0.25),
                 save_step: None,
                 sim_method: SimMethod::Euler,
+                tolerances: Default::default(),
                 time_units: Some("time".to_owned()),
             },
             dimensions: vec![Dimension::Named(
@@ -2374,6 +2700,7 @@ fn test_arrays() {
                         units: None,
                         gf: None,
                         can_be_module_input: false,
+                        supplementary: false,
                         visibility: Visibility::Private,
                     }),
                     Variable::Aux(Aux {
@@ -2383,6 +2710,7 @@ fn test_arrays() {
                         units: None,
                         gf: None,
                         can_be_module_input: false,
+                        supplementary: false,
                         visibility: Visibility::Private,
                     }),
                     Variable::Aux(Aux {
@@ -2396,6 +2724,7 @@ fn test_arrays() {
                         units: None,
                         gf: None,
                         can_be_module_input: false,
+                        supplementary: false,
                         visibility: Visibility::Private,
                     }),
                     Variable::Aux(Aux {
@@ -2405,10 +2734,14 @@ fn test_arrays() {
                         units: None,
                         gf: None,
                         can_be_module_input: false,
+                        supplementary: false,
                         visibility: Visibility::Private,
                     }),
                 ],
                 views: vec![],
+                graphs: vec![],
+                groups: vec![],
+                variable_index: Default::default(),
             }],
         }
     };
@@ -2605,3 +2938,627 @@ fn test_arrays() {
 fn nan_is_approx_eq() {
     assert!(approx_eq!(f64, f64::NAN, f64::NAN));
 }
+
+#[test]
+fn test_subscript_ranges_and_wildcards() {
+    use crate::common::EquationError;
+    use crate::testutils::{sim_specs_with_units, x_a2a, x_arrayed, x_aux, x_model, x_project};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_arrayed(
+                    "constants",
+                    &["letters"],
+                    &[("a", "9"), ("b", "7"), ("c", "5")],
+                    None,
+                ),
+                x_a2a("doubled", &["letters"], "constants[*] * 2", None),
+                x_aux("all", "mean(constants[*])", None),
+                x_aux("some", "mean(constants[a:b])", None),
+                x_aux("qualified", "constants[letters.c]", None),
+            ],
+        )],
+    );
+    project.sim_specs.dt = datamodel::Dt::Dt(1.0);
+    project.dimensions = vec![Dimension::Named(
+        "letters".to_owned(),
+        vec!["a".to_owned(), "b".to_owned(), "c".to_owned()],
+    )];
+
+    let parsed_project = Rc::new(Project::from(project.clone()));
+    assert!(parsed_project.models["main"]
+        .get_variable_errors()
+        .is_empty());
+
+    let sim = Simulation::new(&parsed_project, "main").unwrap();
+    let results1 = sim.run_to_end().unwrap();
+    let mut vm = crate::vm::Vm::new(sim.compile().unwrap()).unwrap();
+    vm.run_to_end().unwrap();
+    let results2 = vm.into_results();
+
+    for results in [results1, results2].iter() {
+        let step = results.iter_rows().next().unwrap();
+        assert_eq!(7.0, step[results.offsets["all"]]);
+        assert_eq!(8.0, step[results.offsets["some"]]);
+        assert_eq!(5.0, step[results.offsets["qualified"]]);
+        assert_eq!(14.0, step[results.offsets["doubled[b]"]]);
+    }
+
+    // references to elements that aren't part of the dimension are
+    // reported against the offending subscript
+    let cases: &[(&str, ErrorCode, u16, u16)] = &[
+        ("constants[d]", ErrorCode::UnknownSubscript, 10, 11),
+        ("constants[4]", ErrorCode::UnknownSubscript, 10, 11),
+        (
+            "mean(constants[a:boston])",
+            ErrorCode::UnknownSubscript,
+            17,
+            23,
+        ),
+        (
+            "mean(constants[*:cities])",
+            ErrorCode::BadDimensionName,
+            15,
+            23,
+        ),
+        ("constants[a, b]", ErrorCode::MismatchedDimensions, 0, 15),
+    ];
+    for (eqn, code, start, end) in cases.iter() {
+        let mut project = project.clone();
+        project.models[0].variables.push(x_aux("bad", eqn, None));
+        let parsed_project = Project::from(project);
+        let errors = parsed_project.models["main"].get_variable_errors();
+        assert_eq!(1, errors.len());
+        assert_eq!(
+            vec![EquationError {
+                start: *start,
+                end: *end,
+                code: *code,
+            }],
+            errors["bad"]
+        );
+    }
+}
+
+#[test]
+fn test_dimension_reordering() {
+    use crate::testutils::{sim_specs_with_units, x_a2a, x_arrayed, x_model, x_project};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_arrayed(
+                    "m",
+                    &["letters", "nums"],
+                    &[
+                        ("a,one", "1"),
+                        ("a,two", "2"),
+                        ("b,one", "3"),
+                        ("b,two", "4"),
+                    ],
+                    None,
+                ),
+                x_a2a("aligned", &["nums", "letters"], "m * 10", None),
+                x_a2a("transposed", &["nums", "letters"], "transpose(m)", None),
+            ],
+        )],
+    );
+    project.sim_specs.dt = datamodel::Dt::Dt(1.0);
+    project.dimensions = vec![
+        Dimension::Named("letters".to_owned(), vec!["a".to_owned(), "b".to_owned()]),
+        Dimension::Named("nums".to_owned(), vec!["one".to_owned(), "two".to_owned()]),
+    ];
+
+    let parsed_project = Rc::new(Project::from(project));
+    assert!(parsed_project.models["main"]
+        .get_variable_errors()
+        .is_empty());
+
+    let sim = Simulation::new(&parsed_project, "main").unwrap();
+    let results1 = sim.run_to_end().unwrap();
+    let mut vm = crate::vm::Vm::new(sim.compile().unwrap()).unwrap();
+    vm.run_to_end().unwrap();
+    let results2 = vm.into_results();
+
+    for results in [results1, results2].iter() {
+        let step = results.iter_rows().next().unwrap();
+        assert_eq!(20.0, step[results.offsets["aligned[two,a]"]]);
+        assert_eq!(30.0, step[results.offsets["aligned[one,b]"]]);
+        assert_eq!(2.0, step[results.offsets["transposed[two,a]"]]);
+        assert_eq!(3.0, step[results.offsets["transposed[one,b]"]]);
+    }
+}
+
+#[test]
+fn test_arrayed_builtins() {
+    use crate::testutils::{sim_specs_with_units, x_a2a, x_arrayed, x_aux, x_model, x_project};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_arrayed(
+                    "weight",
+                    &["letters"],
+                    &[("a", "1"), ("b", "2"), ("c", "3")],
+                    None,
+                ),
+                x_a2a("input", &["letters"], "weight * STEP(10, 1)", None),
+                x_a2a("smoothed", &["letters"], "SMTH1(input, 2) + 1", None),
+                x_a2a("delayed", &["letters"], "DELAYN(input, 2, 2)", None),
+                x_aux("smoothed_ref", "SMTH1(STEP(10, 1), 2) + 1", None),
+                x_aux("delayed_ref", "DELAYN(STEP(10, 1), 2, 2)", None),
+                x_arrayed("pair", &["size"], &[("1", "5"), ("2", "7")], None),
+                x_a2a("pair_doubled", &["size"], "pair[*:size] * 2", None),
+                x_aux("second", "pair[2]", None),
+            ],
+        )],
+    );
+    project.sim_specs.stop = 5.0;
+    project.sim_specs.dt = datamodel::Dt::Dt(0.5);
+    project.dimensions = vec![
+        Dimension::Named(
+            "letters".to_owned(),
+            vec!["a".to_owned(), "b".to_owned(), "c".to_owned()],
+        ),
+        Dimension::Indexed("size".to_owned(), 2),
+    ];
+
+    let parsed_project = Rc::new(Project::from(project));
+    assert!(parsed_project.models["main"]
+        .get_variable_errors()
+        .is_empty());
+
+    let sim = Simulation::new(&parsed_project, "main").unwrap();
+    let results1 = sim.run_to_end().unwrap();
+    let mut vm = crate::vm::Vm::new(sim.compile().unwrap()).unwrap();
+    vm.run_to_end().unwrap();
+    let results2 = vm.into_results();
+
+    for results in [results1, results2].iter() {
+        // each element is smoothed and delayed on its own
+        for step in results.iter_rows() {
+            let value = |ident: &str| step[results.offsets[ident]];
+            for (element, weight) in [("a", 1.0), ("b", 2.0), ("c", 3.0)] {
+                let smoothed = value(&format!("smoothed[{}]", element));
+                let expected = weight * (value("smoothed_ref") - 1.0) + 1.0;
+                assert!((smoothed - expected).abs() < 1e-9);
+                let delayed = value(&format!("delayed[{}]", element));
+                assert!((delayed - weight * value("delayed_ref")).abs() < 1e-9);
+            }
+        }
+        let last = results.iter_rows().last().unwrap();
+        assert!(last[results.offsets["delayed[c]"]] > 0.0);
+
+        let step = results.iter_rows().next().unwrap();
+        assert_eq!(10.0, step[results.offsets["pair_doubled[1]"]]);
+        assert_eq!(14.0, step[results.offsets["pair_doubled[2]"]]);
+        assert_eq!(7.0, step[results.offsets["second"]]);
+    }
+}
+
+#[test]
+fn test_project_constants() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_model, x_module, x_project};
+
+    let constant = |ident: &str, eqn: &str| match x_aux(ident, eqn, None) {
+        datamodel::Variable::Aux(aux) => aux,
+        _ => unreachable!(),
+    };
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[
+            x_model(
+                "main",
+                vec![
+                    x_aux("total", "scaled + sub.y", None),
+                    x_module("sub", &[], None),
+                ],
+            ),
+            x_model(
+                "sub",
+                vec![
+                    // shadows the project-level constant of the same name
+                    x_aux("scaled", "100", None),
+                    x_aux("y", "growth_rate + scaled", None),
+                ],
+            ),
+        ],
+    );
+    project.sim_specs.dt = datamodel::Dt::Dt(1.0);
+    project.constants = vec![
+        constant("growth_rate", "0.5"),
+        constant("scaled", "growth_rate * 2"),
+    ];
+
+    let parsed_project = Rc::new(Project::from(project));
+    for model in ["main", "sub"] {
+        assert!(parsed_project.models[model]
+            .get_variable_errors()
+            .is_empty());
+    }
+
+    let sim = Simulation::new(&parsed_project, "main").unwrap();
+    let results1 = sim.run_to_end().unwrap();
+    let mut vm = crate::vm::Vm::new(sim.compile().unwrap()).unwrap();
+    vm.run_to_end().unwrap();
+    let results2 = vm.into_results();
+
+    for results in [results1, results2].iter() {
+        let step = results.iter_rows().next().unwrap();
+        assert_eq!(1.0, step[results.offsets["scaled"]]);
+        assert_eq!(100.5, step[results.offsets["sub.y"]]);
+        assert_eq!(101.5, step[results.offsets["total"]]);
+    }
+}
+
+#[test]
+fn test_stubs() {
+    use crate::common::ErrorCode;
+    use crate::stubs::{Stub, Stubs};
+    use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_stock("s", "10", &["f"], &[], None),
+                x_flow("f", "rate * s", None),
+                x_aux("rate", "0.1", None),
+                x_aux("doubled", "f * 2", None),
+            ],
+        )],
+    );
+    project.sim_specs.dt = datamodel::Dt::Dt(1.0);
+    project.sim_specs.stop = 2.0;
+
+    let run = |stubs: &Stubs| {
+        let parsed_project = Project::from_with_stubs(project.clone(), stubs).unwrap();
+        assert_eq!(project, parsed_project.datamodel);
+        let sim = Simulation::new(&parsed_project, "main").unwrap();
+        let results = sim.run_to_end().unwrap();
+        let series = |ident: &str| {
+            let off = results.offsets[ident];
+            results
+                .iter_rows()
+                .map(|step| step[off])
+                .collect::<Vec<_>>()
+        };
+        (series("s"), series("f"), series("doubled"))
+    };
+
+    let mut stubs = Stubs::new();
+    stubs.stub("main", "F", Stub::Series(vec![(2.0, 3.0), (0.0, 1.0)]));
+    let (s, f, doubled) = run(&stubs);
+    assert_eq!(vec![10.0, 11.0, 13.0], s);
+    assert_eq!(vec![1.0, 2.0, 3.0], f);
+    assert_eq!(vec![2.0, 4.0, 6.0], doubled);
+
+    let mut stubs = Stubs::new();
+    stubs
+        .stub("main", "s", Stub::Constant(5.0))
+        .stub("main", "rate", Stub::Constant(0.2));
+    let (s, f, _) = run(&stubs);
+    assert_eq!(vec![5.0, 5.0, 5.0], s);
+    assert_eq!(vec![1.0, 1.0, 1.0], f);
+
+    let mut stubs = Stubs::new();
+    stubs.stub("main", "missing", Stub::Constant(1.0));
+    let err = Project::from_with_stubs(project.clone(), &stubs).unwrap_err();
+    assert_eq!(ErrorCode::DoesNotExist, err.code);
+}
+
+#[test]
+fn test_path_stubs() {
+    use crate::common::ErrorCode;
+    use crate::stubs::{Stub, Stubs};
+    use crate::testutils::{
+        sim_specs_with_units, x_aux, x_flow, x_model, x_module, x_project, x_stock,
+    };
+
+    let instance = |ident: &str| match x_module(ident, &[], None) {
+        datamodel::Variable::Module(module) => datamodel::Variable::Module(datamodel::Module {
+            model_name: "population".to_owned(),
+            ..module
+        }),
+        _ => unreachable!(),
+    };
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[
+            x_model("main", vec![instance("hares"), instance("lynxes")]),
+            x_model(
+                "population",
+                vec![
+                    x_stock("count", "init", &["births"], &[], None),
+                    x_flow("births", "count * 0.5", None),
+                    x_aux("init", "10", None),
+                ],
+            ),
+        ],
+    );
+    project.sim_specs.dt = datamodel::Dt::Dt(1.0);
+    project.sim_specs.stop = 1.0;
+
+    let run = |stubs: &Stubs| {
+        let parsed_project = Project::from_with_stubs(project.clone(), stubs).unwrap();
+        assert_eq!(project, parsed_project.datamodel);
+        let sim = Simulation::new(&parsed_project, "main").unwrap();
+        let results = sim.run_to_end().unwrap();
+        (
+            results.get_series("hares.count").unwrap(),
+            results.get_series("lynxes.count").unwrap(),
+        )
+    };
+
+    let mut stubs = Stubs::new();
+    stubs.stub_path("main", "Lynxes.init", Stub::Constant(2.0));
+    assert_eq!((vec![10.0, 15.0], vec![2.0, 3.0]), run(&stubs));
+
+    // stubbing the model affects every instance
+    let mut stubs = Stubs::new();
+    stubs
+        .stub("population", "init", Stub::Constant(4.0))
+        .stub_path("main", "hares.births", Stub::Constant(0.0));
+    assert_eq!((vec![4.0, 4.0], vec![4.0, 6.0]), run(&stubs));
+
+    let mut stubs = Stubs::new();
+    stubs.stub_path("main", "hares.deaths", Stub::Constant(0.0));
+    let err = Project::from_with_stubs(project.clone(), &stubs).unwrap_err();
+    assert_eq!(ErrorCode::DoesNotExist, err.code);
+}
+
+#[test]
+fn test_runlists() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
+
+    let project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_stock("s", "init_val", &["f"], &[], None),
+                x_flow("f", "rate * s", None),
+                x_aux("rate", "base / 10", None),
+                x_aux("init_val", "base * 2", None),
+                x_aux("base", "3", None),
+            ],
+        )],
+    );
+
+    let parsed_project = Rc::new(Project::from(project));
+    let sim = Simulation::new(&parsed_project, "main").unwrap();
+    assert!(sim.runlists("nonexistent").is_none());
+    let runlists = sim.runlists("main").unwrap();
+
+    let pos = |list: &[Ident], ident: &str| list.iter().position(|id| id == ident).unwrap();
+
+    let initials = &runlists.initials;
+    assert!(pos(initials, "base") < pos(initials, "init_val"));
+    assert!(pos(initials, "init_val") < pos(initials, "s"));
+    // only the variables needed to initialize stocks are evaluated
+    assert!(!initials.contains(&"f".to_owned()));
+
+    let flows = &runlists.flows;
+    assert!(pos(flows, "base") < pos(flows, "rate"));
+    assert!(pos(flows, "rate") < pos(flows, "f"));
+    assert!(!flows.contains(&"s".to_owned()));
+
+    assert_eq!(vec!["s".to_owned()], runlists.stocks);
+}
+
+#[test]
+fn test_reciprocal_dt() {
+    use crate::testutils::{sim_specs_with_units, x_flow, x_model, x_project, x_stock};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![x_stock("s", "0", &["f"], &[], None), x_flow("f", "1", None)],
+        )],
+    );
+    project.sim_specs.dt = datamodel::Dt::Reciprocal(3.0);
+    project.sim_specs.save_step = Some(datamodel::Dt::Dt(1.0));
+    project.sim_specs.stop = 10.0;
+
+    let project = Project::from(project);
+    let sim = Simulation::new(&project, "main").unwrap();
+    let results1 = sim.run_to_end().unwrap();
+    let mut vm = crate::vm::Vm::new(sim.compile().unwrap()).unwrap();
+    vm.run_to_end().unwrap();
+    let results2 = vm.into_results();
+
+    for results in [results1, results2].iter() {
+        assert_eq!(11, results.step_count);
+        let times = results
+            .iter_rows()
+            .map(|step| step[TIME_OFF])
+            .collect::<Vec<_>>();
+        // saved times land exactly on whole numbers, through the stop time
+        assert_eq!((0..=10).map(|t| t as f64).collect::<Vec<_>>(), times);
+        let last = results.iter_rows().next_back().unwrap();
+        assert!((last[results.offsets["s"]] - 10.0).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn test_rk4() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
+
+    let mut euler_stock = x_stock("e", "1", &["e_growth"], &[], None);
+    if let datamodel::Variable::Stock(stock) = &mut euler_stock {
+        stock.force_euler = true;
+    }
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_stock("s", "1", &["growth"], &[], None),
+                x_flow("growth", "s * rate", None),
+                euler_stock,
+                x_flow("e_growth", "e * rate", None),
+                x_aux("rate", "1", None),
+            ],
+        )],
+    );
+    project.sim_specs.sim_method = datamodel::SimMethod::RungeKutta4;
+    project.sim_specs.dt = datamodel::Dt::Dt(0.5);
+    project.sim_specs.stop = 1.0;
+
+    let project = Project::from(project);
+    let sim = Simulation::new(&project, "main").unwrap();
+    let results1 = sim.run_to_end().unwrap();
+    let mut vm = crate::vm::Vm::new(sim.compile().unwrap()).unwrap();
+    vm.run_to_end().unwrap();
+    let results2 = vm.into_results();
+
+    for results in [results1, results2].iter() {
+        let series = |ident: &str| {
+            let off = results.offsets[ident];
+            results
+                .iter_rows()
+                .map(|step| step[off])
+                .collect::<Vec<_>>()
+        };
+        // one RK4 step of ds/dt = s is 1 + h/6 * (1 + 2*1.25 + 2*1.3125 + 1.65625)
+        let s = series("s");
+        assert_eq!(1.6484375, s[1]);
+        assert!((s[2] - 1.0f64.exp()).abs() < 1e-2);
+        // the Euler stock is unaffected by the project's method
+        assert_eq!(vec![1.0, 1.5, 2.25], series("e"));
+    }
+}
+
+#[test]
+fn test_rk45() {
+    use crate::testutils::{sim_specs_with_units, x_flow, x_model, x_project, x_stock};
+
+    let mut euler_stock = x_stock("e", "1", &["e_growth"], &[], None);
+    if let datamodel::Variable::Stock(stock) = &mut euler_stock {
+        stock.force_euler = true;
+    }
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_stock("s", "1", &["growth"], &[], None),
+                x_flow("growth", "s", None),
+                euler_stock,
+                x_flow("e_growth", "e", None),
+            ],
+        )],
+    );
+    project.sim_specs.sim_method = datamodel::SimMethod::Rk45;
+    project.sim_specs.stop = 2.0;
+
+    let run = |project: &datamodel::Project| {
+        let project = Project::from(project.clone());
+        let sim = Simulation::new(&project, "main").unwrap();
+        let results1 = sim.run_to_end().unwrap();
+        let mut vm = crate::vm::Vm::new(sim.compile().unwrap()).unwrap();
+        vm.run_to_end().unwrap();
+        let results2 = vm.into_results();
+        let series = |results: &Results, ident: &str| {
+            let off = results.offsets[ident];
+            results
+                .iter_rows()
+                .map(|step| step[off])
+                .collect::<Vec<_>>()
+        };
+        for ident in ["s", "e"] {
+            let (a, b) = (series(&results1, ident), series(&results2, ident));
+            assert!(a.iter().zip(b.iter()).all(|(a, b)| (a - b).abs() < 1e-12));
+        }
+        (series(&results1, "s"), series(&results1, "e"))
+    };
+
+    // even with a dt of 1, the tolerances hold the error well below
+    // what RK4 would manage
+    let (s, e) = run(&project);
+    assert_eq!(3, s.len());
+    assert!((s[1] - 1.0f64.exp()).abs() < 1e-4);
+    assert!((s[2] - 2.0f64.exp()).abs() < 1e-4);
+    assert_eq!(vec![1.0, 2.0, 4.0], e);
+
+    // looser tolerances take fewer, less accurate steps
+    project.sim_specs.tolerances.relative = 1e-2;
+    project.sim_specs.tolerances.absolute = 1e-2;
+    let (loose, _) = run(&project);
+    assert!((loose[2] - 2.0f64.exp()).abs() > (s[2] - 2.0f64.exp()).abs());
+}
+
+#[test]
+fn test_arrayed_lookup() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_model, x_project};
+
+    let mut lookup = x_aux("lookup", "", None);
+    if let datamodel::Variable::Aux(aux) = &mut lookup {
+        aux.equation = datamodel::Equation::ApplyToAll(
+            vec!["letters".to_owned()],
+            "time * factor[letters]".to_owned(),
+            None,
+        );
+        aux.gf = Some(datamodel::GraphicalFunction {
+            kind: datamodel::GraphicalFunctionKind::Continuous,
+            x_points: None,
+            y_points: vec![0.0, 10.0],
+            x_scale: datamodel::GraphicalFunctionScale { min: 0.0, max: 2.0 },
+            y_scale: datamodel::GraphicalFunctionScale {
+                min: 0.0,
+                max: 10.0,
+            },
+        });
+    }
+    let mut factor = x_aux("factor", "", None);
+    if let datamodel::Variable::Aux(aux) = &mut factor {
+        aux.equation = datamodel::Equation::Arrayed(
+            vec!["letters".to_owned()],
+            vec![
+                ("a".to_owned(), "0".to_owned(), None),
+                ("b".to_owned(), "1".to_owned(), None),
+                ("c".to_owned(), "2".to_owned(), None),
+            ],
+        );
+    }
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model("main", vec![lookup, factor])],
+    );
+    project.dimensions = vec![datamodel::Dimension::Named(
+        "letters".to_owned(),
+        vec!["a".to_owned(), "b".to_owned(), "c".to_owned()],
+    )];
+    project.sim_specs.stop = 1.0;
+    project.sim_specs.dt = datamodel::Dt::Dt(1.0);
+
+    let project = Project::from(project);
+    let sim = Simulation::new(&project, "main").unwrap();
+    let compiled = sim.compile().unwrap();
+    // the three elements share one table
+    assert_eq!(
+        1,
+        compiled.modules["main"].context.graphical_functions.len()
+    );
+
+    let results1 = sim.run_to_end().unwrap();
+    let mut vm = crate::vm::Vm::new(compiled).unwrap();
+    vm.run_to_end().unwrap();
+    let results2 = vm.into_results();
+
+    for results in [results1, results2].iter() {
+        let last = results.iter_rows().next_back().unwrap();
+        let off = results.offsets["lookup[a]"];
+        assert_eq!(&[0.0, 5.0, 10.0], &last[off..off + 3]);
+    }
+}
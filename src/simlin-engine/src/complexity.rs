@@ -0,0 +1,230 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Flagging equations that are too complex to review easily, like long
+//! chains of nested IF-THEN-ELSEs, along with the parts of them that
+//! could be split out into their own variables.
+
+use std::collections::BTreeSet;
+
+use crate::ast::{print_eqn, Expr0, IndexExpr0};
+use crate::builtins::UntypedBuiltinFn;
+use crate::common::{canonicalize, Ident};
+use crate::datamodel::{Equation, Model, Project, Variable};
+use crate::token::LexerType;
+
+// the most split points suggested for a single equation
+const MAX_SPLIT_POINTS: usize = 3;
+
+/// ComplexityLimits are the thresholds above which an equation is
+/// reported.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ComplexityLimits {
+    /// the deepest nesting of operators, function calls and IFs
+    pub max_depth: usize,
+    /// the most variables and constants in one equation
+    pub max_operands: usize,
+    /// the most distinct units among the variables an equation refers to
+    pub max_units: usize,
+}
+
+impl Default for ComplexityLimits {
+    fn default() -> Self {
+        ComplexityLimits {
+            max_depth: 6,
+            max_operands: 12,
+            max_units: 3,
+        }
+    }
+}
+
+/// ComplexEquation is an equation over at least one of the limits.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ComplexEquation {
+    pub model_name: String,
+    pub ident: Ident,
+    /// the element of an arrayed equation, if it has one per element
+    pub element: Option<String>,
+    pub depth: usize,
+    pub operands: usize,
+    /// the units of the variables the equation refers to
+    pub units: Vec<String>,
+    /// subexpressions that could become variables of their own, largest
+    /// first
+    pub split_points: Vec<String>,
+}
+
+fn depth(expr: &Expr0) -> usize {
+    1 + children(expr).into_iter().map(depth).max().unwrap_or(0)
+}
+
+fn operands(expr: &Expr0) -> usize {
+    match expr {
+        Expr0::Const(_, _, _) | Expr0::Var(_, _) => 1,
+        Expr0::Subscript(_, _, _) => 1 + children(expr).into_iter().map(operands).sum::<usize>(),
+        _ => children(expr).into_iter().map(operands).sum(),
+    }
+}
+
+pub(crate) fn children(expr: &Expr0) -> Vec<&Expr0> {
+    match expr {
+        Expr0::Const(_, _, _) | Expr0::Var(_, _) => vec![],
+        Expr0::App(UntypedBuiltinFn(_, args), _) => args.iter().collect(),
+        Expr0::Subscript(_, args, _) => args
+            .iter()
+            .flat_map(|arg| match arg {
+                IndexExpr0::Range(l, r, _) => vec![l, r],
+                IndexExpr0::Expr(e) => vec![e],
+                IndexExpr0::Wildcard(_) | IndexExpr0::StarRange(_, _) => vec![],
+            })
+            .collect(),
+        Expr0::Op1(_, l, _) => vec![l.as_ref()],
+        Expr0::Op2(_, l, r, _) => vec![l.as_ref(), r.as_ref()],
+        Expr0::If(cond, t, f, _) => vec![cond.as_ref(), t.as_ref(), f.as_ref()],
+    }
+}
+
+pub(crate) fn variables<'a>(expr: &'a Expr0, idents: &mut BTreeSet<&'a str>) {
+    match expr {
+        Expr0::Var(id, _) | Expr0::Subscript(id, _, _) => {
+            idents.insert(id);
+        }
+        _ => {}
+    }
+    for child in children(expr) {
+        variables(child, idents);
+    }
+}
+
+// split_points returns the largest subexpressions that are worth naming:
+// for a chain of IFs, each condition and result, and otherwise the
+// operands of the outermost operator or function.
+fn split_points(expr: &Expr0) -> Vec<String> {
+    let mut candidates = vec![];
+    let mut pending = vec![expr];
+    while let Some(expr) = pending.pop() {
+        for child in children(expr) {
+            if let Expr0::If(_, _, _, _) = child {
+                pending.push(child);
+            } else if operands(child) > 1 {
+                candidates.push(child);
+            }
+        }
+    }
+    candidates.sort_by_key(|e| std::cmp::Reverse(operands(e)));
+    candidates
+        .into_iter()
+        .take(MAX_SPLIT_POINTS)
+        .map(print_eqn)
+        .collect()
+}
+
+pub(crate) fn equation_texts(var: &Variable) -> Vec<(Option<&str>, &str)> {
+    let equation = match var.get_equation() {
+        Some(equation) => equation,
+        None => return vec![],
+    };
+    match equation {
+        Equation::Scalar(eqn, _) | Equation::ApplyToAll(_, eqn, _) => vec![(None, eqn)],
+        Equation::Arrayed(_, elements) => elements
+            .iter()
+            .map(|(element, eqn, _)| (Some(element.as_str()), eqn.as_str()))
+            .collect(),
+    }
+}
+
+fn model_complexity(model: &Model, limits: &ComplexityLimits) -> Vec<ComplexEquation> {
+    let mut complex = vec![];
+    for var in model.variables.iter() {
+        for (element, eqn) in equation_texts(var) {
+            let expr = match Expr0::new(eqn, LexerType::Equation) {
+                Ok(Some(expr)) => expr,
+                _ => continue,
+            };
+            let mut idents = BTreeSet::new();
+            variables(&expr, &mut idents);
+            let units: BTreeSet<String> = idents
+                .into_iter()
+                .filter_map(|ident| model.get_variable(&canonicalize(ident)))
+                .filter_map(|var| var.get_units().cloned())
+                .collect();
+
+            let (depth, operands) = (depth(&expr), operands(&expr));
+            if depth <= limits.max_depth
+                && operands <= limits.max_operands
+                && units.len() <= limits.max_units
+            {
+                continue;
+            }
+            complex.push(ComplexEquation {
+                model_name: model.name.clone(),
+                ident: canonicalize(var.get_ident()),
+                element: element.map(|e| e.to_owned()),
+                depth,
+                operands,
+                units: units.into_iter().collect(),
+                split_points: split_points(&expr),
+            });
+        }
+    }
+    complex
+}
+
+impl Project {
+    /// find_complex_equations reports the equations in every model that
+    /// are over any of `limits`.
+    pub fn find_complex_equations(&self, limits: &ComplexityLimits) -> Vec<ComplexEquation> {
+        self.models
+            .iter()
+            .flat_map(|model| model_complexity(model, limits))
+            .collect()
+    }
+}
+
+#[test]
+fn test_find_complex_equations() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_model, x_project};
+
+    let project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_aux("a", "1", Some("people")),
+                x_aux("b", "2", Some("dollars")),
+                x_aux("c", "3", Some("widgets")),
+                x_aux("d", "4", Some("days")),
+                x_aux("simple", "a + b", None),
+                x_aux(
+                    "chain",
+                    "IF a > 1 THEN b ELSE IF a > 2 THEN c * 2 ELSE IF a > 3 THEN d ELSE 0",
+                    None,
+                ),
+                x_aux("mixed", "a * b * c * d", None),
+            ],
+        )],
+    );
+
+    let limits = ComplexityLimits {
+        max_depth: 4,
+        max_operands: 12,
+        max_units: 3,
+    };
+    let complex = project.find_complex_equations(&limits);
+    let idents: Vec<&str> = complex.iter().map(|c| c.ident.as_str()).collect();
+    assert_eq!(vec!["chain", "mixed"], idents);
+
+    let chain = &complex[0];
+    assert_eq!(5, chain.depth);
+    assert_eq!(11, chain.operands);
+    assert_eq!(MAX_SPLIT_POINTS, chain.split_points.len());
+    assert!(chain.split_points.contains(&"c * 2".to_owned()));
+
+    let mixed = &complex[1];
+    assert_eq!(vec!["days", "dollars", "people", "widgets"], mixed.units);
+
+    // with the default limits, only the mixture of units is a problem
+    let complex = project.find_complex_equations(&ComplexityLimits::default());
+    assert!(complex.iter().all(|c| c.units.len() > 3));
+}
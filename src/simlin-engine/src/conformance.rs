@@ -0,0 +1,208 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! A table of reference values for builtin functions.  Each case is an
+//! equation simulated on its own, along with the value it should have
+//! at every time step.  The table is plain data, so importers can add
+//! cases pinning down how they translate another tool's builtins (like
+//! Vensim's two-argument `PULSE`) and check them the same way.
+
+use std::fmt;
+
+use crate::common::{Error, ErrorCode, ErrorKind, Result};
+use crate::datamodel::{self, Dt};
+use crate::templates::aux;
+use crate::vm::Vm;
+use crate::{Project, Simulation};
+
+/// Case is an equation and the values it should produce.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Case {
+    pub equation: String,
+    pub dt: f64,
+    pub stop: f64,
+    /// the expected value at each time step, starting at time 0
+    pub expected: Vec<f64>,
+}
+
+impl Case {
+    pub fn new(equation: &str, dt: f64, stop: f64, expected: &[f64]) -> Self {
+        Case {
+            equation: equation.to_owned(),
+            dt,
+            stop,
+            expected: expected.to_vec(),
+        }
+    }
+}
+
+/// Mismatch is a time at which a case didn't produce the expected value.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Mismatch {
+    pub equation: String,
+    pub time: f64,
+    pub expected: f64,
+    pub actual: f64,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at time {}: expected {}, got {}",
+            self.equation, self.time, self.expected, self.actual
+        )
+    }
+}
+
+/// builtin_cases returns the reference cases for the engine's builtins.
+/// The time-dependent builtins are checked with a dt smaller than 1, as
+/// that is where implementations tend to differ.
+pub fn builtin_cases() -> Vec<Case> {
+    vec![
+        Case::new("ABS(-2)", 1.0, 0.0, &[2.0]),
+        Case::new("INT(-2.5)", 1.0, 0.0, &[-3.0]),
+        Case::new("MAX(1, 3)", 1.0, 0.0, &[3.0]),
+        Case::new("MIN(1, 3)", 1.0, 0.0, &[1.0]),
+        Case::new("SAFEDIV(1, 0, 7)", 1.0, 0.0, &[7.0]),
+        Case::new("SAFEDIV(1, 0)", 1.0, 0.0, &[0.0]),
+        Case::new("SQRT(9)", 1.0, 0.0, &[3.0]),
+        Case::new("EXP(0) + LN(1)", 1.0, 0.0, &[1.0]),
+        Case::new("TIME_STEP", 0.5, 1.0, &[0.5, 0.5, 0.5]),
+        // a step takes effect at the time step nearest to its start
+        Case::new("STEP(2, 1.5)", 0.5, 2.0, &[0.0, 0.0, 0.0, 2.0, 2.0]),
+        // the volume of a pulse is spread over a single time step
+        Case::new("PULSE(1, 1)", 0.5, 2.0, &[0.0, 0.0, 2.0, 0.0, 0.0]),
+        Case::new(
+            "PULSE(1, 1, 2)",
+            0.5,
+            4.0,
+            &[0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0],
+        ),
+        Case::new(
+            "RAMP(2, 1, 2)",
+            0.5,
+            3.0,
+            &[0.0, 0.0, 0.0, 1.0, 2.0, 2.0, 2.0],
+        ),
+        Case::new(
+            "DELAY1(STEP(1, 1), 2)",
+            0.5,
+            3.0,
+            &[0.0, 0.0, 0.0, 0.25, 0.4375, 0.578125, 0.68359375],
+        ),
+        Case::new(
+            "DELAYN(STEP(1, 1), 2, 1)",
+            0.5,
+            3.0,
+            &[0.0, 0.0, 0.0, 0.25, 0.4375, 0.578125, 0.68359375],
+        ),
+        Case::new(
+            "SMTH1(STEP(1, 1), 2)",
+            0.5,
+            3.0,
+            &[0.0, 0.0, 0.0, 0.25, 0.4375, 0.578125, 0.68359375],
+        ),
+    ]
+}
+
+fn close(expected: f64, actual: f64) -> bool {
+    if expected.is_nan() {
+        return actual.is_nan();
+    }
+    (expected - actual).abs() <= 1e-9 * expected.abs().max(1.0)
+}
+
+/// check simulates the case's equation with Euler's method, returning
+/// the times it didn't match.  It is an error if the equation doesn't
+/// compile, or the run has a different number of steps than expected.
+pub fn check(case: &Case) -> Result<Vec<Mismatch>> {
+    let project = datamodel::Project {
+        name: "conformance".to_owned(),
+        sim_specs: datamodel::SimSpecs {
+            start: 0.0,
+            stop: case.stop,
+            dt: Dt::Dt(case.dt),
+            ..Default::default()
+        },
+        dimensions: vec![],
+        units: vec![],
+        models: vec![datamodel::Model {
+            name: "main".to_owned(),
+            variables: vec![aux("result", &case.equation)],
+            views: vec![],
+            graphs: vec![],
+            groups: vec![],
+            variable_index: Default::default(),
+        }],
+        constants: vec![],
+        tests: vec![],
+        source: None,
+        import_report: vec![],
+    };
+
+    let project = Project::from(project);
+    if let Some(errs) = project.models["main"].variables["result"].equation_errors() {
+        return Err(Error::new(
+            ErrorKind::Variable,
+            errs[0].code,
+            Some(case.equation.clone()),
+        ));
+    }
+    let sim = Simulation::new(&project, "main")?;
+    let mut vm = Vm::new(sim.compile()?)?;
+    vm.run_to_end()?;
+    let results = vm.into_results();
+    let actual = results.get_series("result").unwrap_or_default();
+    if actual.len() != case.expected.len() {
+        return Err(Error::new(
+            ErrorKind::Simulation,
+            ErrorCode::Generic,
+            Some(format!(
+                "{}: expected {} steps, got {}",
+                case.equation,
+                case.expected.len(),
+                actual.len()
+            )),
+        ));
+    }
+
+    Ok(results
+        .time_points()
+        .into_iter()
+        .zip(case.expected.iter().zip(actual.iter()))
+        .filter(|(_, (expected, actual))| !close(**expected, **actual))
+        .map(|(time, (expected, actual))| Mismatch {
+            equation: case.equation.clone(),
+            time,
+            expected: *expected,
+            actual: *actual,
+        })
+        .collect())
+}
+
+/// check_all checks each case, returning every mismatch.
+pub fn check_all(cases: &[Case]) -> Result<Vec<Mismatch>> {
+    let mut mismatches = vec![];
+    for case in cases.iter() {
+        mismatches.extend(check(case)?);
+    }
+    Ok(mismatches)
+}
+
+#[test]
+fn test_builtin_cases() {
+    let mismatches = check_all(&builtin_cases()).unwrap();
+    let report: Vec<String> = mismatches.iter().map(|m| m.to_string()).collect();
+    assert!(mismatches.is_empty(), "{}", report.join("\n"));
+
+    let wrong = Case::new("STEP(1, 1)", 1.0, 2.0, &[0.0, 0.0, 1.0]);
+    let mismatches = check(&wrong).unwrap();
+    assert_eq!(1, mismatches.len());
+    assert_eq!(1.0, mismatches[0].time);
+    assert_eq!(1.0, mismatches[0].actual);
+
+    assert!(check(&Case::new("STEP(1, 1)", 1.0, 1.0, &[0.0])).is_err());
+    assert!(check(&Case::new("nope(1)", 1.0, 0.0, &[0.0])).is_err());
+}
@@ -2,14 +2,18 @@
 // Use of this source code is governed by the Apache License,
 // Version 2.0, that can be found in the LICENSE file.
 
-use std::collections::BTreeMap;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{Display, Formatter};
 use std::iter::Iterator;
+use std::sync::Mutex;
 
+#[cfg(feature = "json")]
+use serde::{Deserialize, Serialize};
 #[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
-use crate::common::{DimensionName, ElementName};
+use crate::common::{DimensionName, ElementName, Ident};
 
 #[derive(Debug, Default, Eq, Clone)]
 pub struct UnitMap {
@@ -43,6 +47,7 @@ impl UnitMap {
         for (_id, unit) in self.map.iter_mut() {
             *unit *= exp;
         }
+        self.map.retain(|_, unit| *unit != 0);
 
         self
     }
@@ -100,69 +105,80 @@ impl std::ops::Mul for UnitMap {
     }
 }
 
-impl Display for UnitMap {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let unit_names = {
-            let mut unit_names = self
+/// UnitTermOrder is the order of the terms within the numerator and
+/// within the denominator of displayed units.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum UnitTermOrder {
+    /// alphabetical by unit name, like `dollar*widget/month^2`
+    #[default]
+    Alphabetical,
+    /// highest power first, then alphabetical, like `widget^2*dollar`
+    HighestPowerFirst,
+}
+
+/// UnitFormat controls how units are displayed.  The default is the
+/// canonical form used in diagnostics.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct UnitFormat {
+    pub order: UnitTermOrder,
+    /// write a denominator with several terms in parentheses, like
+    /// `1/(meter*second)`, rather than as `1/meter/second`
+    pub group_denominator: bool,
+}
+
+impl UnitMap {
+    /// display returns the units as a unit equation, like `people/year`,
+    /// with the terms arranged as `format` specifies.  Dimensionless units
+    /// are `dmnl`.
+    pub fn display(&self, format: UnitFormat) -> String {
+        let terms = |numerator: bool| {
+            let mut terms: Vec<(&str, i32)> = self
                 .map
-                .keys()
-                .map(|unit| unit.as_str())
-                .collect::<Vec<&str>>();
-            unit_names.sort_unstable();
-            unit_names
+                .iter()
+                .filter(|(_, exp)| (**exp > 0) == numerator && **exp != 0)
+                .map(|(unit, exp)| (unit.as_str(), exp.abs()))
+                .collect();
+            if format.order == UnitTermOrder::HighestPowerFirst {
+                // the sort is stable, so equal powers stay alphabetical
+                terms.sort_by_key(|t| std::cmp::Reverse(t.1));
+            }
+            terms
+                .into_iter()
+                .map(|(unit, exp)| {
+                    if exp > 1 {
+                        format!("{}^{}", unit, exp)
+                    } else {
+                        unit.to_owned()
+                    }
+                })
+                .collect::<Vec<_>>()
         };
+        let numerator = terms(true);
+        let denominator = terms(false);
 
-        let mut written = false;
-        let mut first = true;
-        for (unit, exp) in unit_names
-            .iter()
-            .map(|unit| (unit, self.map[*unit]))
-            .filter(|(_, exp)| *exp > 0)
-        {
-            if !first {
-                write!(f, "*")?;
-            }
-            first = false;
-            write!(f, "{}", unit)?;
-            if exp.abs() > 1 {
-                write!(f, "^{}", exp.abs())?;
-            }
-            written = true;
+        if numerator.is_empty() && denominator.is_empty() {
+            return "dmnl".to_owned();
         }
-
-        for (unit, exp) in unit_names
-            .iter()
-            .map(|unit| (unit, self.map[*unit]))
-            .filter(|(_, exp)| *exp < 0)
-        {
-            if !written {
-                write!(f, "1")?;
-                written = true;
-            }
-            write!(f, "/")?;
-            write!(f, "{}", unit)?;
-            if exp.abs() > 1 {
-                write!(f, "^{}", exp.abs())?;
+        let mut result = if numerator.is_empty() {
+            "1".to_owned()
+        } else {
+            numerator.join("*")
+        };
+        if format.group_denominator && denominator.len() > 1 {
+            result.push_str(&format!("/({})", denominator.join("*")));
+        } else {
+            for term in denominator.iter() {
+                result.push('/');
+                result.push_str(term);
             }
         }
+        result
+    }
+}
 
-        if !written {
-            write!(f, "dmnl")?;
-        }
-
-        // if let Some(ctxs) = &self.ctx {
-        //     write!(f, " (")?;
-        //     for (i, ctx) in ctxs.iter().enumerate() {
-        //         if i == 0 {
-        //             write!(f, "{}", ctx)?;
-        //         } else {
-        //             write!(f, " ,{}", ctx)?;
-        //         }
-        //     }
-        //     write!(f, ")")?;
-        // }
-
-        Ok(())
+impl Display for UnitMap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.display(Default::default()))
     }
 }
 
@@ -177,6 +193,7 @@ impl FromIterator<(String, i32)> for UnitMap {
 
 #[cfg_attr(feature = "wasm", wasm_bindgen)]
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub enum GraphicalFunctionKind {
     Continuous,
     Extrapolate,
@@ -185,12 +202,14 @@ pub enum GraphicalFunctionKind {
 
 #[cfg_attr(feature = "wasm", wasm_bindgen)]
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub struct GraphicalFunctionScale {
     pub min: f64,
     pub max: f64,
 }
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub struct GraphicalFunction {
     pub kind: GraphicalFunctionKind,
     pub x_points: Option<Vec<f64>>,
@@ -200,6 +219,7 @@ pub struct GraphicalFunction {
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub enum Equation {
     Scalar(String, Option<String>),
     ApplyToAll(Vec<DimensionName>, String, Option<String>),
@@ -210,12 +230,14 @@ pub enum Equation {
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub enum Visibility {
     Private,
     Public,
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub struct Stock {
     pub ident: String,
     pub equation: Equation,
@@ -224,11 +246,16 @@ pub struct Stock {
     pub inflows: Vec<String>,
     pub outflows: Vec<String>,
     pub non_negative: bool,
+    /// integrate with Euler's method even when the project uses RK4
+    pub force_euler: bool,
     pub can_be_module_input: bool,
+    /// a Vensim supplementary variable, only computed to be reported
+    pub supplementary: bool,
     pub visibility: Visibility,
 }
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub struct Flow {
     pub ident: String,
     pub equation: Equation,
@@ -237,10 +264,13 @@ pub struct Flow {
     pub gf: Option<GraphicalFunction>,
     pub non_negative: bool,
     pub can_be_module_input: bool,
+    /// a Vensim supplementary variable, only computed to be reported
+    pub supplementary: bool,
     pub visibility: Visibility,
 }
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub struct Aux {
     pub ident: String,
     pub equation: Equation,
@@ -248,16 +278,20 @@ pub struct Aux {
     pub units: Option<String>,
     pub gf: Option<GraphicalFunction>,
     pub can_be_module_input: bool,
+    /// a Vensim supplementary variable, only computed to be reported
+    pub supplementary: bool,
     pub visibility: Visibility,
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub struct ModuleReference {
     pub src: String,
     pub dst: String,
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub struct Module {
     pub ident: String,
     pub model_name: String,
@@ -269,6 +303,7 @@ pub struct Module {
 }
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub enum Variable {
     Stock(Stock),
     Flow(Flow),
@@ -304,6 +339,17 @@ impl Variable {
         }
     }
 
+    /// is_supplementary is true for variables only computed to be
+    /// reported, which nothing else in the model depends on.
+    pub fn is_supplementary(&self) -> bool {
+        match self {
+            Variable::Stock(stock) => stock.supplementary,
+            Variable::Flow(flow) => flow.supplementary,
+            Variable::Aux(aux) => aux.supplementary,
+            Variable::Module(_module) => false,
+        }
+    }
+
     pub fn set_ident(&mut self, ident: String) {
         match self {
             Variable::Stock(stock) => stock.ident = ident,
@@ -374,11 +420,14 @@ impl Variable {
 }
 
 pub mod view_element {
+    #[cfg(feature = "json")]
+    use serde::{Deserialize, Serialize};
     #[cfg(feature = "wasm")]
     use wasm_bindgen::prelude::*;
 
     #[cfg_attr(feature = "wasm", wasm_bindgen)]
     #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+    #[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
     pub enum LabelSide {
         Top,
         Left,
@@ -388,6 +437,7 @@ pub mod view_element {
     }
 
     #[derive(Clone, PartialEq, Debug)]
+    #[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
     pub struct Aux {
         pub name: String,
         pub uid: i32,
@@ -397,6 +447,7 @@ pub mod view_element {
     }
 
     #[derive(Clone, PartialEq, Debug)]
+    #[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
     pub struct Stock {
         pub name: String,
         pub uid: i32,
@@ -407,6 +458,7 @@ pub mod view_element {
 
     #[cfg_attr(feature = "wasm", wasm_bindgen)]
     #[derive(Clone, PartialEq, Debug)]
+    #[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
     pub struct FlowPoint {
         pub x: f64,
         pub y: f64,
@@ -414,6 +466,7 @@ pub mod view_element {
     }
 
     #[derive(Clone, PartialEq, Debug)]
+    #[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
     pub struct Flow {
         pub name: String,
         pub uid: i32,
@@ -426,21 +479,37 @@ pub mod view_element {
     }
 
     #[derive(Clone, PartialEq, Debug)]
+    #[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
     pub enum LinkShape {
         Straight,
         Arc(f64), // angle in [0, 360)
         MultiPoint(Vec<FlowPoint>),
     }
 
+    /// LinkPolarity is the direction of the causal influence a link
+    /// represents, as drawn on causal loop diagrams.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+    #[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+    pub enum LinkPolarity {
+        Positive,
+        Negative,
+    }
+
     #[derive(Clone, PartialEq, Debug)]
+    #[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
     pub struct Link {
         pub uid: i32,
         pub from_uid: i32,
         pub to_uid: i32,
         pub shape: LinkShape,
+        pub polarity: Option<LinkPolarity>,
+        /// true if the link should be drawn with a delay mark
+        pub delay_mark: bool,
     }
 
     #[derive(Clone, PartialEq, Debug)]
+    #[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
     pub struct Module {
         pub name: String,
         pub uid: i32,
@@ -451,6 +520,7 @@ pub mod view_element {
 
     #[cfg_attr(feature = "wasm", wasm_bindgen)]
     #[derive(Clone, PartialEq, Debug)]
+    #[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
     pub struct Alias {
         pub uid: i32,
         pub alias_of_uid: i32,
@@ -460,6 +530,7 @@ pub mod view_element {
     }
 
     #[derive(Clone, PartialEq, Debug)]
+    #[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
     pub struct Cloud {
         pub uid: i32,
         pub flow_uid: i32,
@@ -469,6 +540,7 @@ pub mod view_element {
 }
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub enum ViewElement {
     Aux(view_element::Aux),
     Stock(view_element::Stock),
@@ -506,6 +578,7 @@ impl ViewElement {
 }
 
 #[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub struct Rect {
     pub x: f64,
     pub y: f64,
@@ -514,6 +587,7 @@ pub struct Rect {
 }
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub struct StockFlow {
     pub elements: Vec<ViewElement>,
     pub view_box: Rect,
@@ -533,34 +607,192 @@ impl StockFlow {
 }
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub enum View {
     StockFlow(StockFlow),
 }
 
+/// GraphKind is how a graph presents the variables it plots.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub enum GraphKind {
+    #[default]
+    TimeSeries,
+    /// the first plot is the x axis, and the rest are plotted against it
+    Scatter,
+    Bar,
+    /// the values are listed in columns rather than drawn
+    Table,
+}
+
+/// Plot is one variable shown on a graph.
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct Plot {
+    pub ident: String,
+    /// a CSS color, like `blue` or `#4e79a7`
+    pub color: Option<String>,
+    /// the range of the plot's axis, if it isn't fit to the data
+    pub scale: Option<GraphicalFunctionScale>,
+    /// for tables, how many digits to show after the decimal point
+    pub precision: Option<usize>,
+}
+
+/// Graph is a saved chart or table of simulation results, so the views
+/// of a model's behavior its author set up travel with the model.
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct Graph {
+    pub title: String,
+    pub kind: GraphKind,
+    pub plots: Vec<Plot>,
+    /// for tables, the time between rows; every saved step is a row if
+    /// this isn't set
+    pub interval: Option<f64>,
+}
+
+/// Group is a named set of a model's variables, like a sector of a
+/// Vensim model, with a description of what they have in common.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct Group {
+    pub name: String,
+    pub documentation: String,
+    pub members: Vec<Ident>,
+}
+
+/// VariableIndex caches the position of each of a model's variables by
+/// ident, so lookups don't scan every variable.  It is built on first
+/// use and every hit is checked against the variable it points to, so
+/// changing `variables` directly never returns the wrong variable: the
+/// index is just rebuilt.
+#[derive(Debug, Default)]
+pub struct VariableIndex(Mutex<Option<HashMap<String, usize>>>);
+
+impl VariableIndex {
+    fn position(&self, variables: &[Variable], ident: &str) -> Option<usize> {
+        let mut index = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(&i) = index.as_ref().and_then(|index| index.get(ident)) {
+            if variables.get(i).map(|var| var.get_ident()) == Some(ident) {
+                return Some(i);
+            }
+        }
+        // either the index is stale or there is no such variable
+        let i = variables.iter().position(|var| var.get_ident() == ident)?;
+        let mut positions = HashMap::with_capacity(variables.len());
+        for (i, var) in variables.iter().enumerate() {
+            if let Entry::Vacant(e) = positions.entry(var.get_ident().to_owned()) {
+                e.insert(i);
+            }
+        }
+        *index = Some(positions);
+        Some(i)
+    }
+}
+
+/// a copy of a model builds its own index
+impl Clone for VariableIndex {
+    fn clone(&self) -> Self {
+        Default::default()
+    }
+}
+
+/// the index is a cache, and doesn't affect model equality
+impl PartialEq for VariableIndex {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub struct Model {
     pub name: String,
     pub variables: Vec<Variable>,
     pub views: Vec<View>,
+    pub graphs: Vec<Graph>,
+    pub groups: Vec<Group>,
+    #[cfg_attr(feature = "json", serde(skip))]
+    pub variable_index: VariableIndex,
 }
 
 impl Model {
     pub fn get_variable(&self, ident: &str) -> Option<&Variable> {
-        self.variables.iter().find(|&var| var.get_ident() == ident)
+        let i = self.variable_index.position(&self.variables, ident)?;
+        Some(&self.variables[i])
     }
 
     pub fn get_variable_mut(&mut self, ident: &str) -> Option<&mut Variable> {
-        self.variables
-            .iter_mut()
-            .find(|var| var.get_ident() == ident)
+        let i = self.variable_index.position(&self.variables, ident)?;
+        Some(&mut self.variables[i])
+    }
+
+    pub fn get_stock(&self, ident: &str) -> Option<&Stock> {
+        match self.get_variable(ident)? {
+            Variable::Stock(stock) => Some(stock),
+            _ => None,
+        }
+    }
+
+    pub fn get_flow(&self, ident: &str) -> Option<&Flow> {
+        match self.get_variable(ident)? {
+            Variable::Flow(flow) => Some(flow),
+            _ => None,
+        }
+    }
+
+    pub fn get_aux(&self, ident: &str) -> Option<&Aux> {
+        match self.get_variable(ident)? {
+            Variable::Aux(aux) => Some(aux),
+            _ => None,
+        }
+    }
+
+    pub fn get_module(&self, ident: &str) -> Option<&Module> {
+        match self.get_variable(ident)? {
+            Variable::Module(module) => Some(module),
+            _ => None,
+        }
+    }
+
+    pub fn stocks(&self) -> impl Iterator<Item = &Stock> {
+        self.variables.iter().filter_map(|var| match var {
+            Variable::Stock(stock) => Some(stock),
+            _ => None,
+        })
+    }
+
+    pub fn flows(&self) -> impl Iterator<Item = &Flow> {
+        self.variables.iter().filter_map(|var| match var {
+            Variable::Flow(flow) => Some(flow),
+            _ => None,
+        })
+    }
+
+    pub fn auxs(&self) -> impl Iterator<Item = &Aux> {
+        self.variables.iter().filter_map(|var| match var {
+            Variable::Aux(aux) => Some(aux),
+            _ => None,
+        })
+    }
+
+    pub fn modules(&self) -> impl Iterator<Item = &Module> {
+        self.variables.iter().filter_map(|var| match var {
+            Variable::Module(module) => Some(module),
+            _ => None,
+        })
     }
 }
 
 #[cfg_attr(feature = "wasm", wasm_bindgen)]
 #[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub enum SimMethod {
     Euler,
     RungeKutta4,
+    /// Dormand-Prince 5(4), which takes as many steps within each dt as
+    /// it needs to keep the error of every stock within the tolerances
+    Rk45,
 }
 
 /// The default SimMethod is Euler
@@ -573,6 +805,7 @@ impl Default for SimMethod {
 /// Dt is a UI thing: it can be nice to specify exact
 /// fractions that don't display neatly in the UI, like 1/3
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub enum Dt {
     Dt(f64),
     Reciprocal(f64),
@@ -585,17 +818,41 @@ impl Default for Dt {
     }
 }
 
+/// Tolerances bound the error of each step the adaptive Rk45 method
+/// takes: a step is accepted when its estimated error in every stock is
+/// at most `absolute + relative * |value|`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct Tolerances {
+    pub relative: f64,
+    pub absolute: f64,
+}
+
+impl Default for Tolerances {
+    fn default() -> Self {
+        Tolerances {
+            relative: 1e-6,
+            absolute: 1e-9,
+        }
+    }
+}
+
 #[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub struct SimSpecs {
     pub start: f64,
     pub stop: f64,
+    /// the time between steps, or for Rk45 the longest step it takes
     pub dt: Dt,
     pub save_step: Option<Dt>,
     pub sim_method: SimMethod,
+    /// only used by Rk45
+    pub tolerances: Tolerances,
     pub time_units: Option<String>,
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub enum Dimension {
     Indexed(String, u32),
     Named(String, Vec<String>),
@@ -633,6 +890,7 @@ impl Dimension {
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub struct Unit {
     pub name: String,
     pub equation: Option<String>,
@@ -642,6 +900,7 @@ pub struct Unit {
 
 #[cfg_attr(feature = "wasm", wasm_bindgen)]
 #[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub enum Extension {
     Unspecified,
     Xmile,
@@ -649,19 +908,71 @@ pub enum Extension {
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub struct Source {
     pub extension: Extension,
     pub content: String,
 }
 
+/// ImportIssue is something in an imported file that isn't supported,
+/// and was left out of the project.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct ImportIssue {
+    /// the unsupported construct, like "macro" or "conveyor"
+    pub construct: String,
+    /// where the construct is in the source file, like "line 12"
+    pub location: String,
+    pub reason: String,
+}
+
+/// TestOverride sets a variable to a constant for the duration of a test.
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct TestOverride {
+    pub ident: String,
+    pub value: f64,
+}
+
+/// TestExpectation is a check that a variable is within `tolerance` of
+/// `value` at `time`.
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct TestExpectation {
+    pub ident: String,
+    pub time: f64,
+    pub value: f64,
+    pub tolerance: f64,
+}
+
+/// ModelTest is a scenario to simulate, along with the results it is
+/// expected to produce.
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct ModelTest {
+    pub name: String,
+    pub model_name: String,
+    pub overrides: Vec<TestOverride>,
+    pub expectations: Vec<TestExpectation>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
 pub struct Project {
     pub name: String,
     pub sim_specs: SimSpecs,
     pub dimensions: Vec<Dimension>,
     pub units: Vec<Unit>,
     pub models: Vec<Model>,
+    /// constants is a list of project-scope variables that are visible
+    /// in every model, unless a model defines a variable of the same name.
+    pub constants: Vec<Aux>,
+    /// tests are regression tests of the behavior of the project's models
+    pub tests: Vec<ModelTest>,
     pub source: Option<Source>,
+    /// import_report lists what was dropped when importing the project
+    /// from another format
+    pub import_report: Vec<ImportIssue>,
 }
 
 impl Project {
@@ -674,3 +985,45 @@ impl Project {
         self.models.iter_mut().find(|m| m.name == model_name)
     }
 }
+
+#[test]
+fn test_model_lookup() {
+    let aux = |ident: &str| {
+        Variable::Aux(Aux {
+            ident: ident.to_owned(),
+            equation: Equation::Scalar("1".to_owned(), None),
+            documentation: "".to_owned(),
+            units: None,
+            gf: None,
+            can_be_module_input: false,
+            supplementary: false,
+            visibility: Visibility::Private,
+        })
+    };
+    let mut model = Model {
+        name: "main".to_owned(),
+        variables: vec![aux("a"), aux("b")],
+        views: vec![],
+        graphs: vec![],
+        groups: vec![],
+        variable_index: Default::default(),
+    };
+
+    assert_eq!("b", model.get_aux("b").unwrap().ident);
+    assert!(model.get_stock("b").is_none());
+    assert!(model.get_variable("c").is_none());
+    assert_eq!(2, model.auxs().count());
+    assert_eq!(0, model.stocks().count());
+
+    // the index notices direct changes to the variables
+    model.variables.remove(0);
+    model.variables.push(aux("c"));
+    assert_eq!("b", model.get_variable("b").unwrap().get_ident());
+    assert_eq!("c", model.get_variable("c").unwrap().get_ident());
+    assert!(model.get_variable("a").is_none());
+    if let Some(Variable::Aux(aux)) = model.get_variable_mut("c") {
+        aux.ident = "d".to_owned();
+    }
+    assert!(model.get_variable("c").is_none());
+    assert_eq!("d", model.get_variable("d").unwrap().get_ident());
+}
@@ -0,0 +1,413 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use crate::common::{Ident, Result};
+use crate::model::ModelStage1;
+use crate::model_err;
+use crate::paths::{join_path, split_path};
+use crate::project::Project;
+use crate::variable::{identifier_set, ModuleInput, Variable};
+
+// Scope is a model instance: the root model, or a module instance
+// along with the inputs its parent model provides.
+#[derive(Clone)]
+struct Scope<'a> {
+    model: &'a ModelStage1,
+    // canonical idents of the enclosing module instances
+    path: Vec<Ident>,
+    inputs: &'a [ModuleInput],
+}
+
+// a resolved reference: the scope stack the variable is defined in, its
+// ident there, and a marker if a module boundary was crossed
+type Resolved<'a> = (Vec<Scope<'a>>, Ident, Option<&'static str>);
+
+struct TreeBuilder<'a> {
+    project: &'a Project,
+    max_depth: usize,
+    lines: Vec<String>,
+    // the variables on the path from the root to the current node
+    ancestors: HashSet<String>,
+}
+
+impl<'a> TreeBuilder<'a> {
+    fn model(&self, model_name: &str) -> Result<&'a ModelStage1> {
+        match self.project.models.get(model_name) {
+            Some(model) => Ok(model.as_ref()),
+            None => model_err!(BadModelName, model_name.to_owned()),
+        }
+    }
+
+    // resolve follows an identifier across module boundaries.
+    fn resolve(&self, mut scopes: Vec<Scope<'a>>, ident: &str) -> Result<Option<Resolved<'a>>> {
+        let scope = scopes.last().unwrap().clone();
+        if let Some((module_ident, rest)) = ident.split_once('·') {
+            if let Some(Variable::Module {
+                model_name, inputs, ..
+            }) = scope.model.variables.get(module_ident)
+            {
+                let mut path = scope.path.clone();
+                path.push(module_ident.to_owned());
+                scopes.push(Scope {
+                    model: self.model(model_name)?,
+                    path,
+                    inputs,
+                });
+                return Ok(self
+                    .resolve(scopes, rest)?
+                    .map(|(scopes, ident, _)| (scopes, ident, Some("module output"))));
+            }
+        }
+        if scopes.len() > 1 {
+            if let Some(input) = scope.inputs.iter().find(|input| input.dst == ident) {
+                scopes.pop();
+                return Ok(self
+                    .resolve(scopes, &input.src)?
+                    .map(|(scopes, ident, _)| (scopes, ident, Some("module input"))));
+            }
+        }
+        if scope.model.variables.contains_key(ident) {
+            Ok(Some((scopes, ident.to_owned(), None)))
+        } else {
+            // builtins like time, or references that don't resolve
+            Ok(None)
+        }
+    }
+
+    fn dependencies(&self, var: &Variable) -> Vec<Ident> {
+        let mut deps: Vec<Ident> = match var {
+            Variable::Stock {
+                inflows, outflows, ..
+            } => inflows.iter().chain(outflows.iter()).cloned().collect(),
+            Variable::Var { ast: Some(ast), .. } => {
+                let dims = var.get_dimensions().unwrap_or(&[]);
+                identifier_set(ast, dims, None).into_iter().collect()
+            }
+            Variable::Module { inputs, .. } => {
+                inputs.iter().map(|input| input.src.clone()).collect()
+            }
+            _ => vec![],
+        };
+        deps.sort_unstable();
+        deps.dedup();
+        deps
+    }
+
+    fn walk(
+        &mut self,
+        scopes: Vec<Scope<'a>>,
+        ident: &str,
+        marker: Option<&str>,
+        depth: usize,
+        prefix: &str,
+        connector: &str,
+    ) -> Result<()> {
+        let scope = scopes.last().unwrap();
+        let var = &scope.model.variables[ident];
+        let name = join_path(scope.path.iter().chain(std::iter::once(&ident.to_owned())));
+
+        let mut markers: Vec<&str> = vec![];
+        if var.is_stock() {
+            markers.push("stock");
+        }
+        if var.is_module() {
+            markers.push("module");
+        }
+        markers.extend(marker);
+
+        let deps = self.dependencies(var);
+        let is_cycle = self.ancestors.contains(&name);
+        let suffix = if is_cycle {
+            " (cycle)"
+        } else if depth >= self.max_depth && !deps.is_empty() {
+            " ..."
+        } else {
+            ""
+        };
+        let markers = if markers.is_empty() {
+            "".to_owned()
+        } else {
+            format!(" [{}]", markers.join(", "))
+        };
+        self.lines.push(format!(
+            "{}{}{}{}{}",
+            prefix, connector, name, markers, suffix
+        ));
+        if is_cycle || depth >= self.max_depth {
+            return Ok(());
+        }
+
+        let mut children = vec![];
+        for dep in deps.iter() {
+            if let Some(child) = self.resolve(scopes.clone(), dep)? {
+                children.push(child);
+            }
+        }
+        let child_prefix = match connector {
+            "" => prefix.to_owned(),
+            "└── " => format!("{}    ", prefix),
+            _ => format!("{}│   ", prefix),
+        };
+        self.ancestors.insert(name.clone());
+        let n = children.len();
+        for (i, (child_scopes, child_ident, marker)) in children.into_iter().enumerate() {
+            let connector = if i + 1 == n {
+                "└── "
+            } else {
+                "├── "
+            };
+            self.walk(
+                child_scopes,
+                &child_ident,
+                marker,
+                depth + 1,
+                &child_prefix,
+                connector,
+            )?;
+        }
+        self.ancestors.remove(&name);
+        Ok(())
+    }
+}
+
+/// dependency_tree returns an indented tree of the upstream dependencies
+/// of the variable at `path` (like `hares.births`) in the model
+/// `model_name`, up to `max_depth` levels deep.  A stock's dependencies
+/// are its flows.  Dependencies that cross into or out of a module
+/// instance are marked, and truncated branches end with `...`.
+pub fn dependency_tree(
+    project: &Project,
+    model_name: &str,
+    path: &str,
+    max_depth: usize,
+) -> Result<String> {
+    let mut builder = TreeBuilder {
+        project,
+        max_depth,
+        lines: vec![],
+        ancestors: HashSet::new(),
+    };
+    let root = Scope {
+        model: builder.model(model_name)?,
+        path: vec![],
+        inputs: &[],
+    };
+    let ident = split_path(path).join("·");
+    let (scopes, ident, _) = match builder.resolve(vec![root], &ident)? {
+        Some(resolved) => resolved,
+        None => return model_err!(DoesNotExist, path.to_owned()),
+    };
+    builder.walk(scopes, &ident, None, 0, "", "")?;
+    Ok(builder.lines.join("\n") + "\n")
+}
+
+/// DependencyKind selects which dependencies dependency_graph draws.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum DependencyKind {
+    /// the dependencies evaluated each time step, where stocks take their
+    /// value from the previous step and so depend on nothing
+    #[default]
+    Dt,
+    /// the dependencies evaluated to compute initial values, where stocks
+    /// depend on their initial equations
+    Initial,
+    /// both, with edges colored by the phase that uses them: black for
+    /// both, blue for dt only and red for initial only
+    Combined,
+}
+
+// the variables in the model that `var` directly depends on in the given
+// phase.  References to a module's outputs are dependencies on the
+// module.
+fn phase_dependencies(model: &ModelStage1, var: &Variable, is_initial: bool) -> BTreeSet<Ident> {
+    let deps: Vec<Ident> = match var {
+        Variable::Module { inputs, .. } => inputs.iter().map(|input| input.src.clone()).collect(),
+        Variable::Stock { .. } if !is_initial => vec![],
+        _ => {
+            let ast = if is_initial {
+                var.init_ast()
+            } else {
+                var.ast()
+            };
+            let dims = var.get_dimensions().unwrap_or(&[]);
+            match ast {
+                Some(ast) => identifier_set(ast, dims, None).into_iter().collect(),
+                None => vec![],
+            }
+        }
+    };
+    deps.into_iter()
+        .map(|dep| match dep.split_once('·') {
+            Some((module_ident, _)) => module_ident.to_owned(),
+            None => dep,
+        })
+        .filter(|dep| model.variables.contains_key(dep))
+        .collect()
+}
+
+/// dependency_graph returns the direct dependencies between the variables
+/// of the model `model_name` as a Graphviz DOT digraph, with an edge from
+/// each dependency to the variable that uses it.  Initialization-order
+/// problems only show up in the `Initial` graph, as stocks break every
+/// dependency chain during a time step.
+pub fn dependency_graph(
+    project: &Project,
+    model_name: &str,
+    kind: DependencyKind,
+) -> Result<String> {
+    let model = match project.models.get(model_name) {
+        Some(model) => model.as_ref(),
+        None => return model_err!(BadModelName, model_name.to_owned()),
+    };
+    let variables: BTreeMap<&Ident, &Variable> = model.variables.iter().collect();
+
+    let mut lines = vec![format!("digraph \"{}\" {{", model_name)];
+    for (ident, var) in variables.iter() {
+        let shape = if var.is_stock() {
+            " [shape=box]"
+        } else if var.is_module() {
+            " [shape=component]"
+        } else {
+            ""
+        };
+        lines.push(format!("    \"{}\"{};", ident, shape));
+    }
+    for (ident, var) in variables.iter() {
+        let dt = if kind != DependencyKind::Initial {
+            phase_dependencies(model, var, false)
+        } else {
+            BTreeSet::new()
+        };
+        let initial = if kind != DependencyKind::Dt {
+            phase_dependencies(model, var, true)
+        } else {
+            BTreeSet::new()
+        };
+        for dep in dt.union(&initial) {
+            let color = match (dt.contains(dep), initial.contains(dep)) {
+                (true, false) if kind == DependencyKind::Combined => " [color=blue]",
+                (false, true) if kind == DependencyKind::Combined => " [color=red]",
+                _ => "",
+            };
+            lines.push(format!("    \"{}\" -> \"{}\"{};", dep, ident, color));
+        }
+    }
+    lines.push("}".to_owned());
+    Ok(lines.join("\n") + "\n")
+}
+
+#[test]
+fn test_dependency_tree() {
+    use crate::testutils::{
+        sim_specs_with_units, x_aux, x_flow, x_model, x_module, x_project, x_stock,
+    };
+
+    let project = Project::from(x_project(
+        sim_specs_with_units("time"),
+        &[
+            x_model(
+                "main",
+                vec![
+                    x_stock("population", "100", &["births"], &[], None),
+                    x_flow("births", "population * fertility.rate", None),
+                    x_module("fertility", &[("food", "fertility.food")], None),
+                    x_aux("food", "time * 2", None),
+                ],
+            ),
+            x_model(
+                "fertility",
+                vec![
+                    x_aux("food", "1", None),
+                    x_aux("rate", "food / base", None),
+                    x_aux("base", "10", None),
+                ],
+            ),
+        ],
+    ));
+    let tree = |path: &str, depth: usize| dependency_tree(&project, "main", path, depth).unwrap();
+
+    assert_eq!(
+        concat!(
+            "population [stock]\n",
+            "└── births\n",
+            "    ├── fertility.rate [module output]\n",
+            "    │   ├── fertility.base\n",
+            "    │   └── food [module input]\n",
+            "    └── population [stock] (cycle)\n",
+        ),
+        tree("Population", 3)
+    );
+    // paths inside modules are followed back out through their inputs
+    assert_eq!(
+        "fertility.rate\n├── fertility.base\n└── food [module input]\n",
+        tree("fertility.rate", 5)
+    );
+    assert_eq!(
+        concat!(
+            "births\n",
+            "├── fertility.rate [module output] ...\n",
+            "└── population [stock] ...\n",
+        ),
+        tree("births", 1)
+    );
+    assert!(dependency_tree(&project, "main", "missing", 1).is_err());
+}
+
+#[test]
+fn test_dependency_graph() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
+
+    let project = Project::from(x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_stock("population", "initial_population", &["births"], &[], None),
+                x_flow("births", "population * birth_rate", None),
+                x_aux("birth_rate", "0.1", None),
+                x_aux("initial_population", "100", None),
+            ],
+        )],
+    ));
+    let graph = |kind| dependency_graph(&project, "main", kind).unwrap();
+
+    let nodes = concat!(
+        "digraph \"main\" {\n",
+        "    \"birth_rate\";\n",
+        "    \"births\";\n",
+        "    \"initial_population\";\n",
+        "    \"population\" [shape=box];\n",
+    );
+    assert_eq!(
+        format!(
+            "{}{}",
+            nodes,
+            concat!(
+                "    \"birth_rate\" -> \"births\";\n",
+                "    \"population\" -> \"births\";\n",
+                "}\n",
+            )
+        ),
+        graph(DependencyKind::Dt)
+    );
+    assert_eq!(
+        format!(
+            "{}{}",
+            nodes,
+            concat!(
+                "    \"birth_rate\" -> \"births\";\n",
+                "    \"population\" -> \"births\";\n",
+                "    \"initial_population\" -> \"population\";\n",
+                "}\n",
+            )
+        ),
+        graph(DependencyKind::Initial)
+    );
+    assert!(graph(DependencyKind::Combined)
+        .contains("    \"initial_population\" -> \"population\" [color=red];\n"));
+    assert!(graph(DependencyKind::Combined).contains("    \"birth_rate\" -> \"births\";\n"));
+    assert!(dependency_graph(&project, "nope", DependencyKind::Dt).is_err());
+}
@@ -0,0 +1,179 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+use crate::common::canonicalize;
+use crate::datamodel::{Model, Variable};
+use crate::vm::{Results, TIME_OFF};
+
+impl Results {
+    /// time_points returns the time of each saved step.
+    pub fn time_points(&self) -> Vec<f64> {
+        self.iter_rows().map(|step| step[TIME_OFF]).collect()
+    }
+
+    /// get_series returns the value of a variable at each saved step.
+    pub fn get_series(&self, ident: &str) -> Option<Vec<f64>> {
+        let off = self.offset(ident)?;
+        Some(self.iter_rows().map(|step| step[off]).collect())
+    }
+
+    /// var_names returns the name of every variable in the results, like
+    /// `population` or `hares.births`, in the order they are stored in
+    /// each step.  `time` is always first.
+    pub fn var_names(&self) -> Vec<&str> {
+        let mut names: Vec<(usize, &str)> = self
+            .offsets
+            .iter()
+            .map(|(name, off)| (*off, name.as_str()))
+            .collect();
+        names.sort_unstable();
+        names.into_iter().map(|(_, name)| name).collect()
+    }
+
+    /// columns returns the names of the variables, in the order they are
+    /// stored, along with every saved value as one column-major buffer:
+    /// the series of the `i`th variable is the `step_count` values
+    /// starting at `i * step_count`.
+    pub fn columns(&self) -> (Vec<&str>, Vec<f64>) {
+        let names = self.var_names();
+        let mut data = Vec::with_capacity(names.len() * self.step_count);
+        for name in names.iter() {
+            let off = self.offsets[*name];
+            data.extend(self.iter_rows().map(|step| step[off]));
+        }
+        (names, data)
+    }
+
+    /// differences returns the change in a variable between consecutive
+    /// saved steps, so it has one fewer element than the series.
+    pub fn differences(&self, ident: &str) -> Option<Vec<f64>> {
+        let values = self.get_series(ident)?;
+        Some(values.windows(2).map(|w| w[1] - w[0]).collect())
+    }
+
+    /// rate_of_change returns the differences divided by the time between
+    /// saved steps (which is the save step, not necessarily dt).
+    pub fn rate_of_change(&self, ident: &str) -> Option<Vec<f64>> {
+        let times = self.time_points();
+        let differences = self.differences(ident)?;
+        Some(
+            differences
+                .into_iter()
+                .zip(times.windows(2))
+                .map(|(d, t)| d / (t[1] - t[0]))
+                .collect(),
+        )
+    }
+
+    /// ratio returns numerator / denominator at each saved step, with NaN
+    /// where the denominator is zero.
+    pub fn ratio(&self, numerator: &str, denominator: &str) -> Option<Vec<f64>> {
+        let num = self.get_series(numerator)?;
+        let den = self.get_series(denominator)?;
+        Some(
+            num.into_iter()
+                .zip(den)
+                .map(|(n, d)| if d == 0.0 { f64::NAN } else { n / d })
+                .collect(),
+        )
+    }
+
+    /// phase_plot returns (x, y) pairs of two variables at each saved
+    /// step, in time order.
+    pub fn phase_plot(&self, x: &str, y: &str) -> Option<Vec<(f64, f64)>> {
+        let xs = self.get_series(x)?;
+        let ys = self.get_series(y)?;
+        Some(xs.into_iter().zip(ys).collect())
+    }
+
+    /// net_flow returns the sum of a stock's inflows minus its outflows at
+    /// each saved step.  Unlike differencing the stock, this is the exact
+    /// instantaneous rate regardless of the save step.  `model` is the
+    /// model the results were simulated from.
+    pub fn net_flow(&self, model: &Model, stock: &str) -> Option<Vec<f64>> {
+        let stock = canonicalize(stock);
+        let (inflows, outflows) = model.variables.iter().find_map(|var| match var {
+            Variable::Stock(s) if canonicalize(&s.ident) == stock => {
+                Some((&s.inflows, &s.outflows))
+            }
+            _ => None,
+        })?;
+
+        let mut net = vec![0.0; self.step_count];
+        for (flows, sign) in [(inflows, 1.0), (outflows, -1.0)] {
+            for flow in flows.iter() {
+                for (total, value) in net.iter_mut().zip(self.get_series(flow)?) {
+                    *total += sign * value;
+                }
+            }
+        }
+        Some(net)
+    }
+}
+
+#[test]
+fn test_derived_series() {
+    use crate::datamodel::{self, SimSpecs};
+    use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
+    use crate::{Project, Simulation};
+
+    let model = x_model(
+        "main",
+        vec![
+            x_stock("s", "50", &["inflow"], &["outflow"], None),
+            x_flow("inflow", "10", None),
+            x_flow("outflow", "s * 0.1", None),
+            x_aux("half", "s / 2", None),
+        ],
+    );
+    let project = x_project(
+        SimSpecs {
+            dt: datamodel::Dt::Dt(0.5),
+            save_step: Some(datamodel::Dt::Dt(1.0)),
+            stop: 3.0,
+            ..sim_specs_with_units("time")
+        },
+        std::slice::from_ref(&model),
+    );
+    let project = Project::from(project);
+    let sim = Simulation::new(&project, "main").unwrap();
+    let results = sim.run_to_end().unwrap();
+
+    assert_eq!(vec![0.0, 1.0, 2.0, 3.0], results.time_points());
+    assert!(results.get_series("missing").is_none());
+    assert_eq!(4, results.iter_rows().count());
+    assert_eq!(
+        vec![2.0; 4],
+        results
+            .ratio("s", "half")
+            .unwrap()
+            .into_iter()
+            .map(|r| (r * 1e9).round() / 1e9)
+            .collect::<Vec<_>>()
+    );
+
+    // differencing the saved stock values folds in the changes in the
+    // flows over the intermediate dt steps
+    let net_flow = results.net_flow(&model, "S").unwrap();
+    let rate_of_change = results.rate_of_change("s").unwrap();
+    assert_eq!(5.0, net_flow[0]);
+    assert_eq!(4.875, rate_of_change[0]);
+    assert_eq!(3, results.differences("s").unwrap().len());
+    assert_eq!((50.0, 5.0), results.phase_plot("s", "outflow").unwrap()[0]);
+
+    let var_names = results.var_names();
+    assert_eq!("time", var_names[0]);
+    for name in ["s", "inflow", "outflow", "half"] {
+        assert!(var_names.contains(&name));
+    }
+
+    let (names, data) = results.columns();
+    assert_eq!(var_names, names);
+    assert_eq!(names.len() * results.step_count, data.len());
+    let half = names.iter().position(|name| *name == "half").unwrap();
+    assert_eq!(
+        results.get_series("half").unwrap(),
+        data[half * results.step_count..(half + 1) * results.step_count]
+    );
+}
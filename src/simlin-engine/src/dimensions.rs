@@ -54,6 +54,10 @@ impl DimensionsContext {
         }
     }
 
+    pub(crate) fn is_dimension(&self, name: &str) -> bool {
+        self.dimensions.contains_key(name)
+    }
+
     pub(crate) fn lookup(&self, element: &str) -> Option<u32> {
         if let Some(pos) = element.find('·') {
             let dimension_name = &element[..pos];
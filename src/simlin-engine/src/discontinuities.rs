@@ -0,0 +1,166 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Finding IF-THEN-ELSEs that switch on TIME.  Runge-Kutta evaluates
+//! flows partway through each step, so a condition like `TIME > 10`
+//! flips in the middle of a step, and where it flips (and so the
+//! results) changes with dt.
+
+use std::collections::{BTreeSet, HashSet};
+
+use crate::ast::{print_eqn, Expr0};
+use crate::builtins::UntypedBuiltinFn;
+use crate::common::{canonicalize, Ident};
+use crate::complexity::{children, equation_texts, variables};
+use crate::datamodel::{Model, Project, SimMethod, Variable};
+use crate::token::LexerType;
+
+/// TimeSwitch is an IF condition that depends on TIME.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TimeSwitch {
+    pub model_name: String,
+    pub ident: Ident,
+    /// the element of an arrayed equation, if it has one per element
+    pub element: Option<String>,
+    pub condition: String,
+}
+
+fn parse(eqn: &str) -> Option<Expr0> {
+    Expr0::new(eqn, LexerType::Equation).ok().flatten()
+}
+
+fn calls_time(expr: &Expr0) -> bool {
+    match expr {
+        Expr0::App(UntypedBuiltinFn(func, args), _) if func == "time" && args.is_empty() => true,
+        _ => children(expr).into_iter().any(calls_time),
+    }
+}
+
+// uses returns the variables `expr` refers to, including TIME, which
+// parses as a call to a builtin.
+fn uses(expr: &Expr0) -> BTreeSet<Ident> {
+    let mut vars = BTreeSet::new();
+    variables(expr, &mut vars);
+    let mut idents: BTreeSet<Ident> = vars.into_iter().map(canonicalize).collect();
+    if calls_time(expr) {
+        idents.insert("time".to_owned());
+    }
+    idents
+}
+
+fn references(var: &Variable) -> BTreeSet<Ident> {
+    let mut idents = BTreeSet::new();
+    for (_, eqn) in equation_texts(var) {
+        if let Some(expr) = parse(eqn) {
+            idents.extend(uses(&expr));
+        }
+    }
+    idents
+}
+
+// time_dependent returns the auxes and flows whose values follow TIME,
+// directly or through other auxes and flows.  Stocks smooth over any
+// switch in their inputs, so they aren't followed.
+fn time_dependent(model: &Model) -> HashSet<Ident> {
+    let candidates: Vec<(Ident, BTreeSet<Ident>)> = model
+        .variables
+        .iter()
+        .filter(|var| matches!(var, Variable::Aux(_) | Variable::Flow(_)))
+        .map(|var| (canonicalize(var.get_ident()), references(var)))
+        .collect();
+
+    let mut dependent: HashSet<Ident> = HashSet::new();
+    dependent.insert("time".to_owned());
+    loop {
+        let before = dependent.len();
+        for (ident, refs) in candidates.iter() {
+            if !dependent.contains(ident) && refs.iter().any(|r| dependent.contains(r)) {
+                dependent.insert(ident.clone());
+            }
+        }
+        if dependent.len() == before {
+            return dependent;
+        }
+    }
+}
+
+fn switches<'a>(expr: &'a Expr0, dependent: &HashSet<Ident>, out: &mut Vec<&'a Expr0>) {
+    if let Expr0::If(cond, _, _, _) = expr {
+        if uses(cond).iter().any(|ident| dependent.contains(ident)) {
+            out.push(cond);
+        }
+    }
+    for child in children(expr) {
+        switches(child, dependent, out);
+    }
+}
+
+fn model_time_switches(model: &Model) -> Vec<TimeSwitch> {
+    let dependent = time_dependent(model);
+    let mut found = vec![];
+    for var in model.variables.iter() {
+        for (element, eqn) in equation_texts(var) {
+            let expr = match parse(eqn) {
+                Some(expr) => expr,
+                None => continue,
+            };
+            let mut conditions = vec![];
+            switches(&expr, &dependent, &mut conditions);
+            found.extend(conditions.into_iter().map(|cond| TimeSwitch {
+                model_name: model.name.clone(),
+                ident: canonicalize(var.get_ident()),
+                element: element.map(|e| e.to_owned()),
+                condition: print_eqn(cond),
+            }));
+        }
+    }
+    found
+}
+
+impl Project {
+    /// find_time_switches reports the IF conditions in every model that
+    /// depend on TIME, when the project is simulated with Runge-Kutta.
+    /// With Euler's method every evaluation is at the start of a step,
+    /// so these are only a problem for RK4 and Rk45.
+    pub fn find_time_switches(&self) -> Vec<TimeSwitch> {
+        if self.sim_specs.sim_method == SimMethod::Euler {
+            return vec![];
+        }
+        self.models.iter().flat_map(model_time_switches).collect()
+    }
+}
+
+#[test]
+fn test_find_time_switches() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_stock("s", "0", &["f"], &[], None),
+                x_flow("f", "IF s > 10 THEN 0 ELSE 1", None),
+                x_aux("late", "TIME > 5", None),
+                x_aux("direct", "IF TIME >= 3 THEN 2 ELSE 1", None),
+                x_aux(
+                    "indirect",
+                    "IF late THEN 2 ELSE IF s > 1 THEN 1 ELSE 0",
+                    None,
+                ),
+                x_aux("by_stock", "IF s > 2 THEN 1 ELSE 0", None),
+            ],
+        )],
+    );
+
+    assert!(project.find_time_switches().is_empty());
+
+    project.sim_specs.sim_method = SimMethod::RungeKutta4;
+    let switches = project.find_time_switches();
+    let found: Vec<(&str, &str)> = switches
+        .iter()
+        .map(|s| (s.ident.as_str(), s.condition.as_str()))
+        .collect();
+    assert_eq!(vec![("direct", "time() >= 3"), ("indirect", "late")], found);
+}
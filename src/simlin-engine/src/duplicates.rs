@@ -0,0 +1,226 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+use std::collections::HashMap;
+
+use crate::ast::{print_eqn, BinaryOp, Expr0, IndexExpr0};
+use crate::builtins::UntypedBuiltinFn;
+use crate::common::{canonicalize, Ident};
+use crate::datamodel::{Equation, Model, Project, Variable};
+use crate::token::LexerType;
+
+/// DuplicateGroup is a set of variables in a model whose equations are
+/// structurally identical, and could be consolidated into one.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DuplicateGroup {
+    pub model_name: String,
+    /// the shared, normalized equation.  For near-duplicates constants
+    /// are replaced by `#`.
+    pub equation: String,
+    /// true if the equations are identical, false if they differ only
+    /// by the value of constants
+    pub is_exact: bool,
+    pub idents: Vec<Ident>,
+}
+
+// normalize returns an equivalent expression where constants are
+// printed the same no matter how they were written, and the operands of
+// commutative operators are in a stable order.
+fn normalize(expr: Expr0, mask_constants: bool) -> Expr0 {
+    let norm = |e: Box<Expr0>| Box::new(normalize(*e, mask_constants));
+    match expr {
+        Expr0::Const(_, n, loc) => {
+            let s = if mask_constants {
+                "#".to_owned()
+            } else {
+                format!("{}", n)
+            };
+            Expr0::Const(s, n, loc)
+        }
+        Expr0::Var(id, loc) => Expr0::Var(id, loc),
+        Expr0::App(UntypedBuiltinFn(func, args), loc) => {
+            let args = args
+                .into_iter()
+                .map(|e| normalize(e, mask_constants))
+                .collect();
+            Expr0::App(UntypedBuiltinFn(func, args), loc)
+        }
+        Expr0::Subscript(id, args, loc) => {
+            let args = args
+                .into_iter()
+                .map(|arg| match arg {
+                    IndexExpr0::Range(l, r, loc) => IndexExpr0::Range(
+                        normalize(l, mask_constants),
+                        normalize(r, mask_constants),
+                        loc,
+                    ),
+                    IndexExpr0::Expr(e) => IndexExpr0::Expr(normalize(e, mask_constants)),
+                    arg => arg,
+                })
+                .collect();
+            Expr0::Subscript(id, args, loc)
+        }
+        Expr0::Op1(op, l, loc) => Expr0::Op1(op, norm(l), loc),
+        Expr0::Op2(op, l, r, loc) => {
+            let (l, r) = (norm(l), norm(r));
+            let is_commutative = matches!(
+                op,
+                BinaryOp::Add
+                    | BinaryOp::Mul
+                    | BinaryOp::Eq
+                    | BinaryOp::Neq
+                    | BinaryOp::And
+                    | BinaryOp::Or
+            );
+            if is_commutative && print_eqn(&l) > print_eqn(&r) {
+                Expr0::Op2(op, r, l, loc)
+            } else {
+                Expr0::Op2(op, l, r, loc)
+            }
+        }
+        Expr0::If(cond, t, f, loc) => Expr0::If(norm(cond), norm(t), norm(f), loc),
+    }
+}
+
+fn references_variables(expr: &Expr0) -> bool {
+    match expr {
+        Expr0::Const(_, _, _) => false,
+        Expr0::Var(_, _) | Expr0::Subscript(_, _, _) => true,
+        Expr0::App(UntypedBuiltinFn(_, args), _) => args.iter().any(references_variables),
+        Expr0::Op1(_, l, _) => references_variables(l),
+        Expr0::Op2(_, l, r, _) => references_variables(l) || references_variables(r),
+        Expr0::If(cond, t, f, _) => {
+            references_variables(cond) || references_variables(t) || references_variables(f)
+        }
+    }
+}
+
+// equation_keys returns the (exact, near) keys of a variable's equation,
+// or None if the variable shouldn't be considered.  Stocks (whose
+// equations are initial values), lookups and equations that don't
+// reference any variables aren't interesting candidates.
+fn equation_keys(var: &Variable) -> Option<(String, String)> {
+    let equation = match var {
+        Variable::Flow(flow) if flow.gf.is_none() => &flow.equation,
+        Variable::Aux(aux) if aux.gf.is_none() => &aux.equation,
+        _ => return None,
+    };
+    let (prefix, eqn) = match equation {
+        Equation::Scalar(eqn, _) => (String::new(), eqn),
+        Equation::ApplyToAll(dims, eqn, _) => {
+            let dims: Vec<_> = dims.iter().map(|d| canonicalize(d)).collect();
+            (format!("[{}] ", dims.join(", ")), eqn)
+        }
+        Equation::Arrayed(_, _) => return None,
+    };
+    let expr = Expr0::new(eqn, LexerType::Equation).ok().flatten()?;
+    if !references_variables(&expr) {
+        return None;
+    }
+
+    let exact = print_eqn(&normalize(expr.clone(), false));
+    let near = print_eqn(&normalize(expr, true));
+    Some((
+        format!("{}{}", prefix, exact),
+        format!("{}{}", prefix, near),
+    ))
+}
+
+fn model_duplicates(model: &Model) -> Vec<DuplicateGroup> {
+    let keyed: Vec<(Ident, String, String)> = model
+        .variables
+        .iter()
+        .filter_map(|var| {
+            equation_keys(var).map(|(exact, near)| (canonicalize(var.get_ident()), exact, near))
+        })
+        .collect();
+
+    let mut exact_groups: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut near_groups: HashMap<&str, Vec<(&str, &str)>> = HashMap::new();
+    for (ident, exact, near) in keyed.iter() {
+        exact_groups.entry(exact).or_default().push(ident);
+        near_groups.entry(near).or_default().push((ident, exact));
+    }
+
+    let mut groups = vec![];
+    // iterate in variable order so that results are stable
+    for (_, exact, near) in keyed.iter() {
+        if let Some(idents) = exact_groups.remove(exact.as_str()) {
+            if idents.len() > 1 {
+                groups.push(DuplicateGroup {
+                    model_name: model.name.clone(),
+                    equation: exact.clone(),
+                    is_exact: true,
+                    idents: idents.into_iter().map(|id| id.to_owned()).collect(),
+                });
+            }
+        }
+        if let Some(members) = near_groups.remove(near.as_str()) {
+            // only near-duplicates if the equations aren't all identical,
+            // in which case they were reported above
+            if members.iter().any(|(_, e)| e != exact) {
+                groups.push(DuplicateGroup {
+                    model_name: model.name.clone(),
+                    equation: near.clone(),
+                    is_exact: false,
+                    idents: members.into_iter().map(|(id, _)| id.to_owned()).collect(),
+                });
+            }
+        }
+    }
+
+    groups
+}
+
+impl Project {
+    /// find_duplicate_equations reports groups of flows and auxiliaries
+    /// within each model that have identical equations, or equations that
+    /// differ only by constants.
+    pub fn find_duplicate_equations(&self) -> Vec<DuplicateGroup> {
+        self.models.iter().flat_map(model_duplicates).collect()
+    }
+}
+
+#[test]
+fn test_find_duplicate_equations() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
+
+    let project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_stock("s", "a * 2", &["f1"], &["f2"], None),
+                x_aux("a", "3", None),
+                x_aux("b", "3", None),
+                x_flow("f1", "s * a", None),
+                x_flow("f2", "A*S", None),
+                x_aux("c", "a * 0.5 + s", None),
+                x_aux("d", "s + a * 0.50", None),
+                x_aux("e", "s + a * 0.7", None),
+                x_aux("g", "max(s, 1)", None),
+                x_aux("h", "max(s, 2)", None),
+            ],
+        )],
+    );
+
+    let groups = project.find_duplicate_equations();
+    let summary: Vec<_> = groups
+        .iter()
+        .map(|g| (g.is_exact, g.equation.as_str(), g.idents.clone()))
+        .collect();
+    assert_eq!(
+        vec![
+            (true, "a * s", vec!["f1".to_owned(), "f2".to_owned()]),
+            (true, "0.5 * a + s", vec!["c".to_owned(), "d".to_owned()]),
+            (
+                false,
+                "# * a + s",
+                vec!["c".to_owned(), "d".to_owned(), "e".to_owned()]
+            ),
+            (false, "max(s, #)", vec!["g".to_owned(), "h".to_owned()]),
+        ],
+        summary
+    );
+}
@@ -0,0 +1,523 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+#[cfg(feature = "json")]
+use ::serde::{Deserialize, Serialize};
+use prost::Message;
+
+use crate::common::Result;
+use crate::datamodel::{self, SimSpecs};
+use crate::stubs::{Stub, Stubs};
+use crate::vm::{Results, Vm};
+use crate::{project_io, serde, sim_err, Project, Simulation};
+
+pub const ENGINE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Override sets a variable to a constant for a single run.
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct Override {
+    pub ident: String,
+    pub value: f64,
+}
+
+/// Run is one member of an ensemble.  The engine's builtins are
+/// deterministic; `seed` records the seed used to generate any random
+/// inputs (like the override values) so that they can be regenerated.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Run {
+    pub seed: u64,
+    pub overrides: Vec<Override>,
+}
+
+/// Manifest records everything needed to reproduce an ensemble of
+/// simulation runs.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Manifest {
+    pub engine_version: String,
+    pub project_hash: u64,
+    pub model_name: String,
+    pub sim_specs: SimSpecs,
+    pub runs: Vec<Run>,
+}
+
+/// project_hash returns the 64-bit FNV-1a hash of the protobuf encoding
+/// of the project.
+pub fn project_hash(project: &datamodel::Project) -> u64 {
+    fnv1a(&serde::serialize(project).encode_to_vec())
+}
+
+fn fnv1a(buf: &[u8]) -> u64 {
+    buf.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ (*b as u64)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Rng is a xorshift64* generator, enough to draw samples and drive
+/// searches reproducibly from a seed.
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        // splitmix64 spreads small seeds out, and never yields 0
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        Rng((z ^ (z >> 31)) | 1)
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // next_f64 returns a number in [0, 1)
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/// ContentHash identifies what a project contains, independent of the
+/// order its models and variables happen to be listed in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ContentHash {
+    /// semantic changes only when something that could affect a
+    /// simulation does: diagrams, saved graphs, and the file the project
+    /// was imported from are left out.
+    pub semantic: u64,
+    /// full changes when anything in the project does.
+    pub full: u64,
+}
+
+impl datamodel::Project {
+    /// content_hash returns 64-bit FNV-1a hashes of the canonical
+    /// protobuf encoding of the project.  When only `full` differs
+    /// between two versions, only the layout changed, and anything
+    /// compiled from the first version can be reused for the second.
+    pub fn content_hash(&self) -> ContentHash {
+        let full = fnv1a(&serde::serialize_canonical(self).encode_to_vec());
+
+        let mut project = self.clone();
+        project.source = None;
+        project.import_report.clear();
+        for model in project.models.iter_mut() {
+            model.views.clear();
+            model.graphs.clear();
+        }
+        let semantic = fnv1a(&serde::serialize_canonical(&project).encode_to_vec());
+
+        ContentHash { semantic, full }
+    }
+}
+
+impl Manifest {
+    pub fn new(project: &datamodel::Project, model_name: &str, runs: Vec<Run>) -> Self {
+        Manifest {
+            engine_version: ENGINE_VERSION.to_owned(),
+            project_hash: project_hash(project),
+            model_name: model_name.to_owned(),
+            sim_specs: project.sim_specs.clone(),
+            runs,
+        }
+    }
+
+    /// execute simulates each run in the manifest, in order.  It is an
+    /// error if the project isn't the one the manifest was created from.
+    pub fn execute(&self, project: &datamodel::Project) -> Result<Vec<Results>> {
+        if project_hash(project) != self.project_hash {
+            return sim_err!(
+                ManifestMismatch,
+                format!("project hash doesn't match {:016x}", self.project_hash)
+            );
+        }
+        self.runs
+            .iter()
+            .map(|run| {
+                let mut stubs = Stubs::new();
+                for o in run.overrides.iter() {
+                    stubs.stub(&self.model_name, &o.ident, Stub::Constant(o.value));
+                }
+                let project = Project::from_with_stubs(project.clone(), &stubs)?;
+                let sim = Simulation::new(&project, &self.model_name)?;
+                let mut vm = Vm::new(sim.compile()?)?;
+                vm.run_to_end()?;
+                Ok(vm.into_results())
+            })
+            .collect()
+    }
+}
+
+/// run_ensemble simulates each run and returns the results along with
+/// a manifest that can be used to reproduce them.
+pub fn run_ensemble(
+    project: &datamodel::Project,
+    model_name: &str,
+    runs: Vec<Run>,
+) -> Result<(Manifest, Vec<Results>)> {
+    let manifest = Manifest::new(project, model_name, runs);
+    let results = manifest.execute(project)?;
+    Ok((manifest, results))
+}
+
+/// UncertainInput is a variable whose value isn't known, drawn from a
+/// uniform distribution between `min` and `max` in each run.
+#[derive(Clone, PartialEq, Debug)]
+pub struct UncertainInput {
+    pub ident: String,
+    pub min: f64,
+    pub max: f64,
+}
+
+/// FirstOrderIndices are the first-order Sobol indices of an output: at
+/// each time, the share of the output's variance across runs explained
+/// by each input on its own.
+#[derive(Clone, PartialEq, Debug)]
+pub struct FirstOrderIndices {
+    pub ident: String,
+    pub times: Vec<f64>,
+    /// indexed by input, then by time
+    pub indices: Vec<Vec<f64>>,
+}
+
+/// sobol_runs returns the runs for estimating first-order indices with
+/// Saltelli's scheme: two independent samples, A and B, of `samples`
+/// runs each, and for each input a copy of A with that input's values
+/// taken from B.  The runs are ordered A, B, and then the copies, input
+/// by input, for `samples * (inputs.len() + 2)` runs in total.
+pub fn sobol_runs(inputs: &[UncertainInput], samples: usize, seed: u64) -> Vec<Run> {
+    let mut rng = Rng::new(seed);
+    let mut draw = || -> Vec<f64> {
+        inputs
+            .iter()
+            .map(|input| input.min + rng.next_f64() * (input.max - input.min))
+            .collect()
+    };
+    let a: Vec<Vec<f64>> = (0..samples).map(|_| draw()).collect();
+    let b: Vec<Vec<f64>> = (0..samples).map(|_| draw()).collect();
+
+    let run = |values: &[f64]| Run {
+        seed,
+        overrides: inputs
+            .iter()
+            .zip(values.iter())
+            .map(|(input, value)| Override {
+                ident: input.ident.clone(),
+                value: *value,
+            })
+            .collect(),
+    };
+    let mut runs: Vec<Run> = a.iter().chain(b.iter()).map(|values| run(values)).collect();
+    for i in 0..inputs.len() {
+        for (a, b) in a.iter().zip(b.iter()) {
+            let mut values = a.clone();
+            values[i] = b[i];
+            runs.push(run(&values));
+        }
+    }
+    runs
+}
+
+/// first_order_indices estimates the first-order Sobol index of each of
+/// `inputs` on the output `ident` at each time, from the results of the
+/// runs returned by `sobol_runs`.  The index of input i is
+/// mean((f(B) - E(Y)) * (f(AB_i) - f(A))) / Var(Y), Saltelli et al's
+/// (2010) estimator with f(B) centered to cut its variance, where the
+/// mean and variance of Y are over the runs in A and B.  Times where the
+/// output doesn't vary have indices of 0.  Estimates are noisy with few
+/// samples, and can fall slightly outside of 0 to 1.
+pub fn first_order_indices(
+    inputs: &[UncertainInput],
+    samples: usize,
+    results: &[Results],
+    ident: &str,
+) -> Result<FirstOrderIndices> {
+    if samples == 0 || results.len() != samples * (inputs.len() + 2) {
+        return sim_err!(
+            Generic,
+            format!(
+                "expected {} runs for {} samples, got {}",
+                samples * (inputs.len() + 2),
+                samples,
+                results.len()
+            )
+        );
+    }
+    let series = results
+        .iter()
+        .map(|r| r.get_series(ident))
+        .collect::<Option<Vec<Vec<f64>>>>();
+    let series = match series {
+        Some(series) => series,
+        None => return sim_err!(DoesNotExist, ident.to_owned()),
+    };
+    let (a, rest) = series.split_at(samples);
+    let (b, ab) = rest.split_at(samples);
+
+    let times = results[0].time_points();
+    let mut indices = vec![vec![0.0; times.len()]; inputs.len()];
+    for t in 0..times.len() {
+        let n = (2 * samples) as f64;
+        let mean = a.iter().chain(b.iter()).map(|y| y[t]).sum::<f64>() / n;
+        let variance = a
+            .iter()
+            .chain(b.iter())
+            .map(|y| (y[t] - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        if variance <= 0.0 {
+            continue;
+        }
+        for (i, index) in indices.iter_mut().enumerate() {
+            let ab = &ab[i * samples..(i + 1) * samples];
+            let v = a
+                .iter()
+                .zip(b.iter())
+                .zip(ab.iter())
+                .map(|((a, b), ab)| (b[t] - mean) * (ab[t] - a[t]))
+                .sum::<f64>()
+                / samples as f64;
+            index[t] = v / variance;
+        }
+    }
+
+    Ok(FirstOrderIndices {
+        ident: ident.to_owned(),
+        times,
+        indices,
+    })
+}
+
+/// variance_decomposition runs an ensemble over `inputs` and reports
+/// how much of the variance of each of `outputs` each input explains on
+/// its own, over time.  Along with the indices it returns the manifest
+/// of the `samples * (inputs.len() + 2)` runs it simulated.
+pub fn variance_decomposition(
+    project: &datamodel::Project,
+    model_name: &str,
+    inputs: &[UncertainInput],
+    samples: usize,
+    seed: u64,
+    outputs: &[&str],
+) -> Result<(Manifest, Vec<FirstOrderIndices>)> {
+    if inputs.is_empty() {
+        return sim_err!(Generic, "no uncertain inputs".to_owned());
+    }
+    if let Some(input) = inputs
+        .iter()
+        .find(|input| !input.min.is_finite() || !input.max.is_finite() || input.min > input.max)
+    {
+        return sim_err!(Generic, format!("{} has an invalid range", input.ident));
+    }
+    let runs = sobol_runs(inputs, samples, seed);
+    let (manifest, results) = run_ensemble(project, model_name, runs)?;
+    let indices = outputs
+        .iter()
+        .map(|ident| first_order_indices(inputs, samples, &results, ident))
+        .collect::<Result<Vec<_>>>()?;
+    Ok((manifest, indices))
+}
+
+impl From<Manifest> for project_io::Manifest {
+    fn from(manifest: Manifest) -> Self {
+        project_io::Manifest {
+            engine_version: manifest.engine_version,
+            project_hash: manifest.project_hash,
+            model_name: manifest.model_name,
+            sim_specs: Some(manifest.sim_specs.into()),
+            runs: manifest
+                .runs
+                .into_iter()
+                .map(|run| project_io::manifest::Run {
+                    seed: run.seed,
+                    overrides: run
+                        .overrides
+                        .into_iter()
+                        .map(|o| project_io::manifest::Override {
+                            ident: o.ident,
+                            value: o.value,
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<project_io::Manifest> for Manifest {
+    fn from(manifest: project_io::Manifest) -> Self {
+        Manifest {
+            engine_version: manifest.engine_version,
+            project_hash: manifest.project_hash,
+            model_name: manifest.model_name,
+            sim_specs: manifest.sim_specs.map(SimSpecs::from).unwrap_or_default(),
+            runs: manifest
+                .runs
+                .into_iter()
+                .map(|run| Run {
+                    seed: run.seed,
+                    overrides: run
+                        .overrides
+                        .into_iter()
+                        .map(|o| Override {
+                            ident: o.ident,
+                            value: o.value,
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[test]
+fn test_ensemble_manifest() {
+    use crate::common::ErrorCode;
+    use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_stock("s", "0", &["f"], &[], None),
+                x_flow("f", "rate", None),
+                x_aux("rate", "1", None),
+            ],
+        )],
+    );
+    project.sim_specs.stop = 4.0;
+
+    let runs = (0..3)
+        .map(|i| Run {
+            seed: 42 + i,
+            overrides: vec![Override {
+                ident: "rate".to_owned(),
+                value: i as f64,
+            }],
+        })
+        .collect();
+    let (manifest, results) = run_ensemble(&project, "main", runs).unwrap();
+    let finals: Vec<f64> = results
+        .iter()
+        .map(|r| *r.get_series("s").unwrap().last().unwrap())
+        .collect();
+    assert_eq!(vec![0.0, 4.0, 8.0], finals);
+    assert_eq!(ENGINE_VERSION, manifest.engine_version);
+
+    let decoded = Manifest::from(
+        project_io::Manifest::decode(
+            &*project_io::Manifest::from(manifest.clone()).encode_to_vec(),
+        )
+        .unwrap(),
+    );
+    assert_eq!(manifest, decoded);
+    let rerun: Vec<f64> = decoded
+        .execute(&project)
+        .unwrap()
+        .iter()
+        .map(|r| *r.get_series("s").unwrap().last().unwrap())
+        .collect();
+    assert_eq!(finals, rerun);
+
+    project.sim_specs.stop = 5.0;
+    let err = decoded.execute(&project).unwrap_err();
+    assert_eq!(ErrorCode::ManifestMismatch, err.code);
+}
+
+#[test]
+fn test_content_hash() {
+    use crate::datamodel::{Graph, GraphKind};
+    use crate::testutils::{sim_specs_with_units, x_aux, x_model, x_project};
+
+    let project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![x_aux("a", "1", None), x_aux("b", "a * 2", None)],
+        )],
+    );
+    let hash = project.content_hash();
+    assert_eq!(hash, project.clone().content_hash());
+
+    // the order variables are listed in doesn't matter
+    let mut reordered = project.clone();
+    reordered.models[0].variables.reverse();
+    assert_eq!(hash, reordered.content_hash());
+
+    // a saved graph is a layout change
+    let mut layout = project.clone();
+    layout.models[0].graphs.push(Graph {
+        title: "a".to_owned(),
+        kind: GraphKind::TimeSeries,
+        plots: vec![],
+        interval: None,
+    });
+    let layout_hash = layout.content_hash();
+    assert_eq!(hash.semantic, layout_hash.semantic);
+    assert_ne!(hash.full, layout_hash.full);
+
+    let mut equations = project;
+    equations.models[0].variables[0] = x_aux("a", "2", None);
+    let equations_hash = equations.content_hash();
+    assert_ne!(hash.semantic, equations_hash.semantic);
+    assert_ne!(hash.full, equations_hash.full);
+}
+
+#[test]
+fn test_variance_decomposition() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_model, x_project};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_aux("a", "0.5", None),
+                x_aux("b", "0.5", None),
+                x_aux("y", "a * TIME + b", None),
+                x_aux("c", "1", None),
+            ],
+        )],
+    );
+    project.sim_specs.stop = 3.0;
+
+    let inputs = vec![
+        UncertainInput {
+            ident: "a".to_owned(),
+            min: 0.0,
+            max: 1.0,
+        },
+        UncertainInput {
+            ident: "b".to_owned(),
+            min: 0.0,
+            max: 1.0,
+        },
+    ];
+    let samples = 2000;
+    let (manifest, indices) =
+        variance_decomposition(&project, "main", &inputs, samples, 7, &["y"]).unwrap();
+    assert_eq!(samples * 4, manifest.runs.len());
+    assert_eq!(manifest.runs, sobol_runs(&inputs, samples, 7));
+
+    // at time 0 y is b; by time 3 a's variance is 9 times b's
+    let y = &indices[0];
+    assert_eq!(vec![0.0, 1.0, 2.0, 3.0], y.times);
+    assert!(y.indices[0][0].abs() < 0.15);
+    assert!((y.indices[1][0] - 1.0).abs() < 0.15);
+    assert!((y.indices[0][3] - 0.9).abs() < 0.15);
+    assert!((y.indices[1][3] - 0.1).abs() < 0.15);
+
+    // a constant output has nothing to decompose
+    let (_, indices) = variance_decomposition(&project, "main", &inputs, 10, 7, &["c"]).unwrap();
+    assert!(indices[0].indices.iter().flatten().all(|s| *s == 0.0));
+
+    assert!(variance_decomposition(&project, "main", &inputs, 10, 7, &["nope"]).is_err());
+    assert!(variance_decomposition(&project, "main", &[], 10, 7, &["y"]).is_err());
+}
@@ -0,0 +1,113 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Evaluating a single expression, like `max(a, b) * 2`, without a model.
+
+use crate::ast::Expr0;
+use crate::common::{canonicalize, Error, ErrorCode, ErrorKind, Result};
+use crate::datamodel;
+use crate::model_err;
+use crate::stubs::{Stub, Stubs};
+use crate::templates::aux;
+use crate::token::LexerType;
+use crate::vm::Vm;
+use crate::{Project, Simulation};
+
+/// evaluate returns the value of the expression `expr`, where the
+/// variables it refers to have the values in `env`.  The expression is
+/// compiled and run by the same code as a simulation, at time 0: builtins
+/// that depend on the past, like `smth1` or `delay1`, return their
+/// initial values.
+pub fn evaluate(expr: &str, env: &[(&str, f64)]) -> Result<f64> {
+    let equation_error =
+        |code: ErrorCode| Err(Error::new(ErrorKind::Variable, code, Some(expr.to_owned())));
+    match Expr0::new(expr, LexerType::Equation) {
+        Ok(Some(_)) => {}
+        Ok(None) => return equation_error(ErrorCode::EmptyEquation),
+        Err(errs) => {
+            return equation_error(
+                errs.first()
+                    .map(|err| err.code)
+                    .unwrap_or(ErrorCode::Generic),
+            )
+        }
+    }
+
+    let idents: Vec<String> = env.iter().map(|(ident, _)| canonicalize(ident)).collect();
+    let mut result = "result".to_owned();
+    while idents.contains(&result) {
+        result.push('_');
+    }
+
+    let mut variables: Vec<datamodel::Variable> =
+        idents.iter().map(|ident| aux(ident, "0")).collect();
+    variables.push(aux(&result, expr));
+    let mut stubs = Stubs::new();
+    for (ident, (_, value)) in idents.iter().zip(env.iter()) {
+        stubs.stub("main", ident, Stub::Constant(*value));
+    }
+    let project = datamodel::Project {
+        name: "evaluate".to_owned(),
+        sim_specs: datamodel::SimSpecs {
+            start: 0.0,
+            stop: 0.0,
+            ..Default::default()
+        },
+        dimensions: vec![],
+        units: vec![],
+        models: vec![datamodel::Model {
+            name: "main".to_owned(),
+            variables,
+            views: vec![],
+            graphs: vec![],
+            groups: vec![],
+            variable_index: Default::default(),
+        }],
+        constants: vec![],
+        tests: vec![],
+        source: None,
+        import_report: vec![],
+    };
+
+    let project = Project::from_with_stubs(project, &stubs)?;
+    if let Some(errs) = project.models["main"].variables[&result].equation_errors() {
+        return equation_error(errs[0].code);
+    }
+    let sim = Simulation::new(&project, "main")?;
+    let mut vm = Vm::new(sim.compile()?)?;
+    vm.run_to_end()?;
+    match vm
+        .into_results()
+        .get_series(&result)
+        .and_then(|s| s.first().copied())
+    {
+        Some(value) => Ok(value),
+        None => model_err!(DoesNotExist, result),
+    }
+}
+
+#[test]
+fn test_evaluate() {
+    assert_eq!(Ok(7.0), evaluate("1 + 2 * 3", &[]));
+    assert_eq!(
+        Ok(6.0),
+        evaluate("MAX(a, b) * 2", &[("a", 1.0), ("b", 3.0)])
+    );
+    assert_eq!(
+        Ok(0.5),
+        evaluate(
+            "Birth_Rate / result",
+            &[("birth rate", 1.0), ("result", 2.0)]
+        )
+    );
+    assert_eq!(Ok(1.0), evaluate("if x > 0 then 1 else -1", &[("x", 0.5)]));
+    assert_eq!(Ok(4.0), evaluate("SMTH1(x, 3)", &[("x", 4.0)]));
+    assert_eq!(Ok(0.25), evaluate("2^-2", &[]));
+    assert!(evaluate("x", &[("x", f64::NAN)]).unwrap().is_nan());
+
+    let code = |expr: &str| evaluate(expr, &[]).unwrap_err().code;
+    assert_eq!(ErrorCode::EmptyEquation, code(""));
+    assert_eq!(ErrorCode::UnknownDependency, code("missing + 1"));
+    assert!(evaluate("1 +", &[]).is_err());
+}
@@ -0,0 +1,163 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Scheduled changes to constants, like a policy that starts part way
+//! through a run.  Changes take effect exactly at their times rather than
+//! at the next time step: the simulation takes a partial time step up to
+//! any change that falls between steps.
+
+use std::collections::BTreeMap;
+
+#[cfg(feature = "json")]
+use serde::{Deserialize, Serialize};
+
+use crate::common::{canonicalize, Result};
+use crate::datamodel;
+use crate::model_err;
+use crate::stubs::{Stub, Stubs};
+use crate::vm::{Results, Vm};
+use crate::{Project, Simulation};
+
+/// Change sets the variable at `path` (like `hares.birth_rate`) to
+/// `value` from `time` on.
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct Change {
+    pub time: f64,
+    pub path: String,
+    pub value: f64,
+}
+
+/// Schedule is a set of changes to apply during a run.
+#[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct Schedule {
+    pub changes: Vec<Change>,
+}
+
+impl Schedule {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// change schedules the variable at `path` to be set to `value`
+    /// from `time` on.
+    pub fn change(&mut self, time: f64, path: &str, value: f64) -> &mut Self {
+        self.changes.push(Change {
+            time,
+            path: path.to_owned(),
+            value,
+        });
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// stub adds stubs for the changed variables, relative to the model
+    /// `model_name`, to `stubs`.
+    pub fn stub(&self, model_name: &str, stubs: &mut Stubs) -> Result<()> {
+        let mut steps: BTreeMap<String, (String, Vec<(f64, f64)>)> = BTreeMap::new();
+        for change in self.changes.iter() {
+            if !change.time.is_finite() || !change.value.is_finite() {
+                return model_err!(
+                    Generic,
+                    format!(
+                        "change to {} at time {} must have a finite time and value",
+                        change.path, change.time
+                    )
+                );
+            }
+            steps
+                .entry(canonicalize(&change.path))
+                .or_insert_with(|| (change.path.clone(), vec![]))
+                .1
+                .push((change.time, change.value));
+        }
+        for (path, steps) in steps.into_values() {
+            stubs.stub_path(model_name, &path, Stub::Steps(steps));
+        }
+        Ok(())
+    }
+
+    /// times returns the times of the scheduled changes.
+    pub fn times(&self) -> Vec<f64> {
+        self.changes.iter().map(|change| change.time).collect()
+    }
+}
+
+/// simulate runs the model `model_name` with the scheduled changes.
+pub fn simulate(
+    project: &datamodel::Project,
+    model_name: &str,
+    schedule: &Schedule,
+) -> Result<Results> {
+    let mut stubs = Stubs::new();
+    schedule.stub(model_name, &mut stubs)?;
+    let project = Project::from_with_stubs(project.clone(), &stubs)?;
+    let mut sim = Simulation::new(&project, model_name)?;
+    sim.set_event_times(&schedule.times());
+    let mut vm = Vm::new(sim.compile()?)?;
+    vm.run_to_end()?;
+    Ok(vm.into_results())
+}
+
+#[test]
+fn test_schedule() {
+    use crate::common::ErrorCode;
+    use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_stock("s", "0", &["f"], &[], None),
+                x_flow("f", "rate", None),
+                x_aux("rate", "1", None),
+            ],
+        )],
+    );
+    project.sim_specs.stop = 4.0;
+    project.sim_specs.dt = datamodel::Dt::Dt(1.0);
+
+    let series = |results: &Results, ident: &str| {
+        let off = results.offsets[ident];
+        results
+            .iter_rows()
+            .map(|step| step[off])
+            .collect::<Vec<_>>()
+    };
+
+    // the change lands between time steps 1 and 2, and the interpreter
+    // and VM agree on the result
+    let mut schedule = Schedule::new();
+    schedule.change(1.5, "Rate", 3.0);
+    let results = simulate(&project, "main", &schedule).unwrap();
+    assert_eq!(vec![0.0, 1.0, 3.0, 6.0, 9.0], series(&results, "s"));
+    assert_eq!(vec![1.0, 1.0, 3.0, 3.0, 3.0], series(&results, "rate"));
+
+    let mut stubs = Stubs::new();
+    schedule.stub("main", &mut stubs).unwrap();
+    let sim_project = Project::from_with_stubs(project.clone(), &stubs).unwrap();
+    let mut sim = Simulation::new(&sim_project, "main").unwrap();
+    sim.set_event_times(&schedule.times());
+    let interpreted = sim.run_to_end().unwrap();
+    assert_eq!(series(&results, "s"), series(&interpreted, "s"));
+
+    // staged changes, one of which is on a time step
+    schedule.change(3.0, "rate", 0.0);
+    let results = simulate(&project, "main", &schedule).unwrap();
+    assert_eq!(vec![0.0, 1.0, 3.0, 6.0, 6.0], series(&results, "s"));
+
+    let err = |path: &str, time: f64| {
+        let mut schedule = Schedule::new();
+        schedule.change(time, path, 1.0);
+        simulate(&project, "main", &schedule).unwrap_err().code
+    };
+    assert_eq!(ErrorCode::NotSimulatable, err("s", 1.0));
+    assert_eq!(ErrorCode::DoesNotExist, err("missing", 1.0));
+    assert_eq!(ErrorCode::Generic, err("rate", f64::NAN));
+}
@@ -0,0 +1,162 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Freezing variables at their values from a baseline run turns the
+//! feedback through them into exogenous inputs: when the model is re-run
+//! with a change, the difference from a run without freezing shows how
+//! much the change's effect depends on the frozen feedback paths.
+
+use crate::common::Result;
+use crate::datamodel;
+use crate::model_err;
+use crate::resample::Interpolation;
+use crate::stubs::{Stub, Stubs};
+use crate::vm::{Results, Vm, TIME_OFF};
+use crate::{Project, Simulation};
+
+impl Results {
+    /// timed_series returns the (time, value) of the variable at `path` at
+    /// each saved step.
+    pub fn timed_series(&self, path: &str) -> Option<Vec<(f64, f64)>> {
+        let off = self.offset(path)?;
+        Some(
+            self.iter_rows()
+                .map(|step| (step[TIME_OFF], step[off]))
+                .collect(),
+        )
+    }
+}
+
+/// freeze_stubs returns stubs that replace each of the (scalar) variables
+/// at `paths`, relative to the model `model_name`, with their values in
+/// `baseline`.  Values between saved steps are interpolated.
+pub fn freeze_stubs(baseline: &Results, model_name: &str, paths: &[&str]) -> Result<Stubs> {
+    let mut stubs = Stubs::new();
+    for path in paths.iter() {
+        let series = match baseline.timed_series(path) {
+            Some(series) => series,
+            None => return model_err!(DoesNotExist, path.to_string()),
+        };
+        stubs.stub_path(model_name, path, Stub::Series(series));
+    }
+    Ok(stubs)
+}
+
+/// simulate_with_stubs simulates the model `model_name` of the project
+/// with the given variables replaced.
+pub fn simulate_with_stubs(
+    project: &datamodel::Project,
+    model_name: &str,
+    stubs: &Stubs,
+) -> Result<Results> {
+    let project = Project::from_with_stubs(project.clone(), stubs)?;
+    let sim = Simulation::new(&project, model_name)?;
+    let mut vm = Vm::new(sim.compile()?)?;
+    vm.run_to_end()?;
+    Ok(vm.into_results())
+}
+
+/// simulate_frozen simulates the model with the variables at `paths`
+/// frozen at their values in `baseline`, and the variables in
+/// `overrides` set to constants.
+pub fn simulate_frozen(
+    project: &datamodel::Project,
+    model_name: &str,
+    baseline: &Results,
+    paths: &[&str],
+    overrides: &[(&str, f64)],
+) -> Result<Results> {
+    let mut stubs = freeze_stubs(baseline, model_name, paths)?;
+    for (path, value) in overrides.iter() {
+        stubs.stub_path(model_name, path, Stub::Constant(*value));
+    }
+    simulate_with_stubs(project, model_name, &stubs)
+}
+
+/// Difference is how a variable's behavior differs between two runs.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Difference {
+    pub path: String,
+    /// the largest absolute difference at any saved step
+    pub max_difference: f64,
+    pub baseline_final: f64,
+    pub final_value: f64,
+}
+
+/// compare returns the difference between `baseline` and `results` for
+/// each of the variables at `paths`.  If the runs saved at different
+/// times, `results` is interpolated at the baseline's saved steps.
+pub fn compare(baseline: &Results, results: &Results, paths: &[&str]) -> Result<Vec<Difference>> {
+    let results = results.aligned_with(baseline, Interpolation::Linear);
+    paths
+        .iter()
+        .map(|path| {
+            let (a, b) = match (baseline.timed_series(path), results.timed_series(path)) {
+                (Some(a), Some(b)) => (a, b),
+                _ => return model_err!(DoesNotExist, path.to_string()),
+            };
+            let max_difference = a
+                .iter()
+                .zip(b.iter())
+                .map(|((_, a), (_, b))| (a - b).abs())
+                .fold(0.0, f64::max);
+            Ok(Difference {
+                path: path.to_string(),
+                max_difference,
+                baseline_final: a.last().map(|(_, v)| *v).unwrap_or(f64::NAN),
+                final_value: b.last().map(|(_, v)| *v).unwrap_or(f64::NAN),
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn test_freeze() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
+
+    let mut project = x_project(
+        sim_specs_with_units("year"),
+        &[x_model(
+            "main",
+            vec![
+                x_stock("population", "100", &["births"], &[], None),
+                x_flow("births", "population * birth_rate", None),
+                x_aux("birth_rate", "0.1", None),
+            ],
+        )],
+    );
+    project.sim_specs.stop = 3.0;
+
+    let baseline = simulate_with_stubs(&project, "main", &Stubs::new()).unwrap();
+    assert_eq!(
+        Some(vec![(0.0, 10.0), (1.0, 11.0), (2.0, 12.1), (3.0, 13.31)]),
+        baseline.timed_series("births").map(|series| series
+            .into_iter()
+            .map(|(t, v)| (t, (v * 100.0).round() / 100.0))
+            .collect::<Vec<_>>())
+    );
+
+    // doubling the birth rate changes the population...
+    let changed =
+        simulate_frozen(&project, "main", &baseline, &[], &[("birth_rate", 0.2)]).unwrap();
+    let diffs = compare(&baseline, &changed, &["population"]).unwrap();
+    assert!(diffs[0].max_difference > 10.0);
+
+    // ...but not when births are frozen, cutting the feedback from the
+    // birth rate to the population
+    let frozen = simulate_frozen(
+        &project,
+        "main",
+        &baseline,
+        &["births"],
+        &[("birth_rate", 0.2)],
+    )
+    .unwrap();
+    let diffs = compare(&baseline, &frozen, &["population"]).unwrap();
+    assert!(diffs[0].max_difference < 1e-9);
+    assert_eq!(diffs[0].baseline_final, diffs[0].final_value);
+
+    assert!(freeze_stubs(&baseline, "main", &["nope"]).is_err());
+    assert!(compare(&baseline, &frozen, &["nope"]).is_err());
+}
@@ -0,0 +1,44 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Entry points for fuzzing.  Each takes arbitrary bytes and must return
+//! an error for malformed input, never panic.
+
+use crate::ast::Expr0;
+use crate::common::{Error, ErrorCode, ErrorKind, Result};
+use crate::token::LexerType;
+
+/// parse_equation parses `data` both as an equation and as a units
+/// expression, returning the first error.
+pub fn parse_equation(data: &[u8]) -> Result<()> {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(err) => {
+            return Err(Error::new(
+                ErrorKind::Variable,
+                ErrorCode::Generic,
+                Some(err.to_string()),
+            ))
+        }
+    };
+    for lexer_type in [LexerType::Equation, LexerType::Units] {
+        if let Err(errs) = Expr0::new(text, lexer_type) {
+            let code = errs
+                .first()
+                .map(|err| err.code)
+                .unwrap_or(ErrorCode::Generic);
+            return Err(Error::new(ErrorKind::Variable, code, None));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_parse_equation() {
+    assert!(parse_equation(b"a * (b + 1)").is_ok());
+    assert!(parse_equation(b"").is_ok());
+    assert!(parse_equation(b"1 +").is_err());
+    assert!(parse_equation(b"(((").is_err());
+    assert!(parse_equation(b"\xff\xfe").is_err());
+}
@@ -0,0 +1,374 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+use std::collections::HashMap;
+
+use crate::datamodel::view_element::FlowPoint;
+use crate::datamodel::{Rect, StockFlow, ViewElement};
+
+// these match the sizes the diagram uses when drawing
+pub const STOCK_WIDTH: f64 = 45.0;
+pub const STOCK_HEIGHT: f64 = 35.0;
+pub const CLOUD_RADIUS: f64 = 13.5;
+pub const AUX_RADIUS: f64 = 9.0;
+pub const MODULE_WIDTH: f64 = 55.0;
+pub const MODULE_HEIGHT: f64 = 45.0;
+
+// how far (in diagram units) a point can be off and still count as touching
+const TOLERANCE: f64 = 1.0;
+
+/// FlowPointError describes a problem with the sequence of points that
+/// make up a flow's pipe.  `point` is an index into the flow's points.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum FlowPointError {
+    TooFewPoints {
+        flow_uid: i32,
+    },
+    MissingAttachment {
+        flow_uid: i32,
+        point: usize,
+    },
+    UnknownAttachment {
+        flow_uid: i32,
+        point: usize,
+        uid: i32,
+    },
+    /// the point is attached to something other than a stock, or to a
+    /// cloud belonging to a different flow
+    BadAttachment {
+        flow_uid: i32,
+        point: usize,
+        uid: i32,
+    },
+    /// the point doesn't touch the element it is attached to
+    DetachedEndpoint {
+        flow_uid: i32,
+        point: usize,
+        uid: i32,
+    },
+    /// the segment starting at `point` is neither horizontal nor vertical
+    DiagonalSegment {
+        flow_uid: i32,
+        point: usize,
+    },
+}
+
+/// FlowEnd is one end of a flow to be routed: either a stock (which the
+/// flow attaches to at its edge) or a cloud.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FlowEnd {
+    Stock { uid: i32, x: f64, y: f64 },
+    Cloud { uid: i32, x: f64, y: f64 },
+}
+
+impl FlowEnd {
+    fn uid(&self) -> i32 {
+        match self {
+            FlowEnd::Stock { uid, .. } | FlowEnd::Cloud { uid, .. } => *uid,
+        }
+    }
+
+    fn center(&self) -> (f64, f64) {
+        match self {
+            FlowEnd::Stock { x, y, .. } | FlowEnd::Cloud { x, y, .. } => (*x, *y),
+        }
+    }
+
+    // half of the width and height of the area the flow attaches to
+    fn half_extents(&self) -> (f64, f64) {
+        match self {
+            FlowEnd::Stock { .. } => (STOCK_WIDTH / 2.0, STOCK_HEIGHT / 2.0),
+            FlowEnd::Cloud { .. } => (0.0, 0.0),
+        }
+    }
+}
+
+/// bounds returns the rectangle an element's shape (not including its
+/// label) occupies, or None for links.  Flows are represented by their
+/// valve.
+pub fn bounds(element: &ViewElement) -> Option<Rect> {
+    let (x, y, width, height) = match element {
+        ViewElement::Aux(aux) => (aux.x, aux.y, 2.0 * AUX_RADIUS, 2.0 * AUX_RADIUS),
+        ViewElement::Stock(stock) => (stock.x, stock.y, STOCK_WIDTH, STOCK_HEIGHT),
+        ViewElement::Flow(flow) => (flow.x, flow.y, 2.0 * AUX_RADIUS, 2.0 * AUX_RADIUS),
+        ViewElement::Link(_) => return None,
+        ViewElement::Module(module) => (module.x, module.y, MODULE_WIDTH, MODULE_HEIGHT),
+        ViewElement::Alias(alias) => (alias.x, alias.y, 2.0 * AUX_RADIUS, 2.0 * AUX_RADIUS),
+        ViewElement::Cloud(cloud) => (cloud.x, cloud.y, 2.0 * CLOUD_RADIUS, 2.0 * CLOUD_RADIUS),
+    };
+    Some(Rect {
+        x: x - width / 2.0,
+        y: y - height / 2.0,
+        width,
+        height,
+    })
+}
+
+fn touches_stock(pt: &FlowPoint, x: f64, y: f64) -> bool {
+    (pt.x - x).abs() <= STOCK_WIDTH / 2.0 + TOLERANCE
+        && (pt.y - y).abs() <= STOCK_HEIGHT / 2.0 + TOLERANCE
+}
+
+fn touches_cloud(pt: &FlowPoint, x: f64, y: f64) -> bool {
+    (pt.x - x).hypot(pt.y - y) <= CLOUD_RADIUS + TOLERANCE
+}
+
+impl StockFlow {
+    /// validate_flow_points checks that every flow in the view has at
+    /// least two points, that its endpoints are attached to stocks or its
+    /// own clouds and touch them, and that its segments are orthogonal.
+    pub fn validate_flow_points(&self) -> Vec<FlowPointError> {
+        let elements: HashMap<i32, &ViewElement> =
+            self.elements.iter().map(|e| (e.get_uid(), e)).collect();
+
+        let mut errors = vec![];
+        for element in self.elements.iter() {
+            let flow = match element {
+                ViewElement::Flow(flow) => flow,
+                _ => continue,
+            };
+            let flow_uid = flow.uid;
+            let points = &flow.points;
+            if points.len() < 2 {
+                errors.push(FlowPointError::TooFewPoints { flow_uid });
+                continue;
+            }
+
+            let last = points.len() - 1;
+            for (i, pt) in points.iter().enumerate() {
+                let is_endpoint = i == 0 || i == last;
+                let uid = match pt.attached_to_uid {
+                    Some(uid) => uid,
+                    None => {
+                        if is_endpoint {
+                            errors.push(FlowPointError::MissingAttachment { flow_uid, point: i });
+                        }
+                        continue;
+                    }
+                };
+                let touches = match elements.get(&uid) {
+                    None => {
+                        errors.push(FlowPointError::UnknownAttachment {
+                            flow_uid,
+                            point: i,
+                            uid,
+                        });
+                        continue;
+                    }
+                    Some(ViewElement::Stock(stock)) => touches_stock(pt, stock.x, stock.y),
+                    Some(ViewElement::Cloud(cloud)) if cloud.flow_uid == flow_uid => {
+                        touches_cloud(pt, cloud.x, cloud.y)
+                    }
+                    Some(_) => {
+                        errors.push(FlowPointError::BadAttachment {
+                            flow_uid,
+                            point: i,
+                            uid,
+                        });
+                        continue;
+                    }
+                };
+                if !touches {
+                    errors.push(FlowPointError::DetachedEndpoint {
+                        flow_uid,
+                        point: i,
+                        uid,
+                    });
+                }
+            }
+
+            for (i, segment) in points.windows(2).enumerate() {
+                let (a, b) = (&segment[0], &segment[1]);
+                if (a.x - b.x).abs() > TOLERANCE && (a.y - b.y).abs() > TOLERANCE {
+                    errors.push(FlowPointError::DiagonalSegment { flow_uid, point: i });
+                }
+            }
+        }
+
+        errors
+    }
+}
+
+/// route_flow returns the points of a pipe from `source` to `sink` made up
+/// of horizontal and vertical segments.  The flow leaves and enters stocks
+/// at their edges, and the pipe is straight when the ends are roughly
+/// aligned, otherwise it has a single bend.
+pub fn route_flow(source: FlowEnd, sink: FlowEnd) -> Vec<FlowPoint> {
+    let (sx, sy) = source.center();
+    let (tx, ty) = sink.center();
+    let (sw, sh) = source.half_extents();
+    let (tw, th) = sink.half_extents();
+    let point = |x: f64, y: f64, attached_to_uid: Option<i32>| FlowPoint {
+        x,
+        y,
+        attached_to_uid,
+    };
+    // +1 if the sink is to the right of (or below) the source
+    let dir = |from: f64, to: f64| if to >= from { 1.0 } else { -1.0 };
+
+    // the vertical extents overlap: a straight horizontal pipe
+    let (lo, hi) = ((sy - sh).max(ty - th), (sy + sh).min(ty + th));
+    if lo <= hi {
+        let y = (lo + hi) / 2.0;
+        let d = dir(sx, tx);
+        return vec![
+            point(sx + d * sw, y, Some(source.uid())),
+            point(tx - d * tw, y, Some(sink.uid())),
+        ];
+    }
+
+    // the horizontal extents overlap: a straight vertical pipe
+    let (lo, hi) = ((sx - sw).max(tx - tw), (sx + sw).min(tx + tw));
+    if lo <= hi {
+        let x = (lo + hi) / 2.0;
+        let d = dir(sy, ty);
+        return vec![
+            point(x, sy + d * sh, Some(source.uid())),
+            point(x, ty - d * th, Some(sink.uid())),
+        ];
+    }
+
+    // otherwise leave the source horizontally, and enter the sink vertically
+    vec![
+        point(sx + dir(sx, tx) * sw, sy, Some(source.uid())),
+        point(tx, sy, None),
+        point(tx, ty - dir(sy, ty) * th, Some(sink.uid())),
+    ]
+}
+
+#[test]
+fn test_route_flow() {
+    let stock = |uid: i32, x: f64, y: f64| FlowEnd::Stock { uid, x, y };
+    let cloud = |uid: i32, x: f64, y: f64| FlowEnd::Cloud { uid, x, y };
+    let coords = |points: Vec<FlowPoint>| {
+        points
+            .into_iter()
+            .map(|pt| (pt.x, pt.y, pt.attached_to_uid))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        vec![(122.5, 100.0, Some(1)), (277.5, 100.0, Some(2))],
+        coords(route_flow(stock(1, 100.0, 100.0), stock(2, 300.0, 100.0)))
+    );
+    // slightly misaligned stocks still get a straight pipe
+    assert_eq!(
+        vec![(77.5, 105.0, Some(1)), (-77.5, 105.0, Some(2))],
+        coords(route_flow(stock(1, 100.0, 100.0), stock(2, -100.0, 110.0)))
+    );
+    assert_eq!(
+        vec![(100.0, 100.0, Some(3)), (100.0, 182.5, Some(2))],
+        coords(route_flow(cloud(3, 100.0, 100.0), stock(2, 100.0, 200.0)))
+    );
+    assert_eq!(
+        vec![
+            (122.5, 100.0, Some(1)),
+            (300.0, 100.0, None),
+            (300.0, 282.5, Some(2))
+        ],
+        coords(route_flow(stock(1, 100.0, 100.0), stock(2, 300.0, 300.0)))
+    );
+}
+
+#[test]
+fn test_validate_flow_points() {
+    use crate::datamodel::view_element::{self, LabelSide};
+
+    let stock = |uid: i32, x: f64, y: f64| {
+        ViewElement::Stock(view_element::Stock {
+            name: format!("stock_{}", uid),
+            uid,
+            x,
+            y,
+            label_side: LabelSide::Bottom,
+        })
+    };
+    let flow = |uid: i32, points: Vec<FlowPoint>| {
+        ViewElement::Flow(view_element::Flow {
+            name: format!("flow_{}", uid),
+            uid,
+            x: 0.0,
+            y: 0.0,
+            label_side: LabelSide::Bottom,
+            points,
+        })
+    };
+    let pt = |x: f64, y: f64, attached_to_uid: Option<i32>| FlowPoint {
+        x,
+        y,
+        attached_to_uid,
+    };
+
+    let routed = route_flow(
+        FlowEnd::Stock {
+            uid: 1,
+            x: 100.0,
+            y: 100.0,
+        },
+        FlowEnd::Cloud {
+            uid: 4,
+            x: 300.0,
+            y: 250.0,
+        },
+    );
+    let view = StockFlow {
+        elements: vec![
+            stock(1, 100.0, 100.0),
+            stock(2, 300.0, 100.0),
+            flow(3, routed),
+            ViewElement::Cloud(view_element::Cloud {
+                uid: 4,
+                flow_uid: 3,
+                x: 300.0,
+                y: 250.0,
+            }),
+            flow(5, vec![pt(122.5, 100.0, Some(1))]),
+            flow(
+                6,
+                vec![
+                    pt(122.5, 100.0, Some(1)),
+                    pt(200.0, 150.0, None),
+                    pt(277.5, 150.0, Some(2)),
+                ],
+            ),
+            flow(
+                7,
+                vec![pt(122.5, 100.0, Some(4)), pt(277.5, 100.0, Some(9))],
+            ),
+            flow(8, vec![pt(122.5, 100.0, None), pt(277.5, 100.0, Some(2))]),
+        ],
+        view_box: Default::default(),
+        zoom: 1.0,
+    };
+
+    assert_eq!(
+        vec![
+            FlowPointError::TooFewPoints { flow_uid: 5 },
+            FlowPointError::DetachedEndpoint {
+                flow_uid: 6,
+                point: 2,
+                uid: 2
+            },
+            FlowPointError::DiagonalSegment {
+                flow_uid: 6,
+                point: 0
+            },
+            FlowPointError::BadAttachment {
+                flow_uid: 7,
+                point: 0,
+                uid: 4
+            },
+            FlowPointError::UnknownAttachment {
+                flow_uid: 7,
+                point: 1,
+                uid: 9
+            },
+            FlowPointError::MissingAttachment {
+                flow_uid: 8,
+                point: 0
+            },
+        ],
+        view.validate_flow_points()
+    );
+}
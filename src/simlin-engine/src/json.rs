@@ -0,0 +1,64 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! A human-readable JSON encoding of projects, for web front-ends and
+//! scripts that would rather not deal with protobuf.  It mirrors the
+//! datamodel field for field, so it changes along with it.
+
+use crate::common::{Error, ErrorCode, ErrorKind, Result};
+use crate::datamodel::Project;
+
+/// serialize returns the project as pretty-printed JSON.
+pub fn serialize(project: &Project) -> String {
+    // every type in the datamodel is plain data, so this can't fail
+    serde_json::to_string_pretty(project).unwrap()
+}
+
+/// deserialize parses a project written by `serialize`.
+pub fn deserialize(contents: &str) -> Result<Project> {
+    serde_json::from_str(contents).map_err(json_error)
+}
+
+pub(crate) fn json_error(err: serde_json::Error) -> Error {
+    Error::new(
+        ErrorKind::Import,
+        ErrorCode::JsonDeserialization,
+        Some(err.to_string()),
+    )
+}
+
+#[test]
+fn test_json_roundtrip() {
+    use crate::datamodel::{Dt, Equation, Variable};
+    use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
+
+    let mut project = x_project(
+        sim_specs_with_units("months"),
+        &[x_model(
+            "main",
+            vec![
+                x_stock("population", "100", &["births"], &[], Some("people")),
+                x_flow("births", "population * birth_rate", Some("people/month")),
+                x_aux("birth_rate", "0.01", Some("1/month")),
+            ],
+        )],
+    );
+    project.sim_specs.dt = Dt::Reciprocal(4.0);
+
+    let contents = serialize(&project);
+    assert!(contents.contains("\"birth_rate\""));
+    let decoded = deserialize(&contents).unwrap();
+    assert_eq!(project, decoded);
+    // the variable index is rebuilt rather than stored
+    match decoded.models[0].get_variable("births") {
+        Some(Variable::Flow(flow)) => assert_eq!(
+            Equation::Scalar("population * birth_rate".to_owned(), None),
+            flow.equation
+        ),
+        _ => panic!("expected births to be a flow"),
+    }
+
+    let err = deserialize("{\"name\": 1}").unwrap_err();
+    assert_eq!(ErrorCode::JsonDeserialization, err.code);
+}
@@ -6,7 +6,11 @@
 
 pub use prost;
 
+mod aliases;
+pub mod anonymize;
+pub mod array_edit;
 mod ast;
+pub mod behavior;
 pub mod common;
 pub mod datamodel;
 #[allow(clippy::derive_partial_eq_without_eq)]
@@ -20,20 +24,61 @@ mod equation {
 }
 pub mod builtins;
 mod builtins_visitor;
+pub mod calibrate;
+pub mod capabilities;
+pub mod compile_cache;
 mod compiler;
+pub mod complexity;
+pub mod conformance;
+pub mod dep_tree;
+pub mod derived;
 mod dimensions;
+pub mod discontinuities;
+pub mod duplicates;
+pub mod ensemble;
+pub mod eval;
+pub mod events;
+pub mod freeze;
+pub mod fuzz;
+pub mod geometry;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod loops;
+pub mod math;
 mod model;
+pub mod model_tests;
+pub mod molecules;
+pub mod optimize;
 mod token;
 mod variable;
+pub mod view_cleanup;
+pub mod view_diff;
+#[cfg(feature = "json")]
+pub mod workspace;
 mod stdlib {
     include!(concat!(env!("OUT_DIR"), "/stdlib.rs"));
 }
 mod builder;
 mod bytecode;
 mod interpreter;
+pub mod partial;
+pub mod paths;
+pub mod plot;
+pub mod polarity;
 mod project;
+pub mod query;
+pub mod quota;
+pub mod replace;
+pub mod resample;
+pub mod scaffold;
+pub mod sensitivity;
+mod sim_specs;
+pub mod smoothing;
+pub mod stubs;
+pub mod templates;
 #[cfg(test)]
 mod testutils;
+pub mod time_base;
 mod units;
 mod units_check;
 mod units_infer;
@@ -41,10 +86,13 @@ mod vm;
 
 pub use self::builder::build_sim_with_stderrors;
 pub use self::common::{canonicalize, quoteize, Error, ErrorCode, Ident, Result};
-pub use self::compiler::Simulation;
+pub use self::compiler::{Runlists, Simulation};
 pub use self::project::Project;
+pub use self::units::DEFAULT_UNIT_SYNONYMS;
+pub use self::units_infer::{InferenceStep, UnitExplanation, UnitMismatch};
 pub use self::variable::Variable;
 pub use self::vm::Method;
 pub use self::vm::Results;
 pub use self::vm::Specs as SimSpecs;
+pub use self::vm::Stats;
 pub use self::vm::Vm;
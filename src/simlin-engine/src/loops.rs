@@ -0,0 +1,288 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Feedback loop enumeration, and loop knockout experiments that rank
+//! loops by how much cutting them changes a model's behavior.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::common::{canonicalize, Ident, Result};
+use crate::datamodel;
+use crate::freeze::{compare, simulate_with_stubs, Difference};
+use crate::model_err;
+use crate::project::Project;
+use crate::replace::{replace_idents, texts_mut, Field};
+use crate::stubs::{Stub, Stubs};
+use crate::templates::aux;
+use crate::variable::{identifier_set, Variable};
+
+/// Loop is a feedback loop: each variable influences the next, and the
+/// last influences the first.  It starts with its alphabetically first
+/// variable.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Loop {
+    pub variables: Vec<Ident>,
+}
+
+impl Loop {
+    /// links returns each (from, to) causal link around the loop.
+    pub fn links(&self) -> Vec<(&str, &str)> {
+        let n = self.variables.len();
+        (0..n)
+            .map(|i| {
+                (
+                    self.variables[i].as_str(),
+                    self.variables[(i + 1) % n].as_str(),
+                )
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Loop {
+    /// the loop as a chain, like `births -> population -> births`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for var in self.variables.iter() {
+            write!(f, "{} -> ", var)?;
+        }
+        write!(
+            f,
+            "{}",
+            self.variables.first().map(|v| v.as_str()).unwrap_or("")
+        )
+    }
+}
+
+// the variables each variable in the model directly influences
+fn influence_graph(project: &Project, model_name: &str) -> Result<BTreeMap<Ident, Vec<Ident>>> {
+    let model = match project.models.get(model_name) {
+        Some(model) => model,
+        None => return model_err!(BadModelName, model_name.to_owned()),
+    };
+    let mut graph: BTreeMap<Ident, BTreeSet<Ident>> = BTreeMap::new();
+    for (ident, var) in model.variables.iter() {
+        let deps: Vec<Ident> = match var {
+            Variable::Stock {
+                inflows, outflows, ..
+            } => inflows.iter().chain(outflows.iter()).cloned().collect(),
+            Variable::Var { ast: Some(ast), .. } => {
+                let dims = var.get_dimensions().unwrap_or(&[]);
+                identifier_set(ast, dims, None).into_iter().collect()
+            }
+            // loops through modules aren't followed
+            _ => vec![],
+        };
+        for dep in deps {
+            if model.variables.contains_key(&dep) {
+                graph.entry(dep).or_default().insert(ident.clone());
+            }
+        }
+    }
+    Ok(graph
+        .into_iter()
+        .map(|(ident, influences)| (ident, influences.into_iter().collect()))
+        .collect())
+}
+
+/// find_loops returns every feedback loop in the model `model_name`, in
+/// alphabetical order.  Loops that pass through a module instance aren't
+/// found.  The number of loops can grow exponentially with the size of a
+/// densely connected model.
+pub fn find_loops(project: &Project, model_name: &str) -> Result<Vec<Loop>> {
+    let graph = influence_graph(project, model_name)?;
+    let mut loops = vec![];
+    // each loop is found once, from its smallest variable, by only
+    // visiting larger variables
+    for start in graph.keys() {
+        let mut path = vec![start.clone()];
+        let mut stack = vec![0usize];
+        while let Some(next) = stack.last_mut() {
+            let current = path.last().unwrap();
+            let influences = graph.get(current).map(|v| v.as_slice()).unwrap_or(&[]);
+            if *next >= influences.len() {
+                stack.pop();
+                path.pop();
+                continue;
+            }
+            let ident = &influences[*next];
+            *next += 1;
+            if ident == start {
+                loops.push(Loop {
+                    variables: path.clone(),
+                });
+            } else if ident > start && !path.contains(ident) {
+                path.push(ident.clone());
+                stack.push(0);
+            }
+        }
+    }
+    loops.sort_unstable_by(|a, b| a.variables.cmp(&b.variables));
+    Ok(loops)
+}
+
+// freeze_link returns a copy of the project where `to` refers to a new
+// variable in place of `from`, so that the one link can be replaced
+// without affecting anything else `from` influences, along with the new
+// variable's ident.
+fn freeze_link(
+    project: &datamodel::Project,
+    model_name: &str,
+    from: &str,
+    to: &str,
+) -> Result<(datamodel::Project, Ident)> {
+    let mut project = project.clone();
+    let model = match project.get_model_mut(model_name) {
+        Some(model) => model,
+        None => return model_err!(BadModelName, model_name.to_owned()),
+    };
+    let idents: BTreeSet<Ident> = model
+        .variables
+        .iter()
+        .map(|var| canonicalize(var.get_ident()))
+        .collect();
+    let mut frozen = format!("{}_frozen", from);
+    while idents.contains(&frozen) {
+        frozen.push('_');
+    }
+    let var = match model
+        .variables
+        .iter_mut()
+        .find(|var| canonicalize(var.get_ident()) == to)
+    {
+        Some(var) => var,
+        None => return model_err!(DoesNotExist, to.to_owned()),
+    };
+    for (_, field, text) in texts_mut(var) {
+        if field == Field::Equation {
+            *text = replace_idents(text, field.lexer_type(), |ident| {
+                if ident == from {
+                    Some(frozen.clone())
+                } else {
+                    None
+                }
+            });
+        }
+    }
+    model.variables.push(aux(&frozen, "0"));
+    Ok((project, frozen))
+}
+
+/// LoopImportance is the effect of knocking out a feedback loop.
+#[derive(Clone, PartialEq, Debug)]
+pub struct LoopImportance {
+    pub feedback_loop: Loop,
+    /// the (from, to) link held at its initial value to cut the loop
+    pub frozen_link: (Ident, Ident),
+    pub differences: Vec<Difference>,
+    /// the sum of each output's largest difference from the baseline,
+    /// relative to its largest baseline magnitude
+    pub score: f64,
+}
+
+/// loop_knockout cuts each feedback loop in the model `model_name` in
+/// turn, by holding one of its links at its initial value, and compares
+/// the `outputs` to a run with every loop active.  The loops are ranked
+/// from most to least important.  A link can be shared by several loops,
+/// in which case cutting it knocks them all out.  Loops whose links are
+/// all arrayed or into stocks are skipped.
+pub fn loop_knockout(
+    project: &datamodel::Project,
+    model_name: &str,
+    outputs: &[&str],
+) -> Result<Vec<LoopImportance>> {
+    let baseline = simulate_with_stubs(project, model_name, &Stubs::new())?;
+    let mut scales = vec![];
+    for path in outputs.iter() {
+        let series = match baseline.timed_series(path) {
+            Some(series) => series,
+            None => return model_err!(DoesNotExist, path.to_string()),
+        };
+        let scale = series.iter().map(|(_, v)| v.abs()).fold(0.0, f64::max);
+        scales.push(if scale > 0.0 { scale } else { 1.0 });
+    }
+
+    let compiled = Project::from(project.clone());
+    let model = &compiled.models[model_name];
+    let mut importances = vec![];
+    for feedback_loop in find_loops(&compiled, model_name)? {
+        // flows into stocks can't be held constant without changing the
+        // stock itself, so cut the loop at the first other link
+        let link = feedback_loop.links().into_iter().find_map(|(from, to)| {
+            if model.variables[to].is_stock() {
+                return None;
+            }
+            let initial = baseline.timed_series(from)?.first()?.1;
+            Some((from.to_owned(), to.to_owned(), initial))
+        });
+        let (from, to, initial) = match link {
+            Some(link) => link,
+            None => continue,
+        };
+
+        let (knocked_out, frozen) = freeze_link(project, model_name, &from, &to)?;
+        let mut stubs = Stubs::new();
+        stubs.stub(model_name, &frozen, Stub::Constant(initial));
+        let results = simulate_with_stubs(&knocked_out, model_name, &stubs)?;
+        let differences = compare(&baseline, &results, outputs)?;
+        let score = differences
+            .iter()
+            .zip(scales.iter())
+            .map(|(difference, scale)| difference.max_difference / scale)
+            .sum();
+        importances.push(LoopImportance {
+            feedback_loop,
+            frozen_link: (from, to),
+            differences,
+            score,
+        });
+    }
+    importances.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+    Ok(importances)
+}
+
+#[test]
+fn test_loop_knockout() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
+
+    let mut project = x_project(
+        sim_specs_with_units("year"),
+        &[x_model(
+            "main",
+            vec![
+                x_stock("population", "100", &["births"], &["deaths"], None),
+                x_flow("births", "population * birth_rate", None),
+                x_flow("deaths", "population / lifetime", None),
+                x_aux("birth_rate", "0.1", None),
+                x_aux("lifetime", "5", None),
+            ],
+        )],
+    );
+    project.sim_specs.stop = 10.0;
+
+    let loops = find_loops(&Project::from(project.clone()), "main").unwrap();
+    assert_eq!(
+        vec![
+            "births -> population -> births",
+            "deaths -> population -> deaths"
+        ],
+        loops.iter().map(|l| l.to_string()).collect::<Vec<_>>()
+    );
+
+    let importances = loop_knockout(&project, "main", &["population"]).unwrap();
+    assert_eq!(2, importances.len());
+    // without the balancing deaths loop, the population collapses below
+    // zero, far further from the baseline than when births stop growing
+    assert_eq!(loops[1], importances[0].feedback_loop);
+    assert_eq!(
+        ("population".to_owned(), "deaths".to_owned()),
+        importances[0].frozen_link
+    );
+    let population = &importances[0].differences[0];
+    assert!((population.final_value - (200.0 - 100.0 * 1.1f64.powi(10))).abs() < 1e-6);
+    assert!(importances[0].score > importances[1].score);
+
+    assert!(loop_knockout(&project, "main", &["nope"]).is_err());
+}
@@ -0,0 +1,112 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Transcendental functions used by the simulation builtins.
+//!
+//! Arithmetic, `sqrt`, `floor`, and `abs` are exactly rounded by IEEE 754
+//! on every platform, Rust never contracts multiplies and adds into FMAs
+//! or reorders float operations on its own, and equations are always
+//! evaluated in the same order.  The remaining source of platform
+//! differences is the transcendental functions, which `std` takes from the
+//! platform's C library.  With the `strict-math` feature they come from
+//! the pure Rust `libm` crate instead, so that native and wasm builds
+//! produce bit-identical results.
+
+/// STRICT_MATH is whether results are reproducible across platforms.
+pub const STRICT_MATH: bool = cfg!(feature = "strict-math");
+
+#[cfg(feature = "strict-math")]
+mod imp {
+    pub fn pow(x: f64, y: f64) -> f64 {
+        libm::pow(x, y)
+    }
+    pub fn exp(x: f64) -> f64 {
+        libm::exp(x)
+    }
+    pub fn ln(x: f64) -> f64 {
+        libm::log(x)
+    }
+    pub fn log10(x: f64) -> f64 {
+        libm::log10(x)
+    }
+    pub fn sin(x: f64) -> f64 {
+        libm::sin(x)
+    }
+    pub fn cos(x: f64) -> f64 {
+        libm::cos(x)
+    }
+    pub fn tan(x: f64) -> f64 {
+        libm::tan(x)
+    }
+    pub fn asin(x: f64) -> f64 {
+        libm::asin(x)
+    }
+    pub fn acos(x: f64) -> f64 {
+        libm::acos(x)
+    }
+    pub fn atan(x: f64) -> f64 {
+        libm::atan(x)
+    }
+}
+
+#[cfg(not(feature = "strict-math"))]
+mod imp {
+    pub fn pow(x: f64, y: f64) -> f64 {
+        x.powf(y)
+    }
+    pub fn exp(x: f64) -> f64 {
+        x.exp()
+    }
+    pub fn ln(x: f64) -> f64 {
+        x.ln()
+    }
+    pub fn log10(x: f64) -> f64 {
+        x.log10()
+    }
+    pub fn sin(x: f64) -> f64 {
+        x.sin()
+    }
+    pub fn cos(x: f64) -> f64 {
+        x.cos()
+    }
+    pub fn tan(x: f64) -> f64 {
+        x.tan()
+    }
+    pub fn asin(x: f64) -> f64 {
+        x.asin()
+    }
+    pub fn acos(x: f64) -> f64 {
+        x.acos()
+    }
+    pub fn atan(x: f64) -> f64 {
+        x.atan()
+    }
+}
+
+pub(crate) use imp::*;
+
+#[test]
+fn test_math() {
+    let close = |a: f64, b: f64| (a - b).abs() <= 1e-15 * a.abs().max(1.0);
+    for &x in [0.0f64, 0.5, 1.0, 2.0, 10.0].iter() {
+        assert!(close(x.exp(), exp(x)));
+        assert!(close(x.sin(), sin(x)));
+        assert!(close(x.cos(), cos(x)));
+        assert!(close(x.tan(), tan(x)));
+        assert!(close(x.atan(), atan(x)));
+        assert!(close(2.0f64.powf(x), pow(2.0, x)));
+        if x > 0.0 {
+            assert!(close(x.ln(), ln(x)));
+            assert!(close(x.log10(), log10(x)));
+        }
+        if x <= 1.0 {
+            assert!(close(x.asin(), asin(x)));
+            assert!(close(x.acos(), acos(x)));
+        }
+    }
+    assert_eq!(1.0, exp(0.0));
+    assert_eq!(0.0, ln(1.0));
+    assert_eq!(2.0, log10(100.0));
+    assert_eq!(8.0, pow(2.0, 3.0));
+}
@@ -477,19 +477,20 @@ pub(crate) fn lower_variable(
             inflows,
             outflows,
             non_negative,
+            force_euler,
             errors,
             unit_errors,
         } => {
             let mut errors = errors.clone();
-            let ast = ast
-                .as_ref()
-                .and_then(|ast| match lower_ast(scope, ast.clone()) {
+            let ast = ast.as_ref().and_then(|ast| {
+                match lower_ast(scope, parent_module_name, ast.clone()) {
                     Ok(ast) => Some(ast),
                     Err(err) => {
                         errors.push(err);
                         None
                     }
-                });
+                }
+            });
             Variable::Stock {
                 ident: ident.clone(),
                 init_ast: ast,
@@ -498,6 +499,7 @@ pub(crate) fn lower_variable(
                 inflows: inflows.clone(),
                 outflows: outflows.clone(),
                 non_negative: *non_negative,
+                force_euler: *force_euler,
                 errors,
                 unit_errors: unit_errors.clone(),
             }
@@ -516,24 +518,24 @@ pub(crate) fn lower_variable(
             unit_errors,
         } => {
             let mut errors = errors.clone();
-            let ast = ast
-                .as_ref()
-                .and_then(|ast| match lower_ast(scope, ast.clone()) {
+            let ast = ast.as_ref().and_then(|ast| {
+                match lower_ast(scope, parent_module_name, ast.clone()) {
                     Ok(ast) => Some(ast),
                     Err(err) => {
                         errors.push(err);
                         None
                     }
-                });
-            let init_ast = init_ast
-                .as_ref()
-                .and_then(|ast| match lower_ast(scope, ast.clone()) {
+                }
+            });
+            let init_ast = init_ast.as_ref().and_then(|ast| {
+                match lower_ast(scope, parent_module_name, ast.clone()) {
                     Ok(ast) => Some(ast),
                     Err(err) => {
                         errors.push(err);
                         None
                     }
-                });
+                }
+            });
             Variable::Var {
                 ident: ident.clone(),
                 ast,
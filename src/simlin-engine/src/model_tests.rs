@@ -0,0 +1,197 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+use crate::common::{Error, Result};
+use crate::datamodel::{self, ModelTest, TestExpectation};
+use crate::stubs::{Stub, Stubs};
+use crate::vm::{Results, Vm};
+use crate::{Project, Simulation};
+
+/// TestFailure is an expectation that wasn't met.  `actual` is None if
+/// the variable doesn't exist or `time` isn't in the results.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TestFailure {
+    pub expectation: TestExpectation,
+    pub actual: Option<f64>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct TestResult {
+    pub name: String,
+    /// set if the test's scenario couldn't be simulated
+    pub error: Option<Error>,
+    pub failures: Vec<TestFailure>,
+}
+
+impl TestResult {
+    pub fn is_pass(&self) -> bool {
+        self.error.is_none() && self.failures.is_empty()
+    }
+}
+
+// value_at returns the value of the variable at the saved time step
+// closest to `time`, if there is one within half a save step.
+fn value_at(results: &Results, ident: &str, time: f64) -> Option<f64> {
+    let off = results.offset(ident)?;
+    let time_off = *results.offsets.get("time")?;
+    let tolerance = results.specs.save_step / 2.0;
+    results
+        .iter_rows()
+        .find(|step| (step[time_off] - time).abs() <= tolerance)
+        .map(|step| step[off])
+}
+
+fn simulate(project: &datamodel::Project, test: &ModelTest, stubs: &Stubs) -> Result<Results> {
+    let model_name = if test.model_name.is_empty() {
+        "main"
+    } else {
+        test.model_name.as_str()
+    };
+    let mut stubs = stubs.clone();
+    for o in test.overrides.iter() {
+        stubs.stub(model_name, &o.ident, Stub::Constant(o.value));
+    }
+    let project = Project::from_with_stubs(project.clone(), &stubs)?;
+    let sim = Simulation::new(&project, model_name)?;
+    let mut vm = Vm::new(sim.compile()?)?;
+    vm.run_to_end()?;
+    Ok(vm.into_results())
+}
+
+/// run_test simulates the test's scenario and checks its expectations.
+pub fn run_test(project: &datamodel::Project, test: &ModelTest) -> TestResult {
+    run_test_with_stubs(project, test, &Stubs::new())
+}
+
+fn run_test_with_stubs(
+    project: &datamodel::Project,
+    test: &ModelTest,
+    stubs: &Stubs,
+) -> TestResult {
+    let mut result = TestResult {
+        name: test.name.clone(),
+        error: None,
+        failures: vec![],
+    };
+    let results = match simulate(project, test, stubs) {
+        Ok(results) => results,
+        Err(err) => {
+            result.error = Some(err);
+            return result;
+        }
+    };
+    for expectation in test.expectations.iter() {
+        let actual = value_at(&results, &expectation.ident, expectation.time);
+        let is_ok = actual
+            .map(|actual| (actual - expectation.value).abs() <= expectation.tolerance)
+            .unwrap_or(false);
+        if !is_ok {
+            result.failures.push(TestFailure {
+                expectation: expectation.clone(),
+                actual,
+            });
+        }
+    }
+    result
+}
+
+/// run_tests runs every test stored in the project, in order.
+pub fn run_tests(project: &datamodel::Project) -> Vec<TestResult> {
+    run_tests_with_stubs(project, &Stubs::new())
+}
+
+/// run_tests_with_stubs is like run_tests, but with `stubs` applied to
+/// every test's scenario, along with the test's own overrides.
+pub fn run_tests_with_stubs(project: &datamodel::Project, stubs: &Stubs) -> Vec<TestResult> {
+    project
+        .tests
+        .iter()
+        .map(|test| run_test_with_stubs(project, test, stubs))
+        .collect()
+}
+
+#[test]
+fn test_run_tests() {
+    use crate::common::ErrorCode;
+    use crate::datamodel::TestOverride;
+    use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_stock("population", "100", &["births"], &[], None),
+                x_flow("births", "population * birth_rate", None),
+                x_aux("birth_rate", "0.1", None),
+            ],
+        )],
+    );
+    project.sim_specs.dt = datamodel::Dt::Dt(1.0);
+    project.sim_specs.stop = 2.0;
+
+    let expect = |ident: &str, time: f64, value: f64| TestExpectation {
+        ident: ident.to_owned(),
+        time,
+        value,
+        tolerance: 0.01,
+    };
+    project.tests = vec![
+        ModelTest {
+            name: "baseline".to_owned(),
+            model_name: "".to_owned(),
+            overrides: vec![],
+            expectations: vec![
+                expect("population", 2.0, 121.0),
+                expect("births", 0.0, 10.0),
+            ],
+        },
+        ModelTest {
+            name: "no births".to_owned(),
+            model_name: "main".to_owned(),
+            overrides: vec![TestOverride {
+                ident: "birth_rate".to_owned(),
+                value: 0.0,
+            }],
+            expectations: vec![
+                expect("population", 2.0, 121.0),
+                expect("population", 7.0, 100.0),
+                expect("deaths", 0.0, 0.0),
+            ],
+        },
+        ModelTest {
+            name: "bad override".to_owned(),
+            model_name: "main".to_owned(),
+            overrides: vec![TestOverride {
+                ident: "death_rate".to_owned(),
+                value: 0.0,
+            }],
+            expectations: vec![],
+        },
+    ];
+
+    let results = run_tests(&project);
+    assert_eq!(3, results.len());
+    assert!(results[0].is_pass());
+
+    assert!(!results[1].is_pass());
+    let failures: Vec<_> = results[1]
+        .failures
+        .iter()
+        .map(|f| (f.expectation.ident.as_str(), f.expectation.time, f.actual))
+        .collect();
+    assert_eq!(
+        vec![
+            ("population", 2.0, Some(100.0)),
+            ("population", 7.0, None),
+            ("deaths", 0.0, None),
+        ],
+        failures
+    );
+
+    assert_eq!(
+        Some(ErrorCode::DoesNotExist),
+        results[2].error.as_ref().map(|err| err.code)
+    );
+}
@@ -0,0 +1,140 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! A library of standard system dynamics "molecules": small, reusable
+//! pieces of structure that can be inserted into a model as templates.
+
+use crate::common::canonicalize;
+use crate::templates::{aux, flow, param, stock, Template};
+
+fn smooth() -> Template {
+    Template {
+        name: "smooth".to_owned(),
+        documentation: "first-order exponential smoothing of an input".to_owned(),
+        params: vec![
+            param("input", "the value being smoothed", "1"),
+            param(
+                "smoothing_time",
+                "the average time for the output to adjust to the input",
+                "1",
+            ),
+        ],
+        variables: vec![
+            stock("smoothed", "input", &["change_in_smoothed"], &[]),
+            flow("change_in_smoothed", "(input - smoothed) / smoothing_time"),
+        ],
+    }
+}
+
+fn coflow() -> Template {
+    Template {
+        name: "coflow".to_owned(),
+        documentation: "an attribute, like experience, carried along by a flow of material"
+            .to_owned(),
+        params: vec![
+            param("inflow_rate", "the flow of new material", "10"),
+            param(
+                "residence_time",
+                "the average time material stays in the stock",
+                "10",
+            ),
+            param("initial_material", "the initial amount of material", "100"),
+            param(
+                "attribute_per_unit_of_inflow",
+                "the attribute each unit of new material brings with it",
+                "1",
+            ),
+            param(
+                "initial_attribute_per_unit",
+                "the initial average attribute of the material",
+                "1",
+            ),
+        ],
+        variables: vec![
+            stock(
+                "material",
+                "initial_material",
+                &["material_inflow"],
+                &["material_outflow"],
+            ),
+            flow("material_inflow", "inflow_rate"),
+            flow("material_outflow", "material / residence_time"),
+            stock(
+                "attribute",
+                "initial_material * initial_attribute_per_unit",
+                &["attribute_inflow"],
+                &["attribute_outflow"],
+            ),
+            flow(
+                "attribute_inflow",
+                "material_inflow * attribute_per_unit_of_inflow",
+            ),
+            flow("attribute_outflow", "material_outflow * average_attribute"),
+            aux("average_attribute", "safediv(attribute, material, 0)"),
+        ],
+    }
+}
+
+fn capacity_utilization() -> Template {
+    Template {
+        name: "capacity_utilization".to_owned(),
+        documentation: "production limited by the smaller of demand and capacity".to_owned(),
+        params: vec![
+            param("demand", "the output wanted", "100"),
+            param("capacity", "the most that can be produced", "100"),
+        ],
+        variables: vec![
+            aux("utilization", "min(1, safediv(demand, capacity, 0))"),
+            aux("production", "capacity * utilization"),
+        ],
+    }
+}
+
+/// molecules returns every molecule in the library.
+pub fn molecules() -> Vec<Template> {
+    vec![
+        smooth(),
+        Template::aging_chain(3),
+        coflow(),
+        capacity_utilization(),
+        Template::bass_diffusion(),
+    ]
+}
+
+/// molecule returns the molecule with the given name, if there is one.
+pub fn molecule(name: &str) -> Option<Template> {
+    let name = canonicalize(name);
+    molecules()
+        .into_iter()
+        .find(|molecule| canonicalize(&molecule.name) == name)
+}
+
+#[test]
+fn test_molecules() {
+    use std::collections::HashMap;
+
+    use crate::datamodel::View;
+    use crate::testutils::{sim_specs_with_units, x_model, x_project};
+    use crate::{Project, Simulation, Vm};
+
+    assert_eq!("coflow", molecule("CoFlow").unwrap().name);
+    assert!(molecule("nope").is_none());
+
+    for template in molecules() {
+        let mut project = x_project(sim_specs_with_units("year"), &[x_model("main", vec![])]);
+        project.sim_specs.stop = 10.0;
+        project
+            .insert_template("main", &template, "m", &HashMap::new(), Some((0.0, 0.0)))
+            .unwrap();
+
+        let View::StockFlow(view) = &project.models[0].views[0];
+        assert!(view.validate_flow_points().is_empty(), "{}", template.name);
+
+        // every molecule simulates with its default params
+        let project = Project::from(project);
+        let sim = Simulation::new(&project, "main").unwrap();
+        let mut vm = Vm::new(sim.compile().unwrap()).unwrap();
+        vm.run_to_end().unwrap();
+    }
+}
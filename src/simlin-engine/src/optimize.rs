@@ -0,0 +1,645 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Searching for the parameter values that do best on several payoffs
+//! at once, like the cost and the benefit of a policy.  When payoffs
+//! trade off against each other there is no single best answer, so the
+//! search returns the Pareto front: the candidates that no other
+//! candidate beats on every objective.  The search is NSGA-II, an
+//! evolutionary algorithm that keeps the candidates on the best fronts
+//! and prefers those in the least crowded parts of them.  Along with
+//! constants, the points of graphical functions can be searched over,
+//! to shape policies that respond to the state of the model.
+
+use std::cmp::Ordering;
+
+use crate::common::Result;
+use crate::datamodel;
+use crate::ensemble::Rng;
+use crate::freeze::simulate_with_stubs;
+use crate::sim_err;
+use crate::stubs::{Stub, Stubs};
+use crate::vm::Results;
+
+// the distribution indices of simulated binary crossover and of
+// polynomial mutation: larger values keep children closer to parents
+const CROSSOVER_ETA: f64 = 15.0;
+const MUTATION_ETA: f64 = 20.0;
+const CROSSOVER_PROBABILITY: f64 = 0.9;
+
+/// Parameter is a constant to search over, between `min` and `max`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Parameter {
+    pub ident: String,
+    pub min: f64,
+    pub max: f64,
+}
+
+/// Monotonicity constrains the shape of a table searched over.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Monotonicity {
+    Any,
+    Increasing,
+    Decreasing,
+}
+
+/// TableParameter is a graphical function whose y points are searched
+/// over, each between `min` and `max`, like a policy's response to the
+/// gap it closes.  The x points, and the number of points, stay as they
+/// are in the model.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TableParameter {
+    pub ident: String,
+    pub min: f64,
+    pub max: f64,
+    pub monotonicity: Monotonicity,
+}
+
+/// Aggregate reduces a variable's series to a single payoff.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Aggregate {
+    Final,
+    Sum,
+    Mean,
+    Max,
+    Min,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    Minimize,
+    Maximize,
+}
+
+/// Objective is a payoff computed from the series of the variable
+/// `ident`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Objective {
+    pub ident: String,
+    pub aggregate: Aggregate,
+    pub direction: Direction,
+}
+
+/// SearchOptions sets the size and length of the search.  The same
+/// options and seed always find the same front.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SearchOptions {
+    pub population: usize,
+    pub generations: usize,
+    pub seed: u64,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            population: 40,
+            generations: 50,
+            seed: 1,
+        }
+    }
+}
+
+/// Candidate is a set of parameter values, in the order the parameters
+/// were given followed by the y points of each table, and the payoff of
+/// each objective with those values.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Candidate {
+    pub values: Vec<f64>,
+    pub payoffs: Vec<f64>,
+}
+
+fn graphical_function_mut<'a>(
+    model: &'a mut datamodel::Model,
+    ident: &str,
+) -> Option<&'a mut datamodel::GraphicalFunction> {
+    match model.get_variable_mut(ident)? {
+        datamodel::Variable::Aux(aux) => aux.gf.as_mut(),
+        datamodel::Variable::Flow(flow) => flow.gf.as_mut(),
+        _ => None,
+    }
+}
+
+pub(crate) fn valid_range(ident: &str, min: f64, max: f64) -> Result<()> {
+    if !min.is_finite() || !max.is_finite() || min > max {
+        return sim_err!(
+            Generic,
+            format!("{} has an invalid range: {} to {}", ident, min, max)
+        );
+    }
+    Ok(())
+}
+
+fn aggregate(series: &[f64], aggregate: Aggregate) -> f64 {
+    match aggregate {
+        Aggregate::Final => series.last().copied().unwrap_or(f64::NAN),
+        Aggregate::Sum => series.iter().sum(),
+        Aggregate::Mean => series.iter().sum::<f64>() / series.len() as f64,
+        Aggregate::Max => series.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        Aggregate::Min => series.iter().copied().fold(f64::INFINITY, f64::min),
+    }
+}
+
+fn payoffs(results: &Results, objectives: &[Objective]) -> Result<Vec<f64>> {
+    objectives
+        .iter()
+        .map(|objective| match results.get_series(&objective.ident) {
+            Some(series) => Ok(aggregate(&series, objective.aggregate)),
+            None => sim_err!(DoesNotExist, objective.ident.clone()),
+        })
+        .collect()
+}
+
+// to_costs turns payoffs into values to minimize, with payoffs that
+// aren't numbers the worst possible.
+fn to_costs(payoffs: &[f64], objectives: &[Objective]) -> Vec<f64> {
+    payoffs
+        .iter()
+        .zip(objectives.iter())
+        .map(|(payoff, objective)| {
+            if payoff.is_nan() {
+                f64::INFINITY
+            } else if objective.direction == Direction::Maximize {
+                -payoff
+            } else {
+                *payoff
+            }
+        })
+        .collect()
+}
+
+fn dominates(a: &[f64], b: &[f64]) -> bool {
+    a.iter().zip(b.iter()).all(|(a, b)| a <= b) && a.iter().zip(b.iter()).any(|(a, b)| a < b)
+}
+
+// sort_fronts returns the indexes of `costs` grouped into successive
+// non-dominated fronts, best first.
+fn sort_fronts(costs: &[Vec<f64>]) -> Vec<Vec<usize>> {
+    let n = costs.len();
+    let mut dominated: Vec<Vec<usize>> = vec![vec![]; n];
+    let mut dominators = vec![0usize; n];
+    for (i, a) in costs.iter().enumerate() {
+        for (j, b) in costs.iter().enumerate() {
+            if dominates(a, b) {
+                dominated[i].push(j);
+            } else if dominates(b, a) {
+                dominators[i] += 1;
+            }
+        }
+    }
+
+    let mut fronts = vec![];
+    let mut front: Vec<usize> = (0..n).filter(|i| dominators[*i] == 0).collect();
+    while !front.is_empty() {
+        let mut next = vec![];
+        for i in front.iter() {
+            for j in dominated[*i].iter() {
+                dominators[*j] -= 1;
+                if dominators[*j] == 0 {
+                    next.push(*j);
+                }
+            }
+        }
+        fronts.push(front);
+        front = next;
+    }
+    fronts
+}
+
+// crowding returns how far apart each member of `front` is from its
+// neighbors along every objective, with the extremes infinitely far.
+// `m` picks the same objective out of each member's costs, so there is
+// no single slice to iterate over instead.
+#[allow(clippy::needless_range_loop)]
+fn crowding(costs: &[Vec<f64>], front: &[usize]) -> Vec<f64> {
+    let mut distance = vec![0.0; front.len()];
+    let objectives = costs.get(front[0]).map(|c| c.len()).unwrap_or(0);
+    for m in 0..objectives {
+        let mut order: Vec<usize> = (0..front.len()).collect();
+        order.sort_by(|a, b| {
+            costs[front[*a]][m]
+                .partial_cmp(&costs[front[*b]][m])
+                .unwrap_or(Ordering::Equal)
+        });
+        let (lo, hi) = (
+            costs[front[order[0]]][m],
+            costs[front[order[order.len() - 1]]][m],
+        );
+        distance[order[0]] = f64::INFINITY;
+        distance[order[order.len() - 1]] = f64::INFINITY;
+        let range = hi - lo;
+        if !range.is_finite() || range <= 0.0 {
+            continue;
+        }
+        for k in 1..order.len().saturating_sub(1) {
+            let gap = costs[front[order[k + 1]]][m] - costs[front[order[k - 1]]][m];
+            distance[order[k]] += gap / range;
+        }
+    }
+    distance
+}
+
+struct Search<'a> {
+    project: &'a datamodel::Project,
+    model_name: &'a str,
+    params: &'a [Parameter],
+    tables: &'a [TableParameter],
+    objectives: &'a [Objective],
+    // the range of each value in a candidate: the params, then the
+    // points of each table
+    bounds: Vec<(f64, f64)>,
+    table_sizes: Vec<usize>,
+    rng: Rng,
+}
+
+impl Search<'_> {
+    fn evaluate(&self, values: Vec<f64>) -> Result<Candidate> {
+        let mut stubs = Stubs::new();
+        for (param, value) in self.params.iter().zip(values.iter()) {
+            stubs.stub(self.model_name, &param.ident, Stub::Constant(*value));
+        }
+        let results = if self.tables.is_empty() {
+            simulate_with_stubs(self.project, self.model_name, &stubs)?
+        } else {
+            let mut project = self.project.clone();
+            let model = project.get_model_mut(self.model_name).unwrap();
+            let mut points = &values[self.params.len()..];
+            for table in self.tables.iter() {
+                let gf = graphical_function_mut(model, &table.ident).unwrap();
+                let n = gf.y_points.len();
+                gf.y_points = points[..n].to_vec();
+                gf.y_scale.min = gf.y_scale.min.min(table.min);
+                gf.y_scale.max = gf.y_scale.max.max(table.max);
+                points = &points[n..];
+            }
+            simulate_with_stubs(&project, self.model_name, &stubs)?
+        };
+        let payoffs = payoffs(&results, self.objectives)?;
+        Ok(Candidate { values, payoffs })
+    }
+
+    fn random(&mut self) -> Vec<f64> {
+        let mut values: Vec<f64> = self
+            .bounds
+            .iter()
+            .map(|(min, max)| min + self.rng.next_f64() * (max - min))
+            .collect();
+        self.repair(&mut values);
+        values
+    }
+
+    // repair puts the points of each table constrained to rise or fall
+    // in order.  Sorting keeps every point within its table's range.
+    fn repair(&self, values: &mut [f64]) {
+        let mut start = self.params.len();
+        for (table, n) in self.tables.iter().zip(self.table_sizes.iter().copied()) {
+            let points = &mut values[start..start + n];
+            match table.monotonicity {
+                Monotonicity::Any => {}
+                Monotonicity::Increasing => points.sort_by(|a, b| a.total_cmp(b)),
+                Monotonicity::Decreasing => points.sort_by(|a, b| b.total_cmp(a)),
+            }
+            start += n;
+        }
+    }
+
+    // crossover mixes two parents with simulated binary crossover.
+    fn crossover(&mut self, a: &[f64], b: &[f64]) -> (Vec<f64>, Vec<f64>) {
+        let (mut c1, mut c2) = (a.to_vec(), b.to_vec());
+        if self.rng.next_f64() > CROSSOVER_PROBABILITY {
+            return (c1, c2);
+        }
+        for (i, (min, max)) in self.bounds.iter().copied().enumerate() {
+            if self.rng.next_f64() > 0.5 {
+                continue;
+            }
+            let u = self.rng.next_f64();
+            let beta = if u <= 0.5 {
+                (2.0 * u).powf(1.0 / (CROSSOVER_ETA + 1.0))
+            } else {
+                (1.0 / (2.0 * (1.0 - u))).powf(1.0 / (CROSSOVER_ETA + 1.0))
+            };
+            let (x1, x2) = (a[i], b[i]);
+            c1[i] = (0.5 * ((1.0 + beta) * x1 + (1.0 - beta) * x2)).clamp(min, max);
+            c2[i] = (0.5 * ((1.0 - beta) * x1 + (1.0 + beta) * x2)).clamp(min, max);
+        }
+        self.repair(&mut c1);
+        self.repair(&mut c2);
+        (c1, c2)
+    }
+
+    // mutate perturbs each value, with a chance of one over the number
+    // of values, by polynomial mutation.
+    fn mutate(&mut self, values: &mut [f64]) {
+        let probability = 1.0 / self.bounds.len() as f64;
+        for (value, (min, max)) in values.iter_mut().zip(self.bounds.iter().copied()) {
+            if self.rng.next_f64() >= probability {
+                continue;
+            }
+            let u = self.rng.next_f64();
+            let delta = if u < 0.5 {
+                (2.0 * u).powf(1.0 / (MUTATION_ETA + 1.0)) - 1.0
+            } else {
+                1.0 - (2.0 * (1.0 - u)).powf(1.0 / (MUTATION_ETA + 1.0))
+            };
+            *value = (*value + delta * (max - min)).clamp(min, max);
+        }
+        self.repair(values);
+    }
+
+    // tournament picks the better of two random members of the
+    // population, by front and then by crowding distance.
+    fn tournament(&mut self, ranks: &[(usize, f64)]) -> usize {
+        let (a, b) = (self.rng.below(ranks.len()), self.rng.below(ranks.len()));
+        let better =
+            |(ra, da): (usize, f64), (rb, db): (usize, f64)| ra < rb || (ra == rb && da > db);
+        if better(ranks[b], ranks[a]) {
+            b
+        } else {
+            a
+        }
+    }
+}
+
+// select returns the indexes of the best `size` of `candidates`, along
+// with the front and crowding distance of each of them.
+fn select(
+    candidates: &[Candidate],
+    objectives: &[Objective],
+    size: usize,
+) -> (Vec<usize>, Vec<(usize, f64)>) {
+    let costs: Vec<Vec<f64>> = candidates
+        .iter()
+        .map(|c| to_costs(&c.payoffs, objectives))
+        .collect();
+    let mut chosen = vec![];
+    let mut ranks = vec![];
+    for (rank, front) in sort_fronts(&costs).into_iter().enumerate() {
+        if chosen.len() >= size {
+            break;
+        }
+        let distance = crowding(&costs, &front);
+        let mut members: Vec<(usize, f64)> = front.into_iter().zip(distance).collect();
+        members.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+        for (i, distance) in members.into_iter().take(size - chosen.len()) {
+            chosen.push(i);
+            ranks.push((rank, distance));
+        }
+    }
+    (chosen, ranks)
+}
+
+/// pareto_front returns the candidates no other candidate beats on
+/// every objective.
+pub fn pareto_front(candidates: &[Candidate], objectives: &[Objective]) -> Vec<Candidate> {
+    let costs: Vec<Vec<f64>> = candidates
+        .iter()
+        .map(|c| to_costs(&c.payoffs, objectives))
+        .collect();
+    candidates
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !costs.iter().any(|other| dominates(other, &costs[*i])))
+        .map(|(_, c)| c.clone())
+        .collect()
+}
+
+/// pareto_search searches for the values of `params` and the points of
+/// `tables` that trade off `objectives` best in the model `model_name`,
+/// returning the Pareto front found, ordered by the payoff of the first
+/// objective.  Each candidate is a full simulation, so a search costs
+/// about `population * (generations + 1)` runs.
+pub fn pareto_search(
+    project: &datamodel::Project,
+    model_name: &str,
+    params: &[Parameter],
+    tables: &[TableParameter],
+    objectives: &[Objective],
+    options: &SearchOptions,
+) -> Result<Vec<Candidate>> {
+    if (params.is_empty() && tables.is_empty()) || objectives.is_empty() {
+        return sim_err!(
+            Generic,
+            "a search needs at least one parameter and one objective".to_owned()
+        );
+    }
+    if options.population < 2 {
+        return sim_err!(
+            Generic,
+            "a search needs a population of at least 2".to_owned()
+        );
+    }
+    let mut bounds = vec![];
+    for param in params.iter() {
+        valid_range(&param.ident, param.min, param.max)?;
+        bounds.push((param.min, param.max));
+    }
+    let mut table_sizes = vec![];
+    for table in tables.iter() {
+        valid_range(&table.ident, table.min, table.max)?;
+        let gf = match project
+            .get_model(model_name)
+            .and_then(|model| model.get_variable(&table.ident))
+        {
+            Some(datamodel::Variable::Aux(aux)) => aux.gf.as_ref(),
+            Some(datamodel::Variable::Flow(flow)) => flow.gf.as_ref(),
+            _ => None,
+        };
+        let n = match gf {
+            Some(gf) if !gf.y_points.is_empty() => gf.y_points.len(),
+            _ => return sim_err!(DoesNotExist, table.ident.clone()),
+        };
+        bounds.extend(std::iter::repeat((table.min, table.max)).take(n));
+        table_sizes.push(n);
+    }
+
+    let mut search = Search {
+        project,
+        model_name,
+        params,
+        tables,
+        objectives,
+        bounds,
+        table_sizes,
+        rng: Rng::new(options.seed),
+    };
+    let size = options.population;
+    let mut population = Vec::with_capacity(size);
+    for _ in 0..size {
+        let values = search.random();
+        population.push(search.evaluate(values)?);
+    }
+    let (_, mut ranks) = select(&population, objectives, size);
+
+    for _ in 0..options.generations {
+        let mut offspring = Vec::with_capacity(size);
+        while offspring.len() < size {
+            let a = search.tournament(&ranks);
+            let b = search.tournament(&ranks);
+            let (mut c1, mut c2) = search.crossover(&population[a].values, &population[b].values);
+            search.mutate(&mut c1);
+            search.mutate(&mut c2);
+            offspring.push(search.evaluate(c1)?);
+            if offspring.len() < size {
+                offspring.push(search.evaluate(c2)?);
+            }
+        }
+        population.extend(offspring);
+        let (chosen, next_ranks) = select(&population, objectives, size);
+        population = chosen.into_iter().map(|i| population[i].clone()).collect();
+        ranks = next_ranks;
+    }
+
+    let mut front = pareto_front(&population, objectives);
+    front.sort_by(|a, b| {
+        a.payoffs[0]
+            .partial_cmp(&b.payoffs[0])
+            .unwrap_or(Ordering::Equal)
+    });
+    front.dedup();
+    Ok(front)
+}
+
+#[test]
+fn test_pareto_search() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
+
+    // spending buys benefit, while waste only adds to the cost
+    let mut project = x_project(
+        sim_specs_with_units("year"),
+        &[x_model(
+            "main",
+            vec![
+                x_aux("spending", "5", None),
+                x_aux("waste", "5", None),
+                x_aux("cost", "spending + waste", None),
+                x_stock("benefit", "0", &["gains"], &[], None),
+                x_flow("gains", "SQRT(spending)", None),
+            ],
+        )],
+    );
+    project.sim_specs.stop = 4.0;
+
+    let params = vec![
+        Parameter {
+            ident: "spending".to_owned(),
+            min: 0.0,
+            max: 10.0,
+        },
+        Parameter {
+            ident: "waste".to_owned(),
+            min: 0.0,
+            max: 10.0,
+        },
+    ];
+    let objectives = vec![
+        Objective {
+            ident: "cost".to_owned(),
+            aggregate: Aggregate::Final,
+            direction: Direction::Minimize,
+        },
+        Objective {
+            ident: "benefit".to_owned(),
+            aggregate: Aggregate::Final,
+            direction: Direction::Maximize,
+        },
+    ];
+    let options = SearchOptions {
+        population: 20,
+        generations: 30,
+        seed: 7,
+    };
+
+    let front = pareto_search(&project, "main", &params, &[], &objectives, &options).unwrap();
+    assert!(front.len() > 1);
+    assert_eq!(front, pareto_front(&front, &objectives));
+    // the search is reproducible
+    assert_eq!(
+        front,
+        pareto_search(&project, "main", &params, &[], &objectives, &options).unwrap()
+    );
+    // the front wastes little and spans the tradeoff
+    let waste = front.iter().map(|c| c.values[1]).sum::<f64>() / front.len() as f64;
+    assert!(waste < 1.5);
+    assert!(front.first().unwrap().payoffs[0] < 2.0);
+    assert!(front.last().unwrap().payoffs[0] > 8.0);
+    for pair in front.windows(2) {
+        assert!(pair[0].payoffs[1] <= pair[1].payoffs[1]);
+    }
+
+    let mut missing = objectives.clone();
+    missing[0].ident = "nope".to_owned();
+    assert!(pareto_search(&project, "main", &params, &[], &missing, &options).is_err());
+    let mut backwards = params.clone();
+    backwards[0].min = 20.0;
+    assert!(pareto_search(&project, "main", &backwards, &[], &objectives, &options).is_err());
+}
+
+#[test]
+fn test_table_search() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
+
+    // the policy's effort over time comes from a table, and a higher
+    // final effort is costly
+    let mut response = x_aux("response", "TIME", None);
+    if let datamodel::Variable::Aux(aux) = &mut response {
+        aux.gf = Some(datamodel::GraphicalFunction {
+            kind: datamodel::GraphicalFunctionKind::Continuous,
+            x_points: None,
+            y_points: vec![0.0, 0.0, 0.0],
+            x_scale: datamodel::GraphicalFunctionScale { min: 0.0, max: 4.0 },
+            y_scale: datamodel::GraphicalFunctionScale { min: 0.0, max: 1.0 },
+        });
+    }
+    let mut project = x_project(
+        sim_specs_with_units("year"),
+        &[x_model(
+            "main",
+            vec![
+                response,
+                x_stock("benefit", "0", &["gains"], &[], None),
+                x_flow("gains", "response", None),
+            ],
+        )],
+    );
+    project.sim_specs.stop = 4.0;
+
+    let tables = vec![TableParameter {
+        ident: "response".to_owned(),
+        min: 0.0,
+        max: 10.0,
+        monotonicity: Monotonicity::Increasing,
+    }];
+    let objectives = vec![
+        Objective {
+            ident: "response".to_owned(),
+            aggregate: Aggregate::Final,
+            direction: Direction::Minimize,
+        },
+        Objective {
+            ident: "benefit".to_owned(),
+            aggregate: Aggregate::Final,
+            direction: Direction::Maximize,
+        },
+    ];
+    let options = SearchOptions {
+        population: 16,
+        generations: 20,
+        seed: 3,
+    };
+
+    let front = pareto_search(&project, "main", &[], &tables, &objectives, &options).unwrap();
+    assert!(front.len() > 1);
+    assert_eq!(front, pareto_front(&front, &objectives));
+    for candidate in front.iter() {
+        assert_eq!(3, candidate.values.len());
+        assert!(candidate.values.windows(2).all(|p| p[0] <= p[1]));
+        assert!(candidate.values.iter().all(|v| (0.0..=10.0).contains(v)));
+        // the table's final point is its effort at the end of the run
+        assert_eq!(candidate.values[2], candidate.payoffs[0]);
+    }
+
+    let mut missing = tables.clone();
+    missing[0].ident = "benefit".to_owned();
+    assert!(pareto_search(&project, "main", &[], &missing, &objectives, &options).is_err());
+}
@@ -0,0 +1,114 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Partial simulation of projects where some variables have errors, by
+//! replacing the broken variables with constants.
+
+use crate::common::canonicalize;
+use crate::datamodel::{self, Variable};
+use crate::project::Project;
+use crate::stubs::{Stub, Stubs};
+
+/// BrokenVariable is a variable whose equation has errors.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct BrokenVariable {
+    pub model_name: String,
+    pub ident: String,
+}
+
+/// stub_broken_variables returns stubs that replace every variable with
+/// equation errors with `value` (like NaN or 0), so the rest of the
+/// project can be simulated, along with the variables replaced.  Modules
+/// can't be replaced, so errors in them still prevent simulation, as do
+/// errors that aren't in any one variable.
+pub fn stub_broken_variables(
+    project: &datamodel::Project,
+    value: f64,
+) -> (Stubs, Vec<BrokenVariable>) {
+    let mut stubs = Stubs::new();
+    let mut broken: Vec<BrokenVariable> = vec![];
+    // not every error in a model is necessarily found at once, so check
+    // again until nothing new is broken
+    while let Ok(built) = Project::from_with_stubs(project.clone(), &stubs) {
+        let mut found = vec![];
+        for (model_name, model) in built.models.iter() {
+            if model.implicit {
+                continue;
+            }
+            let model_datamodel = match project.get_model(model_name) {
+                Some(model) => model,
+                None => continue,
+            };
+            for ident in model.get_variable_errors().into_keys() {
+                // implicit variables, like those created for builtins,
+                // aren't in the datamodel
+                let var = model_datamodel
+                    .variables
+                    .iter()
+                    .find(|var| canonicalize(var.get_ident()) == ident);
+                if matches!(var, None | Some(Variable::Module(_))) {
+                    continue;
+                }
+                let var = BrokenVariable {
+                    model_name: model_datamodel.name.clone(),
+                    ident,
+                };
+                if !broken.contains(&var) {
+                    found.push(var);
+                }
+            }
+        }
+        if found.is_empty() {
+            break;
+        }
+        found.sort_unstable();
+        for var in found {
+            stubs.stub(&var.model_name, &var.ident, Stub::Constant(value));
+            broken.push(var);
+        }
+    }
+    (stubs, broken)
+}
+
+#[test]
+fn test_stub_broken_variables() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
+    use crate::{Simulation, Vm};
+
+    let mut project = x_project(
+        sim_specs_with_units("year"),
+        &[x_model(
+            "main",
+            vec![
+                x_stock("population", "100", &["births"], &[], None),
+                x_flow("births", "population * birth_rate", None),
+                x_aux("birth_rate", "0.1 +", None),
+                x_aux("deaths", "population * missing_rate", None),
+                x_aux("growth", "population / 10", None),
+            ],
+        )],
+    );
+    project.sim_specs.stop = 2.0;
+
+    assert!(Simulation::new(&Project::from(project.clone()), "main").is_err());
+
+    let (stubs, broken) = stub_broken_variables(&project, 0.0);
+    assert_eq!(
+        vec!["birth_rate", "deaths"],
+        broken
+            .iter()
+            .map(|var| var.ident.as_str())
+            .collect::<Vec<_>>()
+    );
+
+    let built = Project::from_with_stubs(project, &stubs).unwrap();
+    let sim = Simulation::new(&built, "main").unwrap();
+    let mut vm = Vm::new(sim.compile().unwrap()).unwrap();
+    vm.run_to_end().unwrap();
+    let results = vm.into_results();
+    let growth = results.offset("growth").unwrap();
+    let last = results.iter_rows().last().unwrap();
+    // the broken birth rate is replaced by 0, so the population is constant
+    assert_eq!(10.0, last[growth]);
+}
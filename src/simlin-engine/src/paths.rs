@@ -0,0 +1,174 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Variables inside module instances are addressed by paths like
+//! `hares.births`: the idents of each enclosing module instance, starting
+//! from the root model, followed by the variable's ident and separated
+//! by `.`.  Idents that contain a literal `.` are quoted, as in
+//! `hares."births.total"`.  Paths are used for results columns and
+//! overrides alike.
+
+use crate::common::{canonicalize, quoteize, Ident, Result};
+use crate::datamodel::{self, Variable};
+use crate::model_err;
+use crate::vm::Results;
+
+/// VariablePath is a resolved path to a variable.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct VariablePath {
+    /// canonical idents of the enclosing module instances, outermost first
+    pub modules: Vec<Ident>,
+    /// the model the variable is defined in
+    pub model_name: String,
+    pub ident: Ident,
+}
+
+impl VariablePath {
+    /// name returns the display form of the path.
+    pub fn name(&self) -> String {
+        join_path(self.modules.iter().chain(std::iter::once(&self.ident)))
+    }
+}
+
+/// split_path returns the canonical idents that make up a path.
+pub fn split_path(path: &str) -> Vec<Ident> {
+    canonicalize(path)
+        .split('·')
+        .map(|part| part.to_owned())
+        .collect()
+}
+
+/// join_path returns the path of a variable given the canonical idents
+/// of its enclosing module instances and the variable itself.
+pub fn join_path<'a>(parts: impl IntoIterator<Item = &'a Ident>) -> String {
+    parts
+        .into_iter()
+        .map(|part| quoteize(part))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+// find_variable looks up a variable by canonical ident.  Datamodel
+// idents may contain literal dots, which canonicalize would otherwise
+// treat as module separators.
+fn find_variable<'a>(model: &'a datamodel::Model, ident: &str) -> Option<&'a Variable> {
+    model
+        .variables
+        .iter()
+        .find(|var| canonicalize(&quoteize(var.get_ident())) == ident)
+}
+
+/// resolve_path finds the variable a path refers to, starting from the
+/// model `model_name`.  Any subscript on the final ident is ignored.
+pub fn resolve_path(
+    project: &datamodel::Project,
+    model_name: &str,
+    path: &str,
+) -> Result<VariablePath> {
+    let mut parts = split_path(path);
+    let ident = parts.pop().unwrap_or_default();
+    let ident = match ident.find('[') {
+        Some(pos) => ident[..pos].to_owned(),
+        None => ident,
+    };
+
+    let mut model_name = model_name.to_owned();
+    for module_ident in parts.iter() {
+        let model = match project.get_model(&model_name) {
+            Some(model) => model,
+            None => return model_err!(BadModelName, model_name),
+        };
+        model_name = match find_variable(model, module_ident) {
+            Some(Variable::Module(module)) => module.model_name.clone(),
+            Some(_) => return model_err!(ExpectedModule, quoteize(module_ident)),
+            None => return model_err!(DoesNotExist, path.to_owned()),
+        };
+    }
+
+    let exists = project
+        .get_model(&model_name)
+        .and_then(|model| find_variable(model, &ident))
+        .is_some();
+    if !exists {
+        return model_err!(DoesNotExist, path.to_owned());
+    }
+    Ok(VariablePath {
+        modules: parts,
+        model_name,
+        ident,
+    })
+}
+
+impl Results {
+    /// offset returns the offset of the column for a path in each step
+    /// of the results.
+    pub fn offset(&self, path: &str) -> Option<usize> {
+        self.offsets
+            .get(path)
+            .or_else(|| self.offsets.get(&quoteize(&canonicalize(path))))
+            .copied()
+    }
+}
+
+#[test]
+fn test_paths() {
+    use crate::common::ErrorCode;
+    use crate::testutils::{sim_specs_with_units, x_aux, x_model, x_module, x_project};
+    use crate::{Project, Simulation};
+
+    assert_eq!(
+        vec!["hares", "births.total"],
+        split_path("Hares.\"births.total\"")
+    );
+    assert_eq!(
+        "hares.\"births.total\"",
+        join_path(&["hares".to_owned(), "births.total".to_owned()])
+    );
+
+    let project = x_project(
+        sim_specs_with_units("time"),
+        &[
+            x_model(
+                "main",
+                vec![x_module("hares", &[], None), x_aux("lynxes", "3", None)],
+            ),
+            x_model(
+                "hares",
+                vec![
+                    x_aux("births", "2", None),
+                    x_aux("births.total", "births * 2", None),
+                ],
+            ),
+        ],
+    );
+
+    let path = resolve_path(&project, "main", "Hares.Births").unwrap();
+    assert_eq!(
+        VariablePath {
+            modules: vec!["hares".to_owned()],
+            model_name: "hares".to_owned(),
+            ident: "births".to_owned(),
+        },
+        path
+    );
+    assert_eq!("hares.births", path.name());
+    let err = |path: &str| resolve_path(&project, "main", path).unwrap_err().code;
+    assert_eq!(ErrorCode::DoesNotExist, err("hares.deaths"));
+    assert_eq!(ErrorCode::ExpectedModule, err("lynxes.births"));
+
+    let sim_project = Project::from(project.clone());
+    let sim = Simulation::new(&sim_project, "main").unwrap();
+    let results = sim.run_to_end().unwrap();
+    assert!(results.offsets.contains_key("hares.births"));
+    assert!(results.offsets.contains_key("hares.\"births.total\""));
+    let total = resolve_path(&project, "main", "hares.\"births.total\"").unwrap();
+    assert_eq!(
+        results.offset("hares.\"births.total\""),
+        results.offset(&total.name())
+    );
+    assert_eq!(
+        4.0,
+        results.get_series("Hares.\"Births.Total\"").unwrap()[0]
+    );
+}
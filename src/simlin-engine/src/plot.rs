@@ -0,0 +1,546 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Rendering simulation results as SVG line charts, for reports and
+//! other places where there is no browser to draw them.
+
+use std::fmt::Write;
+
+use crate::common::Result;
+use crate::datamodel::{Graph, GraphKind};
+use crate::vm::Results;
+use crate::{model_err, sim_err};
+
+const WIDTH: f64 = 640.0;
+const HEIGHT: f64 = 400.0;
+// room for the title above the plot, tick labels to its left and below
+// it, and the legend to its right
+const MARGIN_TOP: f64 = 40.0;
+const MARGIN_LEFT: f64 = 70.0;
+const MARGIN_BOTTOM: f64 = 40.0;
+const MARGIN_RIGHT: f64 = 160.0;
+const TARGET_TICKS: f64 = 5.0;
+
+// the Tableau 10 palette: distinguishable, and reasonable in grayscale
+const COLORS: &[&str] = &[
+    "#4e79a7", "#f28e2b", "#e15759", "#76b7b2", "#59a14f", "#edc948", "#b07aa1", "#ff9da7",
+    "#9c755f", "#bab0ac",
+];
+
+/// Line is a variable's value over time in one run.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Line {
+    pub path: String,
+    pub label: String,
+    /// (time, value) at each saved step
+    pub points: Vec<(f64, f64)>,
+    /// comparison runs are drawn dashed
+    pub dashed: bool,
+    /// the color to draw the line, rather than the variable's color
+    pub color: Option<String>,
+}
+
+/// Envelope is the range a variable's value took over time across a
+/// set of runs, like the runs of a sensitivity analysis.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Envelope {
+    pub path: String,
+    pub label: String,
+    /// (time, lowest value, highest value) at each saved step
+    pub points: Vec<(f64, f64, f64)>,
+}
+
+/// Chart is a time-series chart of one or more variables.  Lines and
+/// envelopes of the same variable are drawn in the same color.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Chart {
+    pub title: String,
+    pub width: f64,
+    pub height: f64,
+    pub lines: Vec<Line>,
+    pub envelopes: Vec<Envelope>,
+    /// the range of the y axis, if it isn't fit to the data
+    pub y_range: Option<(f64, f64)>,
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// ticks returns evenly spaced, round values covering [min, max]: about
+/// five of them, at a multiple of 1, 2 or 5 times a power of ten.
+fn ticks(min: f64, max: f64) -> Vec<f64> {
+    let raw = (max - min) / TARGET_TICKS;
+    let magnitude = 10f64.powf(raw.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0]
+        .iter()
+        .map(|m| m * magnitude)
+        .find(|step| *step >= raw)
+        .unwrap_or(10.0 * magnitude);
+    let first = (min / step).ceil() as i64;
+    let last = (max / step).floor() as i64;
+    (first..=last).map(|i| i as f64 * step).collect()
+}
+
+// format_tick prints a tick value with as many decimals as the spacing
+// between ticks needs, so that 0.1 + 0.2 shows as 0.3
+fn format_tick(value: f64, step: f64) -> String {
+    // the tolerance keeps a step of 0.09999... from getting 2 decimals
+    let decimals = (-(step.log10() + 1e-6).floor()).max(0.0) as usize;
+    let s = format!("{:.*}", decimals, value);
+    if s.starts_with('-') && s[1..].chars().all(|c| c == '0' || c == '.') {
+        s[1..].to_owned()
+    } else {
+        s
+    }
+}
+
+// the range of the data, widened if it is empty so there is something
+// to scale against
+fn padded_range(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    let (min, max) = values
+        .filter(|v| v.is_finite())
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| {
+            (lo.min(v), hi.max(v))
+        });
+    if min > max {
+        (0.0, 1.0)
+    } else if min == max {
+        let pad = if min == 0.0 { 1.0 } else { min.abs() * 0.1 };
+        (min - pad, max + pad)
+    } else {
+        (min, max)
+    }
+}
+
+impl Chart {
+    pub fn new(title: &str) -> Self {
+        Chart {
+            title: title.to_owned(),
+            width: WIDTH,
+            height: HEIGHT,
+            lines: vec![],
+            envelopes: vec![],
+            y_range: None,
+        }
+    }
+
+    /// from_graph returns a chart of a graph saved with the model, using
+    /// its colors and scales.  Only time-series graphs can be drawn.
+    pub fn from_graph(graph: &Graph, results: &Results) -> Result<Self> {
+        if graph.kind != GraphKind::TimeSeries {
+            return sim_err!(
+                Generic,
+                format!(
+                    "can't draw '{}': only time-series graphs are drawn",
+                    graph.title
+                )
+            );
+        }
+        let mut chart = Chart::new(&graph.title);
+        for plot in graph.plots.iter() {
+            chart.add_series(&plot.ident, results, &plot.ident)?;
+            if let Some(line) = chart.lines.last_mut() {
+                line.color = plot.color.clone();
+            }
+        }
+        // all plots are drawn against one axis, which covers each of
+        // their scales
+        chart.y_range = graph
+            .plots
+            .iter()
+            .filter_map(|plot| plot.scale.as_ref())
+            .map(|scale| (scale.min, scale.max))
+            .reduce(|(lo, hi), (min, max)| (lo.min(min), hi.max(max)));
+        Ok(chart)
+    }
+
+    /// add_series adds a line for the variable at `path` in `results`.
+    pub fn add_series(&mut self, label: &str, results: &Results, path: &str) -> Result<()> {
+        self.add_line(label, results, path, false)
+    }
+
+    /// add_comparison adds a dashed line for the variable at `path` in
+    /// another run, like a reference run or the baseline of a policy
+    /// change.
+    pub fn add_comparison(&mut self, label: &str, results: &Results, path: &str) -> Result<()> {
+        self.add_line(label, results, path, true)
+    }
+
+    fn add_line(&mut self, label: &str, results: &Results, path: &str, dashed: bool) -> Result<()> {
+        let points = match results.timed_series(path) {
+            Some(points) => points,
+            None => return model_err!(DoesNotExist, path.to_owned()),
+        };
+        self.lines.push(Line {
+            path: path.to_owned(),
+            label: label.to_owned(),
+            points,
+            dashed,
+            color: None,
+        });
+        Ok(())
+    }
+
+    /// add_envelope adds a shaded band between the lowest and highest
+    /// value of the variable at `path` across `runs` at each saved step.
+    /// The runs must have the same saved steps.
+    pub fn add_envelope(&mut self, label: &str, runs: &[Results], path: &str) -> Result<()> {
+        let mut points: Vec<(f64, f64, f64)> = vec![];
+        for (i, results) in runs.iter().enumerate() {
+            let series = match results.timed_series(path) {
+                Some(series) => series,
+                None => return model_err!(DoesNotExist, path.to_owned()),
+            };
+            if i == 0 {
+                points = series.into_iter().map(|(t, v)| (t, v, v)).collect();
+                continue;
+            }
+            for (point, (_, v)) in points.iter_mut().zip(series) {
+                point.1 = point.1.min(v);
+                point.2 = point.2.max(v);
+            }
+        }
+        self.envelopes.push(Envelope {
+            path: path.to_owned(),
+            label: label.to_owned(),
+            points,
+        });
+        Ok(())
+    }
+
+    // each variable gets the next color, envelopes' variables first
+    fn color(&self, path: &str) -> &'static str {
+        let mut paths: Vec<&str> = vec![];
+        for p in self
+            .envelopes
+            .iter()
+            .map(|e| e.path.as_str())
+            .chain(self.lines.iter().map(|l| l.path.as_str()))
+        {
+            if !paths.contains(&p) {
+                paths.push(p);
+            }
+        }
+        let i = paths.iter().position(|p| *p == path).unwrap_or(0);
+        COLORS[i % COLORS.len()]
+    }
+
+    /// to_svg renders the chart as a standalone SVG document.
+    pub fn to_svg(&self) -> String {
+        let (t0, t1) = padded_range(
+            self.lines
+                .iter()
+                .flat_map(|l| l.points.iter().map(|p| p.0))
+                .chain(
+                    self.envelopes
+                        .iter()
+                        .flat_map(|e| e.points.iter().map(|p| p.0)),
+                ),
+        );
+        let (y0, y1) = match self.y_range {
+            Some((min, max)) if min < max => (min, max),
+            _ => padded_range(
+                self.lines
+                    .iter()
+                    .flat_map(|l| l.points.iter().map(|p| p.1))
+                    .chain(
+                        self.envelopes
+                            .iter()
+                            .flat_map(|e| e.points.iter().flat_map(|p| [p.1, p.2])),
+                    ),
+            ),
+        };
+        let (left, top) = (MARGIN_LEFT, MARGIN_TOP);
+        let right = (self.width - MARGIN_RIGHT).max(left + 1.0);
+        let bottom = (self.height - MARGIN_BOTTOM).max(top + 1.0);
+        let x = |t: f64| left + (t - t0) / (t1 - t0) * (right - left);
+        let y = |v: f64| bottom - (v - y0) / (y1 - y0) * (bottom - top);
+
+        let mut svg = String::new();
+        let out = &mut svg;
+        // writing to a String can't fail
+        let _ = writeln!(
+            out,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" font-family=\"sans-serif\" font-size=\"12\">",
+            self.width, self.height, self.width, self.height
+        );
+        let _ = writeln!(
+            out,
+            "<rect width=\"{}\" height=\"{}\" fill=\"white\"/>",
+            self.width, self.height
+        );
+        if !self.title.is_empty() {
+            let _ = writeln!(
+                out,
+                "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\" font-size=\"16\">{}</text>",
+                (left + right) / 2.0,
+                top / 2.0 + 6.0,
+                escape(&self.title)
+            );
+        }
+
+        // axes, with grid lines at each tick
+        let x_ticks = ticks(t0, t1);
+        let x_step = x_ticks.get(1).map(|t| t - x_ticks[0]).unwrap_or(t1 - t0);
+        for t in x_ticks.iter() {
+            let _ = writeln!(
+                out,
+                "<line x1=\"{0:.1}\" y1=\"{1:.1}\" x2=\"{0:.1}\" y2=\"{2:.1}\" stroke=\"#e0e0e0\"/>",
+                x(*t),
+                top,
+                bottom
+            );
+            let _ = writeln!(
+                out,
+                "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>",
+                x(*t),
+                bottom + 16.0,
+                format_tick(*t, x_step)
+            );
+        }
+        let y_ticks = ticks(y0, y1);
+        let y_step = y_ticks.get(1).map(|v| v - y_ticks[0]).unwrap_or(y1 - y0);
+        for v in y_ticks.iter() {
+            let _ = writeln!(
+                out,
+                "<line x1=\"{0:.1}\" y1=\"{1:.1}\" x2=\"{2:.1}\" y2=\"{1:.1}\" stroke=\"#e0e0e0\"/>",
+                left,
+                y(*v),
+                right
+            );
+            let _ = writeln!(
+                out,
+                "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>",
+                left - 6.0,
+                y(*v) + 4.0,
+                format_tick(*v, y_step)
+            );
+        }
+        let _ = writeln!(
+            out,
+            "<path d=\"M{0:.1},{1:.1}V{2:.1}H{3:.1}\" fill=\"none\" stroke=\"black\"/>",
+            left, top, bottom, right
+        );
+
+        // a fixed y range can cut off the data; keep it inside the axes
+        let _ = writeln!(
+            out,
+            "<clipPath id=\"plot-area\"><rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\"/></clipPath>",
+            left,
+            top,
+            right - left,
+            bottom - top
+        );
+        let _ = writeln!(out, "<g clip-path=\"url(#plot-area)\">");
+        for envelope in self.envelopes.iter() {
+            // each stretch of finite values is its own polygon: along the
+            // top forwards, then back along the bottom
+            for run in envelope
+                .points
+                .split(|(_, lo, hi)| !lo.is_finite() || !hi.is_finite())
+                .filter(|run| !run.is_empty())
+            {
+                let mut points: Vec<String> = run
+                    .iter()
+                    .map(|(t, _, hi)| format!("{:.1},{:.1}", x(*t), y(*hi)))
+                    .collect();
+                points.extend(
+                    run.iter()
+                        .rev()
+                        .map(|(t, lo, _)| format!("{:.1},{:.1}", x(*t), y(*lo))),
+                );
+                let _ = writeln!(
+                    out,
+                    "<polygon points=\"{}\" fill=\"{}\" fill-opacity=\"0.25\" stroke=\"none\"/>",
+                    points.join(" "),
+                    self.color(&envelope.path)
+                );
+            }
+        }
+
+        for line in self.lines.iter() {
+            // NaN and infinite values leave gaps in the line
+            for run in line
+                .points
+                .split(|(_, v)| !v.is_finite())
+                .filter(|run| !run.is_empty())
+            {
+                let points: Vec<String> = run
+                    .iter()
+                    .map(|(t, v)| format!("{:.1},{:.1}", x(*t), y(*v)))
+                    .collect();
+                let dash = if line.dashed {
+                    " stroke-dasharray=\"6,4\""
+                } else {
+                    ""
+                };
+                let _ = writeln!(
+                    out,
+                    "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\"{}/>",
+                    points.join(" "),
+                    line.color.as_deref().unwrap_or(self.color(&line.path)),
+                    dash
+                );
+            }
+        }
+        let _ = writeln!(out, "</g>");
+
+        // the legend lists envelopes, then lines, in the order added
+        let entries = self
+            .envelopes
+            .iter()
+            .map(|e| (e.label.as_str(), self.color(&e.path), None))
+            .chain(self.lines.iter().map(|l| {
+                let color = l.color.as_deref().unwrap_or(self.color(&l.path));
+                (l.label.as_str(), color, Some(l.dashed))
+            }));
+        for (i, (label, color, dashed)) in entries.enumerate() {
+            let (lx, ly) = (right + 16.0, top + 8.0 + 20.0 * i as f64);
+            let _ = match dashed {
+                None => writeln!(
+                    out,
+                    "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"20\" height=\"10\" fill=\"{}\" fill-opacity=\"0.25\"/>",
+                    lx,
+                    ly - 5.0,
+                    color
+                ),
+                Some(dashed) => writeln!(
+                    out,
+                    "<line x1=\"{0:.1}\" y1=\"{1:.1}\" x2=\"{2:.1}\" y2=\"{1:.1}\" stroke=\"{3}\" stroke-width=\"2\"{4}/>",
+                    lx,
+                    ly,
+                    lx + 20.0,
+                    color,
+                    if dashed { " stroke-dasharray=\"6,4\"" } else { "" }
+                ),
+            };
+            let _ = writeln!(
+                out,
+                "<text x=\"{:.1}\" y=\"{:.1}\">{}</text>",
+                lx + 26.0,
+                ly + 4.0,
+                escape(label)
+            );
+        }
+
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+#[test]
+fn test_ticks() {
+    assert_eq!(vec![0.0, 2.0, 4.0, 6.0, 8.0, 10.0], ticks(0.0, 10.0));
+    assert_eq!(vec![20.0, 40.0, 60.0, 80.0], ticks(13.0, 97.0));
+    assert_eq!(
+        vec!["0.3", "0.4", "0.5"],
+        ticks(0.25, 0.55)
+            .iter()
+            .map(|t| format_tick(*t, 0.1))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!("0", format_tick(-0.0001, 1.0));
+    assert_eq!((4.5, 5.5), padded_range([5.0, f64::NAN].into_iter()));
+    assert_eq!((0.0, 1.0), padded_range(std::iter::empty()));
+}
+
+#[test]
+fn test_chart_svg() {
+    use crate::common::ErrorCode;
+    use crate::datamodel::{GraphicalFunctionScale, Plot};
+    use crate::ensemble::{run_ensemble, Override, Run};
+    use crate::freeze::simulate_with_stubs;
+    use crate::stubs::Stubs;
+    use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
+
+    let mut project = x_project(
+        sim_specs_with_units("year"),
+        &[x_model(
+            "main",
+            vec![
+                x_stock("population", "100", &["births"], &[], None),
+                x_flow("births", "population * birth_rate", None),
+                x_aux("birth_rate", "0.1", None),
+            ],
+        )],
+    );
+    project.sim_specs.stop = 10.0;
+    let baseline = simulate_with_stubs(&project, "main", &Stubs::new()).unwrap();
+    let runs = [0.05, 0.1, 0.15]
+        .iter()
+        .enumerate()
+        .map(|(i, rate)| Run {
+            seed: i as u64,
+            overrides: vec![Override {
+                ident: "birth_rate".to_owned(),
+                value: *rate,
+            }],
+        })
+        .collect();
+    let (_, sweep) = run_ensemble(&project, "main", runs).unwrap();
+
+    let mut chart = Chart::new("Population <growth>");
+    chart
+        .add_envelope("birth rate 5-15%", &sweep, "population")
+        .unwrap();
+    chart
+        .add_series("population", &baseline, "population")
+        .unwrap();
+    chart
+        .add_comparison("slow growth", &sweep[0], "population")
+        .unwrap();
+    chart.add_series("births", &baseline, "births").unwrap();
+    let err = chart
+        .add_series("missing", &baseline, "missing")
+        .unwrap_err();
+    assert_eq!(ErrorCode::DoesNotExist, err.code);
+
+    let envelope = &chart.envelopes[0];
+    assert_eq!(11, envelope.points.len());
+    let (_, lo, hi) = envelope.points[10];
+    assert!(lo < baseline.timed_series("population").unwrap()[10].1);
+    assert!(hi > baseline.timed_series("population").unwrap()[10].1);
+
+    let svg = chart.to_svg();
+    assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+    assert!(svg.ends_with("</svg>\n"));
+    assert!(svg.contains("Population &lt;growth&gt;"));
+    assert_eq!(3, svg.matches("<polyline").count());
+    assert_eq!(1, svg.matches("<polygon").count());
+    assert_eq!(2, svg.matches("stroke-dasharray").count());
+    // the envelope and both lines of population (and their legend
+    // entries) share a color
+    assert_eq!(6, svg.matches(COLORS[0]).count());
+    assert_eq!(2, svg.matches(COLORS[1]).count());
+
+    let graph = Graph {
+        title: "Births".to_owned(),
+        kind: GraphKind::TimeSeries,
+        plots: vec![Plot {
+            ident: "births".to_owned(),
+            color: Some("green".to_owned()),
+            scale: Some(GraphicalFunctionScale {
+                min: 0.0,
+                max: 50.0,
+            }),
+            precision: None,
+        }],
+        interval: None,
+    };
+    let chart = Chart::from_graph(&graph, &baseline).unwrap();
+    assert_eq!(Some((0.0, 50.0)), chart.y_range);
+    let svg = chart.to_svg();
+    assert_eq!(2, svg.matches("\"green\"").count());
+    assert!(svg.contains(">50<"));
+
+    let table = Graph {
+        kind: GraphKind::Table,
+        ..graph
+    };
+    assert!(Chart::from_graph(&table, &baseline).is_err());
+}
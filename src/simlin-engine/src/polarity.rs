@@ -0,0 +1,389 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+use std::collections::HashMap;
+
+use crate::ast::{BinaryOp, Expr0, UnaryOp};
+use crate::builtins::UntypedBuiltinFn;
+use crate::common::canonicalize;
+use crate::datamodel::view_element::LinkPolarity;
+use crate::datamodel::{Equation, GraphicalFunction, Model, Variable, View, ViewElement};
+use crate::token::LexerType;
+
+/// Sign is the sign of the partial derivative of an expression with
+/// respect to a single variable.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Sign {
+    Independent,
+    Positive,
+    Negative,
+    Unknown,
+}
+
+impl Sign {
+    fn negate(self) -> Sign {
+        match self {
+            Sign::Positive => Sign::Negative,
+            Sign::Negative => Sign::Positive,
+            sign => sign,
+        }
+    }
+
+    // the sign of the sum of two terms
+    fn combine(self, other: Sign) -> Sign {
+        match (self, other) {
+            (Sign::Independent, sign) | (sign, Sign::Independent) => sign,
+            (a, b) if a == b => a,
+            _ => Sign::Unknown,
+        }
+    }
+
+    // the sign of the product of a derivative and a value
+    fn scale(self, value: Option<bool>) -> Sign {
+        match (self, value) {
+            (Sign::Independent, _) => Sign::Independent,
+            (sign, Some(true)) => sign,
+            (sign, Some(false)) => sign.negate(),
+            (_, None) => Sign::Unknown,
+        }
+    }
+}
+
+// is_positive returns the sign of an expression's value, if known.  Model
+// variables are assumed to be positive, as most stocks and flows are.
+fn is_positive(expr: &Expr0) -> Option<bool> {
+    match expr {
+        Expr0::Const(_, n, _) if *n > 0.0 => Some(true),
+        Expr0::Const(_, n, _) if *n < 0.0 => Some(false),
+        Expr0::Const(_, _, _) => None,
+        Expr0::Var(_, _) | Expr0::Subscript(_, _, _) => Some(true),
+        Expr0::Op1(UnaryOp::Positive, l, _) => is_positive(l),
+        Expr0::Op1(UnaryOp::Negative, l, _) => is_positive(l).map(|p| !p),
+        Expr0::Op2(BinaryOp::Mul, l, r, _) | Expr0::Op2(BinaryOp::Div, l, r, _) => {
+            match (is_positive(l), is_positive(r)) {
+                (Some(l), Some(r)) => Some(l == r),
+                _ => None,
+            }
+        }
+        Expr0::Op2(BinaryOp::Add, l, r, _) => match (is_positive(l), is_positive(r)) {
+            (Some(true), Some(true)) => Some(true),
+            (Some(false), Some(false)) => Some(false),
+            _ => None,
+        },
+        Expr0::App(UntypedBuiltinFn(func, _), _) if func == "exp" => Some(true),
+        _ => None,
+    }
+}
+
+// builtins (and stdlib modules) whose value increases with each argument
+fn is_increasing_fn(func: &str) -> bool {
+    matches!(
+        func,
+        "arctan" | "exp" | "int" | "ln" | "log10" | "max" | "mean" | "min" | "sqrt"
+    )
+}
+
+// stdlib modules whose output increases with their first argument
+fn is_increasing_in_input(func: &str) -> bool {
+    matches!(
+        func,
+        "delay1"
+            | "delay3"
+            | "delayn"
+            | "smooth"
+            | "smooth3"
+            | "smooth3i"
+            | "smoothi"
+            | "smth1"
+            | "smth3"
+            | "smthn"
+    )
+}
+
+fn derivative_sign(from: &str, expr: &Expr0) -> Sign {
+    let d = |e: &Expr0| derivative_sign(from, e);
+    match expr {
+        Expr0::Const(_, _, _) => Sign::Independent,
+        Expr0::Var(id, _) | Expr0::Subscript(id, _, _) => {
+            if id == from {
+                Sign::Positive
+            } else {
+                Sign::Independent
+            }
+        }
+        Expr0::Op1(UnaryOp::Positive, l, _) => d(l),
+        Expr0::Op1(UnaryOp::Negative, l, _) => d(l).negate(),
+        Expr0::Op1(UnaryOp::Not, l, _) => match d(l) {
+            Sign::Independent => Sign::Independent,
+            _ => Sign::Unknown,
+        },
+        Expr0::Op2(op, l, r, _) => {
+            let (dl, dr) = (d(l), d(r));
+            match op {
+                BinaryOp::Add => dl.combine(dr),
+                BinaryOp::Sub => dl.combine(dr.negate()),
+                // d(l*r) = dl*r + l*dr
+                BinaryOp::Mul => dl.scale(is_positive(r)).combine(dr.scale(is_positive(l))),
+                // d(l/r) = dl/r - l*dr/r^2
+                BinaryOp::Div => dl
+                    .scale(is_positive(r))
+                    .combine(dr.scale(is_positive(l)).negate()),
+                BinaryOp::Exp => match (dl, dr, r.as_ref()) {
+                    (Sign::Independent, Sign::Independent, _) => Sign::Independent,
+                    (dl, Sign::Independent, Expr0::Const(_, n, _)) if *n > 0.0 => dl,
+                    _ => Sign::Unknown,
+                },
+                _ if dl == Sign::Independent && dr == Sign::Independent => Sign::Independent,
+                _ => Sign::Unknown,
+            }
+        }
+        Expr0::If(cond, t, f, _) => {
+            if d(cond) != Sign::Independent {
+                Sign::Unknown
+            } else {
+                d(t).combine(d(f))
+            }
+        }
+        Expr0::App(UntypedBuiltinFn(func, args), _) => {
+            let signs: Vec<Sign> = args.iter().map(d).collect();
+            if signs.iter().all(|s| *s == Sign::Independent) {
+                Sign::Independent
+            } else if is_increasing_fn(func) {
+                signs.into_iter().fold(Sign::Independent, Sign::combine)
+            } else if is_increasing_in_input(func)
+                && signs[1..].iter().all(|s| *s == Sign::Independent)
+            {
+                signs[0]
+            } else {
+                Sign::Unknown
+            }
+        }
+    }
+}
+
+// the direction of a graphical function: positive if it never decreases,
+// negative if it never increases.
+fn gf_sign(gf: &GraphicalFunction) -> Sign {
+    let ys = &gf.y_points;
+    let increasing = ys.windows(2).all(|w| w[0] <= w[1]);
+    let decreasing = ys.windows(2).all(|w| w[0] >= w[1]);
+    match (increasing, decreasing) {
+        (true, false) => Sign::Positive,
+        (false, true) => Sign::Negative,
+        _ => Sign::Unknown,
+    }
+}
+
+fn equation_sign(from: &str, eqn: &str) -> Sign {
+    match Expr0::new(eqn, LexerType::Equation) {
+        Ok(Some(expr)) => derivative_sign(from, &expr),
+        _ => Sign::Unknown,
+    }
+}
+
+fn variable_sign(from: &str, var: &Variable) -> Sign {
+    let (equation, gf) = match var {
+        Variable::Stock(stock) => (&stock.equation, None),
+        Variable::Flow(flow) => (&flow.equation, flow.gf.as_ref()),
+        Variable::Aux(aux) => (&aux.equation, aux.gf.as_ref()),
+        Variable::Module(_) => return Sign::Unknown,
+    };
+    let sign = match equation {
+        Equation::Scalar(eqn, _) | Equation::ApplyToAll(_, eqn, _) => equation_sign(from, eqn),
+        Equation::Arrayed(_, elements) => elements
+            .iter()
+            .map(|(_, eqn, _)| equation_sign(from, eqn))
+            .fold(Sign::Independent, Sign::combine),
+    };
+    match gf {
+        Some(gf) => match gf_sign(gf) {
+            Sign::Positive => sign,
+            Sign::Negative => sign.negate(),
+            _ if sign == Sign::Independent => sign,
+            _ => Sign::Unknown,
+        },
+        None => sign,
+    }
+}
+
+/// link_polarity infers the polarity of the causal link from the variable
+/// named `from` to `to` by analyzing the structure of `to`'s equation.
+/// Other variables in the equation are assumed to be positive.  Returns
+/// None if the polarity can't be determined.
+pub fn link_polarity(from: &str, to: &Variable) -> Option<LinkPolarity> {
+    match variable_sign(&canonicalize(from), to) {
+        Sign::Positive => Some(LinkPolarity::Positive),
+        Sign::Negative => Some(LinkPolarity::Negative),
+        _ => None,
+    }
+}
+
+impl Model {
+    /// infer_link_polarities sets the polarity of every link in the
+    /// model's views that doesn't already have one, where it can be
+    /// determined from the equations.
+    pub fn infer_link_polarities(&mut self) {
+        let variables: HashMap<String, &Variable> = self
+            .variables
+            .iter()
+            .map(|var| (canonicalize(var.get_ident()), var))
+            .collect();
+
+        for view in self.views.iter_mut() {
+            let View::StockFlow(view) = view;
+            let names: HashMap<i32, String> = view
+                .elements
+                .iter()
+                .filter_map(|element| {
+                    element
+                        .get_name()
+                        .map(|name| (element.get_uid(), canonicalize(name)))
+                })
+                .collect();
+            let aliases: HashMap<i32, i32> = view
+                .elements
+                .iter()
+                .filter_map(|element| match element {
+                    ViewElement::Alias(alias) => Some((alias.uid, alias.alias_of_uid)),
+                    _ => None,
+                })
+                .collect();
+            let name_of = |uid: i32| {
+                let uid = aliases.get(&uid).copied().unwrap_or(uid);
+                names.get(&uid)
+            };
+
+            for element in view.elements.iter_mut() {
+                if let ViewElement::Link(link) = element {
+                    if link.polarity.is_some() {
+                        continue;
+                    }
+                    if let (Some(from), Some(to)) = (name_of(link.from_uid), name_of(link.to_uid)) {
+                        if let Some(to) = variables.get(to) {
+                            link.polarity = link_polarity(from, to);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_link_polarity() {
+    use crate::testutils::{x_aux, x_flow};
+
+    let cases: &[(&str, &str, Option<LinkPolarity>)] = &[
+        ("a", "a * 2", Some(LinkPolarity::Positive)),
+        ("a", "b - a", Some(LinkPolarity::Negative)),
+        ("a", "b / a", Some(LinkPolarity::Negative)),
+        ("a", "a / -3", Some(LinkPolarity::Negative)),
+        ("a", "-(b * a) + 1", Some(LinkPolarity::Negative)),
+        ("a", "a - a * 2", None),
+        (
+            "a",
+            "IF b > 1 THEN a ELSE a * 3",
+            Some(LinkPolarity::Positive),
+        ),
+        ("a", "IF a > 1 THEN 1 ELSE 0", None),
+        ("a", "MAX(a, b) + SMTH1(a, 3)", Some(LinkPolarity::Positive)),
+        ("a", "SIN(a)", None),
+        ("a", "b", None),
+        (
+            "Birth Rate",
+            "population * birth_rate",
+            Some(LinkPolarity::Positive),
+        ),
+    ];
+    for (from, eqn, expected) in cases.iter() {
+        let to = x_aux("to", eqn, None);
+        assert_eq!(*expected, link_polarity(from, &to), "{} in {}", from, eqn);
+    }
+
+    let mut lookup = x_flow("outflow", "a", None);
+    lookup.set_graphical_function(Some(GraphicalFunction {
+        kind: crate::datamodel::GraphicalFunctionKind::Continuous,
+        x_points: None,
+        y_points: vec![3.0, 2.0, 2.0, 1.0],
+        x_scale: crate::datamodel::GraphicalFunctionScale { min: 0.0, max: 1.0 },
+        y_scale: crate::datamodel::GraphicalFunctionScale { min: 0.0, max: 3.0 },
+    }));
+    assert_eq!(Some(LinkPolarity::Negative), link_polarity("a", &lookup));
+}
+
+#[test]
+fn test_infer_link_polarities() {
+    use crate::datamodel::view_element::{self, LabelSide, LinkShape};
+    use crate::datamodel::StockFlow;
+    use crate::testutils::{x_aux, x_model};
+
+    let aux = |name: &str, uid: i32| {
+        ViewElement::Aux(view_element::Aux {
+            name: name.to_owned(),
+            uid,
+            x: 0.0,
+            y: 0.0,
+            label_side: LabelSide::Bottom,
+        })
+    };
+    let link = |uid: i32, from_uid: i32, to_uid: i32, polarity: Option<LinkPolarity>| {
+        ViewElement::Link(view_element::Link {
+            uid,
+            from_uid,
+            to_uid,
+            shape: LinkShape::Straight,
+            polarity,
+            delay_mark: false,
+        })
+    };
+
+    let mut model = x_model(
+        "main",
+        vec![
+            x_aux("price", "10", None),
+            x_aux("demand", "100 / price", None),
+            x_aux("revenue", "price * demand", None),
+        ],
+    );
+    model.views = vec![View::StockFlow(StockFlow {
+        elements: vec![
+            aux("Price", 1),
+            aux("demand", 2),
+            aux("revenue", 3),
+            ViewElement::Alias(view_element::Alias {
+                uid: 4,
+                alias_of_uid: 1,
+                x: 0.0,
+                y: 0.0,
+                label_side: LabelSide::Bottom,
+            }),
+            link(5, 1, 2, None),
+            link(6, 4, 3, None),
+            link(7, 2, 3, Some(LinkPolarity::Negative)),
+        ],
+        view_box: Default::default(),
+        zoom: 1.0,
+    })];
+
+    model.infer_link_polarities();
+
+    let View::StockFlow(view) = &model.views[0];
+    let polarities: Vec<_> = view
+        .elements
+        .iter()
+        .filter_map(|element| match element {
+            ViewElement::Link(link) => Some(link.polarity),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        vec![
+            Some(LinkPolarity::Negative),
+            Some(LinkPolarity::Positive),
+            // already set, so left alone
+            Some(LinkPolarity::Negative),
+        ],
+        polarities
+    );
+}
@@ -2,13 +2,14 @@
 // Use of this source code is governed by the Apache License,
 // Version 2.0, that can be found in the LICENSE file.
 
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeSet, HashMap, HashSet};
 
 use prost::alloc::rc::Rc;
 
-use crate::common::{Error, Ident};
+use crate::common::{canonicalize, Error, Ident, Result};
 use crate::dimensions::DimensionsContext;
 use crate::model::{ModelStage0, ModelStage1, ScopeStage0};
+use crate::stubs::Stubs;
 use crate::units::Context;
 use crate::{datamodel, model};
 
@@ -28,27 +29,76 @@ impl Project {
     }
 }
 
+/// with_project_constants returns a copy of the model with any
+/// project-scope constants it doesn't shadow added as variables.
+fn with_project_constants(
+    model: &datamodel::Model,
+    constants: &[datamodel::Aux],
+) -> datamodel::Model {
+    let mut model = model.clone();
+    let local_idents: HashSet<Ident> = model
+        .variables
+        .iter()
+        .map(|v| canonicalize(v.get_ident()))
+        .collect();
+    model.variables.extend(
+        constants
+            .iter()
+            .filter(|c| !local_idents.contains(&canonicalize(&c.ident)))
+            .map(|c| datamodel::Variable::Aux(c.clone())),
+    );
+    model
+}
+
+fn check_model_units(
+    models: &HashMap<Ident, &ModelStage1>,
+    units_ctx: &Context,
+    model: &mut ModelStage1,
+) {
+    let inferred_units =
+        crate::units_infer::infer(models, units_ctx, model).unwrap_or_else(|_err| {
+            // XXX: for now, ignore inference errors.  They aren't
+            // understandable for anyone but me - we need to thread
+            // location information through at a minimum.
+
+            // let mut errors = model.errors.take().unwrap_or_default();
+            // errors.push(err);
+            // model.errors = Some(errors);
+            Default::default()
+        });
+    model.check_units(units_ctx, &inferred_units)
+}
+
 impl From<datamodel::Project> for Project {
     fn from(project_datamodel: datamodel::Project) -> Self {
-        Self::base_from(project_datamodel, |models, units_ctx, model| {
-            let inferred_units = crate::units_infer::infer(models, units_ctx, model)
-                .unwrap_or_else(|_err| {
-                    // XXX: for now, ignore inference errors.  They aren't
-                    // understandable for anyone but me - we need to thread
-                    // location information through at a minimum.
-
-                    // let mut errors = model.errors.take().unwrap_or_default();
-                    // errors.push(err);
-                    // model.errors = Some(errors);
-                    Default::default()
-                });
-            model.check_units(units_ctx, &inferred_units)
-        })
+        Self::base_from(project_datamodel, check_model_units)
     }
 }
 
 impl Project {
-    pub(crate) fn base_from<F>(project_datamodel: datamodel::Project, mut model_cb: F) -> Self
+    /// from_with_stubs builds a project where the variables in `stubs`
+    /// are replaced by fixed values or time series, for testing parts of
+    /// a model against known inputs.  The datamodel itself is unchanged.
+    pub fn from_with_stubs(project_datamodel: datamodel::Project, stubs: &Stubs) -> Result<Self> {
+        let (specialized, stubs) = stubs.specialize(&project_datamodel)?;
+        stubs.check(&specialized)?;
+        let mut project = Self::base_from_with_stubs(specialized, &stubs, check_model_units);
+        project.datamodel = project_datamodel;
+        Ok(project)
+    }
+
+    pub(crate) fn base_from<F>(project_datamodel: datamodel::Project, model_cb: F) -> Self
+    where
+        F: FnMut(&HashMap<Ident, &ModelStage1>, &Context, &mut ModelStage1),
+    {
+        Self::base_from_with_stubs(project_datamodel, &Stubs::default(), model_cb)
+    }
+
+    fn base_from_with_stubs<F>(
+        project_datamodel: datamodel::Project,
+        stubs: &Stubs,
+        mut model_cb: F,
+    ) -> Self
     where
         F: FnMut(&HashMap<Ident, &ModelStage1>, &Context, &mut ModelStage1),
     {
@@ -75,6 +125,8 @@ impl Project {
                     }
                     Default::default()
                 });
+        project_errors.extend(project_datamodel.sim_specs.check());
+        project_errors.extend(project_datamodel.sim_specs.check_time_units(&units_ctx));
 
         // next, pull in all the models from the stdlib
         let mut models_list: Vec<ModelStage0> = crate::stdlib::MODEL_NAMES
@@ -86,12 +138,10 @@ impl Project {
             .collect();
 
         // extend the list with the models from the project/XMILE file
-        models_list.extend(
-            project_datamodel
-                .models
-                .iter()
-                .map(|m| ModelStage0::new(m, &project_datamodel.dimensions, &units_ctx, false)),
-        );
+        models_list.extend(project_datamodel.models.iter().map(|m| {
+            let m = stubs.apply(&with_project_constants(m, &project_datamodel.constants));
+            ModelStage0::new(&m, &project_datamodel.dimensions, &units_ctx, false)
+        }));
 
         let models: HashMap<Ident, ModelStage0> = models_list
             .iter()
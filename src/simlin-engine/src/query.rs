@@ -0,0 +1,283 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+use crate::common::canonicalize;
+use crate::datamodel::{Equation, Model, Project, Variable, View};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum VariableKind {
+    Stock,
+    Flow,
+    Aux,
+    Module,
+}
+
+impl VariableKind {
+    pub fn of(var: &Variable) -> Self {
+        match var {
+            Variable::Stock(_) => VariableKind::Stock,
+            Variable::Flow(_) => VariableKind::Flow,
+            Variable::Aux(_) => VariableKind::Aux,
+            Variable::Module(_) => VariableKind::Module,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "stock" => Some(VariableKind::Stock),
+            "flow" => Some(VariableKind::Flow),
+            "aux" => Some(VariableKind::Aux),
+            "module" => Some(VariableKind::Module),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            VariableKind::Stock => "stock",
+            VariableKind::Flow => "flow",
+            VariableKind::Aux => "aux",
+            VariableKind::Module => "module",
+        }
+    }
+}
+
+/// Query describes a search over the variables of a project.  A variable
+/// matches if it satisfies every filter that is set.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Query {
+    pub kind: Option<VariableKind>,
+    /// matched case-insensitively against the canonicalized name
+    pub name_contains: Option<String>,
+    /// matched case-sensitively against the equation text, including
+    /// initial and per-element equations
+    pub equation_contains: Option<String>,
+    /// the variable is arrayed over a dimension with this name
+    pub dimension: Option<String>,
+    pub missing_units: bool,
+}
+
+/// EquationMatch is the location of a match within a variable's equation.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct EquationMatch {
+    /// the element of an arrayed equation the match is in
+    pub element: Option<String>,
+    pub is_initial: bool,
+    /// byte offsets of the match in the equation text
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct QueryMatch {
+    pub model_name: String,
+    pub ident: String,
+    pub kind: VariableKind,
+    /// the uid of the variable's element in the model's stock and flow
+    /// view, if it is displayed there
+    pub view_uid: Option<i32>,
+    pub equation_matches: Vec<EquationMatch>,
+}
+
+// (element, is_initial, equation text) for every equation of a variable
+fn equation_texts(eqn: &Equation) -> Vec<(Option<&str>, bool, &str)> {
+    let mut texts = vec![];
+    match eqn {
+        Equation::Scalar(eqn, initial) | Equation::ApplyToAll(_, eqn, initial) => {
+            texts.push((None, false, eqn.as_str()));
+            if let Some(initial) = initial {
+                texts.push((None, true, initial.as_str()));
+            }
+        }
+        Equation::Arrayed(_, elements) => {
+            for (element, eqn, initial) in elements.iter() {
+                texts.push((Some(element.as_str()), false, eqn.as_str()));
+                if let Some(initial) = initial {
+                    texts.push((Some(element.as_str()), true, initial.as_str()));
+                }
+            }
+        }
+    }
+    texts
+}
+
+fn view_uid(model: &Model, ident: &str) -> Option<i32> {
+    model.views.iter().find_map(|view| match view {
+        View::StockFlow(view) => view
+            .elements
+            .iter()
+            .find(|element| {
+                element
+                    .get_name()
+                    .map(|name| canonicalize(name) == ident)
+                    .unwrap_or(false)
+            })
+            .map(|element| element.get_uid()),
+    })
+}
+
+impl Query {
+    fn find_match(&self, model: &Model, var: &Variable) -> Option<QueryMatch> {
+        let kind = VariableKind::of(var);
+        if self.kind.map(|k| k != kind).unwrap_or(false) {
+            return None;
+        }
+
+        let ident = canonicalize(var.get_ident());
+        if let Some(ref name) = self.name_contains {
+            if !ident.contains(&canonicalize(name)) {
+                return None;
+            }
+        }
+
+        if self.missing_units {
+            let has_units = var.get_units().map(|u| !u.is_empty()).unwrap_or(false);
+            if has_units || kind == VariableKind::Module {
+                return None;
+            }
+        }
+
+        if let Some(ref dimension) = self.dimension {
+            let dimension = canonicalize(dimension);
+            let dims = match var.get_equation() {
+                Some(Equation::ApplyToAll(dims, _, _)) | Some(Equation::Arrayed(dims, _)) => {
+                    dims.as_slice()
+                }
+                _ => &[],
+            };
+            if !dims.iter().any(|dim| canonicalize(dim) == dimension) {
+                return None;
+            }
+        }
+
+        let mut equation_matches = vec![];
+        if let Some(ref needle) = self.equation_contains {
+            if let Some(eqn) = var.get_equation() {
+                for (element, is_initial, text) in equation_texts(eqn) {
+                    equation_matches.extend(text.match_indices(needle.as_str()).map(
+                        |(start, s)| EquationMatch {
+                            element: element.map(|e| e.to_owned()),
+                            is_initial,
+                            start,
+                            end: start + s.len(),
+                        },
+                    ));
+                }
+            }
+            if equation_matches.is_empty() {
+                return None;
+            }
+        }
+
+        Some(QueryMatch {
+            model_name: model.name.clone(),
+            view_uid: view_uid(model, &ident),
+            ident,
+            kind,
+            equation_matches,
+        })
+    }
+}
+
+impl Project {
+    /// query returns the variables matching the query, in model and then
+    /// variable order.
+    pub fn query(&self, query: &Query) -> Vec<QueryMatch> {
+        self.models
+            .iter()
+            .flat_map(|model| {
+                model
+                    .variables
+                    .iter()
+                    .filter_map(move |var| query.find_match(model, var))
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_query() {
+    use crate::datamodel::{Aux, Visibility};
+    use crate::testutils::{sim_specs_with_units, x_aux, x_model, x_project, x_stock};
+
+    let arrayed = Variable::Aux(Aux {
+        ident: "regional_gdp".to_owned(),
+        equation: Equation::ApplyToAll(vec!["Region".to_owned()], "GDP / 3".to_owned(), None),
+        documentation: "".to_owned(),
+        units: Some("dollars".to_owned()),
+        gf: None,
+        can_be_module_input: false,
+        supplementary: false,
+        visibility: Visibility::Private,
+    });
+
+    let project = x_project(
+        sim_specs_with_units("year"),
+        &[x_model(
+            "main",
+            vec![
+                x_stock("capital", "100", &[], &[], Some("dollars")),
+                x_aux("gdp", "capital * 0.3", None),
+                x_aux("gdp_growth", "GDP * rate + GDP", Some("dollars/year")),
+                x_aux("rate", "0.1", None),
+                arrayed,
+            ],
+        )],
+    );
+
+    let idents = |query: &Query| {
+        project
+            .query(query)
+            .into_iter()
+            .map(|m| m.ident)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        vec!["capital"],
+        idents(&Query {
+            kind: Some(VariableKind::Stock),
+            ..Default::default()
+        })
+    );
+    assert_eq!(
+        vec!["gdp", "rate"],
+        idents(&Query {
+            missing_units: true,
+            ..Default::default()
+        })
+    );
+    assert_eq!(
+        vec!["gdp", "gdp_growth", "regional_gdp"],
+        idents(&Query {
+            name_contains: Some("GDP".to_owned()),
+            ..Default::default()
+        })
+    );
+    assert_eq!(
+        vec!["regional_gdp"],
+        idents(&Query {
+            dimension: Some("region".to_owned()),
+            ..Default::default()
+        })
+    );
+
+    let matches = project.query(&Query {
+        kind: Some(VariableKind::Aux),
+        equation_contains: Some("GDP".to_owned()),
+        ..Default::default()
+    });
+    assert_eq!(2, matches.len());
+    assert_eq!("main", matches[0].model_name);
+    assert_eq!("gdp_growth", matches[0].ident);
+    assert_eq!(
+        vec![(0, 3), (13, 16)],
+        matches[0]
+            .equation_matches
+            .iter()
+            .map(|m| (m.start, m.end))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!("regional_gdp", matches[1].ident);
+}
@@ -0,0 +1,172 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Limits on the resources a single simulation may use, for hosts that
+//! run models they don't control, like a public service.  Limits are
+//! checked against a compiled simulation's stats before it runs, so a
+//! pathological model is turned away without spending time or memory
+//! simulating it.
+
+use std::fmt;
+
+use crate::common::Result;
+use crate::vm::CompiledSimulation;
+use crate::{sim_err, Stats, Vm};
+
+/// Resource is something a simulation uses that a quota can limit.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Resource {
+    /// values computed each time step, counting each array element and
+    /// the hidden state of builtins like SMTH1
+    Variables,
+    /// time steps computed in a full run
+    Steps,
+    /// bytes allocated to hold the results
+    ResultBytes,
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Resource::Variables => "variables",
+            Resource::Steps => "steps",
+            Resource::ResultBytes => "result_bytes",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Violation is a resource a simulation would use more of than allowed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Violation {
+    pub resource: Resource,
+    pub requested: usize,
+    pub limit: usize,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} over the limit of {}",
+            self.resource, self.requested, self.limit
+        )
+    }
+}
+
+/// Quota is the most of each resource one simulation may use.  The
+/// default is unlimited.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Quota {
+    pub max_variables: usize,
+    pub max_steps: usize,
+    pub max_result_bytes: usize,
+}
+
+impl Default for Quota {
+    fn default() -> Self {
+        Quota {
+            max_variables: usize::MAX,
+            max_steps: usize::MAX,
+            max_result_bytes: usize::MAX,
+        }
+    }
+}
+
+impl Quota {
+    /// violations lists each resource a simulation with `stats` would
+    /// use more of than the quota allows.
+    pub fn violations(&self, stats: &Stats) -> Vec<Violation> {
+        [
+            (Resource::Variables, stats.n_slots, self.max_variables),
+            (Resource::Steps, stats.time_steps, self.max_steps),
+            (
+                Resource::ResultBytes,
+                stats.result_bytes,
+                self.max_result_bytes,
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, requested, limit)| requested > limit)
+        .map(|(resource, requested, limit)| Violation {
+            resource,
+            requested,
+            limit,
+        })
+        .collect()
+    }
+
+    /// check returns a QuotaExceeded error listing every violation, if
+    /// there are any.
+    pub fn check(&self, stats: &Stats) -> Result<()> {
+        let violations = self.violations(stats);
+        if violations.is_empty() {
+            return Ok(());
+        }
+        let details: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+        sim_err!(QuotaExceeded, details.join(", "))
+    }
+
+    /// vm returns a VM for the compiled simulation if it fits within the
+    /// quota, without allocating anything for its results otherwise.
+    pub fn vm(&self, compiled: CompiledSimulation) -> Result<Vm> {
+        self.check(&compiled.stats())?;
+        Vm::new(compiled)
+    }
+}
+
+#[test]
+fn test_quota() {
+    use crate::common::ErrorCode;
+    use crate::testutils::{sim_specs_with_units, x_aux, x_model, x_project};
+    use crate::{Project, Simulation};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![x_aux("a", "time", None), x_aux("b", "a * 2", None)],
+        )],
+    );
+    project.sim_specs.stop = 100.0;
+    let compile = || {
+        let project = Project::from(project.clone());
+        Simulation::new(&project, "main")
+            .unwrap()
+            .compile()
+            .unwrap()
+    };
+    let stats = compile().stats();
+
+    let mut vm = Quota::default().vm(compile()).unwrap();
+    vm.run_to_end().unwrap();
+    assert_eq!(200.0, vm.into_results().get_series("b").unwrap()[100]);
+
+    let quota = Quota {
+        max_steps: 10,
+        max_result_bytes: stats.result_bytes,
+        ..Default::default()
+    };
+    assert_eq!(
+        vec![Violation {
+            resource: Resource::Steps,
+            requested: 100,
+            limit: 10,
+        }],
+        quota.violations(&stats)
+    );
+
+    // every violation is reported, not just the first
+    let quota = Quota {
+        max_variables: 1,
+        max_steps: 10,
+        max_result_bytes: 1024,
+    };
+    assert_eq!(3, quota.violations(&stats).len());
+    let err = quota.vm(compile()).err().unwrap();
+    assert_eq!(ErrorCode::QuotaExceeded, err.code);
+    let details = err.get_details().unwrap();
+    assert!(details.contains("steps 100 over the limit of 10"));
+    assert!(details.contains("result_bytes"));
+}
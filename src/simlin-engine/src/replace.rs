@@ -0,0 +1,258 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Find and replace across every equation (and units string) in a
+//! project, for bulk refactors like renaming a unit or swapping one
+//! function for another.
+
+use std::fmt;
+
+use regex::Regex;
+
+use crate::common::{canonicalize, Result};
+use crate::datamodel::{Equation, Project, Variable};
+use crate::model_err;
+use crate::token::{Lexer, LexerType, Token};
+
+/// Find is what to search for.
+#[derive(Clone, Debug)]
+pub enum Find {
+    /// a regular expression; the replacement may refer to capture groups
+    /// like `$1`
+    Regex(Regex),
+    /// an identifier, like a variable, function or unit name, matched
+    /// case-insensitively and ignoring the difference between spaces and
+    /// underscores
+    Ident(String),
+}
+
+impl Find {
+    pub fn regex(pattern: &str) -> Result<Self> {
+        match Regex::new(pattern) {
+            Ok(re) => Ok(Find::Regex(re)),
+            Err(err) => model_err!(Generic, format!("bad pattern '{}': {}", pattern, err)),
+        }
+    }
+
+    fn replace(&self, text: &str, replacement: &str, lexer_type: LexerType) -> String {
+        match self {
+            Find::Regex(re) => re.replace_all(text, replacement).into_owned(),
+            Find::Ident(ident) => {
+                let ident = canonicalize(ident);
+                replace_idents(text, lexer_type, |id| {
+                    if id == ident {
+                        Some(replacement.to_owned())
+                    } else {
+                        None
+                    }
+                })
+            }
+        }
+    }
+}
+
+/// replace_idents replaces each identifier in `text` that `f`, called
+/// with the canonicalized identifier, returns a replacement for.
+pub(crate) fn replace_idents<F>(text: &str, lexer_type: LexerType, f: F) -> String
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut result = String::with_capacity(text.len());
+    let mut last = 0;
+    // text that doesn't lex is left for the parser to report
+    for (start, token, end) in Lexer::new(text, lexer_type).flatten() {
+        if let Token::Ident(id) = token {
+            if let Some(replacement) = f(&canonicalize(id)) {
+                result.push_str(&text[last..start]);
+                result.push_str(&replacement);
+                last = end;
+            }
+        }
+    }
+    result.push_str(&text[last..]);
+    result
+}
+
+/// Field is the part of a variable an edit applies to.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Field {
+    Equation,
+    InitialEquation,
+    Units,
+}
+
+impl Field {
+    pub(crate) fn lexer_type(&self) -> LexerType {
+        match self {
+            Field::Units => LexerType::Units,
+            _ => LexerType::Equation,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Field::Equation => "equation",
+            Field::InitialEquation => "initial equation",
+            Field::Units => "units",
+        }
+    }
+}
+
+/// Edit is a single changed equation or units string.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Edit {
+    pub model_name: String,
+    pub ident: String,
+    /// the element of an arrayed equation that changed
+    pub element: Option<String>,
+    pub field: Field,
+    pub old: String,
+    pub new: String,
+}
+
+impl fmt::Display for Edit {
+    /// the edit as a diff, like:
+    ///
+    /// ```text
+    /// main.births (equation)
+    /// - SMTH1(x, 3)
+    /// + SMTH3(x, 3)
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.model_name, self.ident)?;
+        if let Some(ref element) = self.element {
+            write!(f, "[{}]", element)?;
+        }
+        writeln!(f, " ({})", self.field.name())?;
+        writeln!(f, "- {}", self.old)?;
+        writeln!(f, "+ {}", self.new)
+    }
+}
+
+// (element, field, text) for every equation and the units of a variable
+pub(crate) fn texts_mut(var: &mut Variable) -> Vec<(Option<&str>, Field, &mut String)> {
+    let (equation, units) = match var {
+        Variable::Stock(stock) => (&mut stock.equation, &mut stock.units),
+        Variable::Flow(flow) => (&mut flow.equation, &mut flow.units),
+        Variable::Aux(aux) => (&mut aux.equation, &mut aux.units),
+        Variable::Module(module) => {
+            return module
+                .units
+                .iter_mut()
+                .map(|u| (None, Field::Units, u))
+                .collect()
+        }
+    };
+    let mut texts = vec![];
+    match equation {
+        Equation::Scalar(eqn, initial) | Equation::ApplyToAll(_, eqn, initial) => {
+            texts.push((None, Field::Equation, eqn));
+            if let Some(initial) = initial {
+                texts.push((None, Field::InitialEquation, initial));
+            }
+        }
+        Equation::Arrayed(_, elements) => {
+            for (element, eqn, initial) in elements.iter_mut() {
+                texts.push((Some(element.as_str()), Field::Equation, eqn));
+                if let Some(initial) = initial {
+                    texts.push((Some(element.as_str()), Field::InitialEquation, initial));
+                }
+            }
+        }
+    }
+    if let Some(units) = units {
+        texts.push((None, Field::Units, units));
+    }
+    texts
+}
+
+impl Project {
+    /// replace_in_equations replaces every match of `find` in the
+    /// project's equations and units with `replacement`, returning the
+    /// edits in model and then variable order.
+    pub fn replace_in_equations(&mut self, find: &Find, replacement: &str) -> Vec<Edit> {
+        let mut edits = vec![];
+        for model in self.models.iter_mut() {
+            for var in model.variables.iter_mut() {
+                let ident = canonicalize(var.get_ident());
+                for (element, field, text) in texts_mut(var) {
+                    let new = find.replace(text, replacement, field.lexer_type());
+                    if new == *text {
+                        continue;
+                    }
+                    edits.push(Edit {
+                        model_name: model.name.clone(),
+                        ident: ident.clone(),
+                        element: element.map(|e| e.to_owned()),
+                        field,
+                        old: std::mem::replace(text, new.clone()),
+                        new,
+                    });
+                }
+            }
+        }
+        edits
+    }
+
+    /// preview_replace returns the edits replace_in_equations would
+    /// make, without changing the project.
+    pub fn preview_replace(&self, find: &Find, replacement: &str) -> Vec<Edit> {
+        self.clone().replace_in_equations(find, replacement)
+    }
+}
+
+#[test]
+fn test_replace_in_equations() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_model, x_project};
+
+    let mut project = x_project(
+        sim_specs_with_units("year"),
+        &[x_model(
+            "main",
+            vec![
+                x_aux("smoothed", "SMTH1(input, 3) + smth1_count", Some("widgets")),
+                x_aux("input", "\"Widget Rate\" * 2", Some("widgets/year")),
+                x_aux("widget_rate", "1", Some("Widgets/Year")),
+                x_aux("smth1_count", "1", None),
+            ],
+        )],
+    );
+
+    // identifiers only match whole tokens, but ignore case and spacing
+    let edits = project.preview_replace(&Find::Ident("smth1".to_owned()), "SMTH3");
+    assert_eq!(1, edits.len());
+    assert_eq!("smoothed", edits[0].ident);
+    assert_eq!("SMTH3(input, 3) + smth1_count", edits[0].new);
+    assert_eq!(
+        "main.smoothed (equation)\n- SMTH1(input, 3) + smth1_count\n+ SMTH3(input, 3) + smth1_count\n",
+        edits[0].to_string()
+    );
+    // previewing doesn't change the project
+    assert_eq!(
+        &Equation::Scalar("SMTH1(input, 3) + smth1_count".to_owned(), None),
+        project.models[0].variables[0].get_equation().unwrap()
+    );
+
+    let edits = project.preview_replace(&Find::Ident("widget rate".to_owned()), "rate");
+    assert_eq!(1, edits.len());
+    assert_eq!("rate * 2", edits[0].new);
+
+    // renaming a unit
+    let edits = project.replace_in_equations(&Find::Ident("widgets".to_owned()), "gadgets");
+    assert_eq!(
+        vec!["gadgets", "gadgets/year", "gadgets/Year"],
+        edits.iter().map(|e| e.new.as_str()).collect::<Vec<_>>()
+    );
+    assert!(edits.iter().all(|e| e.field == Field::Units));
+    assert_eq!(
+        Some(&"gadgets/year".to_owned()),
+        project.models[0].variables[1].get_units()
+    );
+
+    let edits = project.replace_in_equations(&Find::regex(r"(\d+)\)").unwrap(), "$1 * 2)");
+    assert_eq!(1, edits.len());
+    assert_eq!("SMTH1(input, 3 * 2) + smth1_count", edits[0].new);
+
+    assert!(Find::regex("(").is_err());
+}
@@ -0,0 +1,123 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Resampling results onto a different set of times, so that runs with
+//! different time steps or save steps can be compared step by step.
+
+use crate::vm::{Results, TIME_OFF};
+
+/// Interpolation is how a value is found between two saved steps.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Interpolation {
+    /// a straight line between the steps on either side
+    #[default]
+    Linear,
+    /// the value at the most recent step, as a discrete-time model
+    /// would report it
+    Hold,
+}
+
+impl Results {
+    /// resample returns these results at each of `times`, which must be
+    /// increasing.  Times before the first saved step or after the last
+    /// take the value at that step.
+    pub fn resample(&self, times: &[f64], interpolation: Interpolation) -> Results {
+        let time_off = self.offsets.get("time").copied().unwrap_or(TIME_OFF);
+        let steps: Vec<&[f64]> = self.iter_rows().collect();
+
+        let mut data: Vec<f64> = Vec::with_capacity(times.len() * self.step_size);
+        // the first saved step after the current time; times are
+        // increasing, so this only moves forward
+        let mut next = 0;
+        for &t in times.iter() {
+            while next < steps.len() && steps[next][time_off] <= t {
+                next += 1;
+            }
+            if steps.is_empty() {
+                data.extend(std::iter::repeat(f64::NAN).take(self.step_size));
+            } else if next == 0 || next == steps.len() || interpolation == Interpolation::Hold {
+                data.extend_from_slice(steps[next.max(1) - 1]);
+            } else {
+                let (prev, curr) = (steps[next - 1], steps[next]);
+                let fraction = (t - prev[time_off]) / (curr[time_off] - prev[time_off]);
+                data.extend(
+                    prev.iter()
+                        .zip(curr.iter())
+                        .map(|(a, b)| a + (b - a) * fraction),
+                );
+            }
+            let len = data.len();
+            data[len - self.step_size + time_off] = t;
+        }
+
+        let mut specs = self.specs.clone();
+        if let (Some(start), Some(stop)) = (times.first(), times.last()) {
+            specs.start = *start;
+            specs.stop = *stop;
+            if times.len() > 1 {
+                specs.save_step = times[1] - times[0];
+            }
+        }
+
+        Results {
+            offsets: self.offsets.clone(),
+            data: data.into_boxed_slice(),
+            step_size: self.step_size,
+            step_count: times.len(),
+            specs,
+            is_vensim: self.is_vensim,
+        }
+    }
+
+    /// aligned_with returns these results resampled onto the saved steps
+    /// of `other`, when the two runs saved at different times.
+    pub fn aligned_with(&self, other: &Results, interpolation: Interpolation) -> Results {
+        let times = other.time_points();
+        if self.time_points() == times {
+            return self.clone();
+        }
+        self.resample(&times, interpolation)
+    }
+}
+
+#[test]
+fn test_resample() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_model, x_project};
+    use crate::vm::Vm;
+    use crate::{Project, Simulation};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model("main", vec![x_aux("x", "time * 2", None)])],
+    );
+    project.sim_specs.stop = 4.0;
+    project.sim_specs.save_step = Some(crate::datamodel::Dt::Dt(2.0));
+    let project = Project::from(project);
+    let sim = Simulation::new(&project, "main").unwrap();
+    let mut vm = Vm::new(sim.compile().unwrap()).unwrap();
+    vm.run_to_end().unwrap();
+    let results = vm.into_results();
+    assert_eq!(vec![0.0, 2.0, 4.0], results.time_points());
+
+    let times = [-1.0, 0.0, 1.0, 2.5, 4.0, 5.0];
+    let linear = results.resample(&times, Interpolation::Linear);
+    assert_eq!(times.to_vec(), linear.time_points());
+    assert_eq!(
+        Some(vec![0.0, 0.0, 2.0, 5.0, 8.0, 8.0]),
+        linear.get_series("x")
+    );
+    assert_eq!(5.0, linear.specs.stop);
+
+    let hold = results.resample(&times, Interpolation::Hold);
+    assert_eq!(
+        Some(vec![0.0, 0.0, 0.0, 4.0, 8.0, 8.0]),
+        hold.get_series("x")
+    );
+
+    let aligned = results.aligned_with(&hold, Interpolation::Hold);
+    assert_eq!(hold.time_points(), aligned.time_points());
+    assert_eq!(hold.get_series("x"), aligned.get_series("x"));
+    let aligned = results.aligned_with(&results, Interpolation::Linear);
+    assert_eq!(results.get_series("x"), aligned.get_series("x"));
+}
@@ -0,0 +1,215 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Starter projects, like an SIR epidemic, that give a new model a
+//! working structure and diagram to build on.
+
+use std::collections::HashMap;
+
+use crate::common::{canonicalize, Result};
+use crate::datamodel::{Dt, Model, Project, SimMethod, SimSpecs};
+use crate::model_err;
+use crate::templates::{aux, flow, param, stock, Template};
+
+fn sir() -> Template {
+    Template {
+        name: "sir".to_owned(),
+        documentation: "an epidemic spreading through a population, who recover with immunity"
+            .to_owned(),
+        params: vec![
+            param("total_population", "the number of people", "1000"),
+            param("initial_infected", "the number of people infected at the start", "1"),
+            param("contact_rate", "the number of people each person meets a day", "5"),
+            param(
+                "infectivity",
+                "the chance a contact between an infected and a susceptible person spreads the disease",
+                "0.05",
+            ),
+            param("duration_of_illness", "the average number of days people are infected", "7"),
+        ],
+        variables: vec![
+            stock(
+                "susceptible",
+                "total_population - initial_infected",
+                &[],
+                &["infection"],
+            ),
+            stock("infected", "initial_infected", &["infection"], &["recovery"]),
+            stock("recovered", "0", &["recovery"], &[]),
+            flow(
+                "infection",
+                "contact_rate * infectivity * susceptible * infected / total_population",
+            ),
+            flow("recovery", "infected / duration_of_illness"),
+        ],
+    }
+}
+
+fn inventory() -> Template {
+    Template {
+        name: "inventory".to_owned(),
+        documentation: "a factory adjusting production to keep its inventory on target".to_owned(),
+        params: vec![
+            param(
+                "customer_demand",
+                "the units ordered each week, which step up after week 10",
+                "100 + STEP(20, 10)",
+            ),
+            param("target_inventory", "the inventory the factory aims to hold", "400"),
+            param("initial_inventory", "the inventory at the start", "400"),
+            param(
+                "inventory_adjustment_time",
+                "the weeks over which a gap in inventory is closed",
+                "4",
+            ),
+            param("production_time", "the weeks it takes to produce a unit", "4"),
+        ],
+        variables: vec![
+            stock(
+                "work_in_process",
+                "customer_demand * production_time",
+                &["production_starts"],
+                &["production"],
+            ),
+            stock(
+                "inventory",
+                "initial_inventory",
+                &["production"],
+                &["shipments"],
+            ),
+            flow(
+                "production_starts",
+                "MAX(0, customer_demand + (target_inventory - inventory) / inventory_adjustment_time)",
+            ),
+            flow("production", "work_in_process / production_time"),
+            flow("shipments", "MIN(customer_demand, inventory)"),
+        ],
+    }
+}
+
+/// Scaffold is a starter project: a template, and the run to simulate
+/// it over.
+struct Scaffold {
+    name: &'static str,
+    template: Template,
+    stop: f64,
+    time_units: &'static str,
+}
+
+fn scaffolds() -> Vec<Scaffold> {
+    vec![
+        Scaffold {
+            name: "sir",
+            template: sir(),
+            stop: 100.0,
+            time_units: "day",
+        },
+        Scaffold {
+            name: "bass",
+            template: Template::bass_diffusion(),
+            stop: 20.0,
+            time_units: "year",
+        },
+        Scaffold {
+            name: "inventory",
+            template: inventory(),
+            stop: 60.0,
+            time_units: "week",
+        },
+    ]
+}
+
+/// scaffold_names returns the names `new_project` accepts, along with
+/// what each models.
+pub fn scaffold_names() -> Vec<(&'static str, String)> {
+    scaffolds()
+        .into_iter()
+        .map(|scaffold| (scaffold.name, scaffold.template.documentation))
+        .collect()
+}
+
+/// new_project returns a ready-to-simulate project named `project_name`
+/// built from the scaffold `name`.  The template's params become
+/// constants in the main model, so they can be changed without editing
+/// any equations, and every variable is laid out in its diagram.
+pub fn new_project(name: &str, project_name: &str) -> Result<Project> {
+    let scaffold = match scaffolds()
+        .into_iter()
+        .find(|scaffold| scaffold.name == canonicalize(name))
+    {
+        Some(scaffold) => scaffold,
+        None => return model_err!(DoesNotExist, name.to_owned()),
+    };
+
+    let mut template = scaffold.template;
+    for param in std::mem::take(&mut template.params) {
+        let mut constant = aux(&param.name, param.default.as_deref().unwrap_or("0"));
+        constant.set_documentation(&param.documentation);
+        template.variables.push(constant);
+    }
+
+    let mut project = Project {
+        name: project_name.to_owned(),
+        sim_specs: SimSpecs {
+            start: 0.0,
+            stop: scaffold.stop,
+            dt: Dt::Dt(0.25),
+            save_step: Some(Dt::Dt(1.0)),
+            sim_method: SimMethod::Euler,
+            tolerances: Default::default(),
+            time_units: Some(scaffold.time_units.to_owned()),
+        },
+        dimensions: vec![],
+        units: vec![],
+        models: vec![Model {
+            name: "main".to_owned(),
+            variables: vec![],
+            views: vec![],
+            graphs: vec![],
+            groups: vec![],
+            variable_index: Default::default(),
+        }],
+        constants: vec![],
+        tests: vec![],
+        source: None,
+        import_report: vec![],
+    };
+    project.insert_template("main", &template, "", &HashMap::new(), Some((100.0, 100.0)))?;
+    Ok(project)
+}
+
+#[test]
+fn test_new_project() {
+    use crate::datamodel::View;
+    use crate::{Simulation, Vm};
+
+    assert!(new_project("nope", "x").is_err());
+
+    for (name, _) in scaffold_names() {
+        let project = new_project(name, name).unwrap();
+        let View::StockFlow(view) = &project.models[0].views[0];
+        assert!(view.validate_flow_points().is_empty(), "{}", name);
+        let diagrammed = project.models[0].variables.iter().all(|var| {
+            view.elements
+                .iter()
+                .any(|e| e.get_name() == Some(var.get_ident()))
+        });
+        assert!(diagrammed, "{}", name);
+
+        let project = crate::Project::from(project);
+        let sim = Simulation::new(&project, "main").unwrap();
+        let mut vm = Vm::new(sim.compile().unwrap()).unwrap();
+        vm.run_to_end().unwrap();
+        let results = vm.into_results();
+        assert!(
+            results.iter_rows().flatten().all(|v| v.is_finite()),
+            "{}",
+            name
+        );
+    }
+
+    let sir = new_project("SIR", "epidemic").unwrap();
+    assert_eq!("epidemic", sir.name);
+    assert_eq!(Some("day"), sir.sim_specs.time_units.as_deref());
+}
@@ -0,0 +1,531 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Monte Carlo sensitivity analysis: uncertain parameters are drawn
+//! from distributions for each of a number of runs, and the spread of
+//! the results is summarized as percentile bands over time.
+
+#[cfg(feature = "json")]
+use ::serde::{Deserialize, Serialize};
+
+use crate::common::Result;
+use crate::datamodel;
+use crate::ensemble::{run_ensemble, Manifest, Override, Rng, Run};
+use crate::sim_err;
+use crate::vm::Results;
+
+/// Distribution is what a parameter's value is drawn from.
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(tag = "kind", rename_all = "lowercase"))]
+pub enum Distribution {
+    Uniform { min: f64, max: f64 },
+    Normal { mean: f64, std_dev: f64 },
+    Triangular { min: f64, mode: f64, max: f64 },
+}
+
+impl Distribution {
+    fn is_valid(&self) -> bool {
+        match *self {
+            Distribution::Uniform { min, max } => min.is_finite() && max.is_finite() && min <= max,
+            Distribution::Normal { mean, std_dev } => {
+                mean.is_finite() && std_dev.is_finite() && std_dev >= 0.0
+            }
+            Distribution::Triangular { min, mode, max } => {
+                min.is_finite() && max.is_finite() && min <= mode && mode <= max
+            }
+        }
+    }
+
+    fn sample(&self, rng: &mut Rng) -> f64 {
+        match *self {
+            Distribution::Normal { mean, std_dev } => {
+                // Box-Muller, with u1 in (0, 1] so its log is finite
+                let u1 = 1.0 - rng.next_f64();
+                let u2 = rng.next_f64();
+                let z = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+                mean + std_dev * z
+            }
+            _ => self.quantile(rng.next_f64()),
+        }
+    }
+
+    /// quantile returns the value below which a fraction `u` of draws
+    /// fall, for `u` between 0 and 1.
+    fn quantile(&self, u: f64) -> f64 {
+        match *self {
+            Distribution::Uniform { min, max } => min + u * (max - min),
+            Distribution::Normal { mean, std_dev } => {
+                // the ends are pulled in so that they stay finite
+                let u = u.clamp(f64::EPSILON, 1.0 - f64::EPSILON);
+                mean + std_dev * normal_quantile(u)
+            }
+            Distribution::Triangular { min, mode, max } => {
+                // the inverse of the CDF, which has a kink at the mode
+                let width = max - min;
+                if width == 0.0 {
+                    min
+                } else if u < (mode - min) / width {
+                    min + (u * width * (mode - min)).sqrt()
+                } else {
+                    max - ((1.0 - u) * width * (max - mode)).sqrt()
+                }
+            }
+        }
+    }
+}
+
+// normal_quantile is the inverse of the standard normal CDF, using
+// Acklam's rational approximation (relative error below 1.2e-9).
+fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969_683_028_665_376e1,
+        2.209_460_984_245_205e2,
+        -2.759_285_104_469_687e2,
+        1.383_577_518_672_69e2,
+        -3.066_479_806_614_716e1,
+        2.506_628_277_459_239,
+    ];
+    const B: [f64; 5] = [
+        -5.447_609_879_822_406e1,
+        1.615_858_368_580_409e2,
+        -1.556_989_798_598_866e2,
+        6.680_131_188_771_972e1,
+        -1.328_068_155_288_572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784_894_002_430_293e-3,
+        -3.223_964_580_411_365e-1,
+        -2.400_758_277_161_838,
+        -2.549_732_539_343_734,
+        4.374_664_141_464_968,
+        2.938_163_982_698_783,
+    ];
+    const D: [f64; 4] = [
+        7.784_695_709_041_462e-3,
+        3.224_671_290_700_398e-1,
+        2.445_134_137_142_996,
+        3.754_408_661_907_416,
+    ];
+    const P_LOW: f64 = 0.02425;
+
+    // the tails, which are mirror images of each other
+    let tail = |q: f64| {
+        let q = (-2.0 * q.ln()).sqrt();
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+    if p < P_LOW {
+        tail(p)
+    } else if p > 1.0 - P_LOW {
+        -tail(1.0 - p)
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
+
+/// Sampling is how the values of the parameters are drawn for each run.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(rename_all = "snake_case"))]
+pub enum Sampling {
+    /// each value is drawn independently of the others
+    #[default]
+    MonteCarlo,
+    /// each parameter's range is split into as many equally likely
+    /// intervals as there are runs, and every interval is drawn from
+    /// exactly once, so fewer runs cover the range as well
+    LatinHypercube,
+}
+
+/// Parameter is a constant in the main model (or a path into its
+/// modules) whose value isn't known.
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct Parameter {
+    pub ident: String,
+    pub distribution: Distribution,
+}
+
+/// Spec describes a sensitivity analysis: the parameters to vary, how
+/// many runs to take, and which percentiles of which variables to
+/// report.
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(default))]
+pub struct Spec {
+    pub parameters: Vec<Parameter>,
+    pub runs: usize,
+    pub seed: u64,
+    pub sampling: Sampling,
+    /// between 0 and 100, like 50 for the median
+    pub percentiles: Vec<f64>,
+    pub variables: Vec<String>,
+}
+
+impl Default for Spec {
+    fn default() -> Self {
+        Spec {
+            parameters: vec![],
+            runs: 100,
+            seed: 0,
+            sampling: Sampling::MonteCarlo,
+            percentiles: vec![5.0, 25.0, 50.0, 75.0, 95.0],
+            variables: vec![],
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+impl Spec {
+    /// from_json parses a spec.  Everything but the parameters and
+    /// variables has a default: 100 Monte Carlo runs with seed 0,
+    /// reporting the 5th, 25th, 50th, 75th and 95th percentiles.
+    pub fn from_json(contents: &str) -> Result<Self> {
+        serde_json::from_str(contents).map_err(crate::json::json_error)
+    }
+}
+
+/// Envelope is the spread of a variable across runs: for each of
+/// `percentiles`, the value at that percentile at each time.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Envelope {
+    pub ident: String,
+    pub times: Vec<f64>,
+    pub percentiles: Vec<f64>,
+    /// indexed by percentile, then by time
+    pub bands: Vec<Vec<f64>>,
+}
+
+/// sample_runs returns `runs` runs, each with a value drawn for every
+/// parameter.
+pub fn sample_runs(
+    parameters: &[Parameter],
+    runs: usize,
+    seed: u64,
+    sampling: Sampling,
+) -> Vec<Run> {
+    let mut rng = Rng::new(seed);
+    let values: Vec<Vec<f64>> = match sampling {
+        Sampling::MonteCarlo => (0..runs)
+            .map(|_| {
+                parameters
+                    .iter()
+                    .map(|p| p.distribution.sample(&mut rng))
+                    .collect()
+            })
+            .collect(),
+        Sampling::LatinHypercube => {
+            // a draw from each interval, shuffled independently for each
+            // parameter so that the intervals are paired up at random
+            let columns: Vec<Vec<f64>> = parameters
+                .iter()
+                .map(|p| {
+                    let mut column: Vec<f64> = (0..runs)
+                        .map(|i| (i as f64 + rng.next_f64()) / runs as f64)
+                        .map(|u| p.distribution.quantile(u))
+                        .collect();
+                    for i in (1..column.len()).rev() {
+                        column.swap(i, rng.below(i + 1));
+                    }
+                    column
+                })
+                .collect();
+            (0..runs)
+                .map(|run| columns.iter().map(|column| column[run]).collect())
+                .collect()
+        }
+    };
+
+    values
+        .into_iter()
+        .map(|values: Vec<f64>| Run {
+            seed,
+            overrides: parameters
+                .iter()
+                .zip(values)
+                .map(|(p, value)| Override {
+                    ident: p.ident.clone(),
+                    value,
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+// percentile returns the value at `pct` percent of the way through
+// `sorted`, interpolating linearly between the values on either side.
+fn percentile(sorted: &[f64], pct: f64) -> f64 {
+    let rank = pct / 100.0 * (sorted.len() - 1) as f64;
+    let lo = rank.floor() as usize;
+    let hi = rank.ceil() as usize;
+    sorted[lo] + (sorted[hi] - sorted[lo]) * (rank - lo as f64)
+}
+
+/// envelope computes the percentiles of `ident` across `results` at
+/// each time.
+pub fn envelope(results: &[Results], ident: &str, percentiles: &[f64]) -> Result<Envelope> {
+    if results.is_empty() {
+        return sim_err!(Generic, "no runs to summarize".to_owned());
+    }
+    let series = results
+        .iter()
+        .map(|r| r.get_series(ident))
+        .collect::<Option<Vec<Vec<f64>>>>();
+    let series = match series {
+        Some(series) => series,
+        None => return sim_err!(DoesNotExist, ident.to_owned()),
+    };
+
+    let times = results[0].time_points();
+    let mut bands = vec![vec![0.0; times.len()]; percentiles.len()];
+    let mut values = Vec::with_capacity(series.len());
+    for t in 0..times.len() {
+        values.clear();
+        values.extend(series.iter().map(|s| s[t]));
+        values.sort_by(|a, b| a.total_cmp(b));
+        for (band, pct) in bands.iter_mut().zip(percentiles.iter()) {
+            band[t] = percentile(&values, *pct);
+        }
+    }
+
+    Ok(Envelope {
+        ident: ident.to_owned(),
+        times,
+        percentiles: percentiles.to_vec(),
+        bands,
+    })
+}
+
+/// run_sensitivity simulates `spec.runs` runs of the model with values
+/// drawn for each of the spec's parameters, and returns the percentile
+/// envelope of each of its variables along with the manifest of the
+/// runs.
+pub fn run_sensitivity(
+    project: &datamodel::Project,
+    model_name: &str,
+    spec: &Spec,
+) -> Result<(Manifest, Vec<Envelope>)> {
+    if spec.runs == 0 {
+        return sim_err!(Generic, "a sensitivity run needs at least 1 run".to_owned());
+    }
+    if spec.variables.is_empty() {
+        return sim_err!(Generic, "no variables to report".to_owned());
+    }
+    if let Some(pct) = spec
+        .percentiles
+        .iter()
+        .find(|pct| !(0.0..=100.0).contains(*pct))
+    {
+        return sim_err!(
+            Generic,
+            format!("percentile {} isn't between 0 and 100", pct)
+        );
+    }
+    if let Some(p) = spec.parameters.iter().find(|p| !p.distribution.is_valid()) {
+        return sim_err!(Generic, format!("{} has an invalid distribution", p.ident));
+    }
+
+    let runs = sample_runs(&spec.parameters, spec.runs, spec.seed, spec.sampling);
+    let (manifest, results) = run_ensemble(project, model_name, runs)?;
+    let envelopes = spec
+        .variables
+        .iter()
+        .map(|ident| envelope(&results, ident, &spec.percentiles))
+        .collect::<Result<Vec<_>>>()?;
+    Ok((manifest, envelopes))
+}
+
+#[test]
+fn test_distributions() {
+    let draw = |distribution: Distribution| {
+        let parameters = vec![Parameter {
+            ident: "x".to_owned(),
+            distribution,
+        }];
+        let runs = sample_runs(&parameters, 4000, 7, Sampling::MonteCarlo);
+        let values: Vec<f64> = runs.iter().map(|run| run.overrides[0].value).collect();
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+        let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+        let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        (mean, variance.sqrt(), min, max)
+    };
+
+    let (mean, _, min, max) = draw(Distribution::Uniform { min: 2.0, max: 4.0 });
+    assert!((mean - 3.0).abs() < 0.05, "{}", mean);
+    assert!(min >= 2.0 && max < 4.0);
+
+    let (mean, std_dev, _, _) = draw(Distribution::Normal {
+        mean: 10.0,
+        std_dev: 2.0,
+    });
+    assert!((mean - 10.0).abs() < 0.15, "{}", mean);
+    assert!((std_dev - 2.0).abs() < 0.15, "{}", std_dev);
+
+    // the mean of a triangular distribution is (min + mode + max) / 3
+    let (mean, _, min, max) = draw(Distribution::Triangular {
+        min: 0.0,
+        mode: 1.0,
+        max: 5.0,
+    });
+    assert!((mean - 2.0).abs() < 0.1, "{}", mean);
+    assert!(min >= 0.0 && max <= 5.0);
+
+    // runs are reproducible from the seed
+    let parameters = vec![Parameter {
+        ident: "x".to_owned(),
+        distribution: Distribution::Uniform { min: 0.0, max: 1.0 },
+    }];
+    for sampling in [Sampling::MonteCarlo, Sampling::LatinHypercube] {
+        assert_eq!(
+            sample_runs(&parameters, 3, 1, sampling),
+            sample_runs(&parameters, 3, 1, sampling)
+        );
+        assert_ne!(
+            sample_runs(&parameters, 3, 1, sampling),
+            sample_runs(&parameters, 3, 2, sampling)
+        );
+    }
+}
+
+#[test]
+fn test_latin_hypercube() {
+    let parameters = vec![
+        Parameter {
+            ident: "x".to_owned(),
+            distribution: Distribution::Uniform {
+                min: 0.0,
+                max: 10.0,
+            },
+        },
+        Parameter {
+            ident: "y".to_owned(),
+            distribution: Distribution::Normal {
+                mean: 0.0,
+                std_dev: 1.0,
+            },
+        },
+    ];
+    let runs = sample_runs(&parameters, 10, 5, Sampling::LatinHypercube);
+    assert_eq!(10, runs.len());
+
+    // each of x's ten intervals is drawn from exactly once
+    let mut intervals: Vec<usize> = runs
+        .iter()
+        .map(|run| run.overrides[0].value.floor() as usize)
+        .collect();
+    intervals.sort_unstable();
+    assert_eq!((0..10).collect::<Vec<_>>(), intervals);
+
+    // and so is each of y's, through the inverse of the normal CDF
+    let mut ys: Vec<f64> = runs.iter().map(|run| run.overrides[1].value).collect();
+    ys.sort_by(|a, b| a.total_cmp(b));
+    for (i, y) in ys.iter().enumerate() {
+        let lo = parameters[1].distribution.quantile(i as f64 / 10.0);
+        let hi = parameters[1].distribution.quantile((i + 1) as f64 / 10.0);
+        assert!(lo <= *y && *y <= hi, "{} not in [{}, {}]", y, lo, hi);
+    }
+
+    assert!(normal_quantile(0.5).abs() < 1e-9);
+    assert!((normal_quantile(0.975) - 1.959_964).abs() < 1e-6);
+    assert!((normal_quantile(0.01) + 2.326_348).abs() < 1e-6);
+}
+
+#[test]
+fn test_sensitivity() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_stock("s", "0", &["f"], &[], None),
+                x_flow("f", "rate", None),
+                x_aux("rate", "1", None),
+            ],
+        )],
+    );
+    project.sim_specs.stop = 2.0;
+
+    let spec = Spec {
+        parameters: vec![Parameter {
+            ident: "rate".to_owned(),
+            distribution: Distribution::Uniform { min: 0.0, max: 1.0 },
+        }],
+        runs: 200,
+        seed: 3,
+        sampling: Sampling::MonteCarlo,
+        percentiles: vec![0.0, 50.0, 100.0],
+        variables: vec!["s".to_owned()],
+    };
+    let (manifest, envelopes) = run_sensitivity(&project, "main", &spec).unwrap();
+    assert_eq!(200, manifest.runs.len());
+    assert_eq!(1, envelopes.len());
+    let envelope = &envelopes[0];
+    assert_eq!(vec![0.0, 1.0, 2.0], envelope.times);
+    // s is rate * time, so its bands are the rate's spread scaled by time
+    let rates: Vec<f64> = manifest.runs.iter().map(|r| r.overrides[0].value).collect();
+    let lowest = rates.iter().cloned().fold(f64::INFINITY, f64::min);
+    let highest = rates.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    assert_eq!(vec![0.0, lowest, 2.0 * lowest], envelope.bands[0]);
+    assert_eq!(vec![0.0, highest, 2.0 * highest], envelope.bands[2]);
+    assert!((envelope.bands[1][2] - 1.0).abs() < 0.15);
+
+    // percentiles interpolate between runs
+    assert_eq!(2.5, percentile(&[1.0, 2.0, 3.0, 4.0], 50.0));
+    assert_eq!(4.0, percentile(&[1.0, 2.0, 3.0, 4.0], 100.0));
+
+    let bad = |f: fn(&mut Spec)| {
+        let mut spec = spec.clone();
+        f(&mut spec);
+        run_sensitivity(&project, "main", &spec).is_err()
+    };
+    assert!(bad(|spec| spec.runs = 0));
+    assert!(bad(|spec| spec.variables.clear()));
+    assert!(bad(|spec| spec.variables.push("nope".to_owned())));
+    assert!(bad(|spec| spec.percentiles.push(101.0)));
+    assert!(bad(|spec| {
+        spec.parameters[0].distribution = Distribution::Triangular {
+            min: 0.0,
+            mode: 2.0,
+            max: 1.0,
+        }
+    }));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_spec_json() {
+    let spec = Spec::from_json(
+        r#"{
+            "parameters": [
+                {"ident": "rate", "distribution": {"kind": "normal", "mean": 1, "std_dev": 0.1}}
+            ],
+            "variables": ["s"]
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        vec![Parameter {
+            ident: "rate".to_owned(),
+            distribution: Distribution::Normal {
+                mean: 1.0,
+                std_dev: 0.1
+            },
+        }],
+        spec.parameters
+    );
+    assert_eq!(100, spec.runs);
+    assert_eq!(Sampling::MonteCarlo, spec.sampling);
+    let spec = Spec::from_json(r#"{"sampling": "latin_hypercube"}"#).unwrap();
+    assert_eq!(Sampling::LatinHypercube, spec.sampling);
+    assert_eq!(vec![5.0, 25.0, 50.0, 75.0, 95.0], spec.percentiles);
+    assert!(Spec::from_json(r#"{"parameters": [{"ident": "rate"}]}"#).is_err());
+}
@@ -5,9 +5,11 @@
 use float_cmp::approx_eq;
 
 use crate::datamodel::{
-    view_element, Aux, Dimension, Dt, Equation, Extension, Flow, GraphicalFunction,
-    GraphicalFunctionKind, GraphicalFunctionScale, Model, Module, ModuleReference, Project, Rect,
-    SimMethod, SimSpecs, Source, Stock, StockFlow, Unit, Variable, View, ViewElement, Visibility,
+    view_element, Aux, Dimension, Dt, Equation, Extension, Flow, Graph, GraphKind,
+    GraphicalFunction, GraphicalFunctionKind, GraphicalFunctionScale, Group, ImportIssue, Model,
+    ModelTest, Module, ModuleReference, Plot, Project, Rect, SimMethod, SimSpecs, Source, Stock,
+    StockFlow, TestExpectation, TestOverride, Tolerances, Unit, Variable, View, ViewElement,
+    Visibility,
 };
 use crate::project_io;
 
@@ -51,6 +53,7 @@ impl From<SimMethod> for project_io::SimMethod {
         match sim_method {
             SimMethod::Euler => project_io::SimMethod::Euler,
             SimMethod::RungeKutta4 => project_io::SimMethod::RungeKutta4,
+            SimMethod::Rk45 => project_io::SimMethod::Rk45,
         }
     }
 }
@@ -60,13 +63,14 @@ impl From<project_io::SimMethod> for SimMethod {
         match sim_method {
             project_io::SimMethod::Euler => SimMethod::Euler,
             project_io::SimMethod::RungeKutta4 => SimMethod::RungeKutta4,
+            project_io::SimMethod::Rk45 => SimMethod::Rk45,
         }
     }
 }
 
 #[test]
 fn test_sim_method_roundtrip() {
-    let cases: &[SimMethod] = &[SimMethod::Euler, SimMethod::RungeKutta4];
+    let cases: &[SimMethod] = &[SimMethod::Euler, SimMethod::RungeKutta4, SimMethod::Rk45];
     for expected in cases {
         let expected = expected.clone();
         let actual =
@@ -90,6 +94,14 @@ impl From<SimSpecs> for project_io::SimSpecs {
             save_step: sim_specs.save_step.map(project_io::Dt::from),
             sim_method: project_io::SimMethod::from(sim_specs.sim_method) as i32,
             time_units: sim_specs.time_units.unwrap_or_default(),
+            tolerances: if sim_specs.tolerances == Tolerances::default() {
+                None
+            } else {
+                Some(project_io::Tolerances {
+                    relative: sim_specs.tolerances.relative,
+                    absolute: sim_specs.tolerances.absolute,
+                })
+            },
         }
     }
 }
@@ -107,6 +119,13 @@ impl From<project_io::SimSpecs> for SimSpecs {
             sim_method: SimMethod::from(
                 project_io::SimMethod::try_from(sim_specs.sim_method).unwrap_or_default(),
             ),
+            tolerances: sim_specs
+                .tolerances
+                .map(|tolerances| Tolerances {
+                    relative: tolerances.relative,
+                    absolute: tolerances.absolute,
+                })
+                .unwrap_or_default(),
             time_units: if sim_specs.time_units.is_empty() {
                 None
             } else {
@@ -125,6 +144,7 @@ fn test_sim_specs_roundtrip() {
             dt: Dt::Reciprocal(4.0),
             save_step: Some(Dt::Dt(1.0)),
             sim_method: SimMethod::Euler,
+            tolerances: Tolerances::default(),
             time_units: Some("years".to_string()),
         },
         SimSpecs {
@@ -133,6 +153,19 @@ fn test_sim_specs_roundtrip() {
             dt: Dt::Dt(5.0),
             save_step: None,
             sim_method: SimMethod::RungeKutta4,
+            tolerances: Tolerances::default(),
+            time_units: None,
+        },
+        SimSpecs {
+            start: 0.0,
+            stop: 10.0,
+            dt: Dt::Dt(1.0),
+            save_step: None,
+            sim_method: SimMethod::Rk45,
+            tolerances: Tolerances {
+                relative: 1e-4,
+                absolute: 1e-3,
+            },
             time_units: None,
         },
     ];
@@ -411,7 +444,9 @@ impl From<Stock> for project_io::variable::Stock {
             inflows: stock.inflows,
             outflows: stock.outflows,
             non_negative: stock.non_negative,
+            force_euler: stock.force_euler,
             can_be_module_input: stock.can_be_module_input,
+            supplementary: stock.supplementary,
             visibility: project_io::variable::Visibility::from(stock.visibility) as i32,
         }
     }
@@ -431,7 +466,9 @@ impl From<project_io::variable::Stock> for Stock {
             inflows: stock.inflows,
             outflows: stock.outflows,
             non_negative: stock.non_negative,
+            force_euler: stock.force_euler,
             can_be_module_input: stock.can_be_module_input,
+            supplementary: stock.supplementary,
             visibility: Visibility::from(
                 project_io::variable::Visibility::try_from(stock.visibility).unwrap_or_default(),
             ),
@@ -450,7 +487,9 @@ fn test_stock_roundtrip() {
             inflows: vec!["inflow".to_string()],
             outflows: vec![],
             non_negative: false,
+            force_euler: false,
             can_be_module_input: true,
+            supplementary: true,
             visibility: Visibility::Public,
         },
         Stock {
@@ -461,7 +500,9 @@ fn test_stock_roundtrip() {
             inflows: vec!["inflow".to_string()],
             outflows: vec![],
             non_negative: false,
+            force_euler: true,
             can_be_module_input: false,
+            supplementary: false,
             visibility: Visibility::Private,
         },
     ];
@@ -482,6 +523,7 @@ impl From<Flow> for project_io::variable::Flow {
             gf: flow.gf.map(project_io::GraphicalFunction::from),
             non_negative: flow.non_negative,
             can_be_module_input: flow.can_be_module_input,
+            supplementary: flow.supplementary,
             visibility: project_io::variable::Visibility::from(flow.visibility) as i32,
         }
     }
@@ -501,6 +543,7 @@ impl From<project_io::variable::Flow> for Flow {
             gf: flow.gf.map(GraphicalFunction::from),
             non_negative: flow.non_negative,
             can_be_module_input: flow.can_be_module_input,
+            supplementary: flow.supplementary,
             visibility: Visibility::from(
                 project_io::variable::Visibility::try_from(flow.visibility).unwrap_or_default(),
             ),
@@ -519,6 +562,7 @@ fn test_flow_roundtrip() {
             gf: None,
             non_negative: false,
             can_be_module_input: true,
+            supplementary: false,
             visibility: Visibility::Private,
         },
         Flow {
@@ -541,6 +585,7 @@ fn test_flow_roundtrip() {
             }),
             non_negative: false,
             can_be_module_input: false,
+            supplementary: false,
             visibility: Visibility::Public,
         },
     ];
@@ -560,6 +605,7 @@ impl From<Aux> for project_io::variable::Aux {
             units: aux.units.unwrap_or_default(),
             gf: aux.gf.map(project_io::GraphicalFunction::from),
             can_be_module_input: aux.can_be_module_input,
+            supplementary: aux.supplementary,
             visibility: project_io::variable::Visibility::from(aux.visibility).into(),
         }
     }
@@ -578,6 +624,7 @@ impl From<project_io::variable::Aux> for Aux {
             },
             gf: aux.gf.map(GraphicalFunction::from),
             can_be_module_input: aux.can_be_module_input,
+            supplementary: aux.supplementary,
             visibility: Visibility::from(
                 project_io::variable::Visibility::try_from(aux.visibility).unwrap_or_default(),
             ),
@@ -595,6 +642,7 @@ fn test_aux_roundtrip() {
             units: None,
             gf: None,
             can_be_module_input: false,
+            supplementary: false,
             visibility: Visibility::Public,
         },
         Aux {
@@ -616,6 +664,7 @@ fn test_aux_roundtrip() {
                 },
             }),
             can_be_module_input: true,
+            supplementary: true,
             visibility: Visibility::Private,
         },
     ];
@@ -774,6 +823,7 @@ fn test_variable_roundtrip() {
             units: None,
             gf: None,
             can_be_module_input: false,
+            supplementary: false,
             visibility: Visibility::Public,
         }),
         Variable::Module(Module {
@@ -1100,6 +1150,18 @@ impl From<project_io::view_element::Link> for view_element::Link {
                     )
                 }
             },
+            polarity: match project_io::view_element::link::Polarity::try_from(v.polarity)
+                .unwrap_or_default()
+            {
+                project_io::view_element::link::Polarity::Unspecified => None,
+                project_io::view_element::link::Polarity::Positive => {
+                    Some(view_element::LinkPolarity::Positive)
+                }
+                project_io::view_element::link::Polarity::Negative => {
+                    Some(view_element::LinkPolarity::Negative)
+                }
+            },
+            delay_mark: v.delay_mark,
         }
     }
 }
@@ -1128,6 +1190,16 @@ impl From<view_element::Link> for project_io::view_element::Link {
                     ))
                 }
             },
+            polarity: match v.polarity {
+                None => project_io::view_element::link::Polarity::Unspecified,
+                Some(view_element::LinkPolarity::Positive) => {
+                    project_io::view_element::link::Polarity::Positive
+                }
+                Some(view_element::LinkPolarity::Negative) => {
+                    project_io::view_element::link::Polarity::Negative
+                }
+            } as i32,
+            delay_mark: v.delay_mark,
         }
     }
 }
@@ -1140,12 +1212,16 @@ fn test_view_element_link_roundtrip() {
             from_uid: 21,
             to_uid: 22,
             shape: view_element::LinkShape::Straight,
+            polarity: None,
+            delay_mark: false,
         },
         view_element::Link {
             uid: 123,
             from_uid: 21,
             to_uid: 22,
             shape: view_element::LinkShape::Arc(351.0),
+            polarity: Some(view_element::LinkPolarity::Positive),
+            delay_mark: true,
         },
         view_element::Link {
             uid: 123,
@@ -1163,6 +1239,8 @@ fn test_view_element_link_roundtrip() {
                     attached_to_uid: None,
                 },
             ]),
+            polarity: Some(view_element::LinkPolarity::Negative),
+            delay_mark: false,
         },
     ];
     for expected in cases {
@@ -1401,6 +1479,155 @@ impl From<project_io::View> for View {
     }
 }
 
+impl From<GraphKind> for project_io::graph::Kind {
+    fn from(kind: GraphKind) -> Self {
+        match kind {
+            GraphKind::TimeSeries => project_io::graph::Kind::TimeSeries,
+            GraphKind::Scatter => project_io::graph::Kind::Scatter,
+            GraphKind::Bar => project_io::graph::Kind::Bar,
+            GraphKind::Table => project_io::graph::Kind::Table,
+        }
+    }
+}
+
+impl From<project_io::graph::Kind> for GraphKind {
+    fn from(kind: project_io::graph::Kind) -> Self {
+        match kind {
+            project_io::graph::Kind::TimeSeries => GraphKind::TimeSeries,
+            project_io::graph::Kind::Scatter => GraphKind::Scatter,
+            project_io::graph::Kind::Bar => GraphKind::Bar,
+            project_io::graph::Kind::Table => GraphKind::Table,
+        }
+    }
+}
+
+impl From<Graph> for project_io::Graph {
+    fn from(graph: Graph) -> Self {
+        project_io::Graph {
+            title: graph.title,
+            kind: project_io::graph::Kind::from(graph.kind) as i32,
+            plots: graph
+                .plots
+                .into_iter()
+                .map(|plot| project_io::graph::Plot {
+                    ident: plot.ident,
+                    color: plot.color.unwrap_or_default(),
+                    scale: plot.scale.map(project_io::graphical_function::Scale::from),
+                    precision: plot.precision.map(|precision| precision as u32),
+                })
+                .collect(),
+            interval: graph.interval,
+        }
+    }
+}
+
+impl From<project_io::Graph> for Graph {
+    fn from(graph: project_io::Graph) -> Self {
+        Graph {
+            title: graph.title,
+            kind: GraphKind::from(
+                project_io::graph::Kind::try_from(graph.kind).unwrap_or_default(),
+            ),
+            plots: graph
+                .plots
+                .into_iter()
+                .map(|plot| Plot {
+                    ident: plot.ident,
+                    color: if plot.color.is_empty() {
+                        None
+                    } else {
+                        Some(plot.color)
+                    },
+                    scale: plot.scale.map(GraphicalFunctionScale::from),
+                    precision: plot.precision.map(|precision| precision as usize),
+                })
+                .collect(),
+            interval: graph.interval,
+        }
+    }
+}
+
+#[test]
+fn test_graph_roundtrip() {
+    let cases: &[Graph] = &[
+        Graph {
+            title: "Population".to_owned(),
+            kind: GraphKind::TimeSeries,
+            plots: vec![
+                Plot {
+                    ident: "population".to_owned(),
+                    color: Some("#4e79a7".to_owned()),
+                    scale: Some(GraphicalFunctionScale {
+                        min: 0.0,
+                        max: 1000.0,
+                    }),
+                    precision: None,
+                },
+                Plot {
+                    ident: "births".to_owned(),
+                    color: None,
+                    scale: None,
+                    precision: None,
+                },
+            ],
+            interval: None,
+        },
+        Graph {
+            title: "".to_owned(),
+            kind: GraphKind::Table,
+            plots: vec![Plot {
+                ident: "births".to_owned(),
+                color: None,
+                scale: None,
+                precision: Some(2),
+            }],
+            interval: Some(0.5),
+        },
+    ];
+    for expected in cases {
+        let expected = expected.clone();
+        let actual = Graph::from(project_io::Graph::from(expected.clone()));
+        assert_eq!(expected, actual);
+    }
+}
+
+impl From<Group> for project_io::Group {
+    fn from(group: Group) -> Self {
+        project_io::Group {
+            name: group.name,
+            documentation: group.documentation,
+            members: group.members,
+        }
+    }
+}
+
+impl From<project_io::Group> for Group {
+    fn from(group: project_io::Group) -> Self {
+        Group {
+            name: group.name,
+            documentation: group.documentation,
+            members: group.members,
+        }
+    }
+}
+
+#[test]
+fn test_group_roundtrip() {
+    let cases: &[_] = &[
+        Group {
+            name: "Control".to_owned(),
+            documentation: "Simulation control parameters".to_owned(),
+            members: vec!["final_time".to_owned(), "time_step".to_owned()],
+        },
+        Group::default(),
+    ];
+    for expected in cases {
+        let expected = expected.clone();
+        let actual = Group::from(project_io::Group::from(expected.clone()));
+        assert_eq!(expected, actual);
+    }
+}
+
 impl From<Model> for project_io::Model {
     fn from(model: Model) -> Self {
         project_io::Model {
@@ -1415,6 +1642,16 @@ impl From<Model> for project_io::Model {
                 .into_iter()
                 .map(project_io::View::from)
                 .collect(),
+            graphs: model
+                .graphs
+                .into_iter()
+                .map(project_io::Graph::from)
+                .collect(),
+            groups: model
+                .groups
+                .into_iter()
+                .map(project_io::Group::from)
+                .collect(),
         }
     }
 }
@@ -1425,6 +1662,9 @@ impl From<project_io::Model> for Model {
             name: model.name,
             variables: model.variables.into_iter().map(Variable::from).collect(),
             views: model.views.into_iter().map(View::from).collect(),
+            graphs: model.graphs.into_iter().map(Graph::from).collect(),
+            groups: model.groups.into_iter().map(Group::from).collect(),
+            variable_index: Default::default(),
         }
     }
 }
@@ -1534,6 +1774,142 @@ impl From<project_io::Unit> for Unit {
     }
 }
 
+impl From<TestOverride> for project_io::model_test::Override {
+    fn from(o: TestOverride) -> Self {
+        project_io::model_test::Override {
+            ident: o.ident,
+            value: o.value,
+        }
+    }
+}
+
+impl From<project_io::model_test::Override> for TestOverride {
+    fn from(o: project_io::model_test::Override) -> Self {
+        TestOverride {
+            ident: o.ident,
+            value: o.value,
+        }
+    }
+}
+
+impl From<TestExpectation> for project_io::model_test::Expectation {
+    fn from(e: TestExpectation) -> Self {
+        project_io::model_test::Expectation {
+            ident: e.ident,
+            time: e.time,
+            value: e.value,
+            tolerance: e.tolerance,
+        }
+    }
+}
+
+impl From<project_io::model_test::Expectation> for TestExpectation {
+    fn from(e: project_io::model_test::Expectation) -> Self {
+        TestExpectation {
+            ident: e.ident,
+            time: e.time,
+            value: e.value,
+            tolerance: e.tolerance,
+        }
+    }
+}
+
+impl From<ModelTest> for project_io::ModelTest {
+    fn from(test: ModelTest) -> Self {
+        project_io::ModelTest {
+            name: test.name,
+            model_name: test.model_name,
+            overrides: test
+                .overrides
+                .into_iter()
+                .map(project_io::model_test::Override::from)
+                .collect(),
+            expectations: test
+                .expectations
+                .into_iter()
+                .map(project_io::model_test::Expectation::from)
+                .collect(),
+        }
+    }
+}
+
+impl From<project_io::ModelTest> for ModelTest {
+    fn from(test: project_io::ModelTest) -> Self {
+        ModelTest {
+            name: test.name,
+            model_name: test.model_name,
+            overrides: test.overrides.into_iter().map(TestOverride::from).collect(),
+            expectations: test
+                .expectations
+                .into_iter()
+                .map(TestExpectation::from)
+                .collect(),
+        }
+    }
+}
+
+#[test]
+fn test_model_test_roundtrip() {
+    let cases: &[ModelTest] = &[
+        ModelTest {
+            name: "grows".to_owned(),
+            model_name: "main".to_owned(),
+            overrides: vec![TestOverride {
+                ident: "rate".to_owned(),
+                value: 0.2,
+            }],
+            expectations: vec![TestExpectation {
+                ident: "population".to_owned(),
+                time: 10.0,
+                value: 100.0,
+                tolerance: 0.5,
+            }],
+        },
+        ModelTest {
+            name: "empty".to_owned(),
+            model_name: "".to_owned(),
+            overrides: vec![],
+            expectations: vec![],
+        },
+    ];
+    for expected in cases {
+        let expected = expected.clone();
+        let actual = ModelTest::from(project_io::ModelTest::from(expected.clone()));
+        assert_eq!(expected, actual);
+    }
+}
+
+impl From<ImportIssue> for project_io::ImportIssue {
+    fn from(issue: ImportIssue) -> Self {
+        project_io::ImportIssue {
+            construct: issue.construct,
+            location: issue.location,
+            reason: issue.reason,
+        }
+    }
+}
+
+impl From<project_io::ImportIssue> for ImportIssue {
+    fn from(issue: project_io::ImportIssue) -> Self {
+        ImportIssue {
+            construct: issue.construct,
+            location: issue.location,
+            reason: issue.reason,
+        }
+    }
+}
+
+#[test]
+fn test_import_issue_roundtrip() {
+    let expected = ImportIssue {
+        construct: "conveyor".to_owned(),
+        location: "line 12 (variable 'pipeline')".to_owned(),
+        reason: "imported as an ordinary stock".to_owned(),
+    };
+    let actual = ImportIssue::from(project_io::ImportIssue::from(expected.clone()));
+    assert_eq!(expected, actual);
+}
+
 impl From<Project> for project_io::Project {
     fn from(project: Project) -> Self {
         project_io::Project {
@@ -1555,6 +1931,21 @@ impl From<Project> for project_io::Project {
                 .map(project_io::Model::from)
                 .collect(),
             source: project.source.map(|source| source.into()),
+            constants: project
+                .constants
+                .into_iter()
+                .map(project_io::variable::Aux::from)
+                .collect(),
+            tests: project
+                .tests
+                .into_iter()
+                .map(project_io::ModelTest::from)
+                .collect(),
+            import_report: project
+                .import_report
+                .into_iter()
+                .map(project_io::ImportIssue::from)
+                .collect(),
         }
     }
 }
@@ -1571,7 +1962,14 @@ impl From<project_io::Project> for Project {
                 .collect(),
             units: project.units.into_iter().map(Unit::from).collect(),
             models: project.models.into_iter().map(Model::from).collect(),
+            constants: project.constants.into_iter().map(Aux::from).collect(),
+            tests: project.tests.into_iter().map(ModelTest::from).collect(),
             source: project.source.map(|source| source.into()),
+            import_report: project
+                .import_report
+                .into_iter()
+                .map(ImportIssue::from)
+                .collect(),
         }
     }
 }
@@ -1580,6 +1978,30 @@ pub fn serialize(project: &Project) -> project_io::Project {
     project_io::Project::from(project.clone())
 }
 
+/// serialize_canonical is like serialize, but first puts the parts of
+/// the project whose order has no meaning (models, variables, constants,
+/// dimensions, units, groups and tests) in order by name, so that
+/// equivalent projects encode to the same bytes.  View elements and
+/// graphs keep their order, as it is the order they are drawn in.
+pub fn serialize_canonical(project: &Project) -> project_io::Project {
+    let mut project = project.clone();
+    project.models.sort_by(|a, b| a.name.cmp(&b.name));
+    for model in project.models.iter_mut() {
+        model
+            .variables
+            .sort_by(|a, b| a.get_ident().cmp(b.get_ident()));
+        model.groups.sort_by(|a, b| a.name.cmp(&b.name));
+        for group in model.groups.iter_mut() {
+            group.members.sort();
+        }
+    }
+    project.constants.sort_by(|a, b| a.ident.cmp(&b.ident));
+    project.dimensions.sort_by(|a, b| a.name().cmp(b.name()));
+    project.units.sort_by(|a, b| a.name.cmp(&b.name));
+    project.tests.sort_by(|a, b| a.name.cmp(&b.name));
+    project_io::Project::from(project)
+}
+
 pub fn deserialize(project: project_io::Project) -> Project {
     project.into()
 }
@@ -1591,3 +2013,33 @@ pub fn deserialize_view(view: project_io::View) -> View {
 pub fn deserialize_graphical_function(gf: project_io::GraphicalFunction) -> GraphicalFunction {
     gf.into()
 }
+
+#[test]
+fn test_serialize_canonical() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_model, x_project};
+    use prost::Message;
+
+    let a = x_project(
+        sim_specs_with_units("time"),
+        &[
+            x_model("main", vec![x_aux("b", "1", None), x_aux("a", "b", None)]),
+            x_model("other", vec![x_aux("c", "2", None)]),
+        ],
+    );
+    let b = x_project(
+        sim_specs_with_units("time"),
+        &[
+            x_model("other", vec![x_aux("c", "2", None)]),
+            x_model("main", vec![x_aux("a", "b", None), x_aux("b", "1", None)]),
+        ],
+    );
+    assert_ne!(serialize(&a).encode_to_vec(), serialize(&b).encode_to_vec());
+    assert_eq!(
+        serialize_canonical(&a).encode_to_vec(),
+        serialize_canonical(&b).encode_to_vec()
+    );
+
+    let project = deserialize(serialize_canonical(&b));
+    assert_eq!("main", project.models[0].name);
+    assert_eq!("a", project.models[0].variables[0].get_ident());
+}
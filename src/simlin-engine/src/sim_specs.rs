@@ -0,0 +1,217 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+use crate::common::{Error, ErrorCode, ErrorKind};
+use crate::datamodel::{Dt, SimMethod, SimSpecs};
+use crate::units::{parse_units, Context};
+
+// simulations with more steps than this are almost certainly a mistake
+// in the specs (and would take too long to run regardless)
+const MAX_STEPS: f64 = 1e9;
+// how far save_step / dt can be from a whole number
+const TOLERANCE: f64 = 1e-6;
+
+fn bad_specs(details: String) -> Error {
+    Error::new(ErrorKind::Simulation, ErrorCode::BadSimSpecs, Some(details))
+}
+
+// dt_value returns the length of a time step, or an error describing why
+// the given step isn't valid.
+fn dt_value(name: &str, dt: &Dt) -> Result<f64, Error> {
+    match dt {
+        Dt::Dt(value) if value.is_finite() && *value > 0.0 => Ok(*value),
+        Dt::Dt(value) => Err(bad_specs(format!(
+            "{} must be greater than 0, not {}",
+            name, value
+        ))),
+        Dt::Reciprocal(value) if value.is_finite() && *value > 0.0 => Ok(1.0 / *value),
+        Dt::Reciprocal(value) => Err(bad_specs(format!(
+            "reciprocal {} must be greater than 0, not {}",
+            name, value
+        ))),
+    }
+}
+
+impl SimSpecs {
+    /// check returns a list of problems with the specs that would make
+    /// simulation fail, hang, or produce results other than those
+    /// intended.
+    pub fn check(&self) -> Vec<Error> {
+        let mut errors = vec![];
+        if !self.start.is_finite() || !self.stop.is_finite() {
+            errors.push(bad_specs(format!(
+                "start ({}) and stop ({}) must be finite",
+                self.start, self.stop
+            )));
+        } else if self.stop < self.start {
+            // stop == start is fine, and evaluates the model once
+            errors.push(bad_specs(format!(
+                "stop ({}) must not be before start ({})",
+                self.stop, self.start
+            )));
+        }
+
+        let dt = match dt_value("dt", &self.dt) {
+            Ok(dt) => dt,
+            Err(err) => {
+                errors.push(err);
+                return errors;
+            }
+        };
+        if errors.is_empty() && (self.stop - self.start) / dt > MAX_STEPS {
+            errors.push(bad_specs(format!(
+                "dt ({}) is too small: the simulation would take more than {} steps",
+                dt, MAX_STEPS
+            )));
+        }
+
+        if let Some(save_step) = self.save_step.as_ref() {
+            match dt_value("save_step", save_step) {
+                Ok(save_step) => {
+                    let ratio = save_step / dt;
+                    if ratio < 1.0 - TOLERANCE || (ratio - ratio.round()).abs() > TOLERANCE * ratio
+                    {
+                        errors.push(bad_specs(format!(
+                            "save_step ({}) must be a multiple of dt ({})",
+                            save_step, dt
+                        )));
+                    }
+                }
+                Err(err) => errors.push(err),
+            }
+        }
+
+        if self.sim_method == SimMethod::Rk45 {
+            let tolerances = &self.tolerances;
+            for (name, value) in [
+                ("relative", tolerances.relative),
+                ("absolute", tolerances.absolute),
+            ] {
+                if !value.is_finite() || value <= 0.0 {
+                    errors.push(bad_specs(format!(
+                        "{} tolerance must be greater than 0, not {}",
+                        name, value
+                    )));
+                }
+            }
+        }
+        errors
+    }
+
+    /// check_time_units returns an error if the time units aren't a valid
+    /// units expression with dimensions in the given units context.
+    pub(crate) fn check_time_units(&self, ctx: &Context) -> Option<Error> {
+        let time_units = self.time_units.as_deref()?.trim();
+        if time_units.is_empty() {
+            return None;
+        }
+        match parse_units(ctx, Some(time_units)) {
+            Ok(Some(units)) if units.is_empty() => Some(bad_specs(format!(
+                "time units '{}' can't be dimensionless",
+                time_units
+            ))),
+            Ok(_) => None,
+            Err(errs) => Some(bad_specs(format!(
+                "time units '{}': {}",
+                time_units,
+                errs.iter()
+                    .map(|err| err.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))),
+        }
+    }
+}
+
+#[test]
+fn test_check_sim_specs() {
+    let specs = SimSpecs {
+        start: 0.0,
+        stop: 10.0,
+        dt: Dt::Dt(0.25),
+        save_step: Some(Dt::Dt(1.0)),
+        ..Default::default()
+    };
+    assert!(specs.check().is_empty());
+
+    let details = |specs: SimSpecs| {
+        specs
+            .check()
+            .into_iter()
+            .map(|err| err.get_details().unwrap())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        vec!["stop (-1) must not be before start (0)"],
+        details(SimSpecs {
+            stop: -1.0,
+            ..specs.clone()
+        })
+    );
+    assert_eq!(
+        vec!["reciprocal dt must be greater than 0, not 0"],
+        details(SimSpecs {
+            dt: Dt::Reciprocal(0.0),
+            ..specs.clone()
+        })
+    );
+    assert_eq!(
+        vec!["dt must be greater than 0, not -1"],
+        details(SimSpecs {
+            dt: Dt::Dt(-1.0),
+            ..specs.clone()
+        })
+    );
+    assert_eq!(
+        vec!["save_step (0.3) must be a multiple of dt (0.25)"],
+        details(SimSpecs {
+            save_step: Some(Dt::Dt(0.3)),
+            ..specs.clone()
+        })
+    );
+    // save_step and dt often aren't exactly representable
+    assert!(SimSpecs {
+        dt: Dt::Reciprocal(3.0),
+        save_step: Some(Dt::Dt(1.0)),
+        ..specs.clone()
+    }
+    .check()
+    .is_empty());
+    let rk45 = SimSpecs {
+        sim_method: SimMethod::Rk45,
+        ..specs.clone()
+    };
+    assert!(rk45.check().is_empty());
+    let mut loose = rk45.clone();
+    loose.tolerances.absolute = 0.0;
+    assert_eq!(
+        vec!["absolute tolerance must be greater than 0, not 0"],
+        details(loose.clone())
+    );
+    // tolerances are ignored by the other methods
+    loose.sim_method = SimMethod::Euler;
+    assert!(loose.check().is_empty());
+    assert_eq!(
+        1,
+        details(SimSpecs {
+            stop: 1e12,
+            ..specs.clone()
+        })
+        .len()
+    );
+
+    let ctx = Context::new(&[], &specs).unwrap();
+    let time_units = |units: &str| {
+        SimSpecs {
+            time_units: Some(units.to_owned()),
+            ..specs.clone()
+        }
+        .check_time_units(&ctx)
+        .map(|err| err.code)
+    };
+    assert_eq!(None, time_units("months"));
+    assert_eq!(None, time_units(""));
+    assert_eq!(Some(ErrorCode::BadSimSpecs), time_units("1"));
+    assert_eq!(Some(ErrorCode::BadSimSpecs), time_units("months +"));
+}
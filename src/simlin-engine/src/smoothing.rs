@@ -0,0 +1,288 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Smooth approximations of discontinuous functions, for gradient-based
+//! calibration.  With smoothing on, a comparison like `a > b` becomes
+//! the logistic function of `a - b`, an IF on comparisons blends its
+//! branches by that value, STEP rises along a logistic curve, and MIN
+//! and MAX round off their corners.  Blended IFs evaluate both of their
+//! branches, so a branch the condition was guarding (like a division
+//! by zero) can make the result NaN.
+
+use crate::ast::{BinaryOp, Loc};
+use crate::builtins::BuiltinFn;
+use crate::compiler::{Expr, Module};
+use crate::interpreter::UnaryOp;
+
+/// Smoothing configures the approximations used by a simulation
+/// compiled after `Simulation::set_smoothing`.  Both widths have to be
+/// positive; the smaller they are, the closer the results are to the
+/// unsmoothed model.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Smoothing {
+    /// the scale of comparisons, MIN and MAX, in the units of the values
+    /// compared: `a > b` goes from about 0.12 to 0.88 as `a - b` goes
+    /// from `-2 * width` to `2 * width`
+    pub width: f64,
+    /// the scale, in time units, over which a STEP rises around its
+    /// start time
+    pub step_width: f64,
+}
+
+impl Smoothing {
+    pub(crate) fn is_valid(&self) -> bool {
+        self.width > 0.0 && self.step_width > 0.0
+    }
+
+    /// module returns a copy of `module` with smoothing applied to each
+    /// of its runlists.
+    pub(crate) fn module(&self, module: &Module) -> Module {
+        let mut module = module.clone();
+        for expr in module
+            .runlist_initials
+            .iter_mut()
+            .chain(module.runlist_flows.iter_mut())
+            .chain(module.runlist_stocks.iter_mut())
+        {
+            self.smooth(expr);
+        }
+        module
+    }
+
+    fn smooth(&self, expr: &mut Expr) {
+        if let Some(smoothed) = self.condition(expr) {
+            *expr = smoothed;
+            return;
+        }
+        match expr {
+            Expr::If(cond, t, f, loc) => {
+                self.smooth(t);
+                self.smooth(f);
+                match self.condition(cond) {
+                    Some(c) => {
+                        let (t, f) = (t.as_ref().clone(), f.as_ref().clone());
+                        let not_c = op2(BinaryOp::Sub, num(1.0, *loc), c.clone(), *loc);
+                        *expr = op2(
+                            BinaryOp::Add,
+                            op2(BinaryOp::Mul, c, t, *loc),
+                            op2(BinaryOp::Mul, not_c, f, *loc),
+                            *loc,
+                        );
+                    }
+                    None => self.smooth(cond),
+                }
+            }
+            Expr::App(BuiltinFn::Step(height, start), loc) => {
+                self.smooth(height);
+                self.smooth(start);
+                let time = Expr::App(BuiltinFn::Time, *loc);
+                let rise = logistic(time, start.as_ref().clone(), self.step_width, *loc);
+                *expr = op2(BinaryOp::Mul, height.as_ref().clone(), rise, *loc);
+            }
+            Expr::App(BuiltinFn::Max(a, b), loc) => {
+                let loc = *loc;
+                *expr = self.extreme(a, b, BinaryOp::Add, loc);
+            }
+            Expr::App(BuiltinFn::Min(a, b), loc) => {
+                let loc = *loc;
+                *expr = self.extreme(a, b, BinaryOp::Sub, loc);
+            }
+            _ => {
+                for child in children_mut(expr) {
+                    self.smooth(child);
+                }
+            }
+        }
+    }
+
+    // extreme returns (a + b ± sqrt((a - b)^2 + width^2)) / 2, which is
+    // MAX(a, b) when `op` is Add and MIN(a, b) when it is Sub, with the
+    // corner where a equals b rounded off.
+    fn extreme(&self, a: &mut Expr, b: &mut Expr, op: BinaryOp, loc: Loc) -> Expr {
+        self.smooth(a);
+        self.smooth(b);
+        let (a, b) = (a.clone(), b.clone());
+        let diff = op2(BinaryOp::Sub, a.clone(), b.clone(), loc);
+        let corner = Expr::App(
+            BuiltinFn::Sqrt(Box::new(op2(
+                BinaryOp::Add,
+                op2(BinaryOp::Exp, diff, num(2.0, loc), loc),
+                num(self.width * self.width, loc),
+                loc,
+            ))),
+            loc,
+        );
+        let sum = op2(BinaryOp::Add, a, b, loc);
+        op2(BinaryOp::Div, op2(op, sum, corner, loc), num(2.0, loc), loc)
+    }
+
+    // condition returns the smoothed value of a comparison, or of AND, OR
+    // and NOT of comparisons: a number between 0 and 1 that can be used
+    // to blend the branches of an IF.  Other conditions, like a variable
+    // holding 0 or 1, are left alone, as they may hold any number.
+    fn condition(&self, expr: &Expr) -> Option<Expr> {
+        let smoothed = |expr: &Expr| {
+            let mut expr = expr.clone();
+            self.smooth(&mut expr);
+            expr
+        };
+        let result = match expr {
+            Expr::Op2(BinaryOp::Gt | BinaryOp::Gte, l, r, loc) => {
+                logistic(smoothed(l), smoothed(r), self.width, *loc)
+            }
+            Expr::Op2(BinaryOp::Lt | BinaryOp::Lte, l, r, loc) => {
+                logistic(smoothed(r), smoothed(l), self.width, *loc)
+            }
+            Expr::Op2(BinaryOp::And, l, r, loc) => {
+                op2(BinaryOp::Mul, self.condition(l)?, self.condition(r)?, *loc)
+            }
+            Expr::Op2(BinaryOp::Or, l, r, loc) => {
+                let (l, r) = (self.condition(l)?, self.condition(r)?);
+                let both = op2(BinaryOp::Mul, l.clone(), r.clone(), *loc);
+                op2(BinaryOp::Sub, op2(BinaryOp::Add, l, r, *loc), both, *loc)
+            }
+            Expr::Op1(UnaryOp::Not, r, loc) => {
+                op2(BinaryOp::Sub, num(1.0, *loc), self.condition(r)?, *loc)
+            }
+            _ => return None,
+        };
+        Some(result)
+    }
+}
+
+fn num(n: f64, loc: Loc) -> Expr {
+    Expr::Const(n, loc)
+}
+
+fn op2(op: BinaryOp, l: Expr, r: Expr, loc: Loc) -> Expr {
+    Expr::Op2(op, Box::new(l), Box::new(r), loc)
+}
+
+// logistic returns 1 / (1 + exp((lo - hi) / width)), which is 0.5 when
+// hi equals lo and approaches 1 as hi grows past it.
+fn logistic(hi: Expr, lo: Expr, width: f64, loc: Loc) -> Expr {
+    let scaled = op2(
+        BinaryOp::Div,
+        op2(BinaryOp::Sub, lo, hi, loc),
+        num(width, loc),
+        loc,
+    );
+    let denominator = op2(
+        BinaryOp::Add,
+        num(1.0, loc),
+        Expr::App(BuiltinFn::Exp(Box::new(scaled)), loc),
+        loc,
+    );
+    op2(BinaryOp::Div, num(1.0, loc), denominator, loc)
+}
+
+fn children_mut(expr: &mut Expr) -> Vec<&mut Expr> {
+    match expr {
+        Expr::Const(_, _) | Expr::Var(_, _) | Expr::Dt(_) | Expr::ModuleInput(_, _) => vec![],
+        Expr::Subscript(_, args, _, _) | Expr::EvalModule(_, _, args) => args.iter_mut().collect(),
+        Expr::Op2(_, l, r, _) => vec![l.as_mut(), r.as_mut()],
+        Expr::Op1(_, r, _) | Expr::AssignCurr(_, r) | Expr::AssignNext(_, r) => vec![r.as_mut()],
+        Expr::If(cond, t, f, _) => vec![cond.as_mut(), t.as_mut(), f.as_mut()],
+        Expr::App(builtin, _) => match builtin {
+            BuiltinFn::Inf
+            | BuiltinFn::Pi
+            | BuiltinFn::Time
+            | BuiltinFn::TimeStep
+            | BuiltinFn::StartTime
+            | BuiltinFn::FinalTime
+            | BuiltinFn::IsModuleInput(_, _)
+            | BuiltinFn::Transpose(_, _) => vec![],
+            BuiltinFn::Lookup(_, a, _)
+            | BuiltinFn::Abs(a)
+            | BuiltinFn::Arccos(a)
+            | BuiltinFn::Arcsin(a)
+            | BuiltinFn::Arctan(a)
+            | BuiltinFn::Cos(a)
+            | BuiltinFn::Exp(a)
+            | BuiltinFn::Int(a)
+            | BuiltinFn::Ln(a)
+            | BuiltinFn::Log10(a)
+            | BuiltinFn::Sin(a)
+            | BuiltinFn::Sqrt(a)
+            | BuiltinFn::Tan(a)
+            | BuiltinFn::WhiteNoise(a) => vec![a.as_mut()],
+            BuiltinFn::Mean(args) => args.iter_mut().collect(),
+            BuiltinFn::Max(a, b) | BuiltinFn::Min(a, b) | BuiltinFn::Step(a, b) => {
+                vec![a.as_mut(), b.as_mut()]
+            }
+            BuiltinFn::Pulse(a, b, c) | BuiltinFn::Ramp(a, b, c) | BuiltinFn::SafeDiv(a, b, c) => {
+                let mut args = vec![a.as_mut(), b.as_mut()];
+                if let Some(c) = c {
+                    args.push(c.as_mut());
+                }
+                args
+            }
+        },
+    }
+}
+
+#[test]
+fn test_smoothing() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_model, x_project};
+    use crate::vm::Vm;
+    use crate::{Project, Simulation};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_aux("x", "time - 2", None),
+                x_aux("switch", "IF x > 0 THEN 10 ELSE 0", None),
+                x_aux("unsmoothed", "IF x THEN 10 ELSE 0", None),
+                x_aux("step", "STEP(10, 2)", None),
+                x_aux("larger", "MAX(x, 0)", None),
+                x_aux("smaller", "MIN(x, 0)", None),
+            ],
+        )],
+    );
+    project.sim_specs.stop = 4.0;
+    let project = Project::from(project);
+    let mut sim = Simulation::new(&project, "main").unwrap();
+
+    let run = |sim: &Simulation| {
+        let mut vm = Vm::new(sim.compile().unwrap()).unwrap();
+        vm.run_to_end().unwrap();
+        vm.into_results()
+    };
+    let hard = run(&sim);
+    assert_eq!(
+        Some(vec![0.0, 0.0, 0.0, 10.0, 10.0]),
+        hard.get_series("switch")
+    );
+
+    sim.set_smoothing(Some(Smoothing {
+        width: 0.5,
+        step_width: 0.5,
+    }));
+    let smooth = run(&sim);
+    let switch = smooth.get_series("switch").unwrap();
+    // halfway at the threshold, and close to the hard values away from it
+    assert_eq!(5.0, switch[2]);
+    assert!(switch[1] > 0.0 && switch[1] < 1.5);
+    assert!(switch[4] > 9.5 && switch[4] < 10.0);
+    assert_eq!(5.0, smooth.get_series("step").unwrap()[2]);
+    assert_eq!(
+        hard.get_series("unsmoothed"),
+        smooth.get_series("unsmoothed")
+    );
+
+    let larger = smooth.get_series("larger").unwrap();
+    let smaller = smooth.get_series("smaller").unwrap();
+    assert_eq!(0.25, larger[2]);
+    assert_eq!(-0.25, smaller[2]);
+    assert!((larger[4] - 2.0).abs() < 0.05);
+    assert!((smaller[0] + 2.0).abs() < 0.05);
+
+    sim.set_smoothing(Some(Smoothing {
+        width: 0.0,
+        step_width: 1.0,
+    }));
+    assert!(sim.compile().is_err());
+}
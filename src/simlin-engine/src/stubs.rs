@@ -0,0 +1,287 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+use std::collections::HashMap;
+
+use crate::common::{canonicalize, Ident, Result};
+use crate::datamodel::{
+    self, Equation, GraphicalFunction, GraphicalFunctionKind, GraphicalFunctionScale, Variable,
+};
+use crate::model_err;
+use crate::paths::resolve_path;
+
+/// Stub is what a variable is replaced with when testing part of a model
+/// against known inputs.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Stub {
+    Constant(f64),
+    /// (time, value) pairs, linearly interpolated between and held
+    /// constant outside of the given times
+    Series(Vec<(f64, f64)>),
+    /// (time, value) pairs: the variable's own equation until the
+    /// earliest time, then each value from its time on
+    Steps(Vec<(f64, f64)>),
+}
+
+/// Stubs is a set of variables, by model, to replace when building a
+/// project for simulation.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Stubs {
+    models: HashMap<Ident, HashMap<Ident, Stub>>,
+    /// (root model name, path, stub) for variables in specific module
+    /// instances
+    paths: Vec<(String, String, Stub)>,
+}
+
+fn scale(points: impl Iterator<Item = f64> + Clone) -> GraphicalFunctionScale {
+    GraphicalFunctionScale {
+        min: points.clone().fold(f64::INFINITY, f64::min),
+        max: points.fold(f64::NEG_INFINITY, f64::max),
+    }
+}
+
+// stepped returns an equation that evaluates to `eqn` before the first
+// step, and to each step's value from its time on.
+fn stepped(eqn: &str, steps: &[(f64, f64)]) -> String {
+    let mut steps = steps.to_vec();
+    steps.sort_by(|a, b| a.0.total_cmp(&b.0));
+    steps.iter().fold(eqn.to_owned(), |eqn, (time, value)| {
+        format!("if time >= {} then {} else ({})", time, value, eqn)
+    })
+}
+
+impl Stub {
+    fn equation_and_gf(&self, var: &Variable) -> (Equation, Option<GraphicalFunction>) {
+        if let Stub::Steps(steps) = self {
+            // check ensures this is an aux without a graphical function
+            let equation = match var.get_equation().cloned() {
+                Some(Equation::Scalar(eqn, initial)) => {
+                    Equation::Scalar(stepped(&eqn, steps), initial)
+                }
+                Some(Equation::ApplyToAll(dims, eqn, initial)) => {
+                    Equation::ApplyToAll(dims, stepped(&eqn, steps), initial)
+                }
+                Some(Equation::Arrayed(dims, elements)) => Equation::Arrayed(
+                    dims,
+                    elements
+                        .into_iter()
+                        .map(|(name, eqn, initial)| (name, stepped(&eqn, steps), initial))
+                        .collect(),
+                ),
+                None => Equation::Scalar(stepped("0", steps), None),
+            };
+            return (equation, None);
+        }
+        let dims = match var.get_equation() {
+            Some(Equation::ApplyToAll(dims, _, _)) | Some(Equation::Arrayed(dims, _)) => {
+                Some(dims.clone())
+            }
+            _ => None,
+        };
+        let (eqn, gf) = match self {
+            Stub::Constant(value) => (format!("{}", value), None),
+            Stub::Series(points) => {
+                let mut points = points.clone();
+                points.sort_by(|a, b| a.0.total_cmp(&b.0));
+                let gf = GraphicalFunction {
+                    kind: GraphicalFunctionKind::Continuous,
+                    x_points: Some(points.iter().map(|(t, _)| *t).collect()),
+                    y_points: points.iter().map(|(_, v)| *v).collect(),
+                    x_scale: scale(points.iter().map(|(t, _)| *t)),
+                    y_scale: scale(points.iter().map(|(_, v)| *v)),
+                };
+                ("time".to_owned(), Some(gf))
+            }
+            Stub::Steps(_) => unreachable!(),
+        };
+        let equation = match dims {
+            Some(dims) => Equation::ApplyToAll(dims, eqn, None),
+            None => Equation::Scalar(eqn, None),
+        };
+        (equation, gf)
+    }
+}
+
+impl Stubs {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// stub replaces the variable `ident` in the model `model_name`.
+    pub fn stub(&mut self, model_name: &str, ident: &str, stub: Stub) -> &mut Self {
+        self.models
+            .entry(model_name.to_owned())
+            .or_default()
+            .insert(canonicalize(ident), stub);
+        self
+    }
+
+    /// stub_path replaces the variable at `path` (like `hares.births`),
+    /// relative to the model `model_name`.  Unlike `stub`, only the module
+    /// instances along the path are affected, not every instance of the
+    /// variable's model.
+    pub fn stub_path(&mut self, model_name: &str, path: &str, stub: Stub) -> &mut Self {
+        self.paths
+            .push((model_name.to_owned(), path.to_owned(), stub));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.models.is_empty() && self.paths.is_empty()
+    }
+
+    /// specialize resolves path stubs, returning a copy of the project
+    /// where each module instance along a stubbed path has its own copy
+    /// of its model, along with stubs that only refer to models.
+    pub(crate) fn specialize(
+        &self,
+        project: &datamodel::Project,
+    ) -> Result<(datamodel::Project, Stubs)> {
+        let mut project = project.clone();
+        let mut stubs = Stubs {
+            models: self.models.clone(),
+            paths: vec![],
+        };
+        // the copied model for each module instance, by the path to the
+        // instance from its root model
+        let mut copies: HashMap<Vec<Ident>, String> = HashMap::new();
+        for (root, path, stub) in self.paths.iter() {
+            let resolved = resolve_path(&project, root, path)?;
+            let mut model_name = root.clone();
+            let mut instance = vec![root.clone()];
+            for module_ident in resolved.modules.iter() {
+                instance.push(module_ident.clone());
+                if let Some(copy_name) = copies.get(&instance) {
+                    model_name = copy_name.clone();
+                    continue;
+                }
+                let copy_name = instance.join("·");
+                let module = project
+                    .models
+                    .iter_mut()
+                    .find(|m| m.name == model_name || (model_name == "main" && m.name.is_empty()))
+                    .and_then(|model| {
+                        model.variables.iter_mut().find_map(|var| match var {
+                            Variable::Module(module)
+                                if canonicalize(&module.ident) == *module_ident =>
+                            {
+                                Some(module)
+                            }
+                            _ => None,
+                        })
+                    });
+                // resolve_path checked the module exists
+                let module = module.unwrap();
+                let module_model_name =
+                    std::mem::replace(&mut module.model_name, copy_name.clone());
+                let mut copy = project.get_model(&module_model_name).unwrap().clone();
+                copy.name = copy_name.clone();
+                project.models.push(copy);
+                // stubs of the model apply to the copy too
+                if let Some(model_stubs) = self.models.get(&module_model_name) {
+                    stubs.models.insert(copy_name.clone(), model_stubs.clone());
+                }
+                copies.insert(instance.clone(), copy_name.clone());
+                model_name = copy_name;
+            }
+            stubs.stub(&model_name, &resolved.ident, stub.clone());
+        }
+        Ok((project, stubs))
+    }
+
+    /// check returns an error if a stub refers to a variable that doesn't
+    /// exist, or to a module, which can't be stubbed.  Steps can only be
+    /// applied to variables without a graphical function, and not to
+    /// stocks.
+    pub(crate) fn check(&self, project: &datamodel::Project) -> Result<()> {
+        let mut names: Vec<(&str, &str, &Stub)> = self
+            .models
+            .iter()
+            .flat_map(|(model, stubs)| {
+                stubs
+                    .iter()
+                    .map(move |(id, stub)| (model.as_str(), id.as_str(), stub))
+            })
+            .collect();
+        names.sort_unstable_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+        for (model_name, ident, stub) in names {
+            let var = project.get_model(model_name).and_then(|model| {
+                model
+                    .variables
+                    .iter()
+                    .find(|var| canonicalize(var.get_ident()) == ident)
+            });
+            match var {
+                None => {
+                    return model_err!(DoesNotExist, format!("{}.{}", model_name, ident));
+                }
+                Some(Variable::Module(_)) => {
+                    return model_err!(
+                        NotSimulatable,
+                        format!("can't stub module {}.{}", model_name, ident)
+                    );
+                }
+                Some(Variable::Stock(_))
+                | Some(Variable::Aux(datamodel::Aux { gf: Some(_), .. }))
+                | Some(Variable::Flow(datamodel::Flow { gf: Some(_), .. }))
+                    if matches!(stub, Stub::Steps(_)) =>
+                {
+                    return model_err!(
+                        NotSimulatable,
+                        format!(
+                            "can only schedule changes to constants, not {}.{}",
+                            model_name, ident
+                        )
+                    );
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// apply returns a copy of the model with stubbed variables replaced.
+    /// Stubbed stocks become auxiliaries, so the stock's flows no longer
+    /// affect it; stubbed flows remain flows so stocks can reference them.
+    pub(crate) fn apply(&self, model: &datamodel::Model) -> datamodel::Model {
+        let mut model = model.clone();
+        let stubs = match self.models.get(&model.name) {
+            Some(stubs) => stubs,
+            None => return model,
+        };
+        for var in model.variables.iter_mut() {
+            let stub = match stubs.get(&canonicalize(var.get_ident())) {
+                Some(stub) => stub,
+                None => continue,
+            };
+            let (equation, gf) = stub.equation_and_gf(var);
+            *var = match var {
+                Variable::Stock(stock) => Variable::Aux(datamodel::Aux {
+                    ident: stock.ident.clone(),
+                    equation,
+                    documentation: stock.documentation.clone(),
+                    units: stock.units.clone(),
+                    gf,
+                    can_be_module_input: stock.can_be_module_input,
+                    supplementary: stock.supplementary,
+                    visibility: stock.visibility,
+                }),
+                Variable::Flow(flow) => Variable::Flow(datamodel::Flow {
+                    equation,
+                    gf,
+                    non_negative: false,
+                    ..flow.clone()
+                }),
+                Variable::Aux(aux) => Variable::Aux(datamodel::Aux {
+                    equation,
+                    gf,
+                    ..aux.clone()
+                }),
+                // rejected by check
+                Variable::Module(_) => continue,
+            };
+        }
+        model
+    }
+}
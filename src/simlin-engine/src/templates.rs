@@ -0,0 +1,623 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Templates are parameterized pieces of model structure, like an aging
+//! chain or the Bass diffusion model, that can be instantiated into a
+//! model under a prefix.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::common::{canonicalize, Result};
+use crate::datamodel::view_element::{self, LabelSide, LinkShape};
+use crate::datamodel::{
+    Aux, Equation, Flow, Project, Rect, Stock, StockFlow, Variable, View, ViewElement, Visibility,
+};
+use crate::geometry::{route_flow, FlowEnd};
+use crate::model_err;
+use crate::replace::{replace_idents, texts_mut, Field};
+use crate::token::{Lexer, LexerType, Token};
+
+// the distance between the centers of neighboring stocks when laying out
+// a template, and between a stock and a cloud
+pub(crate) const SPACING: f64 = 150.0;
+const AUX_SPACING: f64 = 100.0;
+
+/// Param is a value, like a rate or a time constant, that a template's
+/// equations refer to by name.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Param {
+    pub name: String,
+    pub documentation: String,
+    /// the equation used when an instantiation doesn't provide one
+    pub default: Option<String>,
+}
+
+/// Template is a set of variables whose equations refer to each other
+/// and to the template's params.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Template {
+    pub name: String,
+    pub documentation: String,
+    pub params: Vec<Param>,
+    pub variables: Vec<Variable>,
+}
+
+pub(crate) fn param(name: &str, documentation: &str, default: &str) -> Param {
+    Param {
+        name: name.to_owned(),
+        documentation: documentation.to_owned(),
+        default: Some(default.to_owned()),
+    }
+}
+
+pub(crate) fn stock(ident: &str, eqn: &str, inflows: &[&str], outflows: &[&str]) -> Variable {
+    Variable::Stock(Stock {
+        ident: ident.to_owned(),
+        equation: Equation::Scalar(eqn.to_owned(), None),
+        documentation: "".to_owned(),
+        units: None,
+        inflows: inflows.iter().map(|f| f.to_string()).collect(),
+        outflows: outflows.iter().map(|f| f.to_string()).collect(),
+        non_negative: false,
+        force_euler: false,
+        can_be_module_input: false,
+        supplementary: false,
+        visibility: Visibility::Private,
+    })
+}
+
+pub(crate) fn flow(ident: &str, eqn: &str) -> Variable {
+    Variable::Flow(Flow {
+        ident: ident.to_owned(),
+        equation: Equation::Scalar(eqn.to_owned(), None),
+        documentation: "".to_owned(),
+        units: None,
+        gf: None,
+        non_negative: false,
+        can_be_module_input: false,
+        supplementary: false,
+        visibility: Visibility::Private,
+    })
+}
+
+pub(crate) fn aux(ident: &str, eqn: &str) -> Variable {
+    Variable::Aux(Aux {
+        ident: ident.to_owned(),
+        equation: Equation::Scalar(eqn.to_owned(), None),
+        documentation: "".to_owned(),
+        units: None,
+        gf: None,
+        can_be_module_input: false,
+        supplementary: false,
+        visibility: Visibility::Private,
+    })
+}
+
+// parenthesize wraps an argument in parentheses, unless it is a number
+// or a single identifier, so it binds as tightly as the parameter it
+// replaces.
+fn parenthesize(eqn: &str) -> String {
+    let eqn = eqn.trim();
+    if eqn.parse::<f64>().is_ok() || eqn.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        eqn.to_owned()
+    } else {
+        format!("({})", eqn)
+    }
+}
+
+fn prefixed(prefix: &str, ident: &str) -> String {
+    if prefix.is_empty() {
+        ident.to_owned()
+    } else {
+        format!("{}_{}", prefix, ident)
+    }
+}
+
+impl Template {
+    /// bass_diffusion is the Bass model of the adoption of a new product
+    /// through advertising and word of mouth.
+    pub fn bass_diffusion() -> Self {
+        Template {
+            name: "bass_diffusion".to_owned(),
+            documentation: "adoption of a new product through advertising and word of mouth"
+                .to_owned(),
+            params: vec![
+                param("total_population", "the size of the market", "1000000"),
+                param(
+                    "advertising_effectiveness",
+                    "the fraction of potential adopters adopting each period due to advertising",
+                    "0.011",
+                ),
+                param(
+                    "contact_rate",
+                    "the number of people an adopter contacts each period",
+                    "100",
+                ),
+                param(
+                    "adoption_fraction",
+                    "the fraction of contacts with potential adopters that result in adoption",
+                    "0.015",
+                ),
+            ],
+            variables: vec![
+                stock("potential_adopters", "total_population", &[], &["adoption"]),
+                stock("adopters", "0", &["adoption"], &[]),
+                flow(
+                    "adoption",
+                    "adoption_from_advertising + adoption_from_word_of_mouth",
+                ),
+                aux(
+                    "adoption_from_advertising",
+                    "advertising_effectiveness * potential_adopters",
+                ),
+                aux(
+                    "adoption_from_word_of_mouth",
+                    "contact_rate * adoption_fraction * adopters * potential_adopters / total_population",
+                ),
+            ],
+        }
+    }
+
+    /// aging_chain is a sequence of `stages` stocks (at least one), each
+    /// draining into the next after an average of `stage_duration`.
+    pub fn aging_chain(stages: usize) -> Self {
+        let stages = stages.max(1);
+        let stage = |i: usize| format!("stage_{}", i);
+        let outflow = |i: usize| {
+            if i == stages {
+                "exiting".to_owned()
+            } else {
+                format!("maturing_{}", i)
+            }
+        };
+
+        let mut variables = vec![flow("entering", "entry_rate")];
+        for i in 1..=stages {
+            let inflow = if i == 1 {
+                "entering".to_owned()
+            } else {
+                outflow(i - 1)
+            };
+            variables.push(stock(
+                &stage(i),
+                "initial_stage_value",
+                &[inflow.as_str()],
+                &[outflow(i).as_str()],
+            ));
+            variables.push(flow(&outflow(i), &format!("{} / stage_duration", stage(i))));
+        }
+
+        Template {
+            name: "aging_chain".to_owned(),
+            documentation: format!("material moving through {} stages in sequence", stages),
+            params: vec![
+                param("entry_rate", "the flow into the first stage", "0"),
+                param(
+                    "initial_stage_value",
+                    "the initial value of each stage",
+                    "0",
+                ),
+                param(
+                    "stage_duration",
+                    "the average time spent in each stage",
+                    "1",
+                ),
+            ],
+            variables,
+        }
+    }
+
+    /// instantiate returns the template's variables, with `prefix` and an
+    /// underscore prepended to their names and params replaced with the
+    /// equations in `args`, or their defaults.  Arguments can refer to
+    /// other variables in the model the template is instantiated into.
+    pub fn instantiate(
+        &self,
+        prefix: &str,
+        args: &HashMap<String, String>,
+    ) -> Result<Vec<Variable>> {
+        let mut values: HashMap<String, String> = HashMap::new();
+        for (name, value) in args.iter() {
+            let name = canonicalize(name);
+            if !self.params.iter().any(|p| canonicalize(&p.name) == name) {
+                return model_err!(
+                    DoesNotExist,
+                    format!("{} has no parameter '{}'", self.name, name)
+                );
+            }
+            values.insert(name, parenthesize(value));
+        }
+        for param in self.params.iter() {
+            let name = canonicalize(&param.name);
+            if values.contains_key(&name) {
+                continue;
+            }
+            match param.default {
+                Some(ref default) => {
+                    values.insert(name, parenthesize(default));
+                }
+                None => {
+                    return model_err!(
+                        Generic,
+                        format!("no value for the parameter '{}' of {}", name, self.name)
+                    );
+                }
+            }
+        }
+
+        let prefix = canonicalize(prefix);
+        let renamed: HashMap<String, String> = self
+            .variables
+            .iter()
+            .map(|var| {
+                let ident = canonicalize(var.get_ident());
+                let new_ident = prefixed(&prefix, &ident);
+                (ident, new_ident)
+            })
+            .collect();
+        let rename = |ident: &str| renamed[&canonicalize(ident)].clone();
+
+        let mut variables = self.variables.clone();
+        for var in variables.iter_mut() {
+            let ident = rename(var.get_ident());
+            var.set_ident(ident);
+            if let Variable::Stock(stock) = var {
+                stock.inflows = stock.inflows.iter().map(|f| rename(f)).collect();
+                stock.outflows = stock.outflows.iter().map(|f| rename(f)).collect();
+            }
+            for (_, field, text) in texts_mut(var) {
+                if field == Field::Units {
+                    continue;
+                }
+                *text = replace_idents(text, field.lexer_type(), |id| {
+                    renamed.get(id).or_else(|| values.get(id)).cloned()
+                });
+            }
+        }
+        Ok(variables)
+    }
+}
+
+// the canonicalized identifiers an equation refers to
+pub(crate) fn references(eqn: &Equation) -> HashSet<String> {
+    let texts: Vec<&str> = match eqn {
+        Equation::Scalar(eqn, initial) | Equation::ApplyToAll(_, eqn, initial) => {
+            std::iter::once(eqn.as_str())
+                .chain(initial.as_deref())
+                .collect()
+        }
+        Equation::Arrayed(_, elements) => elements
+            .iter()
+            .flat_map(|(_, eqn, initial)| std::iter::once(eqn.as_str()).chain(initial.as_deref()))
+            .collect(),
+    };
+    texts
+        .into_iter()
+        .flat_map(|text| Lexer::new(text, LexerType::Equation).flatten())
+        .filter_map(|(_, token, _)| match token {
+            Token::Ident(id) => Some(canonicalize(id)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn add_cloud(
+    elements: &mut Vec<ViewElement>,
+    next_uid: &mut i32,
+    flow_uid: i32,
+    x: f64,
+    y: f64,
+) -> FlowEnd {
+    let uid = *next_uid;
+    *next_uid += 1;
+    elements.push(ViewElement::Cloud(view_element::Cloud {
+        uid,
+        flow_uid,
+        x,
+        y,
+    }));
+    FlowEnd::Cloud { uid, x, y }
+}
+
+// drains_into is true if a flow goes directly from stock `a` to `b`
+fn drains_into(a: &Stock, b: &Stock) -> bool {
+    a.outflows
+        .iter()
+        .any(|f| b.inflows.iter().any(|g| canonicalize(f) == canonicalize(g)))
+}
+
+// lay_out adds the variables to the view: chains of stocks connected by
+// flows in rows to the right of (x, y), with the flows between them, and
+// auxiliaries and modules in a row below.
+pub(crate) fn lay_out(view: &mut StockFlow, variables: &[Variable], x: f64, y: f64) {
+    let mut next_uid = view.next_uid();
+    let mut elements = vec![];
+    let mut uids: HashMap<String, i32> = HashMap::new();
+
+    let stocks: Vec<&Stock> = variables
+        .iter()
+        .filter_map(|var| match var {
+            Variable::Stock(stock) => Some(stock),
+            _ => None,
+        })
+        .collect();
+    let mut stock_ends: HashMap<String, FlowEnd> = HashMap::new();
+    // leave room for a cloud to the left of the first stock in each row
+    let (mut stock_x, mut stock_y) = (x + SPACING, y);
+    let mut max_x = x;
+    for (i, stock) in stocks.iter().enumerate() {
+        if i > 0 && drains_into(stocks[i - 1], stock) {
+            stock_x += SPACING;
+        } else if i > 0 {
+            stock_x = x + SPACING;
+            stock_y += SPACING;
+        }
+        max_x = max_x.max(stock_x);
+        let uid = next_uid;
+        next_uid += 1;
+        elements.push(ViewElement::Stock(view_element::Stock {
+            name: stock.ident.clone(),
+            uid,
+            x: stock_x,
+            y: stock_y,
+            label_side: LabelSide::Bottom,
+        }));
+        uids.insert(canonicalize(&stock.ident), uid);
+        stock_ends.insert(
+            canonicalize(&stock.ident),
+            FlowEnd::Stock {
+                uid,
+                x: stock_x,
+                y: stock_y,
+            },
+        );
+    }
+
+    let flows = variables.iter().filter_map(|var| match var {
+        Variable::Flow(flow) => Some(flow),
+        _ => None,
+    });
+    // flows not connected to any stock go to the right of the stocks
+    let mut free_x = max_x + 2.0 * SPACING;
+    for flow in flows {
+        let uid = next_uid;
+        next_uid += 1;
+        let ident = canonicalize(&flow.ident);
+        let end = |is_source: bool| {
+            stocks
+                .iter()
+                .find(|stock| {
+                    let flows = if is_source {
+                        &stock.outflows
+                    } else {
+                        &stock.inflows
+                    };
+                    flows.iter().any(|f| canonicalize(f) == ident)
+                })
+                .map(|stock| stock_ends[&canonicalize(&stock.ident)])
+        };
+        let (source, sink) = match (end(true), end(false)) {
+            (Some(source), Some(sink)) => (source, sink),
+            (None, Some(sink @ FlowEnd::Stock { x, y, .. })) => {
+                let source = add_cloud(&mut elements, &mut next_uid, uid, x - SPACING, y);
+                (source, sink)
+            }
+            (Some(source @ FlowEnd::Stock { x, y, .. }), None) => {
+                let sink = add_cloud(&mut elements, &mut next_uid, uid, x + SPACING, y);
+                (source, sink)
+            }
+            _ => {
+                let source = add_cloud(&mut elements, &mut next_uid, uid, free_x, y);
+                let sink = add_cloud(&mut elements, &mut next_uid, uid, free_x + SPACING, y);
+                free_x += 2.0 * SPACING;
+                (source, sink)
+            }
+        };
+        let points = route_flow(source, sink);
+        let (first, last) = (&points[0], &points[points.len() - 1]);
+        elements.push(ViewElement::Flow(view_element::Flow {
+            name: flow.ident.clone(),
+            uid,
+            x: (first.x + last.x) / 2.0,
+            y: (first.y + last.y) / 2.0,
+            label_side: LabelSide::Bottom,
+            points,
+        }));
+        uids.insert(ident, uid);
+    }
+
+    let auxes = variables
+        .iter()
+        .filter(|var| matches!(var, Variable::Aux(_) | Variable::Module(_)));
+    for (i, var) in auxes.enumerate() {
+        let uid = next_uid;
+        next_uid += 1;
+        let name = var.get_ident().to_owned();
+        let (x, y) = (x + SPACING + AUX_SPACING * i as f64, stock_y + AUX_SPACING);
+        let label_side = LabelSide::Bottom;
+        elements.push(match var {
+            Variable::Module(_) => ViewElement::Module(view_element::Module {
+                name,
+                uid,
+                x,
+                y,
+                label_side,
+            }),
+            _ => ViewElement::Aux(view_element::Aux {
+                name,
+                uid,
+                x,
+                y,
+                label_side,
+            }),
+        });
+        uids.insert(canonicalize(var.get_ident()), uid);
+    }
+
+    // links into flows and auxiliaries; stocks only depend on their flows
+    // (and their initial values)
+    for var in variables.iter() {
+        if matches!(var, Variable::Stock(_)) {
+            continue;
+        }
+        let ident = canonicalize(var.get_ident());
+        let to_uid = uids[&ident];
+        let mut from: Vec<i32> = var
+            .get_equation()
+            .map(references)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|id| *id != ident)
+            .filter_map(|id| uids.get(&id).copied())
+            .collect();
+        from.sort_unstable();
+        for from_uid in from {
+            elements.push(ViewElement::Link(view_element::Link {
+                uid: next_uid,
+                from_uid,
+                to_uid,
+                shape: LinkShape::Straight,
+                polarity: None,
+                delay_mark: false,
+            }));
+            next_uid += 1;
+        }
+    }
+
+    view.elements.extend(elements);
+}
+
+impl Project {
+    /// insert_template instantiates `template` into the model under
+    /// `prefix`, returning the names of the new variables.  If `at` is
+    /// given, the variables are also laid out in the model's diagram
+    /// starting at that point, creating a diagram if the model has none.
+    pub fn insert_template(
+        &mut self,
+        model_name: &str,
+        template: &Template,
+        prefix: &str,
+        args: &HashMap<String, String>,
+        at: Option<(f64, f64)>,
+    ) -> Result<Vec<String>> {
+        let variables = template.instantiate(prefix, args)?;
+        let model = match self.get_model_mut(model_name) {
+            Some(model) => model,
+            None => return model_err!(BadModelName, model_name.to_owned()),
+        };
+
+        let existing: HashSet<String> = model
+            .variables
+            .iter()
+            .map(|var| canonicalize(var.get_ident()))
+            .collect();
+        if let Some(var) = variables
+            .iter()
+            .find(|var| existing.contains(&canonicalize(var.get_ident())))
+        {
+            return model_err!(DuplicateVariable, var.get_ident().to_owned());
+        }
+
+        if let Some((x, y)) = at {
+            if model.views.is_empty() {
+                model.views.push(View::StockFlow(StockFlow {
+                    elements: vec![],
+                    view_box: Rect::default(),
+                    zoom: 1.0,
+                }));
+            }
+            let View::StockFlow(view) = &mut model.views[0];
+            lay_out(view, &variables, x, y);
+        }
+
+        let idents = variables
+            .iter()
+            .map(|var| var.get_ident().to_owned())
+            .collect();
+        model.variables.extend(variables);
+        Ok(idents)
+    }
+}
+
+#[test]
+fn test_insert_template() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_model, x_project};
+
+    let mut project = x_project(
+        sim_specs_with_units("month"),
+        &[x_model("main", vec![x_aux("market_size", "5000", None)])],
+    );
+    let template = Template::bass_diffusion();
+
+    let args: HashMap<String, String> = [
+        ("total_population".to_owned(), "market_size".to_owned()),
+        ("contact_rate".to_owned(), "50 + 50".to_owned()),
+    ]
+    .into_iter()
+    .collect();
+    let idents = project
+        .insert_template("main", &template, "Phone", &args, Some((100.0, 100.0)))
+        .unwrap();
+    assert_eq!(
+        vec![
+            "phone_potential_adopters",
+            "phone_adopters",
+            "phone_adoption",
+            "phone_adoption_from_advertising",
+            "phone_adoption_from_word_of_mouth",
+        ],
+        idents
+    );
+
+    let model = project.get_model("main").unwrap();
+    let eqn = |ident: &str| match model.get_variable(ident).unwrap().get_equation() {
+        Some(Equation::Scalar(eqn, _)) => eqn.clone(),
+        _ => unreachable!(),
+    };
+    assert_eq!("market_size", eqn("phone_potential_adopters"));
+    assert_eq!(
+        "(50 + 50) * 0.015 * phone_adopters * phone_potential_adopters / market_size",
+        eqn("phone_adoption_from_word_of_mouth")
+    );
+    match model.get_variable("phone_adopters") {
+        Some(Variable::Stock(stock)) => assert_eq!(vec!["phone_adoption"], stock.inflows),
+        _ => unreachable!(),
+    }
+
+    // the pipes are attached to the stocks, and links are drawn for the
+    // dependencies of the flow and auxiliaries
+    let View::StockFlow(view) = &model.views[0];
+    assert!(view.validate_flow_points().is_empty());
+    let links = view
+        .elements
+        .iter()
+        .filter(|e| matches!(e, ViewElement::Link(_)))
+        .count();
+    assert_eq!(5, links);
+
+    // instantiating again under the same prefix would duplicate variables
+    assert!(project
+        .insert_template("main", &template, "phone", &HashMap::new(), None)
+        .is_err());
+    let bad_args: HashMap<String, String> =
+        [("nope".to_owned(), "1".to_owned())].into_iter().collect();
+    assert!(template.instantiate("tv", &bad_args).is_err());
+
+    let chain = Template::aging_chain(3)
+        .instantiate("", &HashMap::new())
+        .unwrap();
+    let idents: Vec<&str> = chain.iter().map(|var| var.get_ident()).collect();
+    assert_eq!(
+        vec![
+            "entering",
+            "stage_1",
+            "maturing_1",
+            "stage_2",
+            "maturing_2",
+            "stage_3",
+            "exiting"
+        ],
+        idents
+    );
+}
@@ -13,14 +13,25 @@ fn optional_vec(slice: &[&str]) -> Vec<String> {
 
 #[cfg(test)]
 pub(crate) fn x_aux(ident: &str, eqn: &str, units: Option<&str>) -> datamodel::Variable {
-    use datamodel::{Aux, Equation, Variable, Visibility};
+    let equation = datamodel::Equation::Scalar(eqn.to_string(), None);
+    x_aux_with_equation(ident, equation, units)
+}
+
+#[cfg(test)]
+fn x_aux_with_equation(
+    ident: &str,
+    equation: datamodel::Equation,
+    units: Option<&str>,
+) -> datamodel::Variable {
+    use datamodel::{Aux, Variable, Visibility};
     Variable::Aux(Aux {
         ident: ident.to_string(),
-        equation: Equation::Scalar(eqn.to_string(), None),
+        equation,
         documentation: "".to_string(),
         units: units.map(|s| s.to_owned()),
         gf: None,
         can_be_module_input: false,
+        supplementary: false,
         visibility: Visibility::Private,
     })
 }
@@ -42,6 +53,32 @@ pub(crate) fn aux(ident: &str, eqn: &str) -> Variable {
     lower_variable(&scope, "main", &var)
 }
 
+#[cfg(test)]
+pub(crate) fn x_arrayed(
+    ident: &str,
+    dims: &[&str],
+    elements: &[(&str, &str)],
+    units: Option<&str>,
+) -> datamodel::Variable {
+    let elements = elements
+        .iter()
+        .map(|(element, eqn)| (element.to_string(), eqn.to_string(), None))
+        .collect();
+    let equation = datamodel::Equation::Arrayed(optional_vec(dims), elements);
+    x_aux_with_equation(ident, equation, units)
+}
+
+#[cfg(test)]
+pub(crate) fn x_a2a(
+    ident: &str,
+    dims: &[&str],
+    eqn: &str,
+    units: Option<&str>,
+) -> datamodel::Variable {
+    let equation = datamodel::Equation::ApplyToAll(optional_vec(dims), eqn.to_string(), None);
+    x_aux_with_equation(ident, equation, units)
+}
+
 #[cfg(test)]
 pub(crate) fn x_stock(
     ident: &str,
@@ -59,7 +96,9 @@ pub(crate) fn x_stock(
         inflows: optional_vec(inflows),
         outflows: optional_vec(outflows),
         non_negative: false,
+        force_euler: false,
         can_be_module_input: false,
+        supplementary: false,
         visibility: Visibility::Private,
     })
 }
@@ -87,6 +126,9 @@ pub(crate) fn x_model(ident: &str, variables: Vec<datamodel::Variable>) -> datam
         name: ident.to_string(),
         variables,
         views: vec![],
+        graphs: vec![],
+        groups: vec![],
+        variable_index: Default::default(),
     }
 }
 
@@ -101,7 +143,10 @@ pub(crate) fn x_project(
         dimensions: vec![],
         units: vec![],
         models: models.to_vec(),
+        constants: vec![],
+        tests: vec![],
         source: Default::default(),
+        import_report: vec![],
     }
 }
 
@@ -142,6 +187,7 @@ pub(crate) fn x_flow(ident: &str, eqn: &str, units: Option<&str>) -> datamodel::
         gf: None,
         non_negative: false,
         can_be_module_input: false,
+        supplementary: false,
         visibility: Visibility::Private,
     })
 }
@@ -171,6 +217,7 @@ pub(crate) fn sim_specs_with_units(time_units: &str) -> crate::datamodel::SimSpe
         dt: Default::default(),
         save_step: None,
         sim_method: Default::default(),
+        tolerances: Default::default(),
         time_units: Some(time_units.to_owned()),
     }
 }
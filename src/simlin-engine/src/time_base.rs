@@ -0,0 +1,298 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Converting a project to a different time base, like from months to
+//! years.  The sim specs can be rescaled exactly, but what each number
+//! in an equation means can't be known, so the rate constants, units
+//! and times that have to change along with them are reported rather
+//! than rewritten.
+
+use crate::ast::{BinaryOp, Expr0};
+use crate::builtins::UntypedBuiltinFn;
+use crate::common::{canonicalize, Ident, Result};
+use crate::complexity::{children, equation_texts};
+use crate::datamodel::{Dt, Project, SimSpecs, Variable};
+use crate::sim_err;
+use crate::token::LexerType;
+use crate::units::{parse_units, Context};
+
+// the builtins that take a time, and the positions of those arguments
+const TIME_ARGS: &[(&str, &[usize])] = &[
+    ("step", &[1]),
+    ("pulse", &[1, 2]),
+    ("ramp", &[1, 2]),
+    ("delay1", &[1]),
+    ("delay3", &[1]),
+    ("delayn", &[1]),
+    ("smth1", &[1]),
+    ("smth3", &[1]),
+    ("smooth", &[1]),
+    ("smoothi", &[1]),
+    ("smooth3", &[1]),
+    ("smooth3i", &[1]),
+    ("trend", &[1]),
+    ("pink_noise", &[2]),
+];
+
+/// TimeBaseChange is a variable whose equation or units have to be
+/// updated by hand when the project's time units change.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TimeBaseChange {
+    /// empty for project-wide constants
+    pub model_name: String,
+    pub ident: Ident,
+    /// the variable's units, if they have the time units in them
+    pub units: Option<String>,
+    /// the power of the time units in `units`, like -1 for a rate in
+    /// people/month
+    pub exponent: i32,
+    /// whether the equation is a plain number, which has to be scaled
+    /// along with its units
+    pub is_constant: bool,
+    /// numbers in the equation that are times, like the start of a STEP
+    /// or the delay of a DELAY1
+    pub times: Vec<String>,
+}
+
+fn scale_dt(dt: &Dt, factor: f64) -> Dt {
+    match dt {
+        Dt::Dt(value) => Dt::Dt(value / factor),
+        Dt::Reciprocal(value) => Dt::Reciprocal(value * factor),
+    }
+}
+
+fn is_time(expr: &Expr0) -> bool {
+    match expr {
+        Expr0::Var(id, _) => id == "time",
+        Expr0::App(UntypedBuiltinFn(func, args), _) => func == "time" && args.is_empty(),
+        _ => false,
+    }
+}
+
+// time_literals adds the numbers in `expr` that are times: arguments to
+// builtins like STEP, and numbers compared against TIME.
+fn time_literals(expr: &Expr0, found: &mut Vec<String>) {
+    let mut add = |expr: &Expr0| {
+        if let Expr0::Const(text, _, _) = expr {
+            found.push(text.clone());
+        }
+    };
+    match expr {
+        Expr0::App(UntypedBuiltinFn(func, args), _) => {
+            if let Some((_, positions)) = TIME_ARGS.iter().find(|(name, _)| name == func) {
+                for arg in positions.iter().filter_map(|i| args.get(*i)) {
+                    add(arg);
+                }
+            }
+        }
+        Expr0::Op2(
+            BinaryOp::Lt
+            | BinaryOp::Lte
+            | BinaryOp::Gt
+            | BinaryOp::Gte
+            | BinaryOp::Eq
+            | BinaryOp::Neq,
+            l,
+            r,
+            _,
+        ) => {
+            if is_time(l) {
+                add(r);
+            } else if is_time(r) {
+                add(l);
+            }
+        }
+        _ => {}
+    }
+    for child in children(expr) {
+        time_literals(child, found);
+    }
+}
+
+// time_exponent returns the power of the time units `time_unit` in
+// `units`.
+fn time_exponent(ctx: &Context, time_unit: &str, units: Option<&str>) -> i32 {
+    match parse_units(ctx, units) {
+        Ok(Some(units)) => units.map.get(time_unit).copied().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+fn variable_change(
+    ctx: &Context,
+    time_unit: &str,
+    model_name: &str,
+    var: &Variable,
+) -> Option<TimeBaseChange> {
+    let units = var.get_units().map(|units| units.as_str());
+    let exponent = time_exponent(ctx, time_unit, units);
+
+    let mut is_constant = !matches!(var, Variable::Module(_));
+    let mut times = vec![];
+    let texts = equation_texts(var);
+    for (_, eqn) in texts.iter() {
+        match Expr0::new(eqn, LexerType::Equation) {
+            Ok(Some(expr)) => {
+                is_constant &= matches!(expr, Expr0::Const(_, _, _));
+                time_literals(&expr, &mut times);
+            }
+            _ => is_constant = false,
+        }
+    }
+    is_constant &= !texts.is_empty();
+
+    if exponent == 0 && times.is_empty() {
+        return None;
+    }
+    Some(TimeBaseChange {
+        model_name: model_name.to_owned(),
+        ident: canonicalize(var.get_ident()),
+        units: if exponent == 0 {
+            None
+        } else {
+            units.map(|units| units.to_owned())
+        },
+        exponent,
+        is_constant,
+        times,
+    })
+}
+
+impl Project {
+    /// convert_time_base returns a copy of the project measured in
+    /// `time_units`, where one of them is `factor` of the current time
+    /// units (12 to go from months to years).  Start, stop, dt, save
+    /// step, table intervals and the times of model tests are scaled so
+    /// the same steps are simulated; equations and units are left alone,
+    /// and `find_time_base_changes` on the original project reports the
+    /// ones that need updating to match.
+    pub fn convert_time_base(&self, time_units: &str, factor: f64) -> Result<Project> {
+        if !factor.is_finite() || factor <= 0.0 {
+            return sim_err!(
+                BadSimSpecs,
+                format!("time base factor must be greater than 0, not {}", factor)
+            );
+        }
+        if time_units.trim().is_empty() {
+            return sim_err!(BadSimSpecs, "time units can't be empty".to_owned());
+        }
+
+        let mut project = self.clone();
+        let specs = &self.sim_specs;
+        project.sim_specs = SimSpecs {
+            start: specs.start / factor,
+            stop: specs.stop / factor,
+            dt: scale_dt(&specs.dt, factor),
+            save_step: specs.save_step.as_ref().map(|dt| scale_dt(dt, factor)),
+            time_units: Some(time_units.to_owned()),
+            ..specs.clone()
+        };
+        for model in project.models.iter_mut() {
+            for graph in model.graphs.iter_mut() {
+                if let Some(interval) = graph.interval.as_mut() {
+                    *interval /= factor;
+                }
+            }
+        }
+        for test in project.tests.iter_mut() {
+            for expectation in test.expectations.iter_mut() {
+                expectation.time /= factor;
+            }
+        }
+        Ok(project)
+    }
+
+    /// find_time_base_changes reports every variable that depends on the
+    /// project's time units: those with the time units in their own
+    /// units, like a rate in 1/month, and those with times written into
+    /// their equations, like `STEP(10, 12)`.  The project has to have
+    /// time units.
+    pub fn find_time_base_changes(&self) -> Vec<TimeBaseChange> {
+        let ctx = match Context::new_with_builtins(&self.units, &self.sim_specs) {
+            Ok(ctx) => ctx,
+            Err(_) => return vec![],
+        };
+        if self.sim_specs.time_units.is_none() {
+            return vec![];
+        }
+        // only simple time units, like `month`, can be found in others
+        let time_units = ctx.time_units().map;
+        let time_unit = match time_units.keys().next() {
+            Some(unit) if time_units.len() == 1 => unit.clone(),
+            _ => return vec![],
+        };
+
+        let mut changes: Vec<TimeBaseChange> = self
+            .models
+            .iter()
+            .flat_map(|model| {
+                model
+                    .variables
+                    .iter()
+                    .filter_map(|var| variable_change(&ctx, &time_unit, &model.name, var))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        for constant in self.constants.iter() {
+            let var = Variable::Aux(constant.clone());
+            changes.extend(variable_change(&ctx, &time_unit, "", &var));
+        }
+        changes
+    }
+}
+
+#[test]
+fn test_time_base() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
+    use crate::vm::Vm;
+    use crate::Simulation;
+
+    let mut project = x_project(
+        sim_specs_with_units("months"),
+        &[x_model(
+            "main",
+            vec![
+                x_stock("population", "100", &["births"], &[], Some("people")),
+                x_flow("births", "population * birth_rate", Some("people/month")),
+                x_aux("birth_rate", "0.01", Some("1/month")),
+                x_aux(
+                    "campaign",
+                    "STEP(0.5, 12) + (IF TIME > 6 THEN 1 ELSE 0)",
+                    None,
+                ),
+                x_aux("area", "10", Some("acres")),
+            ],
+        )],
+    );
+    project.sim_specs.stop = 24.0;
+    project.sim_specs.dt = Dt::Reciprocal(4.0);
+
+    let years = project.convert_time_base("years", 12.0).unwrap();
+    assert_eq!(0.0, years.sim_specs.start);
+    assert_eq!(2.0, years.sim_specs.stop);
+    assert_eq!(Dt::Reciprocal(48.0), years.sim_specs.dt);
+    assert_eq!(Some("years"), years.sim_specs.time_units.as_deref());
+    assert!(project.convert_time_base("years", 0.0).is_err());
+    assert!(project.convert_time_base("", 12.0).is_err());
+
+    // the same steps are simulated
+    let saved_steps = |project: &Project| {
+        let project = crate::Project::from(project.clone());
+        let sim = Simulation::new(&project, "main").unwrap();
+        let mut vm = Vm::new(sim.compile().unwrap()).unwrap();
+        vm.run_to_end().unwrap();
+        vm.into_results().step_count
+    };
+    assert_eq!(saved_steps(&project), saved_steps(&years));
+
+    let changes = project.find_time_base_changes();
+    let idents: Vec<&str> = changes.iter().map(|c| c.ident.as_str()).collect();
+    assert_eq!(vec!["births", "birth_rate", "campaign"], idents);
+    assert_eq!(-1, changes[0].exponent);
+    assert!(!changes[0].is_constant);
+    assert_eq!(Some("1/month"), changes[1].units.as_deref());
+    assert!(changes[1].is_constant);
+    assert_eq!(vec!["12", "6"], changes[2].times);
+    assert_eq!(None, changes[2].units);
+}
@@ -3,7 +3,7 @@
 // Version 2.0, that can be found in the LICENSE file.
 
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::result::Result as StdResult;
 
 use float_cmp::approx_eq;
@@ -60,28 +60,66 @@ pub struct Context {
     units: HashMap<String, UnitMap>,
 }
 
+/// DEFAULT_UNIT_SYNONYMS are the spellings of common units that are
+/// treated as the same unit, so that a model mixing `person` and `people`
+/// doesn't report spurious unit mismatches.  A project can extend a set
+/// by declaring the unit with more aliases, or opt out of one by using
+/// any of its names for a unit of its own.
+pub const DEFAULT_UNIT_SYNONYMS: &[(&str, &[&str])] = &[
+    ("dollar", &["dollars", "$", "usd"]),
+    ("person", &["people", "persons", "peoples"]),
+    ("year", &["years"]),
+    ("month", &["months"]),
+    ("week", &["weeks"]),
+    ("day", &["days"]),
+    ("hour", &["hours"]),
+    ("minute", &["minutes"]),
+    ("second", &["seconds"]),
+];
+
 impl Context {
     pub fn new_with_builtins(
         units: &[Unit],
         sim_specs: &SimSpecs,
     ) -> StdResult<Self, Vec<(String, Vec<EquationError>)>> {
-        let builtin_units: &[(&str, &[&str])] = &[
-            // ("dollars", &["$", "usd"]),
-            // ("year", &["years"]),
-            // ("month", &["months"]),
-            // ("person", &["people", "persons", "peoples"]),
-        ];
-        let mut builtin_units = builtin_units
+        let mut units = units.to_vec();
+        let mut taken: HashSet<String> = units
             .iter()
-            .map(|(name, aliases)| Unit {
+            .flat_map(|unit| std::iter::once(&unit.name).chain(unit.aliases.iter()))
+            .map(|name| canonicalize(name))
+            .collect();
+
+        let mut builtin_units = vec![];
+        for (name, synonyms) in DEFAULT_UNIT_SYNONYMS.iter() {
+            let canonical_name = canonicalize(name);
+            // a project's own definition of the unit picks up any
+            // synonyms it doesn't use elsewhere
+            if let Some(unit) = units
+                .iter_mut()
+                .find(|unit| canonicalize(&unit.name) == canonical_name)
+            {
+                for synonym in synonyms.iter() {
+                    if taken.insert(canonicalize(synonym)) {
+                        unit.aliases.push(synonym.to_string());
+                    }
+                }
+                continue;
+            }
+            if std::iter::once(name)
+                .chain(synonyms.iter())
+                .any(|name| taken.contains(&canonicalize(name)))
+            {
+                continue;
+            }
+            builtin_units.push(Unit {
                 name: name.to_string(),
                 equation: None,
                 disabled: false,
-                aliases: aliases.iter().map(|s| s.to_string()).collect(),
-            })
-            .collect::<Vec<_>>();
+                aliases: synonyms.iter().map(|s| s.to_string()).collect(),
+            });
+        }
 
-        builtin_units.append(&mut units.to_vec());
+        builtin_units.append(&mut units);
 
         Self::new(&builtin_units, sim_specs)
     }
@@ -214,6 +252,15 @@ impl Context {
         let normalized = self.aliases.get(ident).map(|s| s.as_str()).unwrap_or(ident);
         self.units.get(normalized)
     }
+
+    /// time_units returns the units of the simulation's time, with any
+    /// synonym resolved.
+    pub(crate) fn time_units(&self) -> UnitMap {
+        let name = canonicalize(self.sim_specs.time_units.as_deref().unwrap_or("time"));
+        self.lookup(&name)
+            .cloned()
+            .unwrap_or_else(|| [(name, 1)].iter().cloned().collect())
+    }
 }
 
 #[allow(dead_code)]
@@ -413,6 +460,63 @@ fn test_pretty_print_unit() {
     }
 }
 
+#[test]
+fn test_unit_display() {
+    use crate::datamodel::{UnitFormat, UnitTermOrder};
+
+    let units = |terms: &[(&str, i32)]| -> UnitMap {
+        terms
+            .iter()
+            .map(|(unit, exp)| (unit.to_string(), *exp))
+            .collect()
+    };
+    let grouped = UnitFormat {
+        group_denominator: true,
+        ..Default::default()
+    };
+    let by_power = UnitFormat {
+        order: UnitTermOrder::HighestPowerFirst,
+        ..Default::default()
+    };
+
+    let cases: &[(UnitMap, UnitFormat, &str)] = &[
+        (
+            units(&[("people", 1), ("year", -1)]),
+            grouped,
+            "people/year",
+        ),
+        (
+            units(&[("widgets", 1), ("dollars", 1), ("month", -2)]),
+            Default::default(),
+            "dollars*widgets/month^2",
+        ),
+        (
+            units(&[("widgets", 2), ("dollars", 1), ("month", -2)]),
+            by_power,
+            "widgets^2*dollars/month^2",
+        ),
+        (
+            units(&[("meter", -1), ("second", -2)]),
+            Default::default(),
+            "1/meter/second^2",
+        ),
+        (
+            units(&[("meter", -1), ("second", -2)]),
+            by_power,
+            "1/second^2/meter",
+        ),
+        (
+            units(&[("meter", -1), ("second", -2)]),
+            grouped,
+            "1/(meter*second^2)",
+        ),
+        (units(&[]), grouped, "dmnl"),
+    ];
+    for (units, format, expected) in cases {
+        assert_eq!(*expected, units.display(*format));
+    }
+}
+
 // we have 3 problems here: the first (and simpler) is evaluating unit equations and turning them in to UnitMaps (done)
 // the second is: given a context of unitmaps, can we _check_ the types of variables.  This won't work if there are builtins in use.
 // the third is: if we only have _some_ units filled in, can we _infer_ the rest? This will also enable units for builtins
@@ -587,6 +691,44 @@ fn test_basic_unit_parsing() {
     }
 }
 
+#[test]
+fn test_unit_synonyms() {
+    let unit = |name: &str, aliases: &[&str]| Unit {
+        name: name.to_owned(),
+        equation: None,
+        disabled: false,
+        aliases: aliases.iter().map(|s| s.to_string()).collect(),
+    };
+    let parse = |ctx: &Context, eqn: &str| parse_units(ctx, Some(eqn)).unwrap().unwrap();
+
+    let context = Context::new_with_builtins(&[], &Default::default()).unwrap();
+    let same = &[
+        ("Person", "people"),
+        ("persons/year", "people/years"),
+        ("$", "usd"),
+        ("Dollars/Month", "$/month"),
+        ("1/year", "year^-1"),
+        ("people/year^2", "person*years^-2"),
+        ("year^0", "dmnl"),
+    ];
+    for (a, b) in same {
+        assert_eq!(parse(&context, a), parse(&context, b));
+    }
+    assert_ne!(parse(&context, "person"), parse(&context, "dollar"));
+
+    // a project's own units take precedence over the synonym sets
+    let context = Context::new_with_builtins(
+        &[unit("person", &["citizen"]), unit("peoples", &[])],
+        &Default::default(),
+    )
+    .unwrap();
+    assert_eq!(parse(&context, "citizen"), parse(&context, "people"));
+    assert_ne!(parse(&context, "person"), parse(&context, "peoples"));
+
+    let context = Context::new_with_builtins(&[unit("usd", &[])], &Default::default()).unwrap();
+    assert_ne!(parse(&context, "usd"), parse(&context, "dollars"));
+}
+
 #[test]
 fn test_basic_unit_checks() {
     let _context = Context::new(
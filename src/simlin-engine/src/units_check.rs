@@ -7,7 +7,7 @@ use std::result::Result as StdResult;
 
 use crate::ast::{Ast, BinaryOp, Expr};
 use crate::builtins::{BuiltinFn, Loc};
-use crate::common::{canonicalize, EquationError, ErrorCode, Ident, Result, UnitError, UnitResult};
+use crate::common::{EquationError, ErrorCode, Ident, Result, UnitError, UnitResult};
 use crate::datamodel::UnitMap;
 use crate::model::ModelStage1;
 use crate::units::{combine, Context, UnitOp, Units};
@@ -23,36 +23,37 @@ struct UnitEvaluator<'a> {
 }
 
 impl<'a> UnitEvaluator<'a> {
+    fn var_units(&self, ident: &str, loc: Loc) -> UnitResult<Units> {
+        use UnitError::ConsistencyError;
+        let units: &UnitMap = if ident == "time" || ident == "initial_time" || ident == "final_time"
+        {
+            // we created this time variable just for unit checking, it is definitely Some
+            self.time.units().unwrap()
+        } else {
+            // use the variable's explicitly defined units unless they don't exist.
+            // if they don't exist, try to use any inferred units (this handles modules)
+            self.model
+                .variables
+                .get(ident)
+                .and_then(|var| var.units())
+                .or_else(|| self.inferred_units.get(ident))
+                .ok_or_else(|| {
+                    ConsistencyError(
+                        ErrorCode::DoesNotExist,
+                        loc,
+                        Some(format!("can't find or no units for dependency '{}'", ident)),
+                    )
+                })?
+        };
+
+        Ok(Units::Explicit(units.clone()))
+    }
+
     fn check(&self, expr: &Expr) -> UnitResult<Units> {
         use UnitError::ConsistencyError;
         match expr {
             Expr::Const(_, _, _) => Ok(Units::Constant),
-            Expr::Var(ident, loc) => {
-                let units: &UnitMap = if ident == "time"
-                    || ident == "initial_time"
-                    || ident == "final_time"
-                {
-                    // we created this time variable just for unit checking, it is definitely Some
-                    self.time.units().unwrap()
-                } else {
-                    // use the variable's explicitly defined units unless they don't exist.
-                    // if they don't exist, try to use any inferred units (this handles modules)
-                    self.model
-                        .variables
-                        .get(ident)
-                        .and_then(|var| var.units())
-                        .or_else(|| self.inferred_units.get(ident))
-                        .ok_or_else(|| {
-                            ConsistencyError(
-                                ErrorCode::DoesNotExist,
-                                *loc,
-                                Some(format!("can't find or no units for dependency '{}'", ident)),
-                            )
-                        })?
-                };
-
-                Ok(Units::Explicit(units.clone()))
-            }
+            Expr::Var(ident, loc) => self.var_units(ident, *loc),
             Expr::App(builtin, _) => match builtin {
                 BuiltinFn::Inf | BuiltinFn::Pi => Ok(Units::Constant),
                 BuiltinFn::Time
@@ -65,8 +66,9 @@ impl<'a> UnitEvaluator<'a> {
                     // returns a bool, which is unitless
                     Ok(Units::Explicit(UnitMap::new()))
                 }
-                BuiltinFn::Lookup(ident, _, loc) => {
-                    // lookups have the units specified on the table
+                BuiltinFn::Lookup(ident, _, loc) | BuiltinFn::Transpose(ident, loc) => {
+                    // lookups have the units specified on the table, and
+                    // transposed arrays have the units of the array
                     if let Some(units) = self
                         .model
                         .variables
@@ -137,14 +139,7 @@ impl<'a> UnitEvaluator<'a> {
                     let a_units = self.check(a)?;
                     let b_units = self.check(b)?;
                     if !a_units.equals(&b_units) {
-                        let a_units = match a_units {
-                            Units::Explicit(units) => units,
-                            Units::Constant => Default::default(),
-                        };
-                        let b_units = match b_units {
-                            Units::Explicit(units) => units,
-                            Units::Constant => Default::default(),
-                        };
+                        let (a_units, b_units) = (explicit(a_units), explicit(b_units));
                         let loc = a.get_loc().union(&b.get_loc());
                         Err(ConsistencyError (
                             ErrorCode::UnitDefinitionErrors,
@@ -159,9 +154,10 @@ impl<'a> UnitEvaluator<'a> {
                         Ok(a_units)
                     }
                 }
-                BuiltinFn::Pulse(_, _, _) | BuiltinFn::Ramp(_, _, _) | BuiltinFn::Step(_, _) => {
-                    Ok(Units::Constant)
-                }
+                BuiltinFn::Pulse(_, _, _)
+                | BuiltinFn::Ramp(_, _, _)
+                | BuiltinFn::Step(_, _)
+                | BuiltinFn::WhiteNoise(_) => Ok(Units::Constant),
                 BuiltinFn::SafeDiv(a, b, c) => {
                     let div = Expr::Op2(
                         BinaryOp::Div,
@@ -173,15 +169,23 @@ impl<'a> UnitEvaluator<'a> {
 
                     if let Some(c) = c {
                         let c_units = self.check(c)?;
-                        if c_units != units {
-                            // TODO: return an error here
+                        if !c_units.equals(&units) {
+                            return Err(ConsistencyError(
+                                ErrorCode::UnitMismatch,
+                                c.get_loc(),
+                                Some(format!(
+                                    "expected the fallback value to have the units of the division, '{}'",
+                                    explicit(units),
+                                )),
+                            ));
                         }
                     }
 
                     Ok(units)
                 }
             },
-            Expr::Subscript(_, _, _) => Ok(Units::Explicit(UnitMap::new())),
+            // an element of an array has the units of the whole array
+            Expr::Subscript(ident, _, loc) => self.var_units(ident, *loc),
             Expr::Op1(_, l, _) => self.check(l),
             Expr::Op2(op, l, r, _) => {
                 let lunits = self.check(l)?;
@@ -243,15 +247,31 @@ impl<'a> UnitEvaluator<'a> {
                 let runits = self.check(r)?;
 
                 if !lunits.equals(&runits) {
-                    eprintln!("TODO: if error, left and right units don't match");
+                    let details = Some(format!(
+                        "expected the THEN and ELSE branches to have the same units, but '{}' and '{}' don't",
+                        explicit(lunits),
+                        explicit(runits),
+                    ));
+                    let loc = l.get_loc().union(&r.get_loc());
+                    return Err(ConsistencyError(ErrorCode::UnitMismatch, loc, details));
                 }
 
-                Ok(lunits)
+                Ok(match lunits {
+                    Units::Constant => runits,
+                    lunits => lunits,
+                })
             }
         }
     }
 }
 
+fn explicit(units: Units) -> UnitMap {
+    match units {
+        Units::Explicit(units) => units,
+        Units::Constant => Default::default(),
+    }
+}
+
 // check uses the model's variables' equations and unit definitions to
 // calculate the concrete units for each equation.  The outer result
 // indicates if we had a problem running the analysis.  The inner result
@@ -271,11 +291,7 @@ pub fn check(
     // for each variable, evaluate the equation given the unit context
     // if the result doesn't match the expected thing, accumulate an error
 
-    let time_units_name = canonicalize(ctx.sim_specs.time_units.as_deref().unwrap_or("time"));
-    let time_units: UnitMap = ctx
-        .lookup(&time_units_name)
-        .cloned()
-        .unwrap_or_else(|| [(time_units_name.clone(), 1)].iter().cloned().collect());
+    let time_units = ctx.time_units();
     let one_over_time: UnitMap = combine(UnitOp::Div, Default::default(), time_units.clone());
 
     let units = UnitEvaluator {
@@ -341,8 +357,18 @@ pub fn check(
                 check_flows(outflows);
             }
             if let Some(ast) = var.ast() {
-                match ast {
-                    Ast::Scalar(expr) => match units.check(expr) {
+                // every element of an arrayed equation has to have the
+                // variable's units, so each is checked on its own
+                let exprs: Vec<&Expr> = match ast {
+                    Ast::Scalar(expr) | Ast::ApplyToAll(_, expr) => vec![expr],
+                    Ast::Arrayed(_, elements) => {
+                        let mut elements: Vec<_> = elements.iter().collect();
+                        elements.sort_unstable_by(|a, b| a.0.cmp(b.0));
+                        elements.into_iter().map(|(_, expr)| expr).collect()
+                    }
+                };
+                for expr in exprs {
+                    match units.check(expr) {
                         Ok(Units::Explicit(actual)) => {
                             if actual != *expected {
                                 let details = format!(
@@ -366,9 +392,7 @@ pub fn check(
                         Err(err) => {
                             errors.push((ident.clone(), err));
                         }
-                    },
-                    Ast::ApplyToAll(_, _) => {}
-                    Ast::Arrayed(_, _) => {}
+                    }
                 }
             }
         }
@@ -380,3 +404,74 @@ pub fn check(
     // log an error.
     Ok(Err(errors))
 }
+
+#[test]
+fn test_check_units() {
+    use crate::datamodel::Dimension;
+    use crate::testutils::{sim_specs_with_units, x_a2a, x_arrayed, x_aux, x_model, x_project};
+
+    let mut project = x_project(
+        sim_specs_with_units("seconds"),
+        &[x_model(
+            "main",
+            vec![
+                x_aux("length", "2", Some("meters")),
+                x_aux("duration", "4", Some("seconds")),
+                x_aux("speed", "length / duration", Some("meters/seconds")),
+                x_aux("bad_speed", "length * duration", Some("meters/seconds")),
+                x_aux(
+                    "branches",
+                    "IF TIME > 1 THEN length ELSE duration",
+                    Some("meters"),
+                ),
+                x_aux(
+                    "fallback",
+                    "SAFEDIV(length, duration, length)",
+                    Some("meters/seconds"),
+                ),
+                x_a2a("lengths", &["letters"], "length * 2", Some("meters")),
+                x_a2a("bad_lengths", &["letters"], "duration", Some("meters")),
+                x_arrayed(
+                    "per_element",
+                    &["letters"],
+                    &[("a", "lengths[a]"), ("b", "duration")],
+                    Some("meters"),
+                ),
+                x_aux(
+                    "element_speed",
+                    "lengths[b] / duration",
+                    Some("meters/seconds"),
+                ),
+            ],
+        )],
+    );
+    project.dimensions = vec![Dimension::Named(
+        "letters".to_owned(),
+        vec!["a".to_owned(), "b".to_owned()],
+    )];
+
+    let project = crate::Project::from(project);
+    let errors = project.models["main"].get_unit_errors();
+    let mut idents: Vec<&str> = errors.keys().map(|ident| ident.as_str()).collect();
+    idents.sort_unstable();
+    assert_eq!(
+        vec![
+            "bad_lengths",
+            "bad_speed",
+            "branches",
+            "fallback",
+            "per_element"
+        ],
+        idents
+    );
+    assert_eq!(1, errors["per_element"].len());
+    for err in errors.values().flatten() {
+        match err {
+            UnitError::ConsistencyError(code, loc, _) => {
+                assert_eq!(ErrorCode::UnitMismatch, *code);
+                assert!(loc.end > loc.start);
+            }
+            UnitError::DefinitionError(_, _) => panic!("unexpected definition error"),
+        }
+    }
+}
@@ -2,7 +2,7 @@
 // Use of this source code is governed by the Apache License,
 // Version 2.0, that can be found in the LICENSE file.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use crate::ast::{Ast, BinaryOp, Expr};
 use crate::builtins::BuiltinFn;
@@ -10,6 +10,7 @@ use crate::common::{canonicalize, Ident, Result, UnitResult};
 use crate::datamodel::UnitMap;
 use crate::model::ModelStage1;
 use crate::model_err;
+use crate::project::Project;
 #[cfg(test)]
 use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
 use crate::units::{combine, Context, UnitOp, Units};
@@ -118,8 +119,9 @@ impl<'a> UnitInferer<'a> {
                     // returns a bool, which is unitless
                     Ok(Units::Explicit(UnitMap::new()))
                 }
-                BuiltinFn::Lookup(ident, _, _loc) => {
-                    // lookups have the units specified on the table
+                BuiltinFn::Lookup(ident, _, _loc) | BuiltinFn::Transpose(ident, _loc) => {
+                    // lookups have the units specified on the table, and
+                    // transposed arrays have the units of the array
                     let units: UnitMap = [(format!("@{}{}", prefix, ident), 1)]
                         .iter()
                         .cloned()
@@ -183,9 +185,10 @@ impl<'a> UnitInferer<'a> {
                     Ok(a_units)
                 }
 
-                BuiltinFn::Pulse(_, _, _) | BuiltinFn::Ramp(_, _, _) | BuiltinFn::Step(_, _) => {
-                    Ok(Units::Constant)
-                }
+                BuiltinFn::Pulse(_, _, _)
+                | BuiltinFn::Ramp(_, _, _)
+                | BuiltinFn::Step(_, _)
+                | BuiltinFn::WhiteNoise(_) => Ok(Units::Constant),
                 BuiltinFn::SafeDiv(a, b, c) => {
                     let div = Expr::Op2(
                         BinaryOp::Div,
@@ -282,7 +285,7 @@ impl<'a> UnitInferer<'a> {
         prefix: &str,
         constraints: &mut Vec<UnitMap>,
     ) {
-        let time_units = canonicalize(self.ctx.sim_specs.time_units.as_deref().unwrap_or("time"));
+        let time_units = self.ctx.time_units();
 
         for (id, var) in model.variables.iter() {
             if let Variable::Stock {
@@ -293,14 +296,12 @@ impl<'a> UnitInferer<'a> {
             } = var
             {
                 let stock_ident = ident;
-                let expected = [
-                    (format!("@{}{}", prefix, stock_ident), 1),
-                    (time_units.clone(), -1),
-                ]
-                .iter()
-                .cloned()
-                .collect::<UnitMap>()
-                .push_ctx(format!("stock@{}{}", prefix, stock_ident));
+                let stock_units: UnitMap = [(format!("@{}{}", prefix, stock_ident), 1)]
+                    .iter()
+                    .cloned()
+                    .collect();
+                let expected = combine(UnitOp::Div, stock_units, time_units.clone())
+                    .push_ctx(format!("stock@{}{}", prefix, stock_ident));
                 let mut check_flows = |flows: &Vec<Ident>| {
                     for ident in flows.iter() {
                         let flow_units: UnitMap = [(format!("@{}{}", prefix, ident), 1)]
@@ -342,9 +343,16 @@ impl<'a> UnitInferer<'a> {
             // the variable _doesn't_ have an associated lookup table/graphical
             // function.
             if var.table().is_none() {
+                // constraints from within the equation, like both sides
+                // of an addition matching, are attributed to it
+                let mut eqn_constraints = vec![];
                 let var_units = match var.ast() {
-                    Some(Ast::Scalar(ast)) => self.gen_constraints(ast, prefix, constraints),
-                    Some(Ast::ApplyToAll(_, ast)) => self.gen_constraints(ast, prefix, constraints),
+                    Some(Ast::Scalar(ast)) => {
+                        self.gen_constraints(ast, prefix, &mut eqn_constraints)
+                    }
+                    Some(Ast::ApplyToAll(_, ast)) => {
+                        self.gen_constraints(ast, prefix, &mut eqn_constraints)
+                    }
                     Some(Ast::Arrayed(_, _asts)) => {
                         // todo!();
                         Ok(Units::Constant)
@@ -355,6 +363,11 @@ impl<'a> UnitInferer<'a> {
                     }
                 }
                 .unwrap();
+                constraints.extend(
+                    eqn_constraints
+                        .into_iter()
+                        .map(|c| c.push_ctx(format!("computed-mv@{}{}", prefix, id))),
+                );
                 match var_units {
                     Units::Constant => {
                         // TODO: constant means ~ unconstrained I think
@@ -610,7 +623,7 @@ pub(crate) fn infer(
     units_ctx: &Context,
     model: &ModelStage1,
 ) -> Result<HashMap<String, UnitMap>> {
-    let time_units = canonicalize(units_ctx.sim_specs.time_units.as_deref().unwrap_or("time"));
+    let time_units = units_ctx.time_units();
 
     let units = UnitInferer {
         ctx: units_ctx,
@@ -620,7 +633,7 @@ pub(crate) fn infer(
             ast: None,
             init_ast: None,
             eqn: None,
-            units: Some([(time_units, 1)].iter().cloned().collect()),
+            units: Some(time_units),
             table: None,
             non_negative: false,
             is_flow: false,
@@ -632,3 +645,259 @@ pub(crate) fn infer(
 
     units.infer(model)
 }
+
+/// InferenceStep is one of the equations or definitions that led unit
+/// inference to a conclusion.  Variables in modules are named by their
+/// path, like `smooth·output`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum InferenceStep {
+    /// the units declared on a variable
+    Declared(Ident),
+    /// a variable's equation
+    Equation(Ident),
+    /// a stock, whose flows have its units per unit of time
+    Stock(Ident),
+    /// a flow, which has its stock's units per unit of time
+    Flow(Ident),
+    /// a module input, which has the units of the variable connected to it
+    ModuleInput { src: Ident, dst: Ident },
+}
+
+impl InferenceStep {
+    fn parse(ctx: &str) -> Option<Self> {
+        let (kind, rest) = ctx.split_once('@')?;
+        let step = match kind {
+            "userdef-mv" => InferenceStep::Declared(rest.to_owned()),
+            "computed-mv" => InferenceStep::Equation(rest.to_owned()),
+            "stock" => InferenceStep::Stock(rest.to_owned()),
+            "stock-flow" => InferenceStep::Flow(rest.to_owned()),
+            "module-input" => {
+                let (src, dst) = rest.split_once('@')?;
+                InferenceStep::ModuleInput {
+                    src: src.to_owned(),
+                    dst: dst.to_owned(),
+                }
+            }
+            _ => return None,
+        };
+        Some(step)
+    }
+
+    fn mentions(&self, ident: &str) -> bool {
+        match self {
+            InferenceStep::Declared(id)
+            | InferenceStep::Equation(id)
+            | InferenceStep::Stock(id)
+            | InferenceStep::Flow(id) => id == ident,
+            InferenceStep::ModuleInput { src, dst } => src == ident || dst == ident,
+        }
+    }
+}
+
+impl std::fmt::Display for InferenceStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let path = |id: &str| id.replace('·', ".");
+        match self {
+            InferenceStep::Declared(id) => write!(f, "the declared units of {}", path(id)),
+            InferenceStep::Equation(id) => write!(f, "the equation of {}", path(id)),
+            InferenceStep::Stock(id) => write!(f, "flows of {} are its units per time", path(id)),
+            InferenceStep::Flow(id) => {
+                write!(f, "{} is a flow, in its stock's units per time", path(id))
+            }
+            InferenceStep::ModuleInput { src, dst } => {
+                write!(
+                    f,
+                    "{} is connected to module input {}",
+                    path(src),
+                    path(dst)
+                )
+            }
+        }
+    }
+}
+
+// the steps recorded on a constraint, without repeats
+fn inference_chain(units: &UnitMap) -> Vec<InferenceStep> {
+    let mut chain: Vec<InferenceStep> = vec![];
+    for step in units
+        .ctx
+        .iter()
+        .flatten()
+        .filter_map(|ctx| InferenceStep::parse(ctx))
+    {
+        if !chain.contains(&step) {
+            chain.push(step);
+        }
+    }
+    chain
+}
+
+/// UnitMismatch is a constraint between units that inference couldn't
+/// satisfy: the units reached along one chain of equations differ from
+/// those reached along another by `residual` (which would be 1 if they
+/// matched).
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct UnitMismatch {
+    pub residual: UnitMap,
+    pub chain: Vec<InferenceStep>,
+}
+
+/// UnitExplanation is how unit inference arrived at a variable's units.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct UnitExplanation {
+    pub declared: Option<UnitMap>,
+    pub inferred: Option<UnitMap>,
+    /// the steps that determined the inferred units
+    pub chain: Vec<InferenceStep>,
+    /// the unsatisfied constraints that involve the variable
+    pub mismatches: Vec<UnitMismatch>,
+}
+
+impl Project {
+    /// explain_all_units returns the declared and inferred units of every
+    /// variable in the model `model_name`, and how they were inferred.
+    pub fn explain_all_units(&self, model_name: &str) -> Result<BTreeMap<Ident, UnitExplanation>> {
+        let model = match self.models.get(model_name) {
+            Some(model) => model.as_ref(),
+            None => return model_err!(BadModelName, model_name.to_owned()),
+        };
+        let models: HashMap<Ident, &ModelStage1> = self
+            .models
+            .iter()
+            .map(|(name, model)| (name.clone(), model.as_ref()))
+            .collect();
+        let units_ctx =
+            Context::new_with_builtins(&self.datamodel.units, &self.datamodel.sim_specs)
+                .unwrap_or_default();
+        let time_units = units_ctx.time_units();
+        let inferer = UnitInferer {
+            ctx: &units_ctx,
+            models: &models,
+            time: Variable::Var {
+                ident: "time".to_string(),
+                ast: None,
+                init_ast: None,
+                eqn: None,
+                units: Some(time_units),
+                table: None,
+                non_negative: false,
+                is_flow: false,
+                is_table_only: false,
+                errors: vec![],
+                unit_errors: vec![],
+            },
+        };
+
+        let mut constraints = vec![];
+        inferer.gen_all_constraints(model, "", &mut constraints);
+        let (resolved, unresolved) = inferer.unify(constraints)?;
+        let unresolved: Vec<UnitMismatch> = unresolved
+            .unwrap_or_default()
+            .into_iter()
+            .map(|residual| UnitMismatch {
+                chain: inference_chain(&residual),
+                residual: UnitMap {
+                    map: residual.map,
+                    ctx: None,
+                },
+            })
+            .collect();
+
+        Ok(model
+            .variables
+            .iter()
+            .map(|(ident, var)| {
+                let inferred = resolved.get(ident);
+                let explanation = UnitExplanation {
+                    declared: var.units().cloned(),
+                    inferred: inferred.map(|units| UnitMap {
+                        map: units.map.clone(),
+                        ctx: None,
+                    }),
+                    chain: inferred.map(inference_chain).unwrap_or_default(),
+                    mismatches: unresolved
+                        .iter()
+                        .filter(|m| m.chain.iter().any(|step| step.mentions(ident)))
+                        .cloned()
+                        .collect(),
+                };
+                (ident.clone(), explanation)
+            })
+            .collect())
+    }
+
+    /// explain_units returns how unit inference arrived at the units of
+    /// the variable `ident` in the model `model_name`, to show why the
+    /// checker found a mismatch.
+    pub fn explain_units(&self, model_name: &str, ident: &str) -> Result<UnitExplanation> {
+        let ident = canonicalize(ident);
+        match self.explain_all_units(model_name)?.remove(&ident) {
+            Some(explanation) => Ok(explanation),
+            None => model_err!(DoesNotExist, ident),
+        }
+    }
+}
+
+#[test]
+fn test_explain_units() {
+    let project = |variables| {
+        Project::from(x_project(
+            sim_specs_with_units("year"),
+            &[x_model("main", variables)],
+        ))
+    };
+
+    let growth = project(vec![
+        x_stock("population", "100", &[], &[], Some("person")),
+        x_aux("growth", "population * birth_rate", None),
+        x_aux("birth_rate", "0.1", Some("1/year")),
+    ]);
+    let growth = growth.explain_units("main", "growth").unwrap();
+    assert_eq!(None, growth.declared);
+    assert_eq!(
+        "person/year",
+        format!("{}", growth.inferred.as_ref().unwrap())
+    );
+    // the declared units are substituted in whichever order
+    assert_eq!(3, growth.chain.len());
+    assert_eq!(
+        InferenceStep::Equation("growth".to_owned()),
+        growth.chain[0]
+    );
+    assert!(growth
+        .chain
+        .contains(&InferenceStep::Declared("population".to_owned())));
+    assert!(growth
+        .chain
+        .contains(&InferenceStep::Declared("birth_rate".to_owned())));
+    assert!(growth.mismatches.is_empty());
+
+    // adding people and meters can't be satisfied, and the mismatch is
+    // reported against every variable involved
+    let mismatched = project(vec![
+        x_aux("population", "100", Some("person")),
+        x_aux("distance", "3", Some("meter")),
+        x_aux("total", "population + distance", None),
+    ]);
+    let distance = mismatched.explain_units("main", "distance").unwrap();
+    assert_eq!("meter", format!("{}", distance.declared.unwrap()));
+    assert_eq!(1, distance.mismatches.len());
+    let mismatch = &distance.mismatches[0];
+    for step in [
+        InferenceStep::Equation("total".to_owned()),
+        InferenceStep::Declared("population".to_owned()),
+        InferenceStep::Declared("distance".to_owned()),
+    ] {
+        assert!(mismatch.chain.contains(&step), "{}", step);
+    }
+    assert_eq!(
+        mismatch,
+        &mismatched
+            .explain_units("main", "population")
+            .unwrap()
+            .mismatches[0]
+    );
+
+    assert!(mismatched.explain_units("main", "nope").is_err());
+    assert!(mismatched.explain_units("nope", "total").is_err());
+}
@@ -43,6 +43,7 @@ pub enum Variable<MI = ModuleInput, E = Expr> {
         inflows: Vec<Ident>,
         outflows: Vec<Ident>,
         non_negative: bool,
+        force_euler: bool,
         errors: Vec<EquationError>,
         unit_errors: Vec<UnitError>,
     },
@@ -243,6 +244,12 @@ fn get_dimensions(
         .map(|name| -> Result<datamodel::Dimension, EquationError> {
             for dim in dimensions {
                 if dim.name() == name {
+                    // indexed dimensions are compiled as if their
+                    // elements were named 1 through their size
+                    if let Dimension::Indexed(name, size) = dim {
+                        let elements = (1..=*size).map(|i| i.to_string()).collect();
+                        return Ok(Dimension::Named(name.clone(), elements));
+                    }
                     return Ok(dim.clone());
                 }
             }
@@ -384,6 +391,7 @@ where
                 inflows: v.inflows.clone(),
                 outflows: v.outflows.clone(),
                 non_negative: v.non_negative,
+                force_euler: v.force_euler,
                 errors,
                 unit_errors,
             }
@@ -632,7 +640,7 @@ fn test_identifier_sets() {
             models: &Default::default(),
             dimensions: &Default::default(),
         };
-        let ast = lower_ast(&scope, ast.unwrap()).unwrap();
+        let ast = lower_ast(&scope, "main", ast.unwrap()).unwrap();
         let id_set_expected: HashSet<Ident> = id_list.iter().map(|s| s.to_string()).collect();
         let module_input_names = module_inputs.iter().map(|mi| mi.dst.clone()).collect();
         let id_set_test = identifier_set(&ast, dimensions, Some(&module_input_names));
@@ -662,6 +670,7 @@ fn test_tables() {
             y_points: vec![0.0, 0.0, 1.0, 1.0, 0.0, 0.0, -1.0, -1.0, 0.0, 0.0],
         }),
         can_be_module_input: false,
+        supplementary: false,
         visibility: datamodel::Visibility::Private,
     });
 
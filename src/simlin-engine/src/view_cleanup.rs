@@ -0,0 +1,533 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use crate::common::{canonicalize, Ident};
+use crate::datamodel::view_element::{self, LinkShape};
+use crate::datamodel::{Model, Rect, StockFlow, Variable, View, ViewElement};
+use crate::geometry::bounds;
+use crate::templates::{lay_out, references, SPACING};
+
+// space left between elements that were nudged apart
+const PADDING: f64 = 5.0;
+// nudging an element can create a new overlap; give up after this many
+// passes over the view
+const MAX_NUDGE_PASSES: usize = 10;
+
+/// ViewIssue is a problem with how a model is displayed.  `view` is an
+/// index into the model's views.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ViewIssue {
+    /// the element refers to a variable, or to another element, that
+    /// doesn't exist
+    Orphan { view: usize, uid: i32 },
+    /// the variable isn't displayed in any of the model's views
+    NotDisplayed { ident: Ident },
+    /// the shapes of the two elements overlap
+    Overlap { view: usize, uids: (i32, i32) },
+    /// the link connects two variables, but the equation at its end
+    /// doesn't refer to the variable at its start
+    UnusedLink { view: usize, uid: i32 },
+}
+
+fn is_orphan(
+    element: &ViewElement,
+    idents: &HashSet<Ident>,
+    elements: &HashMap<i32, &ViewElement>,
+) -> bool {
+    let is_named = |uid: &i32| matches!(elements.get(uid), Some(e) if e.get_name().is_some());
+    match element {
+        ViewElement::Link(link) => {
+            !elements.contains_key(&link.from_uid) || !elements.contains_key(&link.to_uid)
+        }
+        ViewElement::Alias(alias) => !is_named(&alias.alias_of_uid),
+        ViewElement::Cloud(cloud) => {
+            !matches!(elements.get(&cloud.flow_uid), Some(ViewElement::Flow(_)))
+        }
+        _ => match element.get_name() {
+            Some(name) => !idents.contains(&canonicalize(name)),
+            None => false,
+        },
+    }
+}
+
+fn orphans(view: &StockFlow, idents: &HashSet<Ident>) -> Vec<i32> {
+    let elements: HashMap<i32, &ViewElement> =
+        view.elements.iter().map(|e| (e.get_uid(), e)).collect();
+    view.elements
+        .iter()
+        .filter(|e| is_orphan(e, idents, &elements))
+        .map(|e| e.get_uid())
+        .collect()
+}
+
+// the variable an element stands for: its own, or an alias's original
+fn element_ident(element: &ViewElement, elements: &HashMap<i32, &ViewElement>) -> Option<Ident> {
+    match element {
+        ViewElement::Alias(alias) => elements
+            .get(&alias.alias_of_uid)?
+            .get_name()
+            .map(canonicalize),
+        _ => element.get_name().map(canonicalize),
+    }
+}
+
+// the (from, to) variables each link connects
+fn linked_idents(view: &StockFlow) -> Vec<(i32, Ident, Ident)> {
+    let elements: HashMap<i32, &ViewElement> =
+        view.elements.iter().map(|e| (e.get_uid(), e)).collect();
+    view.elements
+        .iter()
+        .filter_map(|e| match e {
+            ViewElement::Link(link) => {
+                let from = element_ident(elements.get(&link.from_uid)?, &elements)?;
+                let to = element_ident(elements.get(&link.to_uid)?, &elements)?;
+                Some((link.uid, from, to))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn unused_links(
+    view: &StockFlow,
+    idents: &HashSet<Ident>,
+    deps: &BTreeMap<Ident, BTreeSet<Ident>>,
+) -> Vec<i32> {
+    linked_idents(view)
+        .into_iter()
+        .filter(|(_, from, _)| idents.contains(from))
+        // links into modules (and deleted variables) aren't checked
+        .filter(|(_, from, to)| matches!(deps.get(to), Some(deps) if !deps.contains(from)))
+        .map(|(uid, _, _)| uid)
+        .collect()
+}
+
+fn overlap(a: &Rect, b: &Rect) -> Option<(f64, f64)> {
+    let width = (a.x + a.width).min(b.x + b.width) - a.x.max(b.x);
+    let height = (a.y + a.height).min(b.y + b.height) - a.y.max(b.y);
+    if width > 0.0 && height > 0.0 {
+        Some((width, height))
+    } else {
+        None
+    }
+}
+
+fn overlaps(view: &StockFlow) -> Vec<(usize, usize)> {
+    let rects: Vec<(usize, Rect)> = view
+        .elements
+        .iter()
+        .enumerate()
+        .filter_map(|(i, e)| bounds(e).map(|r| (i, r)))
+        .collect();
+    let mut pairs = vec![];
+    for (n, (i, a)) in rects.iter().enumerate() {
+        for (j, b) in rects[n + 1..].iter() {
+            if overlap(a, b).is_some() {
+                pairs.push((*i, *j));
+            }
+        }
+    }
+    pairs
+}
+
+// only elements that nothing is attached to by position are moved:
+// moving a stock or cloud would detach its flows, and a flow's valve has
+// to stay on its pipe.
+fn nudge(element: &mut ViewElement, dx: f64) -> bool {
+    match element {
+        ViewElement::Aux(aux) => aux.x += dx,
+        ViewElement::Module(module) => module.x += dx,
+        ViewElement::Alias(alias) => alias.x += dx,
+        _ => return false,
+    }
+    true
+}
+
+fn nudge_overlaps(view: &mut StockFlow) {
+    for _ in 0..MAX_NUDGE_PASSES {
+        let mut moved = false;
+        for (i, j) in overlaps(view) {
+            // an earlier nudge this pass may have already separated them
+            let (a, b) = match (bounds(&view.elements[i]), bounds(&view.elements[j])) {
+                (Some(a), Some(b)) => (a, b),
+                _ => continue,
+            };
+            let width = match overlap(&a, &b) {
+                Some((width, _)) => width,
+                None => continue,
+            };
+            // push the later element away from the earlier one, falling
+            // back to moving the earlier one if the later is fixed
+            let dx = if b.x >= a.x {
+                width + PADDING
+            } else {
+                -(width + PADDING)
+            };
+            moved |= nudge(&mut view.elements[j], dx) || nudge(&mut view.elements[i], -dx);
+        }
+        if !moved {
+            break;
+        }
+    }
+}
+
+impl Model {
+    fn variable_idents(&self) -> HashSet<Ident> {
+        self.variables
+            .iter()
+            .map(|v| canonicalize(v.get_ident()))
+            .collect()
+    }
+
+    // the variables each stock, flow and auxiliary's equations refer to.
+    // A reference to a module's output is a dependency on the module.
+    fn dependencies(&self) -> BTreeMap<Ident, BTreeSet<Ident>> {
+        self.variables
+            .iter()
+            .filter(|var| !matches!(var, Variable::Module(_)))
+            .map(|var| {
+                let ident = canonicalize(var.get_ident());
+                let deps = var
+                    .get_equation()
+                    .map(references)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|id| match id.split_once('·') {
+                        Some((module, _)) => module.to_owned(),
+                        None => id,
+                    })
+                    .filter(|id| *id != ident)
+                    .collect();
+                (ident, deps)
+            })
+            .collect()
+    }
+
+    /// analyze_views reports elements in the model's views that don't
+    /// correspond to anything, variables that aren't displayed, elements
+    /// drawn on top of each other, and links that no equation uses.
+    pub fn analyze_views(&self) -> Vec<ViewIssue> {
+        let idents = self.variable_idents();
+        let deps = self.dependencies();
+        let mut issues = vec![];
+        let mut displayed = HashSet::new();
+        for (n, View::StockFlow(view)) in self.views.iter().enumerate() {
+            displayed.extend(
+                view.elements
+                    .iter()
+                    .filter_map(|e| e.get_name())
+                    .map(canonicalize),
+            );
+            issues.extend(
+                orphans(view, &idents)
+                    .into_iter()
+                    .map(|uid| ViewIssue::Orphan { view: n, uid }),
+            );
+            issues.extend(overlaps(view).into_iter().map(|(i, j)| ViewIssue::Overlap {
+                view: n,
+                uids: (view.elements[i].get_uid(), view.elements[j].get_uid()),
+            }));
+            issues.extend(
+                unused_links(view, &idents, &deps)
+                    .into_iter()
+                    .map(|uid| ViewIssue::UnusedLink { view: n, uid }),
+            );
+        }
+        issues.extend(
+            self.variables
+                .iter()
+                .map(|v| canonicalize(v.get_ident()))
+                .filter(|ident| !displayed.contains(ident))
+                .map(|ident| ViewIssue::NotDisplayed { ident }),
+        );
+        issues
+    }
+
+    /// fix_views removes orphaned elements (along with any links, aliases
+    /// and clouds that refer to them) and moves auxiliaries, aliases and
+    /// modules so they no longer overlap other elements.  Variables that
+    /// aren't displayed are left alone.
+    pub fn fix_views(&mut self) {
+        let idents = self.variable_idents();
+        for View::StockFlow(view) in self.views.iter_mut() {
+            loop {
+                let orphans: HashSet<i32> = orphans(view, &idents).into_iter().collect();
+                if orphans.is_empty() {
+                    break;
+                }
+                view.elements.retain(|e| !orphans.contains(&e.get_uid()));
+            }
+            nudge_overlaps(view);
+        }
+    }
+
+    /// sync_views brings the model's views up to date after its
+    /// variables were edited directly.  Variables that aren't displayed
+    /// are added to the first view (which is created if the model has
+    /// none), below what is already there, and links are added for every
+    /// dependency of a flow or auxiliary between variables displayed in
+    /// the same view.  New elements get uids that aren't in use.  Nothing
+    /// is removed: the remaining issues, like elements for deleted
+    /// variables, are returned for the caller to fix or show.
+    pub fn sync_views(&mut self) -> Vec<ViewIssue> {
+        let displayed: HashSet<Ident> = self
+            .views
+            .iter()
+            .flat_map(|View::StockFlow(view)| view.elements.iter())
+            .filter_map(|e| e.get_name())
+            .map(canonicalize)
+            .collect();
+        let missing: Vec<Variable> = self
+            .variables
+            .iter()
+            .filter(|var| !displayed.contains(&canonicalize(var.get_ident())))
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            if self.views.is_empty() {
+                self.views.push(View::StockFlow(StockFlow {
+                    elements: vec![],
+                    view_box: Rect::default(),
+                    zoom: 1.0,
+                }));
+            }
+            let View::StockFlow(view) = &mut self.views[0];
+            let rects: Vec<Rect> = view.elements.iter().filter_map(bounds).collect();
+            let x = rects.iter().map(|r| r.x).fold(f64::INFINITY, f64::min);
+            let y = rects
+                .iter()
+                .map(|r| r.y + r.height)
+                .fold(f64::NEG_INFINITY, f64::max);
+            let (x, y) = if rects.is_empty() {
+                (0.0, SPACING)
+            } else {
+                (x, y + SPACING)
+            };
+            lay_out(view, &missing, x, y);
+        }
+
+        let deps = self.dependencies();
+        let is_stock: HashSet<Ident> = self
+            .variables
+            .iter()
+            .filter(|var| matches!(var, Variable::Stock(_)))
+            .map(|var| canonicalize(var.get_ident()))
+            .collect();
+        for View::StockFlow(view) in self.views.iter_mut() {
+            let mut uids: HashMap<Ident, i32> = HashMap::new();
+            for element in view.elements.iter() {
+                if let Some(name) = element.get_name() {
+                    uids.entry(canonicalize(name)).or_insert(element.get_uid());
+                }
+            }
+            let linked: HashSet<(Ident, Ident)> = linked_idents(view)
+                .into_iter()
+                .map(|(_, from, to)| (from, to))
+                .collect();
+            let mut next_uid = view.next_uid();
+            let mut links = vec![];
+            for (to, froms) in deps.iter() {
+                // stocks only depend on their flows (and their initial
+                // values), which aren't drawn as links
+                if is_stock.contains(to) {
+                    continue;
+                }
+                let to_uid = match uids.get(to) {
+                    Some(uid) => *uid,
+                    None => continue,
+                };
+                for from in froms.iter() {
+                    let from_uid = match uids.get(from) {
+                        Some(uid) => *uid,
+                        None => continue,
+                    };
+                    if linked.contains(&(from.clone(), to.clone())) {
+                        continue;
+                    }
+                    links.push(ViewElement::Link(view_element::Link {
+                        uid: next_uid,
+                        from_uid,
+                        to_uid,
+                        shape: LinkShape::Straight,
+                        polarity: None,
+                        delay_mark: false,
+                    }));
+                    next_uid += 1;
+                }
+            }
+            view.elements.extend(links);
+        }
+
+        self.analyze_views()
+    }
+}
+
+#[test]
+fn test_view_cleanup() {
+    use crate::datamodel::view_element::{self, LabelSide, LinkShape};
+    use crate::testutils::{x_aux, x_model};
+
+    let aux = |name: &str, uid: i32, x: f64, y: f64| {
+        ViewElement::Aux(view_element::Aux {
+            name: name.to_owned(),
+            uid,
+            x,
+            y,
+            label_side: LabelSide::Bottom,
+        })
+    };
+    let link = |uid: i32, from_uid: i32, to_uid: i32| {
+        ViewElement::Link(view_element::Link {
+            uid,
+            from_uid,
+            to_uid,
+            shape: LinkShape::Straight,
+            polarity: None,
+            delay_mark: false,
+        })
+    };
+
+    let mut model = x_model(
+        "main",
+        vec![
+            x_aux("a", "1", None),
+            x_aux("b", "a", None),
+            x_aux("c", "a", None),
+            x_aux("d", "3", None),
+        ],
+    );
+    model.views.push(View::StockFlow(StockFlow {
+        elements: vec![
+            aux("a", 1, 100.0, 100.0),
+            aux("B", 2, 200.0, 100.0),
+            aux("c", 3, 205.0, 105.0),
+            aux("deleted", 4, 300.0, 100.0),
+            link(5, 1, 2),
+            link(6, 1, 4),
+            link(7, 1, 42),
+            ViewElement::Alias(view_element::Alias {
+                uid: 8,
+                alias_of_uid: 4,
+                x: 400.0,
+                y: 100.0,
+                label_side: LabelSide::Bottom,
+            }),
+        ],
+        view_box: Default::default(),
+        zoom: 1.0,
+    }));
+
+    assert_eq!(
+        vec![
+            ViewIssue::Orphan { view: 0, uid: 4 },
+            ViewIssue::Orphan { view: 0, uid: 7 },
+            ViewIssue::Overlap {
+                view: 0,
+                uids: (2, 3)
+            },
+            ViewIssue::NotDisplayed {
+                ident: "d".to_owned()
+            },
+        ],
+        model.analyze_views()
+    );
+
+    model.fix_views();
+
+    assert_eq!(
+        vec![ViewIssue::NotDisplayed {
+            ident: "d".to_owned()
+        }],
+        model.analyze_views()
+    );
+    let View::StockFlow(view) = &model.views[0];
+    let uids: Vec<i32> = view.elements.iter().map(|e| e.get_uid()).collect();
+    assert_eq!(vec![1, 2, 3, 5], uids);
+    match &view.elements[2] {
+        ViewElement::Aux(aux) => assert_eq!(223.0, aux.x),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn test_sync_views() {
+    use crate::datamodel::view_element::LabelSide;
+    use crate::testutils::{x_aux, x_flow, x_model, x_stock};
+
+    let mut model = x_model(
+        "main",
+        vec![
+            x_stock("population", "100", &["births"], &[], None),
+            x_flow("births", "population * birth_rate", None),
+            x_aux("birth_rate", "0.1", None),
+        ],
+    );
+    model.views.push(View::StockFlow(StockFlow {
+        elements: vec![
+            ViewElement::Stock(view_element::Stock {
+                name: "population".to_owned(),
+                uid: 10,
+                x: 100.0,
+                y: 100.0,
+                label_side: LabelSide::Bottom,
+            }),
+            ViewElement::Aux(view_element::Aux {
+                name: "deleted".to_owned(),
+                uid: 11,
+                x: 300.0,
+                y: 100.0,
+                label_side: LabelSide::Bottom,
+            }),
+            ViewElement::Link(view_element::Link {
+                uid: 12,
+                from_uid: 10,
+                to_uid: 11,
+                shape: LinkShape::Straight,
+                polarity: None,
+                delay_mark: false,
+            }),
+        ],
+        view_box: Default::default(),
+        zoom: 1.0,
+    }));
+
+    let issues = model.sync_views();
+    assert_eq!(vec![ViewIssue::Orphan { view: 0, uid: 11 }], issues);
+
+    let View::StockFlow(view) = &model.views[0];
+    // nothing was removed, and the new elements have new uids
+    assert!(view.elements.iter().any(|e| e.get_uid() == 11));
+    let mut uids: Vec<i32> = view.elements.iter().map(|e| e.get_uid()).collect();
+    uids.sort_unstable();
+    uids.dedup();
+    assert_eq!(view.elements.len(), uids.len());
+    assert!(uids.iter().all(|uid| *uid >= 10));
+
+    let mut links = linked_idents(view)
+        .into_iter()
+        .map(|(_, from, to)| format!("{} -> {}", from, to))
+        .collect::<Vec<_>>();
+    links.sort_unstable();
+    assert_eq!(
+        vec![
+            "birth_rate -> births",
+            "population -> births",
+            "population -> deleted"
+        ],
+        links
+    );
+
+    // syncing again changes nothing
+    let before = model.views.clone();
+    assert_eq!(issues, model.sync_views());
+    assert_eq!(before, model.views);
+
+    // links the equations don't use are flagged
+    model.variables[1] = x_flow("births", "birth_rate * 100", None);
+    let issues = model.analyze_views();
+    assert_eq!(2, issues.len());
+    assert!(matches!(issues[1], ViewIssue::UnusedLink { view: 0, .. }));
+}
@@ -0,0 +1,211 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+use std::collections::{HashMap, HashSet};
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::datamodel::view_element::{FlowPoint, LabelSide, LinkShape};
+use crate::datamodel::{StockFlow, ViewElement};
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ViewChangeKind {
+    Added,
+    Removed,
+    /// the element's position, flow points or link shape changed
+    Moved,
+    /// the element's name or label placement changed
+    Relabeled,
+    /// anything else about the element changed, like a link's polarity
+    Changed,
+}
+
+/// ViewChange is a change to the element `uid`.  An element can be both
+/// moved and relabeled, in which case there is a change for each.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ViewChange {
+    pub uid: i32,
+    pub kind: ViewChangeKind,
+}
+
+#[derive(PartialEq)]
+enum Position<'a> {
+    Point(f64, f64),
+    Flow(f64, f64, &'a [FlowPoint]),
+    Link(&'a LinkShape),
+}
+
+fn position(element: &ViewElement) -> Position<'_> {
+    match element {
+        ViewElement::Aux(e) => Position::Point(e.x, e.y),
+        ViewElement::Stock(e) => Position::Point(e.x, e.y),
+        ViewElement::Flow(e) => Position::Flow(e.x, e.y, &e.points),
+        ViewElement::Link(e) => Position::Link(&e.shape),
+        ViewElement::Module(e) => Position::Point(e.x, e.y),
+        ViewElement::Alias(e) => Position::Point(e.x, e.y),
+        ViewElement::Cloud(e) => Position::Point(e.x, e.y),
+    }
+}
+
+fn label(element: &ViewElement) -> Option<(&str, LabelSide)> {
+    match element {
+        ViewElement::Aux(e) => Some((&e.name, e.label_side)),
+        ViewElement::Stock(e) => Some((&e.name, e.label_side)),
+        ViewElement::Flow(e) => Some((&e.name, e.label_side)),
+        ViewElement::Link(_) => None,
+        ViewElement::Module(e) => Some((&e.name, e.label_side)),
+        ViewElement::Alias(e) => Some(("", e.label_side)),
+        ViewElement::Cloud(_) => None,
+    }
+}
+
+// strip returns a copy of the element with its position and label reset,
+// so that what remains can be compared.
+fn strip(element: &ViewElement) -> ViewElement {
+    let mut element = element.clone();
+    match &mut element {
+        ViewElement::Aux(e) => {
+            (e.x, e.y, e.name, e.label_side) = (0.0, 0.0, String::new(), LabelSide::Center)
+        }
+        ViewElement::Stock(e) => {
+            (e.x, e.y, e.name, e.label_side) = (0.0, 0.0, String::new(), LabelSide::Center)
+        }
+        ViewElement::Flow(e) => {
+            (e.x, e.y, e.name, e.label_side) = (0.0, 0.0, String::new(), LabelSide::Center);
+            e.points.clear();
+        }
+        ViewElement::Link(e) => e.shape = LinkShape::Straight,
+        ViewElement::Module(e) => {
+            (e.x, e.y, e.name, e.label_side) = (0.0, 0.0, String::new(), LabelSide::Center)
+        }
+        ViewElement::Alias(e) => (e.x, e.y, e.label_side) = (0.0, 0.0, LabelSide::Center),
+        ViewElement::Cloud(e) => (e.x, e.y) = (0.0, 0.0),
+    }
+    element
+}
+
+fn is_same_kind(a: &ViewElement, b: &ViewElement) -> bool {
+    std::mem::discriminant(a) == std::mem::discriminant(b)
+}
+
+/// diff_views returns the element-level changes that turn `old` into
+/// `new`, matching elements by uid.  Removals come first, followed by
+/// additions and modifications in the order of `new`'s elements.  An
+/// element whose uid is reused for a different kind of element is
+/// reported as removed and added.
+pub fn diff_views(old: &StockFlow, new: &StockFlow) -> Vec<ViewChange> {
+    let old_elements: HashMap<i32, &ViewElement> =
+        old.elements.iter().map(|e| (e.get_uid(), e)).collect();
+    let new_elements: HashMap<i32, &ViewElement> =
+        new.elements.iter().map(|e| (e.get_uid(), e)).collect();
+
+    let change = |uid: i32, kind: ViewChangeKind| ViewChange { uid, kind };
+    let mut changes = vec![];
+    let mut replaced = HashSet::new();
+    for element in old.elements.iter() {
+        let uid = element.get_uid();
+        match new_elements.get(&uid) {
+            Some(new) if is_same_kind(element, new) => {}
+            Some(_) => {
+                replaced.insert(uid);
+                changes.push(change(uid, ViewChangeKind::Removed));
+            }
+            None => changes.push(change(uid, ViewChangeKind::Removed)),
+        }
+    }
+
+    for element in new.elements.iter() {
+        let uid = element.get_uid();
+        let prev = match old_elements.get(&uid) {
+            Some(prev) if !replaced.contains(&uid) => prev,
+            _ => {
+                changes.push(change(uid, ViewChangeKind::Added));
+                continue;
+            }
+        };
+        if position(prev) != position(element) {
+            changes.push(change(uid, ViewChangeKind::Moved));
+        }
+        if label(prev) != label(element) {
+            changes.push(change(uid, ViewChangeKind::Relabeled));
+        }
+        if strip(prev) != strip(element) {
+            changes.push(change(uid, ViewChangeKind::Changed));
+        }
+    }
+
+    changes
+}
+
+#[test]
+fn test_diff_views() {
+    use crate::datamodel::view_element::{self, LinkPolarity};
+
+    let aux = |name: &str, uid: i32, x: f64| {
+        ViewElement::Aux(view_element::Aux {
+            name: name.to_owned(),
+            uid,
+            x,
+            y: 100.0,
+            label_side: LabelSide::Bottom,
+        })
+    };
+    let link = |uid: i32, shape: LinkShape, polarity: Option<LinkPolarity>| {
+        ViewElement::Link(view_element::Link {
+            uid,
+            from_uid: 1,
+            to_uid: 2,
+            shape,
+            polarity,
+            delay_mark: false,
+        })
+    };
+    let view = |elements: Vec<ViewElement>| StockFlow {
+        elements,
+        view_box: Default::default(),
+        zoom: 1.0,
+    };
+
+    let old = view(vec![
+        aux("a", 1, 100.0),
+        aux("b", 2, 200.0),
+        link(3, LinkShape::Straight, None),
+        aux("c", 4, 300.0),
+        aux("d", 5, 400.0),
+        aux("e", 6, 500.0),
+    ]);
+    assert!(diff_views(&old, &old).is_empty());
+
+    let new = view(vec![
+        aux("a", 1, 100.0),
+        aux("b", 2, 250.0),
+        link(3, LinkShape::Arc(45.0), Some(LinkPolarity::Positive)),
+        aux("c2", 4, 350.0),
+        ViewElement::Cloud(view_element::Cloud {
+            uid: 5,
+            flow_uid: 9,
+            x: 400.0,
+            y: 100.0,
+        }),
+        aux("f", 7, 600.0),
+    ]);
+    let change = |uid: i32, kind: ViewChangeKind| ViewChange { uid, kind };
+    assert_eq!(
+        vec![
+            change(5, ViewChangeKind::Removed),
+            change(6, ViewChangeKind::Removed),
+            change(2, ViewChangeKind::Moved),
+            change(3, ViewChangeKind::Moved),
+            change(3, ViewChangeKind::Changed),
+            change(4, ViewChangeKind::Moved),
+            change(4, ViewChangeKind::Relabeled),
+            change(5, ViewChangeKind::Added),
+            change(7, ViewChangeKind::Added),
+        ],
+        diff_views(&old, &new)
+    );
+}
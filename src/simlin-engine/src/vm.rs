@@ -4,16 +4,20 @@
 
 use std::borrow::BorrowMut;
 use std::collections::HashMap;
+use std::io::{self, Write};
 use std::rc::Rc;
 
 use float_cmp::approx_eq;
 use smallvec::SmallVec;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
 
 use crate::bytecode::{
-    BuiltinId, ByteCode, ByteCodeContext, CompiledModule, ModuleId, Op2, Opcode,
+    BuiltinId, ByteCode, ByteCodeContext, CompiledModule, ModuleId, Op2, Opcode, ParallelBlock,
 };
-use crate::common::{Ident, Result};
-use crate::datamodel::{Dimension, Dt, SimMethod, SimSpecs};
+use crate::common::{canonicalize, quoteize, Ident, Result};
+use crate::datamodel::{Dimension, Dt, Graph, SimMethod, SimSpecs, Tolerances};
+use crate::math;
 use crate::sim_err;
 
 pub(crate) const TIME_OFF: usize = 0;
@@ -22,6 +26,10 @@ pub(crate) const INITIAL_TIME_OFF: usize = 2;
 pub(crate) const FINAL_TIME_OFF: usize = 3;
 pub(crate) const IMPLICIT_VAR_COUNT: usize = 4;
 
+// how close (as a fraction of dt) a time has to be to a step to be
+// considered as falling on it
+const STEP_TOLERANCE: f64 = 1e-6;
+
 pub(crate) fn is_truthy(n: f64) -> bool {
     let is_false = approx_eq!(f64, n, 0.0);
     !is_false
@@ -33,6 +41,85 @@ pub struct CompiledSimulation {
     pub(crate) specs: Specs,
     pub(crate) root: String,
     pub(crate) offsets: HashMap<Ident, usize>,
+    pub(crate) stocks: Vec<StockSlot>,
+}
+
+/// Stats describes the size of a compiled simulation, so that it can be
+/// checked before running whether a run is feasible.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Stats {
+    /// number of f64 values in each time step of the simulation state
+    pub n_slots: usize,
+    /// slots holding the hidden state of builtins like DELAY3 and SMTH1
+    pub hidden_slots: usize,
+    /// size of the bytecode and literals of every compiled model
+    pub bytecode_bytes: usize,
+    /// estimated floating point operations to compute one time step
+    pub flops_per_step: usize,
+    /// number of time steps computed in a full run (at the least, for
+    /// Rk45)
+    pub time_steps: usize,
+    /// number of time steps saved in the results
+    pub saved_steps: usize,
+    /// bytes allocated to hold the results
+    pub result_bytes: usize,
+}
+
+impl CompiledSimulation {
+    /// stats reports the size of the compiled simulation and of the
+    /// results it will produce with the current sim specs.
+    pub fn stats(&self) -> Stats {
+        let n_slots = self.modules[&self.root].n_slots;
+        let saved_steps = self.specs.n_chunks();
+        let stages = match self.specs.method {
+            Method::Euler => 1,
+            Method::RungeKutta4 => 1 + RK4_STAGES.len(),
+            // at the least, as Rk45 takes as many steps as it needs
+            Method::Rk45 => 1 + DP_STAGES.len(),
+        };
+        let mut stats = Stats {
+            n_slots,
+            bytecode_bytes: self
+                .modules
+                .values()
+                .map(|module| {
+                    module.compiled_initials.size()
+                        + module.compiled_flows.size()
+                        + module.compiled_stocks.size()
+                })
+                .sum(),
+            time_steps: self.specs.last_step(self.specs.stop),
+            saved_steps,
+            // the VM keeps two extra time steps of scratch space
+            result_bytes: n_slots
+                .saturating_mul(saved_steps + 2)
+                .saturating_mul(std::mem::size_of::<f64>()),
+            ..Default::default()
+        };
+        self.instance_stats(&self.root, false, &mut stats);
+        stats.flops_per_step *= stages;
+        stats
+    }
+
+    // instance_stats adds the hidden slots and per-step flops of an
+    // instance of the model `model_name`, and of its submodules.
+    fn instance_stats(&self, model_name: &str, is_hidden: bool, stats: &mut Stats) {
+        let module = &self.modules[model_name];
+        stats.flops_per_step += module.compiled_flows.flops() + module.compiled_stocks.flops();
+        if module.implicit && !is_hidden {
+            stats.hidden_slots += module.n_slots;
+        }
+        // each submodule is evaluated by each runlist, so dedupe them by
+        // their offset within this module
+        let mut seen: HashMap<usize, &str> = HashMap::new();
+        for decl in module.context.modules.iter() {
+            seen.insert(decl.off, &decl.model_name);
+        }
+        for model_name in seen.values() {
+            self.instance_stats(model_name, is_hidden || module.implicit, stats);
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -52,6 +139,8 @@ pub(crate) enum StepPart {
 #[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
 pub enum Method {
     Euler,
+    RungeKutta4,
+    Rk45,
 }
 
 #[derive(Clone, Debug)]
@@ -61,13 +150,21 @@ pub struct Specs {
     pub dt: f64,
     pub save_step: f64,
     pub method: Method,
+    /// the error allowed in each step taken by Rk45
+    pub tolerances: Tolerances,
+    /// set when dt was specified as a reciprocal, so that times can be
+    /// computed by division rather than multiplication by an inexact dt
+    pub dt_reciprocal: Option<f64>,
+    /// sorted times between time steps that the simulation takes a
+    /// partial step to, so that scheduled changes happen exactly on time
+    pub event_times: Vec<f64>,
 }
 
 impl Specs {
     pub fn from(specs: &SimSpecs) -> Self {
-        let dt: f64 = match &specs.dt {
-            Dt::Dt(value) => *value,
-            Dt::Reciprocal(value) => 1.0 / *value,
+        let (dt, dt_reciprocal) = match &specs.dt {
+            Dt::Dt(value) => (*value, None),
+            Dt::Reciprocal(value) => (1.0 / *value, Some(*value)),
         };
 
         let save_step: f64 = match &specs.save_step {
@@ -80,10 +177,8 @@ impl Specs {
 
         let method = match specs.sim_method {
             SimMethod::Euler => Method::Euler,
-            SimMethod::RungeKutta4 => {
-                eprintln!("warning, simulation requested 'rk4', but only support Euler");
-                Method::Euler
-            }
+            SimMethod::RungeKutta4 => Method::RungeKutta4,
+            SimMethod::Rk45 => Method::Rk45,
         };
 
         Specs {
@@ -92,11 +187,333 @@ impl Specs {
             dt,
             save_step,
             method,
+            tolerances: specs.tolerances,
+            dt_reciprocal,
+            event_times: vec![],
         }
     }
+
+    /// time returns the simulation time after `step` time steps.  Times
+    /// are computed from the step index rather than accumulated, so
+    /// that runs with an inexact dt (like 1/3) don't drift.
+    pub fn time(&self, step: usize) -> f64 {
+        match self.dt_reciprocal {
+            Some(reciprocal) => self.start + step as f64 / reciprocal,
+            None => self.start + step as f64 * self.dt,
+        }
+    }
+
+    /// last_step returns the index of the last time step at or before
+    /// `end`.
+    pub fn last_step(&self, end: f64) -> usize {
+        let steps = match self.dt_reciprocal {
+            Some(reciprocal) => (end - self.start) * reciprocal,
+            None => (end - self.start) / self.dt,
+        };
+        (steps + STEP_TOLERANCE).floor().max(0.0) as usize
+    }
+
+    /// save_every returns the number of time steps between saved steps.
+    pub fn save_every(&self) -> usize {
+        std::cmp::max(1, (self.save_step / self.dt).round() as usize)
+    }
+
+    /// events_between returns the event times strictly between `from`
+    /// and `to`.
+    pub fn events_between(&self, from: f64, to: f64) -> &[f64] {
+        let start = self.event_times.partition_point(|time| *time <= from);
+        let end = self.event_times.partition_point(|time| *time < to);
+        &self.event_times[start..end.max(start)]
+    }
+
+    /// n_chunks returns the number of saved steps in a full run.
+    pub fn n_chunks(&self) -> usize {
+        self.last_step(self.stop) / self.save_every() + 1
+    }
 }
 
-#[derive(Debug)]
+/// StockSlot is the offset of a stock (or an element of an arrayed
+/// stock) in the flattened simulation state, and whether it is always
+/// integrated with Euler's method.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) struct StockSlot {
+    pub(crate) off: usize,
+    pub(crate) force_euler: bool,
+}
+
+// the fraction of the time step each of the later RK4 stages are
+// evaluated at, and the weight of their slopes in the final update
+const RK4_STAGES: [(f64, f64); 3] = [(0.5, 2.0), (0.5, 2.0), (1.0, 1.0)];
+
+// the Dormand-Prince tableau: the fraction of the step each stage after
+// the first is evaluated at, and the weights of the earlier stages'
+// slopes in its state.  The last stage is the 5th order solution.
+const DP_STAGES: [(f64, [f64; 6]); 6] = [
+    (1.0 / 5.0, [1.0 / 5.0, 0.0, 0.0, 0.0, 0.0, 0.0]),
+    (3.0 / 10.0, [3.0 / 40.0, 9.0 / 40.0, 0.0, 0.0, 0.0, 0.0]),
+    (
+        4.0 / 5.0,
+        [44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0, 0.0, 0.0, 0.0],
+    ),
+    (
+        8.0 / 9.0,
+        [
+            19372.0 / 6561.0,
+            -25360.0 / 2187.0,
+            64448.0 / 6561.0,
+            -212.0 / 729.0,
+            0.0,
+            0.0,
+        ],
+    ),
+    (
+        1.0,
+        [
+            9017.0 / 3168.0,
+            -355.0 / 33.0,
+            46732.0 / 5247.0,
+            49.0 / 176.0,
+            -5103.0 / 18656.0,
+            0.0,
+        ],
+    ),
+    (
+        1.0,
+        [
+            35.0 / 384.0,
+            0.0,
+            500.0 / 1113.0,
+            125.0 / 192.0,
+            -2187.0 / 6784.0,
+            11.0 / 84.0,
+        ],
+    ),
+];
+
+// the difference between the 5th and 4th order weights of each stage's
+// slope, which estimates the error of a step
+const DP_ERROR: [f64; 7] = [
+    71.0 / 57600.0,
+    0.0,
+    -71.0 / 16695.0,
+    71.0 / 1920.0,
+    -17253.0 / 339200.0,
+    22.0 / 525.0,
+    -1.0 / 40.0,
+];
+
+/// Integrator advances stocks over a time step, given a way to evaluate
+/// the flows and stocks parts of a state.  Stock updates are always
+/// compiled as Euler steps of length dt; RK4 and Rk45 are built out of
+/// several of them.
+pub(crate) struct Integrator<'a> {
+    method: Method,
+    tolerances: Tolerances,
+    stocks: &'a [StockSlot],
+    stage: Vec<f64>,
+    deltas: Vec<f64>,
+    sums: Vec<f64>,
+    euler: Vec<f64>,
+    // Rk45's state at the start of its current step, the slopes of each
+    // stage of that step, and the length of the next step to try
+    base: Vec<f64>,
+    slopes: Vec<Vec<f64>>,
+    h: f64,
+}
+
+impl<'a> Integrator<'a> {
+    pub(crate) fn new(specs: &Specs, stocks: &'a [StockSlot]) -> Self {
+        Integrator {
+            method: specs.method,
+            tolerances: specs.tolerances,
+            stocks,
+            stage: vec![],
+            deltas: vec![0.0; stocks.len()],
+            sums: vec![0.0; stocks.len()],
+            euler: vec![0.0; stocks.len()],
+            base: vec![],
+            slopes: vec![vec![0.0; stocks.len()]; 1 + DP_STAGES.len()],
+            h: specs.dt,
+        }
+    }
+
+    /// advance computes the stocks at `end_time` into `next`, from `curr`
+    /// whose flows have already been evaluated.  A partial step is taken
+    /// up to each of `events` so that changes scheduled between time
+    /// steps take effect exactly.
+    pub(crate) fn advance<F>(
+        &mut self,
+        curr: &mut [f64],
+        next: &mut [f64],
+        events: &[f64],
+        end_time: f64,
+        eval: &mut F,
+    ) where
+        F: FnMut(StepPart, &mut [f64], &mut [f64]),
+    {
+        if events.is_empty() {
+            self.step(curr, next, eval);
+            return;
+        }
+        // curr is left untouched, as it may be a saved step
+        let mut partial = curr.to_vec();
+        for (i, time) in events.iter().chain(std::iter::once(&end_time)).enumerate() {
+            if i > 0 {
+                eval(StepPart::Flows, &mut partial, next);
+            }
+            partial[DT_OFF] = time - partial[TIME_OFF];
+            self.step(&mut partial, next, eval);
+            partial[IMPLICIT_VAR_COUNT..].copy_from_slice(&next[IMPLICIT_VAR_COUNT..]);
+            partial[TIME_OFF] = *time;
+        }
+    }
+
+    // step integrates the stocks over a step of curr[DT_OFF].
+    fn step<F>(&mut self, curr: &mut [f64], next: &mut [f64], eval: &mut F)
+    where
+        F: FnMut(StepPart, &mut [f64], &mut [f64]),
+    {
+        eval(StepPart::Stocks, curr, next);
+        match self.method {
+            Method::Euler => return,
+            Method::RungeKutta4 => {}
+            Method::Rk45 => return self.adaptive_step(curr, next, eval),
+        }
+
+        for (i, slot) in self.stocks.iter().enumerate() {
+            self.euler[i] = next[slot.off];
+            self.deltas[i] = next[slot.off] - curr[slot.off];
+            self.sums[i] = self.deltas[i];
+        }
+        let dt = curr[DT_OFF];
+        for (fraction, weight) in RK4_STAGES.iter() {
+            self.stage.clear();
+            self.stage.extend_from_slice(curr);
+            self.stage[TIME_OFF] = curr[TIME_OFF] + fraction * dt;
+            for (i, slot) in self.stocks.iter().enumerate() {
+                // stocks integrated with Euler keep their value from the
+                // start of the step
+                if !slot.force_euler {
+                    self.stage[slot.off] = curr[slot.off] + fraction * self.deltas[i];
+                }
+            }
+            eval(StepPart::Flows, &mut self.stage, next);
+            eval(StepPart::Stocks, &mut self.stage, next);
+            for (i, slot) in self.stocks.iter().enumerate() {
+                self.deltas[i] = next[slot.off] - self.stage[slot.off];
+                self.sums[i] += weight * self.deltas[i];
+            }
+        }
+        for (i, slot) in self.stocks.iter().enumerate() {
+            next[slot.off] = if slot.force_euler {
+                self.euler[i]
+            } else {
+                curr[slot.off] + self.sums[i] / 6.0
+            };
+        }
+    }
+
+    // adaptive_step integrates the stocks over a step of curr[DT_OFF]
+    // with Dormand-Prince, in as many steps as it takes to keep each
+    // one's estimated error within the tolerances.  `next` holds the
+    // Euler step from curr.
+    fn adaptive_step<F>(&mut self, curr: &mut [f64], next: &mut [f64], eval: &mut F)
+    where
+        F: FnMut(StepPart, &mut [f64], &mut [f64]),
+    {
+        let dt = curr[DT_OFF];
+        let end = curr[TIME_OFF] + dt;
+        let min_h = dt * STEP_TOLERANCE;
+        for (i, slot) in self.stocks.iter().enumerate() {
+            self.euler[i] = next[slot.off];
+            self.slopes[0][i] = (next[slot.off] - curr[slot.off]) / dt;
+        }
+        self.base.clear();
+        self.base.extend_from_slice(curr);
+
+        let mut h = self.h.min(dt);
+        while end - self.base[TIME_OFF] > min_h {
+            let t = self.base[TIME_OFF];
+            let h_step = h.min(end - t);
+            for (s, (fraction, weights)) in DP_STAGES.iter().enumerate() {
+                self.stage.clear();
+                self.stage.extend_from_slice(&self.base);
+                self.stage[TIME_OFF] = t + fraction * h_step;
+                for (i, slot) in self.stocks.iter().enumerate() {
+                    // stocks integrated with Euler keep their value from
+                    // the start of the step
+                    if !slot.force_euler {
+                        let slope: f64 = weights
+                            .iter()
+                            .zip(self.slopes.iter())
+                            .take(s + 1)
+                            .map(|(weight, slopes)| weight * slopes[i])
+                            .sum();
+                        self.stage[slot.off] = self.base[slot.off] + h_step * slope;
+                    }
+                }
+                eval(StepPart::Flows, &mut self.stage, next);
+                // a stocks step of length 1 gives the slope of each stock
+                self.stage[DT_OFF] = 1.0;
+                eval(StepPart::Stocks, &mut self.stage, next);
+                self.stage[DT_OFF] = dt;
+                for (i, slot) in self.stocks.iter().enumerate() {
+                    self.slopes[s + 1][i] = next[slot.off] - self.stage[slot.off];
+                }
+            }
+
+            let (rtol, atol) = (self.tolerances.relative, self.tolerances.absolute);
+            let mut err: f64 = 0.0;
+            for (i, slot) in self.stocks.iter().enumerate() {
+                if slot.force_euler {
+                    continue;
+                }
+                let estimate: f64 = DP_ERROR
+                    .iter()
+                    .zip(self.slopes.iter())
+                    .map(|(weight, slopes)| weight * slopes[i])
+                    .sum();
+                let scale = atol + rtol * self.base[slot.off].abs().max(self.stage[slot.off].abs());
+                err = err.max((h_step * estimate).abs() / scale);
+            }
+
+            // a step that can't get any shorter, or whose error can't be
+            // estimated (like when a stock overflows), is taken anyway
+            if err <= 1.0 || !err.is_finite() || h_step <= min_h {
+                // the last stage is at the end of the step, so its flows
+                // and slopes start the next one
+                std::mem::swap(&mut self.base, &mut self.stage);
+                let last = self.slopes.len() - 1;
+                self.slopes.swap(0, last);
+            }
+            if err.is_finite() {
+                let factor = if err == 0.0 {
+                    5.0
+                } else {
+                    (0.9 * math::pow(err, -0.2)).clamp(0.2, 5.0)
+                };
+                h = (h_step * factor).clamp(min_h, dt);
+            }
+        }
+        self.h = h;
+
+        for (i, slot) in self.stocks.iter().enumerate() {
+            next[slot.off] = if slot.force_euler {
+                self.euler[i]
+            } else {
+                self.base[slot.off]
+            };
+        }
+    }
+}
+
+/// Results are the values saved by a simulation run.  Each saved step
+/// is a row of `step_size` values, and `offsets` maps a variable's name
+/// to its column in every row.  Use `get_series` for the values of one
+/// variable over time, `time_points` for the time of each step,
+/// `var_names` for every variable, and `iter_rows` to go through the
+/// steps in order.
+#[derive(Clone, Debug)]
 pub struct Results {
     pub offsets: HashMap<String, usize>,
     // one large allocation
@@ -112,6 +529,21 @@ impl Results {
         self.print_tsv_comparison(None)
     }
     pub fn print_tsv_comparison(&self, reference: Option<&Results>) {
+        self.write_tsv_comparison(&mut std::io::stdout().lock(), reference)
+            .expect("writing to stdout failed")
+    }
+    /// write_tsv writes the results as tab-separated values, with a
+    /// header of variable names.
+    pub fn write_tsv(&self, out: &mut dyn Write) -> io::Result<()> {
+        self.write_tsv_comparison(out, None)
+    }
+    /// write_tsv_comparison is like write_tsv, but when there is a
+    /// reference, interleaves each of its steps with ours.
+    pub fn write_tsv_comparison(
+        &self,
+        out: &mut dyn Write,
+        reference: Option<&Results>,
+    ) -> io::Result<()> {
         let var_names = {
             let offset_name_map: HashMap<usize, &str> =
                 self.offsets.iter().map(|(k, v)| (*v, k.as_str())).collect();
@@ -128,70 +560,155 @@ impl Results {
         };
 
         if reference.is_some() {
-            print!("series\t");
+            write!(out, "series\t")?;
         }
 
         // print header
         for (i, id) in var_names.iter().enumerate() {
-            print!("{}", id);
+            write!(out, "{}", id)?;
             if i == var_names.len() - 1 {
-                println!();
+                writeln!(out)?;
             } else {
-                print!("\t");
+                write!(out, "\t")?;
             }
         }
 
         match reference {
             Some(reference) => {
-                for (curr, ref_curr) in self.iter().zip(reference.iter()) {
+                for (curr, ref_curr) in self.iter_rows().zip(reference.iter_rows()) {
                     if curr[TIME_OFF] > self.specs.stop {
                         break;
                     }
-                    print!("reference\t");
+                    write!(out, "reference\t")?;
                     for (i, _) in curr.iter().enumerate() {
                         let var_name = var_names[i];
                         if let Some(off) = reference.offsets.get(var_name) {
                             let val = ref_curr[*off];
-                            print!("{}", val);
-                        } else {
-                            print!("")
+                            write!(out, "{}", val)?;
                         }
                         if i == var_names.len() - 1 {
-                            println!();
+                            writeln!(out)?;
                         } else {
-                            print!("\t");
+                            write!(out, "\t")?;
                         }
                     }
-                    print!("simlin\t");
+                    write!(out, "simlin\t")?;
                     for (i, val) in curr.iter().enumerate() {
-                        print!("{}", val);
+                        write!(out, "{}", val)?;
                         if i == var_names.len() - 1 {
-                            println!();
+                            writeln!(out)?;
                         } else {
-                            print!("\t");
+                            write!(out, "\t")?;
                         }
                     }
                 }
             }
             None => {
-                for curr in self.iter() {
+                for curr in self.iter_rows() {
                     if curr[TIME_OFF] > self.specs.stop {
                         break;
                     }
                     for (i, val) in curr.iter().enumerate() {
-                        print!("{}", val);
+                        write!(out, "{}", val)?;
                         if i == var_names.len() - 1 {
-                            println!();
+                            writeln!(out)?;
                         } else {
-                            print!("\t");
+                            write!(out, "\t")?;
                         }
                     }
                 }
             }
         }
+        out.flush()
     }
 
-    pub fn iter(&self) -> std::iter::Take<std::slice::Chunks<f64>> {
+    /// write_table writes the variables of a saved table as
+    /// tab-separated values: a column of times followed by a column for
+    /// each variable, with a row every `interval` and values rounded to
+    /// each variable's precision.
+    pub fn write_table(&self, out: &mut dyn Write, table: &Graph) -> io::Result<()> {
+        let mut columns = vec![];
+        for plot in table.plots.iter() {
+            match self.offset(&plot.ident) {
+                Some(off) => columns.push((off, plot.precision)),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("unknown variable '{}' in table", plot.ident),
+                    ))
+                }
+            }
+        }
+
+        write!(out, "time")?;
+        for plot in table.plots.iter() {
+            write!(out, "\t{}", plot.ident)?;
+        }
+        writeln!(out)?;
+
+        for curr in self.iter_rows() {
+            let time = curr[TIME_OFF];
+            if time > self.specs.stop {
+                break;
+            }
+            if let Some(interval) = table.interval.filter(|interval| *interval > 0.0) {
+                let n = (time - self.specs.start) / interval;
+                if !approx_eq!(f64, n, n.round(), epsilon = 1e-9) {
+                    continue;
+                }
+            }
+            write!(out, "{}", time)?;
+            for (off, precision) in columns.iter() {
+                match precision {
+                    Some(precision) => write!(out, "\t{:.*}", precision, curr[*off])?,
+                    None => write!(out, "\t{}", curr[*off])?,
+                }
+            }
+            writeln!(out)?;
+        }
+        out.flush()
+    }
+
+    /// without returns these results without the columns for `idents`,
+    /// including every element of arrayed variables.
+    pub fn without(&self, idents: &[&str]) -> Results {
+        let is_omitted = |name: &str| {
+            idents.iter().any(|ident| {
+                name == *ident || (name.starts_with(ident) && name[ident.len()..].starts_with('['))
+            })
+        };
+        // kept columns stay in the same order, so time stays first
+        let mut columns: Vec<(usize, &str)> = self
+            .offsets
+            .iter()
+            .filter(|(name, _)| !is_omitted(name))
+            .map(|(name, off)| (*off, name.as_str()))
+            .collect();
+        columns.sort_unstable();
+
+        let step_size = columns.len();
+        let mut data = Vec::with_capacity(step_size * self.step_count);
+        for curr in self.iter_rows() {
+            data.extend(columns.iter().map(|(off, _)| curr[*off]));
+        }
+
+        Results {
+            offsets: columns
+                .into_iter()
+                .enumerate()
+                .map(|(i, (_, name))| (name.to_owned(), i))
+                .collect(),
+            data: data.into_boxed_slice(),
+            step_size,
+            step_count: self.step_count,
+            specs: self.specs.clone(),
+            is_vensim: self.is_vensim,
+        }
+    }
+
+    /// iter_rows returns each saved step in order, as a row indexed by
+    /// the columns in `offsets`.
+    pub fn iter_rows(&self) -> std::iter::Take<std::slice::Chunks<f64>> {
         self.data.chunks(self.step_size).take(self.step_count)
     }
 }
@@ -202,9 +719,28 @@ pub struct Vm {
     root: Ident,
     offsets: HashMap<Ident, usize>,
     sliced_sim: CompiledSlicedSimulation,
+    stocks: Vec<StockSlot>,
     n_slots: usize,
     n_chunks: usize,
     data: Option<Box<[f64]>>,
+    state: RunState,
+}
+
+/// RunState is how far a run has gotten, so that it can be continued.
+#[derive(Clone, Debug, Default)]
+struct RunState {
+    is_started: bool,
+    // the number of time steps taken
+    n: usize,
+    // the number of time steps since the last saved one
+    step: usize,
+    // the slab of data holding the current step
+    chunk: usize,
+    // every slab has been filled
+    is_finished: bool,
+    // the length of the next Rk45 step to try, so that a run split over
+    // several calls takes the same steps as one that isn't
+    rk45_h: Option<f64>,
 }
 
 #[derive(Debug)]
@@ -266,13 +802,8 @@ impl Vm {
             return sim_err!(BadSimSpecs, "dt must be greater than 0".to_string());
         }
 
-        let save_step = if sim.specs.save_step > sim.specs.dt {
-            sim.specs.save_step
-        } else {
-            sim.specs.dt
-        };
         let n_slots = sim.modules[&sim.root].n_slots;
-        let n_chunks: usize = ((sim.specs.stop - sim.specs.start) / save_step + 1.0) as usize;
+        let n_chunks = sim.specs.n_chunks();
         let data: Box<[f64]> = vec![0.0; n_slots * (n_chunks + 2)].into_boxed_slice();
         Ok(Vm {
             specs: sim.specs,
@@ -295,9 +826,11 @@ impl Vm {
                     .map(|(id, m)| (id.clone(), CompiledModuleSlice::new(m, StepPart::Stocks)))
                     .collect(),
             },
+            stocks: sim.stocks,
             n_slots,
             n_chunks,
             data: Some(data),
+            state: Default::default(),
         })
     }
 
@@ -306,67 +839,172 @@ impl Vm {
         self.run_to(end)
     }
 
-    #[inline(never)]
+    /// run_steps_to_end is like run_steps, towards the end of the
+    /// simulation.
+    pub fn run_steps_to_end(&mut self, max_steps: usize) -> Result<bool> {
+        let end = self.specs.stop;
+        self.run_steps(end, max_steps)
+    }
+
+    /// run_to runs until the step at `end` has been computed, leaving
+    /// the VM at the time step after it.  Times past the end of the
+    /// simulation run it to the end.
     pub fn run_to(&mut self, end: f64) -> Result<()> {
+        self.run_steps(end, usize::MAX).map(|_| ())
+    }
+
+    /// step takes `n` time steps towards the end of the simulation, or
+    /// as many as are left, and returns true once the run is done.
+    /// `step(0)` only computes the initial values.
+    pub fn step(&mut self, n: usize) -> Result<bool> {
+        self.run_steps_to_end(n)
+    }
+
+    /// reset rewinds the VM to before the start of the simulation, so
+    /// that it can be run again.
+    pub fn reset(&mut self) {
+        if let Some(data) = self.data.as_mut() {
+            data.fill(0.0);
+        }
+        self.state = Default::default();
+    }
+
+    /// time returns the time the VM is at: the start time until it has
+    /// taken a step, then the time of the last step taken.
+    pub fn time(&self) -> f64 {
+        self.specs.time(self.state.n)
+    }
+
+    /// value returns the value of the variable `ident` (or a path like
+    /// `hares.births`) at the current time, or None before the run has
+    /// started.  Flows and auxiliaries are computed for the current
+    /// time from the stocks, without advancing the run.
+    pub fn value(&self, ident: &str) -> Option<f64> {
+        if !self.state.is_started {
+            return None;
+        }
+        let off = *self
+            .offsets
+            .get(ident)
+            .or_else(|| self.offsets.get(&quoteize(&canonicalize(ident))))?;
+        // the slab at the current chunk holds the latest step's stocks
+        let start = self.state.chunk * self.n_slots;
+        let mut curr = self.data.as_ref()?[start..start + self.n_slots].to_vec();
+        let mut next = vec![0.0; self.n_slots];
+        let module_flows = &self.sliced_sim.flow_modules[&self.root];
+        self.eval(
+            module_flows,
+            0,
+            &[],
+            &mut curr,
+            &mut next,
+            &mut Stack::new(),
+        );
+        Some(curr[off])
+    }
+
+    /// run_steps takes up to `max_steps` time steps towards `end`,
+    /// picking up where the previous call left off, and returns true
+    /// once the run has reached `end` (or the end of the simulation, if
+    /// that comes first).  Long runs can be split up this way to give
+    /// control back to a caller, like a browser's event loop, in
+    /// between.
+    #[inline(never)]
+    pub fn run_steps(&mut self, end: f64, max_steps: usize) -> Result<bool> {
+        if self.state.is_finished {
+            return Ok(true);
+        }
         let spec = &self.specs;
+        // there are only slabs for the steps up to the stop time
+        let end = end.min(spec.stop);
 
         let sliced_sim = &self.sliced_sim;
         let module_initials = &sliced_sim.initial_modules[&self.root];
         let module_flows = &sliced_sim.flow_modules[&self.root];
         let module_stocks = &sliced_sim.stock_modules[&self.root];
 
-        let save_every = std::cmp::max(1, (spec.save_step / spec.dt + 0.5).floor() as usize);
+        let save_every = spec.save_every();
+        let last_step = spec.last_step(end);
 
         let dt = spec.dt;
 
         let mut data = None;
         std::mem::swap(&mut data, &mut self.data);
         let mut data = data.unwrap();
+        let mut state = self.state.clone();
+        let mut is_done = false;
 
         {
             let mut stack = Stack::new();
             let module_inputs: &[f64] = &[0.0; 0];
 
-            let mut slabs = data.chunks_mut(self.n_slots);
+            let mut slabs = data.chunks_mut(self.n_slots).skip(state.chunk);
             let mut curr = slabs.next().unwrap();
             let mut next = slabs.next().unwrap();
-            curr[TIME_OFF] = spec.start;
-            curr[DT_OFF] = dt;
-            curr[INITIAL_TIME_OFF] = spec.start;
-            curr[FINAL_TIME_OFF] = spec.stop;
-            self.eval(module_initials, 0, module_inputs, curr, next, &mut stack);
-            let mut is_initial_timestep = true;
-            let mut step = 0;
-            while curr[TIME_OFF] <= end {
-                self.eval(module_flows, 0, module_inputs, curr, next, &mut stack);
-                self.eval(module_stocks, 0, module_inputs, curr, next, &mut stack);
-                next[TIME_OFF] = curr[TIME_OFF] + dt;
+            let mut eval = |part: StepPart, curr: &mut [f64], next: &mut [f64]| {
+                let module = match part {
+                    StepPart::Initials => module_initials,
+                    StepPart::Flows => module_flows,
+                    StepPart::Stocks => module_stocks,
+                };
+                self.eval(module, 0, module_inputs, curr, next, &mut stack);
+            };
+            if !state.is_started {
+                curr[TIME_OFF] = spec.start;
+                curr[DT_OFF] = dt;
+                curr[INITIAL_TIME_OFF] = spec.start;
+                curr[FINAL_TIME_OFF] = spec.stop;
+                eval(StepPart::Initials, curr, next);
+                state.is_started = true;
+            }
+            let mut integrator = Integrator::new(spec, &self.stocks);
+            if let Some(h) = state.rk45_h {
+                integrator.h = h;
+            }
+            let mut steps_taken = 0;
+            while state.n <= last_step && steps_taken < max_steps {
+                eval(StepPart::Flows, curr, next);
+                let end_time = spec.time(state.n + 1);
+                let events = spec.events_between(curr[TIME_OFF], end_time);
+                integrator.advance(curr, next, events, end_time, &mut eval);
+                state.n += 1;
+                steps_taken += 1;
+                next[TIME_OFF] = spec.time(state.n);
                 next[DT_OFF] = curr[DT_OFF];
                 next[INITIAL_TIME_OFF] = curr[INITIAL_TIME_OFF];
                 next[FINAL_TIME_OFF] = curr[FINAL_TIME_OFF];
-                step += 1;
-                if step != save_every && !is_initial_timestep {
+                state.step += 1;
+                if state.step != save_every && state.chunk > 0 {
                     let curr = curr.borrow_mut();
                     curr.copy_from_slice(next);
                 } else {
                     curr = next;
+                    state.chunk += 1;
                     let maybe_next = slabs.next();
                     if maybe_next.is_none() {
+                        state.is_finished = true;
+                        is_done = true;
                         break;
                     }
                     next = maybe_next.unwrap();
-                    step = 0;
-                    is_initial_timestep = false;
+                    state.step = 0;
                 }
             }
-            // ensure we've calculated stock + flow values for the dt <= end_time
-            assert!(curr[TIME_OFF] > end);
+            state.rk45_h = Some(integrator.h);
+            if state.n > last_step {
+                is_done = true;
+            }
+            if is_done {
+                // ensure we've calculated stock + flow values for the dt <= end_time
+                assert!(curr[TIME_OFF] > end);
+            }
         }
 
         let mut data = Some(data);
         std::mem::swap(&mut data, &mut self.data);
+        self.state = state;
 
-        Ok(())
+        Ok(is_done)
     }
 
     pub fn into_results(self) -> Results {
@@ -415,84 +1053,22 @@ impl Vm {
         stack: &mut Stack,
     ) {
         let bytecode = &module.bytecode;
-
-        let mut condition = false;
-        let mut subscript_index: Vec<(u16, u16)> = vec![];
-        let mut subscript_index_valid = true;
+        let graphical_functions = &module.context.graphical_functions;
+        let mut registers = Registers::new();
 
         let code = &bytecode.code;
-        for op in code.iter() {
-            match *op {
-                Opcode::Op2 { op } => {
-                    let r = stack.pop();
-                    let l = stack.pop();
-                    let result = match op {
-                        Op2::Add => l + r,
-                        Op2::Sub => l - r,
-                        Op2::Exp => l.powf(r),
-                        Op2::Mul => l * r,
-                        Op2::Div => l / r,
-                        Op2::Mod => l.rem_euclid(r),
-                        Op2::Gt => (l > r) as i8 as f64,
-                        Op2::Gte => (l >= r) as i8 as f64,
-                        Op2::Lt => (l < r) as i8 as f64,
-                        Op2::Lte => (l <= r) as i8 as f64,
-                        Op2::Eq => approx_eq!(f64, l, r) as i8 as f64,
-                        Op2::And => (is_truthy(l) && is_truthy(r)) as i8 as f64,
-                        Op2::Or => (is_truthy(l) || is_truthy(r)) as i8 as f64,
-                    };
-                    stack.push(result);
-                }
-                Opcode::Not {} => {
-                    let r = stack.pop();
-                    stack.push((!is_truthy(r)) as i8 as f64);
-                }
-                Opcode::LoadConstant { id } => {
-                    stack.push(bytecode.literals[id as usize]);
-                }
-                Opcode::LoadGlobalVar { off } => {
-                    stack.push(curr[off as usize]);
-                }
-                Opcode::LoadVar { off } => {
-                    stack.push(curr[module_off + off as usize]);
-                }
-                Opcode::PushSubscriptIndex { bounds } => {
-                    let index = stack.pop().floor() as u16;
-                    if index == 0 || index > bounds {
-                        subscript_index_valid = false;
-                    } else {
-                        // we convert from 1-based to 0-based here
-                        subscript_index.push((index - 1, bounds));
-                        subscript_index_valid &= true;
-                    };
-                }
-                Opcode::LoadSubscript { off } => {
-                    let result = if subscript_index_valid {
-                        // the subscript index is 1-based, but curr is 0-based.
-                        let mut index = 0;
-                        for (i, bounds) in subscript_index.iter() {
-                            index *= *bounds as usize;
-                            index += *i as usize;
-                        }
-                        curr[module_off + off as usize + index]
-                    } else {
-                        f64::NAN
-                    };
-                    stack.push(result);
-                    subscript_index.clear();
-                    subscript_index_valid = true;
-                }
-                Opcode::SetCond {} => {
-                    condition = is_truthy(stack.pop());
-                }
-                Opcode::If {} => {
-                    let f = stack.pop();
-                    let t = stack.pop();
-                    let result = if condition { t } else { f };
-                    stack.push(result);
+        let mut pc = 0;
+        while pc < code.len() {
+            let op = code[pc];
+            pc += 1;
+            match op {
+                Opcode::JumpIfFalse { skip } => {
+                    if !is_truthy(stack.pop()) {
+                        pc += skip as usize;
+                    }
                 }
-                Opcode::LoadModuleInput { input } => {
-                    stack.push(module_inputs[input as usize]);
+                Opcode::Jump { skip } => {
+                    pc += skip as usize;
                 }
                 Opcode::EvalModule { id, n_inputs } => {
                     use std::iter;
@@ -511,23 +1087,28 @@ impl Vm {
                     next[module_off + off as usize] = stack.pop();
                     assert_eq!(0, stack.stack.len());
                 }
-                Opcode::Apply { func } => {
-                    let time = curr[TIME_OFF];
-                    let dt = curr[DT_OFF];
-                    let c = stack.pop();
-                    let b = stack.pop();
-                    let a = stack.pop();
-
-                    stack.push(apply(func, time, dt, a, b, c));
-                }
-                Opcode::Lookup { gf } => {
-                    let index = stack.pop();
-                    let gf = &module.context.graphical_functions[gf as usize];
-                    stack.push(lookup(gf, index));
+                Opcode::EvalParallel { id } => {
+                    let block = &bytecode.blocks[id as usize];
+                    let env = Env {
+                        literals: &bytecode.literals,
+                        graphical_functions,
+                        module_off,
+                        module_inputs,
+                    };
+                    eval_block(&env, block, curr);
                 }
                 Opcode::Ret => {
                     break;
                 }
+                op => {
+                    let env = Env {
+                        literals: &bytecode.literals,
+                        graphical_functions,
+                        module_off,
+                        module_inputs,
+                    };
+                    eval_op(op, &env, curr, stack, &mut registers);
+                }
             }
         }
     }
@@ -576,19 +1157,193 @@ impl Vm {
     }
 }
 
+/// Env is what the code of a module reads from, other than the
+/// current values of variables.
+struct Env<'a> {
+    literals: &'a [f64],
+    graphical_functions: &'a [Vec<(f64, f64)>],
+    module_off: usize,
+    module_inputs: &'a [f64],
+}
+
+/// Registers is the state of the interpreter carried between opcodes,
+/// other than the stack.
+struct Registers {
+    subscript_index: Vec<(u16, u16)>,
+    subscript_index_valid: bool,
+}
+
+impl Registers {
+    fn new() -> Self {
+        Registers {
+            subscript_index: vec![],
+            subscript_index_valid: true,
+        }
+    }
+}
+
+/// eval_op evaluates an opcode that only reads variables and works on
+/// the stack: everything but assignments, modules and control flow.
+#[inline(always)]
+fn eval_op(op: Opcode, env: &Env, curr: &[f64], stack: &mut Stack, registers: &mut Registers) {
+    match op {
+        Opcode::Op2 { op } => {
+            let r = stack.pop();
+            let l = stack.pop();
+            let result = match op {
+                Op2::Add => l + r,
+                Op2::Sub => l - r,
+                Op2::Exp => math::pow(l, r),
+                Op2::Mul => l * r,
+                Op2::Div => l / r,
+                Op2::Mod => l.rem_euclid(r),
+                Op2::Gt => (l > r) as i8 as f64,
+                Op2::Gte => (l >= r) as i8 as f64,
+                Op2::Lt => (l < r) as i8 as f64,
+                Op2::Lte => (l <= r) as i8 as f64,
+                Op2::Eq => approx_eq!(f64, l, r) as i8 as f64,
+                Op2::And => (is_truthy(l) && is_truthy(r)) as i8 as f64,
+                Op2::Or => (is_truthy(l) || is_truthy(r)) as i8 as f64,
+            };
+            stack.push(result);
+        }
+        Opcode::Not {} => {
+            let r = stack.pop();
+            stack.push((!is_truthy(r)) as i8 as f64);
+        }
+        Opcode::LoadConstant { id } => {
+            stack.push(env.literals[id as usize]);
+        }
+        Opcode::LoadGlobalVar { off } => {
+            stack.push(curr[off as usize]);
+        }
+        Opcode::LoadVar { off } => {
+            stack.push(curr[env.module_off + off as usize]);
+        }
+        Opcode::PushSubscriptIndex { bounds } => {
+            let index = stack.pop().floor() as u16;
+            if index == 0 || index > bounds {
+                registers.subscript_index_valid = false;
+            } else {
+                // we convert from 1-based to 0-based here
+                registers.subscript_index.push((index - 1, bounds));
+            };
+        }
+        Opcode::LoadSubscript { off } => {
+            let result = if registers.subscript_index_valid {
+                // the subscript index is 1-based, but curr is 0-based.
+                let mut index = 0;
+                for (i, bounds) in registers.subscript_index.iter() {
+                    index *= *bounds as usize;
+                    index += *i as usize;
+                }
+                curr[env.module_off + off as usize + index]
+            } else {
+                f64::NAN
+            };
+            stack.push(result);
+            registers.subscript_index.clear();
+            registers.subscript_index_valid = true;
+        }
+        Opcode::LoadModuleInput { input } => {
+            stack.push(env.module_inputs[input as usize]);
+        }
+        Opcode::Apply { func } => {
+            let time = curr[TIME_OFF];
+            let dt = curr[DT_OFF];
+            let c = stack.pop();
+            let b = stack.pop();
+            let a = stack.pop();
+
+            stack.push(apply(func, time, dt, a, b, c));
+        }
+        Opcode::Lookup { gf } => {
+            let index = stack.pop();
+            let gf = &env.graphical_functions[gf as usize];
+            stack.push(lookup(gf, index));
+        }
+        Opcode::EvalModule { .. }
+        | Opcode::AssignCurr { .. }
+        | Opcode::AssignNext { .. }
+        | Opcode::EvalParallel { .. }
+        | Opcode::JumpIfFalse { .. }
+        | Opcode::Jump { .. }
+        | Opcode::Ret => unreachable!(),
+    }
+}
+
+/// eval_elements evaluates the code for elements `from` up to
+/// `from + out.len()` of a parallel block into `out`.
+fn eval_elements(env: &Env, block: &ParallelBlock, from: usize, curr: &[f64], out: &mut [f64]) {
+    let mut stack = Stack::new();
+    let mut registers = Registers::new();
+    let first_off = env.module_off + block.off as usize;
+    let code = block.element_code(from, from + out.len());
+    let mut pc = 0;
+    while pc < code.len() {
+        let op = code[pc];
+        pc += 1;
+        match op {
+            Opcode::JumpIfFalse { skip } => {
+                if !is_truthy(stack.pop()) {
+                    pc += skip as usize;
+                }
+            }
+            Opcode::Jump { skip } => {
+                pc += skip as usize;
+            }
+            Opcode::AssignCurr { off } => {
+                out[env.module_off + off as usize - first_off - from] = stack.pop();
+            }
+            op => eval_op(op, env, curr, &mut stack, &mut registers),
+        }
+    }
+}
+
+/// eval_block evaluates the elements of a parallel block, split into a
+/// chunk for each thread in rayon's pool.  The pool's threads live for
+/// the whole process, so each step only pays to hand them work.
+#[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+fn eval_block(env: &Env, block: &ParallelBlock, curr: &mut [f64]) {
+    use rayon::prelude::*;
+
+    let n = block.starts.len();
+    let chunk_size = n.div_ceil(rayon::current_num_threads());
+    let mut out = vec![0.0; n];
+    {
+        let curr: &[f64] = curr;
+        out.par_chunks_mut(chunk_size)
+            .enumerate()
+            .for_each(|(i, out)| eval_elements(env, block, i * chunk_size, curr, out));
+    }
+    let first_off = env.module_off + block.off as usize;
+    curr[first_off..first_off + n].copy_from_slice(&out);
+}
+
+/// eval_block evaluates the elements of a parallel block one after the
+/// other, for builds without threads.
+#[cfg(not(all(feature = "parallel", not(target_arch = "wasm32"))))]
+fn eval_block(env: &Env, block: &ParallelBlock, curr: &mut [f64]) {
+    let n = block.starts.len();
+    let mut out = vec![0.0; n];
+    eval_elements(env, block, 0, curr, &mut out);
+    let first_off = env.module_off + block.off as usize;
+    curr[first_off..first_off + n].copy_from_slice(&out);
+}
+
 #[inline(always)]
 fn apply(func: BuiltinId, time: f64, dt: f64, a: f64, b: f64, c: f64) -> f64 {
     match func {
         BuiltinId::Abs => a.abs(),
-        BuiltinId::Arccos => a.acos(),
-        BuiltinId::Arcsin => a.asin(),
-        BuiltinId::Arctan => a.atan(),
-        BuiltinId::Cos => a.cos(),
-        BuiltinId::Exp => a.exp(),
+        BuiltinId::Arccos => math::acos(a),
+        BuiltinId::Arcsin => math::asin(a),
+        BuiltinId::Arctan => math::atan(a),
+        BuiltinId::Cos => math::cos(a),
+        BuiltinId::Exp => math::exp(a),
         BuiltinId::Inf => f64::INFINITY,
         BuiltinId::Int => a.floor(),
-        BuiltinId::Ln => a.ln(),
-        BuiltinId::Log10 => a.log10(),
+        BuiltinId::Ln => math::ln(a),
+        BuiltinId::Log10 => math::log10(a),
         BuiltinId::Max => {
             if a > b {
                 a
@@ -623,17 +1378,40 @@ fn apply(func: BuiltinId, time: f64, dt: f64, a: f64, b: f64, c: f64) -> f64 {
                 c
             }
         }
-        BuiltinId::Sin => a.sin(),
+        BuiltinId::Sin => math::sin(a),
         BuiltinId::Sqrt => a.sqrt(),
         BuiltinId::Step => {
             let height = a;
             let step_time = b;
             step(time, dt, height, step_time)
         }
-        BuiltinId::Tan => a.tan(),
+        BuiltinId::Tan => math::tan(a),
+        BuiltinId::WhiteNoise => white_noise(a, time),
     }
 }
 
+// mix is the finalizer of splitmix64, which spreads every bit of its
+// input over the output.
+fn mix(z: u64) -> u64 {
+    let z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// white_noise returns a standard normal draw determined by `seed` and
+/// `time` alone, so re-running a model, or evaluating an equation more
+/// than once in a step, gives the same noise.
+pub(crate) fn white_noise(seed: f64, time: f64) -> f64 {
+    let a = mix(mix(seed.to_bits()) ^ time.to_bits());
+    let b = mix(a);
+    // Box-Muller, with u1 in (0, 1] so its log is finite
+    let scale = (1u64 << 53) as f64;
+    let u1 = ((a >> 11) as f64 + 1.0) / scale;
+    let u2 = (b >> 11) as f64 / scale;
+    (-2.0 * math::ln(u1)).sqrt() * math::cos(2.0 * std::f64::consts::PI * u2)
+}
+
 pub(crate) fn ramp(time: f64, slope: f64, start_time: f64, end_time: Option<f64>) -> f64 {
     if time > start_time {
         let done_ramping = end_time.is_some() && time >= end_time.unwrap();
@@ -897,3 +1675,370 @@ fn lookup(table: &[(f64, f64)], index: f64) -> f64 {
         (index - table[i - 1].0) * slope + table[i - 1].1
     }
 }
+
+#[test]
+fn test_stats() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
+    use crate::{datamodel, Project, Simulation};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_stock("s", "0", &["f"], &[], None),
+                x_flow("f", "SMTH1(x, 2)", None),
+                x_aux("x", "1 + 2 * time", None),
+            ],
+        )],
+    );
+    project.sim_specs.stop = 4.0;
+    project.sim_specs.dt = Dt::Dt(0.5);
+    project.sim_specs.save_step = Some(Dt::Dt(1.0));
+
+    let stats = |project: &datamodel::Project| {
+        let project = Project::from(project.clone());
+        let sim = Simulation::new(&project, "main").unwrap();
+        sim.compile().unwrap().stats()
+    };
+
+    let euler = stats(&project);
+    assert_eq!(8, euler.time_steps);
+    assert_eq!(5, euler.saved_steps);
+    assert_eq!(euler.n_slots * 7 * 8, euler.result_bytes);
+    // the smoothing stock and its inputs and flow are hidden state
+    assert!(euler.hidden_slots > 0);
+    assert!(euler.hidden_slots < euler.n_slots);
+    assert!(euler.bytecode_bytes > 0);
+    // at least x's add and multiply
+    assert!(euler.flops_per_step >= 2);
+
+    project.sim_specs.sim_method = SimMethod::RungeKutta4;
+    let rk4 = stats(&project);
+    assert_eq!(4 * euler.flops_per_step, rk4.flops_per_step);
+    assert_eq!(euler.n_slots, rk4.n_slots);
+}
+
+#[test]
+fn test_write_table() {
+    use crate::datamodel::{GraphKind, Plot};
+    use crate::testutils::{sim_specs_with_units, x_aux, x_model, x_project};
+    use crate::{Project, Simulation};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model("main", vec![x_aux("x", "time / 3", None)])],
+    );
+    project.sim_specs.stop = 4.0;
+    let project = Project::from(project);
+    let sim = Simulation::new(&project, "main").unwrap();
+    let mut vm = Vm::new(sim.compile().unwrap()).unwrap();
+    vm.run_to_end().unwrap();
+    let results = vm.into_results();
+
+    let mut table = Graph {
+        title: "".to_owned(),
+        kind: GraphKind::Table,
+        plots: vec![Plot {
+            ident: "x".to_owned(),
+            color: None,
+            scale: None,
+            precision: Some(2),
+        }],
+        interval: Some(2.0),
+    };
+    let mut out = vec![];
+    results.write_table(&mut out, &table).unwrap();
+    assert_eq!(
+        "time\tx\n0\t0.00\n2\t0.67\n4\t1.33\n",
+        String::from_utf8(out).unwrap()
+    );
+
+    table.plots[0].ident = "missing".to_owned();
+    assert!(results.write_table(&mut vec![], &table).is_err());
+}
+
+#[test]
+fn test_results_without() {
+    use crate::datamodel::Variable;
+    use crate::testutils::{sim_specs_with_units, x_aux, x_model, x_project};
+    use crate::{Project, Simulation};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_aux("x", "time", None),
+                x_aux("y", "x * 2", None),
+                x_aux("z", "y + 1", None),
+            ],
+        )],
+    );
+    project.sim_specs.stop = 2.0;
+    if let Some(Variable::Aux(aux)) = project.models[0].get_variable_mut("y") {
+        aux.supplementary = true;
+    }
+    let supplementary: Vec<&str> = project.models[0]
+        .variables
+        .iter()
+        .filter(|var| var.is_supplementary())
+        .map(|var| var.get_ident())
+        .collect();
+    assert_eq!(vec!["y"], supplementary);
+
+    let project = Project::from(project.clone());
+    let sim = Simulation::new(&project, "main").unwrap();
+    let mut vm = Vm::new(sim.compile().unwrap()).unwrap();
+    vm.run_to_end().unwrap();
+    let results = vm.into_results();
+
+    let trimmed = results.without(&supplementary);
+    assert_eq!(results.step_size - 1, trimmed.step_size);
+    assert_eq!(None, trimmed.offsets.get("y"));
+    assert_eq!(results.time_points(), trimmed.time_points());
+    assert_eq!(results.get_series("x"), trimmed.get_series("x"));
+    assert_eq!(results.get_series("z"), trimmed.get_series("z"));
+}
+
+#[test]
+fn test_parallel_elements() {
+    use crate::compiler::PARALLEL_MIN_ELEMENTS;
+    use crate::testutils::{sim_specs_with_units, x_a2a, x_aux, x_model, x_project};
+    use crate::{Project, Simulation};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_aux("rate", "time * 2", None),
+                x_a2a("level", &["cells"], "rate + 1", None),
+                x_a2a(
+                    "doubled",
+                    &["cells"],
+                    "IF level > 3 THEN level * 2 ELSE 0",
+                    None,
+                ),
+            ],
+        )],
+    );
+    project.dimensions = vec![Dimension::Named(
+        "cells".to_owned(),
+        (1..=PARALLEL_MIN_ELEMENTS)
+            .map(|i| format!("c{}", i))
+            .collect(),
+    )];
+    project.sim_specs.stop = 3.0;
+    let project = Project::from(project);
+    let sim = Simulation::new(&project, "main").unwrap();
+
+    let compiled = sim.compile().unwrap();
+    let n_blocks = compiled.modules["main"].compiled_flows.blocks.len();
+    if cfg!(feature = "parallel") {
+        assert_eq!(2, n_blocks);
+    } else {
+        assert_eq!(0, n_blocks);
+    }
+
+    let mut vm = Vm::new(compiled).unwrap();
+    vm.run_to_end().unwrap();
+    let results = vm.into_results();
+    let expected = sim.run_to_end().unwrap();
+    let last = format!("doubled[c{}]", PARALLEL_MIN_ELEMENTS);
+    assert_eq!(Some(vec![0.0, 0.0, 10.0, 14.0]), results.get_series(&last));
+    for ident in ["level[c1]", "doubled[c1]", last.as_str()] {
+        assert_eq!(expected.get_series(ident), results.get_series(ident));
+    }
+}
+
+#[test]
+fn test_run_steps() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
+    use crate::{Project, Simulation};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_stock("s", "1", &["f"], &[], None),
+                x_flow("f", "s * rate", None),
+                x_aux("rate", "0.1", None),
+            ],
+        )],
+    );
+    project.sim_specs.stop = 10.0;
+    project.sim_specs.dt = Dt::Dt(0.25);
+    project.sim_specs.save_step = Some(Dt::Dt(1.0));
+    let project = Project::from(project);
+    let sim = Simulation::new(&project, "main").unwrap();
+
+    let mut vm = Vm::new(sim.compile().unwrap()).unwrap();
+    vm.run_to_end().unwrap();
+    let expected = vm.into_results();
+
+    let mut vm = Vm::new(sim.compile().unwrap()).unwrap();
+    let mut calls = 1;
+    while !vm.run_steps_to_end(3).unwrap() {
+        calls += 1;
+    }
+    assert!(calls > 10);
+    assert!(vm.run_steps_to_end(3).unwrap());
+    let results = vm.into_results();
+    assert_eq!(expected.time_points(), results.time_points());
+    assert_eq!(expected.get_series("s"), results.get_series("s"));
+
+    // a run stopped partway can be continued to the end
+    let mut vm = Vm::new(sim.compile().unwrap()).unwrap();
+    vm.run_to(4.0).unwrap();
+    vm.run_to_end().unwrap();
+    assert_eq!(expected.get_series("s"), vm.into_results().get_series("s"));
+}
+
+#[test]
+fn test_vm_stepping() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
+    use crate::{Project, Simulation};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_stock("s", "1", &["f"], &[], None),
+                x_flow("f", "s * rate", None),
+                x_aux("rate", "0.1", None),
+            ],
+        )],
+    );
+    project.sim_specs.stop = 10.0;
+    project.sim_specs.dt = Dt::Dt(0.25);
+    project.sim_specs.save_step = Some(Dt::Dt(1.0));
+    let project = Project::from(project);
+    let sim = Simulation::new(&project, "main").unwrap();
+
+    let mut vm = Vm::new(sim.compile().unwrap()).unwrap();
+    vm.run_to_end().unwrap();
+    let expected = vm.into_results();
+    let expected_s = expected.get_series("s").unwrap();
+
+    let mut vm = Vm::new(sim.compile().unwrap()).unwrap();
+    assert_eq!(0.0, vm.time());
+    assert_eq!(None, vm.value("s"));
+    assert!(!vm.step(0).unwrap());
+    assert_eq!(Some(1.0), vm.value("s"));
+    assert_eq!(Some(0.1), vm.value("f"));
+
+    // four steps of 0.25 reach the next saved time, whether or not it
+    // falls in between saved steps
+    assert!(!vm.step(1).unwrap());
+    assert_eq!(0.25, vm.time());
+    assert!(!vm.step(3).unwrap());
+    assert_eq!(1.0, vm.time());
+    assert_eq!(Some(expected_s[1]), vm.value("s"));
+    assert_eq!(Some(expected_s[1] * 0.1), vm.value("f"));
+    assert_eq!(None, vm.value("missing"));
+
+    // a paused run continues from where it was
+    assert!(vm.step(usize::MAX).unwrap());
+    assert!(vm.step(1).unwrap());
+
+    // reset starts over, giving the same results as a fresh VM
+    vm.reset();
+    assert_eq!(None, vm.value("s"));
+    vm.run_to(2.5).unwrap();
+    assert_eq!(2.75, vm.time());
+    vm.run_to_end().unwrap();
+    assert_eq!(expected.get_series("s"), vm.into_results().get_series("s"));
+
+    // running past the stop time stops at the end
+    let mut vm = Vm::new(sim.compile().unwrap()).unwrap();
+    vm.run_to(11.0).unwrap();
+    assert!(vm.step(1).unwrap());
+    assert_eq!(expected.get_series("s"), vm.into_results().get_series("s"));
+    let mut vm = Vm::new(sim.compile().unwrap()).unwrap();
+    assert!(vm.run_steps(f64::INFINITY, usize::MAX).unwrap());
+    assert_eq!(expected.get_series("s"), vm.into_results().get_series("s"));
+}
+
+#[test]
+fn test_rk45_chunked() {
+    use crate::testutils::{sim_specs_with_units, x_flow, x_model, x_project, x_stock};
+    use crate::{Project, Simulation};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_stock("s", "1", &["growth"], &[], None),
+                x_flow("growth", "s * (1 - s / 10)", None),
+            ],
+        )],
+    );
+    project.sim_specs.sim_method = SimMethod::Rk45;
+    project.sim_specs.stop = 6.0;
+    let project = Project::from(project);
+    let sim = Simulation::new(&project, "main").unwrap();
+
+    let mut vm = Vm::new(sim.compile().unwrap()).unwrap();
+    vm.run_to_end().unwrap();
+    let expected = vm.into_results();
+
+    // the adaptive step size carries over from one call to the next, so
+    // a run taken a step at a time is bit-identical to one taken at once
+    let mut vm = Vm::new(sim.compile().unwrap()).unwrap();
+    while !vm.step(1).unwrap() {}
+    assert_eq!(expected.get_series("s"), vm.into_results().get_series("s"));
+
+    let mut vm = Vm::new(sim.compile().unwrap()).unwrap();
+    vm.run_to(2.0).unwrap();
+    vm.run_to(4.5).unwrap();
+    vm.run_to_end().unwrap();
+    assert_eq!(expected.get_series("s"), vm.into_results().get_series("s"));
+}
+
+#[test]
+fn test_if_short_circuit() {
+    use crate::testutils::{sim_specs_with_units, x_aux, x_model, x_project};
+    use crate::{Project, Simulation};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_aux("x", "time - 1", None),
+                x_aux("guarded", "IF x = 0 THEN 0 ELSE 1 / x", None),
+                x_aux(
+                    "nested",
+                    "IF time < 1 THEN 1 ELSE IF time < 2 THEN 2 ELSE 3",
+                    None,
+                ),
+            ],
+        )],
+    );
+    project.sim_specs.stop = 3.0;
+    let project = Project::from(project);
+    let sim = Simulation::new(&project, "main").unwrap();
+
+    let compiled = sim.compile().unwrap();
+    let code = &compiled.modules["main"].compiled_flows.code;
+    let jumps = code
+        .iter()
+        .filter(|op| matches!(op, Opcode::JumpIfFalse { .. }))
+        .count();
+    assert_eq!(3, jumps);
+
+    let mut vm = Vm::new(compiled).unwrap();
+    vm.run_to_end().unwrap();
+    let results = vm.into_results();
+    assert_eq!(
+        Some(vec![-1.0, 0.0, 1.0, 0.5]),
+        results.get_series("guarded")
+    );
+    assert_eq!(Some(vec![1.0, 2.0, 3.0, 3.0]), results.get_series("nested"));
+    let expected = sim.run_to_end().unwrap();
+    assert_eq!(expected.get_series("nested"), results.get_series("nested"));
+}
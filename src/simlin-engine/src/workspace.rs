@@ -0,0 +1,248 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+//! Workspaces tie a model file to the scenarios, data, saved runs and
+//! tests used to analyze it, so an analysis can be saved, shared and
+//! reproduced as a unit.  A workspace is stored as JSON, and refers to
+//! its model and data files by path rather than including them.
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::Result;
+use crate::datamodel::{self, ModelTest};
+use crate::ensemble::Override;
+use crate::events::Schedule;
+use crate::json::json_error;
+use crate::model_tests::{run_tests_with_stubs, TestResult};
+use crate::stubs::{Stub, Stubs};
+use crate::vm::{Results, Vm};
+use crate::{model_err, sim_err, Project, Simulation};
+
+/// Scenario is a named set of changes to the main model, like a
+/// policy to compare against the model as-is.
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Scenario {
+    pub name: String,
+    pub overrides: Vec<Override>,
+    pub schedule: Schedule,
+}
+
+/// DataBinding drives a variable in the main model (or a path into its
+/// modules, like `hares.birth_rate`) with a time series from a data
+/// file, linearly interpolated between the times in the file.
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DataBinding {
+    pub ident: String,
+    /// a file readable by `load_run`, relative to the workspace file
+    pub path: String,
+    /// the series in the file to use, if it isn't named `ident`
+    pub column: Option<String>,
+}
+
+/// SavedRun is a simulation of one of the workspace's scenarios (or
+/// of the model as-is), saved to a file.
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SavedRun {
+    pub name: String,
+    pub scenario: Option<String>,
+    /// where the results are written, relative to the workspace file
+    pub path: String,
+}
+
+/// Workspace is a model and the setup used to analyze it.
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Workspace {
+    /// the model file, relative to the workspace file
+    pub model: String,
+    pub scenarios: Vec<Scenario>,
+    /// data applied to every run, including the model's tests
+    pub data: Vec<DataBinding>,
+    pub runs: Vec<SavedRun>,
+    /// tests run along with the ones stored in the project
+    pub tests: Vec<ModelTest>,
+}
+
+impl Workspace {
+    pub fn new(model: &str) -> Self {
+        Workspace {
+            model: model.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    /// from_json parses a workspace written by `to_json`.  Fields left
+    /// out are empty, so a workspace can be written by hand.
+    pub fn from_json(contents: &str) -> Result<Self> {
+        serde_json::from_str(contents).map_err(json_error)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn get_scenario(&self, name: &str) -> Option<&Scenario> {
+        self.scenarios.iter().find(|scenario| scenario.name == name)
+    }
+
+    // stubs returns the stubs for the data bindings, given the
+    // (time, value) series loaded for each, and the scenario's changes.
+    fn stubs(&self, scenario: Option<&Scenario>, data: &[Vec<(f64, f64)>]) -> Result<Stubs> {
+        if data.len() != self.data.len() {
+            return sim_err!(
+                Generic,
+                format!(
+                    "expected a series for each of {} data bindings, not {}",
+                    self.data.len(),
+                    data.len()
+                )
+            );
+        }
+        let mut stubs = Stubs::new();
+        for (binding, series) in self.data.iter().zip(data.iter()) {
+            stubs.stub_path("main", &binding.ident, Stub::Series(series.clone()));
+        }
+        if let Some(scenario) = scenario {
+            for o in scenario.overrides.iter() {
+                stubs.stub_path("main", &o.ident, Stub::Constant(o.value));
+            }
+            scenario.schedule.stub("main", &mut stubs)?;
+        }
+        Ok(stubs)
+    }
+
+    /// simulate runs the main model with the data bindings and, if
+    /// `scenario` is given, that scenario's changes.  `data` is the
+    /// series loaded for each data binding, in order.
+    pub fn simulate(
+        &self,
+        project: &datamodel::Project,
+        scenario: Option<&str>,
+        data: &[Vec<(f64, f64)>],
+    ) -> Result<Results> {
+        let scenario = match scenario {
+            Some(name) => match self.get_scenario(name) {
+                Some(scenario) => Some(scenario),
+                None => return model_err!(DoesNotExist, name.to_owned()),
+            },
+            None => None,
+        };
+        let stubs = self.stubs(scenario, data)?;
+        let project = Project::from_with_stubs(project.clone(), &stubs)?;
+        let mut sim = Simulation::new(&project, "main")?;
+        if let Some(scenario) = scenario {
+            sim.set_event_times(&scenario.schedule.times());
+        }
+        let mut vm = Vm::new(sim.compile()?)?;
+        vm.run_to_end()?;
+        Ok(vm.into_results())
+    }
+
+    /// run_tests runs the tests stored in the project, then the
+    /// workspace's, with the data bindings applied.
+    pub fn run_tests(
+        &self,
+        project: &datamodel::Project,
+        data: &[Vec<(f64, f64)>],
+    ) -> Result<Vec<TestResult>> {
+        let stubs = self.stubs(None, data)?;
+        let mut project = project.clone();
+        project.tests.extend(self.tests.iter().cloned());
+        Ok(run_tests_with_stubs(&project, &stubs))
+    }
+}
+
+#[test]
+fn test_workspace() {
+    use crate::common::ErrorCode;
+    use crate::datamodel::{TestExpectation, TestOverride};
+    use crate::testutils::{sim_specs_with_units, x_aux, x_flow, x_model, x_project, x_stock};
+
+    let mut project = x_project(
+        sim_specs_with_units("time"),
+        &[x_model(
+            "main",
+            vec![
+                x_stock("s", "0", &["f"], &[], None),
+                x_flow("f", "rate * demand * open", None),
+                x_aux("rate", "1", None),
+                x_aux("demand", "1", None),
+                x_aux("open", "1", None),
+            ],
+        )],
+    );
+    project.sim_specs.stop = 4.0;
+
+    let mut workspace = Workspace::new("model.stmx");
+    let mut schedule = Schedule::new();
+    schedule.change(2.0, "open", 0.0);
+    workspace.scenarios.push(Scenario {
+        name: "halt".to_owned(),
+        overrides: vec![Override {
+            ident: "rate".to_owned(),
+            value: 2.0,
+        }],
+        schedule,
+    });
+    workspace.data.push(DataBinding {
+        ident: "demand".to_owned(),
+        path: "demand.csv".to_owned(),
+        column: None,
+    });
+    workspace.runs.push(SavedRun {
+        name: "halted".to_owned(),
+        scenario: Some("halt".to_owned()),
+        path: "halted.tsv".to_owned(),
+    });
+    workspace.tests.push(ModelTest {
+        name: "doubled".to_owned(),
+        model_name: "".to_owned(),
+        overrides: vec![TestOverride {
+            ident: "rate".to_owned(),
+            value: 2.0,
+        }],
+        expectations: vec![TestExpectation {
+            ident: "s".to_owned(),
+            time: 2.0,
+            value: 5.0,
+            tolerance: 1e-9,
+        }],
+    });
+
+    let contents = workspace.to_json();
+    assert_eq!(workspace, Workspace::from_json(&contents).unwrap());
+    // fields left out are empty
+    let partial = Workspace::from_json("{\"model\": \"model.stmx\"}").unwrap();
+    assert_eq!(Workspace::new("model.stmx"), partial);
+    let err = Workspace::from_json("{\"model\": 1}").unwrap_err();
+    assert_eq!(ErrorCode::JsonDeserialization, err.code);
+
+    // demand rises from 1 to 2 over the first two time units
+    let data = vec![vec![(0.0, 1.0), (2.0, 2.0)]];
+    let baseline = workspace.simulate(&project, None, &data).unwrap();
+    assert_eq!(
+        Some(vec![1.0, 1.5, 2.0, 2.0, 2.0]),
+        baseline.get_series("demand")
+    );
+    assert_eq!(
+        Some(vec![0.0, 1.0, 2.5, 4.5, 6.5]),
+        baseline.get_series("s")
+    );
+    let halted = workspace.simulate(&project, Some("halt"), &data).unwrap();
+    assert_eq!(Some(vec![0.0, 2.0, 5.0, 5.0, 5.0]), halted.get_series("s"));
+
+    let err = workspace
+        .simulate(&project, Some("nope"), &data)
+        .unwrap_err();
+    assert_eq!(ErrorCode::DoesNotExist, err.code);
+    assert!(workspace.simulate(&project, None, &[]).is_err());
+
+    // the data is used by tests too
+    let results = workspace.run_tests(&project, &data).unwrap();
+    assert_eq!(1, results.len());
+    assert!(results[0].is_pass(), "{:?}", results[0]);
+}
@@ -0,0 +1,80 @@
+// Copyright 2021 The Simlin Authors. All rights reserved.
+// Use of this source code is governed by the Apache License,
+// Version 2.0, that can be found in the LICENSE file.
+
+use std::fmt::Display;
+use std::fs::File;
+use std::io::BufReader;
+
+use extendr_api::prelude::*;
+
+use simlin_compat::engine::datamodel;
+use simlin_compat::engine::stubs::{Stub, Stubs};
+use simlin_compat::engine::{Project, Simulation, Vm};
+use simlin_compat::{open_vensim, open_xmile};
+
+fn r_err(err: impl Display) -> Error {
+    Error::Other(err.to_string())
+}
+
+/// SimlinModel is a project loaded from an XMILE or Vensim file.
+pub struct SimlinModel {
+    project: datamodel::Project,
+}
+
+#[extendr]
+impl SimlinModel {
+    fn load(path: &str, is_vensim: bool) -> Result<Self> {
+        let file = File::open(path).map_err(r_err)?;
+        let mut reader = BufReader::new(file);
+        let project = if is_vensim {
+            open_vensim(&mut reader)
+        } else {
+            open_xmile(&mut reader)
+        }
+        .map_err(r_err)?;
+        Ok(SimlinModel { project })
+    }
+
+    fn model_names(&self) -> Vec<String> {
+        self.project
+            .models
+            .iter()
+            .map(|model| model.name.clone())
+            .collect()
+    }
+
+    /// simulate runs `model_name` to the end with each of the
+    /// `override_names` held constant at the matching value, and returns
+    /// a named list with one column of values per variable.
+    fn simulate(
+        &self,
+        model_name: &str,
+        override_names: Vec<String>,
+        override_values: Vec<f64>,
+    ) -> Result<List> {
+        if override_names.len() != override_values.len() {
+            return Err(r_err("every override needs a name"));
+        }
+        let mut stubs = Stubs::new();
+        for (ident, value) in override_names.iter().zip(override_values) {
+            stubs.stub(model_name, ident, Stub::Constant(value));
+        }
+        let project = Project::from_with_stubs(self.project.clone(), &stubs).map_err(r_err)?;
+        let sim = Simulation::new(&project, model_name).map_err(r_err)?;
+        let mut vm = Vm::new(sim.compile().map_err(r_err)?).map_err(r_err)?;
+        vm.run_to_end().map_err(r_err)?;
+
+        let results = vm.into_results();
+        let (names, data) = results.columns();
+        let columns = data
+            .chunks(results.step_count.max(1))
+            .map(|column| Robj::from(column.to_vec()));
+        List::from_names_and_values(names, columns)
+    }
+}
+
+extendr_module! {
+    mod simlin;
+    impl SimlinModel;
+}
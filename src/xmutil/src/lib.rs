@@ -29,7 +29,8 @@ lazy_static! {
 
 pub fn convert_vensim_mdl(mdl_source: &str, is_compact: bool) -> (Option<String>, Option<String>) {
     // always grab the lock guard before calling in to _convert_mdl_to_xmile
-    let _guard = LOCK.lock().unwrap();
+    // a panic while holding the lock doesn't leave any state behind
+    let _guard = LOCK.lock().unwrap_or_else(|err| err.into_inner());
 
     let str_ptr = mdl_source.as_ptr();
     let str_len = mdl_source.len() as u32;
@@ -51,11 +52,11 @@ pub fn convert_vensim_mdl(mdl_source: &str, is_compact: bool) -> (Option<String>
         let log = if !log_buf.is_null() {
             // a reference to non-owned data
             let c_str: &CStr = CStr::from_ptr(log_buf);
-            let str_slice: &str = c_str.to_str().unwrap();
+            let str_slice = c_str.to_string_lossy();
             if str_slice.is_empty() {
                 None
             } else {
-                Some(str_slice.to_owned())
+                Some(str_slice.into_owned())
             }
         } else {
             None
@@ -17,6 +17,7 @@ public:
   }
   std::string sName;
   std::string sOwner;
+  std::string sComment;
   std::vector<Variable *> vVariables;
 };
 
@@ -205,6 +205,12 @@ public:
   void SetUnwanted(bool set) {
     _unwanted = set;
   }
+  bool Supplementary() const {
+    return _supplementary;
+  }
+  void SetSupplementary(bool set) {
+    _supplementary = set;
+  }
   const std::string &Comment() {
     return _comment;
   }
@@ -324,6 +330,7 @@ private:
   size_t iNelm;  // used for subscript owners
   View *_view;   // view defined in
   bool _unwanted;
+  bool _supplementary;  // marked :SUPPLEMENTARY - computed only for output
   bool _hasUpstream;
   bool _hasDownstream;
   bool bAsFlow;
@@ -187,8 +187,14 @@ int VensimLex::NextToken()  // also sets token type
       } while (c != '\r' && c != '\n' && c != ' ' && c != '\t');
       while ((c = GetNextChar(false)) != '*' && c != '|')
         ;
-      while (c && c != '|')
+      // the closing stars may be followed by ~ and a description of the group
+      sGroupComment.clear();
+      while (c && c != '~' && c != '|')
         c = GetNextChar(false);
+      if (c == '~') {
+        sGroupComment = GetComment("|");
+        FindToken("|");
+      }
       return VPTT_groupstar;
     }
     break;
@@ -573,6 +579,9 @@ std::string VensimLex::GetComment(const char *tok) {
       SyncBuffers();
       return rval;  // an error
     }
+    // strip leading white space
+    if (rval.empty() && (c == ' ' || c == '\t' || c == '\r' || c == '\n'))
+      continue;
     rval.push_back(c);
   }
   return rval;  // this is an error condition
@@ -25,6 +25,9 @@ public:
     return iCurPos - iLineStart;
   }
   std::string GetComment(const char *tok);
+  const std::string &GroupComment(void) {
+    return sGroupComment;
+  }
   bool FindToken(const char *tok);
   bool BufferReadLine(char *buf, size_t buflen);  // start with buffer then read the line
   bool ReadLine(char *buf, size_t buflen);        // read a line if enough room otherwise part of it
@@ -34,6 +37,7 @@ private:
   void SyncBuffers(void);
   bool TestTokenMatch(const char *tok, bool update);
   std::string sToken;
+  std::string sGroupComment;  // the description of the last group banner
   std::string sBuffer;
   const char *ucContent;
   off_t iCurPos, iHoldPos;
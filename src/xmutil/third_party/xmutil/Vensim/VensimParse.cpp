@@ -237,6 +237,7 @@ bool VensimParse::ProcessFile(const std::string &filename, const char *contents,
         else
           group_owner = _model->Groups().back().sOwner;
         { _model->Groups().push_back(ModelGroup(*mVensimLex.CurToken(), group_owner)); }
+        _model->Groups().back().sComment = mVensimLex.GroupComment();
       } else if (rval != endtok) {
         log("Unknown terminal token %d\n", rval);
         if (!FindNextEq(false))
@@ -451,6 +452,16 @@ UnitExpression *VensimParse::InsertUnitExpression(Units *u) {
 bool VensimParse::FindNextEq(bool want_comment) {
   if (want_comment && this->pActiveVar) {
     std::string comment = mVensimLex.GetComment("|");
+    // a third field, after another ~, holds flags like :SUPPLEMENTARY
+    size_t flags = comment.find('~');
+    if (flags != std::string::npos) {
+      if (comment.find(":SUP", flags) != std::string::npos)
+        this->pActiveVar->SetSupplementary(true);
+      comment.erase(flags);
+      while (!comment.empty() && (comment.back() == ' ' || comment.back() == '\t' || comment.back() == '\r' ||
+                                  comment.back() == '\n'))
+        comment.pop_back();
+    }
     if (!comment.empty())  // multile appearances okay - take last non empty
       this->pActiveVar->SetComment(comment);
   }
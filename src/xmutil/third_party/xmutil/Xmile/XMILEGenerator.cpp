@@ -20,6 +20,7 @@ std::string XMILEGenerator::Print(bool is_compact, std::vector<std::string> &err
   root->SetName("xmile");
   root->SetAttribute("xmlns", "http://docs.oasis-open.org/xmile/ns/XMILE/v1.0");
   root->SetAttribute("xmlns:isee", "http://iseesystems.com/XMILE");
+  root->SetAttribute("xmlns:simlin", "https://simlin.com/XMILE/v1.0");
   root->SetAttribute("version", "1.0");
   doc.InsertFirstChild(root);
 
@@ -308,6 +309,8 @@ void XMILEGenerator::generateModelAsSectors(tinyxml2::XMLElement *element, std::
 
     variables->InsertEndChild(xvar);
     xvar->SetAttribute("name", var->GetAlternateName().c_str());
+    if (var->Supplementary())
+      xvar->SetAttribute("simlin:supplementary", "true");
 
     if (type == XMILE_Type_DELAYAUX) {
       tinyxml2::XMLElement *xcomment = doc->NewElement("isee:delay_aux");
@@ -502,6 +505,8 @@ void XMILEGenerator::generateModelAsSectors(tinyxml2::XMLElement *element, std::
       units->SetText(un->GetEquationString().c_str());
     }
   }
+  if (ns == NULL)
+    this->generateGroups(variables);
   if (want_diagram) {
     tinyxml2::XMLElement *views = doc->NewElement("views");
     this->generateSectorViews(views, variables, errs, ns == NULL);
@@ -537,6 +542,8 @@ void XMILEGenerator::generateEquations(std::set<Variable *> &included, tinyxml2:
 
     variables->InsertEndChild(xvar);
     xvar->SetAttribute("name", var->GetAlternateName().c_str());
+    if (var->Supplementary())
+      xvar->SetAttribute("simlin:supplementary", "true");
 
     if (type == XMILE_Type_DELAYAUX) {
       tinyxml2::XMLElement *xcomment = doc->NewElement("isee:delay_aux");
@@ -844,29 +851,37 @@ void XMILEGenerator::generateModelAsModules(tinyxml2::XMLElement *element, std::
   // element->InsertEndChild(views);
 }
 
+// the groups named by the banners in the equations, with their descriptions
+void XMILEGenerator::generateGroups(tinyxml2::XMLElement *xvars) {
+  tinyxml2::XMLDocument *doc = xvars->GetDocument();
+  for (ModelGroup &group : _model->Groups()) {
+    tinyxml2::XMLElement *xgroup = doc->NewElement("group");
+    xgroup->SetAttribute("name", group.sName.c_str());
+    if (group.sOwner != group.sName)
+      xgroup->SetAttribute("owner", group.sOwner.c_str());
+    xvars->InsertEndChild(xgroup);
+    if (!group.sComment.empty()) {
+      tinyxml2::XMLElement *xcomment = doc->NewElement("doc");
+      xgroup->InsertEndChild(xcomment);
+      xcomment->SetText(group.sComment.c_str());
+    }
+    for (Variable *var : group.vVariables) {
+      if (var->Unwanted())
+        continue;
+      tinyxml2::XMLElement *xentity = doc->NewElement("entity");
+      xentity->SetAttribute("name", SpaceToUnderBar(var->GetAlternateName()).c_str());
+      xgroup->InsertEndChild(xentity);
+    }
+  }
+}
+
 void XMILEGenerator::generateSectorViews(tinyxml2::XMLElement *element, tinyxml2::XMLElement *xvars,
                                          std::vector<std::string> &errs, bool mainmodel) {
   tinyxml2::XMLDocument *doc = element->GetDocument();
 
   std::vector<View *> &views = _model->Views();
-  if (views.empty() && mainmodel) {
-    std::vector<ModelGroup> &groups = _model->Groups();
-    if (!groups.empty()) {
-      for (ModelGroup &group : groups) {
-        tinyxml2::XMLElement *xgroup = doc->NewElement("group");
-        xgroup->SetAttribute("name", group.sName.c_str());
-        if (group.sOwner != group.sName)
-          xgroup->SetAttribute("owner", group.sOwner.c_str());
-        element->InsertEndChild(xgroup);
-        for (Variable *var : group.vVariables) {
-          tinyxml2::XMLElement *xvar = doc->NewElement("var");
-          xvar->SetText(SpaceToUnderBar(var->GetAlternateName()).c_str());
-          xgroup->InsertEndChild(xvar);
-        }
-      }
-    }
-    return;
-  }
+  if (views.empty() && mainmodel)
+    return;  // the groups from the equations are in generateGroups
   int x, y;
   // start at a reasonable distance from 0 - the x,y values are generally around hte center
   // of the var
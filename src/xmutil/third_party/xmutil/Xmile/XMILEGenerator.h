@@ -28,6 +28,7 @@ protected:
                               bool wantDiagram);
   void generateEquations(std::set<Variable *> &included, tinyxml2::XMLDocument *doc, tinyxml2::XMLElement *variables);
   void generateModelAsModules(tinyxml2::XMLElement *element, std::vector<std::string> &errs, SymbolNameSpace *ns);
+  void generateGroups(tinyxml2::XMLElement *xvars);
   void generateSectorViews(tinyxml2::XMLElement *views, tinyxml2::XMLElement *vars, std::vector<std::string> &errs,
                            bool mainmodel);
   void generateView(VensimView *view, tinyxml2::XMLElement *element, std::vector<std::string> &errs,